@@ -0,0 +1,70 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Time converting a block bitmap to records.
+//!
+//! Run with `cargo bench --bench decode_block`.
+
+use ahda::decoder::bitmap_decoder::BitmapDecoder;
+use ahda::decoder::bitmap_decoder::decode_roaring32;
+use ahda::headers::file::build_file_header_and_flags;
+use ahda::compression::MetadataCompression;
+use ahda::simulate::Simulator;
+use ahda::PseudoAln;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use roaring::RoaringBitmap;
+
+const N_QUERIES: u32 = 65536;
+const N_TARGETS: u32 = 64;
+const N_ROUNDS: u32 = 20;
+
+fn time<F: FnMut() -> usize>(
+    name: &str,
+    mut f: F,
+) {
+    let mut best = Duration::MAX;
+    let mut n_ones = 0;
+    for _ in 0..N_ROUNDS {
+        let start = Instant::now();
+        n_ones = f();
+        best = best.min(start.elapsed());
+    }
+    println!("{}\t{} ones\tbest of {}: {:?}", name, n_ones, N_ROUNDS, best);
+}
+
+fn main() {
+    for density in [0.01, 0.1, 0.5] {
+        let mut simulator = Simulator::new(N_QUERIES, N_TARGETS, density, 0);
+        let targets = simulator.targets();
+        let bitmap = RoaringBitmap::from_iter(simulator.by_ref().flat_map(|record| {
+            let query_id = record.query_id.unwrap();
+            record.ones.unwrap().into_iter().map(move |target_id| query_id * N_TARGETS + target_id)
+        }));
+        let (header, _) = build_file_header_and_flags(&targets, N_QUERIES as usize, b"bench", &MetadataCompression::default()).unwrap();
+
+        println!("density {}", density);
+        time("BitmapDecoder", || {
+            let mut bits = bitmap.iter().map(|x| x as u64);
+            // Decoder collects the whole block before returning records
+            let block: Vec<PseudoAln> = BitmapDecoder::new(&mut bits, header.clone()).collect();
+            block.into_iter().map(|x| x.ones.unwrap().len()).sum()
+        });
+        time("decode_roaring32", || {
+            decode_roaring32(&bitmap, N_TARGETS).into_iter().map(|x| x.ones.unwrap().len()).sum()
+        });
+    }
+}
@@ -13,7 +13,7 @@
 //
 
 fn main() {
-    #[cfg(feature = "cxxbridge")]
+    #[cfg(feature = "cxx-api")]
     cxx_build::bridge("src/cxx_api/mod.rs")
         .compile("ahda-cxx");
 }
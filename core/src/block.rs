@@ -0,0 +1,642 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Block header, block layout, and block payloads.
+//!
+//! A block is a [BlockHeader] followed by `flags_len` bytes of block flags
+//! and `block_len` bytes of payload. The flags are gzipped bincode unless
+//! [BLOCK_FLAGS_RAW] is set, and the payload is a serialized roaring bitmap,
+//! gzipped unless [BLOCK_PAYLOAD_RAW] is set. Bit
+//! `query_id * n_targets + target_index` of the bitmap is set if the query
+//! aligned to the target.
+
+use crate::Error;
+use crate::gzip::inflate;
+use crate::roaring::deserialize_roaring32;
+use crate::roaring::deserialize_roaring64;
+
+use alloc::vec::Vec;
+
+use bincode::{Encode, Decode};
+use bincode::decode_from_slice;
+use bincode::decode_from_slice_with_context;
+use bincode::encode_to_vec;
+
+/// Length of an encoded [BlockHeader].
+pub const BLOCK_HEADER_LEN: usize = 32;
+
+/// Block payload is a roaring bitmap (32-bit address space), see [BlockHeader::bitmap_type].
+pub const BITMAP_ROARING32: u16 = 0;
+/// Block payload is a roaring treemap (64-bit address space), see [BlockHeader::bitmap_type].
+pub const BITMAP_ROARING64: u16 = 1;
+
+/// Block contents are stored in an earlier block.
+///
+/// The block does not store its payload and `block_len` is 0. The payload is
+/// the same as in the block `placeholder2` blocks before this one.
+pub const BLOCK_IS_REFERENCE: u8 = 1 << 0;
+/// Block contents are referenced by a later block.
+pub const BLOCK_IS_REFERENCED: u8 = 1 << 1;
+
+/// Block payload is stored without gzip compression.
+///
+/// Blocks written before this flag was introduced never set it and always
+/// contain a gzipped payload.
+pub const BLOCK_PAYLOAD_RAW: u8 = 1 << 2;
+
+/// Block header records the [BlockStats] of the block.
+///
+/// Blocks written before this flag was introduced never set it and leave
+/// the `placeholder3` field of the block header as zero.
+pub const BLOCK_HAS_STATS: u8 = 1 << 3;
+
+/// Block flags are stored as bincode without gzip compression.
+///
+/// Set together with [BLOCK_PAYLOAD_RAW] in the single block of small
+/// records, where the gzip headers would take more space than compressing
+/// saves. Blocks written before this flag was introduced never set it and
+/// always contain gzipped flags.
+pub const BLOCK_FLAGS_RAW: u8 = 1 << 4;
+
+/// Query ids in the block flags are in ascending order and larger than the
+/// query ids of the previous block in the record.
+///
+/// The query names and mates are stored in the same order as the ids. Runs
+/// of blocks with this flag can be binary searched by query id. Blocks
+/// written before this flag was introduced never set it.
+pub const BLOCK_QUERY_IDS_SORTED: u8 = 1 << 5;
+
+/// Query ids in the block flags are stored as u16 offsets from the smallest
+/// id, see [encode_query_ids].
+///
+/// Blocks written before this flag was introduced never set it and store the
+/// query ids as an `Option<Vec<u32>>`.
+pub const BLOCK_QUERY_IDS_U16: u8 = 1 << 6;
+
+/// Minimum size reduction, in percent, for storing a gzipped block payload.
+pub const MIN_PAYLOAD_SAVINGS: usize = 10;
+
+/// Block header for encoded data
+///
+/// Always the first 32 bytes at the beginning of a .ahda block.
+///
+/// Must always conform to this format.
+///
+/// The BlockHeader contains the following data:
+/// - Number of records stored in this block.
+/// - The metadata compression method used for the block flags.
+/// - Type of bitmap stored in this block.
+/// - Block storage flags (`placeholder1`), see [BLOCK_IS_REFERENCE],
+///   [BLOCK_IS_REFERENCED], [BLOCK_PAYLOAD_RAW], [BLOCK_HAS_STATS],
+///   [BLOCK_FLAGS_RAW], [BLOCK_QUERY_IDS_SORTED], and [BLOCK_QUERY_IDS_U16].
+/// - The number of bytes in the block contents that follow the block flags bytes.
+/// - Number of bytes containing the block flags that follow the header.
+/// - Fields that are present in the block flags.
+/// - Distance to the block whose contents this block refers to (`placeholder2`).
+/// - The number of set bits and the largest target index in the block, see [BlockStats].
+///
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct BlockHeader {
+    /// Number of records (queries) stored in this block
+    pub num_records: u32,
+
+    /// Compression method used for the block flags.
+    pub metadata_compression: u8,
+
+    /// Bitmap type used to encode this block, [BITMAP_ROARING32] or [BITMAP_ROARING64].
+    ///
+    /// The address space of the block bitmap type must fit within the file
+    /// bitmap type specified in [FileHeader](crate::file::FileHeader). Given
+    /// that this is satisfied, the block bitmap type may differ from the file
+    /// bitmap type.
+    pub bitmap_type: u16,
+
+    /// Block storage flags, a bitwise or of [BLOCK_IS_REFERENCE],
+    /// [BLOCK_IS_REFERENCED], [BLOCK_PAYLOAD_RAW], [BLOCK_HAS_STATS],
+    /// [BLOCK_FLAGS_RAW], [BLOCK_QUERY_IDS_SORTED], and [BLOCK_QUERY_IDS_U16].
+    pub placeholder1: u8,
+
+    /// Number of bytes in the block contents that follow the flags bytes.
+    pub block_len: u32,
+
+    /// Number of bytes in the block flags that follow the header bytes.
+    pub flags_len: u64,
+
+    /// Block flags fields that are present for records in this block.
+    ///
+    /// Every block must contain at least the fields given in the
+    /// [FileHeader](crate::file::FileHeader) `fields_present`. A block may
+    /// also contain extra fields that are not specified in the file header.
+    pub fields_present: u16,
+
+    /// Distance to the block whose contents this block refers to, see [BLOCK_IS_REFERENCE].
+    ///
+    /// At least 1 in blocks that set [BLOCK_IS_REFERENCE] and 0 in others.
+    pub placeholder2: u16,
+
+    /// Statistics of the bitmap in this block, see [BlockStats].
+    ///
+    /// Only valid if [BLOCK_HAS_STATS] is set in `placeholder1`.
+    pub placeholder3: u64,
+}
+
+impl BlockHeader {
+    pub fn promises_query_names(
+        &self,
+    ) -> bool {
+        (self.fields_present & crate::file::MASK_QUERIES) != 0
+    }
+
+    pub fn promises_query_ids(
+        &self,
+    ) -> bool {
+        (self.fields_present & crate::file::MASK_QUERY_IDS) != 0
+    }
+
+    /// Get the statistics recorded for this block.
+    ///
+    /// Returns None for blocks written before the statistics were introduced.
+    pub fn stats(
+        &self,
+    ) -> Option<BlockStats> {
+        if self.placeholder1 & BLOCK_HAS_STATS == 0 {
+            return None
+        }
+        let max_target = (self.placeholder3 >> 32) as u32;
+        Some(BlockStats{
+            n_ones: self.placeholder3 as u32,
+            max_target: max_target.checked_sub(1),
+        })
+    }
+
+    /// Record `stats` in this block header.
+    pub fn set_stats(
+        &mut self,
+        stats: &BlockStats,
+    ) {
+        let max_target = stats.max_target.map_or(0, |x| x as u64 + 1);
+        self.placeholder3 = (max_target << 32) | stats.n_ones as u64;
+        self.placeholder1 |= BLOCK_HAS_STATS;
+    }
+}
+
+/// Number of set bits and the largest target index in a block.
+///
+/// Written by the encoder in the `placeholder3` field of [BlockHeader] so
+/// that readers can tell how full a block is without decompressing it. The
+/// lower 32 bits store `n_ones` and the upper 32 bits `max_target + 1`, or 0
+/// if no bits are set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockStats {
+    /// Number of set bits in the block, capped at [u32::MAX].
+    pub n_ones: u32,
+    /// Largest target index with a set bit, or None if no bits are set.
+    pub max_target: Option<u32>,
+}
+
+impl BlockStats {
+    /// Compute the statistics from the target indexes of each record in a block.
+    pub fn from_ones<'a, I: Iterator<Item = &'a [u32]>>(
+        ones: I,
+    ) -> Self {
+        let mut n_ones: u64 = 0;
+        let mut max_target: Option<u32> = None;
+        ones.for_each(|ones| {
+            n_ones += ones.len() as u64;
+            max_target = max_target.max(ones.iter().max().copied());
+        });
+        BlockStats{ n_ones: n_ones.min(u32::MAX as u64) as u32, max_target }
+    }
+
+    /// Fraction of bits that are set among `num_records` records with `n_targets` targets.
+    ///
+    /// Returns 0.0 for blocks without bits.
+    pub fn density(
+        &self,
+        num_records: u32,
+        n_targets: u32,
+    ) -> f64 {
+        let n_bits = num_records as f64 * n_targets as f64;
+        if n_bits == 0.0 { 0.0 } else { self.n_ones as f64 / n_bits }
+    }
+}
+
+/// Encode `header` as [BLOCK_HEADER_LEN] bytes.
+pub fn encode_block_header(
+    header: &BlockHeader,
+) -> Vec<u8> {
+    let bytes = encode_to_vec(header, bincode::config::standard().with_fixed_int_encoding()).unwrap();
+    assert_eq!(bytes.len(), BLOCK_HEADER_LEN);
+    bytes
+}
+
+/// Decode the [BlockHeader] at the start of `bytes`.
+///
+/// ## Errors and panics
+/// Errors with [Error::Truncated] if `bytes` is shorter than [BLOCK_HEADER_LEN].
+///
+pub fn decode_block_header(
+    bytes: &[u8],
+) -> Result<BlockHeader, Error> {
+    let header_bytes = bytes.get(0..BLOCK_HEADER_LEN).ok_or(Error::Truncated)?;
+    Ok(decode_from_slice(header_bytes, bincode::config::standard().with_fixed_int_encoding())?.0)
+}
+
+/// Assemble a block from its header, encoded flags, and payload.
+///
+/// Sets `flags_len` and `block_len` in `header` to the lengths of `flags`
+/// and `payload`.
+///
+/// ## Errors and panics
+/// Panics if `payload` is longer than [u32::MAX] bytes.
+///
+pub fn pack_block(
+    header: &mut BlockHeader,
+    flags: &[u8],
+    payload: &[u8],
+) -> Vec<u8> {
+    header.flags_len = flags.len() as u64;
+    header.block_len = u32::try_from(payload.len()).unwrap();
+    let mut block = encode_block_header(header);
+    block.reserve(flags.len() + payload.len());
+    block.extend_from_slice(flags);
+    block.extend_from_slice(payload);
+    block
+}
+
+/// Pick the gzipped payload if it is at least [MIN_PAYLOAD_SAVINGS] percent smaller.
+///
+/// `deflated` is `bytes` gzipped with any compression level. Returns the
+/// payload to store and the flags to set in `placeholder1`, either 0 or
+/// [BLOCK_PAYLOAD_RAW].
+pub fn choose_payload(
+    bytes: Vec<u8>,
+    deflated: Vec<u8>,
+) -> (Vec<u8>, u8) {
+    if deflated.len() * 100 <= bytes.len() * (100 - MIN_PAYLOAD_SAVINGS) {
+        (deflated, 0)
+    } else {
+        (bytes, BLOCK_PAYLOAD_RAW)
+    }
+}
+
+/// Get the serialized bitmap from the payload of a block with `header`.
+///
+/// ## Errors and panics
+/// Errors with [Error::InvalidGzip] if the payload is compressed and corrupted.
+///
+pub fn decompress_payload(
+    payload: &[u8],
+    header: &BlockHeader,
+) -> Result<Vec<u8>, Error> {
+    if header.placeholder1 & BLOCK_PAYLOAD_RAW != 0 {
+        Ok(payload.to_vec())
+    } else {
+        inflate(payload)
+    }
+}
+
+/// Get the bincode-encoded block flags from the flags of a block with `header`.
+///
+/// ## Errors and panics
+/// Errors with [Error::InvalidGzip] if the flags are compressed and corrupted.
+///
+pub fn decompress_flags(
+    flags: &[u8],
+    header: &BlockHeader,
+) -> Result<Vec<u8>, Error> {
+    if header.placeholder1 & BLOCK_FLAGS_RAW != 0 {
+        Ok(flags.to_vec())
+    } else {
+        inflate(flags)
+    }
+}
+
+/// Number of bytes in the variable length encoding of `x`.
+fn varint_len(
+    x: u32,
+) -> usize {
+    if x < 251 { 1 } else if x <= u16::MAX as u32 { 3 } else { 5 }
+}
+
+/// Check if `query_ids` are stored as u16 offsets by [encode_query_ids].
+///
+/// True if the ids differ from the smallest id by at most [u16::MAX], there
+/// are at most 65536 of them, and the offsets take less space than the ids.
+pub fn query_ids_fit_u16(
+    query_ids: Option<&[u32]>,
+) -> bool {
+    let Some(ids) = query_ids else {
+        return false
+    };
+    let base = ids.iter().min().copied().unwrap_or(0);
+    let max = ids.iter().max().copied().unwrap_or(0);
+    let wide_len: usize = ids.iter().map(|x| varint_len(*x)).sum();
+    !ids.is_empty() && ids.len() <= 65536 && max - base <= u16::MAX as u32 && 2 * ids.len() + varint_len(base) < wide_len
+}
+
+/// Encode the `query_ids` field of the block flags.
+///
+/// If `as_u16` is true the ids are written as the smallest id followed by
+/// the offsets from it, and the block header must set [BLOCK_QUERY_IDS_U16].
+/// The offsets are stored as the low bytes of every offset followed by the
+/// high bytes, which gzip compresses much better than interleaved bytes for
+/// consecutive ids. Otherwise the ids are written as an `Option<Vec<u32>>`.
+///
+/// ## Errors and panics
+/// Errors if `as_u16` is true but [query_ids_fit_u16] is not.
+///
+pub fn encode_query_ids<En: bincode::enc::Encoder>(
+    query_ids: Option<&[u32]>,
+    as_u16: bool,
+    encoder: &mut En,
+) -> Result<(), bincode::error::EncodeError> {
+    if !as_u16 {
+        return query_ids.encode(encoder)
+    }
+    if !query_ids_fit_u16(query_ids) {
+        return Err(bincode::error::EncodeError::Other("query ids don't fit in u16 offsets"))
+    }
+    let ids = query_ids.unwrap_or_default();
+    let base = ids.iter().min().copied().unwrap_or(0);
+    let mut offsets: Vec<u8> = ids.iter().map(|x| (x - base) as u8).collect();
+    offsets.extend(ids.iter().map(|x| ((x - base) >> 8) as u8));
+    base.encode(encoder)?;
+    offsets.encode(encoder)
+}
+
+/// Decode the `query_ids` field of the block flags written by [encode_query_ids].
+///
+/// `as_u16` is true if the block header sets [BLOCK_QUERY_IDS_U16].
+///
+/// ## Errors and panics
+/// Errors if the field can't be decoded or if an id overflows [u32].
+///
+pub fn decode_query_ids<Context, D: bincode::de::Decoder<Context = Context>>(
+    as_u16: bool,
+    decoder: &mut D,
+) -> Result<Option<Vec<u32>>, bincode::error::DecodeError> {
+    if !as_u16 {
+        return Option::<Vec<u32>>::decode(decoder)
+    }
+    let base = u32::decode(decoder)?;
+    let offsets = Vec::<u8>::decode(decoder)?;
+    if offsets.len() % 2 != 0 {
+        return Err(bincode::error::DecodeError::Other("query id offsets end in the middle of an id"))
+    }
+    let (low, high) = offsets.split_at(offsets.len() / 2);
+    low.iter().zip(high.iter()).map(|(low, high)| {
+        base.checked_add(u16::from_le_bytes([*low, *high]) as u32).ok_or(bincode::error::DecodeError::Other("query id overflows u32"))
+    }).collect::<Result<Vec<u32>, _>>().map(Some)
+}
+
+/// Query names and query ids at the start of the block flags.
+///
+/// The context is true if the query ids are stored as u16 offsets.
+struct Queries(Option<Vec<Vec<u8>>>, Option<Vec<u32>>);
+
+impl Decode<bool> for Queries {
+    fn decode<D: bincode::de::Decoder<Context = bool>>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let as_u16 = *decoder.context();
+        let queries = Option::<Vec<Vec<u8>>>::decode(decoder)?;
+        Ok(Queries(queries, decode_query_ids(as_u16, decoder)?))
+    }
+}
+
+/// Get the query names and query ids from the flags of a block with `header`.
+///
+/// The other fields of the flags are ignored.
+///
+/// ## Errors and panics
+/// Errors with [Error::InvalidGzip] or [Error::InvalidMetadata] if the flags
+/// can't be decoded. Flags compressed with a zstd dictionary, which have
+/// `metadata_compression` 2 in the [BlockHeader], are not supported and error
+/// with [Error::InvalidGzip].
+///
+#[allow(clippy::type_complexity)]
+pub fn decode_queries(
+    flags: &[u8],
+    header: &BlockHeader,
+) -> Result<(Option<Vec<Vec<u8>>>, Option<Vec<u32>>), Error> {
+    let bytes = decompress_flags(flags, header)?;
+    let as_u16 = header.placeholder1 & BLOCK_QUERY_IDS_U16 != 0;
+    let queries: Queries = decode_from_slice_with_context(&bytes, bincode::config::standard().with_limit::<{ crate::METADATA_LIMIT }>(), as_u16)?.0;
+    Ok((queries.0, queries.1))
+}
+
+/// A block borrowed from the input bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block<'a> {
+    /// Header of the block.
+    ///
+    /// If the block is a reference, `block_len` is the length of the
+    /// referenced payload.
+    pub header: BlockHeader,
+    /// Encoded block flags, see [Block::queries].
+    pub flags: &'a [u8],
+    /// Block payload, resolved from the referenced block if the block is a reference.
+    pub payload: &'a [u8],
+}
+
+impl Block<'_> {
+    /// Get the query names and query ids from the block flags.
+    ///
+    /// ## Errors and panics
+    /// Errors like [decode_queries].
+    ///
+    #[allow(clippy::type_complexity)]
+    pub fn queries(
+        &self,
+    ) -> Result<(Option<Vec<Vec<u8>>>, Option<Vec<u32>>), Error> {
+        decode_queries(self.flags, &self.header)
+    }
+
+    /// Get the set bits of the block bitmap in increasing order.
+    ///
+    /// ## Errors and panics
+    /// Errors with [Error::UnknownBitmapType] if the bitmap type is unknown and
+    /// if the payload is corrupted.
+    ///
+    pub fn bits(
+        &self,
+    ) -> Result<Vec<u64>, Error> {
+        let payload = decompress_payload(self.payload, &self.header)?;
+        match self.header.bitmap_type {
+            BITMAP_ROARING32 => Ok(deserialize_roaring32(&payload)?.into_iter().map(|x| x as u64).collect()),
+            BITMAP_ROARING64 => deserialize_roaring64(&payload),
+            x => Err(Error::UnknownBitmapType(x)),
+        }
+    }
+}
+
+/// Iterator over the blocks in a byte slice.
+///
+/// Resolves references to earlier blocks, so a slice that starts in the
+/// middle of a record can't contain references to blocks before the start.
+/// Stops after the first error.
+#[derive(Clone, Debug)]
+pub struct Blocks<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    payloads: Vec<&'a [u8]>,
+}
+
+impl<'a> Blocks<'a> {
+    /// Iterate over the blocks in `bytes`, which contains only whole blocks.
+    pub fn new(
+        bytes: &'a [u8],
+    ) -> Self {
+        Blocks{ bytes, pos: 0, payloads: Vec::new() }
+    }
+
+    fn read_block(
+        &mut self,
+    ) -> Result<Block<'a>, Error> {
+        let rest = &self.bytes[self.pos..];
+        let mut header = decode_block_header(rest)?;
+        let flags_end = usize::try_from(header.flags_len).ok().and_then(|x| x.checked_add(BLOCK_HEADER_LEN)).ok_or(Error::Truncated)?;
+        let block_end = flags_end.checked_add(header.block_len as usize).ok_or(Error::Truncated)?;
+        let flags = rest.get(BLOCK_HEADER_LEN..flags_end).ok_or(Error::Truncated)?;
+        let mut payload = rest.get(flags_end..block_end).ok_or(Error::Truncated)?;
+
+        if header.placeholder1 & BLOCK_IS_REFERENCE != 0 {
+            // A block can't refer to itself
+            let target = self.payloads.len().checked_sub(header.placeholder2 as usize).filter(|_| header.placeholder2 > 0).ok_or(Error::MissingReference)?;
+            payload = self.payloads.get(target).ok_or(Error::MissingReference)?;
+            header.block_len = payload.len() as u32;
+        }
+
+        self.pos += block_end;
+        self.payloads.push(payload);
+        Ok(Block{ header, flags, payload })
+    }
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = Result<Block<'a>, Error>;
+
+    fn next(
+        &mut self,
+    ) -> Option<Self::Item> {
+        if self.pos >= self.bytes.len() {
+            return None
+        }
+        let block = self.read_block();
+        if block.is_err() {
+            self.pos = self.bytes.len();
+        }
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn block_header_round_trip() {
+        use super::decode_block_header;
+        use super::encode_block_header;
+        use super::BlockHeader;
+        use alloc::vec;
+
+        let header = BlockHeader{ num_records: 31, placeholder1: 0, block_len: 65511, flags_len: 921, fields_present: 0, placeholder2: 0, placeholder3: 0, bitmap_type: 0, metadata_compression: 0 };
+        let bytes = encode_block_header(&header);
+        assert_eq!(bytes, vec![31, 0, 0, 0, 0, 0, 0, 0, 231, 255, 0, 0, 153, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(decode_block_header(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn blocks_resolve_references() {
+        use super::pack_block;
+        use super::BlockHeader;
+        use super::Blocks;
+        use super::BLOCK_IS_REFERENCE;
+        use super::BLOCK_PAYLOAD_RAW;
+        use crate::Error;
+        use crate::roaring::serialize_roaring32;
+        use alloc::vec;
+
+        let payload = serialize_roaring32(&[1, 4]).unwrap();
+        let mut first = BlockHeader{ num_records: 2, placeholder1: BLOCK_PAYLOAD_RAW, block_len: 0, flags_len: 0, fields_present: 0, placeholder2: 0, placeholder3: 0, bitmap_type: 0, metadata_compression: 0 };
+        let mut second = first.clone();
+        second.placeholder1 |= BLOCK_IS_REFERENCE;
+        second.placeholder2 = 1;
+
+        let mut bytes = pack_block(&mut first, &[], &payload);
+        bytes.extend(pack_block(&mut second, &[], &[]));
+
+        let blocks: alloc::vec::Vec<_> = Blocks::new(&bytes).map(|x| x.unwrap()).collect();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].payload, payload.as_slice());
+        assert_eq!(blocks[1].header.block_len as usize, payload.len());
+        assert_eq!(blocks[1].bits().unwrap(), vec![1, 4]);
+
+        let mut blocks = Blocks::new(&bytes[32..]);
+        assert_eq!(blocks.next().unwrap(), Err(Error::Truncated));
+        assert!(blocks.next().is_none());
+
+        // Reference to the block itself
+        second.placeholder2 = 0;
+        let bytes = pack_block(&mut second, &[], &[]);
+        assert_eq!(Blocks::new(&bytes).next().unwrap(), Err(Error::MissingReference));
+    }
+
+    #[test]
+    fn query_ids_as_u16_offsets() {
+        use super::decode_query_ids;
+        use super::encode_query_ids;
+        use super::query_ids_fit_u16;
+        use alloc::vec;
+        use alloc::vec::Vec;
+
+        struct Ids(Option<Vec<u32>>);
+        impl bincode::Encode for Ids {
+            fn encode<En: bincode::enc::Encoder>(&self, encoder: &mut En) -> Result<(), bincode::error::EncodeError> {
+                encode_query_ids(self.0.as_deref(), query_ids_fit_u16(self.0.as_deref()), encoder)
+            }
+        }
+        impl bincode::Decode<bool> for Ids {
+            fn decode<D: bincode::de::Decoder<Context = bool>>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
+                let as_u16 = *decoder.context();
+                Ok(Ids(decode_query_ids(as_u16, decoder)?))
+            }
+        }
+        let config = bincode::config::standard();
+        let round_trip = |ids: Option<Vec<u32>>| {
+            let bytes = bincode::encode_to_vec(Ids(ids.clone()), config).unwrap();
+            let got: Ids = bincode::decode_from_slice_with_context(&bytes, config, query_ids_fit_u16(ids.as_deref())).unwrap().0;
+            assert_eq!(got.0, ids);
+            bytes
+        };
+
+        let narrow: Vec<u32> = (1_000_000..1_001_000).collect();
+        assert!(query_ids_fit_u16(Some(&narrow)));
+        let bytes = round_trip(Some(narrow.clone()));
+        assert_eq!(bytes.len(), 5 + 3 + 2 * narrow.len());
+        assert_eq!(bincode::encode_to_vec(Some(narrow), config).unwrap().len(), 1 + 3 + 5 * 1000);
+
+        // Ids that don't fit in u16 offsets or would not get smaller
+        for ids in [vec![0, 70_000], vec![3, 1, 2], Vec::new()] {
+            assert!(!query_ids_fit_u16(Some(&ids)));
+            assert_eq!(round_trip(Some(ids))[0], 1);
+        }
+        assert_eq!(round_trip(None), vec![0]);
+
+        assert!(encode_query_ids(Some(&[0, 70_000]), true, &mut bincode::enc::EncoderImpl::new(bincode::enc::write::SizeWriter::default(), config)).is_err());
+        let invalid = [0, 3, 1, 2, 3];
+        assert!(bincode::decode_from_slice_with_context::<bool, Ids, _>(&invalid, config, true).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Errors from decoding and encoding byte slices.
+
+use core::fmt;
+
+/// Error from the codec.
+///
+/// Without std there is no boxed error type to carry the details, so the
+/// variants only say which part of the input was invalid.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Input ends in the middle of a header, flags, or block.
+    Truncated,
+    /// Input doesn't start with [MAGIC](crate::file::MAGIC).
+    NotAhda,
+    /// Bincode-encoded header or flags can't be decoded.
+    InvalidMetadata,
+    /// Gzipped bytes are corrupted.
+    InvalidGzip,
+    /// Serialized roaring bitmap is corrupted.
+    InvalidBitmap,
+    /// Set bits given to a serializer are not in increasing order.
+    NotSorted,
+    /// Bitmap type of a block is unknown.
+    UnknownBitmapType(u16),
+    /// Metadata compression of a file is unknown.
+    UnknownMetadataCompression(u8),
+    /// Block refers to a block that is not in the input, see [Blocks](crate::block::Blocks).
+    MissingReference,
+    /// Rest of the record is in a zstd-seekable container.
+    ZstdContainer,
+    /// Blocks of the record split their targets across sub-blocks, see
+    /// [FILE_HAS_TARGET_CHUNKS](crate::file::FILE_HAS_TARGET_CHUNKS).
+    TargetChunks,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "input ends before the end of a header, flags, or block"),
+            Error::NotAhda => write!(f, "input is not an .ahda record"),
+            Error::InvalidMetadata => write!(f, "header or flags can't be decoded"),
+            Error::InvalidGzip => write!(f, "gzipped bytes are corrupted"),
+            Error::InvalidBitmap => write!(f, "serialized roaring bitmap is corrupted"),
+            Error::NotSorted => write!(f, "set bits are not in increasing order"),
+            Error::UnknownBitmapType(x) => write!(f, "unknown bitmap type {}", x),
+            Error::UnknownMetadataCompression(x) => write!(f, "unknown metadata compression {}", x),
+            Error::MissingReference => write!(f, "block refers to a block that is not in the input"),
+            Error::ZstdContainer => write!(f, "record is in a zstd-seekable container"),
+            Error::TargetChunks => write!(f, "record splits the targets of its blocks across sub-blocks"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl From<bincode::error::DecodeError> for Error {
+    fn from(_: bincode::error::DecodeError) -> Self {
+        Error::InvalidMetadata
+    }
+}
@@ -0,0 +1,237 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! File header and the fields of the file flags that every decoder needs.
+
+use crate::Error;
+use crate::gzip::inflate;
+
+use alloc::vec::Vec;
+
+use bincode::{Encode, Decode};
+use bincode::decode_from_slice;
+use bincode::encode_to_vec;
+
+/// Bytes at the start of every .ahda file, spelling "ahda" in ASCII.
+pub const MAGIC: [u8; 4] = [97, 104, 100, 97];
+
+/// Length of an encoded [FileHeader].
+pub const FILE_HEADER_LEN: usize = 32;
+
+/// Every record has a query name, see [FileHeader::fields_present].
+pub const MASK_QUERIES: u16 = 1 << 0;
+/// Every record has a query id, see [FileHeader::fields_present].
+pub const MASK_QUERY_IDS: u16 = 1 << 1;
+/// Every block records the mate of its records, see [FileHeader::fields_present].
+pub const MASK_MATES: u16 = 1 << 2;
+/// Every block has a Bloom filter of its query names, see [FileHeader::fields_present].
+pub const MASK_QUERY_FILTER: u16 = 1 << 3;
+
+/// Everything after the [FileHeader] is in a zstd-seekable container.
+///
+/// Set in the `fields_present` field of the [FileHeader]. The bit is not a
+/// block flags field and is never set in the block headers.
+pub const FILE_IS_ZSTD_SEEKABLE: u16 = 1 << 15;
+
+/// Blocks split their targets across consecutive sub-blocks.
+///
+/// Set in the `fields_present` field of the [FileHeader]. Each row of
+/// queries is stored as one sub-block per range of targets, and the number
+/// of targets in a range is stored in the file flags. Like
+/// [FILE_IS_ZSTD_SEEKABLE], the bit is never set in the block headers, so
+/// readers that check the block fields against the file header reject these
+/// files instead of misreading them.
+pub const FILE_HAS_TARGET_CHUNKS: u16 = 1 << 14;
+
+/// File flags are stored as bincode, see [FileHeader::metadata_compression].
+pub const METADATA_BINCODE: u8 = 0;
+/// File flags are stored as gzipped bincode, see [FileHeader::metadata_compression].
+pub const METADATA_GZIP: u8 = 1;
+
+/// File header for encoded data
+///
+/// Always the first 32 bytes at the beginning of a .ahda file.
+///
+/// Must always conform to this format.
+///
+/// The FileHeader contains the following data:
+/// - Bytes identifying the data as a .ahda file.
+/// - Bytes providing the ahda library version.
+/// - The metadata compression method used for the file flags.
+/// - Fields that must be present in the flags of every block that follows.
+/// - Number of target sequences in the alignment.
+/// - Number of query sequences in the alignment. This may be 0 if the number was not known in advance.
+/// - Type of bitmap stored in the blocks. This may differ for each block if they were not generated with the ahda encode API.
+/// - Number of records stored in each block. This may be lower for each individual block.
+/// - The number of bytes containing the file flags that follow the header.
+///
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct FileHeader {
+    /// Ahda header, consists of four ASCII bytes spelling "ahda" and two bytes specifying the version.
+    ///
+    /// First four bytes can be used to check that a binary record is an ahda record.
+    /// Next two bytes can be used to check which version of ahda was used to generate this file.
+    pub ahda_header: [u8; 6], // = [97, 104, 100, 97, ...];
+
+    /// File format version, indicates (in)compatible versions of the file format.
+    pub file_format: u8,
+
+    /// Compression method used for the file flags, [METADATA_BINCODE] or [METADATA_GZIP].
+    pub metadata_compression: u8,
+
+    /// Fields of the block flags that must be present for all blocks in this
+    /// file, see [MASK_QUERIES] and the other masks.
+    ///
+    /// Every block must contain the fields specified here. Blocks may include
+    /// additional fields.
+    pub fields_present: u16,
+
+    /// Number of alignment targets, this must match the length of `target_names` in the file flags.
+    pub n_targets: u32,
+
+    /// Number of query sequences, this should be greater or equal to the sum of all `num_records` in the blocks.
+    ///
+    /// Can be set to 0 if the number is not known in advance.
+    pub n_queries: u32,
+
+    /// Bitmap type used to encode blocks in this file, see [BlockHeader::bitmap_type](crate::block::BlockHeader::bitmap_type).
+    ///
+    /// The address space of the file bitmap type must be large enough to store
+    /// data from all blocks in this file. The block bitmap types may differ
+    /// from the file bitmap type.
+    pub bitmap_type: u16,
+
+    /// Block size (number of records) used to encode blocks in this file. Actual number of records per block may be different.
+    pub block_size: u32,
+
+    /// Number of bytes in the file flags that follow the header bytes.
+    pub flags_len: u64,
+}
+
+impl FileHeader {
+    pub fn promises_query_names(
+        &self,
+    ) -> bool {
+        (self.fields_present & MASK_QUERIES) != 0
+    }
+
+    pub fn promises_query_ids(
+        &self,
+    ) -> bool {
+        (self.fields_present & MASK_QUERY_IDS) != 0
+    }
+
+    /// Check if the rest of the record is in a zstd-seekable container, see
+    /// [FILE_IS_ZSTD_SEEKABLE].
+    pub fn is_zstd_seekable(
+        &self,
+    ) -> bool {
+        (self.fields_present & FILE_IS_ZSTD_SEEKABLE) != 0
+    }
+
+    /// Check if the blocks split their targets across sub-blocks, see
+    /// [FILE_HAS_TARGET_CHUNKS].
+    pub fn has_target_chunks(
+        &self,
+    ) -> bool {
+        (self.fields_present & FILE_HAS_TARGET_CHUNKS) != 0
+    }
+}
+
+/// Encode `header` as [FILE_HEADER_LEN] bytes.
+pub fn encode_file_header(
+    header: &FileHeader,
+) -> Vec<u8> {
+    let bytes = encode_to_vec(header, bincode::config::standard().with_fixed_int_encoding()).unwrap();
+    assert_eq!(bytes.len(), FILE_HEADER_LEN);
+    bytes
+}
+
+/// Decode the [FileHeader] at the start of `bytes`.
+///
+/// ## Errors and panics
+/// Errors with [Error::Truncated] if `bytes` is shorter than
+/// [FILE_HEADER_LEN] and with [Error::NotAhda] if it doesn't start with
+/// [MAGIC].
+///
+pub fn decode_file_header(
+    bytes: &[u8],
+) -> Result<FileHeader, Error> {
+    let header_bytes = bytes.get(0..FILE_HEADER_LEN).ok_or(Error::Truncated)?;
+    if header_bytes[0..4] != MAGIC {
+        return Err(Error::NotAhda)
+    }
+    Ok(decode_from_slice(header_bytes, bincode::config::standard().with_fixed_int_encoding())?.0)
+}
+
+/// Get the query file name and the target names from the file flags.
+///
+/// `flags` are the `flags_len` bytes after the header and
+/// `metadata_compression` is the field of the same name in the
+/// [FileHeader]. The other fields of the flags are ignored.
+///
+/// ## Errors and panics
+/// Errors with [Error::UnknownMetadataCompression] if the compression method
+/// is unknown and with [Error::InvalidMetadata] if the flags can't be decoded.
+///
+pub fn decode_names(
+    flags: &[u8],
+    metadata_compression: u8,
+) -> Result<(Vec<u8>, Vec<Vec<u8>>), Error> {
+    let inflated;
+    let bytes = match metadata_compression {
+        METADATA_BINCODE => flags,
+        METADATA_GZIP => {
+            inflated = inflate(flags)?;
+            &inflated
+        },
+        x => return Err(Error::UnknownMetadataCompression(x)),
+    };
+    Ok(decode_from_slice(bytes, bincode::config::standard().with_limit::<{ crate::METADATA_LIMIT }>())?.0)
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn file_header_round_trip() {
+        use super::decode_file_header;
+        use super::encode_file_header;
+        use super::FileHeader;
+        use crate::Error;
+
+        let header = FileHeader{ ahda_header: [97, 104, 100, 97, 0, 0], file_format: 0, metadata_compression: 1, fields_present: 3, n_targets: 2, n_queries: 5, bitmap_type: 0, block_size: 65536, flags_len: 40 };
+        let bytes = encode_file_header(&header);
+        assert_eq!(bytes[0..8], [97, 104, 100, 97, 0, 0, 0, 1]);
+        assert_eq!(decode_file_header(&bytes).unwrap(), header);
+
+        assert_eq!(decode_file_header(&bytes[0..31]), Err(Error::Truncated));
+        assert_eq!(decode_file_header(&[0; 32]), Err(Error::NotAhda));
+    }
+
+    #[test]
+    fn decode_names_ignores_later_fields() {
+        use super::decode_names;
+        use super::METADATA_GZIP;
+        use crate::gzip::deflate;
+        use alloc::vec;
+        use alloc::vec::Vec;
+
+        let names: (Vec<u8>, Vec<Vec<u8>>) = (b"sample".to_vec(), vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()]);
+        let mut bytes = bincode::encode_to_vec(&names, bincode::config::standard()).unwrap();
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(decode_names(&deflate(&bytes, 6), METADATA_GZIP).unwrap(), names);
+    }
+}
@@ -0,0 +1,108 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Gzip members on byte slices.
+//!
+//! Block payloads and flags are single gzip members. This module reads and
+//! writes them with [miniz_oxide], which needs no std. The ahda crate writes
+//! the members with flate2 instead; the output of both can be read by either.
+
+use crate::Error;
+
+use alloc::vec::Vec;
+
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+/// Gzip `bytes` using compression `level` from 1 (fastest) to 9 (smallest).
+pub fn deflate(
+    bytes: &[u8],
+    level: u8,
+) -> Vec<u8> {
+    let mut deflated: Vec<u8> = Vec::with_capacity(bytes.len() + 18);
+    deflated.extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255]);
+    deflated.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(bytes, level));
+    deflated.extend_from_slice(&crc32fast::hash(bytes).to_le_bytes());
+    deflated.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    deflated
+}
+
+/// Gunzip a single gzip member.
+///
+/// The output is limited to the uncompressed size stored at the end of the
+/// member, so corrupted input can't allocate more memory than it claims to
+/// contain.
+///
+/// ## Errors and panics
+/// Errors with [Error::InvalidGzip] if `deflated` is not a gzip member or if
+/// its checksum or size don't match the contents.
+///
+pub fn inflate(
+    deflated: &[u8],
+) -> Result<Vec<u8>, Error> {
+    if deflated.len() < 18 || deflated[0..3] != [0x1f, 0x8b, 8] {
+        return Err(Error::InvalidGzip)
+    }
+    let flags = deflated[3];
+    let mut start: usize = 10;
+    if flags & FEXTRA != 0 {
+        let len = deflated.get(start..(start + 2)).ok_or(Error::InvalidGzip)?;
+        start += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let len = deflated.get(start..).and_then(|x| x.iter().position(|x| *x == 0)).ok_or(Error::InvalidGzip)?;
+            start += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        start += 2;
+    }
+    let end = deflated.len() - 8;
+    let data = deflated.get(start..end).ok_or(Error::InvalidGzip)?;
+
+    let crc = u32::from_le_bytes(deflated[end..(end + 4)].try_into().unwrap());
+    let size = u32::from_le_bytes(deflated[(end + 4)..].try_into().unwrap());
+    let inflated = miniz_oxide::inflate::decompress_to_vec_with_limit(data, size as usize).map_err(|_| Error::InvalidGzip)?;
+    if inflated.len() != size as usize || crc32fast::hash(&inflated) != crc {
+        return Err(Error::InvalidGzip)
+    }
+    Ok(inflated)
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn deflate_and_inflate() {
+        use super::deflate;
+        use super::inflate;
+        use crate::Error;
+
+        let bytes: alloc::vec::Vec<u8> = (0..1000_u32).map(|x| (x % 7) as u8).collect();
+        let deflated = deflate(&bytes, 6);
+        assert!(deflated.len() < bytes.len());
+        assert_eq!(inflate(&deflated).unwrap(), bytes);
+
+        assert_eq!(inflate(&deflate(&[], 1)).unwrap(), alloc::vec::Vec::<u8>::new());
+
+        let mut corrupted = deflated.clone();
+        let last = corrupted.len() - 5;
+        corrupted[last] ^= 1;
+        assert_eq!(inflate(&corrupted), Err(Error::InvalidGzip));
+        assert_eq!(inflate(&deflated[0..12]), Err(Error::InvalidGzip));
+    }
+}
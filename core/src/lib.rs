@@ -0,0 +1,98 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Allocation-only codec for the .ahda format.
+//!
+//! ahda-core reads and writes the parts of an .ahda record that have a fixed
+//! layout on byte slices, without std:
+//! - The [file header](file::FileHeader) and the target names in the file flags.
+//! - The [block header](block::BlockHeader), the layout of a block, and the
+//!   query names and ids in the block flags.
+//! - [Gzip](gzip) members and [roaring bitmaps](roaring) in the block payloads.
+//!
+//! This is enough to decode the alignments in a record in eg. a wasm module
+//! or another runtime that has an allocator but no std. The
+//! [ahda](https://docs.rs/ahda) crate wraps these functions in its Read and
+//! Write based API and re-exports the header types.
+//!
+//! Records in a zstd-seekable container and records whose blocks split
+//! their targets across sub-blocks are not supported.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda_core::block::Block;
+//! use ahda_core::file::decode_names;
+//! use ahda_core::split_record;
+//!
+//! fn print_record(bytes: &[u8]) -> Result<(), ahda_core::Error> {
+//!     let (header, flags, blocks) = split_record(bytes)?;
+//!     let (_, target_names) = decode_names(flags, header.metadata_compression)?;
+//!     for block in blocks {
+//!         let block: Block = block?;
+//!         let (_, query_ids) = block.queries()?;
+//!         for bit in block.bits()? {
+//!             let query_id = bit / header.n_targets as u64;
+//!             let target = &target_names[(bit % header.n_targets as u64) as usize];
+//!             // ...
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod block;
+pub mod error;
+pub mod file;
+pub mod gzip;
+pub mod roaring;
+
+pub use error::Error;
+
+/// Most bytes that decoding the file or block flags may allocate.
+///
+/// The lengths of the names and other fields are read from the input, so a
+/// corrupted length must not be able to allocate an arbitrary amount of memory.
+pub const METADATA_LIMIT: usize = 1 << 30;
+
+/// Split an .ahda record into the file header, the file flags, and the blocks.
+///
+/// The file flags are the encoded bytes, see
+/// [decode_names](file::decode_names).
+///
+/// ## Errors and panics
+/// Errors if the file header can't be decoded, if `bytes` ends before the
+/// file flags, with [Error::ZstdContainer] if the record is in a
+/// zstd-seekable container, and with [Error::TargetChunks] if its blocks
+/// split their targets across sub-blocks.
+///
+pub fn split_record(
+    bytes: &[u8],
+) -> Result<(file::FileHeader, &[u8], block::Blocks<'_>), Error> {
+    let header = file::decode_file_header(bytes)?;
+    if header.is_zstd_seekable() {
+        return Err(Error::ZstdContainer)
+    }
+    if header.has_target_chunks() {
+        return Err(Error::TargetChunks)
+    }
+    let flags_end = usize::try_from(header.flags_len).ok().and_then(|x| x.checked_add(file::FILE_HEADER_LEN)).ok_or(Error::Truncated)?;
+    let flags = bytes.get(file::FILE_HEADER_LEN..flags_end).ok_or(Error::Truncated)?;
+    Ok((header, flags, block::Blocks::new(&bytes[flags_end..])))
+}
@@ -0,0 +1,280 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Roaring bitmaps in the portable serialization format.
+//!
+//! Block payloads are roaring bitmaps (32-bit address space) or roaring
+//! treemaps (64-bit address space) serialized in the [portable
+//! format](https://github.com/RoaringBitmap/RoaringFormatSpec) used by the
+//! roaring crate. The functions here convert between the serialized bytes and
+//! sorted arrays of set bits without the roaring crate, which needs std for
+//! serialization.
+//!
+//! The serializers never write run containers, so their output is larger
+//! than that of an optimized roaring bitmap. The deserializers read all
+//! container types.
+
+use crate::Error;
+
+use alloc::vec::Vec;
+
+const SERIAL_COOKIE_NO_RUNCONTAINER: u32 = 12346;
+const SERIAL_COOKIE: u16 = 12347;
+const NO_OFFSET_THRESHOLD: usize = 4;
+const ARRAY_LIMIT: usize = 4096;
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(
+        &mut self,
+        n: usize,
+    ) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(n).ok_or(Error::InvalidBitmap)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(Error::InvalidBitmap)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Read one serialized roaring bitmap from `cursor`, adding `high` to each bit.
+fn read_bitmap(
+    cursor: &mut Cursor,
+    high: u64,
+    bits: &mut Vec<u64>,
+) -> Result<(), Error> {
+    let cookie = cursor.u32()?;
+    let (size, has_offsets, run_bitmap) = if cookie == SERIAL_COOKIE_NO_RUNCONTAINER {
+        (cursor.u32()? as usize, true, &[][..])
+    } else if cookie as u16 == SERIAL_COOKIE {
+        let size = (cookie >> 16) as usize + 1;
+        (size, size >= NO_OFFSET_THRESHOLD, cursor.take(size.div_ceil(8))?)
+    } else {
+        return Err(Error::InvalidBitmap)
+    };
+
+    let descriptions = cursor.take(size.checked_mul(4).ok_or(Error::InvalidBitmap)?)?;
+    if has_offsets {
+        cursor.take(size * 4)?;
+    }
+
+    let start = bits.len();
+    for (i, description) in descriptions.chunks_exact(4).enumerate() {
+        let key = high | (u16::from_le_bytes([description[0], description[1]]) as u64) << 16;
+        let len = u16::from_le_bytes([description[2], description[3]]) as usize + 1;
+        if run_bitmap.get(i / 8).is_some_and(|x| x & (1 << (i % 8)) != 0) {
+            let n_runs = cursor.u16()? as usize;
+            for run in cursor.take(n_runs * 4)?.chunks_exact(4) {
+                let run_start = u16::from_le_bytes([run[0], run[1]]) as u64;
+                let run_end = run_start + u16::from_le_bytes([run[2], run[3]]) as u64;
+                if run_end > u16::MAX as u64 {
+                    return Err(Error::InvalidBitmap)
+                }
+                bits.extend((run_start..=run_end).map(|x| key | x));
+            }
+        } else if len <= ARRAY_LIMIT {
+            bits.extend(cursor.take(len * 2)?.chunks_exact(2).map(|x| key | u16::from_le_bytes([x[0], x[1]]) as u64));
+        } else {
+            for (j, word) in cursor.take(8192)?.chunks_exact(8).enumerate() {
+                let mut word = u64::from_le_bytes(word.try_into().unwrap());
+                while word != 0 {
+                    bits.push(key | (j as u64 * 64 + word.trailing_zeros() as u64));
+                    word &= word - 1;
+                }
+            }
+        }
+    }
+
+    if bits[start..].windows(2).any(|x| x[0] >= x[1]) {
+        return Err(Error::InvalidBitmap)
+    }
+    Ok(())
+}
+
+/// Write `bits`, which all share the upper 32 bits, as one roaring bitmap.
+fn write_bitmap(
+    bits: &[u64],
+    bytes: &mut Vec<u8>,
+) {
+    let mut containers: Vec<&[u64]> = Vec::new();
+    let mut rest = bits;
+    while let Some(first) = rest.first() {
+        let len = rest.partition_point(|x| x >> 16 == first >> 16);
+        containers.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    bytes.extend_from_slice(&SERIAL_COOKIE_NO_RUNCONTAINER.to_le_bytes());
+    bytes.extend_from_slice(&(containers.len() as u32).to_le_bytes());
+    for container in containers.iter() {
+        bytes.extend_from_slice(&((container[0] >> 16) as u16).to_le_bytes());
+        bytes.extend_from_slice(&((container.len() - 1) as u16).to_le_bytes());
+    }
+    let mut offset = 8 + 8 * containers.len() as u32;
+    for container in containers.iter() {
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        offset += if container.len() <= ARRAY_LIMIT { container.len() as u32 * 2 } else { 8192 };
+    }
+    for container in containers.iter() {
+        if container.len() <= ARRAY_LIMIT {
+            container.iter().for_each(|x| bytes.extend_from_slice(&(*x as u16).to_le_bytes()));
+        } else {
+            let mut words: [u64; 1024] = [0; 1024];
+            container.iter().for_each(|x| words[(*x as u16 / 64) as usize] |= 1 << (x % 64));
+            words.iter().for_each(|x| bytes.extend_from_slice(&x.to_le_bytes()));
+        }
+    }
+}
+
+/// Get the set bits of a serialized roaring bitmap in increasing order.
+///
+/// ## Errors and panics
+/// Errors with [Error::InvalidBitmap] if `bytes` is not a serialized roaring
+/// bitmap.
+///
+pub fn deserialize_roaring32(
+    bytes: &[u8],
+) -> Result<Vec<u32>, Error> {
+    let mut bits: Vec<u64> = Vec::new();
+    read_bitmap(&mut Cursor{ bytes, pos: 0 }, 0, &mut bits)?;
+    Ok(bits.into_iter().map(|x| x as u32).collect())
+}
+
+/// Get the set bits of a serialized roaring treemap in increasing order.
+///
+/// ## Errors and panics
+/// Errors with [Error::InvalidBitmap] if `bytes` is not a serialized roaring
+/// treemap.
+///
+pub fn deserialize_roaring64(
+    bytes: &[u8],
+) -> Result<Vec<u64>, Error> {
+    let mut cursor = Cursor{ bytes, pos: 0 };
+    let mut bits: Vec<u64> = Vec::new();
+    let n_bitmaps = cursor.u64()?;
+    for _ in 0..n_bitmaps {
+        let high = cursor.u32()? as u64;
+        let start = bits.len();
+        read_bitmap(&mut cursor, high << 32, &mut bits)?;
+        if start > 0 && bits.get(start).is_some_and(|x| *x <= bits[start - 1]) {
+            return Err(Error::InvalidBitmap)
+        }
+    }
+    Ok(bits)
+}
+
+/// Serialize strictly increasing `bits` as a roaring bitmap.
+///
+/// ## Errors and panics
+/// Errors with [Error::NotSorted] if `bits` is not strictly increasing.
+///
+pub fn serialize_roaring32(
+    bits: &[u32],
+) -> Result<Vec<u8>, Error> {
+    if bits.windows(2).any(|x| x[0] >= x[1]) {
+        return Err(Error::NotSorted)
+    }
+    let bits: Vec<u64> = bits.iter().map(|x| *x as u64).collect();
+    let mut bytes: Vec<u8> = Vec::new();
+    write_bitmap(&bits, &mut bytes);
+    Ok(bytes)
+}
+
+/// Serialize strictly increasing `bits` as a roaring treemap.
+///
+/// ## Errors and panics
+/// Errors with [Error::NotSorted] if `bits` is not strictly increasing.
+///
+pub fn serialize_roaring64(
+    bits: &[u64],
+) -> Result<Vec<u8>, Error> {
+    if bits.windows(2).any(|x| x[0] >= x[1]) {
+        return Err(Error::NotSorted)
+    }
+    let mut bitmaps: Vec<&[u64]> = Vec::new();
+    let mut rest = bits;
+    while let Some(first) = rest.first() {
+        let len = rest.partition_point(|x| x >> 32 == first >> 32);
+        bitmaps.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(&(bitmaps.len() as u64).to_le_bytes());
+    for bitmap in bitmaps {
+        bytes.extend_from_slice(&((bitmap[0] >> 32) as u32).to_le_bytes());
+        write_bitmap(bitmap, &mut bytes);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn roaring32_round_trip() {
+        use super::deserialize_roaring32;
+        use super::serialize_roaring32;
+        use crate::Error;
+        use alloc::vec;
+        use alloc::vec::Vec;
+
+        // One array container, one bitmap container, and an empty bitmap
+        let mut bits: Vec<u32> = vec![1, 7, 65535];
+        bits.extend((1 << 16)..((1 << 16) + 5000));
+        let bytes = serialize_roaring32(&bits).unwrap();
+        assert_eq!(bytes.len(), 8 + 2 * 8 + 3 * 2 + 8192);
+        assert_eq!(deserialize_roaring32(&bytes).unwrap(), bits);
+
+        assert_eq!(deserialize_roaring32(&serialize_roaring32(&[]).unwrap()).unwrap(), Vec::<u32>::new());
+        assert_eq!(serialize_roaring32(&[2, 1]), Err(Error::NotSorted));
+        assert_eq!(deserialize_roaring32(&bytes[0..20]), Err(Error::InvalidBitmap));
+    }
+
+    #[test]
+    fn roaring64_round_trip() {
+        use super::deserialize_roaring64;
+        use super::serialize_roaring64;
+        use alloc::vec;
+
+        let bits: alloc::vec::Vec<u64> = vec![0, 3, 1 << 32, (1 << 40) + 2];
+        let bytes = serialize_roaring64(&bits).unwrap();
+        assert_eq!(deserialize_roaring64(&bytes).unwrap(), bits);
+    }
+
+    #[test]
+    fn deserialize_run_containers() {
+        use super::deserialize_roaring32;
+        use alloc::vec;
+
+        // Runs 0..=2 and 10..=10 in the first container, written with runs by the roaring crate
+        let bytes: [u8; 19] = [59, 48, 0, 0, 1, 0, 0, 3, 0, 2, 0, 0, 0, 2, 0, 10, 0, 0, 0];
+        assert_eq!(deserialize_roaring32(&bytes).unwrap(), vec![0, 1, 2, 10]);
+    }
+}
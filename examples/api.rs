@@ -0,0 +1,158 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Chain the streaming structs of the library.
+//!
+//! Parses Themisto output with a [Parser] and encodes the records with an
+//! [Encoder] one block at a time, decodes the blocks with a [Decoder] and
+//! prints the records that have hits with a [Printer], and counts the reads
+//! aligned to each target with a [BitmapDecoder].
+//!
+//! Each step uses the iterators that return a [Result] for every record, so
+//! that a bad line or record is reported and skipped instead of ending the
+//! stream or panicking.
+//!
+//! Run with `cargo run --example api`.
+
+use ahda::Format;
+use ahda::PseudoAln;
+use ahda::decoder::Decoder;
+use ahda::decoder::bitmap_decoder::BitmapDecoder;
+use ahda::encoder::Encoder;
+use ahda::headers::file::FileHeader;
+use ahda::parser::Parser;
+use ahda::printer::Printer;
+
+use std::io::Write;
+
+use roaring::RoaringBitmap;
+
+type E = Box<dyn std::error::Error>;
+
+// Themisto output of 6 reads against 3 targets, the fourth line is broken
+const THEMISTO: &[u8] = b"0 2\n1\n2 0 2\n3 0 x\n4 0 1 2\n5 1\n";
+
+/// Reports and drops the records that can't be read.
+struct SkipErrors<I: Iterator<Item=Result<PseudoAln, E>>> {
+    inner: I,
+    n_skipped: usize,
+}
+
+impl<I: Iterator<Item=Result<PseudoAln, E>>> Iterator for SkipErrors<I> {
+    type Item = PseudoAln;
+
+    fn next(
+        &mut self,
+    ) -> Option<PseudoAln> {
+        loop {
+            match self.inner.next()? {
+                Ok(record) => return Some(record),
+                Err(e) => {
+                    eprintln!("api: skipping record: {}", e);
+                    self.n_skipped += 1;
+                },
+            }
+        }
+    }
+}
+
+/// Parse `input` and encode it into an .ahda record with two records per block.
+fn encode(
+    input: &[u8],
+    targets: &[Vec<u8>],
+    queries: &[Vec<u8>],
+) -> Result<Vec<u8>, E> {
+    let mut conn = input;
+    let mut t_it = targets.iter().cloned();
+    let mut q_it = queries.iter().cloned();
+    let mut parser = Parser::new(&mut conn, Some(&mut q_it), Some(&mut t_it))?;
+    let targets = parser.get_targets().unwrap();
+
+    let mut records = SkipErrors{ inner: parser.try_iter(), n_skipped: 0 };
+    let mut encoder = Encoder::new(&mut records, &targets, b"sample", queries.len());
+    encoder.set_block_size(2)?;
+    encoder.set_fields_present(3_u16); // Have query_names and query_ids
+
+    let mut bytes = encoder.encode_file_header_and_flags()?;
+    // Each item is a block, or the error from a record that can't be encoded
+    for block in encoder.by_ref() {
+        bytes.extend(block?);
+    }
+    eprintln!("api: encoded {} bytes, skipped {} lines", bytes.len(), records.n_skipped);
+    Ok(bytes)
+}
+
+/// Print the records of `bytes` that have hits in Metagraph format to `conn`.
+///
+/// Returns the header of the file and the records that were printed.
+fn print_hits<W: Write>(
+    mut bytes: &[u8],
+    conn: &mut W,
+) -> Result<(FileHeader, Vec<PseudoAln>), E> {
+    let mut decoder = Decoder::try_new(&mut bytes)?;
+    let header = decoder.file_header().clone();
+    let flags = decoder.file_flags().clone();
+
+    // Any iterator over PseudoAln can be put between the Decoder and the Printer
+    let hits: Vec<PseudoAln> = SkipErrors{ inner: decoder.try_iter(), n_skipped: 0 }
+        .filter(|record| record.ones.as_ref().is_some_and(|x| !x.is_empty()))
+        .collect();
+
+    let mut records = hits.clone().into_iter();
+    let mut printer = Printer::new_from_header_and_flags(&mut records, header.clone(), flags, Format::Metagraph);
+    while let Some(line) = printer.try_next() {
+        conn.write_all(&line?)?;
+    }
+    Ok((header, hits))
+}
+
+/// Count the reads aligned to each target from the set bits of `records`.
+///
+/// Bit `query_id * n_targets + target_id` is set if the query aligned to the target.
+fn count_reads(
+    header: FileHeader,
+    records: &[PseudoAln],
+) -> Vec<usize> {
+    let n_targets = header.n_targets;
+    let bitmap: RoaringBitmap = records.iter().flat_map(|record| {
+        let query_id = record.query_id.unwrap();
+        record.ones.iter().flatten().map(move |target_id| query_id * n_targets + target_id)
+    }).collect();
+
+    let mut counts: Vec<usize> = vec![0; n_targets as usize];
+    let mut bits = bitmap.iter().map(|x| x as u64);
+    BitmapDecoder::new(&mut bits, header).for_each_row(|_, target_ids| {
+        target_ids.iter().for_each(|target_id| counts[*target_id as usize] += 1);
+    });
+    counts
+}
+
+fn main() -> Result<(), E> {
+    let targets: Vec<Vec<u8>> = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+    let queries: Vec<Vec<u8>> = (1..=6).map(|i| format!("r{}", i).into_bytes()).collect();
+
+    let bytes = encode(THEMISTO, &targets, &queries)?;
+
+    let mut stdout = std::io::stdout();
+    let (header, hits) = print_hits(&bytes, &mut stdout)?;
+    assert_eq!(hits.iter().map(|x| x.query_id.unwrap()).collect::<Vec<u32>>(), vec![0, 2, 4, 5]);
+
+    let counts = count_reads(header, &hits);
+    for (name, count) in targets.iter().zip(counts.iter()) {
+        writeln!(stdout, "{}\t{}", String::from_utf8_lossy(name), count)?;
+    }
+    assert_eq!(counts, vec![2, 2, 3]);
+
+    Ok(())
+}
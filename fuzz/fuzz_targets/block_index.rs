@@ -0,0 +1,34 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Index the blocks of a .ahda file from arbitrary bytes and read them.
+//!
+//! Run with `cargo fuzz run block_index`.
+
+#![no_main]
+
+use ahda::decoder::block_index::BlockIndex;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(index) = BlockIndex::new(data) {
+        for block_idx in 0..index.n_blocks() {
+            let _ = index.block_flags(block_idx);
+            let _ = index.block_bitmap(block_idx);
+        }
+        let _ = index.find_query(0);
+        let _ = index.query_range_bitmap(0, 16);
+    }
+});
@@ -0,0 +1,34 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Split a .ahda file from arbitrary bytes with ahda-core and read its blocks.
+//!
+//! Run with `cargo fuzz run core_record`.
+
+#![no_main]
+
+use ahda_core::file::decode_names;
+use ahda_core::split_record;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((header, flags, blocks)) = split_record(data) {
+        let _ = decode_names(flags, header.metadata_compression);
+        for block in blocks.flatten() {
+            let _ = block.queries();
+            let _ = block.bits();
+        }
+    }
+});
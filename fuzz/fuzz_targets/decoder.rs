@@ -0,0 +1,32 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Decode all records of a .ahda file from arbitrary bytes.
+//!
+//! Run with `cargo fuzz run decoder`.
+
+#![no_main]
+
+use ahda::decoder::Decoder;
+
+use libfuzzer_sys::fuzz_target;
+
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let mut conn = Cursor::new(data);
+    if let Ok(mut decoder) = Decoder::try_new(&mut conn) {
+        decoder.try_iter().for_each(|_| ());
+    }
+});
@@ -0,0 +1,31 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Decode a file header and flags from arbitrary bytes.
+//!
+//! Run with `cargo fuzz run file_header`.
+
+#![no_main]
+
+use ahda::headers::file::decode_file_header_and_flags;
+use ahda::headers::file::read_file_header_and_flags;
+
+use libfuzzer_sys::fuzz_target;
+
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_file_header_and_flags(data);
+    let _ = read_file_header_and_flags(&mut Cursor::new(data));
+});
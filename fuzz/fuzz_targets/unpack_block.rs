@@ -0,0 +1,46 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Unpack a block read from arbitrary bytes as both bitmap types.
+//!
+//! Run with `cargo fuzz run unpack_block`.
+
+#![no_main]
+
+use ahda::compression::dedup::BlockCache;
+use ahda::compression::roaring32::unpack_block_roaring32;
+use ahda::compression::roaring64::unpack_block_roaring64;
+use ahda::headers::block::decode_block_header;
+
+use libfuzzer_sys::fuzz_target;
+
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    // Header lengths that don't match the bytes
+    if let (Some(header_bytes), Some(bytes)) = (data.get(0..32), data.get(32..)) {
+        if let Ok(header) = decode_block_header(header_bytes) {
+            let _ = unpack_block_roaring32(bytes, &header);
+            let _ = unpack_block_roaring64(bytes, &header);
+        }
+    }
+
+    // Blocks resolved through the block cache
+    let mut cache = BlockCache::new();
+    let mut conn = Cursor::new(data);
+    while let Ok(Some((header, bytes))) = cache.try_read_block(&mut conn) {
+        let _ = unpack_block_roaring32(&bytes, &header);
+        let _ = unpack_block_roaring64(&bytes, &header);
+    }
+});
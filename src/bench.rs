@@ -0,0 +1,225 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Compare the .ahda encoding of plain text against gzip and zstd of the text.
+//!
+//! Before adopting the format, the question is how much smaller and how
+//! much slower .ahda is than compressing the aligner output as is, and
+//! whether it kept every alignment. [bench_file] encodes the input, decodes
+//! the result, compresses the plain text with gzip and zstd at their default
+//! levels, and checks that the [digest](crate::digest_from_read) of the
+//! encoded data matches the [digest](crate::digest_plain_text_from_read) of
+//! the input. [Bench::report] lists the sizes, compression ratios, and wall
+//! times.
+//!
+//! The zstd baseline requires the `zstd` feature and is missing without it.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::bench::bench_file;
+//! use ahda::EncodeOpts;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+//! let input = b"0 0 1\n1 1\n2\n";
+//!
+//! let bench = bench_file(input, Some(&targets), Some(&queries), EncodeOpts::default()).unwrap();
+//! assert_eq!(bench.input_bytes, input.len());
+//! assert_eq!(bench.n_records, 3);
+//! assert!(bench.ahda.bytes > 0 && bench.gzip.bytes > 0);
+//! ```
+//!
+
+use crate::EncodeOpts;
+use crate::compression::gzwrapper::deflate_bytes;
+use crate::compression::gzwrapper::inflate_bytes;
+use crate::report::Report;
+use crate::report::Value;
+
+use std::time::Duration;
+use std::time::Instant;
+
+type E = Box<dyn std::error::Error>;
+
+/// Zstd compression level of the zstd baseline.
+pub const ZSTD_BASELINE_LEVEL: i32 = 3;
+
+/// Size and wall time of one way to compress the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    /// Size of the compressed input in bytes.
+    pub bytes: usize,
+    /// Time taken to compress the input.
+    pub compress_time: Duration,
+    /// Time taken to decompress the compressed input.
+    pub decompress_time: Duration,
+}
+
+/// Result of [bench_file].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bench {
+    /// Size of the plain text input in bytes.
+    pub input_bytes: usize,
+    /// Number of records in the encoded input.
+    pub n_records: usize,
+    /// .ahda encoding with [encode_from_read_to_write](crate::encode_from_read_to_write) and decoding with [decode_from_read](crate::decode_from_read).
+    pub ahda: Measurement,
+    /// Gzip of the plain text at the [default level](crate::compression::gzwrapper::DEFAULT_LEVEL).
+    pub gzip: Measurement,
+    /// Zstd of the plain text at [ZSTD_BASELINE_LEVEL], None if ahda was
+    /// compiled without the `zstd` feature.
+    pub zstd: Option<Measurement>,
+    /// Digest of the alignments that both the input and the encoded data have.
+    pub digest: [u8; 32],
+}
+
+impl Bench {
+    /// Sizes, compression ratios, and times in seconds of each method, and the digest.
+    ///
+    /// Each method is a `method` row with its name, size in bytes, ratio of
+    /// the input size to the compressed size, and the compression and
+    /// decompression times.
+    pub fn report(
+        &self,
+    ) -> Report {
+        let mut report = Report::default();
+        report.field("input_bytes", Value::number(self.input_bytes));
+        report.field("records", Value::number(self.n_records));
+        let row = |name: &str, x: Option<&Measurement>| {
+            let ratio = x.map(|x| Value::number(format!("{:.2}", self.input_bytes as f64 / x.bytes.max(1) as f64)));
+            vec![
+                Value::text(name),
+                Value::from(x.map(|x| Value::number(x.bytes))),
+                Value::from(ratio),
+                Value::from(x.map(|x| Value::number(format!("{:.4}", x.compress_time.as_secs_f64())))),
+                Value::from(x.map(|x| Value::number(format!("{:.4}", x.decompress_time.as_secs_f64())))),
+            ]
+        };
+        report.row("method", row("ahda", Some(&self.ahda)));
+        report.row("method", row("gzip", Some(&self.gzip)));
+        report.row("method", row("zstd", self.zstd.as_ref()));
+        report.field("round_trip", Value::text("ok"));
+        report.field("digest", Value::text(self.digest.iter().map(|x| format!("{:02x}", x)).collect::<String>()));
+        report
+    }
+}
+
+/// Encode the plain text in `input` and compare it against gzip and zstd of the text.
+///
+/// `targets` and `queries` are the target and query names needed by the
+/// format of `input`, see [Parser](crate::parser::Parser). The encoding is
+/// decoded again and its [digest](crate::digest_from_read) compared to the
+/// digest of `input`.
+///
+/// ## Errors and panics
+/// Errors if `input` can't be encoded, and with
+/// [RoundTripMismatchErr](crate::errors::RoundTripMismatchErr) if the
+/// encoded data doesn't contain the same alignments as `input`.
+///
+pub fn bench_file(
+    input: &[u8],
+    targets: Option<&[Vec<u8>]>,
+    queries: Option<&[Vec<u8>]>,
+    opts: EncodeOpts,
+) -> Result<Bench, E> {
+    let mut encoded: Vec<u8> = Vec::new();
+    let start = Instant::now();
+    crate::encode_from_read_to_write(targets.map(|x| x.iter().cloned()).as_mut(), queries.map(|x| x.iter().cloned()).as_mut(), &mut &input[..], &mut encoded, opts)?;
+    let encode_time = start.elapsed();
+
+    let start = Instant::now();
+    let (_, _, records) = crate::decode_from_read(&mut encoded.as_slice())?;
+    let decode_time = start.elapsed();
+
+    let input_digest = crate::digest_plain_text_from_read(targets.map(|x| x.iter().cloned()).as_mut(), queries.map(|x| x.iter().cloned()).as_mut(), &mut &input[..])?;
+    let output_digest = crate::digest_from_read(&mut encoded.as_slice())?;
+    if input_digest != output_digest {
+        return Err(Box::new(crate::errors::RoundTripMismatchErr{ input_digest, output_digest }))
+    }
+
+    let start = Instant::now();
+    let gzipped = deflate_bytes(input)?;
+    let gzip_time = start.elapsed();
+    let start = Instant::now();
+    inflate_bytes(&gzipped)?;
+    let gunzip_time = start.elapsed();
+
+    Ok(Bench {
+        input_bytes: input.len(),
+        n_records: records.len(),
+        ahda: Measurement{ bytes: encoded.len(), compress_time: encode_time, decompress_time: decode_time },
+        gzip: Measurement{ bytes: gzipped.len(), compress_time: gzip_time, decompress_time: gunzip_time },
+        zstd: zstd_baseline(input)?,
+        digest: input_digest,
+    })
+}
+
+fn zstd_baseline(
+    input: &[u8],
+) -> Result<Option<Measurement>, E> {
+    #[cfg(feature = "zstd")]
+    {
+        let start = Instant::now();
+        let compressed = zstd::bulk::compress(input, ZSTD_BASELINE_LEVEL)?;
+        let compress_time = start.elapsed();
+        let start = Instant::now();
+        zstd::bulk::decompress(&compressed, input.len())?;
+        let decompress_time = start.elapsed();
+        Ok(Some(Measurement{ bytes: compressed.len(), compress_time, decompress_time }))
+    }
+    #[cfg(not(feature = "zstd"))]
+    {
+        let _ = input;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn bench_report_lists_methods() {
+        use super::Bench;
+        use super::Measurement;
+        use crate::report::ReportFormat;
+        use std::time::Duration;
+
+        let measurement = |bytes: usize| Measurement{ bytes, compress_time: Duration::from_millis(1500), decompress_time: Duration::from_millis(250) };
+        let bench = Bench{ input_bytes: 100, n_records: 3, ahda: measurement(40), gzip: measurement(50), zstd: None, digest: [0; 32] };
+
+        let mut tsv: Vec<u8> = Vec::new();
+        bench.report().write(&mut tsv, ReportFormat::Tsv, false).unwrap();
+        let expected = format!("input_bytes\t100\nrecords\t3\nmethod\tahda\t40\t2.50\t1.5000\t0.2500\nmethod\tgzip\t50\t2.00\t1.5000\t0.2500\nmethod\tzstd\t-\t-\t-\t-\nround_trip\tok\ndigest\t{}\n", "0".repeat(64));
+        assert_eq!(String::from_utf8(tsv).unwrap(), expected);
+    }
+
+    #[test]
+    fn bench_file_digest_matches_encoded() {
+        use super::bench_file;
+        use crate::EncodeOpts;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries: Vec<Vec<u8>> = (0..100).map(|i| format!("r{}", i).into_bytes()).collect();
+        let input: Vec<u8> = (0..100).flat_map(|i| format!("{} {}\n", i, i % 2).into_bytes()).collect();
+
+        let bench = bench_file(&input, Some(&targets), Some(&queries), EncodeOpts::default()).unwrap();
+        let mut encoded: Vec<u8> = Vec::new();
+        crate::encode_from_read_to_write(Some(&mut targets.into_iter()), Some(&mut queries.into_iter()), &mut input.as_slice(), &mut encoded, EncodeOpts::default()).unwrap();
+        assert_eq!(bench.n_records, 100);
+        assert_eq!(bench.ahda.bytes, encoded.len());
+        assert_eq!(bench.digest, crate::digest_from_read(&mut encoded.as_slice()).unwrap());
+        assert_eq!(bench.zstd.is_some(), cfg!(feature = "zstd"));
+    }
+}
@@ -0,0 +1,214 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Packing and unpacking single .ahda blocks.
+//!
+//! This module is the stable interface for programs that write or read .ahda
+//! blocks themselves, eg. aligners that produce .ahda output directly. A block
+//! written by [pack_roaring32] or [pack_roaring64] can be appended to any
+//! .ahda record whose [FileHeader](crate::headers::file::FileHeader) has the
+//! same targets and a bitmap type with a large enough address space.
+//!
+//! The functions write blocks for file format version
+//! [FORMAT_VERSION](crate::FORMAT_VERSION). Their signatures and output will
+//! not change without a new [AhdaFormatVersion](crate::AhdaFormatVersion), and
+//! blocks written by older versions of these functions can always be unpacked.
+//!
+//! Blocks are packed from a bitmap with the set bits at `query_id * n_targets
+//! + target_id`, the names of the queries in the block, and the query ids.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::blocks::{pack_roaring32, read_block, unpack_roaring32};
+//! use roaring::RoaringBitmap;
+//! use std::io::Cursor;
+//!
+//! // Two targets, query 0 aligns to target 1 and query 1 to both targets
+//! let bitmap = RoaringBitmap::from_iter([1, 2, 3]);
+//! let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+//!
+//! let bytes = pack_roaring32(&queries, &[0, 1], bitmap.clone()).unwrap();
+//!
+//! let (header, contents) = read_block(&mut Cursor::new(bytes)).unwrap();
+//! let (got, flags) = unpack_roaring32(&contents, &header).unwrap();
+//!
+//! assert_eq!(header.num_records, 2);
+//! assert_eq!(got, bitmap);
+//! assert_eq!(flags.queries, Some(queries));
+//! assert_eq!(flags.query_ids, Some(vec![0, 1]));
+//! ```
+//!
+
+pub use crate::headers::block::BlockHeader;
+pub use crate::headers::block::BlockFlags;
+
+use crate::compression::dedup::BlockCache;
+use crate::compression::roaring32::pack_block_roaring32;
+use crate::compression::roaring32::unpack_block_roaring32;
+use crate::compression::roaring64::pack_block_roaring64;
+use crate::compression::roaring64::unpack_block_roaring64;
+
+use std::io::Read;
+
+use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
+
+type E = Box<dyn std::error::Error>;
+
+fn check_queries(
+    queries: &[Vec<u8>],
+    query_ids: &[u32],
+) -> Result<(), E> {
+    if !queries.is_empty() && queries.len() != query_ids.len() {
+        return Err(Box::new(crate::errors::BlockQueryCountErr{}))
+    }
+    Ok(())
+}
+
+fn check_len(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+) -> Result<(), E> {
+    if (bytes.len() as u64) < block_header.flags_len + block_header.block_len as u64 {
+        return Err(Box::new(crate::errors::TruncatedBlockErr{}))
+    }
+    Ok(())
+}
+
+/// Pack a block using a 32-bit address space.
+///
+/// `queries` may be empty if the query names are not stored. Otherwise it
+/// must have one name for each value in `query_ids`.
+///
+/// Returns the block header, block flags, and block contents.
+///
+/// ## Errors and panics
+///
+/// Errors with [BlockQueryCountErr](crate::errors::BlockQueryCountErr) if
+/// `queries` is not empty and has a different length than `query_ids`.
+///
+pub fn pack_roaring32(
+    queries: &[Vec<u8>],
+    query_ids: &[u32],
+    bitmap: RoaringBitmap,
+) -> Result<Vec<u8>, E> {
+    check_queries(queries, query_ids)?;
+    pack_block_roaring32(queries, query_ids, bitmap)
+}
+
+/// Pack a block using a 64-bit address space.
+///
+/// See [pack_roaring32] for details.
+///
+pub fn pack_roaring64(
+    queries: &[Vec<u8>],
+    query_ids: &[u32],
+    bitmap: RoaringTreemap,
+) -> Result<Vec<u8>, E> {
+    check_queries(queries, query_ids)?;
+    pack_block_roaring64(queries, query_ids, bitmap)
+}
+
+/// Read the next block header and the bytes that follow it from [Read].
+///
+/// The returned bytes contain the block flags and block contents and can be
+/// passed to [unpack_roaring32] or [unpack_roaring64]. Use
+/// [BlockCache::read_block] instead when reading all blocks of a record that
+/// may contain [deduplicated](crate::compression::dedup) blocks.
+///
+pub fn read_block<R: Read>(
+    conn: &mut R,
+) -> Result<(BlockHeader, Vec<u8>), E> {
+    BlockCache::new().read_block(conn)
+}
+
+/// Unpack a block that uses a 32-bit address space.
+///
+/// `bytes` must contain the block flags and block contents, ie. all bytes
+/// following the [BlockHeader].
+///
+/// ## Errors and panics
+///
+/// Errors with [TruncatedBlockErr](crate::errors::TruncatedBlockErr) if `bytes`
+/// is shorter than `flags_len + block_len` in the header.
+///
+pub fn unpack_roaring32(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+) -> Result<(RoaringBitmap, BlockFlags), E> {
+    check_len(bytes, block_header)?;
+    unpack_block_roaring32(bytes, block_header)
+}
+
+/// Unpack a block that uses a 64-bit address space.
+///
+/// See [unpack_roaring32] for details.
+///
+pub fn unpack_roaring64(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+) -> Result<(RoaringTreemap, BlockFlags), E> {
+    check_len(bytes, block_header)?;
+    unpack_block_roaring64(bytes, block_header)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn pack_and_unpack_roaring64() {
+        use super::pack_roaring64;
+        use super::read_block;
+        use super::unpack_roaring64;
+        use roaring::RoaringTreemap;
+        use std::io::Cursor;
+
+        let bitmap = RoaringTreemap::from_iter([0, 5, 1_u64 << 33]);
+
+        let bytes = pack_roaring64(&[], &[0, 1, 2], bitmap.clone()).unwrap();
+        let (header, contents) = read_block(&mut Cursor::new(bytes)).unwrap();
+        let (got, flags) = unpack_roaring64(&contents, &header).unwrap();
+
+        assert_eq!(got, bitmap);
+        assert_eq!(flags.query_ids, Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn pack_with_wrong_number_of_queries_fails() {
+        use super::pack_roaring32;
+        use roaring::RoaringBitmap;
+
+        let got = pack_roaring32(&[b"r1".to_vec()], &[0, 1], RoaringBitmap::new());
+
+        assert!(got.is_err());
+    }
+
+    #[test]
+    fn unpack_truncated_block_fails() {
+        use super::pack_roaring32;
+        use super::read_block;
+        use super::unpack_roaring32;
+        use roaring::RoaringBitmap;
+        use std::io::Cursor;
+
+        let bytes = pack_roaring32(&[b"r1".to_vec()], &[0], RoaringBitmap::from_iter([1])).unwrap();
+        let (header, contents) = read_block(&mut Cursor::new(bytes)).unwrap();
+
+        let got = unpack_roaring32(&contents[0..(contents.len() - 1)], &header);
+
+        assert!(got.is_err());
+    }
+}
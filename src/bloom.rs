@@ -0,0 +1,237 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Bloom filters of query names.
+//!
+//! A [BloomFilter] answers whether a query name was inserted into it without
+//! storing the names. Names that were inserted are always found, and names
+//! that were not are found with a probability close to the false positive
+//! rate the filter was built with.
+//!
+//! The filters are simple enough to reimplement in other tools. A filter
+//! with `n_bits` bits and `n_hashes` hash functions sets the bits
+//! `(h1 + i * h2) mod n_bits` for `i` in `0..n_hashes`, where `h1` is the
+//! 64-bit FNV-1a hash of the name, `h2` is the splitmix64 finalizer of `h1`
+//! with the lowest bit set, and the additions and multiplications wrap
+//! around at 2^64. Bit `j` is bit `j mod 64` of the `j / 64`th word in `bits`.
+//!
+//! A [FilterSet] stores several named filters, for example one per target
+//! group, in a file that starts with [FILTER_SET_MAGIC] followed by the
+//! FilterSet encoded with the standard bincode configuration.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::bloom::BloomFilter;
+//!
+//! let mut filter = BloomFilter::with_fpr(2, 0.01);
+//! filter.insert(b"ERR4035126.1");
+//! filter.insert(b"ERR4035126.2");
+//!
+//! assert!(filter.contains(b"ERR4035126.1"));
+//! assert!(filter.contains(b"ERR4035126.2"));
+//! ```
+//!
+
+use std::io::Read;
+use std::io::Write;
+
+use bincode::{Encode, Decode};
+use bincode::decode_from_slice;
+use bincode::encode_to_vec;
+
+type E = Box<dyn std::error::Error>;
+
+/// First bytes of a file containing a [FilterSet].
+pub const FILTER_SET_MAGIC: [u8; 8] = *b"AHDABLM1";
+
+/// 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(
+    bytes: &[u8],
+) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325_u64, |hash, x| (hash ^ *x as u64).wrapping_mul(0x100000001b3))
+}
+
+/// splitmix64 finalizer of `x`.
+fn splitmix64(
+    x: u64,
+) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Bloom filter of byte strings.
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct BloomFilter {
+    /// Number of hash functions.
+    pub n_hashes: u32,
+    /// Bits of the filter.
+    pub bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Create an empty filter with at least `n_bits` bits and `n_hashes` hash functions.
+    pub fn new(
+        n_bits: u64,
+        n_hashes: u32,
+    ) -> Self {
+        BloomFilter{ n_hashes: n_hashes.max(1), bits: vec![0; n_bits.div_ceil(64).max(1) as usize] }
+    }
+
+    /// Create an empty filter sized for `n_items` names at false positive rate `fpr`.
+    pub fn with_fpr(
+        n_items: usize,
+        fpr: f64,
+    ) -> Self {
+        let n_items = n_items.max(1) as f64;
+        let fpr = fpr.clamp(f64::MIN_POSITIVE, 1.0);
+        let ln2 = std::f64::consts::LN_2;
+        let n_bits = (-n_items * fpr.ln() / (ln2 * ln2)).ceil().max(1.0);
+        let n_hashes = (n_bits / n_items * ln2).round().clamp(1.0, 32.0);
+        Self::new(n_bits as u64, n_hashes as u32)
+    }
+
+    /// Number of bits in the filter.
+    pub fn n_bits(
+        &self,
+    ) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+
+    fn positions(
+        &self,
+        item: &[u8],
+    ) -> impl Iterator<Item = u64> {
+        let h1 = fnv1a(item);
+        let h2 = splitmix64(h1) | 1;
+        let n_bits = self.n_bits();
+        (0..self.n_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % n_bits)
+    }
+
+    /// Add `item` to the filter.
+    pub fn insert(
+        &mut self,
+        item: &[u8],
+    ) {
+        for pos in self.positions(item) {
+            self.bits[(pos / 64) as usize] |= 1 << (pos % 64);
+        }
+    }
+
+    /// Check if `item` may have been added to the filter.
+    ///
+    /// A filter without bits, eg. one decoded from a corrupted input, may
+    /// contain any item.
+    pub fn contains(
+        &self,
+        item: &[u8],
+    ) -> bool {
+        if self.bits.is_empty() {
+            return true
+        }
+        self.positions(item).all(|pos| self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+    }
+}
+
+/// Named [BloomFilter]s.
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq)]
+pub struct FilterSet {
+    /// Name of each filter.
+    pub names: Vec<Vec<u8>>,
+    /// Filters in the same order as `names`.
+    pub filters: Vec<BloomFilter>,
+}
+
+impl FilterSet {
+    /// Names of the filters that may contain `item`.
+    pub fn matches<'a>(
+        &'a self,
+        item: &'a [u8],
+    ) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.names.iter().zip(self.filters.iter()).filter(move |(_, filter)| filter.contains(item)).map(|(name, _)| name.as_slice())
+    }
+
+    /// Write the filters to `conn`.
+    pub fn write_to<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        conn.write_all(&FILTER_SET_MAGIC)?;
+        conn.write_all(&encode_to_vec(self, bincode::config::standard())?)?;
+        conn.flush()?;
+        Ok(())
+    }
+
+    /// Read filters written by [write_to](FilterSet::write_to) from `conn`.
+    ///
+    /// Errors with [CorruptedInputErr](crate::errors::CorruptedInputErr) if
+    /// `conn` does not start with [FILTER_SET_MAGIC] or has a different
+    /// number of names and filters.
+    ///
+    pub fn read_from<R: Read>(
+        conn: &mut R,
+    ) -> Result<Self, E> {
+        let mut bytes: Vec<u8> = Vec::new();
+        conn.read_to_end(&mut bytes)?;
+        if !bytes.starts_with(&FILTER_SET_MAGIC) {
+            return Err(Box::new(crate::errors::CorruptedInputErr{}))
+        }
+        let filters: FilterSet = decode_from_slice(&bytes[FILTER_SET_MAGIC.len()..], bincode::config::standard())?.0;
+        if filters.names.len() != filters.filters.len() {
+            return Err(Box::new(crate::errors::CorruptedInputErr{}))
+        }
+        Ok(filters)
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn bloom_filter_false_positive_rate() {
+        use super::BloomFilter;
+
+        let mut filter = BloomFilter::with_fpr(1000, 0.01);
+        for idx in 0..1000 {
+            filter.insert(format!("ERR4035126.{}", idx).as_bytes());
+        }
+
+        assert!((0..1000).all(|idx| filter.contains(format!("ERR4035126.{}", idx).as_bytes())));
+        let n_false = (1000..11000).filter(|idx| filter.contains(format!("ERR4035126.{}", idx).as_bytes())).count();
+        assert!(n_false < 200, "{} false positives", n_false);
+    }
+
+    #[test]
+    fn filter_set_write_and_read() {
+        use super::BloomFilter;
+        use super::FilterSet;
+
+        let mut a = BloomFilter::new(128, 3);
+        a.insert(b"r1");
+        let mut b = BloomFilter::new(128, 3);
+        b.insert(b"r2");
+        let filters = FilterSet{ names: vec![b"a".to_vec(), b"b".to_vec()], filters: vec![a, b] };
+
+        let mut bytes: Vec<u8> = Vec::new();
+        filters.write_to(&mut bytes).unwrap();
+        let got = FilterSet::read_from(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(got, filters);
+        assert_eq!(got.matches(b"r2").collect::<Vec<&[u8]>>(), vec![b"b".as_slice()]);
+        assert!(FilterSet::read_from(&mut &bytes[1..]).is_err());
+    }
+}
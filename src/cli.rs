@@ -13,16 +13,62 @@
 //
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+
+/// Parses a probability given as a fraction or a percentage, eg. `0.001` or `0.1%`.
+fn parse_rate(
+    s: &str,
+) -> Result<f64, String> {
+    let (value, scale) = match s.strip_suffix('%') {
+        Some(percent) => (percent, 100.0),
+        None => (s, 1.0),
+    };
+    let rate = value.parse::<f64>().map_err(|e| e.to_string())? / scale;
+    if rate > 0.0 && rate < 1.0 {
+        Ok(rate)
+    } else {
+        Err(format!("'{}' is not between 0 and 1 (0% and 100%)", s))
+    }
+}
+
+/// Version and build capabilities shown by `ahda --version`.
+fn long_version() -> &'static str {
+    static LONG_VERSION: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    LONG_VERSION.get_or_init(|| ahda::compat_info().to_string())
+}
+
+/// Exit codes listed in `ahda --help`.
+const EXIT_CODES: &str = "Exit codes:
+  0  Success
+  1  Other error
+  2  Invalid command-line arguments
+  3  Invalid input data, eg. unrecognized format, corrupted file, or unknown reads or targets
+  4  Incompatible inputs, eg. different targets or index versions
+  5  Error reading or writing files";
 
 #[derive(Parser)]
-#[command(version)]
+#[command(version, about, long_version = long_version(), after_help = EXIT_CODES)]
 #[command(propagate_version = true)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Commands>,
+
+    // Suppress everything but errors on stderr
+    #[arg(long = "quiet", global = true, default_value_t = false, help = "Only print errors to stderr, overrides `--verbose`")]
+    pub quiet: bool,
+
+    // Size of the buffer in front of each output file or stdout
+    #[arg(long = "buffer-size", global = true, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..), help = "Output buffer size in KiB")]
+    pub buffer_size: u32,
 }
 
+/// Command-line definition shared by the parser, `ahda completions`, and `ahda man`.
+pub fn command() -> clap::Command {
+    Cli::command()
+}
+
+// The commands are parsed once, so their size doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     // Encode pseudoalignment data in .ahda format
@@ -33,21 +79,85 @@ pub enum Commands {
         input_file: Option<PathBuf>,
 
         // FastX file used to generate the alignment
-        #[arg(short = 'q', long = "query", help_heading = "Inputs", help = "Query .fastX file")]
+        #[arg(short = 'q', long = "query", help_heading = "Inputs", help = "Query .fasta or .fastq file of reads or contigs, may be compressed")]
         query_file: Option<PathBuf>,
 
+        // Second mate FastX file of paired-end reads, aligned after the first mates
+        #[arg(long = "mate", requires = "query_file", help_heading = "Inputs", help = "Second mate .fasta or .fastq file of paired-end reads")]
+        mate_file: Option<PathBuf>,
+
+        // FastX files of the other lanes of the sample, numbered after the query file
+        #[arg(long = "lane", value_name = "FILE", requires = "query_file", conflicts_with = "mate_file", help_heading = "Inputs", help = "Another lane of the sample, its reads are numbered after the query file and the previous lanes (repeatable)")]
+        lanes: Vec<PathBuf>,
+
+        // Sidecar file with the query names parsed from the query file
+        #[arg(long = "query-cache", requires = "query_file", help_heading = "Inputs", help = "Read query names from this cache file if it matches the query file, otherwise write it")]
+        query_cache: Option<PathBuf>,
+
+        // Also match the query name cache on the CRC32 of the query file
+        #[arg(long = "query-cache-checksum", requires = "query_cache", help_heading = "Inputs", help = "Also check the CRC32 of the query file before using the query cache, reads the whole query file")]
+        query_cache_checksum: bool,
+
+        // Suffix repeated query names instead of failing
+        #[arg(long = "rename-duplicates", requires = "query_file", default_value_t = false, help_heading = "Inputs", help = "Rename repeated query names to `name_2`, `name_3`, ... instead of failing")]
+        rename_duplicates: bool,
+
+        // Name the reads that are missing from a subsampled query file
+        #[arg(long = "allow-extra", default_value_t = false, requires = "query_file", help_heading = "Inputs", help = "Name queries whose rank is past the end of the query file `extra.<rank>` instead of failing")]
+        allow_extra: bool,
+
+        // How to assign query ids to records that only have a query name
+        #[arg(long = "query-ids", conflicts_with = "query_id_map", help_heading = "Inputs", help = "Query ids of named records: position in query file (list), order of appearance (sequential), or number after the last `.` minus 1 (name-suffix)")]
+        query_ids: Option<ahda::parser::query_ids::QueryIdPolicy>,
+
+        // Tab-separated file mapping query names to query ids
+        #[arg(long = "query-id-map", help_heading = "Inputs", help = "Read query ids of named records from a file with query names and ids")]
+        query_id_map: Option<PathBuf>,
+
         // File listing target sequence names in the order they appear in the index
-        #[arg(short = 't', long = "targets", help_heading = "Inputs", help = "File listing target sequence names")]
+        #[arg(short = 't', long = "targets", group = "target_source", help_heading = "Inputs", help = "File listing target sequence names")]
         target_list: Option<PathBuf>,
 
+        // Directory with a Themisto, Fulgor, or Bifrost index and its target list
+        #[arg(long = "index-dir", group = "target_source", help_heading = "Inputs", help = "Read target sequence names from `<prefix>.targets.txt` next to the index in this directory")]
+        index_dir: Option<PathBuf>,
+
+        // Tab-separated table assigning the targets to groups
+        #[arg(long = "groups", requires = "target_source", help_heading = "Inputs", help = "Table of target groups, eg. species and genus")]
+        target_groups: Option<PathBuf>,
+
+        // Two-column table assigning the targets, eg. contigs, to fewer targets, eg. genomes
+        #[arg(long = "aliases", requires = "target_source", help_heading = "Inputs", help = "Table merging the targets, eg. contig to genome")]
+        target_aliases: Option<PathBuf>,
+
+        // Keep the original targets in the output
+        #[arg(long = "store-aliases", default_value_t = false, requires = "target_aliases", help_heading = "Outputs", help = "Store the merged targets in the output")]
+        store_aliases: bool,
+
         // Sample name
-        #[arg(short = 'n', long = "name", help_heading = "Inputs", help = "Sample name (default: .fastX file path)")]
+        #[arg(short = 'n', long = "name", help_heading = "Inputs", help = "Sample name (default: query file path)")]
         sample_name: Option<String>,
 
         // Override input format detection
         #[arg(short = 'F', long = "format", help_heading = "Inputs", help = "Force input format for plain text parser")]
         input_format: Option<ahda::Format>,
 
+        // Keep reading the input file as it grows until interrupted
+        #[arg(long = "follow", default_value_t = false, requires = "input_file", help_heading = "Inputs", help = "Keep reading new data from the input file until Ctrl-C, keep original file")]
+        follow: bool,
+
+        // Sort the records before encoding
+        #[arg(long = "sort-input", default_value_t = false, conflicts_with = "follow", help_heading = "Inputs", help = "Sort the input by query id, for aligners that don't write the reads in order")]
+        sort_input: bool,
+
+        // Memory for sorting
+        #[arg(long = "sort-memory", value_name = "MB", default_value_t = 1024, requires = "sort_input", help_heading = "Inputs", help = "Memory to use for sorting before writing temporary files")]
+        sort_memory: usize,
+
+        // Temporary files for sorting
+        #[arg(long = "tmp-dir", requires = "sort_input", help_heading = "Inputs", help = "Directory for temporary files when sorting (default: system temporary directory)")]
+        tmp_dir: Option<PathBuf>,
+
         // Write to stdout
         #[arg(short = 'c', long = "stdout", default_value_t = false, help = "Write to stdout, keep original file")]
         stdout: bool,
@@ -56,6 +166,58 @@ pub enum Commands {
         #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
         force: bool,
 
+        // Gzip compression level
+        #[arg(short = 'l', long = "level", default_value_t = 6, value_parser = clap::value_parser!(u32).range(1..=9), help_heading = "Outputs", help = "Compression level from 1 (fastest) to 9 (smallest)")]
+        level: u32,
+
+        // Never store small files uncompressed
+        #[arg(long = "no-inline", default_value_t = false, help_heading = "Outputs", help = "Compress small files that fit in one block even if storing them uncompressed is smaller")]
+        no_inline: bool,
+
+        // Store a Bloom filter of the query names in each block
+        #[arg(long = "query-filter", value_parser = parse_rate, help_heading = "Outputs", help = "Store a filter of the query names in each block with this false positive rate to speed up `ahda grep`, eg. 0.01 or 1%")]
+        query_filter: Option<f64>,
+
+        // Store the targets with hits in each block
+        #[arg(long = "target-summary", default_value_t = false, help_heading = "Outputs", help = "Store the targets that each block has hits to, to speed up `ahda slice`")]
+        target_summary: bool,
+
+        // Whole-file zstd compression
+        #[arg(long = "zstd-seekable", value_name = "LEVEL", num_args = 0..=1, require_equals = true, default_missing_value = "3", help_heading = "Outputs", help = "Write the output in a zstd-seekable container with this zstd level, eg. `--zstd-seekable=19` [default: 3], needs the `zstd` feature")]
+        zstd_seekable: Option<i32>,
+
+        // Zstd dictionary for the block flags
+        #[arg(long = "flags-dictionary", default_value_t = false, help_heading = "Outputs", help = "Compress the query names in the blocks with a zstd dictionary trained on the first blocks, needs the `zstd` feature")]
+        flags_dictionary: bool,
+
+        // Bitmap type
+        #[arg(long = "bitmap", value_name = "TYPE", help_heading = "Outputs", help = "Bitmap type, roaring32 or roaring64 [default: roaring32 if the alignments fit in 32 bits]")]
+        bitmap: Option<ahda::compression::BitmapType>,
+
+        // Split the targets of each block across sub-blocks
+        #[arg(long = "target-chunks", value_name = "N", help_heading = "Outputs", help = "Split the targets of each block across sub-blocks of N targets, for inputs with millions of targets")]
+        target_chunks: Option<u32>,
+
+        // Cap the number of targets per query
+        #[arg(long = "max-hits", value_name = "N", help_heading = "Outputs", help = "Truncate or drop the alignments of queries that align to more than N targets")]
+        max_hits: Option<u32>,
+
+        // What to do with queries over the cap
+        #[arg(long = "max-hits-action", value_name = "ACTION", requires = "max_hits", default_value_t = ahda::max_hits::MaxHitsAction::Truncate, help_heading = "Outputs", help = "Keep the first N targets (truncate) or none (drop) of queries over `--max-hits`")]
+        max_hits_action: ahda::max_hits::MaxHitsAction,
+
+        // Covered buckets of the targets from SAM positions
+        #[arg(long = "coverage-bucket", value_name = "BP", value_parser = clap::value_parser!(u32).range(1..), help_heading = "Outputs", help = "Store which BP-sized buckets of each target the reads cover, shown by `ahda stats`, needs SAM input")]
+        coverage_bucket: Option<u32>,
+
+        // Version of the index the input was aligned against
+        #[arg(long = "index-version", value_name = "VERSION", help_heading = "Inputs", help = "Version of the index the input was aligned against, checked by `ahda cat` and `ahda set`")]
+        index_version: Option<String>,
+
+        // Keep the plain text input
+        #[arg(long = "tee", value_name = "FILE", help_heading = "Outputs", help = "Also write the plain text input gzipped to this file while encoding, eg. `raw.txt.gz`")]
+        tee: Option<PathBuf>,
+
         // Overwrite query names
         #[arg(long = "rename", default_value_t = false, help = "Overwrite query names with `sample_name`.`query_id`")]
         rename: bool,
@@ -65,8 +227,8 @@ pub enum Commands {
         keep: bool,
 
         // Verbosity
-        #[arg(short = 'v', long = "verbose", default_value_t = false, help = "Print extra information")]
-        verbose: bool,
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each block")]
+        verbose: u8,
     },
 
     // Decode .ahda format
@@ -80,6 +242,34 @@ pub enum Commands {
         #[arg(short = 'F', long = "format", required = false, help = "Output plain text format")]
         format: Option<ahda::Format>,
 
+        // Collapse targets to their groups
+        #[arg(long = "collapse", required = false, help = "Report alignments to the target groups at this level")]
+        collapse: Option<String>,
+
+        // Sort output by query id
+        #[arg(long = "sorted-by-rank", default_value_t = false, conflicts_with = "collapse", help = "Sort output by query id (rank)")]
+        sorted_by_rank: bool,
+
+        // Write one output per target group
+        #[arg(long = "split-by-group", requires = "input_file", conflicts_with_all = ["collapse", "sorted_by_rank", "stdout"], help = "Write the queries that align to each group in this target groups table to their own file")]
+        split_by_group: Option<PathBuf>,
+
+        // Fail instead of warning if the output format loses information or the query ids are out of range
+        #[arg(long = "strict", default_value_t = false, help = "Fail if the output format can't store all fields in the input or if the query ids don't fit in the number of queries")]
+        strict: bool,
+
+        // Cap the number of targets per query
+        #[arg(long = "max-hits", value_name = "N", conflicts_with_all = ["collapse", "sorted_by_rank", "split_by_group"], help = "Truncate or drop the alignments of queries that align to more than N targets")]
+        max_hits: Option<u32>,
+
+        // What to do with queries over the cap
+        #[arg(long = "max-hits-action", value_name = "ACTION", requires = "max_hits", default_value_t = ahda::max_hits::MaxHitsAction::Truncate, help = "Keep the first N targets (truncate) or none (drop) of queries over `--max-hits`")]
+        max_hits_action: ahda::max_hits::MaxHitsAction,
+
+        // Continue from the next block if a block is corrupt
+        #[arg(long = "skip-corrupt", default_value_t = false, conflicts_with_all = ["collapse", "sorted_by_rank", "split_by_group", "max_hits"], help = "Skip blocks that can't be unpacked and report them instead of failing")]
+        skip_corrupt: bool,
+
         // Write to stdout
         #[arg(short = 'c', long = "stdout", default_value_t = false, help = "Write to stdout, keep original file")]
         stdout: bool,
@@ -93,21 +283,33 @@ pub enum Commands {
         keep: bool,
 
         // Verbosity
-        #[arg(short = 'v', long = "verbose", default_value_t = false, help = "Print extra information")]
-        verbose: bool,
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each block")]
+        verbose: u8,
     },
 
     // Convert plaintext to another plaintext format
     #[command(name = "convert", about = "Convert between plain text formats")]
     Convert {
-        // Input fasta or fastq sequence file(s)
-        #[arg(group = "input", required = false, help = "Input file")]
-        input_file: Option<PathBuf>,
+        // Input plain text or .ahda file(s)
+        #[arg(group = "input", required = false, help = "Input file(s)")]
+        input_files: Vec<PathBuf>,
 
         // FastX file used to generate the alignment
-        #[arg(short = 'q', long = "query", help_heading = "Inputs", help = "Query .fastX file")]
+        #[arg(short = 'q', long = "query", help_heading = "Inputs", help = "Query .fasta or .fastq file of reads or contigs, may be compressed")]
         query_file: Option<PathBuf>,
 
+        // Sidecar file with the query names parsed from the query file
+        #[arg(long = "query-cache", requires = "query_file", help_heading = "Inputs", help = "Read query names from this cache file if it matches the query file, otherwise write it")]
+        query_cache: Option<PathBuf>,
+
+        // Also match the query name cache on the CRC32 of the query file
+        #[arg(long = "query-cache-checksum", requires = "query_cache", help_heading = "Inputs", help = "Also check the CRC32 of the query file before using the query cache, reads the whole query file")]
+        query_cache_checksum: bool,
+
+        // Suffix repeated query names instead of failing
+        #[arg(long = "rename-duplicates", requires = "query_file", default_value_t = false, help_heading = "Inputs", help = "Rename repeated query names to `name_2`, `name_3`, ... instead of failing")]
+        rename_duplicates: bool,
+
         // File listing target sequence names in the order they appear in the index
         #[arg(short = 't', long = "targets", help_heading = "Inputs", help = "File listing target sequence names")]
         target_list: Option<PathBuf>,
@@ -116,14 +318,50 @@ pub enum Commands {
         #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file, keep original file")]
         output_file: Option<PathBuf>,
 
+        // Output file names for several inputs
+        #[arg(long = "output-template", conflicts_with_all = ["output_file", "stdout"], help_heading = "Outputs", help = "Output to files named by replacing {dir}, {name}, and {stem} with the input file directory, name, and name without extension, keep original files")]
+        output_template: Option<String>,
+
         // Output format, defaults to Themisto
         #[arg(short = 'F', long = "format", required = false, help_heading = "Outputs", help = "Output plain text format")]
         format: Option<ahda::Format>,
 
         // Sample name
-        #[arg(short = 'n', long = "name", help_heading = "Inputs", help = "Sample name (default: .fastX file path)")]
+        #[arg(short = 'n', long = "name", help_heading = "Inputs", help = "Sample name (default: query file path)")]
         sample_name: Option<String>,
 
+        // Fail instead of warning if the output format loses information
+        #[arg(long = "strict", default_value_t = false, help = "Fail if the output format can't store all fields in the input")]
+        strict: bool,
+
+        // File listing the target sequences to keep in the output
+        #[arg(long = "keep-targets", help_heading = "Outputs", help = "Only output the targets listed in this file")]
+        keep_targets: Option<PathBuf>,
+
+        // Tab-separated file with the old and new name of each renamed target
+        #[arg(long = "rename-map", help_heading = "Outputs", help = "Rename targets using this tab-separated file of old and new names")]
+        rename_map: Option<PathBuf>,
+
+        // Don't record ahda and the command line in the output header
+        #[arg(long = "no-program-record", default_value_t = false, help_heading = "Outputs", help = "Don't add a @PG (SAM) or #PG (Bifrost, AhdaTSV) line with the ahda version and command line to the header")]
+        no_program_record: bool,
+
+        // Re-parse the output and compare its digest to the input
+        #[arg(long = "round-trip-check", default_value_t = false, conflicts_with_all = ["keep_targets", "rename_map"], help_heading = "Outputs", help = "Check that the output has the same alignments as the input before writing it, holds both in memory")]
+        round_trip_check: bool,
+
+        // Records to convert before flushing the output
+        #[arg(long = "chunk-size", default_value_t = ahda::CONVERT_CHUNK_SIZE, help_heading = "Outputs", help = "Write the output every N records")]
+        chunk_size: usize,
+
+        // Number of threads
+        #[arg(short = 'T', long = "threads", help = "Number of inputs to convert in parallel (default: all available)")]
+        threads: Option<usize>,
+
+        // Check the inputs and outputs without writing anything
+        #[arg(long = "dry-run", default_value_t = false, help = "Check the inputs and outputs and print the plan without writing the output")]
+        dry_run: bool,
+
         // Write to stdout
         #[arg(short = 'c', long = "stdout", default_value_t = false, help = "Write to stdout, keep original file")]
         stdout: bool,
@@ -137,21 +375,61 @@ pub enum Commands {
         keep: bool,
 
         // Verbosity
-        #[arg(short = 'v', long = "verbose", default_value_t = false, help = "Print extra information")]
-        verbose: bool,
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each block")]
+        verbose: u8,
     },
 
     // Concatenate encoded data
     #[command(name = "cat", about = "Concatenate binary data")]
     Cat {
         // Input files
-        #[arg(group = "input", required = true, help = "Input file(s)")]
+        #[arg(group = "input", required_unless_present = "merge_plan", help = "Input file(s), directories, or quoted glob patterns")]
         input_files: Vec<PathBuf>,
 
+        // Order of the inputs
+        #[arg(long = "order-by", default_value = "given", help_heading = "Inputs", help = "Input order: given, name (natural order), or start-idx (first query id)")]
+        order_by: ahda::inputs::OrderBy,
+
+        // Inputs from a merge plan
+        #[arg(long = "merge-plan", value_name = "FILE", conflicts_with = "input_files", help_heading = "Inputs", help = "Concatenate the shard outputs listed in a merge plan from `ahda shard-plan` in shard order")]
+        merge_plan: Option<PathBuf>,
+
         // Output file name
         #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
         output_file: Option<PathBuf>,
 
+        // Deduplicate identical blocks
+        #[arg(long = "dedup", default_value_t = false, help_heading = "Outputs", help = "Store identical blocks only once")]
+        dedup: bool,
+
+        // Temporary file for deduplicating
+        #[arg(long = "tmp-dir", requires = "dedup", help_heading = "Outputs", help = "Directory for the temporary copy of the output when deduplicating (default: system temporary directory)")]
+        tmp_dir: Option<PathBuf>,
+
+        // Manifest of the inputs
+        #[arg(long = "manifest", value_name = "FILE", help_heading = "Outputs", help = "Write the path, sample name, query id range, record count, and MD5 of each input to FILE as a TSV")]
+        manifest: Option<PathBuf>,
+
+        // Store the manifest in the output
+        #[arg(long = "embed-manifest", default_value_t = false, help_heading = "Outputs", help = "Store the manifest of the inputs in the output, see `ahda stats`")]
+        embed_manifest: bool,
+
+        // Skip the panel check
+        #[arg(long = "ignore-panel", default_value_t = false, help_heading = "Inputs", help = "Combine inputs aligned against different target panels or index versions")]
+        ignore_panel: bool,
+
+        // Shift the query ids of each input past the earlier inputs
+        #[arg(long = "renumber-queries", default_value_t = false, help_heading = "Inputs", help = "Number the queries of each input after the queries of the earlier inputs, for inputs that all number their queries from 0")]
+        renumber_queries: bool,
+
+        // Combine the targets of the inputs
+        #[arg(long = "merge-targets", default_value_t = false, help_heading = "Inputs", help = "Concatenate inputs aligned against different targets, the output has the targets of all inputs")]
+        merge_targets: bool,
+
+        // Check the inputs and outputs without writing anything
+        #[arg(long = "dry-run", default_value_t = false, help = "Check the inputs and outputs and print the plan and estimated output size without writing the output")]
+        dry_run: bool,
+
         // Write to stdout
         #[arg(short = 'c', long = "stdout", default_value_t = false, help = "Write to stdout, keep original file")]
         stdout: bool,
@@ -161,8 +439,8 @@ pub enum Commands {
         force: bool,
 
         // Verbosity
-        #[arg(short = 'v', long = "verbose", default_value_t = false, help = "Print extra information")]
-        verbose: bool,
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
     },
 
     // Set operations on .ahda files
@@ -180,6 +458,30 @@ pub enum Commands {
         #[arg(short = 'm', long = "mode", default_value = "union", help = "Merge operation")]
         operation: Option<ahda::MergeOp>,
 
+        // Match queries by name instead of by position
+        #[arg(long = "by-name", default_value_t = false, help = "Match queries by name instead of position (uses more memory)")]
+        by_name: bool,
+
+        // Write plain text instead of .ahda
+        #[arg(short = 'F', long = "format", help_heading = "Outputs", help = "Write plain text in this format instead of binary data")]
+        format: Option<ahda::Format>,
+
+        // Queries left with no alignments
+        #[arg(long = "output-empty-rows", value_name = "POLICY", default_value_t = ahda::EmptyRows::Keep, help_heading = "Outputs", help = "Keep queries that align to no targets after the operation, drop them, or drop them and record how many were dropped (summary)")]
+        output_empty_rows: ahda::EmptyRows,
+
+        // Skip the panel check
+        #[arg(long = "ignore-panel", default_value_t = false, help = "Combine inputs aligned against different target panels or index versions")]
+        ignore_panel: bool,
+
+        // Number of threads
+        #[arg(short = 'T', long = "threads", help = "Number of threads to decode the inputs with (default: all available)")]
+        threads: Option<usize>,
+
+        // Check the inputs and outputs without writing anything
+        #[arg(long = "dry-run", default_value_t = false, help = "Check the inputs and outputs and print the plan and estimated output size without writing the output")]
+        dry_run: bool,
+
         // Write to stdout
         #[arg(short = 'c', long = "stdout", default_value_t = false, help = "Write to stdout, keep original file")]
         stdout: bool,
@@ -189,7 +491,623 @@ pub enum Commands {
         force: bool,
 
         // Verbosity
-        #[arg(short = 'v', long = "verbose", default_value_t = false, help = "Print extra information")]
-        verbose: bool,
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each input")]
+        verbose: u8,
+    },
+
+    // Compare two .ahda files
+    #[command(name = "compare", about = "Compare binary data")]
+    Compare {
+        // First input file
+        #[arg(required = true, help = "First input file")]
+        input_a: PathBuf,
+
+        // Second input file
+        #[arg(required = true, help = "Second input file")]
+        input_b: PathBuf,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Check that two files refer to the same queries
+    #[command(name = "check-consistency", about = "Check that two files refer to the same queries")]
+    CheckConsistency {
+        // First input file
+        #[arg(required = true, help = "First input file")]
+        input_a: PathBuf,
+
+        // Second input file
+        #[arg(required = true, help = "Second input file")]
+        input_b: PathBuf,
+
+        // Number of mismatches to list
+        #[arg(long = "max-mismatches", default_value_t = 20, help_heading = "Outputs", help = "List at most this many mismatches, 0 lists all")]
+        max_mismatches: usize,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Report format
+        #[arg(long = "output-format", default_value = "auto", help_heading = "Outputs", help = "Output format: tsv, json, or pretty; auto is pretty in a terminal and tsv otherwise")]
+        output_format: ahda::report::ReportFormat,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Hash the alignments independently of the block layout
+    #[command(name = "digest", about = "Print a digest of the alignments in binary data")]
+    Digest {
+        // Input files
+        #[arg(group = "input", required = false, help = "Input file(s)")]
+        input_files: Vec<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Cross-check plain text input against the query and target names
+    #[command(name = "doctor", about = "Check plain text data against the reads and targets")]
+    Doctor {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // FastX file used to generate the input
+        #[arg(short = 'q', long = "query", help_heading = "Inputs", help = "Query .fasta or .fastq file of reads or contigs, may be compressed")]
+        query_file: Option<PathBuf>,
+
+        // File listing target sequence names in the order they appear in the index
+        #[arg(short = 't', long = "targets", conflicts_with = "index_dir", help_heading = "Inputs", help = "File listing target sequence names")]
+        target_list: Option<PathBuf>,
+
+        // Directory with a Themisto, Fulgor, or Bifrost index and its target list
+        #[arg(long = "index-dir", help_heading = "Inputs", help = "Read target sequence names from `<prefix>.targets.txt` next to the index in this directory")]
+        index_dir: Option<PathBuf>,
+
+        // Override input format detection
+        #[arg(short = 'F', long = "format", help_heading = "Inputs", help = "Force input format for plain text parser")]
+        input_format: Option<ahda::Format>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Compare encoding plain text against gzip and zstd of the text
+    #[command(name = "bench-file", about = "Compare encoding plain text against gzip and zstd of the text")]
+    BenchFile {
+        // Input file
+        #[arg(required = true, help = "Input file")]
+        input_file: PathBuf,
+
+        // FastX file used to generate the input
+        #[arg(short = 'q', long = "query", help_heading = "Inputs", help = "Query .fasta or .fastq file of reads or contigs, may be compressed")]
+        query_file: Option<PathBuf>,
+
+        // File listing target sequence names in the order they appear in the index
+        #[arg(short = 't', long = "targets", help_heading = "Inputs", help = "File listing target sequence names")]
+        target_list: Option<PathBuf>,
+
+        // Override input format detection
+        #[arg(short = 'F', long = "format", help_heading = "Inputs", help = "Force input format for plain text parser")]
+        input_format: Option<ahda::Format>,
+
+        // Gzip compression level of the blocks
+        #[arg(short = 'l', long = "level", default_value_t = 6, value_parser = clap::value_parser!(u32).range(1..=9), help_heading = "Outputs", help = "Compression level of the .ahda blocks from 1 (fastest) to 9 (smallest)")]
+        level: u32,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Report format
+        #[arg(long = "output-format", default_value = "auto", help_heading = "Outputs", help = "Output format: tsv, json, or pretty; auto is pretty in a terminal and tsv otherwise")]
+        output_format: ahda::report::ReportFormat,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Print information stored in the file header and flags
+    #[command(name = "stats", about = "Print information about binary data")]
+    Stats {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Report format
+        #[arg(long = "output-format", default_value = "auto", help_heading = "Outputs", help = "Output format: tsv, json, or pretty; auto is pretty in a terminal and tsv otherwise")]
+        output_format: ahda::report::ReportFormat,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Print the file header and flags
+    #[command(name = "header", about = "Print the file header without reading the blocks")]
+    Header {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Print the stored target names
+    #[command(name = "targets", about = "Print the target names in index order")]
+    Targets {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Print the original targets that were merged with --aliases
+        #[arg(long = "aliases", default_value_t = false, help = "Print the stored target aliases instead")]
+        aliases: bool,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Print the stored query names
+    #[command(name = "queries", about = "Print the query names in query id order")]
+    Queries {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Print the layout of the file
+    #[command(name = "info", about = "Print the layout of binary data")]
+    Info {
+        #[command(subcommand)]
+        command: InfoCommands,
+    },
+
+    // Merge separately aligned mates of paired-end reads
+    #[command(name = "merge-mates", about = "Merge separately aligned paired-end mates")]
+    MergeMates {
+        // Alignments of the first mates
+        #[arg(required = true, help = "First mate input file")]
+        input_first: PathBuf,
+
+        // Alignments of the second mates
+        #[arg(required = true, help = "Second mate input file")]
+        input_second: PathBuf,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Store the mates as separate records
+        #[arg(long = "keep-mates", default_value_t = false, help_heading = "Outputs", help = "Keep the alignments of each mate instead of merging them")]
+        keep_mates: bool,
+
+        // Sample name
+        #[arg(short = 'n', long = "name", help_heading = "Outputs", help = "Sample name (default: from the first input)")]
+        sample_name: Option<String>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each block")]
+        verbose: u8,
+    },
+
+    // Split into shards for parallel processing
+    #[command(name = "shard-plan", about = "Split binary data into shards of blocks for parallel processing")]
+    ShardPlan {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Number of shards
+        #[arg(short = 'n', long = "shards", required = true, value_parser = clap::value_parser!(u32).range(1..), help_heading = "Inputs", help = "Number of shards, fewer if there are fewer blocks")]
+        n_shards: u32,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Write one shard instead of the plan
+        #[arg(long = "extract", value_name = "SHARD", requires = "input_file", help_heading = "Outputs", help = "Write shard SHARD as binary data instead of writing the plan")]
+        extract: Option<usize>,
+
+        // Merge plan
+        #[arg(long = "merge-plan", value_name = "FILE", help_heading = "Outputs", help = "Write the output path of each shard to FILE for `ahda cat --merge-plan`")]
+        merge_plan: Option<PathBuf>,
+
+        // Output paths in the merge plan
+        #[arg(long = "prefix", default_value = "shard", help_heading = "Outputs", help = "Output paths in the merge plan are `prefix``shard`.ahda")]
+        prefix: String,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Remove hits of simulated reads to their source genome
+    #[command(name = "exclude-self-hits", about = "Remove the hits of simulated reads to their source genome")]
+    ExcludeSelfHits {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Table of query names and source targets
+        #[arg(long = "sources", required = true, help_heading = "Inputs", help = "Tab-separated file with the name of each read and the target or target group it was simulated from")]
+        sources: PathBuf,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each block")]
+        verbose: u8,
+    },
+
+    // Export a Bloom filter of the aligned query names
+    #[command(name = "export-filter", about = "Export a Bloom filter of the aligned reads")]
+    ExportFilter {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // False positive rate
+        #[arg(long = "fpr", default_value = "1%", value_parser = parse_rate, help_heading = "Outputs", help = "False positive rate, eg. 0.001 or 0.1%")]
+        fpr: f64,
+
+        // One filter per target group
+        #[arg(long = "level", help_heading = "Outputs", help = "Export one filter per target group at this level")]
+        level: Option<String>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each block")]
+        verbose: u8,
     },
+
+    // Export the names of the queries aligned to each target
+    #[command(name = "export-reads", about = "Export the aligned reads of each target")]
+    ExportReads {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // One file per target
+        #[arg(long = "output-dir", conflicts_with = "output_file", help_heading = "Outputs", help = "Write one file named `<target>.txt` per target to this directory")]
+        output_dir: Option<PathBuf>,
+
+        // One list per target group
+        #[arg(long = "level", help_heading = "Outputs", help = "List the reads of each target group at this level")]
+        level: Option<String>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each block")]
+        verbose: u8,
+    },
+
+    // Find the alignments of a query by name
+    #[command(name = "grep", about = "Print the alignments of a query")]
+    Grep {
+        // Query name
+        #[arg(required = true, help = "Query name, without the /1 or /2 mate suffix")]
+        query_name: String,
+
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output format, defaults to Themisto
+        #[arg(short = 'F', long = "format", required = false, help = "Output plain text format")]
+        format: Option<ahda::Format>,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Find the alignments to some targets
+    #[command(name = "slice", about = "Print the alignments of the queries that aligned to some targets")]
+    Slice {
+        // Target names
+        #[arg(short = 'T', long = "target", required = true, value_name = "NAME", help = "Target name (repeatable)")]
+        targets: Vec<String>,
+
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output format, defaults to Themisto
+        #[arg(short = 'F', long = "format", required = false, help = "Output plain text format")]
+        format: Option<ahda::Format>,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Print a random sample of the alignments
+    #[command(name = "peek-random", about = "Print the alignments of queries picked at random")]
+    PeekRandom {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Number of records to pick
+        #[arg(short = 'n', long = "records", default_value_t = 100, help = "Number of queries to pick")]
+        n_records: usize,
+
+        // Random seed
+        #[arg(short = 's', long = "seed", default_value_t = 0, help = "Random seed")]
+        seed: u64,
+
+        // Output format, defaults to Themisto
+        #[arg(short = 'F', long = "format", required = false, help = "Output plain text format")]
+        format: Option<ahda::Format>,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // Generate synthetic data
+    #[command(name = "simulate", about = "Generate synthetic data")]
+    Simulate {
+        // Number of query sequences
+        #[arg(short = 'n', long = "queries", default_value_t = 1000, help_heading = "Inputs", help = "Number of query sequences")]
+        n_queries: u32,
+
+        // Number of target sequences
+        #[arg(short = 't', long = "targets", default_value_t = 10, help_heading = "Inputs", help = "Number of target sequences")]
+        n_targets: u32,
+
+        // Probability that a query aligns to a target
+        #[arg(short = 'd', long = "density", default_value_t = 0.1, help_heading = "Inputs", help = "Probability of each query aligning to each target")]
+        density: f64,
+
+        // Random seed
+        #[arg(short = 's', long = "seed", default_value_t = 0, help_heading = "Inputs", help = "Random seed")]
+        seed: u64,
+
+        // Prefix for query names
+        #[arg(long = "query-prefix", default_value = "read", help_heading = "Inputs", help = "Query names are `prefix`.`query_id`")]
+        query_prefix: String,
+
+        // Prefix for target names
+        #[arg(long = "target-prefix", default_value = "target", help_heading = "Inputs", help = "Target names are `prefix`_`target_id`")]
+        target_prefix: String,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Output format, defaults to Themisto
+        #[arg(short = 'F', long = "format", required = false, help_heading = "Outputs", help = "Output plain text format")]
+        format: Option<ahda::Format>,
+
+        // Write .ahda instead of plain text
+        #[arg(short = 'b', long = "binary", default_value_t = false, help_heading = "Outputs", help = "Write binary data instead of plain text")]
+        binary: bool,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also print the time taken by each block")]
+        verbose: u8,
+    },
+
+    // Answer queries about .ahda files over HTTP
+    #[cfg(feature = "serve")]
+    #[command(name = "serve", about = "Answer queries about .ahda files over HTTP")]
+    Serve {
+        // Input files
+        #[arg(required = true, help = "Input .ahda file(s) or directories")]
+        input_files: Vec<PathBuf>,
+
+        // Address to listen on
+        #[arg(long = "bind", default_value = "127.0.0.1:8080", help = "Address and port to listen on")]
+        bind: String,
+
+        // Number of threads
+        #[arg(short = 'T', long = "threads", default_value_t = 4, help = "Number of requests to answer in parallel")]
+        threads: usize,
+
+        // Timeout for reading requests
+        #[arg(long = "timeout", default_value_t = 10, help = "Seconds to wait for a client to send its request, 0 to wait forever")]
+        timeout: u64,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information, repeat to also log each request")]
+        verbose: u8,
+    },
+
+    // Generate shell completions from the command-line definition
+    #[command(name = "completions", about = "Print a shell completion script")]
+    Completions {
+        // Shell to generate for
+        #[arg(help = "Shell to generate the completions for")]
+        shell: clap_complete::Shell,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+    },
+
+    // Generate man pages from the command-line definition
+    #[command(name = "man", about = "Print the man page")]
+    Man {
+        // Write a page for every subcommand
+        #[arg(long = "output-dir", help_heading = "Outputs", help = "Write ahda.1 and a page for each subcommand to a directory")]
+        output_dir: Option<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", conflicts_with = "output_dir", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum InfoCommands {
+    // List the blocks without decompressing them
+    #[command(name = "blocks", about = "List the blocks and their sizes")]
+    Blocks {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+
+    // List the extension fields added by other tools
+    #[command(name = "schema", about = "List the extension fields in the file and block flags")]
+    Schema {
+        // Input file
+        #[arg(group = "input", required = false, help = "Input file")]
+        input_file: Option<PathBuf>,
+
+        // Output file name
+        #[arg(short = 'o', long = "output", help_heading = "Outputs", help = "Output to file")]
+        output_file: Option<PathBuf>,
+
+        // Overwrite output file
+        #[arg(short = 'f', long = "force", default_value_t = false, help = "Force overwriting")]
+        force: bool,
+
+        // Verbosity
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, help = "Print extra information")]
+        verbose: u8,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn command_is_valid() {
+        super::command().debug_assert();
+    }
 }
@@ -0,0 +1,406 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Content-addressed storage for byte-identical block contents.
+//!
+//! Blocks that store exactly the same bitmap bytes as an earlier block in the
+//! same record can be replaced by a reference to the earlier block. This
+//! happens eg. when a concatenation contains several blocks without any
+//! alignments.
+//!
+//! Deduplication is recorded in the `placeholder1` and `placeholder2` fields
+//! of [BlockHeader]:
+//! - `placeholder1 & BLOCK_IS_REFERENCED`: the block contents are needed by a
+//!   later block and must be kept in memory when decoding.
+//! - `placeholder1 & BLOCK_IS_REFERENCE`: the block does not store its
+//!   contents and `block_len` is 0. The contents are the same as in the block
+//!   `placeholder2` blocks before this one.
+//!
+//! The [BlockFlags](crate::headers::block::BlockFlags) are always stored in
+//! the block itself.
+//!
+//! References are relative so deduplicated records can be concatenated
+//! without modifying the blocks.
+//!
+//! Use [BlockCache::read_block] to read blocks with the references resolved.
+//!
+
+use crate::headers::block::BlockHeader;
+use crate::headers::block::encode_block_header;
+use crate::headers::block::read_block_header;
+use crate::headers::file::encode_file_header;
+use crate::headers::file::read_file_header;
+use crate::headers::read_len_bytes;
+use crate::headers::read_len_bytes_into;
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+
+type E = Box<dyn std::error::Error>;
+
+pub use ahda_core::block::BLOCK_IS_REFERENCE;
+pub use ahda_core::block::BLOCK_IS_REFERENCED;
+
+/// Keeps the contents of referenced blocks while reading a record.
+#[derive(Debug, Default)]
+pub struct BlockCache {
+    index: u64,
+    contents: HashMap<u64, Vec<u8>>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        BlockCache::default()
+    }
+
+    /// Read the next block from `conn` and resolve its contents.
+    ///
+    /// Returns the [BlockHeader] and the bytes following the header, ie. the
+    /// [BlockFlags](crate::headers::block::BlockFlags) bytes and the block
+    /// contents. If the block is a reference, the contents are copied from the
+    /// referenced block and `block_len` in the returned header is updated to
+    /// match.
+    ///
+    /// Errors with [MissingBlockReferenceErr](crate::errors::MissingBlockReferenceErr)
+    /// if the referenced block has not been read by this BlockCache.
+    ///
+    pub fn read_block<R: Read>(
+        &mut self,
+        conn: &mut R,
+    ) -> Result<(BlockHeader, Vec<u8>), E> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let header = self.read_block_into(conn, &mut bytes)?;
+        Ok((header, bytes))
+    }
+
+    /// Same as [BlockCache::read_block] but reads the bytes into `bytes`.
+    ///
+    /// The previous contents of `bytes` are replaced and its capacity is
+    /// reused.
+    ///
+    pub fn read_block_into<R: Read>(
+        &mut self,
+        conn: &mut R,
+        bytes: &mut Vec<u8>,
+    ) -> Result<BlockHeader, E> {
+        let mut header = read_block_header(conn)?;
+        let len = header.flags_len.checked_add(header.block_len as u64).ok_or(crate::errors::TruncatedBlockErr{})?;
+        bytes.clear();
+        read_len_bytes_into(conn, len, bytes)?;
+
+        if header.placeholder1 & BLOCK_IS_REFERENCE != 0 {
+            let target = self.index.checked_sub(header.placeholder2 as u64).ok_or(crate::errors::MissingBlockReferenceErr{})?;
+            let contents = self.contents.get(&target).ok_or(crate::errors::MissingBlockReferenceErr{})?;
+            bytes.extend_from_slice(contents);
+            header.block_len = contents.len() as u32;
+        }
+
+        if header.placeholder1 & BLOCK_IS_REFERENCED != 0 {
+            self.contents.insert(self.index, bytes[(header.flags_len as usize)..].to_vec());
+        }
+
+        self.index += 1;
+        Ok(header)
+    }
+
+    /// Read the next block from `conn` if there is one.
+    ///
+    /// Same as [BlockCache::read_block] but returns `None` if `conn` is at the
+    /// end of the input, so that truncated blocks can be told apart from the
+    /// end of the record.
+    ///
+    pub fn try_read_block<R: Read>(
+        &mut self,
+        conn: &mut R,
+    ) -> Result<Option<(BlockHeader, Vec<u8>)>, E> {
+        let mut bytes: Vec<u8> = Vec::new();
+        Ok(self.try_read_block_into(conn, &mut bytes)?.map(|header| (header, bytes)))
+    }
+
+    /// Same as [BlockCache::try_read_block] but reads the bytes into `bytes`.
+    pub fn try_read_block_into<R: Read>(
+        &mut self,
+        conn: &mut R,
+        bytes: &mut Vec<u8>,
+    ) -> Result<Option<BlockHeader>, E> {
+        let mut first: [u8; 1] = [0];
+        loop {
+            match conn.read(&mut first) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+        let mut chained = first.as_slice().chain(conn);
+        Ok(Some(self.read_block_into(&mut chained, bytes)?))
+    }
+}
+
+fn read_raw_block<R: Read>(
+    conn: &mut R,
+) -> Result<(BlockHeader, Vec<u8>, Vec<u8>), E> {
+    let header = read_block_header(conn)?;
+    let flags = read_len_bytes(conn, header.flags_len)?;
+    let contents = read_len_bytes(conn, header.block_len as u64)?;
+    Ok((header, flags, contents))
+}
+
+fn hash_contents(
+    contents: &[u8],
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Replace byte-identical block contents with references to earlier blocks.
+///
+/// Reads the .ahda record in `conn_in` twice: first to find the duplicated
+/// block contents, then to write the deduplicated record to `conn_out`.
+///
+/// Blocks that are already references are kept as is. A block can only refer
+/// to the at most [u16::MAX] blocks preceding it.
+///
+/// Returns the number of blocks that were replaced with references.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::{decode_from_read, encode_to_write, try_concatenate_from_read_to_write};
+/// use ahda::EncodeOpts;
+/// use ahda::PseudoAln;
+/// use ahda::compression::dedup::deduplicate_blocks;
+/// use std::io::{Cursor, Seek};
+///
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec()];
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = "sample".as_bytes().to_vec();
+///
+/// // Two records with no alignments have identical block contents
+/// let data_1 = vec![PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![]), query_name: None }];
+/// let data_2 = vec![PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![]), query_name: None }];
+/// let mut bytes_1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut bytes_2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// encode_to_write(&targets, &queries, &data_1, &mut bytes_1, opts.clone()).unwrap();
+/// encode_to_write(&targets, &queries, &data_2, &mut bytes_2, opts).unwrap();
+/// bytes_1.rewind();
+/// bytes_2.rewind();
+///
+/// let mut concatenated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// try_concatenate_from_read_to_write(&mut [bytes_1, bytes_2], &mut concatenated).unwrap();
+/// concatenated.rewind();
+///
+/// let mut deduplicated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let n_refs = deduplicate_blocks(&mut concatenated, &mut deduplicated).unwrap();
+/// deduplicated.rewind();
+///
+/// assert_eq!(n_refs, 1);
+/// assert!(deduplicated.get_ref().len() < concatenated.get_ref().len());
+///
+/// // Decoding is transparent
+/// let (_, _, records) = decode_from_read(&mut deduplicated).unwrap();
+/// assert_eq!(records.len(), 2);
+/// ```
+///
+pub fn deduplicate_blocks<R: Read + Seek, W: Write>(
+    conn_in: &mut R,
+    conn_out: &mut W,
+) -> Result<usize, E> {
+    let start = conn_in.stream_position()?;
+
+    // Find the first block containing each distinct contents
+    let header = read_file_header(conn_in)?;
+    conn_in.seek_relative(header.flags_len as i64)?;
+
+    let mut first_seen: HashMap<(u64, usize, u8), u64> = HashMap::new();
+    let mut references: HashMap<u64, u64> = HashMap::new();
+    let mut index = 0_u64;
+    while let Ok((block_header, _, contents)) = read_raw_block(conn_in) {
+        if block_header.placeholder1 & BLOCK_IS_REFERENCE == 0 {
+            let key = (hash_contents(&contents), contents.len(), block_header.placeholder1 & crate::compression::BLOCK_PAYLOAD_RAW);
+            match first_seen.get(&key) {
+                Some(first) if index - *first <= u16::MAX as u64 => {
+                    references.insert(index, *first);
+                },
+                _ => {
+                    first_seen.insert(key, index);
+                },
+            }
+        }
+        index += 1;
+    }
+    let referenced: std::collections::HashSet<u64> = references.values().cloned().collect();
+
+    // Write the deduplicated record
+    conn_in.seek(std::io::SeekFrom::Start(start))?;
+    let header = read_file_header(conn_in)?;
+    let flags = read_len_bytes(conn_in, header.flags_len)?;
+    conn_out.write_all(&encode_file_header(&header)?)?;
+    conn_out.write_all(&flags)?;
+
+    let mut kept: HashMap<u64, Vec<u8>> = HashMap::new();
+    let mut n_refs = 0_usize;
+    let mut index = 0_u64;
+    while let Ok((mut block_header, flags, contents)) = read_raw_block(conn_in) {
+        let mut write_contents = true;
+        if let Some(first) = references.get(&index) {
+            // Compare the bytes to guard against hash collisions
+            if kept.get(first).is_some_and(|x| *x == contents) {
+                block_header.placeholder1 |= BLOCK_IS_REFERENCE;
+                block_header.placeholder2 = (index - *first) as u16;
+                block_header.block_len = 0;
+                write_contents = false;
+                n_refs += 1;
+            }
+        }
+        if referenced.contains(&index) {
+            block_header.placeholder1 |= BLOCK_IS_REFERENCED;
+            kept.insert(index, contents.clone());
+        }
+
+        conn_out.write_all(&encode_block_header(&block_header)?)?;
+        conn_out.write_all(&flags)?;
+        if write_contents {
+            conn_out.write_all(&contents)?;
+        }
+        index += 1;
+    }
+    conn_out.flush()?;
+
+    Ok(n_refs)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn deduplicate_blocks_round_trip() {
+        use super::deduplicate_blocks;
+        use crate::decode_from_read;
+        use crate::encode_to_write;
+        use crate::try_concatenate_from_read_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+        use std::io::Seek;
+
+        let targets = ["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let queries = ["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec(), "r3".as_bytes().to_vec()];
+        let opts = EncodeOpts{ accession: "sample".as_bytes().to_vec(), ..Default::default() };
+
+        let data = [
+            PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![]), query_name: None },
+            PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![0, 1]), query_name: None },
+            PseudoAln{ones_names: None, query_id: Some(2), ones: Some(vec![]), query_name: None },
+        ];
+        let mut inputs: Vec<Cursor<Vec<u8>>> = data.iter().map(|record| {
+            let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+            encode_to_write(&targets, &queries, std::slice::from_ref(record), &mut bytes, opts.clone()).unwrap();
+            bytes.rewind().unwrap();
+            bytes
+        }).collect();
+
+        let mut concatenated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        try_concatenate_from_read_to_write(&mut inputs, &mut concatenated).unwrap();
+        concatenated.rewind().unwrap();
+        let (_, _, expected) = decode_from_read(&mut concatenated).unwrap();
+        concatenated.rewind().unwrap();
+
+        let mut deduplicated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let n_refs = deduplicate_blocks(&mut concatenated, &mut deduplicated).unwrap();
+        deduplicated.rewind().unwrap();
+        let (_, _, got) = decode_from_read(&mut deduplicated).unwrap();
+
+        assert_eq!(n_refs, 1);
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn read_block_into_reused_buffers() {
+        use super::deduplicate_blocks;
+        use super::BlockCache;
+        use crate::compression::BlockBuffers;
+        use crate::compression::roaring32::unpack_block_roaring32;
+        use crate::compression::roaring32::unpack_block_roaring32_with_buffers;
+        use crate::encoder::Encoder;
+        use crate::headers::file::read_file_header_and_flags;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+        use std::io::Seek;
+
+        // Blocks of different sizes, with the first and last blocks identical
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data: Vec<PseudoAln> = [vec![0], vec![0, 1], vec![1], vec![0, 1], vec![0], vec![0]].into_iter().enumerate().map(|(id, ones)| {
+            PseudoAln{ ones_names: None, query_id: Some(id as u32), ones: Some(if id == 4 { vec![0] } else { ones }), query_name: None }
+        }).collect();
+        let mut iter = data.into_iter();
+        let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 6);
+        encoder.set_block_size(2).unwrap();
+        let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+        for block in encoder.by_ref() {
+            bytes.append(&mut block.unwrap());
+        }
+        let mut deduplicated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        deduplicate_blocks(&mut Cursor::new(bytes), &mut deduplicated).unwrap();
+
+        let mut conn = Cursor::new(deduplicated.get_ref().clone());
+        read_file_header_and_flags(&mut conn).unwrap();
+        let mut expected = Vec::new();
+        let mut cache = BlockCache::new();
+        while let Ok((header, bytes)) = cache.read_block(&mut conn) {
+            expected.push(unpack_block_roaring32(&bytes, &header).unwrap());
+        }
+
+        deduplicated.rewind().unwrap();
+        read_file_header_and_flags(&mut deduplicated).unwrap();
+        let mut got = Vec::new();
+        let mut cache = BlockCache::new();
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut buffers = BlockBuffers::default();
+        while let Some(header) = cache.try_read_block_into(&mut deduplicated, &mut bytes).unwrap() {
+            got.push(unpack_block_roaring32_with_buffers(&bytes, &header, &mut buffers).unwrap());
+        }
+
+        assert_eq!(got.len(), 3);
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn read_block_with_missing_reference_fails() {
+        use super::BlockCache;
+        use super::BLOCK_IS_REFERENCE;
+        use crate::headers::block::BlockHeader;
+        use crate::headers::block::encode_block_header;
+
+        use std::io::Cursor;
+
+        let header = BlockHeader{ num_records: 0, metadata_compression: 0, bitmap_type: 0, placeholder1: BLOCK_IS_REFERENCE, block_len: 0, flags_len: 0, fields_present: 0, placeholder2: 1, placeholder3: 0 };
+        let mut bytes: Cursor<Vec<u8>> = Cursor::new(encode_block_header(&header).unwrap());
+
+        let mut cache = BlockCache::new();
+        let got = cache.read_block(&mut bytes);
+
+        assert!(got.is_err());
+    }
+}
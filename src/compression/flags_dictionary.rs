@@ -0,0 +1,222 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Compress the block flags with a zstd dictionary trained on the query names.
+//!
+//! Query names make up most of the block flags, and in files with short
+//! blocks gzip has little context to learn their shared prefixes from. A
+//! [FlagsDictionary] is trained on the names of the first blocks, stored in
+//! the file extensions under [FLAGS_DICTIONARY_KEY], and used to compress the
+//! flags of every block with zstd. The blocks that use it have
+//! [MetadataCompression::ZstdDictionary](crate::compression::MetadataCompression::ZstdDictionary) in the `metadata_compression` field
+//! of their block header, see
+//! [decode_block_flags_with_dictionary](crate::headers::block::decode_block_flags_with_dictionary).
+//!
+//! [Encoder::train_flags_dictionary](crate::encoder::Encoder::train_flags_dictionary)
+//! trains and sets the dictionary and [Decoder](crate::decoder::Decoder)
+//! reads it from the file flags. Versions of ahda before the dictionary was
+//! added and [ahda_core] can't read the block flags of these files.
+//!
+//! Training and compressing requires the `zstd` feature, which needs a C
+//! compiler.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::compression::flags_dictionary::FlagsDictionary;
+//!
+//! let names: Vec<Vec<u8>> = (0..10000).map(|i| format!("ERR4035126.{}", i * 7).into_bytes()).collect();
+//! # #[cfg(feature = "zstd")]
+//! # {
+//! let dictionary = FlagsDictionary::train(&names, 4096).unwrap().unwrap();
+//!
+//! let bytes = b"ERR4035126.70ERR4035126.77ERR4035126.84".to_vec();
+//! let compressed = dictionary.compress(&bytes).unwrap();
+//! let mut decompressed: Vec<u8> = Vec::new();
+//! dictionary.decompress_into(&compressed, &mut decompressed).unwrap();
+//! assert_eq!(decompressed, bytes);
+//! # }
+//! ```
+//!
+
+use crate::headers::file::FileFlags;
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the [FlagsDictionary] stored in the file extensions.
+pub const FLAGS_DICTIONARY_KEY: &[u8] = b"ahda.flags_dictionary";
+
+/// Default maximum size of a trained dictionary in bytes.
+pub const DEFAULT_DICTIONARY_SIZE: usize = 16 * 1024;
+
+/// Default number of blocks whose query names the dictionary is trained on.
+pub const DEFAULT_TRAINING_BLOCKS: usize = 4;
+
+/// Zstd compression level of the block flags.
+pub const FLAGS_LEVEL: i32 = 9;
+
+// Query names in each training sample, about the size of a small block
+const NAMES_PER_SAMPLE: usize = 64;
+
+// Fewer samples than this don't train a useful dictionary
+const MIN_SAMPLES: usize = 8;
+
+/// Zstd dictionary for the block flags, see [MetadataCompression::ZstdDictionary](crate::compression::MetadataCompression::ZstdDictionary).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlagsDictionary {
+    bytes: Vec<u8>,
+}
+
+impl FlagsDictionary {
+    /// Use the dictionary in `bytes`, eg. one returned by [as_bytes](FlagsDictionary::as_bytes).
+    pub fn new(
+        bytes: Vec<u8>,
+    ) -> Self {
+        FlagsDictionary{ bytes }
+    }
+
+    /// Train a dictionary of at most `max_size` bytes on the query names in `names`.
+    ///
+    /// The dictionary is also at most a tenth of the size of the encoded names.
+    /// Returns None if there are too few names to train a dictionary, or if
+    /// zstd can't find anything to learn from them.
+    ///
+    /// ## Errors and panics
+    /// Errors with [MissingFeatureErr](crate::errors::MissingFeatureErr) if
+    /// ahda was compiled without the `zstd` feature.
+    ///
+    pub fn train(
+        names: &[Vec<u8>],
+        max_size: usize,
+    ) -> Result<Option<Self>, E> {
+        // Samples are encoded like the names in the block flags
+        let samples = names.chunks(NAMES_PER_SAMPLE).map(|chunk| {
+            bincode::encode_to_vec(chunk, bincode::config::standard())
+        }).collect::<Result<Vec<Vec<u8>>, _>>()?;
+        if samples.len() < MIN_SAMPLES {
+            return Ok(None)
+        }
+
+        #[cfg(feature = "zstd")]
+        {
+            // A dictionary larger than a tenth of the samples costs more than it saves
+            let total_size: usize = samples.iter().map(|x| x.len()).sum();
+            match zstd::dict::from_samples(&samples, max_size.min(total_size / 10)) {
+                Ok(bytes) => Ok(Some(FlagsDictionary{ bytes })),
+                Err(e) => {
+                    log::debug!("can't train a flags dictionary: {}", e);
+                    Ok(None)
+                },
+            }
+        }
+        #[cfg(not(feature = "zstd"))]
+        {
+            let _ = max_size;
+            Err(Box::new(crate::errors::MissingFeatureErr{ feature: "zstd" }))
+        }
+    }
+
+    /// Read the dictionary stored in `flags`, if any.
+    pub fn from_flags(
+        flags: &FileFlags,
+    ) -> Option<Self> {
+        flags.extensions.as_ref().and_then(|x| x.get(FLAGS_DICTIONARY_KEY)).map(|x| FlagsDictionary::new(x.to_vec()))
+    }
+
+    /// Bytes of the dictionary.
+    pub fn as_bytes(
+        &self,
+    ) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Compress `bytes` with zstd using the dictionary.
+    ///
+    /// ## Errors and panics
+    /// Errors with [MissingFeatureErr](crate::errors::MissingFeatureErr) if
+    /// ahda was compiled without the `zstd` feature.
+    ///
+    pub fn compress(
+        &self,
+        bytes: &[u8],
+    ) -> Result<Vec<u8>, E> {
+        #[cfg(feature = "zstd")]
+        {
+            let mut compressor = zstd::bulk::Compressor::with_dictionary(FLAGS_LEVEL, &self.bytes)?;
+            Ok(compressor.compress(bytes)?)
+        }
+        #[cfg(not(feature = "zstd"))]
+        {
+            let _ = bytes;
+            Err(Box::new(crate::errors::MissingFeatureErr{ feature: "zstd" }))
+        }
+    }
+
+    /// Decompress `bytes` written by [compress](FlagsDictionary::compress) into `buffer`.
+    ///
+    /// `buffer` is cleared first.
+    ///
+    /// ## Errors and panics
+    /// Errors if `bytes` were not compressed with this dictionary, and with
+    /// [MissingFeatureErr](crate::errors::MissingFeatureErr) if ahda was
+    /// compiled without the `zstd` feature.
+    ///
+    pub fn decompress_into(
+        &self,
+        bytes: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), E> {
+        buffer.clear();
+        #[cfg(feature = "zstd")]
+        {
+            use std::io::Read;
+            let mut decoder = zstd::stream::read::Decoder::with_dictionary(bytes, &self.bytes)?;
+            decoder.read_to_end(buffer)?;
+            Ok(())
+        }
+        #[cfg(not(feature = "zstd"))]
+        {
+            let _ = bytes;
+            Err(Box::new(crate::errors::MissingFeatureErr{ feature: "zstd" }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn train_needs_enough_names() {
+        use super::FlagsDictionary;
+
+        let names: Vec<Vec<u8>> = (0..10).map(|i| format!("r{}", i).into_bytes()).collect();
+        assert_eq!(FlagsDictionary::train(&names, 1024).unwrap(), None);
+    }
+
+    #[test]
+    fn dictionary_from_flags() {
+        use super::FlagsDictionary;
+        use super::FLAGS_DICTIONARY_KEY;
+        use crate::headers::extensions::Extensions;
+        use crate::headers::file::FileFlags;
+
+        let mut flags = FileFlags::default();
+        assert_eq!(FlagsDictionary::from_flags(&flags), None);
+
+        let mut extensions = Extensions::default();
+        extensions.insert(FLAGS_DICTIONARY_KEY, vec![1, 2, 3]);
+        flags.extensions = Some(extensions);
+        assert_eq!(FlagsDictionary::from_flags(&flags), Some(FlagsDictionary::new(vec![1, 2, 3])));
+    }
+}
@@ -22,22 +22,90 @@ use std::io::Write;
 
 type E = Box<dyn std::error::Error>;
 
+/// Gzip compression level used unless another level is requested.
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// Check that `level` is a valid gzip compression level (1-9).
+pub fn check_level(
+    level: u32,
+) -> Result<(), E> {
+    if !(1..=9).contains(&level) {
+        return Err(Box::new(crate::errors::InvalidCompressionLevelErr{}))
+    }
+    Ok(())
+}
+
 pub fn deflate_bytes(
     bytes: &[u8],
 ) -> Result<Vec<u8>, E> {
+    deflate_bytes_with_level(bytes, DEFAULT_LEVEL)
+}
+
+/// Gzip `bytes` using compression `level` from 1 (fastest) to 9 (smallest).
+pub fn deflate_bytes_with_level(
+    bytes: &[u8],
+    level: u32,
+) -> Result<Vec<u8>, E> {
+    check_level(level)?;
     let mut deflated: Vec<u8> = Vec::with_capacity(bytes.len());
-    let mut encoder = GzEncoder::new(&mut deflated, Compression::default());
+    let mut encoder = GzEncoder::new(&mut deflated, Compression::new(level));
     encoder.write_all(bytes)?;
     encoder.finish()?;
     Ok(deflated)
 }
 
+/// Pad the gzip member `deflated` to exactly `len` bytes.
+///
+/// The padding is stored as a subfield of the optional extra field in the
+/// gzip header, which decompressors skip, so the member decompresses to the
+/// same bytes. Returns None if `deflated` already has an extra field or
+/// can't be padded to `len`, ie. if it is longer, less than 6 bytes shorter
+/// but not equal, or more than 65535 bytes shorter.
+pub fn pad_deflated(
+    deflated: &[u8],
+    len: usize,
+) -> Option<Vec<u8>> {
+    const FEXTRA: u8 = 1 << 2;
+    if deflated.len() == len {
+        return Some(deflated.to_vec())
+    }
+    if deflated.len() < 10 || deflated[3] & FEXTRA != 0 {
+        return None
+    }
+    // Extra field length, then the subfield id `AP` and its length
+    let xlen = u16::try_from(len.checked_sub(deflated.len())?.checked_sub(2)?).ok()?;
+    let sublen = xlen.checked_sub(4)?;
+
+    let mut padded: Vec<u8> = Vec::with_capacity(len);
+    padded.extend_from_slice(&deflated[0..10]);
+    padded[3] |= FEXTRA;
+    padded.extend_from_slice(&xlen.to_le_bytes());
+    padded.extend_from_slice(b"AP");
+    padded.extend_from_slice(&sublen.to_le_bytes());
+    padded.resize(padded.len() + sublen as usize, 0);
+    padded.extend_from_slice(&deflated[10..]);
+    Some(padded)
+}
+
 pub fn inflate_bytes(
     deflated: &[u8],
 ) -> Result<Vec<u8>, E> {
     let mut inflated: Vec<u8> = Vec::new();
-    let mut decoder = GzDecoder::new(&mut inflated);
+    inflate_bytes_into(deflated, &mut inflated)?;
+    Ok(inflated)
+}
+
+/// Gunzip `deflated` into `inflated`, replacing its contents.
+///
+/// Reuses the capacity of `inflated`, so decompressing many blocks into the
+/// same buffer only allocates when a block is larger than the previous ones.
+pub fn inflate_bytes_into(
+    deflated: &[u8],
+    inflated: &mut Vec<u8>,
+) -> Result<(), E> {
+    inflated.clear();
+    let mut decoder = GzDecoder::new(inflated);
     decoder.write_all(deflated)?;
     decoder.finish()?;
-    Ok(inflated)
+    Ok(())
 }
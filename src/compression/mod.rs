@@ -26,28 +26,121 @@
 //! - Serialize the bitmap representation to a valid .ahda block record (u8 bytes).
 //! - Deserialize a valid .ahda block record (u8 bytes) to the bitmap representation.
 //!
+//! ## Block payload compression
+//! The serialized bitmap in each block is gzipped only if this shrinks it by at
+//! least [MIN_PAYLOAD_SAVINGS] percent. Serialized roaring bitmaps are often
+//! close to incompressible, in which case the bytes are stored as-is and
+//! [BLOCK_PAYLOAD_RAW] is set in the block header `placeholder1` field.
+//!
+//! The gzip compression level can be set from 1 to 9, see
+//! [pack_records_with_level]. Lower levels encode faster and higher levels
+//! produce smaller blocks. The default level 6 is close to the smallest size
+//! for roaring bitmaps and levels above it are rarely worth the extra time.
+//! Decoding speed does not depend on the level.
+//!
+//! Gzip uses the zlib-rs backend of [flate2] by default. Building with the
+//! `zlib-ng` feature uses zlib-ng instead, which is faster but requires a C
+//! compiler and cmake.
+//!
+//! ## Inline blocks
+//! Records small enough to fit in a single block of at most
+//! [INLINE_MAX_RECORDS] records are stored with the block flags and bitmap
+//! uncompressed if this is smaller than compressing them, see
+//! [pack_records_inline]. [BLOCK_FLAGS_RAW] and [BLOCK_PAYLOAD_RAW] are set
+//! in the block header.
+//!
+//! ## Block statistics
+//! The number of set bits and the largest target index of each block are
+//! recorded in the block header, see
+//! [BlockStats](crate::headers::block::BlockStats). Roaring treemaps are
+//! optimized for run containers only if the block is at least
+//! [RUN_OPTIMIZE_DENSITY] dense; roaring bitmaps are always optimized.
+//!
+//! ## Whole-file compression
+//! Building with the `zstd` feature adds a container that compresses the
+//! whole record with zstd, one frame per block, see `zstd_seekable`.
+//!
 //! ## Metadata compression schemes
 //! Currently supported:
 //! - Flate2
+//! - Zstd with a dictionary trained on the query names, for the block flags
+//!   only, see [flags_dictionary].
 //!
 //! New schemes should implement the following:
 //! - Compress bytes (u8).
 //! - Decompress bytes (u8).
 
+pub mod dedup;
+pub mod flags_dictionary;
 pub mod gzwrapper;
 pub mod roaring32;
 pub mod roaring64;
+#[cfg(feature = "zstd")]
+pub mod zstd_seekable;
 
 use crate::PseudoAln;
+use crate::bloom::BloomFilter;
+use crate::headers::block::BlockFlags;
+use crate::headers::block::BlockHeader;
+use crate::headers::block::BlockStats;
+use crate::headers::extensions::Extensions;
+use crate::headers::block::decode_block_flags_with_dictionary;
+use crate::headers::block::encode_block_flags_with_header;
+use crate::headers::block::pack_mates;
 use crate::headers::file::FileHeader;
+use crate::headers::file::RemapTable;
+use crate::compression::flags_dictionary::FlagsDictionary;
+use crate::compression::gzwrapper::DEFAULT_LEVEL;
+use crate::compression::gzwrapper::deflate_bytes_with_level;
+use crate::compression::gzwrapper::inflate_bytes;
+use crate::compression::gzwrapper::inflate_bytes_into;
 
 use roaring32::convert_to_roaring32;
-use roaring32::pack_block_roaring32;
+use roaring32::pack_block_roaring32_inline;
+use roaring32::pack_block_roaring32_with_dictionary;
 use roaring64::convert_to_roaring64;
-use roaring64::pack_block_roaring64;
+use roaring64::pack_block_roaring64_inline;
+use roaring64::pack_block_roaring64_with_dictionary;
+
+use ahda_core::block::choose_payload;
+use ahda_core::block::pack_block;
+
+use roaring::RoaringBitmap;
 
 type E = Box<dyn std::error::Error>;
 
+pub use ahda_core::block::BLOCK_FLAGS_RAW;
+pub use ahda_core::block::BLOCK_PAYLOAD_RAW;
+pub use ahda_core::block::BLOCK_HAS_STATS;
+pub use ahda_core::block::BLOCK_QUERY_IDS_SORTED;
+pub use ahda_core::block::BLOCK_QUERY_IDS_U16;
+pub use ahda_core::block::MIN_PAYLOAD_SAVINGS;
+pub use ahda_core::file::FILE_IS_ZSTD_SEEKABLE;
+pub use ahda_core::file::FILE_HAS_TARGET_CHUNKS;
+
+/// Files of at most this many records in a single block may be stored inline.
+///
+/// The [Encoder](crate::encoder::Encoder) stores the block flags and bitmap
+/// of these without compression if that is smaller than compressing them,
+/// see [pack_records_inline]. This is usually the case for files of a few
+/// dozen records, where the gzip headers take more space than compressing
+/// saves.
+pub const INLINE_MAX_RECORDS: usize = 4096;
+
+/// Blocks at least this dense are optimized for run containers before
+/// storing them as a [RoaringTreemap](roaring::RoaringTreemap).
+///
+/// Runs of set bits only occur when most targets are aligned to, and
+/// looking for them in sparse blocks is wasted time.
+pub const RUN_OPTIMIZE_DENSITY: f64 = 0.5;
+
+/// Blocks should fit at least this many queries, see [check_shape].
+///
+/// A [Roaring32](BitmapType::Roaring32) block fits fewer queries with more
+/// than about four million targets.
+pub const MIN_BLOCK_QUERIES: u32 = 1024;
+
+
 /// Supported bitmap types for an .ahda record
 #[non_exhaustive]
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
@@ -65,7 +158,7 @@ impl BitmapType {
         match val {
             0 => Ok(BitmapType::Roaring32),
             1 => Ok(BitmapType::Roaring64),
-            _ => panic!("Not a valid BitmapType"),
+            _ => Err(Box::new(crate::errors::UnknownBitmapTypeErr{})),
         }
     }
 
@@ -75,6 +168,160 @@ impl BitmapType {
             BitmapType::Roaring64 => 1,
         }
     }
+
+    /// Smallest bitmap type that fits `n_queries` times `n_targets` bits.
+    ///
+    /// Encoders pick this type when they are created, see
+    /// [auto](BitmapType::auto). Files that don't fit in
+    /// the 32-bit address space of [Roaring32](BitmapType::Roaring32) are
+    /// promoted to [Roaring64](BitmapType::Roaring64).
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::compression::BitmapType;
+    ///
+    /// assert_eq!(BitmapType::for_size(64, 1000), BitmapType::Roaring32);
+    /// assert_eq!(BitmapType::for_size(1_000_000, 1_000_000), BitmapType::Roaring64);
+    /// ```
+    ///
+    pub fn for_size(
+        n_targets: usize,
+        n_queries: usize,
+    ) -> Self {
+        let bitmap_size = (n_targets as u64) * (n_queries as u64);
+        if bitmap_size < u32::MAX as u64 { BitmapType::Roaring32 } else { BitmapType::Roaring64 }
+    }
+
+    /// Bitmap type that encoders pick for `n_targets` targets and `n_queries` queries.
+    ///
+    /// Same as [for_size](BitmapType::for_size), except that an unknown
+    /// number of queries, ie. 0, with so many targets that a
+    /// [Roaring32](BitmapType::Roaring32) block would fit fewer than
+    /// [MIN_BLOCK_QUERIES] queries uses [Roaring64](BitmapType::Roaring64).
+    /// The query ids of such files would overflow the 32-bit address space
+    /// after the first few blocks.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::compression::BitmapType;
+    ///
+    /// assert_eq!(BitmapType::auto(10_000_000, 400), BitmapType::Roaring32);
+    /// assert_eq!(BitmapType::auto(10_000_000, 0), BitmapType::Roaring64);
+    /// assert_eq!(BitmapType::auto(64, 0), BitmapType::Roaring32);
+    /// ```
+    ///
+    pub fn auto(
+        n_targets: usize,
+        n_queries: usize,
+    ) -> Self {
+        let block_size = crate::headers::file::default_block_size(&BitmapType::Roaring32, n_targets);
+        if n_queries == 0 && block_size < MIN_BLOCK_QUERIES {
+            BitmapType::Roaring64
+        } else {
+            BitmapType::for_size(n_targets, n_queries)
+        }
+    }
+}
+
+/// Number of queries and targets that split a file into very many small blocks, see [check_shape].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShapeWarning {
+    /// Number of targets.
+    pub n_targets: u32,
+    /// Number of queries, 0 if unknown.
+    pub n_queries: u32,
+    /// Number of queries in a block.
+    pub block_size: u32,
+}
+
+impl ShapeWarning {
+    /// Suggested fix for the warning.
+    pub fn suggestion(
+        &self,
+    ) -> String {
+        let width = crate::target_chunks::DEFAULT_TARGET_CHUNK_WIDTH;
+        format!("encode with `--bitmap roaring64` to fit {} queries in each block, or with `--target-chunks {}` to fit {} queries in each sub-block", crate::headers::file::default_block_size(&BitmapType::Roaring64, self.n_targets as usize), width, crate::headers::file::default_block_size(&BitmapType::Roaring32, width as usize))
+    }
+}
+
+impl std::fmt::Display for ShapeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.n_queries == 0 {
+            write!(f, "blocks fit only {} queries with {} targets", self.block_size, self.n_targets)
+        } else {
+            let n_blocks = self.n_queries.div_ceil(self.block_size.max(1));
+            write!(f, "blocks fit only {} queries with {} targets, which splits {} queries into {} blocks", self.block_size, self.n_targets, self.n_queries, n_blocks)
+        }
+    }
+}
+
+/// Check if the blocks of `header` are impractically small for its number of targets.
+///
+/// Returns a [ShapeWarning] if there are so many targets that a
+/// [Roaring32](BitmapType::Roaring32) block would fit fewer than
+/// [MIN_BLOCK_QUERIES] queries, the blocks of `header` are that small, and
+/// the file has more queries than fit in one block or an unknown number.
+/// Decoding such files spends most of its time on the block headers and
+/// flags.
+///
+/// [Roaring64](BitmapType::Roaring64) blocks fit the same number of queries
+/// regardless of the number of targets and are the layout to use for these
+/// files, see [BitmapType::auto]. Alternatively, the targets of each block
+/// can be split across sub-blocks with fewer targets, see
+/// [target_chunks](crate::target_chunks).
+///
+/// ## Usage
+/// ```rust
+/// use ahda::compression::check_shape;
+/// use ahda::compression::BitmapType;
+/// use ahda::compression::MetadataCompression;
+/// use ahda::headers::file::FileHeader;
+/// use ahda::headers::file::build_file_header_and_flags;
+/// use ahda::headers::file::default_block_size;
+///
+/// let (header, _) = build_file_header_and_flags(&[b"chr.fasta".to_vec()], 0, b"sample", &MetadataCompression::default()).unwrap();
+///
+/// // Ten million targets leave room for 428 queries in a Roaring32 block
+/// let block_size = default_block_size(&BitmapType::Roaring32, 10_000_000);
+/// let header = FileHeader{ n_targets: 10_000_000, n_queries: 100_000, block_size, ..header };
+/// assert_eq!(check_shape(&header).unwrap().to_string(), "blocks fit only 428 queries with 10000000 targets, which splits 100000 queries into 234 blocks");
+///
+/// let block_size = default_block_size(&BitmapType::Roaring64, 10_000_000);
+/// let header = FileHeader{ bitmap_type: BitmapType::Roaring64.to_u16(), block_size, ..header };
+/// assert_eq!(check_shape(&header), None);
+/// ```
+///
+pub fn check_shape(
+    header: &FileHeader,
+) -> Option<ShapeWarning> {
+    let capacity = crate::headers::file::default_block_size(&BitmapType::Roaring32, header.n_targets as usize);
+    let small = capacity < MIN_BLOCK_QUERIES && header.block_size < MIN_BLOCK_QUERIES;
+    if small && (header.n_queries == 0 || header.n_queries > header.block_size) {
+        Some(ShapeWarning{ n_targets: header.n_targets, n_queries: header.n_queries, block_size: header.block_size })
+    } else {
+        None
+    }
+}
+
+impl std::str::FromStr for BitmapType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "roaring32" => Ok(BitmapType::Roaring32),
+            "roaring64" => Ok(BitmapType::Roaring64),
+            _ => Err(format!("'{}' is not a valid BitmapType", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for BitmapType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BitmapType::Roaring32 => write!(f, "roaring32"),
+            BitmapType::Roaring64 => write!(f, "roaring64"),
+        }
+    }
 }
 
 /// Supported compression methods for [FileFlags](crate::headers::file::FileFlags) and [BlockFlags](crate::headers::block::BlockFlags).
@@ -86,6 +333,11 @@ pub enum MetadataCompression {
     BincodeStandard,
     /// Gz with flate2
     Flate2,
+    /// Zstd with the [FlagsDictionary](flags_dictionary::FlagsDictionary) stored in the file flags
+    ///
+    /// Only used for the block flags, the file flags that store the
+    /// dictionary use one of the other methods.
+    ZstdDictionary,
 }
 
 
@@ -94,7 +346,8 @@ impl MetadataCompression {
         match val {
             0 => Ok(MetadataCompression::BincodeStandard),
             1 => Ok(MetadataCompression::Flate2),
-            _ => panic!("Not a valid MetadataCompression"),
+            2 => Ok(MetadataCompression::ZstdDictionary),
+            _ => Err(Box::new(crate::errors::UnknownMetadataCompressionErr{})),
         }
     }
 
@@ -102,6 +355,7 @@ impl MetadataCompression {
         match &self {
             MetadataCompression::BincodeStandard => 0,
             MetadataCompression::Flate2 => 1,
+            MetadataCompression::ZstdDictionary => 2,
         }
     }
 }
@@ -111,6 +365,102 @@ pub fn pack_records(
     file_header: &FileHeader,
     records: Vec<PseudoAln>,
 ) -> Result<Vec<u8>, E> {
+    pack_records_with_level(file_header, records, DEFAULT_LEVEL)
+}
+
+/// Compress a block of [PseudoAln] records with gzip compression `level` (1-9).
+pub fn pack_records_with_level(
+    file_header: &FileHeader,
+    records: Vec<PseudoAln>,
+    level: u32,
+) -> Result<Vec<u8>, E> {
+    pack_records_with_mates(file_header, records, level, None)
+}
+
+/// Same as [pack_records_with_level] but also stores the mate of each record.
+///
+/// Records whose query id is in `second_mates` are marked as the second mate
+/// of a read pair and the others as the first mate, see [BlockFlags::mate].
+pub fn pack_records_with_mates(
+    file_header: &FileHeader,
+    records: Vec<PseudoAln>,
+    level: u32,
+    second_mates: Option<&RoaringBitmap>,
+) -> Result<Vec<u8>, E> {
+    pack_records_with_query_filter(file_header, records, level, second_mates, None)
+}
+
+/// Same as [pack_records_with_mates] but also stores a Bloom filter of the query names.
+///
+/// The filter is built with false positive rate `query_filter_fpr`, see
+/// [BlockFlags::might_contain]. The [BlockStats] of the records are recorded
+/// in the block header.
+pub fn pack_records_with_query_filter(
+    file_header: &FileHeader,
+    records: Vec<PseudoAln>,
+    level: u32,
+    second_mates: Option<&RoaringBitmap>,
+    query_filter_fpr: Option<f64>,
+) -> Result<Vec<u8>, E> {
+    pack_records_with_flags_dictionary(file_header, records, level, second_mates, query_filter_fpr, None)
+}
+
+/// Same as [pack_records_with_query_filter] but compresses the block flags with `dictionary`.
+///
+/// The block flags are gzipped if `dictionary` is None, see
+/// [FlagsDictionary](flags_dictionary::FlagsDictionary).
+pub fn pack_records_with_flags_dictionary(
+    file_header: &FileHeader,
+    records: Vec<PseudoAln>,
+    level: u32,
+    second_mates: Option<&RoaringBitmap>,
+    query_filter_fpr: Option<f64>,
+    dictionary: Option<&FlagsDictionary>,
+) -> Result<Vec<u8>, E> {
+    pack_records_with_extensions(file_header, records, level, second_mates, query_filter_fpr, dictionary, None)
+}
+
+/// Same as [pack_records_with_flags_dictionary] but also stores `extensions` in the block flags.
+pub fn pack_records_with_extensions(
+    file_header: &FileHeader,
+    records: Vec<PseudoAln>,
+    level: u32,
+    second_mates: Option<&RoaringBitmap>,
+    query_filter_fpr: Option<f64>,
+    dictionary: Option<&FlagsDictionary>,
+    extensions: Option<Extensions>,
+) -> Result<Vec<u8>, E> {
+    pack_records_with_payload(file_header, records, Some((level, dictionary)), second_mates, query_filter_fpr, extensions)
+}
+
+/// Same as [pack_records_with_extensions] but stores the block flags and bitmap without compressing them.
+///
+/// Meant for records small enough to fit in a single block, where the gzip
+/// headers of the flags and the bitmap take more space than compressing
+/// saves, see [INLINE_MAX_RECORDS] and [BLOCK_FLAGS_RAW].
+pub fn pack_records_inline(
+    file_header: &FileHeader,
+    records: Vec<PseudoAln>,
+    second_mates: Option<&RoaringBitmap>,
+    query_filter_fpr: Option<f64>,
+    extensions: Option<Extensions>,
+) -> Result<Vec<u8>, E> {
+    pack_records_with_payload(file_header, records, None, second_mates, query_filter_fpr, extensions)
+}
+
+// Compresses the block with `compression` (gzip level and flags dictionary), or stores it inline if None
+fn pack_records_with_payload(
+    file_header: &FileHeader,
+    records: Vec<PseudoAln>,
+    compression: Option<(u32, Option<&FlagsDictionary>)>,
+    second_mates: Option<&RoaringBitmap>,
+    query_filter_fpr: Option<f64>,
+    extensions: Option<Extensions>,
+) -> Result<Vec<u8>, E> {
+    // Block flags store the records in query id order
+    let mut records = records;
+    records.sort_by_key(|record| record.query_id);
+
     let queries: Vec<Vec<u8>> = records.iter().filter_map(|record| {
         record.query_name.clone()
     }).collect();
@@ -120,16 +470,416 @@ pub fn pack_records(
         record.query_id
     }).collect();
 
+    let mates = second_mates.map(|second| pack_mates(query_ids.iter().map(|id| second.contains(*id))));
+    let query_filter = query_filter_fpr.map(|fpr| {
+        let mut filter = BloomFilter::with_fpr(queries.len(), fpr);
+        queries.iter().for_each(|name| filter.insert(name));
+        filter
+    });
+    let flags = BlockFlags{ queries: Some(queries), query_ids: Some(query_ids), mates, query_filter, extensions, unknown_fields: Vec::new() };
+    let stats = BlockStats::from_ones(records.iter().filter_map(|record| record.ones.as_deref()));
+    let density = stats.density(records.len() as u32, file_header.n_targets);
+
     let block = match BitmapType::from_u16(file_header.bitmap_type)? {
         BitmapType::Roaring32 => {
             let bitmap = convert_to_roaring32(file_header, records)?;
-            pack_block_roaring32(&queries, &query_ids, bitmap)?
+            match compression {
+                Some((level, dictionary)) => pack_block_roaring32_with_dictionary(&flags, bitmap, level, Some(&stats), dictionary)?,
+                None => pack_block_roaring32_inline(&flags, bitmap, Some(&stats))?,
+            }
         },
         BitmapType::Roaring64 => {
-            let bitmap = convert_to_roaring64(file_header, records)?;
-            pack_block_roaring64(&queries, &query_ids, bitmap)?
+            let mut bitmap = convert_to_roaring64(file_header, records)?;
+            if density >= RUN_OPTIMIZE_DENSITY {
+                bitmap.optimize();
+            }
+            match compression {
+                Some((level, dictionary)) => pack_block_roaring64_with_dictionary(&flags, bitmap, level, Some(&stats), dictionary)?,
+                None => pack_block_roaring64_inline(&flags, bitmap, Some(&stats))?,
+            }
         }
     };
 
     Ok(block)
 }
+
+pub fn compress_payload(
+    bytes: Vec<u8>,
+) -> Result<(Vec<u8>, u8), E> {
+    compress_payload_with_level(bytes, DEFAULT_LEVEL)
+}
+
+/// Gzip a serialized bitmap with compression `level` (1-9) if it is worth it.
+///
+/// See [compress_payload] for details.
+pub fn compress_payload_with_level(
+    bytes: Vec<u8>,
+    level: u32,
+) -> Result<(Vec<u8>, u8), E> {
+    let deflated = deflate_bytes_with_level(&bytes, level)?;
+    Ok(choose_payload(bytes, deflated))
+}
+
+/// Get the serialized bitmap from block payload written with [compress_payload].
+pub fn decompress_payload(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+) -> Result<Vec<u8>, E> {
+    if block_header.placeholder1 & BLOCK_PAYLOAD_RAW != 0 {
+        Ok(bytes.to_vec())
+    } else {
+        inflate_bytes(bytes)
+    }
+}
+
+/// Same as [decompress_payload] but decompresses into `buffer`.
+///
+/// Returns `bytes` itself if the payload is not compressed.
+pub fn decompress_payload_with_buffer<'a>(
+    bytes: &'a [u8],
+    block_header: &BlockHeader,
+    buffer: &'a mut Vec<u8>,
+) -> Result<&'a [u8], E> {
+    if block_header.placeholder1 & BLOCK_PAYLOAD_RAW != 0 {
+        Ok(bytes)
+    } else {
+        inflate_bytes_into(bytes, buffer)?;
+        Ok(buffer.as_slice())
+    }
+}
+
+/// Add `offset` to the query ids of the block in `bytes` without decoding its records.
+///
+/// `bytes` has the block flags and payload of the block with
+/// `block_header`, eg. from
+/// [BlockCache::try_read_block_into](dedup::BlockCache::try_read_block_into),
+/// and `n_targets` is the number of targets in the record. The set bits of
+/// the bitmap are shifted by `offset` rows and the block flags are
+/// compressed with `dictionary`, or gzipped if it is None. The statistics
+/// and [BLOCK_QUERY_IDS_SORTED] are kept from `block_header`.
+///
+/// The block is written with `file_bitmap_type`, the bitmap type of the
+/// output [FileHeader], so a roaring32 block is promoted to a roaring64
+/// block if the output is [Roaring64](BitmapType::Roaring64).
+///
+/// Returns the block and its renumbered flags.
+///
+/// ## Errors and panics
+/// Errors with [Roaring32OverflowErr](crate::errors::Roaring32OverflowErr) if
+/// the shifted bits don't fit in 32 bits but `file_bitmap_type` is
+/// [Roaring32](BitmapType::Roaring32), with
+/// [QueryIndexOutOfRangeErr](crate::errors::QueryIndexOutOfRangeErr) if a
+/// query id doesn't fit in a u32, and if the block can't be decoded.
+///
+pub fn renumber_block(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+    offset: u32,
+    n_targets: u32,
+    file_bitmap_type: &BitmapType,
+    buffers: &mut BlockBuffers,
+    dictionary: Option<&FlagsDictionary>,
+) -> Result<(Vec<u8>, BlockFlags), E> {
+    reindex_block(bytes, block_header, offset, None, n_targets, file_bitmap_type, buffers, dictionary)
+}
+
+/// Move the targets of the block in `bytes` into merged targets and add `offset` to its query ids.
+///
+/// Same as [renumber_block] but the target indexes of the bits are also
+/// replaced with their indexes in `table`, see
+/// [TargetDict::merge](crate::headers::file::TargetDict::merge), and
+/// `n_targets` is the number of merged targets. The largest target in the
+/// statistics of the block is updated to match.
+///
+/// ## Errors and panics
+/// Same as [renumber_block].
+///
+#[allow(clippy::too_many_arguments)]
+pub fn remap_block(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+    offset: u32,
+    table: &RemapTable,
+    n_targets: u32,
+    file_bitmap_type: &BitmapType,
+    buffers: &mut BlockBuffers,
+    dictionary: Option<&FlagsDictionary>,
+) -> Result<(Vec<u8>, BlockFlags), E> {
+    reindex_block(bytes, block_header, offset, Some(table), n_targets, file_bitmap_type, buffers, dictionary)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn reindex_block(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+    offset: u32,
+    table: Option<&RemapTable>,
+    n_targets: u32,
+    file_bitmap_type: &BitmapType,
+    buffers: &mut BlockBuffers,
+    dictionary: Option<&FlagsDictionary>,
+) -> Result<(Vec<u8>, BlockFlags), E> {
+    let (bitmap, mut flags) = match BitmapType::from_u16(block_header.bitmap_type)? {
+        BitmapType::Roaring32 => {
+            let (bitmap, flags) = roaring32::unpack_block_roaring32_with_buffers(bytes, block_header, buffers)?;
+            (roaring::RoaringTreemap::from_bitmaps([(0, bitmap)]), flags)
+        },
+        BitmapType::Roaring64 => roaring64::unpack_block_roaring64_with_buffers(bytes, block_header, buffers)?,
+    };
+    let bitmap = match table {
+        Some(table) => table.remap_bitmap(&bitmap, n_targets)?,
+        None => bitmap,
+    };
+
+    let shift = (offset as u64).checked_mul(n_targets as u64).ok_or(crate::errors::QueryIndexOutOfRangeErr{})?;
+    let max = bitmap.max().map(|x| x.checked_add(shift).ok_or(crate::errors::QueryIndexOutOfRangeErr{})).transpose()?;
+    let mut payload: Vec<u8> = Vec::new();
+    match file_bitmap_type {
+        BitmapType::Roaring32 => {
+            if max.is_some_and(|x| x > u32::MAX as u64) {
+                return Err(Box::new(crate::errors::Roaring32OverflowErr{}))
+            }
+            RoaringBitmap::from_sorted_iter(bitmap.iter().map(|x| (x + shift) as u32))?.serialize_into(&mut payload)?;
+        },
+        BitmapType::Roaring64 => {
+            roaring::RoaringTreemap::from_sorted_iter(bitmap.iter().map(|x| x + shift))?.serialize_into(&mut payload)?;
+        },
+    }
+
+    let query_ids = flags.query_ids.as_mut().ok_or(crate::errors::MissingBlockFieldsErr{})?;
+    query_ids.iter_mut().try_for_each(|id| {
+        *id = id.checked_add(offset).ok_or(crate::errors::QueryIndexOutOfRangeErr{})?;
+        Ok::<(), crate::errors::QueryIndexOutOfRangeErr>(())
+    })?;
+
+    let (payload, storage_flags) = compress_payload(payload)?;
+    let metadata_compression = if dictionary.is_some() { MetadataCompression::ZstdDictionary } else { MetadataCompression::default() };
+    let mut header = BlockHeader{
+        metadata_compression: metadata_compression.to_u8(),
+        bitmap_type: file_bitmap_type.to_u16(),
+        placeholder1: storage_flags | flags.storage_flags() | (block_header.placeholder1 & (BLOCK_HAS_STATS | BLOCK_QUERY_IDS_SORTED)),
+        placeholder2: 0,
+        ..block_header.clone()
+    };
+    if let Some(stats) = header.stats().filter(|_| table.is_some()) {
+        let max_target = bitmap.iter().map(|bit| (bit % n_targets as u64) as u32).max();
+        header.set_stats(&BlockStats{ max_target, ..stats });
+    }
+    let flags_bytes = encode_block_flags_with_header(&flags, &header, dictionary)?;
+    Ok((pack_block(&mut header, &flags_bytes, &payload), flags))
+}
+
+/// Scratch space for decompressing blocks.
+///
+/// Keeping one BlockBuffers across the blocks of a record lets the
+/// `unpack_block_*_with_buffers` functions reuse the memory of the previous
+/// block instead of allocating new buffers for each block. Use
+/// [BlockCache::read_block_into](dedup::BlockCache::read_block_into) to also
+/// reuse the buffer that the block is read into.
+#[derive(Debug, Default)]
+pub struct BlockBuffers {
+    pub(crate) flags: Vec<u8>,
+    pub(crate) payload: Vec<u8>,
+    pub(crate) flags_dictionary: Option<FlagsDictionary>,
+}
+
+impl BlockBuffers {
+    /// Decompress block flags written with [MetadataCompression::ZstdDictionary] using `dictionary`.
+    ///
+    /// Set this from the file flags of the record, see
+    /// [FlagsDictionary::from_flags].
+    pub fn set_flags_dictionary(
+        &mut self,
+        dictionary: Option<FlagsDictionary>,
+    ) {
+        self.flags_dictionary = dictionary;
+    }
+
+    /// Decode the block flags in `bytes` of the block with `block_header`.
+    pub(crate) fn decode_flags(
+        &mut self,
+        bytes: &[u8],
+        block_header: &BlockHeader,
+    ) -> Result<BlockFlags, E> {
+        decode_block_flags_with_dictionary(bytes, block_header, self.flags_dictionary.as_ref(), &mut self.flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn compress_payload_skips_incompressible() {
+        use super::compress_payload;
+        use super::decompress_payload;
+        use super::BLOCK_PAYLOAD_RAW;
+        use crate::headers::block::BlockHeader;
+
+        let bytes: Vec<u8> = vec![58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 2, 0, 16, 0, 0, 0, 0, 0, 3, 0, 7, 0];
+        let (got, flags) = compress_payload(bytes.clone()).unwrap();
+
+        assert_eq!(flags, BLOCK_PAYLOAD_RAW);
+        assert_eq!(got, bytes);
+
+        let header = BlockHeader{ num_records: 0, metadata_compression: 0, bitmap_type: 0, placeholder1: flags, block_len: 0, flags_len: 0, fields_present: 0, placeholder2: 0, placeholder3: 0 };
+        assert_eq!(decompress_payload(&got, &header).unwrap(), bytes);
+    }
+
+    #[test]
+    fn compress_payload_compresses_repetitive() {
+        use super::compress_payload;
+        use super::decompress_payload;
+        use crate::headers::block::BlockHeader;
+
+        let bytes: Vec<u8> = vec![1; 4096];
+        let (got, flags) = compress_payload(bytes.clone()).unwrap();
+
+        assert_eq!(flags, 0);
+        assert!(got.len() < bytes.len());
+
+        let header = BlockHeader{ num_records: 0, metadata_compression: 0, bitmap_type: 0, placeholder1: flags, block_len: 0, flags_len: 0, fields_present: 0, placeholder2: 0, placeholder3: 0 };
+        assert_eq!(decompress_payload(&got, &header).unwrap(), bytes);
+    }
+
+    #[test]
+    fn compress_payload_with_level_checks_level() {
+        use super::compress_payload_with_level;
+        use super::decompress_payload;
+        use crate::headers::block::BlockHeader;
+
+        let bytes: Vec<u8> = (0..4096).map(|x: u32| (x % 7 + x % 13) as u8).collect();
+
+        let (fast, fast_flags) = compress_payload_with_level(bytes.clone(), 1).unwrap();
+        let (best, best_flags) = compress_payload_with_level(bytes.clone(), 9).unwrap();
+        assert!(best.len() <= fast.len());
+
+        let header = BlockHeader{ num_records: 0, metadata_compression: 0, bitmap_type: 0, placeholder1: fast_flags, block_len: 0, flags_len: 0, fields_present: 0, placeholder2: 0, placeholder3: 0 };
+        assert_eq!(decompress_payload(&fast, &header).unwrap(), bytes);
+        let header = BlockHeader{ placeholder1: best_flags, ..header };
+        assert_eq!(decompress_payload(&best, &header).unwrap(), bytes);
+
+        assert!(compress_payload_with_level(bytes.clone(), 0).is_err());
+        assert!(compress_payload_with_level(bytes, 10).is_err());
+    }
+
+    #[test]
+    fn bitmap_type_auto_for_unknown_queries() {
+        use super::BitmapType;
+        use super::MIN_BLOCK_QUERIES;
+        use crate::headers::file::default_block_size;
+
+        // Most targets that still leave room for MIN_BLOCK_QUERIES queries in a Roaring32 block
+        let n_targets = (u32::MAX / (MIN_BLOCK_QUERIES + 1)) as usize;
+        assert_eq!(default_block_size(&BitmapType::Roaring32, n_targets), MIN_BLOCK_QUERIES);
+        assert_eq!(BitmapType::auto(n_targets, 0), BitmapType::Roaring32);
+        assert_eq!(BitmapType::auto(n_targets + 1, 0), BitmapType::Roaring64);
+        assert_eq!(BitmapType::auto(n_targets + 1, 10), BitmapType::Roaring32);
+        assert_eq!(BitmapType::auto(n_targets, 100_000), BitmapType::Roaring64);
+    }
+
+    #[test]
+    fn bitmap_type_for_size_boundary() {
+        use super::BitmapType;
+
+        // Stand-ins for large indexes, the boundary is the same for any number of targets
+        for n_targets in [1_usize, 3, 64, 1000, 65537, 1 << 20, u32::MAX as usize] {
+            let n_queries = (u32::MAX as usize - 1) / n_targets;
+            assert_eq!(BitmapType::for_size(n_targets, n_queries), BitmapType::Roaring32);
+            assert_eq!(BitmapType::for_size(n_targets, n_queries + 1), BitmapType::Roaring64);
+        }
+        assert_eq!(BitmapType::for_size(0, usize::MAX), BitmapType::Roaring32);
+
+        for bitmap_type in [BitmapType::Roaring32, BitmapType::Roaring64] {
+            assert_eq!(bitmap_type.to_string().parse::<BitmapType>().unwrap(), bitmap_type);
+        }
+        assert!("roaring16".parse::<BitmapType>().is_err());
+    }
+
+    #[test]
+    fn convert_to_roaring32_overflow_fails() {
+        use super::roaring32::convert_to_roaring32;
+        use super::roaring64::convert_to_roaring64;
+        use super::MetadataCompression;
+        use crate::headers::file::FileHeader;
+        use crate::headers::file::build_file_header_and_flags;
+        use crate::PseudoAln;
+
+        // Query id 4096 with 2^20 targets is past the 32-bit address space
+        let (header, _) = build_file_header_and_flags(&[b"chr.fasta".to_vec()], 0, b"sample", &MetadataCompression::default()).unwrap();
+        let header = FileHeader{ n_targets: 1 << 20, ..header };
+        let records = vec![PseudoAln{ ones_names: None, query_id: Some(4096), ones: Some(vec![1]), query_name: None }];
+
+        assert!(convert_to_roaring32(&header, records.clone()).is_err());
+        let got = convert_to_roaring64(&header, records).unwrap();
+        assert_eq!(got.iter().collect::<Vec<u64>>(), vec![(1 << 32) + 1]);
+    }
+
+    #[test]
+    fn pack_records_records_block_stats() {
+        use super::pack_records;
+        use super::BitmapType;
+        use super::MetadataCompression;
+        use crate::headers::block::BlockStats;
+        use crate::headers::block::decode_block_header;
+        use crate::headers::file::FileHeader;
+        use crate::headers::file::build_file_header_and_flags;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+        let (header, _) = build_file_header_and_flags(&targets, 3, b"sample", &MetadataCompression::default()).unwrap();
+        let records = vec![
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![1]), query_name: None },
+        ];
+
+        for bitmap_type in [BitmapType::Roaring32, BitmapType::Roaring64] {
+            let header = FileHeader{ bitmap_type: bitmap_type.to_u16(), ..header.clone() };
+            let block = pack_records(&header, records.clone()).unwrap();
+            let got = decode_block_header(&block[0..32]).unwrap().stats().unwrap();
+            assert_eq!(got, BlockStats{ n_ones: 3, max_target: Some(1) });
+            assert_eq!(got.density(3, 3), 3.0 / 9.0);
+
+            let empty = records.iter().map(|x| PseudoAln{ ones: Some(vec![]), ..x.clone() }).collect();
+            let block = pack_records(&header, empty).unwrap();
+            let got = decode_block_header(&block[0..32]).unwrap().stats().unwrap();
+            assert_eq!(got, BlockStats{ n_ones: 0, max_target: None });
+        }
+    }
+
+    #[test]
+    fn core_codec_reads_roaring_and_gzip() {
+        use super::gzwrapper::deflate_bytes;
+        use super::gzwrapper::inflate_bytes;
+        use ahda_core::gzip::deflate;
+        use ahda_core::gzip::inflate;
+        use ahda_core::roaring::deserialize_roaring32;
+        use ahda_core::roaring::deserialize_roaring64;
+        use ahda_core::roaring::serialize_roaring32;
+        use ahda_core::roaring::serialize_roaring64;
+        use roaring::RoaringBitmap;
+        use roaring::RoaringTreemap;
+
+        let bits: Vec<u32> = [1, 2, 3, 70000].into_iter().chain(100000..106000).collect();
+        let mut bitmap: RoaringBitmap = bits.iter().copied().collect();
+        let mut expected: Vec<u8> = Vec::new();
+        bitmap.serialize_into(&mut expected).unwrap();
+        assert_eq!(serialize_roaring32(&bits).unwrap(), expected);
+
+        // Run containers are only written by the roaring crate
+        bitmap.optimize();
+        let mut bytes: Vec<u8> = Vec::new();
+        bitmap.serialize_into(&mut bytes).unwrap();
+        assert_eq!(deserialize_roaring32(&bytes).unwrap(), bits);
+
+        let bits: Vec<u64> = vec![5, 1 << 33, (1 << 33) + 1, u64::MAX];
+        let treemap: RoaringTreemap = bits.iter().copied().collect();
+        let mut bytes: Vec<u8> = Vec::new();
+        treemap.serialize_into(&mut bytes).unwrap();
+        assert_eq!(serialize_roaring64(&bits).unwrap(), bytes);
+        assert_eq!(deserialize_roaring64(&bytes).unwrap(), bits);
+
+        assert_eq!(inflate(&deflate_bytes(&bytes).unwrap()).unwrap(), bytes);
+        assert_eq!(inflate_bytes(&deflate(&bytes, 6)).unwrap(), bytes);
+    }
+}
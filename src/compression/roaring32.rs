@@ -17,17 +17,25 @@
 use crate::PseudoAln;
 use crate::headers::block::BlockFlags;
 use crate::headers::block::BlockHeader;
+use crate::headers::block::BlockStats;
 use crate::headers::file::FileHeader;
-use crate::headers::block::encode_block_header;
-use crate::headers::block::encode_block_flags;
-use crate::headers::block::decode_block_flags;
+use crate::headers::block::encode_block_flags_with_header;
 
+use crate::compression::compress_payload_with_level;
+use crate::compression::decompress_payload_with_buffer;
+use crate::compression::BlockBuffers;
+use crate::compression::flags_dictionary::FlagsDictionary;
+use crate::compression::gzwrapper::DEFAULT_LEVEL;
 use crate::compression::gzwrapper::deflate_bytes;
 use crate::compression::gzwrapper::inflate_bytes;
 
 use super::BitmapType;
 use super::MetadataCompression;
 
+use ahda_core::block::BLOCK_FLAGS_RAW;
+use ahda_core::block::BLOCK_PAYLOAD_RAW;
+use ahda_core::block::pack_block;
+
 use roaring::bitmap::RoaringBitmap;
 
 type E = Box<dyn std::error::Error>;
@@ -41,15 +49,23 @@ pub fn convert_to_roaring32(
     let mut bits: RoaringBitmap = RoaringBitmap::new();
 
     for record in records.iter() {
-        if record.ones.is_none() || record.query_id.is_none() {
+        if record.query_id.is_none() {
             return Err(Box::new(crate::errors::EncodeError{}))
         }
+        // Unknown targets are not stored as unaligned
+        if record.ones.is_none() {
+            return Err(Box::new(crate::errors::MissingHitsErr{ query_id: record.query_id.unwrap() }))
+        }
         let ones = record.ones.as_ref().unwrap();
+        if ones.iter().any(|bit_idx| *bit_idx as usize >= n_targets) {
+            return Err(Box::new(crate::errors::TargetIndexOutOfRangeErr{}))
+        }
         let idx = *record.query_id.as_ref().unwrap();
-        ones.iter().for_each(|bit_idx| {
-            let index = idx *n_targets as u32 + *bit_idx;
+        ones.iter().try_for_each(|bit_idx| {
+            let index = idx.checked_mul(n_targets as u32).and_then(|x| x.checked_add(*bit_idx)).ok_or(crate::errors::Roaring32OverflowErr{})?;
             bits.insert(index);
-        });
+            Ok::<(), crate::errors::Roaring32OverflowErr>(())
+        })?;
     }
 
     bits.optimize();
@@ -73,44 +89,125 @@ pub fn deserialize_roaring32(
     Ok(bitmap)
 }
 
+/// See [blocks](crate::blocks) for the stable interface.
 pub fn pack_block_roaring32(
     queries: &[Vec<u8>],
     query_ids: &[u32],
     bitmap: RoaringBitmap,
 ) -> Result<Vec<u8>, E> {
-    let mut serialized = serialize_roaring32(bitmap)?;
+    pack_block_roaring32_with_level(queries, query_ids, bitmap, DEFAULT_LEVEL)
+}
+
+/// Same as [pack_block_roaring32] but gzips the bitmap with compression `level` (1-9).
+pub fn pack_block_roaring32_with_level(
+    queries: &[Vec<u8>],
+    query_ids: &[u32],
+    bitmap: RoaringBitmap,
+    level: u32,
+) -> Result<Vec<u8>, E> {
+    let flags: BlockFlags = BlockFlags{ queries: Some(queries.to_vec()), query_ids: Some(query_ids.to_vec()), ..Default::default() };
+    pack_block_roaring32_with_flags(&flags, bitmap, level)
+}
+
+/// Same as [pack_block_roaring32_with_level] but stores `flags` as the [BlockFlags].
+pub fn pack_block_roaring32_with_flags(
+    flags: &BlockFlags,
+    bitmap: RoaringBitmap,
+    level: u32,
+) -> Result<Vec<u8>, E> {
+    pack_block_roaring32_with_stats(flags, bitmap, level, None)
+}
+
+/// Same as [pack_block_roaring32_with_flags] but records `stats` in the [BlockHeader].
+pub fn pack_block_roaring32_with_stats(
+    flags: &BlockFlags,
+    bitmap: RoaringBitmap,
+    level: u32,
+    stats: Option<&BlockStats>,
+) -> Result<Vec<u8>, E> {
+    pack_block_roaring32_with_dictionary(flags, bitmap, level, stats, None)
+}
+
+/// Same as [pack_block_roaring32_with_stats] but compresses `flags` with `dictionary`, see [FlagsDictionary].
+pub fn pack_block_roaring32_with_dictionary(
+    flags: &BlockFlags,
+    bitmap: RoaringBitmap,
+    level: u32,
+    stats: Option<&BlockStats>,
+    dictionary: Option<&FlagsDictionary>,
+) -> Result<Vec<u8>, E> {
+    let mut bytes: Vec<u8> = Vec::new();
+    bitmap.serialize_into(&mut bytes)?;
+    let (serialized, storage_flags) = compress_payload_with_level(bytes, level)?;
+    let metadata_compression = if dictionary.is_some() { MetadataCompression::ZstdDictionary } else { MetadataCompression::default() };
+
+    let mut header = BlockHeader{
+        num_records: flags.queries.as_ref().map_or(0, |x| x.len()) as u32,
+        block_len: 0,
+        flags_len: 0,
+        bitmap_type: BitmapType::Roaring32.to_u16(),
+        metadata_compression: metadata_compression.to_u8(),
+        fields_present: flags.fields_present(),
+        placeholder1: storage_flags | flags.storage_flags(),
+        placeholder2: 0,
+        placeholder3: 0,
+    };
+    if let Some(stats) = stats {
+        header.set_stats(stats);
+    }
+    let block_flags = encode_block_flags_with_header(flags, &header, dictionary)?;
 
-    let flags: BlockFlags = BlockFlags{ queries: Some(queries.to_vec()), query_ids: Some(query_ids.to_vec()) };
-    let fields_present = flags.fields_present();
-    let mut block_flags: Vec<u8> = encode_block_flags(&flags)?;
+    Ok(pack_block(&mut header, &block_flags, &serialized))
+}
 
-    let flags_len = block_flags.len() as u64;
-    let block_len = serialized.len() as u32;
+/// Same as [pack_block_roaring32_with_stats] but stores `flags` and `bitmap` without compressing them.
+///
+/// Sets [BLOCK_FLAGS_RAW] and [BLOCK_PAYLOAD_RAW] in the [BlockHeader], see
+/// [pack_records_inline](crate::compression::pack_records_inline).
+pub fn pack_block_roaring32_inline(
+    flags: &BlockFlags,
+    bitmap: RoaringBitmap,
+    stats: Option<&BlockStats>,
+) -> Result<Vec<u8>, E> {
+    let mut bytes: Vec<u8> = Vec::new();
+    bitmap.serialize_into(&mut bytes)?;
 
-    let header = BlockHeader{
-        num_records: queries.len() as u32,
-        block_len,
-        flags_len,
+    let mut header = BlockHeader{
+        num_records: flags.queries.as_ref().map_or(0, |x| x.len()) as u32,
+        block_len: 0,
+        flags_len: 0,
         bitmap_type: BitmapType::Roaring32.to_u16(),
         metadata_compression: MetadataCompression::default().to_u8(),
-        fields_present,
-        placeholder1: 0,
+        fields_present: flags.fields_present(),
+        placeholder1: BLOCK_FLAGS_RAW | BLOCK_PAYLOAD_RAW | flags.storage_flags(),
         placeholder2: 0,
         placeholder3: 0,
     };
+    if let Some(stats) = stats {
+        header.set_stats(stats);
+    }
+    let block_flags = encode_block_flags_with_header(flags, &header, None)?;
 
-    let mut block: Vec<u8> = encode_block_header(&header)?;
-    block.append(&mut block_flags);
-    block.append(&mut serialized);
-
-    Ok(block)
+    Ok(pack_block(&mut header, &block_flags, &bytes))
 }
 
 pub fn unpack_block_roaring32(
     bytes: &[u8],
     block_header: &BlockHeader,
 ) -> Result<(RoaringBitmap, BlockFlags), E> {
-    let block_flags = decode_block_flags(&bytes[0..(block_header.flags_len as usize)])?;
-    let bitmap = deserialize_roaring32(&bytes[(block_header.flags_len as usize)..((block_header.flags_len + block_header.block_len as u64).try_into()?)])?;
+    unpack_block_roaring32_with_buffers(bytes, block_header, &mut BlockBuffers::default())
+}
+
+/// Same as [unpack_block_roaring32] but decompresses into `buffers`.
+pub fn unpack_block_roaring32_with_buffers(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+    buffers: &mut BlockBuffers,
+) -> Result<(RoaringBitmap, BlockFlags), E> {
+    let flags_len: usize = block_header.flags_len.try_into()?;
+    let block_end = flags_len.checked_add(block_header.block_len as usize).ok_or(crate::errors::TruncatedBlockErr{})?;
+    let block_flags = buffers.decode_flags(bytes.get(0..flags_len).ok_or(crate::errors::TruncatedBlockErr{})?, block_header)?;
+    let payload = decompress_payload_with_buffer(bytes.get(flags_len..block_end).ok_or(crate::errors::TruncatedBlockErr{})?, block_header, &mut buffers.payload)?;
+    let bitmap = RoaringBitmap::deserialize_from(payload)?;
     Ok((bitmap, block_flags))
 }
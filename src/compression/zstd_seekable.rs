@@ -0,0 +1,442 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Zstd-seekable container for a whole .ahda record.
+//!
+//! Compressing the blocks one by one with gzip can't use the redundancy
+//! between blocks. The container instead compresses everything after the
+//! [FileHeader](crate::headers::file::FileHeader) with zstd, one frame per block, so that the whole record
+//! compresses better and single blocks can still be read without
+//! decompressing the ones before them. A container has this layout:
+//!
+//! - The [FileHeader](crate::headers::file::FileHeader), uncompressed, with
+//!   [FILE_IS_ZSTD_SEEKABLE](crate::compression::FILE_IS_ZSTD_SEEKABLE) set in
+//!   `fields_present`.
+//! - The [FileFlags](crate::headers::file::FileFlags) in one zstd frame.
+//! - Each block, with its header, flags, and contents, in one zstd frame. The
+//!   block payloads are stored without gzip, see
+//!   [BLOCK_PAYLOAD_RAW](crate::compression::BLOCK_PAYLOAD_RAW).
+//! - A seek table in a skippable frame, in the [zstd seekable format](https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md).
+//!   The offsets in the seek table start after the file header.
+//!
+//! [Decoder](crate::decoder::Decoder) reads containers like any other .ahda
+//! record. The other functions that read the blocks directly error with
+//! [ZstdContainerErr](crate::errors::ZstdContainerErr).
+//!
+//! Requires the `zstd` feature, which needs a C compiler.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::compression::zstd_seekable::SeekableWriter;
+//! use ahda::compression::zstd_seekable::SeekTable;
+//! use ahda::decoder::Decoder;
+//! use ahda::encoder::Encoder;
+//! use ahda::PseudoAln;
+//! use std::io::{Cursor, Write};
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let data: Vec<PseudoAln> = (0..6).map(|id| {
+//!     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+//! }).collect();
+//!
+//! // Encode in blocks of 2 queries into a container
+//! let mut iter = data.clone().into_iter();
+//! let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 6);
+//! encoder.set_block_size(2).unwrap();
+//! let mut container = SeekableWriter::new(Cursor::new(Vec::new()), 3).unwrap();
+//! container.write_all(&encoder.encode_file_header_and_flags().unwrap()).unwrap();
+//! for block in encoder.by_ref() {
+//!     container.write_all(&block.unwrap()).unwrap();
+//! }
+//! let mut bytes = container.finish().unwrap();
+//!
+//! // Frames for the flags and the 3 blocks
+//! let table = SeekTable::read(&mut bytes).unwrap();
+//! assert_eq!(table.n_frames(), 4);
+//!
+//! bytes.set_position(0);
+//! let decoded: Vec<Vec<u32>> = Decoder::new(&mut bytes).map(|x| x.ones.unwrap()).collect();
+//! let expected: Vec<Vec<u32>> = data.into_iter().map(|x| x.ones.unwrap()).collect();
+//! assert_eq!(decoded, expected);
+//! ```
+//!
+
+use crate::compression::BLOCK_PAYLOAD_RAW;
+use crate::compression::FILE_IS_ZSTD_SEEKABLE;
+use crate::compression::dedup::BLOCK_IS_REFERENCE;
+use crate::compression::gzwrapper::inflate_bytes;
+use crate::headers::block::decode_block_header;
+use crate::headers::block::encode_block_header;
+use crate::headers::file::decode_file_header;
+use crate::headers::file::encode_file_header;
+
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+type E = Box<dyn std::error::Error>;
+
+/// Magic number of the skippable frame that holds the seek table.
+const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+/// Magic number at the end of the seek table.
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+/// Bytes in the seek table footer.
+const FOOTER_LEN: u64 = 9;
+
+/// Part of the .ahda stream that [SeekableWriter] is waiting for.
+#[derive(Debug, Clone, PartialEq)]
+enum Stage {
+    FileHeader,
+    FileFlags(usize),
+    BlockHeader,
+    Block(usize),
+}
+
+/// Writes an .ahda stream in a zstd-seekable container.
+///
+/// Takes the bytes of a plain .ahda record, as written by eg.
+/// [encode_to_write](crate::encode_to_write), and splits them into frames at
+/// the block boundaries. Call [finish](SeekableWriter::finish) after writing
+/// the last block to add the seek table.
+///
+pub struct SeekableWriter<W: Write> {
+    conn: W,
+    level: i32,
+    stage: Stage,
+    pending: Vec<u8>,
+    // Compressed and decompressed size of each frame
+    frames: Vec<(u32, u32)>,
+}
+
+impl<W: Write> SeekableWriter<W> {
+    /// Write the container to `conn` using zstd compression `level`.
+    ///
+    /// Errors if `level` is not a valid zstd compression level.
+    pub fn new(
+        conn: W,
+        level: i32,
+    ) -> Result<Self, E> {
+        let range = zstd::compression_level_range();
+        if !range.contains(&level) {
+            return Err(Box::new(crate::errors::InvalidZstdLevelErr{ min: *range.start(), max: *range.end() }))
+        }
+        Ok(SeekableWriter{ conn, level, stage: Stage::FileHeader, pending: Vec::new(), frames: Vec::new() })
+    }
+
+    /// Compress `bytes` into one frame.
+    fn write_frame(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<(), E> {
+        let compressed = zstd::bulk::compress(bytes, self.level)?;
+        self.conn.write_all(&compressed)?;
+        self.frames.push((u32::try_from(compressed.len())?, u32::try_from(bytes.len())?));
+        Ok(())
+    }
+
+    /// Write the parts of the stream that are complete in `self.pending`.
+    fn process(
+        &mut self,
+    ) -> Result<(), E> {
+        loop {
+            match self.stage {
+                Stage::FileHeader if self.pending.len() >= 32 => {
+                    let mut header = decode_file_header(&self.pending[0..32])?;
+                    header.fields_present |= FILE_IS_ZSTD_SEEKABLE;
+                    self.conn.write_all(&encode_file_header(&header)?)?;
+                    self.pending.drain(0..32);
+                    self.stage = Stage::FileFlags(usize::try_from(header.flags_len)?);
+                },
+                Stage::FileFlags(len) if self.pending.len() >= len => {
+                    let flags: Vec<u8> = self.pending.drain(0..len).collect();
+                    self.write_frame(&flags)?;
+                    self.stage = Stage::BlockHeader;
+                },
+                Stage::BlockHeader if self.pending.len() >= 32 => {
+                    let header = decode_block_header(&self.pending[0..32])?;
+                    self.stage = Stage::Block(32 + usize::try_from(header.flags_len)? + header.block_len as usize);
+                },
+                Stage::Block(len) if self.pending.len() >= len => {
+                    let block: Vec<u8> = self.pending.drain(0..len).collect();
+                    let block = store_payload_raw(block)?;
+                    self.write_frame(&block)?;
+                    self.stage = Stage::BlockHeader;
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Write the seek table and return the inner writer.
+    ///
+    /// Errors with [TruncatedBlockErr](crate::errors::TruncatedBlockErr) if
+    /// the stream ended in the middle of the file header, flags, or a block.
+    pub fn finish(
+        mut self,
+    ) -> Result<W, E> {
+        if self.stage != Stage::BlockHeader || !self.pending.is_empty() {
+            return Err(Box::new(crate::errors::TruncatedBlockErr{}))
+        }
+
+        let mut table: Vec<u8> = Vec::with_capacity(8 + self.frames.len() * 8 + FOOTER_LEN as usize);
+        table.extend(SKIPPABLE_MAGIC.to_le_bytes());
+        table.extend(u32::try_from(self.frames.len() * 8 + FOOTER_LEN as usize)?.to_le_bytes());
+        for (compressed, decompressed) in self.frames.iter() {
+            table.extend(compressed.to_le_bytes());
+            table.extend(decompressed.to_le_bytes());
+        }
+        table.extend(u32::try_from(self.frames.len())?.to_le_bytes());
+        // Seek table descriptor, no checksums
+        table.push(0);
+        table.extend(SEEKABLE_MAGIC.to_le_bytes());
+
+        self.conn.write_all(&table)?;
+        self.conn.flush()?;
+        Ok(self.conn)
+    }
+}
+
+impl<W: Write> Write for SeekableWriter<W> {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.process().map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(
+        &mut self,
+    ) -> std::io::Result<()> {
+        self.conn.flush()
+    }
+}
+
+/// Replace a gzipped block payload with the raw bytes.
+fn store_payload_raw(
+    mut block: Vec<u8>,
+) -> Result<Vec<u8>, E> {
+    let mut header = decode_block_header(&block[0..32])?;
+    if header.placeholder1 & (BLOCK_PAYLOAD_RAW | BLOCK_IS_REFERENCE) != 0 || header.block_len == 0 {
+        return Ok(block)
+    }
+
+    let payload_start = 32 + header.flags_len as usize;
+    let raw = inflate_bytes(&block[payload_start..])?;
+    header.placeholder1 |= BLOCK_PAYLOAD_RAW;
+    header.block_len = u32::try_from(raw.len())?;
+
+    block.truncate(payload_start);
+    block[0..32].copy_from_slice(&encode_block_header(&header)?);
+    block.extend(raw);
+    Ok(block)
+}
+
+/// Sizes of the frames in a container, read from its seek table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SeekTable {
+    /// Compressed and decompressed size of each frame.
+    pub frames: Vec<(u32, u32)>,
+}
+
+impl SeekTable {
+    /// Read the seek table from the end of a container.
+    ///
+    /// Leaves `conn` at an unspecified position.
+    ///
+    /// Errors with [CorruptedInputErr](crate::errors::CorruptedInputErr) if
+    /// `conn` does not end in a seek table.
+    pub fn read<R: Read + Seek>(
+        conn: &mut R,
+    ) -> Result<Self, E> {
+        let end = conn.seek(SeekFrom::End(0))?;
+        if end < 8 + FOOTER_LEN {
+            return Err(Box::new(crate::errors::CorruptedInputErr{}))
+        }
+        conn.seek(SeekFrom::Start(end - FOOTER_LEN))?;
+        let mut footer: [u8; FOOTER_LEN as usize] = [0; FOOTER_LEN as usize];
+        conn.read_exact(&mut footer)?;
+        let n_frames = u32::from_le_bytes(footer[0..4].try_into()?) as u64;
+        let checksums = footer[4] & 0x80 != 0;
+        if u32::from_le_bytes(footer[5..9].try_into()?) != SEEKABLE_MAGIC {
+            return Err(Box::new(crate::errors::CorruptedInputErr{}))
+        }
+
+        let entry_len: u64 = if checksums { 12 } else { 8 };
+        let table_len = n_frames.checked_mul(entry_len).and_then(|x| x.checked_add(8 + FOOTER_LEN)).filter(|x| *x <= end).ok_or(crate::errors::CorruptedInputErr{})?;
+        conn.seek(SeekFrom::Start(end - table_len))?;
+        let mut table: Vec<u8> = Vec::new();
+        conn.take(table_len - FOOTER_LEN).read_to_end(&mut table)?;
+        if u32::from_le_bytes(table[0..4].try_into()?) != SKIPPABLE_MAGIC {
+            return Err(Box::new(crate::errors::CorruptedInputErr{}))
+        }
+
+        let frames = table[8..].chunks_exact(entry_len as usize).map(|entry| {
+            (u32::from_le_bytes(entry[0..4].try_into().unwrap()), u32::from_le_bytes(entry[4..8].try_into().unwrap()))
+        }).collect();
+        Ok(SeekTable{ frames })
+    }
+
+    /// Number of frames, one for the file flags and one for each block.
+    pub fn n_frames(
+        &self,
+    ) -> usize {
+        self.frames.len()
+    }
+
+    /// Offset of frame `idx` from the start of the container.
+    pub fn frame_offset(
+        &self,
+        idx: usize,
+    ) -> u64 {
+        32 + self.frames.iter().take(idx).map(|x| x.0 as u64).sum::<u64>()
+    }
+
+    /// Read the bytes of block `block_idx`, with its header and flags.
+    ///
+    /// Blocks that are [references](crate::compression::dedup) to an earlier
+    /// block don't contain the bitmap, decode the container from the start to
+    /// resolve them.
+    ///
+    /// Errors with [UnknownBlockErr](crate::errors::UnknownBlockErr) if there
+    /// is no block `block_idx`.
+    pub fn read_block<R: Read + Seek>(
+        &self,
+        conn: &mut R,
+        block_idx: usize,
+    ) -> Result<Vec<u8>, E> {
+        let frame_idx = block_idx + 1;
+        let (compressed, decompressed) = *self.frames.get(frame_idx).ok_or(crate::errors::UnknownBlockErr{})?;
+        conn.seek(SeekFrom::Start(self.frame_offset(frame_idx)))?;
+        let mut frame: Vec<u8> = Vec::new();
+        conn.take(compressed as u64).read_to_end(&mut frame)?;
+        Ok(zstd::bulk::decompress(&frame, decompressed as usize)?)
+    }
+}
+
+/// Reader for the decompressed stream after the [FileHeader](crate::headers::file::FileHeader) of a container.
+pub(crate) type ContainerReader<R> = zstd::stream::read::Decoder<'static, std::io::BufReader<R>>;
+
+/// Decompress the frames that follow the [FileHeader](crate::headers::file::FileHeader) in `conn`.
+pub(crate) fn container_reader<R: Read>(
+    conn: R,
+) -> Result<ContainerReader<R>, E> {
+    Ok(zstd::stream::read::Decoder::new(conn)?)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn read_block_from_container() {
+        use super::SeekableWriter;
+        use super::SeekTable;
+        use crate::compression::BLOCK_PAYLOAD_RAW;
+        use crate::compression::roaring32::unpack_block_roaring32;
+        use crate::encoder::Encoder;
+        use crate::headers::block::decode_block_header;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+        use std::io::Write;
+
+        let targets: Vec<Vec<u8>> = (0..64).map(|x| format!("target_{}", x).into_bytes()).collect();
+        let data: Vec<PseudoAln> = (0..1000).map(|id: u32| {
+            PseudoAln{ ones_names: None, query_id: Some(id), ones: Some((0..64).filter(|x| (id * 7 + x) % 5 < 2).collect()), query_name: None }
+        }).collect();
+
+        let mut iter = data.clone().into_iter();
+        let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 1000);
+        encoder.set_block_size(100).unwrap();
+        let mut plain: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+        let mut blocks: Vec<Vec<u8>> = Vec::new();
+        for block in encoder.by_ref() {
+            blocks.push(block.unwrap());
+        }
+
+        // Write in pieces that don't line up with the blocks
+        blocks.iter().for_each(|x| plain.extend(x));
+        let mut container = SeekableWriter::new(Cursor::new(Vec::new()), 3).unwrap();
+        for chunk in plain.chunks(77) {
+            container.write_all(chunk).unwrap();
+        }
+        let mut bytes = container.finish().unwrap();
+
+        let table = SeekTable::read(&mut bytes).unwrap();
+        assert_eq!(table.n_frames(), 11);
+
+        let block = table.read_block(&mut bytes, 4).unwrap();
+        let header = decode_block_header(&block[0..32]).unwrap();
+        assert_ne!(header.placeholder1 & BLOCK_PAYLOAD_RAW, 0);
+
+        let expected_header = decode_block_header(&blocks[4][0..32]).unwrap();
+        let got = unpack_block_roaring32(&block[32..], &header).unwrap();
+        let expected = unpack_block_roaring32(&blocks[4][32..], &expected_header).unwrap();
+        assert_eq!(got, expected);
+
+        assert!(table.read_block(&mut bytes, 10).is_err());
+    }
+
+    #[test]
+    fn decode_container_written_by_encode_to_write() {
+        use crate::decoder::Decoder;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data: Vec<PseudoAln> = (0..10).map(|id: u32| {
+            PseudoAln{ ones_names: None, query_id: Some(id), ones: Some((0..2).filter(|x| (id + x) % 2 == 1).collect()), query_name: None }
+        }).collect();
+
+        let opts = EncodeOpts{ zstd_level: Some(3), ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+
+        let mut plain: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut plain, EncodeOpts::default()).unwrap();
+        assert_ne!(bytes, plain);
+
+        let mut conn = Cursor::new(&bytes);
+        let mut decoder = Decoder::new(&mut conn);
+        assert!(!decoder.file_header().is_zstd_seekable());
+        let got: Vec<Vec<u32>> = decoder.by_ref().map(|x| x.ones.unwrap()).collect();
+        let expected: Vec<Vec<u32>> = data.into_iter().map(|x| x.ones.unwrap()).collect();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn finish_truncated_stream_fails() {
+        use super::SeekableWriter;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        use std::io::Write;
+
+        let targets = vec![b"chr.fasta".to_vec()];
+        let data = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None }];
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+
+        let mut container = SeekableWriter::new(Vec::new(), 3).unwrap();
+        container.write_all(&bytes[0..bytes.len() - 1]).unwrap();
+        assert!(container.finish().is_err());
+    }
+}
@@ -0,0 +1,297 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Check that two .ahda files refer to the same queries.
+//!
+//! Set operations and comparisons between files match the records by query
+//! id, which is only meaningful if both files were encoded against the same
+//! query file. Runs of different tools, or of the same tool on a re-sorted
+//! or re-downloaded query file, may number the reads differently. [QuerySet]
+//! reads the query ids and names stored in a file, and [check_consistency]
+//! compares two of them and reports whether the files use the same ids, the
+//! same names in a different order, or different reads.
+//!
+//! Files that don't store the query names can only be compared by their
+//! number of queries. Queries that are not in a file, eg. reads that an
+//! aligner didn't report, are not mismatches since their name is unknown.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::consistency::check_consistency;
+//! use ahda::consistency::Consistency;
+//! use ahda::consistency::QuerySet;
+//! use ahda::encode_to_write;
+//! use ahda::EncodeOpts;
+//! use ahda::PseudoAln;
+//!
+//! let targets = vec![b"chr.fasta".to_vec()];
+//! let encode = |names: &[&[u8]]| {
+//!     let names: Vec<Vec<u8>> = names.iter().map(|x| x.to_vec()).collect();
+//!     let data: Vec<PseudoAln> = names.iter().enumerate().map(|(id, name)| {
+//!         PseudoAln{ ones_names: None, query_id: Some(id as u32), ones: Some(vec![0]), query_name: Some(name.clone()) }
+//!     }).collect();
+//!     let mut opts = EncodeOpts::default();
+//!     opts.encode_query_names = true;
+//!     let mut bytes: Vec<u8> = Vec::new();
+//!     encode_to_write(&targets, &names, &data, &mut bytes, opts).unwrap();
+//!     QuerySet::from_read(&mut bytes.as_slice()).unwrap()
+//! };
+//!
+//! let a = encode(&[b"r1", b"r2", b"r3"]);
+//! assert_eq!(check_consistency(&a, &encode(&[b"r1", b"r2", b"r3"])).consistency, Consistency::SameOrder);
+//! assert_eq!(check_consistency(&a, &encode(&[b"r3", b"r1", b"r2"])).consistency, Consistency::Reordered);
+//!
+//! let report = check_consistency(&a, &encode(&[b"r1", b"r2", b"r4"]));
+//! assert_eq!(report.consistency, Consistency::Inconsistent);
+//! assert_eq!(report.mismatches.len(), 1);
+//! ```
+//!
+
+use crate::headers::file::read_file_flags;
+use crate::headers::file::read_file_header;
+use crate::report::Value;
+
+use std::collections::HashMap;
+use std::io::Read;
+
+type E = Box<dyn std::error::Error>;
+
+/// Query ids and names stored in an .ahda file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuerySet {
+    /// Number of queries in the file header.
+    pub n_queries: u32,
+    /// Name of each query id that has a record, or None if the file doesn't
+    /// store the query names.
+    pub names: Option<Vec<Option<Vec<u8>>>>,
+}
+
+impl QuerySet {
+    /// Read the query ids and names of the .ahda record in `conn`.
+    ///
+    /// Only the block flags are decoded. Names of query ids past the number
+    /// of queries in the header extend the list.
+    ///
+    /// ## Errors and panics
+    /// Errors if the headers or the block flags can't be read.
+    ///
+    pub fn from_read<R: Read>(
+        conn: &mut R,
+    ) -> Result<Self, E> {
+        let header = read_file_header(conn)?;
+        let flags = read_file_flags(&header, conn)?;
+        let mut names: Option<Vec<Option<Vec<u8>>>> = header.promises_query_names().then(|| vec![None; header.n_queries as usize]);
+        crate::try_for_each_block_flags(conn, &flags, |block_flags| {
+            let (Some(names), Some(queries), Some(query_ids)) = (names.as_mut(), block_flags.queries, block_flags.query_ids) else {
+                return Ok(())
+            };
+            for (name, query_id) in queries.into_iter().zip(query_ids) {
+                if query_id as usize >= names.len() {
+                    names.resize(query_id as usize + 1, None);
+                }
+                names[query_id as usize] = Some(name);
+            }
+            Ok(())
+        })?;
+        Ok(QuerySet{ n_queries: header.n_queries, names })
+    }
+
+    /// Query ids of each stored name, and the names that have several ids.
+    fn ids_by_name(
+        &self,
+    ) -> (HashMap<&[u8], u32>, Vec<Vec<u8>>) {
+        let mut ids: HashMap<&[u8], u32> = HashMap::new();
+        let mut duplicated: Vec<Vec<u8>> = Vec::new();
+        let named = self.names.iter().flatten().enumerate().filter_map(|(id, name)| Some((id as u32, name.as_deref()?)));
+        for (id, name) in named {
+            if ids.insert(name, id).is_some() {
+                duplicated.push(name.to_vec());
+            }
+        }
+        (ids, duplicated)
+    }
+}
+
+/// How the queries of two files relate, see [check_consistency].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consistency {
+    /// Same number of queries and every query id has the same name in both files.
+    SameOrder,
+    /// Same names with different query ids, so the files can only be
+    /// combined by name, eg. with
+    /// [decode_from_read_into_roaring_by_name](crate::decode_from_read_into_roaring_by_name).
+    Reordered,
+    /// The files refer to different queries.
+    Inconsistent,
+}
+
+impl std::fmt::Display for Consistency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Consistency::SameOrder => write!(f, "same-order"),
+            Consistency::Reordered => write!(f, "reordered"),
+            Consistency::Inconsistent => write!(f, "inconsistent"),
+        }
+    }
+}
+
+/// A difference between the queries of two files.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryMismatch {
+    /// The files have a different number of queries.
+    NQueries{ a: u32, b: u32 },
+    /// The query id has a different name in each file and neither name is
+    /// in the other file.
+    Name{ query_id: u32, a: Vec<u8>, b: Vec<u8> },
+    /// The name has a different query id in each file.
+    Reordered{ name: Vec<u8>, a: u32, b: u32 },
+    /// The name has several query ids in file `a` (`in_a` is true) or `b`.
+    Duplicated{ name: Vec<u8>, in_a: bool },
+}
+
+impl QueryMismatch {
+    /// Kind of the mismatch and its values as a row of a [Report](crate::report::Report).
+    ///
+    /// Written as TSV, the row is the same as the [Display](std::fmt::Display) of the mismatch.
+    pub fn report_row(
+        &self,
+    ) -> (&'static str, Vec<Value>) {
+        match self {
+            QueryMismatch::NQueries{ a, b } => ("n_queries", vec![Value::number(a), Value::number(b)]),
+            QueryMismatch::Name{ query_id, a, b } => ("name", vec![Value::number(query_id), Value::text(String::from_utf8_lossy(a)), Value::text(String::from_utf8_lossy(b))]),
+            QueryMismatch::Reordered{ name, a, b } => ("reordered", vec![Value::text(String::from_utf8_lossy(name)), Value::number(a), Value::number(b)]),
+            QueryMismatch::Duplicated{ name, in_a } => ("duplicated", vec![Value::text(String::from_utf8_lossy(name)), Value::text(if *in_a { "a" } else { "b" })]),
+        }
+    }
+}
+
+impl std::fmt::Display for QueryMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            QueryMismatch::NQueries{ a, b } => write!(f, "n_queries\t{}\t{}", a, b),
+            QueryMismatch::Name{ query_id, a, b } => write!(f, "name\t{}\t{}\t{}", query_id, String::from_utf8_lossy(a), String::from_utf8_lossy(b)),
+            QueryMismatch::Reordered{ name, a, b } => write!(f, "reordered\t{}\t{}\t{}", String::from_utf8_lossy(name), a, b),
+            QueryMismatch::Duplicated{ name, in_a } => write!(f, "duplicated\t{}\t{}", String::from_utf8_lossy(name), if *in_a { "a" } else { "b" }),
+        }
+    }
+}
+
+/// Result of [check_consistency].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    /// How the queries relate.
+    pub consistency: Consistency,
+    /// True if both files store the query names, otherwise only the number
+    /// of queries was compared.
+    pub names_checked: bool,
+    /// Number of query names that are in both files.
+    pub n_shared: usize,
+    /// Differences between the files, [QueryMismatch::NQueries] and
+    /// [QueryMismatch::Duplicated] first and the others in order of query id
+    /// in file `a`.
+    pub mismatches: Vec<QueryMismatch>,
+}
+
+/// Check whether the files with queries `a` and `b` refer to the same queries.
+///
+/// The files are [Consistency::SameOrder] if they have the same number of
+/// queries and no query id has a different name in each file,
+/// [Consistency::Reordered] if the only differences are names with
+/// different ids and no name is duplicated, and otherwise
+/// [Consistency::Inconsistent].
+pub fn check_consistency(
+    a: &QuerySet,
+    b: &QuerySet,
+) -> ConsistencyReport {
+    let mut mismatches: Vec<QueryMismatch> = Vec::new();
+    if a.n_queries != b.n_queries {
+        mismatches.push(QueryMismatch::NQueries{ a: a.n_queries, b: b.n_queries });
+    }
+    let (Some(names_a), Some(names_b)) = (&a.names, &b.names) else {
+        let consistency = if mismatches.is_empty() { Consistency::SameOrder } else { Consistency::Inconsistent };
+        return ConsistencyReport{ consistency, names_checked: false, n_shared: 0, mismatches }
+    };
+
+    let (ids_a, duplicated_a) = a.ids_by_name();
+    let (ids_b, duplicated_b) = b.ids_by_name();
+    mismatches.extend(duplicated_a.into_iter().map(|name| QueryMismatch::Duplicated{ name, in_a: true }));
+    mismatches.extend(duplicated_b.into_iter().map(|name| QueryMismatch::Duplicated{ name, in_a: false }));
+
+    let mut n_shared: usize = 0;
+    let mut inconsistent = !mismatches.is_empty();
+    let mut reordered = false;
+    for (query_id, name) in names_a.iter().enumerate().filter_map(|(id, name)| Some((id as u32, name.as_ref()?))) {
+        if let Some(id_b) = ids_b.get(name.as_slice()) {
+            n_shared += 1;
+            if *id_b != query_id {
+                reordered = true;
+                mismatches.push(QueryMismatch::Reordered{ name: name.clone(), a: query_id, b: *id_b });
+            }
+        }
+        // A different read under the same id that neither file has elsewhere
+        let Some(Some(name_b)) = names_b.get(query_id as usize) else {
+            continue
+        };
+        if name_b != name && !ids_b.contains_key(name.as_slice()) && !ids_a.contains_key(name_b.as_slice()) {
+            inconsistent = true;
+            mismatches.push(QueryMismatch::Name{ query_id, a: name.clone(), b: name_b.clone() });
+        }
+    }
+
+    let consistency = match (inconsistent, reordered) {
+        (true, _) => Consistency::Inconsistent,
+        (false, true) => Consistency::Reordered,
+        (false, false) => Consistency::SameOrder,
+    };
+    ConsistencyReport{ consistency, names_checked: true, n_shared, mismatches }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn check_consistency_of_query_sets() {
+        use super::check_consistency;
+        use super::Consistency;
+        use super::QueryMismatch;
+        use super::QuerySet;
+
+        let set = |names: &[Option<&[u8]>]| QuerySet{ n_queries: names.len() as u32, names: Some(names.iter().map(|x| x.map(|name| name.to_vec())).collect()) };
+        let a = set(&[Some(b"r1"), Some(b"r2"), Some(b"r3"), None]);
+
+        // Unknown names are not mismatches
+        let got = check_consistency(&a, &set(&[Some(b"r1"), None, Some(b"r3"), Some(b"r4")]));
+        assert_eq!((got.consistency, got.n_shared), (Consistency::SameOrder, 2));
+
+        let got = check_consistency(&a, &set(&[Some(b"r2"), Some(b"r1"), Some(b"r3"), None]));
+        assert_eq!(got.consistency, Consistency::Reordered);
+        assert_eq!(got.mismatches, vec![QueryMismatch::Reordered{ name: b"r1".to_vec(), a: 0, b: 1 }, QueryMismatch::Reordered{ name: b"r2".to_vec(), a: 1, b: 0 }]);
+
+        let got = check_consistency(&a, &set(&[Some(b"r1"), Some(b"r1"), Some(b"r3"), None]));
+        assert_eq!(got.consistency, Consistency::Inconsistent);
+        assert_eq!(got.mismatches[0], QueryMismatch::Duplicated{ name: b"r1".to_vec(), in_a: false });
+
+        let got = check_consistency(&a, &set(&[Some(b"r1"), Some(b"r2")]));
+        assert_eq!(got.consistency, Consistency::Inconsistent);
+        assert_eq!(got.mismatches, vec![QueryMismatch::NQueries{ a: 4, b: 2 }]);
+        assert_eq!(got.mismatches[0].to_string(), "n_queries\t4\t2");
+
+        // Without names only the number of queries is compared
+        let got = check_consistency(&a, &QuerySet{ n_queries: 4, names: None });
+        assert_eq!((got.consistency, got.names_checked), (Consistency::SameOrder, false));
+    }
+}
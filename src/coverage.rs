@@ -0,0 +1,265 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Approximate breadth of coverage of the targets from SAM input.
+//!
+//! Pseudoalignments only record which targets a read hits, but SAM records
+//! also have the position of the alignment. [CoverageBuilder] divides each
+//! target into buckets of `bucket_size` bases and marks the buckets that an
+//! aligned read overlaps. The share of the target in the marked buckets
+//! estimates its breadth of coverage: with `bucket_size` 1 the estimate is
+//! exact, and larger buckets overestimate the breadth by at most one bucket
+//! at each end of every covered stretch.
+//!
+//! [Encoder::set_coverage](crate::encoder::Encoder::set_coverage) stores the
+//! [Coverage] in the file extensions under [COVERAGE_KEY], see
+//! [Coverage::from_flags]. Inputs without positions have no coverage.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::coverage::CoverageBuilder;
+//!
+//! // One 1000 bp target divided into 100 bp buckets
+//! let mut builder = CoverageBuilder::new(100, vec![1000]);
+//! builder.add(0, 0, 150);
+//! builder.add(0, 950, 50);
+//! let coverage = builder.finish();
+//! assert_eq!(coverage.covered[0], vec![0, 1, 9]);
+//! assert_eq!(coverage.covered_bases(0), 300);
+//! assert_eq!(coverage.breadth(0), Some(0.3));
+//! ```
+//!
+
+use crate::headers::file::FileFlags;
+
+use bincode::{Encode, Decode};
+use roaring::RoaringBitmap;
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the [Coverage] stored in the file extensions.
+pub const COVERAGE_KEY: &[u8] = b"ahda.coverage";
+
+/// Buckets of each target that aligned reads overlap.
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq, Eq)]
+pub struct Coverage {
+    /// Number of bases in each bucket.
+    pub bucket_size: u32,
+    /// Length of each target in the order of the target names, 0 if unknown.
+    pub lengths: Vec<u64>,
+    /// Indexes of the covered buckets of each target in increasing order.
+    pub covered: Vec<Vec<u32>>,
+}
+
+impl Coverage {
+    /// Number of bases in the covered buckets of `target`.
+    ///
+    /// The last bucket of a target with a known length only counts the bases
+    /// up to the end of the target.
+    ///
+    /// ## Errors and panics
+    /// Panics if `target` is out of bounds.
+    ///
+    pub fn covered_bases(
+        &self,
+        target: usize,
+    ) -> u64 {
+        let length = self.lengths[target];
+        self.covered[target].iter().map(|bucket| {
+            let start = *bucket as u64 * self.bucket_size as u64;
+            let end = start + self.bucket_size as u64;
+            if length > 0 { end.min(length).saturating_sub(start) } else { self.bucket_size as u64 }
+        }).sum()
+    }
+
+    /// Share of the bases of `target` in the covered buckets.
+    ///
+    /// Returns None if the length of `target` is not known.
+    ///
+    /// ## Errors and panics
+    /// Panics if `target` is out of bounds.
+    ///
+    pub fn breadth(
+        &self,
+        target: usize,
+    ) -> Option<f64> {
+        let length = self.lengths[target];
+        if length == 0 {
+            return None
+        }
+        Some(self.covered_bases(target) as f64 / length as f64)
+    }
+
+    /// Read the coverage stored in `flags`, if any.
+    ///
+    /// ## Errors and panics
+    /// Errors if the stored value can't be decoded.
+    ///
+    pub fn from_flags(
+        flags: &FileFlags,
+    ) -> Result<Option<Coverage>, E> {
+        flags.extensions.as_ref().and_then(|x| x.get_as::<Coverage>(COVERAGE_KEY)).transpose()
+    }
+}
+
+/// Collects the covered buckets of the targets one alignment at a time.
+#[derive(Clone, Debug)]
+pub struct CoverageBuilder {
+    bucket_size: u32,
+    lengths: Vec<u64>,
+    covered: Vec<RoaringBitmap>,
+}
+
+impl CoverageBuilder {
+    /// Divide targets with `lengths` into buckets of `bucket_size` bases.
+    ///
+    /// ## Errors and panics
+    /// Panics if `bucket_size` is 0.
+    ///
+    pub fn new(
+        bucket_size: u32,
+        lengths: Vec<u64>,
+    ) -> Self {
+        assert!(bucket_size > 0);
+        let covered = vec![RoaringBitmap::new(); lengths.len()];
+        CoverageBuilder{ bucket_size, lengths, covered }
+    }
+
+    /// Mark the buckets that `n_bases` from the 0-based position `start` of `target` overlap.
+    ///
+    /// Alignments to targets past the lengths given to
+    /// [new](CoverageBuilder::new) and alignments with no bases are ignored.
+    pub fn add(
+        &mut self,
+        target: usize,
+        start: u64,
+        n_bases: u64,
+    ) {
+        if n_bases == 0 {
+            return
+        }
+        if let Some(covered) = self.covered.get_mut(target) {
+            let first = start / self.bucket_size as u64;
+            let last = (start + n_bases - 1) / self.bucket_size as u64;
+            covered.insert_range((first.min(u32::MAX as u64) as u32)..=(last.min(u32::MAX as u64) as u32));
+        }
+    }
+
+    /// Get the [Coverage] of the alignments added so far.
+    pub fn finish(
+        &self,
+    ) -> Coverage {
+        let covered = self.covered.iter().map(|x| x.iter().collect()).collect();
+        Coverage{ bucket_size: self.bucket_size, lengths: self.lengths.clone(), covered }
+    }
+}
+
+/// Get the target, 0-based start, and number of reference bases of a SAM line.
+///
+/// The number of reference bases is the sum of the M, D, N, =, and X
+/// operations of the CIGAR string. Returns None for header lines, unmapped
+/// records, and records without a position or a CIGAR string.
+pub fn read_sam_span(
+    line: &[u8],
+) -> Option<(&[u8], u64, u64)> {
+    if line.first() == Some(&b'@') {
+        return None
+    }
+    let mut fields = line.split(|x| *x == b'\t');
+    let _query_name = fields.next()?;
+    let flag: u16 = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let target = fields.next()?;
+    let pos: u64 = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let _mapq = fields.next()?;
+    let cigar = fields.next()?;
+    if flag & 0x4 != 0 || target == b"*" || pos == 0 || cigar == b"*" {
+        return None
+    }
+
+    let mut n_bases: u64 = 0;
+    let mut len: u64 = 0;
+    for c in cigar {
+        match c {
+            b'0'..=b'9' => len = len * 10 + (c - b'0') as u64,
+            b'M' | b'D' | b'N' | b'=' | b'X' => { n_bases += len; len = 0 },
+            _ => len = 0,
+        }
+    }
+    Some((target, pos - 1, n_bases))
+}
+
+/// Get the target names and lengths from the @SQ lines of a SAM header.
+///
+/// Lines without a length get length 0.
+pub fn read_sam_target_lengths(
+    header: &[u8],
+) -> Vec<(Vec<u8>, u64)> {
+    header.split(|x| *x == b'\n').filter(|line| line.starts_with(b"@SQ\t")).filter_map(|line| {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let mut name = None;
+        let mut length = 0;
+        for field in line.split(|x| *x == b'\t').skip(1) {
+            if let Some(x) = field.strip_prefix(b"SN:") {
+                name = Some(x.to_vec());
+            } else if let Some(x) = field.strip_prefix(b"LN:") {
+                length = std::str::from_utf8(x).ok().and_then(|x| x.parse().ok()).unwrap_or(0);
+            }
+        }
+        name.map(|x| (x, length))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn read_sam_span_from_cigar() {
+        use super::read_sam_span;
+
+        let line = b"r1\t0\tchr\t101\t60\t5S10M2I3D4N5=1X\t*\t0\t0\tACGT\t*";
+        assert_eq!(read_sam_span(line), Some((&b"chr"[..], 100, 23)));
+
+        assert_eq!(read_sam_span(b"r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*"), None);
+        assert_eq!(read_sam_span(b"r1\t0\tchr\t101\t60\t*\t*\t0\t0\tACGT\t*"), None);
+        assert_eq!(read_sam_span(b"@SQ\tSN:chr\tLN:1000"), None);
+    }
+
+    #[test]
+    fn read_sam_target_lengths_from_header() {
+        use super::read_sam_target_lengths;
+
+        let header = b"@HD\tVN:1.6\n@SQ\tSN:chr\tLN:1000\n@SQ\tLN:20\tSN:plasmid\r\n@SQ\tSN:virus\n";
+        let expected = vec![(b"chr".to_vec(), 1000), (b"plasmid".to_vec(), 20), (b"virus".to_vec(), 0)];
+        assert_eq!(read_sam_target_lengths(header), expected);
+    }
+
+    #[test]
+    fn coverage_of_partial_last_bucket() {
+        use super::CoverageBuilder;
+
+        let mut builder = CoverageBuilder::new(100, vec![250, 0]);
+        builder.add(0, 199, 2);
+        builder.add(0, 240, 10);
+        builder.add(1, 500, 1);
+        builder.add(2, 0, 100);
+        let coverage = builder.finish();
+
+        assert_eq!(coverage.covered, vec![vec![1, 2], vec![5]]);
+        assert_eq!(coverage.covered_bases(0), 150);
+        assert_eq!(coverage.breadth(0), Some(0.6));
+        assert_eq!(coverage.covered_bases(1), 100);
+        assert_eq!(coverage.breadth(1), None);
+    }
+}
@@ -33,29 +33,63 @@
 //! If called via [encode_block64], the API will use a 64-bit address space.
 //! This can fit a `num_queries * num_targets < 2^64` input alignment.
 //!
+//! Use [decode_block_bitmap] and [decode_query_range] to decode parts of a
+//! large record without decoding all of it with [decode_bitmap].
+//!
+//! The functions above abort the program if the input can't be encoded or
+//! decoded. Each has a `try_` variant that instead returns a [Status] code and
+//! writes the error message to an out-parameter. The `try_` variants take the
+//! input bytes as a `rust::Slice` and append their output to a `std::vector`
+//! given by the caller, so large bitmaps are written directly into C++ memory
+//! instead of being copied out of a Rust vector.
+//!
+//! Aligners that produce the target ids of one read at a time can use a
+//! [StreamingBlockWriter] instead. It handles flattening the alignments, block
+//! boundaries, and the choice of address space internally. Its methods also
+//! have `try_` variants, and [try_new_streaming_block_writer] throws a
+//! `rust::Error` instead of aborting if the writer can't be created.
+//!
 //! ## Usage
 //! **TODO** Usage examples for the C++ API.
 //!
 
 use crate::decode_from_read_to_roaring;
+use crate::decoder::block_index::BlockIndex;
 use crate::headers::file::build_file_header_and_flags;
 use crate::headers::file::read_file_header_and_flags;
-use crate::headers::block::read_block_header_and_flags;
+use crate::headers::block::BlockFlags;
 use crate::encoder::bitmap_encoder::BitmapEncoder;
+use crate::encoder::streaming::StreamingEncoder;
 use crate::compression::MetadataCompression;
-use crate::compression::roaring32::pack_block_roaring32;
-use crate::compression::roaring64::pack_block_roaring64;
+use crate::blocks::pack_roaring32;
+use crate::blocks::pack_roaring64;
 
 use std::io::Cursor;
+use std::pin::Pin;
 
 use cxx::CxxString;
 use cxx::CxxVector;
 use roaring::RoaringBitmap;
 use roaring::RoaringTreemap;
 
+pub use ffi::Status;
+
+type E = Box<dyn std::error::Error>;
+
 #[cxx::bridge(namespace = "ahda")]
 mod ffi {
 
+    /// Return codes of the `try_` functions.
+    #[derive(Debug)]
+    enum Status {
+        /// The call succeeded.
+        Ok = 0,
+        /// The input is not valid .ahda data or can't be encoded.
+        InvalidInput = 1,
+        /// A block index is out of range for the record.
+        OutOfRange = 2,
+    }
+
     extern "Rust" {
         fn encode_file_header_and_flags(
             targets: &CxxVector<CxxString>,
@@ -86,6 +120,17 @@ mod ffi {
             bytes: &CxxVector<u8>,
         ) -> Vec<u64>;
 
+        fn decode_block_bitmap(
+            bytes: &CxxVector<u8>,
+            block_idx: u32,
+        ) -> Vec<u64>;
+
+        fn decode_query_range(
+            bytes: &CxxVector<u8>,
+            start_id: u32,
+            end_id: u32,
+        ) -> Vec<u64>;
+
         fn decode_target_names(
             bytes: &CxxVector<u8>,
         ) -> Vec<String>;
@@ -97,9 +142,127 @@ mod ffi {
         pub fn decode_query_names(
             bytes: &CxxVector<u8>,
         ) -> Vec<String>;
+
+        fn try_encode_file_header_and_flags(
+            targets: &CxxVector<CxxString>,
+            name: &CxxString,
+            n_queries: u32,
+            out: Pin<&mut CxxVector<u8>>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_encode_bitmap(
+            targets: &CxxVector<CxxString>,
+            queries: &CxxVector<CxxString>,
+            name: &CxxString,
+            set_bits: &[u64],
+            out: Pin<&mut CxxVector<u8>>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_decode_bitmap(
+            bytes: &[u8],
+            out: Pin<&mut CxxVector<u64>>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_decode_block_bitmap(
+            bytes: &[u8],
+            block_idx: u32,
+            out: Pin<&mut CxxVector<u64>>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_decode_query_range(
+            bytes: &[u8],
+            start_id: u32,
+            end_id: u32,
+            out: Pin<&mut CxxVector<u64>>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_decode_target_names(
+            bytes: &[u8],
+            out: &mut Vec<String>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_decode_query_ids(
+            bytes: &[u8],
+            out: Pin<&mut CxxVector<u32>>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_decode_query_names(
+            bytes: &[u8],
+            out: &mut Vec<String>,
+            error: &mut String,
+        ) -> Status;
+
+        type StreamingBlockWriter;
+
+        fn new_streaming_block_writer(
+            targets: &CxxVector<CxxString>,
+            name: &CxxString,
+            n_queries: u32,
+        ) -> Box<StreamingBlockWriter>;
+
+        fn encode_header(
+            self: &mut StreamingBlockWriter,
+        ) -> Vec<u8>;
+
+        fn add_read(
+            self: &mut StreamingBlockWriter,
+            query_name: &CxxString,
+            query_id: u32,
+            target_ids: &CxxVector<u32>,
+        ) -> Vec<u8>;
+
+        fn finish(
+            self: &mut StreamingBlockWriter,
+        ) -> Vec<u8>;
+
+        fn try_new_streaming_block_writer(
+            targets: &CxxVector<CxxString>,
+            name: &CxxString,
+            n_queries: u32,
+        ) -> Result<Box<StreamingBlockWriter>>;
+
+        fn try_encode_header(
+            self: &mut StreamingBlockWriter,
+            out: Pin<&mut CxxVector<u8>>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_add_read(
+            self: &mut StreamingBlockWriter,
+            query_name: &CxxString,
+            query_id: u32,
+            target_ids: &CxxVector<u32>,
+            out: Pin<&mut CxxVector<u8>>,
+            error: &mut String,
+        ) -> Status;
+
+        fn try_finish(
+            self: &mut StreamingBlockWriter,
+            out: Pin<&mut CxxVector<u8>>,
+            error: &mut String,
+        ) -> Status;
 }
 }
 
+fn encode_file_header_and_flags_impl(
+    targets: &CxxVector<CxxString>,
+    name: &CxxString,
+    n_queries: u32,
+) -> Result<Vec<u8>, E> {
+    let target_names: Vec<Vec<u8>> = targets.iter().map(|x| x.as_bytes().to_vec()).collect();
+    let query_name: Vec<u8> = name.as_bytes().to_vec();
+
+    let (mut header, flags) = build_file_header_and_flags(&target_names, n_queries.try_into()?, &query_name, &MetadataCompression::default())?;
+    crate::headers::file::encode_file_header_and_flags(&mut header, &flags)
+}
+
 /// Encode the file header and file flags bytes.
 ///
 /// Calls [build_file_header_and_flags] on the input data and then creates the
@@ -112,18 +275,12 @@ pub fn encode_file_header_and_flags(
     name: &CxxString,
     n_queries: u32,
 ) -> Vec<u8> {
-    let target_names: Vec<Vec<u8>> = targets.iter().map(|x| x.as_bytes().to_vec()).collect();
-    let query_name: Vec<u8> = name.as_bytes().to_vec();
-
-    let (mut header, flags) = build_file_header_and_flags(&target_names, n_queries.try_into().unwrap(), &query_name, &MetadataCompression::default()).unwrap();
-    let bytes: Vec<u8> = crate::headers::file::encode_file_header_and_flags(&mut header, &flags).unwrap();
-
-    bytes
+    encode_file_header_and_flags_impl(targets, name, n_queries).unwrap()
 }
 
 /// Encode a single .ahda block and its block header and flags in 32-bit address space.
 ///
-/// Creates a [RoaringBitmap] from the set bit indexes and calls [pack_block_roaring32] to
+/// Creates a [RoaringBitmap] from the set bit indexes and calls [pack_roaring32] to
 /// encode the block header, block flags, and block contents.
 ///
 /// The output is a valid block record that can be appended to an .ahda record
@@ -136,13 +293,13 @@ pub fn encode_block32(
 ) -> Vec<u8> {
     let bitmap = RoaringBitmap::from_iter(set_bits.iter());
     let query_names: Vec<Vec<u8>> = queries.iter().map(|x| x.as_bytes().to_vec()).collect();
-    let block = pack_block_roaring32(&query_names, query_ids.as_slice(), bitmap);
+    let block = pack_roaring32(&query_names, query_ids.as_slice(), bitmap);
     block.unwrap()
 }
 
 /// Encode a single .ahda block and its block header and flags in 64-bit address space.
 ///
-/// Creates a [RoaringTreemap] from the set bit indexes and calls [pack_block_roaring64] to
+/// Creates a [RoaringTreemap] from the set bit indexes and calls [pack_roaring64] to
 /// encode the block header, block flags, and block contents.
 ///
 /// The output is a valid block record that can be appended to an .ahda record
@@ -155,7 +312,7 @@ pub fn encode_block64(
 ) -> Vec<u8> {
     let bitmap = RoaringTreemap::from_iter(set_bits.iter());
     let query_names: Vec<Vec<u8>> = queries.iter().map(|x| x.as_bytes().to_vec()).collect();
-    let block = pack_block_roaring64(&query_names, query_ids.as_slice(), bitmap);
+    let block = pack_roaring64(&query_names, query_ids.as_slice(), bitmap);
     block.unwrap()
 }
 
@@ -174,19 +331,28 @@ pub fn encode_bitmap(
     name: &CxxString,
     set_bits: &CxxVector<u64>,
 ) -> Vec<u8> {
+    encode_bitmap_impl(targets, queries, name, set_bits.as_slice()).unwrap()
+}
+
+fn encode_bitmap_impl(
+    targets: &CxxVector<CxxString>,
+    queries: &CxxVector<CxxString>,
+    name: &CxxString,
+    set_bits: &[u64],
+) -> Result<Vec<u8>, E> {
     let query_names: Vec<Vec<u8>> = queries.iter().map(|x| x.as_bytes().to_vec()).collect();
     let target_names: Vec<Vec<u8>> = targets.iter().map(|x| x.as_bytes().to_vec()).collect();
     let query_name: Vec<u8> = name.as_bytes().to_vec();
 
-    let mut set_bits_iter = set_bits.as_slice().iter().cloned();
+    let mut set_bits_iter = set_bits.iter().cloned();
     let mut encoder = BitmapEncoder::new(&mut set_bits_iter, &target_names, &query_names, &query_name);
 
-    let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+    let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags()?;
     for block in encoder {
-        bytes.extend(block.unwrap());
+        bytes.extend(block?);
     }
 
-    bytes
+    Ok(bytes)
 }
 
 /// Decodes the indexes of set bits in a flattened pseudoalignment from an .ahda record.
@@ -202,10 +368,52 @@ pub fn encode_bitmap(
 pub fn decode_bitmap(
     bytes: &CxxVector<u8>,
 ) -> Vec<u64> {
-    let mut cursor = Cursor::new(bytes.as_slice());
-    let (bitmap, _, _, _) = decode_from_read_to_roaring(&mut cursor).unwrap();
-    let set_bits: Vec<u64> = bitmap.iter().collect();
-    set_bits
+    decode_bitmap_impl(bytes.as_slice()).unwrap().iter().collect()
+}
+
+fn decode_bitmap_impl(
+    bytes: &[u8],
+) -> Result<RoaringTreemap, E> {
+    let mut cursor = Cursor::new(bytes);
+    let (bitmap, _, _, _) = decode_from_read_to_roaring(&mut cursor)?;
+    Ok(bitmap)
+}
+
+/// Decodes the indexes of set bits in a single block of an .ahda record.
+///
+/// Uses a [BlockIndex] to find block `block_idx` from the block headers and
+/// only decompresses that block.
+///
+/// The input should contain the full contents of the .ahda record.
+///
+/// The output is a vector containing the indexes of set bits in the flattened
+/// pseudoalignment of the whole record, for the queries in the block.
+///
+/// Panics if the record has `block_idx` or fewer blocks.
+///
+pub fn decode_block_bitmap(
+    bytes: &CxxVector<u8>,
+    block_idx: u32,
+) -> Vec<u64> {
+    BlockIndex::new(bytes.as_slice()).and_then(|index| index.block_bitmap(block_idx as usize)).unwrap().iter().collect()
+}
+
+/// Decodes the indexes of set bits for a range of queries in an .ahda record.
+///
+/// Uses a [BlockIndex] to only decompress the blocks that contain queries
+/// with `start_id <= query_id < end_id`.
+///
+/// The input should contain the full contents of the .ahda record.
+///
+/// The output is a vector containing the indexes of set bits in the flattened
+/// pseudoalignment of the whole record, for the queries in the range.
+///
+pub fn decode_query_range(
+    bytes: &CxxVector<u8>,
+    start_id: u32,
+    end_id: u32,
+) -> Vec<u64> {
+    BlockIndex::new(bytes.as_slice()).and_then(|index| index.query_range_bitmap(start_id, end_id)).unwrap().iter().collect()
 }
 
 /// Decodes the target sequence names from the file flags of an .ahda record.
@@ -220,10 +428,26 @@ pub fn decode_bitmap(
 pub fn decode_target_names(
     bytes: &CxxVector<u8>,
 ) -> Vec<String> {
-    let mut cursor = Cursor::new(bytes.as_slice());
-    let (header, flags) = read_file_header_and_flags(&mut cursor).unwrap();
-    assert_eq!(header.n_targets as usize, flags.target_names.len());
-    flags.target_names.into_iter().map(|x| String::from_utf8(x.to_vec()).unwrap()).collect()
+    decode_target_names_impl(bytes.as_slice()).unwrap()
+}
+
+fn decode_target_names_impl(
+    bytes: &[u8],
+) -> Result<Vec<String>, E> {
+    let mut cursor = Cursor::new(bytes);
+    let (header, flags) = read_file_header_and_flags(&mut cursor)?;
+    if header.n_targets as usize != flags.target_names.len() {
+        return Err(Box::new(crate::errors::AhdaHeaderError{}))
+    }
+    flags.target_names.into_iter().map(|x| Ok(String::from_utf8(x)?)).collect()
+}
+
+/// Reads the block flags of every block in an .ahda record.
+fn decode_block_flags_impl(
+    bytes: &[u8],
+) -> Result<Vec<BlockFlags>, E> {
+    let index = BlockIndex::new(bytes)?;
+    (0..index.n_blocks()).map(|block_idx| index.block_flags(block_idx)).collect()
 }
 
 /// Decodes the query sequence names from the block flags in an .ahda record.
@@ -238,19 +462,19 @@ pub fn decode_target_names(
 pub fn decode_query_names(
     bytes: &CxxVector<u8>,
 ) -> Vec<String> {
-    let mut cursor = Cursor::new(bytes.as_slice());
-    let (header, _) = read_file_header_and_flags(&mut cursor).unwrap();
-
-    let mut query_names: Vec<String> = Vec::with_capacity(header.n_queries as usize);
-    while let Ok((header, flags)) = read_block_header_and_flags(&mut cursor) {
-        assert_eq!(header.num_records as usize, query_names.len());
-        assert_eq!(flags.query_ids.as_ref().unwrap().len(), query_names.len());
-        let mut queries_str = flags.queries.unwrap().into_iter().map(|x| String::from_utf8(x.to_vec()).unwrap());
-        query_names.extend(&mut queries_str);
-        cursor.set_position(cursor.position() + header.block_len as u64);
-    }
+    decode_query_names_impl(bytes.as_slice()).unwrap()
+}
 
-    query_names
+fn decode_query_names_impl(
+    bytes: &[u8],
+) -> Result<Vec<String>, E> {
+    let mut query_names: Vec<String> = Vec::new();
+    for flags in decode_block_flags_impl(bytes)? {
+        for name in flags.queries.ok_or(crate::errors::MissingBlockFieldsErr{})? {
+            query_names.push(String::from_utf8(name)?);
+        }
+    }
+    Ok(query_names)
 }
 
 /// Decodes the query sequence indexes from the block flags in an .ahda record.
@@ -266,16 +490,277 @@ pub fn decode_query_names(
 pub fn decode_query_ids(
     bytes: &CxxVector<u8>,
 ) -> Vec<u32> {
-    let mut cursor = Cursor::new(bytes.as_slice());
-    let (header, _) = read_file_header_and_flags(&mut cursor).unwrap();
-
-    let mut query_ids: Vec<u32> = Vec::with_capacity(header.n_queries as usize);
-    while let Ok((header, flags)) = read_block_header_and_flags(&mut cursor) {
-        assert_eq!(header.num_records as usize, query_ids.len());
-        assert_eq!(flags.query_ids.as_ref().unwrap().len(), query_ids.len());
-        query_ids.append(&mut flags.query_ids.unwrap());
-        cursor.set_position(cursor.position() + header.block_len as u64);
+    decode_query_ids_impl(bytes.as_slice()).unwrap()
+}
+
+fn decode_query_ids_impl(
+    bytes: &[u8],
+) -> Result<Vec<u32>, E> {
+    let mut query_ids: Vec<u32> = Vec::new();
+    for flags in decode_block_flags_impl(bytes)? {
+        query_ids.append(&mut flags.query_ids.ok_or(crate::errors::MissingBlockFieldsErr{})?);
+    }
+    Ok(query_ids)
+}
+
+/// Writes the message of `e` to `error` and returns its [Status].
+fn fail(
+    e: E,
+    error: &mut String,
+) -> Status {
+    *error = e.to_string();
+    if e.is::<crate::errors::UnknownBlockErr>() {
+        Status::OutOfRange
+    } else {
+        Status::InvalidInput
+    }
+}
+
+/// Appends `values` to `out`.
+fn extend<T: cxx::vector::VectorElement + cxx::ExternType<Kind = cxx::kind::Trivial>, I: ExactSizeIterator<Item = T>>(
+    mut out: Pin<&mut CxxVector<T>>,
+    values: I,
+) {
+    out.as_mut().reserve(values.len());
+    values.for_each(|value| out.as_mut().push(value));
+}
+
+/// Same as [encode_file_header_and_flags] but returns an error code.
+///
+/// Appends the bytes to `out` and returns [Status::Ok] on success. Otherwise
+/// writes the error to `error` and returns the [Status] for the error.
+///
+pub fn try_encode_file_header_and_flags(
+    targets: &CxxVector<CxxString>,
+    name: &CxxString,
+    n_queries: u32,
+    out: Pin<&mut CxxVector<u8>>,
+    error: &mut String,
+) -> Status {
+    match encode_file_header_and_flags_impl(targets, name, n_queries) {
+        Ok(bytes) => { extend(out, bytes.into_iter()); Status::Ok },
+        Err(e) => fail(e, error),
+    }
+}
+
+/// Same as [encode_bitmap] but returns an error code.
+///
+/// Appends the .ahda record to `out` and returns [Status::Ok] on success.
+/// Otherwise writes the error to `error` and returns the [Status] for the
+/// error.
+///
+pub fn try_encode_bitmap(
+    targets: &CxxVector<CxxString>,
+    queries: &CxxVector<CxxString>,
+    name: &CxxString,
+    set_bits: &[u64],
+    out: Pin<&mut CxxVector<u8>>,
+    error: &mut String,
+) -> Status {
+    match encode_bitmap_impl(targets, queries, name, set_bits) {
+        Ok(bytes) => { extend(out, bytes.into_iter()); Status::Ok },
+        Err(e) => fail(e, error),
+    }
+}
+
+/// Same as [decode_bitmap] but returns an error code.
+///
+/// Appends the set bits to `out` and returns [Status::Ok] on success.
+/// Otherwise writes the error to `error` and returns the [Status] for the
+/// error.
+///
+pub fn try_decode_bitmap(
+    bytes: &[u8],
+    out: Pin<&mut CxxVector<u64>>,
+    error: &mut String,
+) -> Status {
+    match decode_bitmap_impl(bytes) {
+        Ok(bitmap) => { extend(out, bitmap.iter()); Status::Ok },
+        Err(e) => fail(e, error),
+    }
+}
+
+/// Same as [decode_block_bitmap] but returns an error code.
+///
+/// Appends the set bits to `out` and returns [Status::Ok] on success.
+/// Returns [Status::OutOfRange] if the record has `block_idx` or fewer
+/// blocks.
+///
+pub fn try_decode_block_bitmap(
+    bytes: &[u8],
+    block_idx: u32,
+    out: Pin<&mut CxxVector<u64>>,
+    error: &mut String,
+) -> Status {
+    match BlockIndex::new(bytes).and_then(|index| index.block_bitmap(block_idx as usize)) {
+        Ok(bitmap) => { extend(out, bitmap.iter()); Status::Ok },
+        Err(e) => fail(e, error),
+    }
+}
+
+/// Same as [decode_query_range] but returns an error code.
+///
+/// Appends the set bits to `out` and returns [Status::Ok] on success.
+/// Otherwise writes the error to `error` and returns the [Status] for the
+/// error.
+///
+pub fn try_decode_query_range(
+    bytes: &[u8],
+    start_id: u32,
+    end_id: u32,
+    out: Pin<&mut CxxVector<u64>>,
+    error: &mut String,
+) -> Status {
+    match BlockIndex::new(bytes).and_then(|index| index.query_range_bitmap(start_id, end_id)) {
+        Ok(bitmap) => { extend(out, bitmap.iter()); Status::Ok },
+        Err(e) => fail(e, error),
     }
+}
+
+/// Same as [decode_target_names] but returns an error code.
+pub fn try_decode_target_names(
+    bytes: &[u8],
+    out: &mut Vec<String>,
+    error: &mut String,
+) -> Status {
+    match decode_target_names_impl(bytes) {
+        Ok(mut names) => { out.append(&mut names); Status::Ok },
+        Err(e) => fail(e, error),
+    }
+}
+
+/// Same as [decode_query_ids] but returns an error code.
+pub fn try_decode_query_ids(
+    bytes: &[u8],
+    out: Pin<&mut CxxVector<u32>>,
+    error: &mut String,
+) -> Status {
+    match decode_query_ids_impl(bytes) {
+        Ok(ids) => { extend(out, ids.into_iter()); Status::Ok },
+        Err(e) => fail(e, error),
+    }
+}
+
+/// Same as [decode_query_names] but returns an error code.
+pub fn try_decode_query_names(
+    bytes: &[u8],
+    out: &mut Vec<String>,
+    error: &mut String,
+) -> Status {
+    match decode_query_names_impl(bytes) {
+        Ok(mut names) => { out.append(&mut names); Status::Ok },
+        Err(e) => fail(e, error),
+    }
+}
+
+/// Writes an .ahda record one read at a time.
+///
+/// Created with [new_streaming_block_writer]. The bytes from
+/// [encode_header](StreamingBlockWriter::encode_header) must be written first,
+/// followed by the bytes returned from each call to
+/// [add_read](StreamingBlockWriter::add_read) and finally the bytes from
+/// [finish](StreamingBlockWriter::finish).
+///
+pub struct StreamingBlockWriter {
+    encoder: StreamingEncoder,
+}
+
+/// Create a [StreamingBlockWriter] for the target sequences in `targets`.
+///
+/// `n_queries` is the number of reads in the input, or 0 if it is not known.
+///
+pub fn new_streaming_block_writer(
+    targets: &CxxVector<CxxString>,
+    name: &CxxString,
+    n_queries: u32,
+) -> Box<StreamingBlockWriter> {
+    try_new_streaming_block_writer(targets, name, n_queries).unwrap()
+}
 
-    query_ids
+/// Same as [new_streaming_block_writer] but returns an error if the header can't be built.
+///
+/// The error is thrown as a `rust::Error` in C++.
+///
+pub fn try_new_streaming_block_writer(
+    targets: &CxxVector<CxxString>,
+    name: &CxxString,
+    n_queries: u32,
+) -> Result<Box<StreamingBlockWriter>, E> {
+    let target_names: Vec<Vec<u8>> = targets.iter().map(|x| x.as_bytes().to_vec()).collect();
+    let query_name: Vec<u8> = name.as_bytes().to_vec();
+
+    Ok(Box::new(StreamingBlockWriter{ encoder: StreamingEncoder::try_new(&target_names, &query_name, n_queries as usize)? }))
+}
+
+impl StreamingBlockWriter {
+    /// Encode the file header and file flags bytes.
+    pub fn encode_header(
+        &mut self,
+    ) -> Vec<u8> {
+        self.encoder.encode_file_header_and_flags().unwrap()
+    }
+
+    /// Add the ids of the targets that the read `query_id` aligns to.
+    ///
+    /// Returns the bytes of a complete block if adding the read filled it,
+    /// otherwise returns an empty vector.
+    ///
+    pub fn add_read(
+        &mut self,
+        query_name: &CxxString,
+        query_id: u32,
+        target_ids: &CxxVector<u32>,
+    ) -> Vec<u8> {
+        self.encoder.push(query_name.as_bytes(), query_id, target_ids.as_slice()).unwrap().unwrap_or_default()
+    }
+
+    /// Encode the reads that have not been written yet.
+    pub fn finish(
+        &mut self,
+    ) -> Vec<u8> {
+        self.encoder.finish().unwrap().unwrap_or_default()
+    }
+
+    /// Same as [encode_header](StreamingBlockWriter::encode_header) but appends the bytes to `out`.
+    pub fn try_encode_header(
+        &mut self,
+        out: Pin<&mut CxxVector<u8>>,
+        error: &mut String,
+    ) -> Status {
+        match self.encoder.encode_file_header_and_flags() {
+            Ok(bytes) => { extend(out, bytes.into_iter()); Status::Ok },
+            Err(e) => fail(e, error),
+        }
+    }
+
+    /// Same as [add_read](StreamingBlockWriter::add_read) but appends the bytes to `out`.
+    ///
+    /// Fails with [Status::InvalidInput] if a target id is not less than the
+    /// number of targets or if `query_id` is not less than `n_queries`.
+    ///
+    pub fn try_add_read(
+        &mut self,
+        query_name: &CxxString,
+        query_id: u32,
+        target_ids: &CxxVector<u32>,
+        out: Pin<&mut CxxVector<u8>>,
+        error: &mut String,
+    ) -> Status {
+        match self.encoder.push(query_name.as_bytes(), query_id, target_ids.as_slice()) {
+            Ok(bytes) => { extend(out, bytes.unwrap_or_default().into_iter()); Status::Ok },
+            Err(e) => fail(e, error),
+        }
+    }
+
+    /// Same as [finish](StreamingBlockWriter::finish) but appends the bytes to `out`.
+    pub fn try_finish(
+        &mut self,
+        out: Pin<&mut CxxVector<u8>>,
+        error: &mut String,
+    ) -> Status {
+        match self.encoder.finish() {
+            Ok(bytes) => { extend(out, bytes.unwrap_or_default().into_iter()); Status::Ok },
+            Err(e) => fail(e, error),
+        }
+    }
 }
@@ -14,12 +14,18 @@
 use crate::PseudoAln;
 use crate::headers::file::FileHeader;
 
+use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
+
 pub struct BitmapDecoder<'a, I: Iterator> where I: Iterator<Item=u64> {
     // Inputs
     bits_iter: &'a mut I,
     index: Option<u64>,
 
     file_header: FileHeader,
+
+    // Target ids of the current row
+    row: Vec<u32>,
 }
 
 impl<'a, I: Iterator> BitmapDecoder<'a, I> where I: Iterator<Item=u64> {
@@ -32,57 +38,197 @@ impl<'a, I: Iterator> BitmapDecoder<'a, I> where I: Iterator<Item=u64> {
             bits_iter,
             file_header,
             index: None,
+            row: Vec::new(),
         }
     }
-}
-
-impl<I: Iterator> Iterator for BitmapDecoder<'_, I> where I: Iterator<Item=u64>{
-    type Item = PseudoAln;
 
-    fn next(
+    /// Decode the next row without allocating a record.
+    ///
+    /// A row is a run of consecutive set bits that belong to the same query.
+    /// Returns the query index and the target ids of the row. The target ids
+    /// are stored in a buffer that is reused for the next row, so the slice
+    /// is only valid until the next call.
+    ///
+    /// If the set bits are not sorted, a query can appear in more than one
+    /// row.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::compression::MetadataCompression;
+    /// use ahda::decoder::bitmap_decoder::BitmapDecoder;
+    /// use ahda::headers::file::build_file_header_and_flags;
+    ///
+    /// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+    /// let (header, _) = build_file_header_and_flags(&targets, 5, b"sample", &MetadataCompression::default()).unwrap();
+    ///
+    /// let mut bits = vec![2_u64, 9, 11, 12, 13, 14].into_iter();
+    /// let mut decoder = BitmapDecoder::new(&mut bits, header);
+    ///
+    /// assert_eq!(decoder.next_row(), Some((0, [2].as_slice())));
+    /// assert_eq!(decoder.next_row(), Some((3, [0, 2].as_slice())));
+    /// assert_eq!(decoder.next_row(), Some((4, [0, 1, 2].as_slice())));
+    /// assert_eq!(decoder.next_row(), None);
+    /// ```
+    ///
+    pub fn next_row(
         &mut self,
-    ) -> Option<Self::Item> {
-        let mut ones: Vec<u32> = Vec::new();
+    ) -> Option<(u32, &[u32])> {
+        self.row.clear();
         let mut query_id: Option<u32> = None;
 
         let n_targets: u64 = self.file_header.n_targets as u64;
-        if self.index.is_some() {
-            let query_idx = self.index.as_ref().unwrap() / n_targets;
-            let target_idx = self.index.as_ref().unwrap() % n_targets;
-            ones.push(target_idx as u32);
-            query_id = Some(query_idx as u32);
-            self.index = None;
+        if n_targets == 0 {
+            // No bits can be set if there are no targets
+            return None
+        }
+
+        if let Some(idx) = self.index.take() {
+            self.row.push((idx % n_targets) as u32);
+            query_id = Some((idx / n_targets) as u32);
         }
 
         for idx in self.bits_iter.by_ref() {
-            self.index = Some(idx);
-            let query_idx = self.index.as_ref().unwrap() / n_targets;
-            if query_id.is_some() && query_idx as u32 != *query_id.as_ref().unwrap() {
+            let query_idx = (idx / n_targets) as u32;
+            if query_id.is_some_and(|x| x != query_idx) {
+                // First bit of the next row
+                self.index = Some(idx);
                 break;
             }
-            let target_idx = self.index.as_ref().unwrap() % n_targets;
-            self.index = None;
-            ones.push(target_idx as u32);
-            query_id = Some(query_idx as u32);
+            self.row.push((idx % n_targets) as u32);
+            query_id = Some(query_idx);
         }
 
-        if query_id.is_some() {
-            Some(PseudoAln{
-                ones: Some(ones),
-                query_id,
-                // Filling names for the whole block is slow and takes a lot of space if the alignment is dense
-                ones_names: None,
-                query_name: None,
-            })
-        } else {
-            None
+        Some((query_id?, &self.row))
+    }
+
+    /// Call `f` with the query index and target ids of each remaining row.
+    ///
+    /// See [next_row](BitmapDecoder::next_row) for details.
+    pub fn for_each_row<F: FnMut(u32, &[u32])>(
+        &mut self,
+        mut f: F,
+    ) {
+        while let Some((query_idx, targets)) = self.next_row() {
+            f(query_idx, targets);
         }
     }
 }
 
+impl<I: Iterator> Iterator for BitmapDecoder<'_, I> where I: Iterator<Item=u64>{
+    type Item = PseudoAln;
+
+    fn next(
+        &mut self,
+    ) -> Option<Self::Item> {
+        let (query_id, ones) = self.next_row()?;
+        Some(PseudoAln{
+            ones: Some(ones.to_vec()),
+            query_id: Some(query_id),
+            // Filling names for the whole block is slow and takes a lot of space if the alignment is dense
+            ones_names: None,
+            query_name: None,
+        })
+    }
+}
+
+/// Convert sorted set bits to records in two passes.
+///
+/// The first pass stores the target ids of all bits in one buffer and records
+/// where each row ends, and the second copies each row into a vector of
+/// exactly the right size.
+fn decode_sorted_bits<I: Iterator<Item=u64>>(
+    bits: I,
+    n_bits: usize,
+    n_rows: usize,
+    n_targets: u32,
+) -> Vec<PseudoAln> {
+    if n_targets == 0 {
+        // No bits can be set if there are no targets
+        return Vec::new()
+    }
+    let n_targets = n_targets as u64;
+
+    let mut target_ids: Vec<u32> = Vec::with_capacity(n_bits);
+    let mut rows: Vec<(u32, usize)> = Vec::with_capacity(n_rows.min(n_bits));
+    for idx in bits {
+        let query_id = (idx / n_targets) as u32;
+        if rows.last().is_none_or(|(prev_id, _)| *prev_id != query_id) {
+            rows.push((query_id, target_ids.len()));
+        }
+        target_ids.push((idx % n_targets) as u32);
+    }
+
+    let row_ends = rows.iter().skip(1).map(|(_, start)| *start).chain(std::iter::once(target_ids.len()));
+    rows.iter().zip(row_ends).map(|((query_id, start), end)| {
+        PseudoAln{
+            ones: Some(target_ids[*start..end].to_vec()),
+            query_id: Some(*query_id),
+            // Filling names for the whole block is slow and takes a lot of space if the alignment is dense
+            ones_names: None,
+            query_name: None,
+        }
+    }).collect()
+}
+
+/// Convert all set bits in a block with a 32-bit address space to records.
+///
+/// Returns the same records as collecting a [BitmapDecoder] over
+/// `bitmap.iter()` but is faster for whole blocks because the records are
+/// allocated only once.
+///
+pub fn decode_roaring32(
+    bitmap: &RoaringBitmap,
+    n_targets: u32,
+) -> Vec<PseudoAln> {
+    let n_rows = match (bitmap.min(), bitmap.max()) {
+        (Some(min), Some(max)) if n_targets > 0 => (max / n_targets - min / n_targets) as usize + 1,
+        _ => 0,
+    };
+    decode_sorted_bits(bitmap.iter().map(|x| x as u64), bitmap.len() as usize, n_rows, n_targets)
+}
+
+/// Convert all set bits in a block with a 64-bit address space to records.
+///
+/// See [decode_roaring32] for details.
+///
+pub fn decode_roaring64(
+    bitmap: &RoaringTreemap,
+    n_targets: u32,
+) -> Vec<PseudoAln> {
+    let n_rows = match (bitmap.min(), bitmap.max()) {
+        (Some(min), Some(max)) if n_targets > 0 => (max / n_targets as u64 - min / n_targets as u64) as usize + 1,
+        _ => 0,
+    };
+    decode_sorted_bits(bitmap.iter(), bitmap.len() as usize, n_rows, n_targets)
+}
+
 #[cfg(test)]
 mod tests {
 
+    #[test]
+    fn for_each_row_matches_next() {
+        use super::BitmapDecoder;
+        use crate::PseudoAln;
+        use crate::compression::MetadataCompression;
+        use crate::headers::file::build_file_header_and_flags;
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let (header, _) = build_file_header_and_flags(&targets, 6, b"sample", &MetadataCompression::default()).unwrap();
+        let bits: Vec<u64> = vec![0, 1, 3, 6, 7, 8, 11];
+
+        let mut it = bits.clone().into_iter();
+        let expected: Vec<PseudoAln> = BitmapDecoder::new(&mut it, header.clone()).collect();
+
+        let mut got: Vec<PseudoAln> = Vec::new();
+        let mut it = bits.into_iter();
+        BitmapDecoder::new(&mut it, header).for_each_row(|query_id, ones| {
+            got.push(PseudoAln{ ones: Some(ones.to_vec()), query_id: Some(query_id), ones_names: None, query_name: None });
+        });
+
+        assert_eq!(got, expected);
+        assert_eq!(got.iter().map(|x| x.query_id.unwrap()).collect::<Vec<u32>>(), vec![0, 1, 3, 4, 5]);
+    }
+
     #[test]
     fn next_ends_with_one() {
         use super::BitmapDecoder;
@@ -237,4 +383,29 @@ mod tests {
 
         assert_eq!(got, expected);
     }
+
+    #[test]
+    fn decode_roaring32_matches_bitmap_decoder() {
+        use super::BitmapDecoder;
+        use super::decode_roaring32;
+        use super::decode_roaring64;
+        use crate::PseudoAln;
+        use crate::compression::MetadataCompression;
+        use crate::headers::file::build_file_header_and_flags;
+
+        use roaring::RoaringBitmap;
+        use roaring::RoaringTreemap;
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+        let (header, _) = build_file_header_and_flags(&targets, 10, b"ERR4035126", &MetadataCompression::default()).unwrap();
+
+        let data = RoaringBitmap::from_iter([0, 1, 2, 5, 9, 10, 29]);
+        let mut bits = data.iter().map(|x| x as u64);
+        let expected: Vec<PseudoAln> = BitmapDecoder::new(&mut bits, header).collect();
+
+        assert_eq!(decode_roaring32(&data, 3), expected);
+        assert_eq!(decode_roaring64(&RoaringTreemap::from_iter(data.iter().map(|x| x as u64)), 3), expected);
+        assert!(decode_roaring32(&RoaringBitmap::new(), 3).is_empty());
+        assert!(decode_roaring32(&data, 0).is_empty());
+    }
 }
@@ -0,0 +1,417 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Random access to the blocks of an .ahda record held in memory.
+//!
+//! [BlockIndex] walks the [block headers](crate::headers::block::BlockHeader)
+//! of a record once and remembers where each block starts. Single blocks can
+//! then be decoded without decompressing the blocks before them, and queries
+//! in a range of query ids only decompress the blocks that contain them.
+//!
+//! Blocks that are [references](crate::compression::dedup) to the contents
+//! of an earlier block are resolved when the index is built.
+//!
+//! Runs of blocks marked with
+//! [BLOCK_QUERY_IDS_SORTED](crate::compression::BLOCK_QUERY_IDS_SORTED) are
+//! binary searched by query id, so [find_query](BlockIndex::find_query) and
+//! [query_range_bitmap](BlockIndex::query_range_bitmap) only decode the
+//! flags of a logarithmic number of blocks in files written by the
+//! [Encoder](crate::encoder::Encoder) from input in query id order. Other
+//! blocks are checked one by one.
+//!
+//! The bitmaps are returned in the 64-bit address space of
+//! [decode_from_read_to_roaring](crate::decode_from_read_to_roaring), with
+//! the set bits at `query_id * n_targets + target_id`.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::encoder::Encoder;
+//! use ahda::decoder::block_index::BlockIndex;
+//! use ahda::PseudoAln;
+//! use roaring::RoaringTreemap;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let data: Vec<PseudoAln> = (0..6).map(|id| {
+//!     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+//! }).collect();
+//!
+//! // Encode in blocks of 2 queries
+//! let mut iter = data.into_iter();
+//! let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 6);
+//! encoder.set_block_size(2).unwrap();
+//! let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+//! for block in encoder.by_ref() {
+//!     bytes.append(&mut block.unwrap());
+//! }
+//!
+//! let index = BlockIndex::new(&bytes).unwrap();
+//! assert_eq!(index.n_blocks(), 3);
+//!
+//! // Queries 2 and 3 are in the second block
+//! assert_eq!(index.block_bitmap(1).unwrap(), RoaringTreemap::from_iter([4, 7]));
+//! assert_eq!(index.query_range_bitmap(3, 5).unwrap(), RoaringTreemap::from_iter([7, 8]));
+//!
+//! // Query 4 is the first record in the third block
+//! assert_eq!(index.find_query(4).unwrap(), Some((2, 0)));
+//! assert_eq!(index.find_query(6).unwrap(), None);
+//! ```
+//!
+
+use crate::headers::block::BlockFlags;
+use crate::headers::block::BlockHeader;
+use crate::headers::block::decode_block_flags_with_dictionary;
+use crate::headers::block::decode_block_header;
+use crate::headers::file::FileHeader;
+use crate::headers::file::FileFlags;
+use crate::headers::file::read_file_header_and_flags;
+use crate::compression::BitmapType;
+use crate::compression::decompress_payload;
+use crate::compression::flags_dictionary::FlagsDictionary;
+use crate::compression::dedup::BLOCK_IS_REFERENCE;
+use crate::compression::BLOCK_QUERY_IDS_SORTED;
+
+use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
+
+use std::ops::Range;
+
+type E = Box<dyn std::error::Error>;
+
+/// Position of a block in the record.
+#[derive(Clone, Debug, PartialEq)]
+struct BlockLocation {
+    header: BlockHeader,
+    // Start of the block flags
+    flags_start: usize,
+    // Start of the block contents, in an earlier block for references
+    contents_start: usize,
+}
+
+/// Index of the blocks in an .ahda record.
+pub struct BlockIndex<'a> {
+    // Inputs
+    bytes: &'a [u8],
+
+    header: FileHeader,
+    flags: FileFlags,
+
+    // Internals
+    blocks: Vec<BlockLocation>,
+    // Consecutive blocks in query id order
+    runs: Vec<Range<usize>>,
+    flags_dictionary: Option<FlagsDictionary>,
+}
+
+impl<'a> BlockIndex<'a> {
+    /// Find the blocks in the .ahda record in `bytes`.
+    ///
+    /// Only the block headers are read.
+    ///
+    /// Errors with [TruncatedBlockErr](crate::errors::TruncatedBlockErr) if
+    /// the last block is incomplete and with
+    /// [MissingBlockReferenceErr](crate::errors::MissingBlockReferenceErr) if
+    /// a block refers to a block that is not in the record.
+    ///
+    pub fn new(
+        bytes: &'a [u8],
+    ) -> Result<Self, E> {
+        let mut conn = bytes;
+        let (header, flags) = read_file_header_and_flags(&mut conn)?;
+
+        let mut blocks: Vec<BlockLocation> = Vec::new();
+        let mut pos = bytes.len() - conn.len();
+        while pos < bytes.len() {
+            let header_bytes = bytes.get(pos..(pos + 32)).ok_or(crate::errors::TruncatedBlockErr{})?;
+            let mut block_header = decode_block_header(header_bytes)?;
+            let flags_start = pos + 32;
+            let flags_len: usize = block_header.flags_len.try_into().map_err(|_| crate::errors::TruncatedBlockErr{})?;
+            let mut contents_start = flags_start.checked_add(flags_len).ok_or(crate::errors::TruncatedBlockErr{})?;
+            pos = contents_start.checked_add(block_header.block_len as usize).ok_or(crate::errors::TruncatedBlockErr{})?;
+            if pos > bytes.len() {
+                return Err(Box::new(crate::errors::TruncatedBlockErr{}))
+            }
+
+            if block_header.placeholder1 & BLOCK_IS_REFERENCE != 0 {
+                // A block can't refer to itself
+                let target = blocks.len().checked_sub(block_header.placeholder2 as usize).filter(|_| block_header.placeholder2 > 0).ok_or(crate::errors::MissingBlockReferenceErr{})?;
+                let referenced = blocks.get(target).ok_or(crate::errors::MissingBlockReferenceErr{})?;
+                contents_start = referenced.contents_start;
+                block_header.block_len = referenced.header.block_len;
+                // Payload storage flags of the referenced block apply
+                block_header.placeholder1 = (referenced.header.placeholder1 & !BLOCK_QUERY_IDS_SORTED) | (block_header.placeholder1 & BLOCK_QUERY_IDS_SORTED);
+            }
+
+            blocks.push(BlockLocation{ header: block_header, flags_start, contents_start });
+        }
+
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for (block_idx, block) in blocks.iter().enumerate() {
+            match runs.last_mut() {
+                Some(run) if block.header.placeholder1 & BLOCK_QUERY_IDS_SORTED != 0 => run.end += 1,
+                _ => runs.push(block_idx..(block_idx + 1)),
+            }
+        }
+
+        let flags_dictionary = FlagsDictionary::from_flags(&flags);
+        Ok(BlockIndex{ bytes, header, flags, blocks, runs, flags_dictionary })
+    }
+
+    pub fn file_header(
+        &self,
+    ) -> &FileHeader {
+        &self.header
+    }
+
+    pub fn file_flags(
+        &self,
+    ) -> &FileFlags {
+        &self.flags
+    }
+
+    /// Number of blocks in the record.
+    pub fn n_blocks(
+        &self,
+    ) -> usize {
+        self.blocks.len()
+    }
+
+    fn location(
+        &self,
+        block_idx: usize,
+    ) -> Result<&BlockLocation, E> {
+        Ok(self.blocks.get(block_idx).ok_or(crate::errors::UnknownBlockErr{})?)
+    }
+
+    /// Decode the [BlockFlags] of block `block_idx`.
+    ///
+    /// Errors with [UnknownBlockErr](crate::errors::UnknownBlockErr) if the
+    /// record has fewer blocks.
+    pub fn block_flags(
+        &self,
+        block_idx: usize,
+    ) -> Result<BlockFlags, E> {
+        let block = self.location(block_idx)?;
+        let bytes = &self.bytes[block.flags_start..(block.flags_start + block.header.flags_len as usize)];
+        decode_block_flags_with_dictionary(bytes, &block.header, self.flags_dictionary.as_ref(), &mut Vec::new())
+    }
+
+    /// Decode the bitmap of block `block_idx`.
+    ///
+    /// Errors with [UnknownBlockErr](crate::errors::UnknownBlockErr) if the
+    /// record has fewer blocks.
+    pub fn block_bitmap(
+        &self,
+        block_idx: usize,
+    ) -> Result<RoaringTreemap, E> {
+        let block = self.location(block_idx)?;
+        let payload = decompress_payload(&self.bytes[block.contents_start..(block.contents_start + block.header.block_len as usize)], &block.header)?;
+        let bitmap = match BitmapType::from_u16(self.header.bitmap_type)? {
+            BitmapType::Roaring32 => RoaringTreemap::from_bitmaps([(0, RoaringBitmap::deserialize_from(payload.as_slice())?)]),
+            BitmapType::Roaring64 => RoaringTreemap::deserialize_from(payload.as_slice())?,
+        };
+        Ok(bitmap)
+    }
+
+    fn block_query_ids(
+        &self,
+        block_idx: usize,
+    ) -> Result<Vec<u32>, E> {
+        Ok(self.block_flags(block_idx)?.query_ids.ok_or(crate::errors::MissingBlockFieldsErr{})?)
+    }
+
+    /// Find the block and the index in its [BlockFlags] of the record with `query_id`.
+    ///
+    /// Returns None if no block contains `query_id`.
+    ///
+    /// ## Errors and panics
+    /// Errors with [MissingBlockFieldsErr](crate::errors::MissingBlockFieldsErr)
+    /// if a block has no query ids, and if the block flags can't be decoded.
+    ///
+    pub fn find_query(
+        &self,
+        query_id: u32,
+    ) -> Result<Option<(usize, usize)>, E> {
+        for run in self.runs.iter() {
+            let (mut lo, mut hi) = (run.start, run.end);
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                let flags = self.block_flags(mid)?;
+                let query_ids = flags.query_ids.as_ref().ok_or(crate::errors::MissingBlockFieldsErr{})?;
+                match (query_ids.iter().min(), query_ids.iter().max()) {
+                    (Some(min), _) if query_id < *min => hi = mid,
+                    (_, Some(max)) if query_id > *max => lo = mid + 1,
+                    // Only the first block of a run may be empty
+                    (None, None) => lo = mid + 1,
+                    _ => {
+                        if let Some(pos) = flags.position(query_id) {
+                            return Ok(Some((mid, pos)))
+                        }
+                        break
+                    },
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decode the alignments of the queries with `start_id <= query_id < end_id`.
+    ///
+    /// Only the blocks that contain some of the queries are decompressed.
+    pub fn query_range_bitmap(
+        &self,
+        start_id: u32,
+        end_id: u32,
+    ) -> Result<RoaringTreemap, E> {
+        let mut bitmap = RoaringTreemap::new();
+        if start_id >= end_id {
+            return Ok(bitmap)
+        }
+
+        for run in self.runs.iter() {
+            // First block of the run with ids at or after `start_id`
+            let (mut lo, mut hi) = (run.start, run.end);
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                if self.block_query_ids(mid)?.iter().max().is_none_or(|max| *max < start_id) {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            for block_idx in lo..run.end {
+                let query_ids = self.block_query_ids(block_idx)?;
+                if query_ids.iter().any(|id| *id >= start_id && *id < end_id) {
+                    bitmap |= self.block_bitmap(block_idx)?;
+                }
+                if query_ids.iter().min().is_some_and(|min| *min >= end_id) {
+                    break
+                }
+            }
+        }
+
+        let n_targets = self.header.n_targets as u64;
+        bitmap.remove_range(..(start_id as u64 * n_targets));
+        bitmap.remove_range((end_id as u64 * n_targets)..);
+        Ok(bitmap)
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn block_index_resolves_references() {
+        use super::BlockIndex;
+        use crate::compression::dedup::deduplicate_blocks;
+        use crate::try_concatenate_from_read_to_write;
+        use crate::decode_from_read_to_roaring;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let mut inputs: Vec<Cursor<Vec<u8>>> = (0..3).map(|id| {
+            let ones = if id == 1 { vec![1] } else { vec![] };
+            let data = vec![PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(ones), query_name: None }];
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+            Cursor::new(bytes)
+        }).collect();
+
+        let mut concatenated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        try_concatenate_from_read_to_write(&mut inputs, &mut concatenated).unwrap();
+        concatenated.set_position(0);
+        let mut bytes: Vec<u8> = Vec::new();
+        assert_eq!(deduplicate_blocks(&mut concatenated, &mut bytes).unwrap(), 1);
+
+        let index = BlockIndex::new(&bytes).unwrap();
+        assert_eq!(index.n_blocks(), 3);
+        assert_eq!(index.block_flags(2).unwrap().query_ids, Some(vec![2]));
+        assert!(index.block_bitmap(2).unwrap().is_empty());
+        assert!(index.block_bitmap(3).is_err());
+
+        let (expected, _, _, _) = decode_from_read_to_roaring(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(index.query_range_bitmap(0, 3).unwrap(), expected);
+        assert!(index.query_range_bitmap(2, 3).unwrap().is_empty());
+        assert!(index.query_range_bitmap(3, 0).unwrap().is_empty());
+
+        assert!(BlockIndex::new(&bytes[0..(bytes.len() - 1)]).is_err());
+    }
+
+    #[test]
+    fn block_index_rejects_crafted_headers() {
+        use super::BlockIndex;
+        use crate::encode_to_write;
+        use crate::headers::block::BlockHeader;
+        use crate::headers::block::encode_block_header;
+        use crate::EncodeOpts;
+        use ahda_core::block::BLOCK_IS_REFERENCE;
+
+        let mut record: Vec<u8> = Vec::new();
+        encode_to_write(&[b"chr.fasta".to_vec()], &[], &[], &mut record, EncodeOpts::default()).unwrap();
+
+        // Reference to the block itself
+        let header = BlockHeader{ num_records: 0, metadata_compression: 0, bitmap_type: 0, placeholder1: BLOCK_IS_REFERENCE, block_len: 0, flags_len: 0, fields_present: 0, placeholder2: 0, placeholder3: 0 };
+        let mut bytes = record.clone();
+        bytes.extend(encode_block_header(&header).unwrap());
+        assert!(BlockIndex::new(&bytes).is_err());
+
+        // Flags that would end past the address space
+        let header = BlockHeader{ placeholder1: 0, flags_len: u64::MAX, ..header };
+        let mut bytes = record.clone();
+        bytes.extend(encode_block_header(&header).unwrap());
+        assert!(BlockIndex::new(&bytes).is_err());
+    }
+
+    #[test]
+    fn find_query_in_runs_of_sorted_blocks() {
+        use super::BlockIndex;
+        use crate::try_concatenate_from_read_to_write;
+        use crate::encoder::Encoder;
+        use crate::PseudoAln;
+        use roaring::RoaringTreemap;
+
+        use std::io::Cursor;
+
+        // Inputs are concatenated out of order, so the second one starts a new run
+        let targets = vec![b"chr.fasta".to_vec()];
+        let mut inputs: Vec<Cursor<Vec<u8>>> = [10..20, 0..10].into_iter().map(|ids| {
+            let mut records = ids.map(|id| PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![0]), query_name: None });
+            let mut encoder = Encoder::new(&mut records, &targets, b"sample", 20);
+            encoder.set_block_size(3).unwrap();
+            let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+            for block in encoder.by_ref() {
+                bytes.append(&mut block.unwrap());
+            }
+            Cursor::new(bytes)
+        }).collect();
+        let mut concatenated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        try_concatenate_from_read_to_write(&mut inputs, &mut concatenated).unwrap();
+        let bytes = concatenated.into_inner();
+
+        let index = BlockIndex::new(&bytes).unwrap();
+        assert_eq!(index.n_blocks(), 8);
+        assert_eq!(index.runs, vec![0..4, 4..8]);
+        for query_id in 0..20 {
+            let (block_idx, pos) = index.find_query(query_id).unwrap().unwrap();
+            assert_eq!(index.block_flags(block_idx).unwrap().query_ids.unwrap()[pos], query_id);
+        }
+        assert_eq!(index.find_query(20).unwrap(), None);
+        assert_eq!(index.query_range_bitmap(8, 12).unwrap(), RoaringTreemap::from_iter(8..12));
+    }
+}
@@ -34,6 +34,31 @@
 //! Decoder will pad the output from BitmapDecoder to include queries that are
 //! included in [BlockFlags] but did not align against any target.
 //!
+//! Iterating over Decoder stops at the first record that can't be decoded. Use
+//! [Decoder::try_iter] to get an error for each bad record instead, eg. to
+//! report or skip them in a streaming pipeline.
+//!
+//! Decoder reads the next block only after the previous one has been decoded.
+//! Wrap the input in a [PrefetchReader](prefetch::PrefetchReader) to read the
+//! next blocks on a background thread instead.
+//!
+//! Records are returned in the order they are stored in, which need not match
+//! the order of their query ids. Wrap the decoder in
+//! [RankSorted](sorted::RankSorted) to sort the records by query id.
+//!
+//! Records held in memory can be decoded one block or query range at a time
+//! with a [BlockIndex](block_index::BlockIndex).
+//!
+//! Query ids that are not less than `n_queries` in the [FileHeader] extend
+//! `n_queries` by default, see [QueryIdCheck] for details.
+//!
+//! [Decoder::iter_targets] transposes the blocks to return the query ids
+//! aligned to each target instead of the targets of each query.
+//!
+//! If the record stores [TargetGroups](crate::headers::file::TargetGroups),
+//! [Decoder::collapse] makes the decoder return alignments to the groups at
+//! some level instead of the target sequences.
+//!
 //! ## Usage
 //!
 //! ### Decoder
@@ -111,7 +136,7 @@
 //!                                n_targets: 3_u32,
 //!                                n_queries: 5_u32,
 //!                                ahda_header: ahda::headers::file::build_ahda_header(),
-//!                                file_format: ahda::AhdaFormatVersion::V1_0_0.to_u8(),
+//!                                file_format: ahda::AhdaFormatVersion::V1_1_0.to_u8(),
 //!                                metadata_compression: ahda::compression::MetadataCompression::default().to_u8(),
 //!                                fields_present: 0,
 //!                                bitmap_type: ahda::compression::BitmapType::Roaring32.to_u16(),
@@ -131,36 +156,151 @@
 //!
 
 pub mod bitmap_decoder;
+pub mod block_index;
+pub mod prefetch;
+pub mod sorted;
 
 use crate::PseudoAln;
+use crate::max_hits::MaxHits;
+use crate::target_chunks::TargetChunks;
+use crate::target_summary::TargetSummary;
 use crate::headers::file::FileHeader;
 use crate::headers::file::FileFlags;
+use crate::headers::file::collapse_file_header_and_flags;
 use crate::headers::file::read_file_header;
 use crate::headers::file::read_file_flags;
 use crate::headers::block::BlockFlags;
-use crate::headers::block::read_block_header;
+use crate::headers::block::BlockHeader;
 use crate::compression::BitmapType;
-use crate::compression::roaring32::unpack_block_roaring32;
-use crate::compression::roaring64::unpack_block_roaring64;
+use crate::compression::BlockBuffers;
+use crate::compression::FILE_HAS_TARGET_CHUNKS;
+use crate::compression::dedup::BlockCache;
+use crate::compression::flags_dictionary::FlagsDictionary;
+use crate::compression::roaring32::unpack_block_roaring32_with_buffers;
+use crate::compression::roaring64::unpack_block_roaring64_with_buffers;
 
 use indexmap::IndexSet;
 
 use roaring::RoaringBitmap;
 use roaring::RoaringTreemap;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io::Read;
+use std::time::Instant;
 
 type E = Box<dyn std::error::Error>;
 
+static EMPTY_BLOCK_FLAGS: BlockFlags = BlockFlags{ queries: None, query_ids: None, mates: None, query_filter: None, extensions: None, unknown_fields: Vec::new() };
+
+/// Add the query ids of the set `bits` in a block to the bitmap of their target.
+fn transpose_bits<I: Iterator<Item = u64>>(
+    bits: I,
+    n_targets: u64,
+    membership: Option<&[u32]>,
+    targets: &mut [RoaringBitmap],
+) -> Result<(), E> {
+    for idx in bits {
+        let query_id = (idx / n_targets) as u32;
+        let mut target_idx = (idx % n_targets) as usize;
+        if let Some(membership) = membership {
+            target_idx = *membership.get(target_idx).ok_or(crate::errors::UnknownTargetErr{})? as usize;
+        }
+        targets.get_mut(target_idx).ok_or(crate::errors::UnknownTargetErr{})?.insert(query_id);
+    }
+    Ok(())
+}
+
+/// Blocks that a [Decoder] skipped because they could not be unpacked.
+///
+/// See [Decoder::set_skip_corrupt].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CorruptBlocks {
+    /// Indexes of the skipped blocks in the input, starting from 0.
+    pub blocks: Vec<usize>,
+    /// Number of records in the skipped blocks.
+    ///
+    /// Counted from the query ids in the block flags, or from the block
+    /// header if the flags are corrupt. Blocks without query names record 0
+    /// records in the header, so this is a lower bound.
+    pub n_records: usize,
+}
+
+/// How [Decoder] handles query ids that don't fit in `n_queries` of the [FileHeader].
+///
+/// Files written by buggy encoders can contain query ids that are not less
+/// than `n_queries`. The check is skipped for files with `n_queries` 0,
+/// which means the number of queries was not known when encoding.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum QueryIdCheck {
+    /// Increase `n_queries` in [Decoder::file_header] to fit the ids, see
+    /// [Decoder::original_n_queries].
+    #[default]
+    Extend,
+    /// Error with [QueryIndexOutOfRangeErr](crate::errors::QueryIndexOutOfRangeErr)
+    /// when reading a block with such ids.
+    Strict,
+}
+
 pub enum Bitmap {
     Roaring32(RoaringBitmap),
     Roaring64(RoaringTreemap),
 }
 
+impl Bitmap {
+    fn iter_bits(
+        &self,
+    ) -> Box<dyn Iterator<Item = u64> + '_> {
+        match self {
+            Bitmap::Roaring32(bits) => Box::new(bits.iter().map(|x| x as u64)),
+            Bitmap::Roaring64(bits) => Box::new(bits.iter()),
+        }
+    }
+}
+
+/// Unpack the bitmap and flags of the block in `bytes`.
+fn unpack_bitmap(
+    bitmap_type: &BitmapType,
+    bytes: &[u8],
+    block_header: &BlockHeader,
+    buffers: &mut BlockBuffers,
+) -> Result<(Bitmap, BlockFlags), E> {
+    Ok(match bitmap_type {
+        BitmapType::Roaring32 => {
+            let (bitmap, block_flags) = unpack_block_roaring32_with_buffers(bytes, block_header, buffers)?;
+            (Bitmap::Roaring32(bitmap), block_flags)
+        },
+        BitmapType::Roaring64 => {
+            let (bitmap, block_flags) = unpack_block_roaring64_with_buffers(bytes, block_header, buffers)?;
+            (Bitmap::Roaring64(bitmap), block_flags)
+        },
+    })
+}
+
+/// Where [Decoder] reads the blocks from.
+enum Input<'a, R: Read> {
+    Plain(&'a mut R),
+    #[cfg(feature = "zstd")]
+    Zstd(crate::compression::zstd_seekable::ContainerReader<&'a mut R>),
+}
+
+impl<R: Read> Read for Input<'_, R> {
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> std::io::Result<usize> {
+        match self {
+            Input::Plain(conn) => conn.read(buf),
+            #[cfg(feature = "zstd")]
+            Input::Zstd(conn) => conn.read(buf),
+        }
+    }
+}
+
 pub struct Decoder<'a, R: Read> {
     // Inputs
-    conn: &'a mut R,
+    conn: Input<'a, R>,
 
     header: FileHeader,
     flags: FileFlags,
@@ -170,45 +310,127 @@ pub struct Decoder<'a, R: Read> {
     block_flags: Option<BlockFlags>,
     block_index: usize,
     bitmap: Bitmap,
+    cache: BlockCache,
+    target_chunks: Option<TargetChunks>,
+    // Reused across blocks
+    block_bytes: Vec<u8>,
+    // Sub-blocks after the first one of the current block
+    sub_blocks: Vec<(BlockHeader, Vec<u8>)>,
+    buffers: BlockBuffers,
+    done: bool,
     q_ids: IndexSet<u32>,
     q_names: Option<IndexSet<Vec<u8>>>,
     t_names: IndexSet<Vec<u8>>,
+    membership: Option<Vec<u32>>,
+    query_id_check: QueryIdCheck,
+    original_n_queries: Option<u32>,
+    max_hits: Option<MaxHits>,
+    records_capped: usize,
+    skip_corrupt: bool,
+    corrupt: CorruptBlocks,
+    blocks_skipped: usize,
+    blocks_read: usize,
+    records_read: usize,
+    started: Option<Instant>,
+    error: Option<E>,
 
     // What values to fill in the records
     fill_query_id: bool,
     fill_query_name: bool,
     fill_target_ids: bool,
     fill_target_names: bool,
+    fill_mate_suffix: bool,
 }
 
 impl<'a, R: Read> Decoder<'a, R> {
+    /// Read the file header and flags from `conn` and create a decoder for the blocks.
+    ///
+    /// Panics if the header or flags can't be read, use
+    /// [try_new](Decoder::try_new) for inputs that may be invalid.
     pub fn new(
         conn: &'a mut R,
     ) -> Self {
+        Self::try_new(conn).unwrap()
+    }
 
-        let header = read_file_header(conn).unwrap();
-        let flags = read_file_flags(&header, conn).unwrap();
+    /// Same as [new](Decoder::new) but returns an error if the header or flags can't be read.
+    ///
+    /// Inputs in a zstd-seekable container, see
+    /// [FILE_IS_ZSTD_SEEKABLE](crate::compression::FILE_IS_ZSTD_SEEKABLE),
+    /// are decompressed on the fly and error with
+    /// [MissingFeatureErr](crate::errors::MissingFeatureErr) if ahda was
+    /// built without the `zstd` feature.
+    ///
+    /// The sub-blocks of inputs that split the targets of their blocks, see
+    /// [target_chunks](crate::target_chunks), are merged into one block, and
+    /// [file_header](Decoder::file_header) has the
+    /// [FILE_HAS_TARGET_CHUNKS] bit cleared.
+    pub fn try_new(
+        conn: &'a mut R,
+    ) -> Result<Self, E> {
+
+        let chunked_header = read_file_header(conn)?;
+        let header = FileHeader{ fields_present: chunked_header.fields_present & !FILE_HAS_TARGET_CHUNKS, ..chunked_header.clone() };
+        let (conn, header, flags) = if header.is_zstd_seekable() {
+            #[cfg(feature = "zstd")]
+            {
+                let header = FileHeader{ fields_present: header.fields_present & !crate::compression::FILE_IS_ZSTD_SEEKABLE, ..header };
+                let mut conn = crate::compression::zstd_seekable::container_reader(conn)?;
+                let flags = read_file_flags(&header, &mut conn)?;
+                (Input::Zstd(conn), header, flags)
+            }
+            #[cfg(not(feature = "zstd"))]
+            return Err(Box::new(crate::errors::MissingFeatureErr{ feature: "zstd" }))
+        } else {
+            let flags = read_file_flags(&header, conn)?;
+            (Input::Plain(conn), header, flags)
+        };
 
-        let bitmap = match BitmapType::from_u16(header.bitmap_type).unwrap() {
+        let target_chunks = TargetChunks::from_header_and_flags(&chunked_header, &flags)?;
+        let bitmap = match BitmapType::from_u16(header.bitmap_type)? {
             BitmapType::Roaring32 => Bitmap::Roaring32(RoaringBitmap::new()),
             BitmapType::Roaring64 => Bitmap::Roaring64(RoaringTreemap::new()),
         };
 
-        Decoder{
-            block: Vec::with_capacity(header.block_size as usize),
-            q_ids: IndexSet::with_capacity(header.block_size as usize),
-            q_names: if header.promises_query_names() { Some(IndexSet::with_capacity(header.block_size as usize)) } else { None },
+        let mut buffers = BlockBuffers::default();
+        buffers.set_flags_dictionary(FlagsDictionary::from_flags(&flags));
+
+        // `block_size` is read from the input, don't trust it for the allocation
+        let capacity = (header.block_size as usize).min(1 << 16);
+        Ok(Decoder{
+            block: Vec::with_capacity(capacity),
+            q_ids: IndexSet::with_capacity(capacity),
+            q_names: if header.promises_query_names() { Some(IndexSet::with_capacity(capacity)) } else { None },
             t_names: IndexSet::from_iter(flags.target_names.iter().cloned()),
+            membership: None,
+            query_id_check: QueryIdCheck::default(),
+            max_hits: None,
+            records_capped: 0,
+            skip_corrupt: false,
+            corrupt: CorruptBlocks::default(),
+            original_n_queries: None,
+            blocks_skipped: 0_usize,
+            blocks_read: 0_usize,
+            error: None,
+            records_read: 0_usize,
+            started: None,
             conn,
             header, flags,
             block_index: 0_usize,
             block_flags: None,
             bitmap,
+            cache: BlockCache::new(),
+            target_chunks,
+            block_bytes: Vec::new(),
+            sub_blocks: Vec::new(),
+            buffers,
+            done: false,
             fill_query_id: true,
             fill_query_name: true,
             fill_target_ids: true,
             fill_target_names: true,
-        }
+            fill_mate_suffix: true,
+        })
     }
 
     pub fn fill_query_id(
@@ -238,6 +460,108 @@ impl<'a, R: Read> Decoder<'a, R> {
     ) {
         self.fill_target_names = val;
     }
+
+    /// Append `/1` or `/2` to the query names if the blocks store read pair mates.
+    ///
+    /// Names that already end with the suffix are not changed. Enabled by
+    /// default.
+    pub fn fill_mate_suffix(
+        &mut self,
+        val: bool,
+    ) {
+        self.fill_mate_suffix = val;
+    }
+
+    /// Set how query ids that don't fit in `n_queries` are handled.
+    pub fn set_query_id_check(
+        &mut self,
+        check: QueryIdCheck,
+    ) {
+        self.query_id_check = check;
+    }
+
+    /// Cap the number of targets of the returned records, see [MaxHits].
+    ///
+    /// The cap is applied after collapsing the targets with [collapse](Decoder::collapse).
+    pub fn set_max_hits(
+        &mut self,
+        max_hits: MaxHits,
+    ) {
+        self.max_hits = Some(max_hits);
+    }
+
+    /// Number of records returned so far that exceeded the cap set with [set_max_hits](Decoder::set_max_hits).
+    pub fn records_capped(
+        &self,
+    ) -> usize {
+        self.records_capped
+    }
+
+    /// Skip blocks that can't be unpacked instead of failing.
+    ///
+    /// A block whose flags or payload fail to decompress or decode is logged
+    /// and skipped, and decoding continues from the next block header, which
+    /// starts right after the `flags_len` and `block_len` bytes of the corrupt
+    /// block. Errors in the block headers themselves and query ids out of
+    /// range still end decoding. Defaults to false.
+    pub fn set_skip_corrupt(
+        &mut self,
+        skip_corrupt: bool,
+    ) {
+        self.skip_corrupt = skip_corrupt;
+    }
+
+    /// Blocks skipped so far with [set_skip_corrupt](Decoder::set_skip_corrupt).
+    pub fn corrupt_blocks(
+        &self,
+    ) -> &CorruptBlocks {
+        &self.corrupt
+    }
+
+    /// Take the error that ended [next](Iterator::next) or [next_block](Decoder::next_block).
+    ///
+    /// Both return None on errors, so a None from them is the end of the
+    /// input only if this returns None. Iteration stops until the error is
+    /// taken. Use [try_next](Decoder::try_next) and
+    /// [try_next_block](Decoder::try_next_block) to get the errors directly.
+    pub fn take_error(
+        &mut self,
+    ) -> Option<E> {
+        self.error.take()
+    }
+
+    /// `n_queries` stored in the file if it has been extended to fit the query ids.
+    ///
+    /// Returns None if all query ids decoded so far fit in `n_queries`.
+    pub fn original_n_queries(
+        &self,
+    ) -> Option<u32> {
+        self.original_n_queries
+    }
+
+    /// Collapse the alignment targets to the groups at `level`.
+    ///
+    /// Records returned after calling this refer to the group indices and
+    /// names in [TargetGroups](crate::headers::file::TargetGroups) for
+    /// `level`. A query aligns to a group if it aligns to any target in the
+    /// group.
+    ///
+    /// Returns the [FileHeader] and [FileFlags] describing the collapsed
+    /// records, see [collapse_file_header_and_flags].
+    ///
+    pub fn collapse(
+        &mut self,
+        level: &[u8],
+    ) -> Result<(FileHeader, FileFlags), E> {
+        let (header, flags) = collapse_file_header_and_flags(&self.header, &self.flags, level)?;
+        let groups = self.flags.target_group(level).ok_or(crate::errors::UnknownTargetGroupErr{})?;
+        groups.validate(self.header.n_targets as usize)?;
+
+        self.membership = Some(groups.membership.clone());
+        self.t_names = IndexSet::from_iter(flags.target_names.iter().cloned());
+
+        Ok((header, flags))
+    }
 }
 
 impl<R: Read> Decoder<'_, R> {
@@ -245,23 +569,15 @@ impl<R: Read> Decoder<'_, R> {
     fn alns_from_set_bits(
         &mut self,
     ) -> Result<(), E> {
-        let mut it: Box<dyn Iterator<Item=u64>> = match &mut self.bitmap {
-            Bitmap::Roaring32(bits) => {
-                let tmp = bits.iter().map(|x| x as u64);
-                Box::new(tmp)
-            },
-            Bitmap::Roaring64(bits) => {
-                let tmp = bits.iter();
-                Box::new(tmp)
-            },
+        self.block = match &self.bitmap {
+            Bitmap::Roaring32(bits) => bitmap_decoder::decode_roaring32(bits, self.header.n_targets),
+            Bitmap::Roaring64(bits) => bitmap_decoder::decode_roaring64(bits, self.header.n_targets),
         };
 
-        let bitmap_decoder = bitmap_decoder::BitmapDecoder::new(&mut it, self.header.clone());
-        self.block = bitmap_decoder.collect();
-
-        let query_ids = self.block_flags.as_ref().unwrap().query_ids.as_ref().unwrap();
-        let query_names = self.block_flags.as_ref().unwrap().queries.as_ref().unwrap();
-        let seen: HashSet<u32> = HashSet::from_iter(self.block.iter().map(|x| x.query_id.unwrap()));
+        let block_flags = self.block_flags.as_ref().ok_or(crate::errors::MissingBlockFieldsErr{})?;
+        let query_ids = block_flags.query_ids.as_ref().ok_or(crate::errors::MissingBlockFieldsErr{})?;
+        let query_names = block_flags.queries.as_ref().ok_or(crate::errors::MissingBlockFieldsErr{})?;
+        let seen: HashSet<u32> = HashSet::from_iter(self.block.iter().filter_map(|x| x.query_id));
         self.block.extend(query_ids.iter().filter_map(|idx| {
             if !seen.contains(idx) {
                 Some(PseudoAln{ ones_names: None, query_id: Some(*idx), ones: Some(vec![]), query_name: None })
@@ -289,38 +605,169 @@ impl<R: Read> Decoder<'_, R> {
         &self.flags
     }
 
+    /// How the input splits the targets of its blocks, None if it doesn't, see [target_chunks](crate::target_chunks).
+    pub fn target_chunks(
+        &self,
+    ) -> Option<&TargetChunks> {
+        self.target_chunks.as_ref()
+    }
+
+    /// Read the next block into `block_bytes` and the rest of its sub-blocks into `sub_blocks`.
+    ///
+    /// Returns `Ok(None)` at the end of the input.
+    fn try_read_block(
+        &mut self,
+    ) -> Result<Option<BlockHeader>, E> {
+        let Some(block_header) = self.cache.try_read_block_into(&mut self.conn, &mut self.block_bytes)? else {
+            return Ok(None)
+        };
+        if let Some(target_chunks) = self.target_chunks {
+            let mut buffers = std::mem::take(&mut self.sub_blocks).into_iter().map(|(_, bytes)| bytes);
+            for _ in 1..target_chunks.n_chunks() {
+                let mut bytes = buffers.next().unwrap_or_default();
+                let sub_block_header = self.cache.try_read_block_into(&mut self.conn, &mut bytes)?.ok_or(crate::errors::TruncatedBlockErr{})?;
+                self.sub_blocks.push((sub_block_header, bytes));
+            }
+        }
+        Ok(Some(block_header))
+    }
+
     /// Read next block and update internal state.
+    ///
+    /// Returns None at the end of the input and if the block can't be read,
+    /// see [take_error](Decoder::take_error).
     pub fn next_block(
         &mut self,
     ) -> Option<()> {
+        if self.error.is_some() {
+            return None
+        }
+        self.try_next_block().unwrap_or_else(|e| {
+            log::warn!("can't read block {}: {}", self.blocks_read, e);
+            self.error = Some(e);
+            None
+        })
+    }
+
+    /// Read next block and update internal state.
+    ///
+    /// Returns `Ok(None)` at the end of the input and an error if the next
+    /// block can't be read.
+    pub fn try_next_block(
+        &mut self,
+    ) -> Result<Option<()>, E> {
         self.block.clear();
         self.q_ids.clear();
         self.q_names = if self.header.promises_query_names() { Some(IndexSet::new()) } else { None };
-        match read_block_header(self.conn) {
-            Ok(block_header) => {
-                for i in 0..16 {
-                    if (self.header.fields_present & (1 << i)) != 0 {
-                        assert!((block_header.fields_present & (1 << i)) != 0);
-                    }
+        let mut block_start = Instant::now();
+        loop {
+            let Some(block_header) = self.try_read_block()? else {
+                self.block_flags = None;
+                if let Some(started) = self.started.take() {
+                    let secs = started.elapsed().as_secs_f64();
+                    log::info!("decoded {} records in {} blocks in {:.2}s ({:.0} records/s)", self.records_read, self.blocks_read, secs, self.records_read as f64 / secs.max(f64::MIN_POSITIVE));
                 }
-                let deflated_len: usize = ((block_header.flags_len) + (block_header.block_len as u64)).try_into().unwrap();
-                let mut bytes: Vec<u8> = vec![0; deflated_len];
-                self.conn.read_exact(&mut bytes).unwrap();
-                match BitmapType::from_u16(self.header.bitmap_type).unwrap() {
-                    BitmapType::Roaring32 => {
-                        let (bitmap, block_flags) = unpack_block_roaring32(&bytes, &block_header).unwrap();
-                        self.bitmap = Bitmap::Roaring32(bitmap);
-                        self.block_flags = Some(block_flags);
-                    },
-                    BitmapType::Roaring64 => {
-                        let (bitmap, block_flags) = unpack_block_roaring64(&bytes, &block_header).unwrap();
-                        self.bitmap = Bitmap::Roaring64(bitmap);
-                        self.block_flags = Some(block_flags);
+                return Ok(None)
+            };
+            self.started.get_or_insert(block_start);
+            match self.unpack_block(&block_header) {
+                Err(e) if self.skip_corrupt => {
+                    // Blocks without query names don't record their size in the header
+                    let flags_bytes = self.block_bytes.get(..(block_header.flags_len as usize)).unwrap_or_default();
+                    let n_records = self.buffers.decode_flags(flags_bytes, &block_header).ok()
+                        .and_then(|x| x.query_ids.map(|ids| ids.len()))
+                        .unwrap_or(block_header.num_records as usize);
+                    log::warn!("skipping corrupt block {} with {} records: {}", self.blocks_read, n_records, e);
+                    self.block_flags = None;
+                    self.corrupt.blocks.push(self.blocks_read);
+                    self.corrupt.n_records += n_records;
+                    self.blocks_read += 1;
+                    block_start = Instant::now();
+                },
+                res => break res?,
+            }
+        }
+        self.check_query_ids()?;
+
+        let n_records = self.block_flags.as_ref().and_then(|x| x.query_ids.as_ref()).map_or(0, |x| x.len());
+        log::debug!("decoded block {} with {} records in {:.3}s", self.blocks_read, n_records, block_start.elapsed().as_secs_f64());
+        self.blocks_read += 1;
+        self.records_read += n_records;
+        Ok(Some(()))
+    }
+
+    /// Unpack the block in `block_bytes` and check its query ids.
+    fn load_block(
+        &mut self,
+        block_header: &BlockHeader,
+    ) -> Result<(), E> {
+        self.unpack_block(block_header)?;
+        self.check_query_ids()
+    }
+
+    /// Unpack the block in `block_bytes` and check its query filter.
+    ///
+    /// The sub-blocks in `sub_blocks` are merged into one bitmap of the whole
+    /// block, which must store the same queries as the first sub-block.
+    fn unpack_block(
+        &mut self,
+        block_header: &BlockHeader,
+    ) -> Result<(), E> {
+        let mut sub_block_headers = std::iter::once(block_header).chain(self.sub_blocks.iter().map(|(header, _)| header));
+        if sub_block_headers.any(|x| (self.header.fields_present & x.fields_present) != self.header.fields_present) {
+            return Err(Box::new(crate::errors::MissingBlockFieldsErr{}))
+        }
+        let bitmap_type = BitmapType::from_u16(self.header.bitmap_type)?;
+        let (bitmap, block_flags) = unpack_bitmap(&bitmap_type, &self.block_bytes, block_header, &mut self.buffers)?;
+        self.bitmap = match self.target_chunks {
+            Some(target_chunks) => {
+                let mut bits = RoaringTreemap::new();
+                target_chunks.merge_bits(0, bitmap.iter_bits(), &mut bits)?;
+                for (chunk, (sub_block_header, bytes)) in self.sub_blocks.iter().enumerate() {
+                    let (bitmap, sub_block_flags) = unpack_bitmap(&bitmap_type, bytes, sub_block_header, &mut self.buffers)?;
+                    if sub_block_flags.query_ids != block_flags.query_ids {
+                        return Err(Box::new(crate::errors::InvalidTargetChunksErr{}))
                     }
+                    target_chunks.merge_bits(chunk + 1, bitmap.iter_bits(), &mut bits)?;
                 }
-                Some(())
+                Bitmap::Roaring64(bits)
             },
-            _ => None,
+            None => bitmap,
+        };
+        self.block_flags = Some(block_flags);
+        self.check_query_filter()
+    }
+
+    fn check_query_filter(
+        &self,
+    ) -> Result<(), E> {
+        let Some(block_flags) = &self.block_flags else {
+            return Ok(())
+        };
+        let all_contained = match (&block_flags.query_filter, &block_flags.queries) {
+            (Some(filter), Some(queries)) => queries.iter().all(|name| filter.contains(name)),
+            _ => true,
+        };
+        if !all_contained {
+            return Err(Box::new(crate::errors::QueryFilterMismatchErr{}))
+        }
+        Ok(())
+    }
+
+    fn check_query_ids(
+        &mut self,
+    ) -> Result<(), E> {
+        let max_id = self.block_flags.as_ref().and_then(|x| x.query_ids.as_ref()).and_then(|x| x.iter().max().copied());
+        let Some(max_id) = max_id.filter(|x| self.header.n_queries > 0 && *x >= self.header.n_queries) else {
+            return Ok(())
+        };
+        match self.query_id_check {
+            QueryIdCheck::Extend => {
+                self.original_n_queries.get_or_insert(self.header.n_queries);
+                self.header.n_queries = max_id.checked_add(1).ok_or(crate::errors::QueryIndexOutOfRangeErr{})?;
+                Ok(())
+            },
+            QueryIdCheck::Strict => Err(Box::new(crate::errors::QueryIndexOutOfRangeErr{})),
         }
     }
 
@@ -352,76 +799,589 @@ impl<R: Read> Decoder<'_, R> {
         &self.bitmap
     }
 
-    /// Get bitmap in the current block, use [next_block] to advance.
+    /// Get block flags in the current block, use [next_block] to advance.
+    ///
+    /// Returns empty flags if no block has been read, eg. if the input has no
+    /// records.
     pub fn block_flags(
         &self,
     ) -> &BlockFlags {
-        self.block_flags.as_ref().unwrap()
+        self.block_flags.as_ref().unwrap_or(&EMPTY_BLOCK_FLAGS)
+    }
+
+    fn collapse_record(
+        &self,
+        record: &mut PseudoAln,
+    ) -> Result<(), E> {
+        if let (Some(membership), Some(ones)) = (&self.membership, record.ones.as_mut()) {
+            let mut groups = ones.iter().map(|target_idx| {
+                Ok(*membership.get(*target_idx as usize).ok_or(crate::errors::UnknownTargetErr{})?)
+            }).collect::<Result<Vec<u32>, E>>()?;
+            groups.sort_unstable();
+            groups.dedup();
+            *ones = groups;
+        }
+        Ok(())
     }
 
     fn fill_record(
         &self,
         record: &mut PseudoAln,
-    ) {
+    ) -> Result<(), E> {
         if record.query_id.is_none() && self.fill_query_id {
             // This branch shouldn't be reachable for a valid .ahda file
-            assert!(record.query_name.is_some());
-            assert!(self.q_names.is_some());
-            let key: Vec<u8> = record.query_name.as_ref().unwrap().to_vec();
-            let index = self.q_names.as_ref().unwrap().get_index_of(&key).unwrap();
-            let query_id = self.q_ids.get_index(index).unwrap();
+            let key = record.query_name.as_ref().ok_or(crate::errors::UnknownQueryErr{})?;
+            let index = self.q_names.as_ref().and_then(|q_names| q_names.get_index_of(key)).ok_or(crate::errors::UnknownQueryErr{})?;
+            let query_id = self.q_ids.get_index(index).ok_or(crate::errors::UnknownQueryErr{})?;
             record.query_id = Some(*query_id);
         }
 
         if record.query_name.is_none() && self.fill_query_name {
-            let key: u32 = record.query_id.unwrap();
-            let index = self.q_ids.get_index_of(&key).unwrap();
-            let query_name = if let Some(q_names) = &self.q_names {
-                q_names.get_index(index).unwrap().clone()
+            let key: u32 = record.query_id.ok_or(crate::errors::UnknownQueryErr{})?;
+            let index = self.q_ids.get_index_of(&key).ok_or(crate::errors::UnknownQueryErr{})?;
+            let mut query_name = if let Some(q_names) = &self.q_names {
+                q_names.get_index(index).ok_or(crate::errors::UnknownQueryErr{})?.clone()
             } else {
                 let mut new_name = self.flags.query_name.clone();
                 new_name.append(&mut vec![b'.']);
                 new_name.append(&mut (key + 1).to_string().as_bytes().to_vec());
                 new_name
             };
+            if let Some(mate) = self.block_flags().mate(index).filter(|_| self.fill_mate_suffix) {
+                let suffix = [b'/', b'0' + mate];
+                if !query_name.ends_with(&suffix) {
+                    query_name.extend_from_slice(&suffix);
+                }
+            }
             record.query_name = Some(query_name);
         }
 
         if record.ones_names.is_none() && self.fill_target_names {
-            let ones_names = record.ones.as_ref().unwrap().iter().map(|target_idx| {
+            let ones_names = record.ones.as_ref().ok_or(crate::errors::UnknownTargetErr{})?.iter().map(|target_idx| {
                 let key = *target_idx as usize;
-                self.t_names.get_index(key).unwrap().clone()
-            }).collect::<Vec<Vec<u8>>>();
+                Ok(self.t_names.get_index(key).ok_or(crate::errors::UnknownTargetErr{})?.clone())
+            }).collect::<Result<Vec<Vec<u8>>, E>>()?;
             record.ones_names = Some(ones_names);
         }
 
         if record.ones.is_none() && self.fill_target_ids {
-            let ones = record.ones_names.as_ref().unwrap().iter().map(|key| {
-                self.t_names.get_index_of(key).unwrap() as u32
-            }).collect::<Vec<u32>>();
+            let ones = record.ones_names.as_ref().ok_or(crate::errors::UnknownTargetErr{})?.iter().map(|key| {
+                Ok(self.t_names.get_index_of(key).ok_or(crate::errors::UnknownTargetErr{})? as u32)
+            }).collect::<Result<Vec<u32>, E>>()?;
             record.ones = Some(ones);
         }
+
+        Ok(())
+    }
+
+    /// Decode the next record, returning an error if it can't be read.
+    ///
+    /// Returns `None` at the end of the input. A record that can't be filled
+    /// is returned as an error and decoding can continue from the next record,
+    /// but errors from reading a block end the iteration.
+    pub fn try_next(
+        &mut self,
+    ) -> Option<Result<PseudoAln, E>> {
+        while self.block_index >= self.block.len() {
+            if self.done {
+                return None
+            }
+            let next_block = self.try_next_block().and_then(|next| {
+                if next.is_some() {
+                    self.alns_from_set_bits()?;
+                }
+                Ok(next)
+            });
+            match next_block {
+                Ok(Some(())) => self.block_index = 0,
+                Ok(None) => return None,
+                Err(e) => {
+                    // Don't try to continue from a broken stream
+                    self.block.clear();
+                    self.block_index = 0;
+                    self.done = true;
+                    return Some(Err(e))
+                },
+            }
+        }
+
+        self.block_index += 1;
+        let mut ret = self.block[self.block_index - 1].clone();
+        Some(self.collapse_record(&mut ret).and_then(|_| self.fill_record(&mut ret)).map(|_| {
+            if self.max_hits.is_some_and(|x| x.apply(&mut ret)) {
+                self.records_capped += 1;
+            }
+            ret
+        }))
+    }
+
+    /// Iterate over the records returning an error for records that can't be decoded.
+    pub fn try_iter<'b>(
+        &'b mut self,
+    ) -> Box<dyn Iterator<Item=Result<PseudoAln, E>> + 'b> {
+        Box::new(std::iter::from_fn(move || self.try_next()))
+    }
+
+    /// Find the records of the queries named in `names`.
+    ///
+    /// Reads the remaining records, including records of the current block
+    /// that were not returned yet. Blocks with a query filter, see
+    /// [BlockFlags::might_contain], that rules out all `names` are skipped
+    /// without decompressing their contents. The names are matched against
+    /// the stored query names, ie. without the `/1` or `/2` mate suffix.
+    ///
+    /// Errors with [MissingQueryNamesErr](crate::errors::MissingQueryNamesErr)
+    /// if the input does not store the query names, and with
+    /// [QueryFilterMismatchErr](crate::errors::QueryFilterMismatchErr) if the
+    /// filter of a decompressed block does not contain all of its query names.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::encode_to_write;
+    /// use ahda::EncodeOpts;
+    /// use ahda::PseudoAln;
+    /// use ahda::decoder::Decoder;
+    /// use std::collections::HashSet;
+    /// use std::io::Cursor;
+    ///
+    /// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+    /// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+    /// let data = vec![
+    ///     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some(b"r1".to_vec()) },
+    ///     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: Some(b"r2".to_vec()) },
+    ///     PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![]), query_name: Some(b"r3".to_vec()) },
+    /// ];
+    ///
+    /// let mut opts = EncodeOpts::default();
+    /// opts.encode_query_names = true;
+    /// opts.query_filter_fpr = Some(0.01);
+    /// let mut bytes: Vec<u8> = Vec::new();
+    /// encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
+    ///
+    /// let mut cursor = Cursor::new(bytes);
+    /// let mut decoder = Decoder::new(&mut cursor);
+    /// let names = HashSet::from([b"r2".to_vec()]);
+    /// let got = decoder.grep(&names).unwrap();
+    ///
+    /// assert_eq!(got.len(), 1);
+    /// assert_eq!(got[0].ones_names, Some(vec![b"plasmid.fasta".to_vec()]));
+    /// ```
+    ///
+    pub fn grep(
+        &mut self,
+        names: &HashSet<Vec<u8>>,
+    ) -> Result<Vec<PseudoAln>, E> {
+        if !self.header.promises_query_names() {
+            return Err(Box::new(crate::errors::MissingQueryNamesErr{}))
+        }
+
+        let mut found: Vec<PseudoAln> = Vec::new();
+        loop {
+            while self.block_index < self.block.len() {
+                self.block_index += 1;
+                let mut record = self.block[self.block_index - 1].clone();
+                let index = record.query_id.and_then(|query_id| self.q_ids.get_index_of(&query_id));
+                let name = index.and_then(|idx| self.q_names.as_ref()?.get_index(idx));
+                if name.is_some_and(|name| names.contains(name)) {
+                    self.collapse_record(&mut record)?;
+                    self.fill_record(&mut record)?;
+                    found.push(record);
+                }
+            }
+            if self.done {
+                break
+            }
+
+            self.block.clear();
+            self.block_index = 0;
+            let Some(block_header) = self.try_read_block()? else {
+                self.block_flags = None;
+                break
+            };
+            let flags_len: usize = block_header.flags_len.try_into()?;
+            let flags_bytes = self.block_bytes.get(..flags_len).ok_or(crate::errors::TruncatedBlockErr{})?;
+            let block_flags = self.buffers.decode_flags(flags_bytes, &block_header)?;
+            if !names.iter().any(|name| block_flags.might_contain(name)) {
+                self.blocks_skipped += 1;
+                continue
+            }
+            self.load_block(&block_header)?;
+            self.alns_from_set_bits()?;
+            self.blocks_read += 1;
+        }
+        log::info!("found {} records, skipped {} blocks using their query filters", found.len(), self.blocks_skipped);
+
+        Ok(found)
+    }
+
+    /// Find the records that aligned to any of the target ids in `targets`.
+    ///
+    /// Reads the remaining blocks and returns the matching records with
+    /// their alignments to all targets. Blocks whose
+    /// [TargetSummary](crate::target_summary::TargetSummary) has no hits to
+    /// `targets` are skipped without decompressing their contents. Blocks
+    /// encoded without a summary are always decompressed.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::PseudoAln;
+    /// use ahda::decoder::Decoder;
+    /// use ahda::encoder::Encoder;
+    /// use roaring::RoaringBitmap;
+    /// use std::io::Cursor;
+    ///
+    /// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+    /// let data: Vec<PseudoAln> = (0..6).map(|id| {
+    ///     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![if id < 3 { 0 } else { 2 }]), query_name: None }
+    /// }).collect();
+    ///
+    /// let mut records = data.into_iter();
+    /// let mut encoder = Encoder::new(&mut records, &targets, b"sample", 6);
+    /// encoder.set_block_size(3).unwrap();
+    /// encoder.set_target_summary();
+    /// let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+    /// encoder.for_each(|block| bytes.extend(block.unwrap()));
+    ///
+    /// let mut cursor = Cursor::new(bytes);
+    /// let mut decoder = Decoder::new(&mut cursor);
+    /// let got = decoder.slice(&RoaringBitmap::from_iter([2])).unwrap();
+    ///
+    /// assert_eq!(got.iter().map(|x| x.query_id.unwrap()).collect::<Vec<u32>>(), vec![3, 4, 5]);
+    /// assert_eq!(decoder.blocks_skipped(), 1);
+    /// ```
+    ///
+    /// ## Errors and panics
+    /// Errors if a block or its [TargetSummary](crate::target_summary::TargetSummary)
+    /// can't be read.
+    ///
+    pub fn slice(
+        &mut self,
+        targets: &RoaringBitmap,
+    ) -> Result<Vec<PseudoAln>, E> {
+        let mut found: Vec<PseudoAln> = Vec::new();
+        loop {
+            while self.block_index < self.block.len() {
+                self.block_index += 1;
+                let mut record = self.block[self.block_index - 1].clone();
+                if record.ones.as_ref().is_some_and(|ones| ones.iter().any(|x| targets.contains(*x))) {
+                    self.collapse_record(&mut record)?;
+                    self.fill_record(&mut record)?;
+                    found.push(record);
+                }
+            }
+            if self.done {
+                break
+            }
+
+            self.block.clear();
+            self.block_index = 0;
+            let Some(block_header) = self.try_read_block()? else {
+                self.block_flags = None;
+                break
+            };
+            let flags_len: usize = block_header.flags_len.try_into()?;
+            let flags_bytes = self.block_bytes.get(..flags_len).ok_or(crate::errors::TruncatedBlockErr{})?;
+            let block_flags = self.buffers.decode_flags(flags_bytes, &block_header)?;
+            if TargetSummary::from_block_flags(&block_flags)?.is_some_and(|summary| !summary.contains_any(targets)) {
+                self.blocks_skipped += 1;
+                continue
+            }
+            self.load_block(&block_header)?;
+            self.alns_from_set_bits()?;
+            self.blocks_read += 1;
+        }
+        log::info!("found {} records, skipped {} blocks using their target summaries", found.len(), self.blocks_skipped);
+
+        Ok(found)
+    }
+
+    /// Pick `n` of the remaining records uniformly at random.
+    ///
+    /// Reads the remaining blocks with reservoir sampling, see Li (1994)
+    /// <https://doi.org/10.1145/198429.198435>. The number of records in
+    /// each block is read from its [BlockFlags], and blocks that none of the
+    /// picked records fall in are skipped without decompressing their
+    /// contents. Returns all remaining records if there are at most `n`.
+    ///
+    /// The records are returned in the order they are stored in. The same
+    /// `seed` picks the same records from the same input.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::PseudoAln;
+    /// use ahda::decoder::Decoder;
+    /// use ahda::encoder::Encoder;
+    /// use std::io::Cursor;
+    ///
+    /// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+    /// let data: Vec<PseudoAln> = (0..1000).map(|id| {
+    ///     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+    /// }).collect();
+    ///
+    /// let mut records = data.into_iter();
+    /// let mut encoder = Encoder::new(&mut records, &targets, b"sample", 1000);
+    /// encoder.set_block_size(10).unwrap();
+    /// let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+    /// encoder.for_each(|block| bytes.extend(block.unwrap()));
+    ///
+    /// let mut cursor = Cursor::new(bytes);
+    /// let mut decoder = Decoder::new(&mut cursor);
+    /// let got = decoder.sample(5, 42).unwrap();
+    ///
+    /// assert_eq!(got.len(), 5);
+    /// assert!(got.windows(2).all(|x| x[0].query_id < x[1].query_id));
+    /// // About 5 * ln(1000 / 5) records replace an earlier pick
+    /// assert!(decoder.blocks_skipped() > 50);
+    /// ```
+    ///
+    /// ## Errors and panics
+    /// Errors with [MissingBlockFieldsErr](crate::errors::MissingBlockFieldsErr)
+    /// if a block has no query ids, and if a block can't be read.
+    ///
+    pub fn sample(
+        &mut self,
+        n: usize,
+        seed: u64,
+    ) -> Result<Vec<PseudoAln>, E> {
+        if n == 0 {
+            return Ok(Vec::new())
+        }
+        let mut state = seed;
+        // Picked records and their positions in the input
+        let mut picked: Vec<(usize, PseudoAln)> = Vec::with_capacity(n);
+        // Position of the next record to pick once `picked` is full, and
+        // the largest of the random keys of the picked records
+        let mut next: usize = usize::MAX;
+        let mut weight: f64 = 1.0;
+        let mut position: usize = 0;
+        loop {
+            while self.block_index < self.block.len() {
+                self.block_index += 1;
+                if picked.len() < n || position == next {
+                    let mut record = self.block[self.block_index - 1].clone();
+                    self.collapse_record(&mut record)?;
+                    self.fill_record(&mut record)?;
+                    if picked.len() < n {
+                        picked.push((position, record));
+                    } else {
+                        let slot = (next_f64(&mut state) * n as f64) as usize;
+                        picked[slot.min(n - 1)] = (position, record);
+                    }
+                    if picked.len() == n {
+                        weight *= ((1.0 - next_f64(&mut state)).ln() / n as f64).exp();
+                        let skip = ((1.0 - next_f64(&mut state)).ln() / (1.0 - weight).ln()).floor();
+                        next = position.saturating_add(skip.min(usize::MAX as f64) as usize).saturating_add(1);
+                    }
+                }
+                position += 1;
+            }
+            if self.done {
+                break
+            }
+
+            self.block.clear();
+            self.block_index = 0;
+            let Some(block_header) = self.try_read_block()? else {
+                self.block_flags = None;
+                break
+            };
+            let flags_len: usize = block_header.flags_len.try_into()?;
+            let flags_bytes = self.block_bytes.get(..flags_len).ok_or(crate::errors::TruncatedBlockErr{})?;
+            let block_flags = self.buffers.decode_flags(flags_bytes, &block_header)?;
+            let n_records = block_flags.query_ids.as_ref().ok_or(crate::errors::MissingBlockFieldsErr{})?.len();
+            if picked.len() == n && next >= position + n_records {
+                position += n_records;
+                self.blocks_skipped += 1;
+                continue
+            }
+            self.load_block(&block_header)?;
+            self.alns_from_set_bits()?;
+            self.blocks_read += 1;
+        }
+        log::info!("picked {} of {} records, skipped {} blocks", picked.len(), position, self.blocks_skipped);
+
+        picked.sort_by_key(|(position, _)| *position);
+        Ok(picked.into_iter().map(|(_, record)| record).collect())
+    }
+
+    /// Number of blocks that [grep](Decoder::grep), [slice](Decoder::slice), or [sample](Decoder::sample) skipped without decompressing them.
+    pub fn blocks_skipped(
+        &self,
+    ) -> usize {
+        self.blocks_skipped
+    }
+
+    /// Iterate over the targets and the query ids that aligned to them.
+    ///
+    /// Reads the remaining blocks and transposes each block into one
+    /// [RoaringBitmap] of query ids per target, so only the bitmaps are held
+    /// in memory. Records of the current block that were not returned yet are
+    /// included. Targets are returned in order of their id, including targets
+    /// that no query aligned to. If [collapse](Decoder::collapse) has been
+    /// called, the ids refer to the target groups instead.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::encode_to_write;
+    /// use ahda::EncodeOpts;
+    /// use ahda::PseudoAln;
+    /// use ahda::decoder::Decoder;
+    /// use roaring::RoaringBitmap;
+    /// use std::io::Cursor;
+    ///
+    /// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+    /// let data = vec![
+    ///     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: None },
+    ///     PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![1]), query_name: None },
+    /// ];
+    /// let mut bytes: Vec<u8> = Vec::new();
+    /// encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+    ///
+    /// let mut cursor = Cursor::new(bytes);
+    /// let mut decoder = Decoder::new(&mut cursor);
+    /// let got: Vec<(u32, RoaringBitmap)> = decoder.iter_targets().unwrap().collect();
+    ///
+    /// assert_eq!(got, vec![(0, RoaringBitmap::from([0])), (1, RoaringBitmap::from([0, 2]))]);
+    /// ```
+    ///
+    /// ## Errors and panics
+    /// Errors if a block can't be read or refers to a target that is not in
+    /// the file header.
+    ///
+    pub fn iter_targets(
+        &mut self,
+    ) -> Result<impl Iterator<Item = (u32, RoaringBitmap)>, E> {
+        let targets = self.transpose_remaining(None)?;
+        Ok(targets.into_iter().enumerate().map(|(target_idx, queries)| (target_idx as u32, queries)))
+    }
+
+    /// Iterate over the targets and the query ids that aligned to them, with the query names.
+    ///
+    /// Same as [iter_targets](Decoder::iter_targets), but also returns the
+    /// names of the queries in the remaining blocks by their query id.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::encode_to_write;
+    /// use ahda::EncodeOpts;
+    /// use ahda::PseudoAln;
+    /// use ahda::decoder::Decoder;
+    /// use std::io::Cursor;
+    ///
+    /// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+    /// let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+    /// let data = vec![
+    ///     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![1]), query_name: Some(b"r1".to_vec()) },
+    ///     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: Some(b"r2".to_vec()) },
+    /// ];
+    /// let mut opts = EncodeOpts::default();
+    /// opts.encode_query_names = true;
+    /// let mut bytes: Vec<u8> = Vec::new();
+    /// encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
+    ///
+    /// let mut cursor = Cursor::new(bytes);
+    /// let mut decoder = Decoder::new(&mut cursor);
+    /// let (mut targets, names) = decoder.iter_targets_with_query_names().unwrap();
+    ///
+    /// let (_, plasmid) = targets.nth(1).unwrap();
+    /// let got: Vec<&[u8]> = plasmid.iter().map(|id| names[&id].as_slice()).collect();
+    /// assert_eq!(got, vec![b"r1", b"r2"]);
+    /// ```
+    ///
+    /// ## Errors and panics
+    /// Errors with [MissingQueryNamesErr](crate::errors::MissingQueryNamesErr)
+    /// if the record does not store the query names and otherwise as
+    /// [iter_targets](Decoder::iter_targets).
+    ///
+    #[allow(clippy::type_complexity)]
+    pub fn iter_targets_with_query_names(
+        &mut self,
+    ) -> Result<(impl Iterator<Item = (u32, RoaringBitmap)>, HashMap<u32, Vec<u8>>), E> {
+        if !self.header.promises_query_names() {
+            return Err(Box::new(crate::errors::MissingQueryNamesErr{}))
+        }
+        let mut names: HashMap<u32, Vec<u8>> = HashMap::new();
+        let targets = self.transpose_remaining(Some(&mut names))?;
+        Ok((targets.into_iter().enumerate().map(|(target_idx, queries)| (target_idx as u32, queries)), names))
     }
 
+    /// Transposes the remaining records into one bitmap of query ids per target.
+    ///
+    /// Stores the query names of the blocks in `names` if it is given.
+    fn transpose_remaining(
+        &mut self,
+        mut names: Option<&mut HashMap<u32, Vec<u8>>>,
+    ) -> Result<Vec<RoaringBitmap>, E> {
+        let mut targets: Vec<RoaringBitmap> = vec![RoaringBitmap::new(); self.t_names.len()];
+        let n_targets = self.header.n_targets as u64;
+        let membership = self.membership.clone();
+
+        let block = std::mem::take(&mut self.block);
+        let remaining = &block[self.block_index.min(block.len())..];
+        self.block_index = 0;
+        let bits = remaining.iter().filter_map(|record| Some((record.query_id?, record.ones.as_ref()?))).flat_map(|(query_id, ones)| {
+            ones.iter().map(move |target_idx| query_id as u64 * n_targets + *target_idx as u64)
+        });
+        transpose_bits(bits, n_targets, membership.as_deref(), &mut targets)?;
+        if let Some(names) = names.as_deref_mut() {
+            insert_block_query_names(self.block_flags.as_ref(), names);
+        }
+
+        while !self.done && self.try_next_block()?.is_some() {
+            match &self.bitmap {
+                Bitmap::Roaring32(bits) => transpose_bits(bits.iter().map(|x| x as u64), n_targets, membership.as_deref(), &mut targets)?,
+                Bitmap::Roaring64(bits) => transpose_bits(bits.iter(), n_targets, membership.as_deref(), &mut targets)?,
+            }
+            if let Some(names) = names.as_deref_mut() {
+                insert_block_query_names(self.block_flags.as_ref(), names);
+            }
+        }
+        self.done = true;
+
+        Ok(targets)
+    }
+
+}
+
+/// Adds the query names in `block_flags` to `names` by their query id.
+fn insert_block_query_names(
+    block_flags: Option<&BlockFlags>,
+    names: &mut HashMap<u32, Vec<u8>>,
+) {
+    if let Some(BlockFlags{ queries: Some(queries), query_ids: Some(query_ids), .. }) = block_flags {
+        names.extend(query_ids.iter().copied().zip(queries.iter().cloned()));
+    }
+}
+
+// splitmix64, returns a value in [0, 1)
+fn next_f64(
+    state: &mut u64,
+) -> f64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1_u64 << 53) as f64
 }
 
 impl<R: Read> Iterator for Decoder<'_, R> {
     type Item = PseudoAln;
 
+    /// Decode the next record.
+    ///
+    /// Returns None at the end of the input and if a record can't be
+    /// decoded, see [take_error](Decoder::take_error).
     fn next(
         &mut self,
     ) -> Option<Self::Item> {
-        if self.block_index < self.block.len() {
-            self.block_index += 1;
-            let mut ret = self.block[self.block_index - 1].clone();
-            self.fill_record(&mut ret);
-            Some(ret)
-        } else {
-            self.next_block()?;
-            self.alns_from_set_bits();
-            self.block_index = 0;
-            self.next()
+        if self.error.is_some() {
+            return None
         }
+        self.try_next()?.map_err(|e| {
+            log::warn!("stopped decoding after {} blocks: {}", self.blocks_read, e);
+            self.error = Some(e);
+        }).ok()
     }
 }
 
@@ -440,7 +1400,7 @@ mod tests {
 
         use std::io::Cursor;
 
-        let expected_flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()] };
+        let expected_flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], ..Default::default() };
         let expected_header = FileHeader {
             ahda_header: build_ahda_header(),
             file_format: AhdaFormatVersion::V1_0_0.to_u8(),
@@ -453,6 +1413,7 @@ mod tests {
             flags_len: 36_u64,
         };
 
+        // A V1_0_0 file, which must still decode
         let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_bytes);
 
@@ -547,4 +1508,358 @@ mod tests {
 
         assert_eq!(got, expected);
     }
+
+    #[test]
+    fn try_iter_reports_truncated_input() {
+        use super::Decoder;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let data = vec![
+            PseudoAln{ones_names: None,  query_id: Some(0), ones: Some(vec![0]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(1), ones: Some(vec![1]), query_name: None },
+        ];
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+        bytes.pop();
+
+        let mut cursor = Cursor::new(bytes.clone());
+        let mut decoder = Decoder::new(&mut cursor);
+        let got: Vec<_> = decoder.try_iter().collect();
+
+        assert_eq!(got.len(), 1);
+        assert!(got[0].is_err());
+        assert!(decoder.try_next().is_none());
+
+        // The iterator keeps the error
+        let mut cursor = Cursor::new(bytes.clone());
+        let mut decoder = Decoder::new(&mut cursor);
+        assert_eq!(decoder.by_ref().count(), 0);
+        assert!(decoder.take_error().is_some());
+        assert!(decoder.take_error().is_none());
+        assert!(crate::decode_from_read(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn skip_corrupt_blocks() {
+        use super::CorruptBlocks;
+        use super::Decoder;
+        use crate::PseudoAln;
+        use crate::encoder::Encoder;
+        use crate::headers::block::read_block_header;
+        use crate::headers::file::read_file_header_and_flags;
+
+        use std::io::Cursor;
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let data: Vec<PseudoAln> = (0..6).map(|idx| {
+            PseudoAln{ones_names: None, query_id: Some(idx), ones: Some(vec![idx % 2]), query_name: None }
+        }).collect();
+
+        let mut records = data.clone().into_iter();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 6);
+        encoder.set_block_size(2).unwrap();
+        let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+        encoder.for_each(|block| bytes.extend(block.unwrap()));
+
+        // Garble the payload of the second block
+        let mut conn = bytes.as_slice();
+        read_file_header_and_flags(&mut conn).unwrap();
+        let first = read_block_header(&mut conn).unwrap();
+        conn = &conn[(first.flags_len as usize + first.block_len as usize)..];
+        let second = read_block_header(&mut conn).unwrap();
+        let start = bytes.len() - conn.len();
+        let payload_start = start + second.flags_len as usize;
+        bytes[payload_start..(payload_start + second.block_len as usize)].iter_mut().for_each(|x| *x ^= 0xff);
+
+        let mut cursor = Cursor::new(bytes.clone());
+        let mut decoder = Decoder::new(&mut cursor);
+        let got: Vec<_> = decoder.try_iter().collect();
+        assert_eq!(got.len(), 3);
+        assert!(got[2].is_err());
+
+        let mut cursor = Cursor::new(bytes);
+        let mut decoder = Decoder::new(&mut cursor);
+        decoder.set_skip_corrupt(true);
+        let got: Vec<u32> = decoder.by_ref().map(|x| x.query_id.unwrap()).collect();
+        assert_eq!(got, vec![0, 1, 4, 5]);
+        assert_eq!(decoder.corrupt_blocks(), &CorruptBlocks{ blocks: vec![1], n_records: 2 });
+    }
+
+    #[test]
+    fn collapse_to_target_groups() {
+        use super::Decoder;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+        use crate::headers::file::TargetGroups;
+
+        use std::io::Cursor;
+
+        let data = vec![
+            PseudoAln{ones_names: None,  query_id: Some(0), ones: Some(vec![0, 1]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(1), ones: Some(vec![2]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(2), ones: Some(vec![1, 2]), query_name: None },
+        ];
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+        let groups = TargetGroups{ level: b"species".to_vec(), group_names: vec![b"E. coli".to_vec(), b"Phage".to_vec()], membership: vec![0, 0, 1] };
+
+        let opts = EncodeOpts{ target_groups: vec![groups], ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+
+        let mut cursor = Cursor::new(bytes);
+        let mut decoder = Decoder::new(&mut cursor);
+        assert!(decoder.collapse(b"genus").is_err());
+
+        let (header, flags) = decoder.collapse(b"species").unwrap();
+        decoder.fill_query_name(false);
+        let mut got: Vec<PseudoAln> = decoder.collect();
+        got.sort_by_key(|x| x.query_id);
+
+        let expected = vec![
+            PseudoAln{ones_names: Some(vec![b"E. coli".to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: None },
+            PseudoAln{ones_names: Some(vec![b"Phage".to_vec()]),  query_id: Some(1), ones: Some(vec![1]), query_name: None },
+            PseudoAln{ones_names: Some(vec![b"E. coli".to_vec(), b"Phage".to_vec()]),  query_id: Some(2), ones: Some(vec![0, 1]), query_name: None },
+        ];
+
+        assert_eq!(header.n_targets, 2);
+        assert_eq!(flags.target_names, vec![b"E. coli".to_vec(), b"Phage".to_vec()]);
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn iter_targets_after_next() {
+        use super::Decoder;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+        use crate::headers::file::TargetGroups;
+
+        use roaring::RoaringBitmap;
+
+        use std::io::Cursor;
+
+        let data = vec![
+            PseudoAln{ones_names: None,  query_id: Some(0), ones: Some(vec![0, 1]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(1), ones: Some(vec![2]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(2), ones: Some(vec![1, 2]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(3), ones: Some(vec![]), query_name: None },
+        ];
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+        let groups = TargetGroups{ level: b"species".to_vec(), group_names: vec![b"E. coli".to_vec(), b"Phage".to_vec()], membership: vec![0, 0, 1] };
+
+        let opts = EncodeOpts{ target_groups: vec![groups], ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+
+        let mut cursor = Cursor::new(bytes.clone());
+        let mut decoder = Decoder::new(&mut cursor);
+        let got: Vec<(u32, RoaringBitmap)> = decoder.iter_targets().unwrap().collect();
+        let expected = vec![
+            (0, RoaringBitmap::from([0])),
+            (1, RoaringBitmap::from([0, 2])),
+            (2, RoaringBitmap::from([1, 2])),
+        ];
+        assert_eq!(got, expected);
+        assert!(decoder.next().is_none());
+
+        let mut cursor = Cursor::new(bytes);
+        let mut decoder = Decoder::new(&mut cursor);
+        decoder.collapse(b"species").unwrap();
+        let first = decoder.next().unwrap().query_id.unwrap();
+        let got: Vec<(u32, RoaringBitmap)> = decoder.iter_targets().unwrap().collect();
+        let mut expected = vec![
+            (0, RoaringBitmap::from([0, 2])),
+            (1, RoaringBitmap::from([1, 2])),
+        ];
+        expected.iter_mut().for_each(|(_, queries)| { queries.remove(first); });
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn extend_n_queries_to_fit_query_ids() {
+        use super::Decoder;
+        use super::QueryIdCheck;
+        use crate::PseudoAln;
+        use crate::encoder::Encoder;
+
+        use std::io::Cursor;
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let mut records = vec![
+            PseudoAln{ones_names: None,  query_id: Some(0), ones: Some(vec![0]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(4), ones: Some(vec![1]), query_name: None },
+        ].into_iter();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 2);
+        let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+        encoder.for_each(|block| bytes.extend(block.unwrap()));
+
+        let mut cursor = Cursor::new(bytes.clone());
+        let mut decoder = Decoder::new(&mut cursor);
+        decoder.fill_query_name(false);
+        assert_eq!(decoder.original_n_queries(), None);
+        let got: Vec<u32> = decoder.by_ref().map(|x| x.query_id.unwrap()).collect();
+        assert_eq!(got, vec![0, 4]);
+        assert_eq!(decoder.original_n_queries(), Some(2));
+        assert_eq!(decoder.file_header().n_queries, 5);
+
+        let mut cursor = Cursor::new(bytes);
+        let mut decoder = Decoder::new(&mut cursor);
+        decoder.set_query_id_check(QueryIdCheck::Strict);
+        let got: Vec<_> = decoder.try_iter().collect();
+        assert_eq!(got.len(), 1);
+        assert!(got[0].is_err());
+    }
+
+    #[test]
+    fn decode_file_without_blocks() {
+        use super::Decoder;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+
+        use std::io::Cursor;
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &[], &mut bytes, EncodeOpts::default()).unwrap();
+
+        let mut cursor = Cursor::new(bytes);
+        let mut decoder = Decoder::new(&mut cursor);
+
+        assert_eq!(decoder.file_header().n_queries, 0);
+        assert_eq!(decoder.block_flags().query_ids, None);
+        assert!(decoder.try_next_block().unwrap().is_none());
+        assert!(decoder.records().is_empty());
+        assert!(decoder.try_iter().next().is_none());
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn slice_skips_blocks_without_hits() {
+        use super::Decoder;
+        use crate::PseudoAln;
+        use crate::encoder::Encoder;
+
+        use roaring::RoaringBitmap;
+        use std::io::Cursor;
+
+        let targets: Vec<Vec<u8>> = (0..5).map(|idx| format!("target{}", idx).into_bytes()).collect();
+        let data: Vec<PseudoAln> = (0..20).map(|idx| {
+            PseudoAln{ones_names: None, query_id: Some(idx), ones: Some(vec![idx / 4]), query_name: None }
+        }).collect();
+
+        for target_summary in [true, false] {
+            let mut records = data.clone().into_iter();
+            let mut encoder = Encoder::new(&mut records, &targets, b"sample", 20);
+            encoder.set_block_size(2).unwrap();
+            if target_summary {
+                encoder.set_target_summary();
+            }
+            let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+            encoder.for_each(|block| bytes.extend(block.unwrap()));
+
+            let mut cursor = Cursor::new(bytes);
+            let mut decoder = Decoder::new(&mut cursor);
+            let got = decoder.slice(&RoaringBitmap::from_iter([1, 3])).unwrap();
+            assert_eq!(got.iter().map(|x| x.query_id.unwrap()).collect::<Vec<u32>>(), vec![4, 5, 6, 7, 12, 13, 14, 15]);
+            assert_eq!(got[0].ones_names, Some(vec![b"target1".to_vec()]));
+            assert_eq!(decoder.blocks_skipped(), if target_summary { 6 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn sample_picks_records_uniformly() {
+        use super::Decoder;
+        use crate::PseudoAln;
+        use crate::encoder::Encoder;
+
+        use std::io::Cursor;
+
+        let targets: Vec<Vec<u8>> = vec![b"chr.fasta".to_vec()];
+        let data: Vec<PseudoAln> = (0..20).map(|idx| {
+            PseudoAln{ones_names: None, query_id: Some(idx), ones: Some(vec![0]), query_name: None }
+        }).collect();
+        let mut records = data.into_iter();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 20);
+        encoder.set_block_size(2).unwrap();
+        let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+        encoder.for_each(|block| bytes.extend(block.unwrap()));
+
+        let sample = |n: usize, seed: u64| {
+            let mut cursor = Cursor::new(bytes.clone());
+            let mut decoder = Decoder::new(&mut cursor);
+            let got: Vec<u32> = decoder.sample(n, seed).unwrap().iter().map(|x| x.query_id.unwrap()).collect();
+            (got, decoder.blocks_skipped())
+        };
+
+        assert_eq!(sample(25, 0), ((0..20).collect(), 0));
+        assert_eq!(sample(0, 0), (vec![], 0));
+        assert_eq!(sample(5, 7), sample(5, 7));
+
+        // Each record is picked 2000 * 5 / 20 = 500 times on average
+        let mut counts: Vec<usize> = vec![0; 20];
+        for seed in 0..2000 {
+            let (got, _) = sample(5, seed);
+            assert_eq!(got.len(), 5);
+            got.iter().for_each(|x| counts[*x as usize] += 1);
+        }
+        assert!(counts.iter().all(|x| (400..600).contains(x)), "{:?}", counts);
+    }
+
+    #[test]
+    fn grep_skips_filtered_blocks() {
+        use super::Decoder;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+        use crate::encoder::Encoder;
+
+        use std::collections::HashSet;
+        use std::io::Cursor;
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let data: Vec<PseudoAln> = (0..20).map(|idx| {
+            PseudoAln{ones_names: None, query_id: Some(idx), ones: Some(vec![idx % 2]), query_name: Some(format!("read{}", idx).into_bytes()) }
+        }).collect();
+
+        let mut records = data.clone().into_iter();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 20);
+        encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
+        encoder.set_block_size(2).unwrap();
+        encoder.set_query_filter(0.001).unwrap();
+        let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+        encoder.for_each(|block| bytes.extend(block.unwrap()));
+
+        let mut cursor = Cursor::new(bytes);
+        let mut decoder = Decoder::new(&mut cursor);
+        let first = decoder.next().unwrap();
+        assert_eq!(first.query_name, Some(b"read0".to_vec()));
+
+        let names = HashSet::from([b"read1".to_vec(), b"read13".to_vec(), b"missing".to_vec()]);
+        let got = decoder.grep(&names).unwrap();
+        let expected = vec![
+            PseudoAln{ones_names: Some(vec![b"plasmid.fasta".to_vec()]), query_id: Some(1), ones: Some(vec![1]), query_name: Some(b"read1".to_vec()) },
+            PseudoAln{ones_names: Some(vec![b"plasmid.fasta".to_vec()]), query_id: Some(13), ones: Some(vec![1]), query_name: Some(b"read13".to_vec()) },
+        ];
+        assert_eq!(got, expected);
+        assert_eq!(decoder.blocks_skipped(), 8);
+        assert!(decoder.next().is_none());
+
+        // Files without query names can't be searched or filtered
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+        let mut cursor = Cursor::new(bytes);
+        let mut decoder = Decoder::new(&mut cursor);
+        assert!(decoder.grep(&names).is_err());
+
+        let opts = EncodeOpts{ query_filter_fpr: Some(0.001), ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        assert!(encode_to_write(&targets, &[], &data, &mut bytes, opts).is_err());
+    }
 }
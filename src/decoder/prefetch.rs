@@ -0,0 +1,245 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Read-ahead of .ahda blocks on a background thread.
+//!
+//! [PrefetchReader] reads the next blocks of an .ahda record from a
+//! connection on a background thread while the previous block is being
+//! decoded. This overlaps reading the input with decompressing the blocks,
+//! which helps most with slow storage such as spinning disks and network
+//! filesystems.
+//!
+//! The background thread only splits the input into blocks and doesn't check
+//! their contents. At most `n_blocks` blocks are buffered in memory.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::{decode_from_read, encode_to_write};
+//! use ahda::EncodeOpts;
+//! use ahda::PseudoAln;
+//! use ahda::decoder::prefetch::PrefetchReader;
+//! use std::io::Cursor;
+//!
+//! let data = vec![
+//!     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("r1".as_bytes().to_vec()) },
+//!     PseudoAln{ones_names: Some(vec!["plasmid.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![1]), query_name: Some("r2".as_bytes().to_vec()) },
+//! ];
+//! let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+//! let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec()];
+//! let mut opts = EncodeOpts::default();
+//! opts.encode_query_names = true;
+//!
+//! let mut bytes: Vec<u8> = Vec::new();
+//! encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
+//!
+//! // Any connection that can be sent to another thread works
+//! let mut conn = PrefetchReader::new(Cursor::new(bytes), 4);
+//! let (_, _, alns) = decode_from_read(&mut conn).unwrap();
+//!
+//! assert_eq!(alns, data);
+//! ```
+//!
+
+use crate::headers::block::decode_block_header;
+use crate::headers::file::decode_file_header;
+
+use std::io::Cursor;
+use std::io::Read;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::sync_channel;
+
+/// Reads `buf.len()` bytes or until the end of the input.
+///
+/// Returns the number of bytes read.
+fn read_up_to<R: Read>(
+    conn: &mut R,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut nbytes = 0;
+    while nbytes < buf.len() {
+        match conn.read(&mut buf[nbytes..]) {
+            Ok(0) => break,
+            Ok(n) => nbytes += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(nbytes)
+}
+
+/// Reads the next header and the `len(header)` bytes that follow it.
+///
+/// Returns the bytes and whether there may be more input after them.
+fn read_chunk<R: Read>(
+    conn: &mut R,
+    decode_len: impl Fn(&[u8]) -> Option<u64>,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut bytes: Vec<u8> = vec![0; 32];
+    let nbytes = read_up_to(conn, &mut bytes)?;
+    if nbytes < bytes.len() {
+        // End of input or truncated header, let the decoder handle it
+        bytes.truncate(nbytes);
+        return Ok((bytes, false))
+    }
+
+    let Some(len) = decode_len(&bytes) else {
+        // Not a valid header, let the decoder report the error
+        return Ok((bytes, false))
+    };
+    conn.take(len).read_to_end(&mut bytes)?;
+    Ok((bytes, true))
+}
+
+fn prefetch<R: Read>(
+    mut conn: R,
+    sender: SyncSender<std::io::Result<Vec<u8>>>,
+) {
+    // File header and flags
+    let mut next = read_chunk(&mut conn, |bytes| Some(decode_file_header(bytes).ok()?.flags_len));
+
+    loop {
+        match next {
+            Ok((bytes, more)) => {
+                if !bytes.is_empty() && sender.send(Ok(bytes)).is_err() {
+                    // Reader was dropped
+                    return
+                }
+                if !more {
+                    return
+                }
+            },
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return
+            },
+        }
+
+        next = read_chunk(&mut conn, |bytes| {
+            let header = decode_block_header(bytes).ok()?;
+            Some(header.flags_len + header.block_len as u64)
+        });
+    }
+}
+
+/// [Read] implementation that reads .ahda blocks ahead on a background thread.
+pub struct PrefetchReader {
+    // Inputs
+    receiver: Receiver<std::io::Result<Vec<u8>>>,
+
+    // Internals
+    current: Cursor<Vec<u8>>,
+}
+
+impl PrefetchReader {
+    /// Start reading .ahda data from `conn` on a background thread.
+    ///
+    /// At most `n_blocks` blocks that have not been read from the
+    /// PrefetchReader yet are kept in memory.
+    ///
+    pub fn new<R: Read + Send + 'static>(
+        conn: R,
+        n_blocks: usize,
+    ) -> Self {
+        let (sender, receiver) = sync_channel(n_blocks.max(1));
+        std::thread::spawn(move || prefetch(conn, sender));
+
+        PrefetchReader{
+            receiver,
+            current: Cursor::new(Vec::new()),
+        }
+    }
+}
+
+impl Read for PrefetchReader {
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> std::io::Result<usize> {
+        loop {
+            let nbytes = self.current.read(buf)?;
+            if nbytes > 0 || buf.is_empty() {
+                return Ok(nbytes)
+            }
+            match self.receiver.recv() {
+                Ok(next) => self.current = Cursor::new(next?),
+                // Background thread has finished
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn prefetch_reader_returns_input_bytes() {
+        use super::PrefetchReader;
+        use crate::encoder::Encoder;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+        use std::io::Read;
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let data: Vec<PseudoAln> = (0..10).map(|query_id| {
+            PseudoAln{ ones_names: None, query_id: Some(query_id), ones: Some(vec![query_id % 2]), query_name: None }
+        }).collect();
+
+        let mut iter = data.into_iter();
+        let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 10);
+        encoder.set_fields_present(crate::MASK_QUERY_IDS);
+        encoder.set_block_size(3).unwrap();
+        let mut expected = encoder.encode_file_header_and_flags().unwrap();
+        for block in encoder.by_ref() {
+            expected.append(&mut block.unwrap());
+        }
+
+        let mut got: Vec<u8> = Vec::new();
+        PrefetchReader::new(Cursor::new(expected.clone()), 1).read_to_end(&mut got).unwrap();
+
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn prefetch_reader_passes_truncated_input() {
+        use super::PrefetchReader;
+        use crate::decoder::Decoder;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let data = vec![
+            PseudoAln{ones_names: None,  query_id: Some(0), ones: Some(vec![0]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(1), ones: Some(vec![1]), query_name: None },
+        ];
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+        bytes.pop();
+
+        let mut conn = PrefetchReader::new(Cursor::new(bytes), 2);
+        let mut decoder = Decoder::new(&mut conn);
+        let got: Vec<_> = decoder.try_iter().collect();
+
+        assert_eq!(got.len(), 1);
+        assert!(got[0].is_err());
+    }
+}
@@ -0,0 +1,188 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Return decoded records in the order of their query ids.
+//!
+//! [Decoder](super::Decoder) returns the records in the order they are stored
+//! in the blocks. Within a block, queries that did not align against any
+//! target come after the ones that did, and blocks from concatenated or
+//! merged files may be in any order. Tools that expect the reads in the order
+//! they appear in the query file, such as mSWEEP with Themisto input, need the
+//! records sorted by their query id (rank).
+//!
+//! [RankSorted] relies on the encoder storing the query ids as contiguous
+//! ranges starting from 0: a record is returned as soon as every query id
+//! before it has been returned. Records that arrive early are held in memory
+//! until the gap before them is filled, which for files written by `ahda
+//! encode` is at most one block.
+//!
+//! If the query ids have gaps or the blocks are not in order, the records
+//! after the first gap are held in memory until the input ends and returned
+//! sorted. In the worst case this keeps all records in memory.
+//!
+//! Records without a query id are returned last, in the order they were read.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::PseudoAln;
+//! use ahda::decoder::sorted::RankSorted;
+//!
+//! let data = vec![
+//!     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None },
+//!     PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![1]), query_name: None },
+//!     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![]), query_name: None },
+//! ];
+//!
+//! let got: Vec<u32> = RankSorted::new(data.into_iter()).map(|x| x.query_id.unwrap()).collect();
+//!
+//! assert_eq!(got, vec![0, 1, 2]);
+//! ```
+//!
+
+use crate::PseudoAln;
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+
+/// Iterator adaptor that returns [PseudoAln] records sorted by query id.
+pub struct RankSorted<I: Iterator<Item = PseudoAln>> {
+    // Inputs
+    records: I,
+
+    // Internals
+    next_id: u32,
+    pending: BTreeMap<u32, VecDeque<PseudoAln>>,
+    no_id: VecDeque<PseudoAln>,
+    done: bool,
+}
+
+impl<I: Iterator<Item = PseudoAln>> RankSorted<I> {
+    pub fn new(
+        records: I,
+    ) -> Self {
+        RankSorted{
+            records,
+            next_id: 0,
+            pending: BTreeMap::new(),
+            no_id: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Number of records currently held in memory.
+    pub fn n_pending(
+        &self,
+    ) -> usize {
+        self.pending.values().map(|x| x.len()).sum::<usize>() + self.no_id.len()
+    }
+
+    fn pop_pending(
+        &mut self,
+    ) -> Option<PseudoAln> {
+        let mut entry = self.pending.first_entry()?;
+        let record = entry.get_mut().pop_front();
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        if let Some(id) = record.as_ref().and_then(|x| x.query_id) {
+            self.next_id = self.next_id.max(id.saturating_add(1));
+        }
+        record
+    }
+}
+
+impl<I: Iterator<Item = PseudoAln>> Iterator for RankSorted<I> {
+    type Item = PseudoAln;
+
+    fn next(
+        &mut self,
+    ) -> Option<PseudoAln> {
+        loop {
+            if self.pending.first_key_value().is_some_and(|(id, _)| *id <= self.next_id) {
+                return self.pop_pending()
+            }
+
+            if self.done {
+                if !self.pending.is_empty() {
+                    return self.pop_pending()
+                }
+                return self.no_id.pop_front()
+            }
+
+            match self.records.next() {
+                Some(record) => match record.query_id {
+                    Some(id) if id <= self.next_id => {
+                        self.next_id = self.next_id.max(id.saturating_add(1));
+                        return Some(record)
+                    },
+                    Some(id) => {
+                        // Duplicate ids are returned in the order they were read
+                        self.pending.entry(id).or_default().push_back(record);
+                    },
+                    None => self.no_id.push_back(record),
+                },
+                None => self.done = true,
+            }
+        }
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn rank_sorted_sorts_blocks_with_empty_rows_last() {
+        use super::RankSorted;
+        use crate::PseudoAln;
+
+        // Aligned queries first, then the unaligned ones in each block
+        let ids: Vec<u32> = vec![0, 2, 1, 3, 5, 4];
+        let data: Vec<PseudoAln> = ids.iter().map(|id| {
+            PseudoAln{ ones_names: None, query_id: Some(*id), ones: Some(vec![]), query_name: None }
+        }).collect();
+
+        let mut sorted = RankSorted::new(data.into_iter());
+        let mut got: Vec<u32> = Vec::new();
+        let mut max_pending = 0;
+        while let Some(record) = sorted.next() {
+            got.push(record.query_id.unwrap());
+            max_pending = max_pending.max(sorted.n_pending());
+        }
+
+        assert_eq!(got, vec![0, 1, 2, 3, 4, 5]);
+        assert!(max_pending <= 1);
+    }
+
+    #[test]
+    fn rank_sorted_handles_gaps_and_missing_ids() {
+        use super::RankSorted;
+        use crate::PseudoAln;
+
+        let data = vec![
+            PseudoAln{ ones_names: None, query_id: Some(10), ones: Some(vec![0]), query_name: Some(b"r10".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: None, ones: Some(vec![1]), query_name: Some(b"a".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(7), ones: Some(vec![0]), query_name: Some(b"r7".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(7), ones: Some(vec![1]), query_name: Some(b"r7b".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: None, ones: Some(vec![1]), query_name: Some(b"b".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(3), ones: Some(vec![0]), query_name: Some(b"r3".to_vec()) },
+        ];
+
+        let got: Vec<Vec<u8>> = RankSorted::new(data.into_iter()).map(|x| x.query_name.unwrap()).collect();
+        let expected: Vec<Vec<u8>> = vec![b"r3".to_vec(), b"r7".to_vec(), b"r7b".to_vec(), b"r10".to_vec(), b"a".to_vec(), b"b".to_vec()];
+
+        assert_eq!(got, expected);
+    }
+}
@@ -0,0 +1,427 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Check plain text input against the query and target names before encoding.
+//!
+//! [Parser](crate::parser::Parser) assumes that the .fastX file, the target
+//! list, and the alignments belong together. When they don't, for example
+//! because the target list is from another index or the reads were filtered
+//! after aligning, the mismatch is only noticed when a record refers to a read
+//! or target that doesn't exist.
+//!
+//! [check] reads the whole input without filling in the records and reports
+//! every kind of mismatch it finds as an [Issue], with the line where it was
+//! first seen and a suggested fix.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::doctor::check;
+//! use ahda::doctor::Issue;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+//!
+//! // Themisto output refers to a third target and read
+//! let mut input: &[u8] = b"0 1\n1 0 2\n2 0\n";
+//! let report = check(&mut input, Some(&queries), Some(&targets), None).unwrap();
+//!
+//! assert_eq!(report.n_records, 3);
+//! assert!(matches!(report.issues[0], Issue::TargetIdOutOfRange{ line: 2, id: 2, .. }));
+//! assert!(matches!(report.issues[1], Issue::QueryIdOutOfRange{ line: 3, id: 2, .. }));
+//! ```
+//!
+
+use crate::Format;
+use crate::parser::guess_format;
+use crate::parser::read_record;
+
+use indexmap::IndexSet;
+
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+
+type E = Box<dyn std::error::Error>;
+
+/// Mismatch between the input, the query names, and the target names.
+///
+/// Issues that concern single lines record the first line where they occur,
+/// counting from 1, and how many times they occur.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Issue {
+    /// The input format can't be guessed from the first line.
+    UnknownFormat { error: String },
+    /// The format doesn't list the targets and no target list was given.
+    NeedTargets { format: Format },
+    /// The format identifies reads by name and no query names were given.
+    NeedQueries { format: Format },
+    /// A name appears twice in the target list.
+    DuplicateTarget { name: String, first: usize, second: usize },
+    /// A name appears twice in the query names.
+    DuplicateQuery { name: String, first: usize, second: usize },
+    /// The target list and the header of the input list a different number of targets.
+    TargetCount { list: usize, input: usize },
+    /// The target list and the header of the input name a target differently.
+    TargetOrder { position: usize, list: String, input: String },
+    /// A record refers to a target id past the end of the target list.
+    TargetIdOutOfRange { line: usize, id: u32, n_targets: usize, count: usize },
+    /// A record refers to a target name that isn't in the target list.
+    UnknownTarget { line: usize, name: String, count: usize },
+    /// A record refers to a read id past the end of the query names.
+    QueryIdOutOfRange { line: usize, id: u32, n_queries: usize, count: usize },
+    /// A record refers to a read name that isn't in the query names.
+    UnknownQuery { line: usize, name: String, count: usize },
+    /// A read has more than one record.
+    RepeatedQuery { line: usize, query: String, count: usize },
+    /// There are more records than query names.
+    TooManyRecords { records: usize, queries: usize },
+    /// A line can't be parsed in the input format.
+    UnparseableLine { line: usize, error: String, count: usize },
+}
+
+impl Issue {
+    /// How to fix the issue with the `ahda encode` options.
+    pub fn suggestion(
+        &self,
+    ) -> &'static str {
+        match self {
+            Issue::UnknownFormat{ .. } => "set the input format with `--format`",
+            Issue::NeedTargets{ .. } => "pass the target list used to build the index with `--targets` or `--index-dir`",
+            Issue::NeedQueries{ .. } => "pass the aligned .fastX file with `--query`, or assign query ids with `--query-ids`",
+            Issue::DuplicateTarget{ .. } => "remove the repeated line from the target list",
+            Issue::DuplicateQuery{ .. } => "pass `--rename-duplicates` to number the repeated read names",
+            Issue::TargetCount{ .. } | Issue::TargetOrder{ .. } => "use the target list of the index that produced the alignments, or `--index-dir`",
+            Issue::TargetIdOutOfRange{ .. } | Issue::UnknownTarget{ .. } => "use the target list of the index that produced the alignments, or `--index-dir`",
+            Issue::QueryIdOutOfRange{ .. } | Issue::UnknownQuery{ .. } | Issue::TooManyRecords{ .. } => "pass the .fastX file that was aligned, before any filtering, with `--query`",
+            Issue::RepeatedQuery{ .. } => "merge separately aligned mates with `ahda merge-mates`, or remove the repeated records",
+            Issue::UnparseableLine{ .. } => "check that the input is complete and set the input format with `--format`",
+        }
+    }
+
+    fn count_mut(
+        &mut self,
+    ) -> Option<&mut usize> {
+        match self {
+            Issue::TargetIdOutOfRange{ count, .. } |
+            Issue::UnknownTarget{ count, .. } |
+            Issue::QueryIdOutOfRange{ count, .. } |
+            Issue::UnknownQuery{ count, .. } |
+            Issue::RepeatedQuery{ count, .. } |
+            Issue::UnparseableLine{ count, .. } => Some(count),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Issue::UnknownFormat{ error } => write!(f, "can't guess the input format: {}", error),
+            Issue::NeedTargets{ format } => write!(f, "{} output doesn't list the targets", format),
+            Issue::NeedQueries{ format } => write!(f, "{} output identifies reads by name only", format),
+            Issue::DuplicateTarget{ name, first, second } => write!(f, "target `{}` is listed on lines {} and {} of the target list", name, first + 1, second + 1),
+            Issue::DuplicateQuery{ name, first, second } => write!(f, "read `{}` appears as reads {} and {} of the query file", name, first + 1, second + 1),
+            Issue::TargetCount{ list, input } => write!(f, "target list has {} targets but the input header has {}", list, input),
+            Issue::TargetOrder{ position, list, input } => write!(f, "target {} is `{}` in the target list but `{}` in the input header", position + 1, list, input),
+            Issue::TargetIdOutOfRange{ line, id, n_targets, count } => write!(f, "line {}: target id {} is past the {} targets ({} records)", line, id, n_targets, count),
+            Issue::UnknownTarget{ line, name, count } => write!(f, "line {}: target `{}` is not in the target list ({} records)", line, name, count),
+            Issue::QueryIdOutOfRange{ line, id, n_queries, count } => write!(f, "line {}: read id {} is past the {} reads in the query file ({} records)", line, id, n_queries, count),
+            Issue::UnknownQuery{ line, name, count } => write!(f, "line {}: read `{}` is not in the query file ({} records)", line, name, count),
+            Issue::RepeatedQuery{ line, query, count } => write!(f, "line {}: read `{}` already has a record ({} records)", line, query, count),
+            Issue::TooManyRecords{ records, queries } => write!(f, "input has {} records but the query file has {} reads", records, queries),
+            Issue::UnparseableLine{ line, error, count } => write!(f, "line {}: can't parse record: {} ({} lines)", line, error, count),
+        }
+    }
+}
+
+/// Summary of the input and the issues found by [check].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub format: Option<Format>,
+    /// Number of lines with records, excluding headers.
+    pub n_records: usize,
+    /// Number of query names, if given.
+    pub n_queries: Option<usize>,
+    /// Number of targets in the target list or the input header.
+    pub n_targets: Option<usize>,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    // Count repeats of a per-line issue instead of listing all of them
+    fn add(
+        &mut self,
+        mut issue: Issue,
+    ) {
+        let kind = std::mem::discriminant(&issue);
+        if issue.count_mut().is_some() {
+            if let Some(count) = self.issues.iter_mut().find(|x| std::mem::discriminant(*x) == kind).and_then(|x| x.count_mut()) {
+                *count += 1;
+                return
+            }
+        }
+        self.issues.push(issue);
+    }
+}
+
+// Names from a list, reporting repeated names
+fn index_names(
+    names: &[Vec<u8>],
+    mut on_duplicate: impl FnMut(String, usize, usize),
+) -> IndexSet<Vec<u8>> {
+    let mut index: IndexSet<Vec<u8>> = IndexSet::with_capacity(names.len());
+    for (pos, name) in names.iter().enumerate() {
+        let (first, inserted) = index.insert_full(name.clone());
+        if !inserted {
+            on_duplicate(String::from_utf8_lossy(name).to_string(), first, pos);
+        }
+    }
+    index
+}
+
+// Target names in the header of formats that have one
+fn header_targets(
+    format: &Format,
+    line: &[u8],
+) -> Option<Vec<Vec<u8>>> {
+    let skip = match format {
+        Format::Bifrost => 1,
+        Format::AhdaTSV => 2,
+        _ => return None,
+    };
+    Some(line.split(|x| *x == b'\t').skip(skip).map(|x| x.to_vec()).collect())
+}
+
+/// Check plain text input against the query and target names.
+///
+/// Reads all records in `conn_in` in `format`, or the format guessed from the
+/// first line, and compares the query and target ids or names in them to
+/// `queries` and `targets`. The targets listed in the header of Bifrost,
+/// SAM, and Ahda .tsv input are compared to `targets` and used in its
+/// place if it is None.
+///
+/// Problems with the input are returned in [Report::issues].
+///
+/// ## Errors and panics
+/// Returns an error if reading `conn_in` fails.
+///
+pub fn check<R: Read>(
+    conn_in: &mut R,
+    queries: Option<&[Vec<u8>]>,
+    targets: Option<&[Vec<u8>]>,
+    format: Option<Format>,
+) -> Result<Report, E> {
+    let mut report = Report::default();
+    let mut reader = BufReader::new(conn_in);
+    let mut line: Vec<u8> = Vec::new();
+    let mut line_nr: usize = 0;
+
+    let next_line = |reader: &mut BufReader<&mut R>, line: &mut Vec<u8>| -> Result<bool, E> {
+        line.clear();
+        if reader.read_until(b'\n', line)? == 0 {
+            return Ok(false)
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        Ok(true)
+    };
+
+    if !next_line(&mut reader, &mut line)? {
+        return Ok(report)
+    }
+    line_nr += 1;
+    // Program records written by `ahda convert` before the header
+    while line.starts_with(b"#PG\t") {
+        if !next_line(&mut reader, &mut line)? {
+            return Ok(report)
+        }
+        line_nr += 1;
+    }
+
+    let format = match format {
+        Some(format) => format,
+        None => match guess_format(&line) {
+            Ok(format) => format,
+            Err(e) => {
+                report.add(Issue::UnknownFormat{ error: e.to_string() });
+                return Ok(report)
+            },
+        },
+    };
+    report.format = Some(format.clone());
+
+    let query_index = queries.map(|x| index_names(x, |name, first, second| report.issues.push(Issue::DuplicateQuery{ name, first, second })));
+    report.n_queries = queries.map(|x| x.len());
+    let list_index = targets.map(|x| index_names(x, |name, first, second| report.issues.push(Issue::DuplicateTarget{ name, first, second })));
+
+    // Targets from the header of the input
+    let mut input_targets = header_targets(&format, &line);
+    let mut have_line = input_targets.is_none();
+    if format == Format::SAM {
+        let mut names: Vec<Vec<u8>> = Vec::new();
+        loop {
+            if line.first() != Some(&b'@') {
+                have_line = true;
+                break
+            }
+            if line.starts_with(b"@SQ") {
+                if let Some(name) = line.split(|x| *x == b'\t').find_map(|x| x.strip_prefix(b"SN:")) {
+                    names.push(name.to_vec());
+                }
+            }
+            if !next_line(&mut reader, &mut line)? {
+                have_line = false;
+                break
+            }
+            line_nr += 1;
+        }
+        input_targets = Some(names);
+    }
+
+    if let (Some(list), Some(input)) = (targets, input_targets.as_ref()) {
+        if list.len() != input.len() {
+            report.add(Issue::TargetCount{ list: list.len(), input: input.len() });
+        }
+        if let Some((position, (list, input))) = list.iter().zip(input.iter()).enumerate().find(|(_, (a, b))| a != b) {
+            report.add(Issue::TargetOrder{ position, list: String::from_utf8_lossy(list).to_string(), input: String::from_utf8_lossy(input).to_string() });
+        }
+    }
+
+    // Target ids refer to lines of the list even if names repeat
+    report.n_targets = targets.map(|x| x.len()).or(input_targets.as_ref().map(|x| x.len()));
+    let target_index = list_index.or(input_targets.map(|x| x.into_iter().collect::<IndexSet<Vec<u8>>>()));
+    if target_index.is_none() {
+        report.add(Issue::NeedTargets{ format: format.clone() });
+    }
+    if queries.is_none() && matches!(format, Format::Bifrost | Format::Fulgor | Format::SAM) {
+        report.add(Issue::NeedQueries{ format: format.clone() });
+    }
+
+    // SAM has one line per alignment so reads can repeat
+    let check_repeats = format != Format::SAM;
+    let mut seen_ids: HashSet<u32> = HashSet::new();
+    let mut seen_names: HashSet<Vec<u8>> = HashSet::new();
+
+    loop {
+        if !have_line {
+            if !next_line(&mut reader, &mut line)? {
+                break
+            }
+            line_nr += 1;
+        }
+        have_line = false;
+        if line.is_empty() {
+            continue
+        }
+        report.n_records += 1;
+
+        let record = match read_record(&format, &mut line.as_slice()) {
+            Ok(record) => record,
+            Err(e) => {
+                report.add(Issue::UnparseableLine{ line: line_nr, error: e.to_string(), count: 1 });
+                continue
+            },
+        };
+
+        if let (Some(index), Some(n_targets)) = (target_index.as_ref(), report.n_targets) {
+            for id in record.ones.iter().flatten() {
+                if *id as usize >= n_targets {
+                    report.add(Issue::TargetIdOutOfRange{ line: line_nr, id: *id, n_targets, count: 1 });
+                }
+            }
+            for name in record.ones_names.iter().flatten() {
+                if !index.contains(name) {
+                    report.add(Issue::UnknownTarget{ line: line_nr, name: String::from_utf8_lossy(name).to_string(), count: 1 });
+                }
+            }
+        }
+
+        if let Some(index) = query_index.as_ref() {
+            if let Some(id) = record.query_id {
+                if id as usize >= index.len() {
+                    report.add(Issue::QueryIdOutOfRange{ line: line_nr, id, n_queries: index.len(), count: 1 });
+                }
+            } else if let Some(name) = record.query_name.as_ref() {
+                if !index.contains(name) {
+                    report.add(Issue::UnknownQuery{ line: line_nr, name: String::from_utf8_lossy(name).to_string(), count: 1 });
+                }
+            }
+        }
+
+        if check_repeats {
+            let repeated = match (record.query_id, record.query_name.as_ref()) {
+                (Some(id), _) => (!seen_ids.insert(id)).then(|| id.to_string()),
+                (None, Some(name)) => (!seen_names.insert(name.clone())).then(|| String::from_utf8_lossy(name).to_string()),
+                (None, None) => None,
+            };
+            if let Some(query) = repeated {
+                report.add(Issue::RepeatedQuery{ line: line_nr, query, count: 1 });
+            }
+        }
+    }
+
+    if let Some(n_queries) = report.n_queries {
+        if check_repeats && report.n_records > n_queries {
+            report.add(Issue::TooManyRecords{ records: report.n_records, queries: n_queries });
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn check_bifrost_header_and_names() {
+        use super::check;
+        use super::Issue;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+
+        let mut input: &[u8] = b"query_name\tchr.fasta\tvirus.fasta\nr1\t1\t0\nr3\t0\t1\nr4\t0\tx\nr1\t1\t1\n";
+        let got = check(&mut input, Some(&queries), Some(&targets), None).unwrap();
+
+        assert_eq!(got.n_records, 4);
+        assert_eq!(got.n_targets, Some(2));
+        assert_eq!(got.issues, vec![
+            Issue::TargetOrder{ position: 1, list: "plasmid.fasta".to_string(), input: "virus.fasta".to_string() },
+            Issue::UnknownQuery{ line: 3, name: "r3".to_string(), count: 1 },
+            Issue::UnparseableLine{ line: 4, error: "invalid digit found in string".to_string(), count: 1 },
+            Issue::RepeatedQuery{ line: 5, query: "r1".to_string(), count: 1 },
+            Issue::TooManyRecords{ records: 4, queries: 2 },
+        ]);
+    }
+
+    #[test]
+    fn check_reports_missing_names_and_counts_repeats() {
+        use super::check;
+        use super::Issue;
+        use crate::Format;
+
+        let mut input: &[u8] = b"r1\t1\t0\nr2\t1\t5\nr3\t1\t7\n";
+        let got = check(&mut input, None, Some(&[b"a".to_vec(), b"a".to_vec()]), Some(Format::Fulgor)).unwrap();
+
+        assert_eq!(got.issues, vec![
+            Issue::DuplicateTarget{ name: "a".to_string(), first: 0, second: 1 },
+            Issue::NeedQueries{ format: Format::Fulgor },
+            Issue::TargetIdOutOfRange{ line: 2, id: 5, n_targets: 2, count: 2 },
+        ]);
+    }
+}
@@ -0,0 +1,390 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Check what `ahda cat`, `ahda set`, and `ahda convert` would do without writing anything.
+//!
+//! A run over hundreds of inputs can fail hours in because one input was
+//! aligned against another index or an output file already exists. With
+//! `--dry-run` these commands read only the headers of their inputs into a
+//! [Plan], check that the inputs can be combined and that the outputs can be
+//! created, estimate the size of the output from the size of the inputs,
+//! and print the plan as a [Report] instead of writing the output.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::dry_run::plan_concatenate;
+//! use ahda::dry_run::PlannedInput;
+//! use ahda::encode_to_write;
+//! use ahda::CatOpts;
+//! use ahda::EncodeOpts;
+//! use ahda::PseudoAln;
+//!
+//! let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+//! let data = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None }];
+//!
+//! let mut inputs: Vec<PlannedInput> = Vec::new();
+//! for (path, targets) in [("a.ahda", vec![b"chr.fasta".to_vec()]), ("b.ahda", vec![b"plasmid.fasta".to_vec()])] {
+//!     let mut bytes: Vec<u8> = Vec::new();
+//!     encode_to_write(&targets, &queries, &data, &mut bytes, EncodeOpts::default()).unwrap();
+//!     inputs.push(PlannedInput::from_read(path.as_bytes(), &mut bytes.as_slice(), Some(bytes.len() as u64)).unwrap());
+//! }
+//!
+//! let plan = plan_concatenate(inputs, &CatOpts::default()).unwrap();
+//! assert!(!plan.is_ok());
+//! assert_eq!(plan.incompatibilities[0].to_string(), "input 1 has a different name for target 0");
+//! ```
+//!
+
+use crate::CatOpts;
+use crate::Incompatibility;
+use crate::MergeOp;
+use crate::compression::dedup::BlockCache;
+use crate::compression::flags_dictionary::FlagsDictionary;
+use crate::headers::block::decode_block_flags_with_dictionary;
+use crate::headers::file::FileFlags;
+use crate::headers::file::FileHeader;
+use crate::headers::file::read_file_flags;
+use crate::headers::file::read_file_header;
+use crate::parser::guess_format;
+use crate::report::Report;
+use crate::report::Value;
+
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::io::Read;
+use std::path::Path;
+
+type E = Box<dyn std::error::Error>;
+
+/// One input of a [Plan].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlannedInput {
+    /// Path of the input as given, `-` for stdin.
+    pub path: Vec<u8>,
+    /// Size of the input in bytes, None if it is not known before reading it.
+    pub len: Option<u64>,
+    /// Format of the input, `ahda` for .ahda data.
+    pub format: Option<String>,
+    /// File header and flags of .ahda data.
+    pub headers: Option<(FileHeader, FileFlags)>,
+    /// Query ids in the first block of .ahda data.
+    pub first_query_ids: Vec<u32>,
+}
+
+impl PlannedInput {
+    /// Read the beginning of the input in `conn` of `len` bytes from `path`.
+    ///
+    /// Reads the file header, flags, and first block of .ahda data, or
+    /// guesses the format of plain text from its first lines.
+    ///
+    /// ## Errors and panics
+    /// Errors if the file header or flags can't be read, eg. if the input
+    /// is in a zstd-seekable container.
+    ///
+    pub fn from_read<R: Read>(
+        path: &[u8],
+        conn: &mut R,
+        len: Option<u64>,
+    ) -> Result<Self, E> {
+        let mut prefix: Vec<u8> = Vec::with_capacity(crate::MAGIC.len());
+        conn.by_ref().take(crate::MAGIC.len() as u64).read_to_end(&mut prefix)?;
+        let mut input = PlannedInput{ path: path.to_vec(), len, ..Default::default() };
+        if crate::is_ahda_file(&mut prefix.as_slice()) {
+            let mut conn = Cursor::new(prefix).chain(conn);
+            let header = read_file_header(&mut conn)?;
+            let flags = read_file_flags(&header, &mut conn)?;
+            if let Some((block_header, bytes)) = BlockCache::new().try_read_block(&mut conn)? {
+                let dictionary = FlagsDictionary::from_flags(&flags);
+                let block_flags = decode_block_flags_with_dictionary(&bytes[..(block_header.flags_len as usize)], &block_header, dictionary.as_ref(), &mut Vec::new())?;
+                input.first_query_ids = block_flags.query_ids.unwrap_or_default();
+            }
+            input.format = Some("ahda".to_string());
+            input.headers = Some((header, flags));
+        } else if !prefix.is_empty() {
+            conn.by_ref().take(4096).read_to_end(&mut prefix)?;
+            input.format = guess_format(&prefix).ok().map(|x| x.to_string());
+        }
+        Ok(input)
+    }
+
+    // Bytes after the file header and flags
+    fn block_bytes(
+        &self,
+    ) -> Option<u64> {
+        self.len.zip(self.headers.as_ref()).map(|(len, (header, _))| len.saturating_sub(32 + header.flags_len))
+    }
+}
+
+/// What a command would read and write, and what would stop it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Inputs in the order they are read.
+    pub inputs: Vec<PlannedInput>,
+    /// Output files, empty if the output goes to stdout.
+    pub outputs: Vec<Vec<u8>>,
+    /// Approximate size of the output in bytes, see the function that made the plan.
+    pub estimated_bytes: Option<u64>,
+    /// Inputs that can't be combined with the first input.
+    pub incompatibilities: Vec<Incompatibility>,
+    /// Other problems, like unreadable inputs or existing outputs.
+    pub problems: Vec<String>,
+}
+
+impl Plan {
+    /// Check if the command would run.
+    pub fn is_ok(
+        &self,
+    ) -> bool {
+        self.incompatibilities.is_empty() && self.problems.is_empty()
+    }
+
+    /// Add the output file at `path` and check that it can be created.
+    ///
+    /// An existing file can be overwritten only if `force` is true. The
+    /// directory of the output must exist and be writable, and no other
+    /// output of the plan may have the same path.
+    pub fn add_output(
+        &mut self,
+        path: &Path,
+        force: bool,
+    ) {
+        let name = path.to_string_lossy();
+        let dir = path.parent().filter(|x| !x.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if self.outputs.iter().any(|x| x == name.as_bytes()) {
+            self.problems.push(format!("output file `{}` is written by several inputs", name));
+        } else if path.is_dir() {
+            self.problems.push(format!("output file `{}` is a directory", name));
+        } else if path.exists() && !force {
+            self.problems.push(format!("output file `{}` exists, use `--force` to overwrite it", name));
+        } else if !dir.is_dir() {
+            self.problems.push(format!("directory `{}` of output file `{}` doesn't exist", dir.to_string_lossy(), name));
+        } else if dir.metadata().is_ok_and(|x| x.permissions().readonly()) {
+            self.problems.push(format!("directory `{}` of output file `{}` is not writable", dir.to_string_lossy(), name));
+        }
+        self.outputs.push(name.as_bytes().to_vec());
+    }
+
+    /// Error if the command would not run.
+    ///
+    /// ## Errors and panics
+    /// Errors with [IncompatibleInputsErr](crate::errors::IncompatibleInputsErr)
+    /// if some inputs are incompatible, and otherwise with
+    /// [DryRunErr](crate::errors::DryRunErr) if the plan has other problems.
+    ///
+    pub fn check(
+        &self,
+    ) -> Result<(), E> {
+        if !self.incompatibilities.is_empty() {
+            return Err(Box::new(crate::errors::IncompatibleInputsErr{ incompatibilities: self.incompatibilities.clone() }))
+        }
+        if !self.problems.is_empty() {
+            return Err(Box::new(crate::errors::DryRunErr{ n_problems: self.problems.len() }))
+        }
+        Ok(())
+    }
+
+    /// Describe the plan as a [Report].
+    ///
+    /// Has an `input` row with the path, format, number of queries, number
+    /// of targets, and size of each input, an `output` row per output file,
+    /// the `estimated_bytes` of the output, and a `problem` row for each
+    /// incompatibility and problem.
+    pub fn report(
+        &self,
+    ) -> Report {
+        let mut report = Report::default();
+        for input in self.inputs.iter() {
+            let headers = input.headers.as_ref();
+            report.row("input", vec![
+                Value::text(String::from_utf8_lossy(&input.path)),
+                input.format.as_ref().map_or(Value::Missing, Value::text),
+                headers.map_or(Value::Missing, |(header, _)| Value::number(header.n_queries)),
+                headers.map_or(Value::Missing, |(header, _)| Value::number(header.n_targets)),
+                input.len.map_or(Value::Missing, Value::number),
+            ]);
+        }
+        for output in self.outputs.iter() {
+            report.row("output", vec![Value::text(String::from_utf8_lossy(output))]);
+        }
+        report.field("estimated_bytes", self.estimated_bytes.map_or(Value::Missing, Value::number));
+        for incompatibility in self.incompatibilities.iter() {
+            report.row("problem", vec![Value::text(incompatibility)]);
+        }
+        for problem in self.problems.iter() {
+            report.row("problem", vec![Value::text(problem)]);
+        }
+        report
+    }
+}
+
+/// Plan concatenating `inputs` like [try_concatenate_from_read_to_write_with_opts](crate::try_concatenate_from_read_to_write_with_opts).
+///
+/// The inputs are checked like the concatenation would check them,
+/// including the query ids in their first blocks unless the queries are
+/// renumbered, see [CatOpts::renumber_queries]. The estimated size is the size of the blocks of all inputs and the headers
+/// of the first input, which is exact unless the blocks are deduplicated,
+/// the queries are renumbered, the targets are merged, or a manifest is stored. It is None if the size of an input is not known.
+///
+/// Inputs that are not .ahda data are reported as problems.
+///
+/// ## Errors and panics
+/// Errors if the panel of an input can't be decoded.
+///
+pub fn plan_concatenate(
+    inputs: Vec<PlannedInput>,
+    opts: &CatOpts,
+) -> Result<Plan, E> {
+    let mut plan = Plan{ inputs, ..Default::default() };
+    plan.problems.extend(not_ahda(&plan.inputs));
+    if plan.inputs.iter().all(|x| x.headers.is_some()) {
+        let headers_flags: Vec<(FileHeader, FileFlags)> = plan.inputs.iter().filter_map(|x| x.headers.clone()).collect();
+        plan.incompatibilities = crate::concatenation_incompatibilities(&headers_flags, opts)?;
+        let mut seen_query_ids: HashSet<u32> = HashSet::new();
+        for input in plan.inputs.iter().filter(|_| !opts.renumber_queries) {
+            if !input.first_query_ids.iter().all(|id| seen_query_ids.insert(*id)) {
+                plan.problems.push(format!("input `{}` has queries that are in an earlier input, use a set operation to merge instead", String::from_utf8_lossy(&input.path)));
+            }
+        }
+        let first_headers = plan.inputs.first().and_then(|x| x.len.zip(x.block_bytes())).map(|(len, blocks)| len - blocks);
+        plan.estimated_bytes = plan.inputs.iter().try_fold(first_headers.unwrap_or(0), |acc, x| x.block_bytes().map(|y| acc + y));
+    }
+    Ok(plan)
+}
+
+/// Plan combining `inputs` with `operation` like `ahda set`.
+///
+/// The panels are compared only if `check_panel` is true, and the inputs
+/// must have the same targets. The output is encoded again, so the
+/// estimated size is an upper bound from the size of the blocks: the sum
+/// over the inputs for [MergeOp::Union] and [MergeOp::Xor], the smallest
+/// input for [MergeOp::Intersection], and the first input for
+/// [MergeOp::Diff]. It is None if the size of an input is not known.
+///
+/// Inputs that are not .ahda data are reported as problems.
+///
+/// ## Errors and panics
+/// Errors if the panel of an input can't be decoded.
+///
+pub fn plan_set(
+    inputs: Vec<PlannedInput>,
+    operation: &MergeOp,
+    check_panel: bool,
+) -> Result<Plan, E> {
+    let mut plan = Plan{ inputs, ..Default::default() };
+    plan.problems.extend(not_ahda(&plan.inputs));
+    if plan.inputs.iter().all(|x| x.headers.is_some()) {
+        let headers_flags: Vec<(FileHeader, FileFlags)> = plan.inputs.iter().filter_map(|x| x.headers.clone()).collect();
+        // Set operations decode the blocks, so only the targets and panels must match
+        let opts = CatOpts{ check_panel, ..Default::default() };
+        plan.incompatibilities = crate::concatenation_incompatibilities(&headers_flags, &opts)?.into_iter().filter(|x| {
+            matches!(x, Incompatibility::NTargets{ .. } | Incompatibility::TargetNames{ .. } | Incompatibility::Panel{ .. })
+        }).collect();
+        let block_bytes: Option<Vec<u64>> = plan.inputs.iter().map(|x| x.block_bytes()).collect();
+        let first_headers = plan.inputs.first().and_then(|x| x.len.zip(x.block_bytes())).map(|(len, blocks)| len - blocks);
+        plan.estimated_bytes = block_bytes.zip(first_headers).map(|(sizes, headers)| headers + match operation {
+            MergeOp::Union | MergeOp::Xor => sizes.iter().sum(),
+            MergeOp::Intersection => sizes.iter().min().copied().unwrap_or(0),
+            MergeOp::Diff => sizes.first().copied().unwrap_or(0),
+        });
+    }
+    Ok(plan)
+}
+
+fn not_ahda(
+    inputs: &[PlannedInput],
+) -> Vec<String> {
+    inputs.iter().filter(|x| x.headers.is_none()).map(|x| {
+        format!("input `{}` is not .ahda data", String::from_utf8_lossy(&x.path))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn plan_concatenate_estimates_output_size() {
+        use super::plan_concatenate;
+        use super::plan_set;
+        use super::PlannedInput;
+        use crate::encode_to_write;
+        use crate::try_concatenate_from_read_to_write;
+        use crate::EncodeOpts;
+        use crate::MergeOp;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries: Vec<Vec<u8>> = (0..100).map(|i| format!("r{}", i).into_bytes()).collect();
+        let encoded: Vec<Vec<u8>> = (0..3).map(|i| {
+            let data: Vec<PseudoAln> = (0..30).map(|j| PseudoAln{ ones_names: None, query_id: Some(i * 30 + j), ones: Some(vec![j % 2]), query_name: None }).collect();
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(&targets, &queries, &data, &mut bytes, EncodeOpts::default()).unwrap();
+            bytes
+        }).collect();
+        let inputs: Vec<PlannedInput> = encoded.iter().enumerate().map(|(i, bytes)| {
+            PlannedInput::from_read(format!("{}.ahda", i).as_bytes(), &mut bytes.as_slice(), Some(bytes.len() as u64)).unwrap()
+        }).collect();
+
+        let plan = plan_concatenate(inputs.clone(), &Default::default()).unwrap();
+        assert!(plan.is_ok());
+        assert_eq!(plan.inputs[1].first_query_ids, (30..60).collect::<Vec<u32>>());
+        let mut output: Vec<u8> = Vec::new();
+        try_concatenate_from_read_to_write(&mut encoded.iter().map(|x| x.as_slice()).collect::<Vec<&[u8]>>(), &mut output).unwrap();
+        assert_eq!(plan.estimated_bytes, Some(output.len() as u64));
+
+        let union = plan_set(inputs.clone(), &MergeOp::Union, true).unwrap().estimated_bytes.unwrap();
+        let intersection = plan_set(inputs.clone(), &MergeOp::Intersection, true).unwrap().estimated_bytes.unwrap();
+        assert_eq!(union, output.len() as u64);
+        assert!(intersection < union);
+
+        // Same queries twice
+        let plan = plan_concatenate(vec![inputs[0].clone(), inputs[0].clone()], &Default::default()).unwrap();
+        assert_eq!(plan.problems, vec!["input `0.ahda` has queries that are in an earlier input, use a set operation to merge instead".to_string()]);
+
+        // Unknown size and plain text input
+        let mut unknown = inputs.clone();
+        unknown[1].len = None;
+        assert_eq!(plan_concatenate(unknown, &Default::default()).unwrap().estimated_bytes, None);
+        let text = PlannedInput::from_read(b"aln.txt", &mut b"0 1\n1 0\n".as_slice(), None).unwrap();
+        assert_eq!(text.format, Some("themisto".to_string()));
+        let plan = plan_concatenate(vec![inputs[0].clone(), text], &Default::default()).unwrap();
+        assert_eq!(plan.problems, vec!["input `aln.txt` is not .ahda data".to_string()]);
+        assert!(plan.check().is_err());
+    }
+
+    #[test]
+    fn add_output_checks_path() {
+        use super::Plan;
+
+        let dir = std::env::temp_dir().join(format!("ahda-dry-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("exists.ahda"), b"").unwrap();
+        std::fs::write(dir.join("overwrite.ahda"), b"").unwrap();
+
+        let mut plan = Plan::default();
+        plan.add_output(&dir.join("new.ahda"), false);
+        plan.add_output(&dir.join("overwrite.ahda"), true);
+        assert!(plan.is_ok());
+
+        plan.add_output(&dir.join("exists.ahda"), false);
+        plan.add_output(&dir.join("missing").join("out.ahda"), false);
+        plan.add_output(&dir.join("new.ahda"), false);
+        assert_eq!(plan.problems.len(), 3);
+        assert!(plan.problems[0].ends_with("exists, use `--force` to overwrite it"));
+        assert!(plan.problems[1].ends_with("doesn't exist"));
+        assert!(plan.problems[2].ends_with("is written by several inputs"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
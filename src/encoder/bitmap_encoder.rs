@@ -13,26 +13,75 @@
 //
 
 //! Encoder implementation for an iterator over set bit indexes.
-
+//!
+//! [BitmapEncoder] encodes a pseudoalignment given as the set bits of the
+//! flattened `n_queries` x `n_targets` alignment matrix. The bit of query
+//! `query_id` aligning to target `target_id` has the 64-bit index
+//! `query_id * n_targets + target_id`, where `n_targets` is the number of
+//! targets given to the constructor. This is the layout that
+//! [decode_from_read_to_roaring](crate::decode_from_read_to_roaring) returns.
+//!
+//! The input must be sorted in ascending order, so that each block can be
+//! written as soon as the first bit of the next block is read. Repeated
+//! indexes are merged. [BitmapEncoder::new] takes the bit indexes and
+//! [BitmapEncoder::from_pairs] takes `(query_id, target_id)` pairs and
+//! computes the indexes. Both check the input while encoding and return an
+//! error from [next](BitmapEncoder::next) instead of writing a corrupted
+//! block if it is unsorted or out of range.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::encoder::bitmap_encoder::BitmapEncoder;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+//!
+//! // r1 aligns to chr.fasta and r3 to both targets
+//! let mut pairs = vec![(0_u32, 0_u32), (2, 0), (2, 1)].into_iter();
+//! let mut encoder = BitmapEncoder::from_pairs(&mut pairs, &targets, &queries, b"sample");
+//!
+//! let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+//! for block in encoder.by_ref() {
+//!     bytes.extend(block.unwrap());
+//! }
+//!
+//! let mut conn_in = std::io::Cursor::new(bytes);
+//! let (bitmap, _, _, _) = ahda::decode_from_read_to_roaring(&mut conn_in).unwrap();
+//! assert_eq!(bitmap.iter().collect::<Vec<u64>>(), vec![0, 4, 5]);
+//! ```
+//!
+
+use crate::headers::extensions::Extensions;
 use crate::headers::file::FileHeader;
+use crate::headers::file::TargetGroups;
+use crate::headers::file::Provenance;
 use crate::headers::file::FileFlags;
 use crate::headers::file::build_file_header_and_flags;
+use crate::headers::file::default_block_size;
 use crate::headers::file::encode_file_header;
 use crate::headers::file::encode_file_flags;
 use crate::compression::BitmapType;
 use crate::compression::MetadataCompression;
+use crate::compression::check_shape;
 use crate::compression::roaring32::pack_block_roaring32;
 use crate::compression::roaring64::pack_block_roaring64;
+use crate::errors::QueryIndexOutOfRangeErr;
+use crate::errors::SetBitsIteratorNotSortedErr;
+use crate::errors::TargetIndexOutOfRangeErr;
 
 use roaring::RoaringBitmap;
 use roaring::RoaringTreemap;
 
 type E = Box<dyn std::error::Error>;
 
-pub struct BitmapEncoder<'a, I: Iterator> where I: Iterator<Item=u64> {
+/// Encoder for the sorted set bit indexes of a flattened pseudoalignment.
+///
+/// Implements [Iterator] over the encoded blocks, see the [module
+/// documentation](crate::encoder::bitmap_encoder) for the input contract.
+pub struct BitmapEncoder<'a> {
     // Input iterator
-    set_bits: &'a mut I,
-    end: bool,
+    set_bits: Box<dyn Iterator<Item = Result<u64, E>> + 'a>,
 
     // These are given as construtor parameters
     header: FileHeader,
@@ -41,27 +90,61 @@ pub struct BitmapEncoder<'a, I: Iterator> where I: Iterator<Item=u64> {
 
     // Internals
     blocks_written: usize,
-    bits_buffer: Vec<u64>,
-    last_idx: usize,
-    prev_idx: u64,
+    // First bit of a later block, read while filling the current block
+    pending: Option<u64>,
+    prev_idx: Option<u64>,
 }
 
-impl<'a, I: Iterator> BitmapEncoder<'a, I> where I: Iterator<Item=u64> {
-    pub fn new(
+impl<'a> BitmapEncoder<'a> {
+    /// Encode the set bit indexes in `set_bits`.
+    ///
+    /// `set_bits` must be sorted in ascending order and each index must be
+    /// less than `queries.len() * targets.len()`.
+    pub fn new<I: Iterator<Item=u64>>(
         set_bits: &'a mut I,
         targets: &[Vec<u8>],
         queries: &[Vec<u8>],
         sample_name: &[u8],
+    ) -> Self {
+        BitmapEncoder::with_set_bits(Box::new(set_bits.map(Ok)), targets, queries, sample_name)
+    }
+
+    /// Encode the `(query_id, target_id)` pairs in `pairs`.
+    ///
+    /// `pairs` must be sorted by query id and then by target id, and the ids
+    /// must be less than `queries.len()` and `targets.len()`.
+    pub fn from_pairs<I: Iterator<Item=(u32, u32)>>(
+        pairs: &'a mut I,
+        targets: &[Vec<u8>],
+        queries: &[Vec<u8>],
+        sample_name: &[u8],
+    ) -> Self {
+        let n_targets = targets.len() as u64;
+        let set_bits = pairs.map(move |(query_id, target_id)| {
+            if target_id as u64 >= n_targets {
+                return Err(Box::new(TargetIndexOutOfRangeErr{}) as E)
+            }
+            Ok(query_id as u64 * n_targets + target_id as u64)
+        });
+
+        BitmapEncoder::with_set_bits(Box::new(set_bits), targets, queries, sample_name)
+    }
+
+    fn with_set_bits(
+        set_bits: Box<dyn Iterator<Item = Result<u64, E>> + 'a>,
+        targets: &[Vec<u8>],
+        queries: &[Vec<u8>],
+        sample_name: &[u8],
     ) -> Self {
         let (header, flags) = build_file_header_and_flags(targets, queries.len(), sample_name, &MetadataCompression::default()).unwrap();
 
         BitmapEncoder{
-            set_bits, end: false,
+            set_bits,
             header, flags,
             queries: queries.to_vec(),
             blocks_written: 0_usize,
-            bits_buffer: Vec::new(), last_idx: 0_usize,
-            prev_idx: 0,
+            pending: None,
+            prev_idx: None,
         }
     }
 
@@ -74,12 +157,50 @@ impl<'a, I: Iterator> BitmapEncoder<'a, I> where I: Iterator<Item=u64> {
     ) {
         self.header.fields_present = fields_present;
     }
-}
 
-impl<I: Iterator> BitmapEncoder<'_, I> where I: Iterator<Item=u64> {
+    /// Update `target_groups` in stored FileFlags.
+    ///
+    /// Should be called before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    ///
+    /// Errors with [InvalidTargetGroupsErr](crate::errors::InvalidTargetGroupsErr)
+    /// if a level does not assign every target to a group.
+    pub fn set_target_groups(
+        &mut self,
+        target_groups: Vec<TargetGroups>,
+    ) -> Result<(), E> {
+        target_groups.iter().try_for_each(|groups| groups.validate(self.header.n_targets as usize))?;
+        self.flags.target_groups = target_groups;
+        self.header.flags_len = encode_file_flags(&self.flags, &MetadataCompression::from_u8(self.header.metadata_compression)?)?.len() as u64;
+        Ok(())
+    }
+
+    /// Record how the encoded data was derived from other files.
+    pub fn set_provenance(
+        &mut self,
+        provenance: Provenance,
+    ) -> Result<(), E> {
+        self.flags.provenance = Some(provenance);
+        self.header.flags_len = encode_file_flags(&self.flags, &MetadataCompression::from_u8(self.header.metadata_compression)?)?.len() as u64;
+        Ok(())
+    }
+
+    /// Store fields added by other tools in FileFlags.
+    pub fn set_extensions(
+        &mut self,
+        extensions: Extensions,
+    ) -> Result<(), E> {
+        self.flags.extensions = Some(extensions);
+        self.header.flags_len = encode_file_flags(&self.flags, &MetadataCompression::from_u8(self.header.metadata_compression)?)?.len() as u64;
+        Ok(())
+    }
+
+    /// Encode the FileHeader and FileFlags that precede the blocks.
     pub fn encode_file_header_and_flags(
         &mut self,
     ) -> Result<Vec<u8>, E> {
+        if let Some(warning) = check_shape(&self.header) {
+            log::warn!("{}, {}", warning, warning.suggestion());
+        }
         let mut flags_bytes = encode_file_flags(&self.flags, &MetadataCompression::from_u8(self.header.metadata_compression)?)?;
         let mut header_bytes = encode_file_header(&self.header)?;
 
@@ -90,6 +211,10 @@ impl<I: Iterator> BitmapEncoder<'_, I> where I: Iterator<Item=u64> {
         Ok(out)
     }
 
+    /// Set the number of queries in each block.
+    ///
+    /// Roaring32 blocks are capped at 65536 queries and all blocks have at
+    /// least 2 queries.
     pub fn set_block_size(
         &mut self,
         block_size: usize
@@ -110,92 +235,87 @@ impl<I: Iterator> BitmapEncoder<'_, I> where I: Iterator<Item=u64> {
         Ok(())
     }
 
-    pub fn build_roaring32(
-        &mut self
-    ) -> Option<RoaringBitmap> {
-        if !self.bits_buffer.is_empty() && self.end {
-            let bits = self.bits_buffer.iter().map(|x| *x as u32);
-            let bitmap = RoaringBitmap::from_iter(bits);
-            self.bits_buffer.clear();
-            Some(bitmap)
-        } else if !self.bits_buffer.is_empty() {
-            let bits = self.bits_buffer.iter().take(self.bits_buffer.len() - 1).map(|x| *x as u32);
-            let bitmap = RoaringBitmap::from_iter(bits);
-            self.bits_buffer = self.bits_buffer[(self.bits_buffer.len() - 1)..self.bits_buffer.len()].to_vec();
-            Some(bitmap)
-        } else if self.last_idx < self.header.n_queries as usize && self.end {
-            Some(RoaringBitmap::new())
-        } else {
-            None
+    /// Force the bitmap type instead of choosing it from the number of queries and targets.
+    ///
+    /// Resets the block size to the default for `bitmap_type`. Errors with
+    /// [Roaring32OverflowErr](crate::errors::Roaring32OverflowErr) if
+    /// `bitmap_type` is [Roaring32](BitmapType::Roaring32) and the queries
+    /// and targets don't fit in it, see [BitmapType::for_size].
+    ///
+    /// Should be called before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    pub fn set_bitmap_type(
+        &mut self,
+        bitmap_type: BitmapType,
+    ) -> Result<(), E> {
+        let n_targets = self.header.n_targets as usize;
+        if bitmap_type == BitmapType::Roaring32 && BitmapType::for_size(n_targets, self.header.n_queries as usize) != BitmapType::Roaring32 {
+            return Err(Box::new(crate::errors::Roaring32OverflowErr{}))
         }
+        self.header.block_size = default_block_size(&bitmap_type, n_targets);
+        self.header.bitmap_type = bitmap_type.to_u16();
+        Ok(())
     }
 
-    pub fn build_roaring64(
-        &mut self
-    ) -> Option<RoaringTreemap> {
-        if !self.bits_buffer.is_empty() && self.end {
-            let bits = self.bits_buffer.iter();
-            let bitmap = RoaringTreemap::from_iter(bits);
-            self.bits_buffer.clear();
-            Some(bitmap)
-        } else if !self.bits_buffer.is_empty() {
-            let bits = self.bits_buffer.iter().take(self.bits_buffer.len() - 1);
-            let bitmap = RoaringTreemap::from_iter(bits);
-            self.bits_buffer = self.bits_buffer[(self.bits_buffer.len() - 1)..self.bits_buffer.len()].to_vec();
-            Some(bitmap)
-        } else if self.last_idx < self.header.n_queries as usize && self.end {
-            Some(RoaringTreemap::new())
-        } else {
-            None
+    // Next set bit from the input, checking that it is sorted and in range
+    fn next_bit(
+        &mut self,
+    ) -> Option<Result<u64, E>> {
+        let next_idx = match self.set_bits.next()? {
+            Ok(next_idx) => next_idx,
+            Err(e) => return Some(Err(e)),
+        };
+        if self.prev_idx.is_some_and(|prev| next_idx < prev) {
+            return Some(Err(Box::new(SetBitsIteratorNotSortedErr{})))
         }
+        if next_idx >= self.header.n_queries as u64 * self.header.n_targets as u64 {
+            return Some(Err(Box::new(QueryIndexOutOfRangeErr{})))
+        }
+        self.prev_idx = Some(next_idx);
+        Some(Ok(next_idx))
     }
 }
 
-impl<I: Iterator> Iterator for BitmapEncoder<'_, I> where I: Iterator<Item=u64> {
+impl Iterator for BitmapEncoder<'_> {
     type Item = Result<Vec<u8>, E>;
 
     fn next(
         &mut self,
     ) -> Option<Result<Vec<u8>, E>> {
-        let end_idx = ((self.blocks_written + 1) * self.header.block_size as usize).min(self.header.n_queries as usize) as u64;
-        let n_targets = self.header.n_targets as u64;
-        loop {
-            if let Some(next_idx) = self.set_bits.next() {
-                if next_idx < self.prev_idx {
-                    return Some(Err(Box::new(crate::errors::SetBitsIteratorNotSortedErr{})))
-                }
-                self.prev_idx = next_idx;
+        let start_idx = self.blocks_written * self.header.block_size as usize;
+        if start_idx >= self.header.n_queries as usize {
+            return None
+        }
+        let end_idx = (start_idx + self.header.block_size as usize).min(self.header.n_queries as usize);
+        let end_bit = end_idx as u64 * self.header.n_targets as u64;
 
-                self.bits_buffer.push(next_idx);
-                if next_idx > end_idx * n_targets {
-                    break;
-                }
-            } else {
-                self.end = true;
+        // Bits of later blocks are kept for the next call
+        let mut bits: Vec<u64> = Vec::new();
+        loop {
+            let next_idx = match self.pending.take() {
+                Some(next_idx) => next_idx,
+                None => match self.next_bit() {
+                    Some(Ok(next_idx)) => next_idx,
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => break,
+                },
+            };
+            if next_idx >= end_bit {
+                self.pending = Some(next_idx);
                 break;
             }
+            bits.push(next_idx);
         }
 
-        let bytes = match BitmapType::from_u16(self.header.bitmap_type).unwrap() {
-            BitmapType::Roaring32 => {
-                let start_idx = self.blocks_written * self.header.block_size as usize;
-                let block_ids = ((start_idx as u32)..(end_idx as u32)).collect::<Vec<u32>>();
-                self.blocks_written += 1;
-                self.last_idx = end_idx as usize;
-                let bitmap = self.build_roaring32()?;
-                pack_block_roaring32(&self.queries[start_idx..(end_idx.try_into().unwrap())], &block_ids, bitmap).unwrap()
-            },
-            BitmapType::Roaring64 => {
-                let start_idx = self.blocks_written * self.header.block_size as usize;
-                let block_ids = ((start_idx as u32)..(end_idx as u32)).collect::<Vec<u32>>();
-                self.blocks_written += 1;
-                self.last_idx = end_idx as usize;
-                let bitmap = self.build_roaring64()?;
-                pack_block_roaring64(&self.queries[start_idx..(end_idx.try_into().unwrap())], &block_ids, bitmap).unwrap()
-            }
+        let block_ids = ((start_idx as u32)..(end_idx as u32)).collect::<Vec<u32>>();
+        let queries = &self.queries[start_idx..end_idx];
+        self.blocks_written += 1;
+        let bytes = match BitmapType::from_u16(self.header.bitmap_type) {
+            Ok(BitmapType::Roaring32) => pack_block_roaring32(queries, &block_ids, RoaringBitmap::from_iter(bits.into_iter().map(|x| x as u32))),
+            Ok(BitmapType::Roaring64) => pack_block_roaring64(queries, &block_ids, RoaringTreemap::from_iter(bits)),
+            Err(e) => Err(e),
         };
 
-        Some(Ok(bytes))
+        Some(bytes)
     }
 
 }
@@ -209,7 +329,7 @@ mod tests {
 
         let data = vec![0_u64, 2, 4, 5, 7];
 
-        let expected = vec![97, 104, 100, 97, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97];
+        let expected = vec![97, 104, 100, 97, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
@@ -229,7 +349,7 @@ mod tests {
 
         let data = vec![0_u64, 2, 4, 5, 7];
 
-        let expected = vec![5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let expected = vec![5, 0, 0, 0, 0, 0, 0, 4, 26, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 16, 0, 0, 0, 0, 0, 2, 0, 4, 0, 5, 0, 7, 0];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
@@ -271,7 +391,8 @@ mod tests {
 
         let data = vec![0_u64, 2, 4, 5, 7];
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 2, 0, 0, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 38, 6, 1, 40, 205, 194, 0, 0, 207, 21, 220, 40, 22, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 37, 0, 0, 0, 51, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 18, 116, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 51, 53, 180, 52, 48, 230, 71, 18, 49, 55, 53, 49, 102, 100, 98, 98, 6, 0, 108, 239, 38, 102, 40, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 6, 6, 6, 86, 6, 118, 6, 0, 242, 32, 178, 210, 20, 0, 0, 0];
+        // Query 2 (bits 4 and 5) starts the second block and query 4 has no alignments
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 2, 0, 0, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 4, 20, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 16, 0, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 4, 22, 0, 0, 0, 51, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 18, 116, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 51, 53, 180, 52, 48, 230, 71, 18, 49, 55, 53, 49, 102, 100, 98, 98, 6, 0, 108, 239, 38, 102, 40, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 2, 0, 16, 0, 0, 0, 4, 0, 5, 0, 7, 0, 1, 0, 0, 0, 0, 0, 0, 4, 8, 0, 0, 0, 39, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 117, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 52, 99, 100, 100, 1, 0, 252, 37, 226, 246, 19, 0, 0, 0, 58, 48, 0, 0, 0, 0, 0, 0];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
@@ -291,6 +412,51 @@ mod tests {
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn from_pairs_with_empty_blocks() {
+        use super::BitmapEncoder;
+        use crate::Format;
+        use crate::decode_from_read_to_write;
+        use std::io::Cursor;
+
+        let targets = vec![b"a".to_vec(), b"b".to_vec()];
+        let queries: Vec<Vec<u8>> = (0..7).map(|x| format!("r{}", x).into_bytes()).collect();
+
+        // Queries 2 to 5 and the blocks containing them have no alignments
+        let mut pairs = vec![(0_u32, 1_u32), (1, 0), (1, 1), (6, 0)].into_iter();
+        let mut encoder = BitmapEncoder::from_pairs(&mut pairs, &targets, &queries, b"sample");
+        encoder.set_fields_present(3_u16);
+        encoder.set_block_size(2).unwrap();
+
+        let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+        let mut n_blocks = 0;
+        for block in encoder.by_ref() {
+            bytes.extend(block.unwrap());
+            n_blocks += 1;
+        }
+        assert_eq!(n_blocks, 4);
+
+        let mut got: Vec<u8> = Vec::new();
+        decode_from_read_to_write(Format::Themisto, &mut Cursor::new(bytes), &mut got).unwrap();
+        assert_eq!(String::from_utf8(got).unwrap(), "0 1\n1 0 1\n2\n3\n4\n5\n6 0\n");
+    }
+
+    #[test]
+    fn from_pairs_errors_on_out_of_range_ids() {
+        use super::BitmapEncoder;
+
+        let targets = vec![b"a".to_vec(), b"b".to_vec()];
+        let queries = vec![b"r0".to_vec(), b"r1".to_vec()];
+
+        let mut pairs = vec![(0_u32, 2_u32)].into_iter();
+        let mut encoder = BitmapEncoder::from_pairs(&mut pairs, &targets, &queries, b"sample");
+        assert!(encoder.next().unwrap().is_err());
+
+        let mut pairs = vec![(0_u32, 0_u32), (2, 0)].into_iter();
+        let mut encoder = BitmapEncoder::from_pairs(&mut pairs, &targets, &queries, b"sample");
+        assert!(encoder.next().unwrap().is_err());
+    }
+
     #[test]
     fn encode_three_blocks_with_next_on_shuffled_bits() {
         use super::BitmapEncoder;
@@ -18,7 +18,9 @@
 //! other struct that implements an iterator returning [PseudoAln] data.
 //!
 //! Calling next on Encoder will return a single block consisting of encoded
-//! bytes representing all records in the block.
+//! bytes representing all records in the block, or an error if a record in
+//! the block can't be encoded (e.g. it refers to a target index that is not
+//! in `targets`).
 //!
 //! To create a valid .ahda record, [Encoder::encode_file_header_and_flags] should be
 //! called first and its output included as the first bytes in the record. This
@@ -83,7 +85,7 @@
 //!
 //! // Iterate over `encoder` to get the 2 encoded blocks
 //! for block in encoder.by_ref() {
-//!     output.write_all(&block).unwrap();
+//!     output.write_all(&block.unwrap()).unwrap();
 //! }
 //!
 //! // The alignments can be decoded from `output`
@@ -128,7 +130,7 @@
 //!
 //! // Iterate over `encoder` to get the 2 encoded blocks
 //! for block in encoder.by_ref() {
-//!     output.write_all(&block).unwrap();
+//!     output.write_all(&block.unwrap()).unwrap();
 //! }
 //!
 //! // The alignments can be decoded from `output`
@@ -144,15 +146,48 @@
 //!
 
 pub mod bitmap_encoder;
+pub mod sorted;
+pub mod streaming;
 
 use crate::PseudoAln;
+use crate::headers::extensions::Extensions;
 use crate::headers::file::FileHeader;
+use crate::headers::file::FileFlags;
+use crate::headers::file::TargetAliases;
+use crate::headers::file::TargetGroups;
 use crate::headers::file::build_file_header_and_flags;
+use crate::coverage::Coverage;
+use crate::coverage::COVERAGE_KEY;
+use crate::lanes::Lanes;
+use crate::lanes::LANES_KEY;
+use crate::panel::Panel;
+use crate::panel::PANEL_KEY;
+use crate::max_hits::MaxHits;
+use crate::max_hits::MAX_HITS_KEY;
+use crate::target_chunks::TargetChunks;
+use crate::target_chunks::TARGET_CHUNKS_KEY;
+use crate::target_summary::TargetSummary;
+use crate::headers::file::default_block_size;
 use crate::headers::file::encode_file_header;
 use crate::headers::file::encode_file_flags;
+use crate::headers::block::decode_block_header;
+use crate::headers::block::encode_block_header;
 use crate::compression::BitmapType;
 use crate::compression::MetadataCompression;
-use crate::compression::pack_records;
+use crate::compression::gzwrapper::DEFAULT_LEVEL;
+use crate::compression::gzwrapper::check_level;
+use crate::compression::flags_dictionary::FlagsDictionary;
+use crate::compression::flags_dictionary::FLAGS_DICTIONARY_KEY;
+use crate::compression::INLINE_MAX_RECORDS;
+use crate::compression::BLOCK_QUERY_IDS_SORTED;
+use crate::compression::FILE_HAS_TARGET_CHUNKS;
+use crate::compression::check_shape;
+use crate::compression::pack_records_inline;
+use crate::compression::pack_records_with_extensions;
+
+use roaring::RoaringBitmap;
+
+use std::time::Instant;
 
 type E = Box<dyn std::error::Error>;
 
@@ -162,10 +197,27 @@ pub struct Encoder<'a, I: Iterator> where I: Iterator<Item=PseudoAln> {
 
     // These are given as construtor parameters
     header: FileHeader,
+    flags: FileFlags,
 
     // Internals
     flags_bytes: Vec<u8>,
+    compression_level: u32,
+    target_aliases: Option<TargetAliases>,
+    second_mates: Option<RoaringBitmap>,
+    query_filter_fpr: Option<f64>,
+    max_hits: Option<MaxHits>,
+    flags_dictionary: Option<FlagsDictionary>,
+    target_summary: bool,
+    target_chunks: Option<TargetChunks>,
+    inline_max_records: usize,
+    records_capped: usize,
     blocks_written: usize,
+    records_written: usize,
+    // Largest query id in the previous block
+    last_query_id: Option<u32>,
+    started: Option<Instant>,
+    // Records read ahead to train the flags dictionary
+    pending: std::vec::IntoIter<PseudoAln>,
     block: Vec<PseudoAln>,
 }
 
@@ -183,8 +235,22 @@ impl<'a, I: Iterator> Encoder<'a, I> where I: Iterator<Item=PseudoAln> {
         Encoder{
             records,
             block: Vec::with_capacity(header.block_size as usize),
-            header, flags_bytes,
+            header, flags, flags_bytes,
+            compression_level: DEFAULT_LEVEL,
+            target_aliases: None,
+            second_mates: None,
+            query_filter_fpr: None,
+            max_hits: None,
+            flags_dictionary: None,
+            target_summary: false,
+            target_chunks: None,
+            inline_max_records: INLINE_MAX_RECORDS,
+            records_capped: 0_usize,
             blocks_written: 0_usize,
+            records_written: 0_usize,
+            last_query_id: None,
+            started: None,
+            pending: Vec::new().into_iter(),
         }
     }
 }
@@ -193,6 +259,9 @@ impl<I: Iterator> Encoder<'_, I> where I: Iterator<Item=PseudoAln> {
     pub fn encode_file_header_and_flags(
         &mut self,
     ) -> Result<Vec<u8>, E> {
+        if let Some(warning) = check_shape(&self.header) {
+            log::warn!("{}, {}", warning, warning.suggestion());
+        }
         let mut header_bytes = encode_file_header(&self.header)?;
 
         let mut out: Vec<u8> = Vec::with_capacity(self.header.flags_len as usize + header_bytes.len());
@@ -222,6 +291,40 @@ impl<I: Iterator> Encoder<'_, I> where I: Iterator<Item=PseudoAln> {
         Ok(())
     }
 
+    /// Force the bitmap type instead of choosing it from the number of queries and targets.
+    ///
+    /// Resets the block size to the default for `bitmap_type`. Errors with
+    /// [Roaring32OverflowErr](crate::errors::Roaring32OverflowErr) if
+    /// `bitmap_type` is [Roaring32](BitmapType::Roaring32) and the queries
+    /// and targets don't fit in it, see [BitmapType::for_size].
+    ///
+    /// Should be called before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    pub fn set_bitmap_type(
+        &mut self,
+        bitmap_type: BitmapType,
+    ) -> Result<(), E> {
+        // Sub-blocks store the targets of one chunk
+        let n_targets = self.target_chunks.map_or(self.header.n_targets, |x| x.width()) as usize;
+        if bitmap_type == BitmapType::Roaring32 && BitmapType::for_size(n_targets, self.header.n_queries as usize) != BitmapType::Roaring32 {
+            return Err(Box::new(crate::errors::Roaring32OverflowErr{}))
+        }
+        self.header.block_size = default_block_size(&bitmap_type, n_targets);
+        self.header.bitmap_type = bitmap_type.to_u16();
+        Ok(())
+    }
+
+    /// Set the gzip compression level for the blocks, from 1 (fastest) to 9 (smallest).
+    ///
+    /// See [compression](crate::compression) for the trade-offs.
+    pub fn set_compression_level(
+        &mut self,
+        level: u32,
+    ) -> Result<(), E> {
+        check_level(level)?;
+        self.compression_level = level;
+        Ok(())
+    }
+
     /// Update `fields_present` in stored FileHeader.
     ///
     /// Should be called before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
@@ -230,27 +333,392 @@ impl<I: Iterator> Encoder<'_, I> where I: Iterator<Item=PseudoAln> {
         fields_present: u16,
     ) {
         self.header.fields_present = fields_present;
+        if self.second_mates.is_some() {
+            self.header.fields_present |= crate::MASK_MATES;
+        }
+        if self.query_filter_fpr.is_some() {
+            self.header.fields_present |= crate::MASK_QUERY_FILTER;
+        }
+        if self.target_chunks.is_some() {
+            self.header.fields_present |= FILE_HAS_TARGET_CHUNKS;
+        }
+    }
+
+    /// Store which mate of a read pair each query is.
+    ///
+    /// Queries whose id is in `second_mates` are stored as the second mate
+    /// and all other queries as the first mate, see
+    /// [BlockFlags::mate](crate::headers::block::BlockFlags::mate).
+    ///
+    /// Should be called before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    pub fn set_second_mates(
+        &mut self,
+        second_mates: RoaringBitmap,
+    ) {
+        self.second_mates = Some(second_mates);
+        self.header.fields_present |= crate::MASK_MATES;
+    }
+
+    /// Store a Bloom filter of the query names in each block.
+    ///
+    /// The filters are built with false positive rate `fpr` and let
+    /// [Decoder::grep](crate::decoder::Decoder::grep) skip blocks that don't
+    /// contain the names it looks for, see
+    /// [BlockFlags::might_contain](crate::headers::block::BlockFlags::might_contain).
+    ///
+    /// Should be called after [set_fields_present](Encoder::set_fields_present)
+    /// and before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    ///
+    /// Errors with [MissingQueryNamesErr](crate::errors::MissingQueryNamesErr)
+    /// if the query names are not stored.
+    pub fn set_query_filter(
+        &mut self,
+        fpr: f64,
+    ) -> Result<(), E> {
+        if !self.header.promises_query_names() {
+            return Err(Box::new(crate::errors::MissingQueryNamesErr{}))
+        }
+        self.query_filter_fpr = Some(fpr);
+        self.header.fields_present |= crate::MASK_QUERY_FILTER;
+        Ok(())
+    }
+
+    /// Store the targets that each block has hits to in its block flags.
+    ///
+    /// Lets [Decoder::slice](crate::decoder::Decoder::slice) skip the blocks
+    /// without hits to the targets it looks for, see
+    /// [TargetSummary](crate::target_summary::TargetSummary). The summary
+    /// lists the targets after [set_target_aliases](Encoder::set_target_aliases)
+    /// and [set_max_hits](Encoder::set_max_hits) are applied.
+    pub fn set_target_summary(
+        &mut self,
+    ) {
+        self.target_summary = true;
+    }
+
+    /// Split the targets of each block across sub-blocks of `width` targets, see [target_chunks](crate::target_chunks).
+    ///
+    /// Picks the bitmap type and block size as if the file had `width`
+    /// targets, see [BitmapType::auto], and stores `width` in the FileFlags
+    /// extensions under [TARGET_CHUNKS_KEY]. Should be called after
+    /// [set_target_aliases](Encoder::set_target_aliases) and
+    /// [set_extensions](Encoder::set_extensions), which replace the header
+    /// and the extensions, and before [set_bitmap_type](Encoder::set_bitmap_type)
+    /// and [set_block_size](Encoder::set_block_size).
+    ///
+    /// Errors with [InvalidTargetChunksErr](crate::errors::InvalidTargetChunksErr)
+    /// if `width` is 0.
+    pub fn set_target_chunks(
+        &mut self,
+        width: u32,
+    ) -> Result<(), E> {
+        let target_chunks = TargetChunks::new(width, self.header.n_targets)?;
+        let mut extensions = self.flags.extensions.take().unwrap_or_default();
+        extensions.insert_as(TARGET_CHUNKS_KEY, &width)?;
+        self.target_chunks = Some(target_chunks);
+        self.set_extensions(extensions)?;
+
+        let bitmap_type = BitmapType::auto(width as usize, self.header.n_queries as usize);
+        self.header.fields_present |= FILE_HAS_TARGET_CHUNKS;
+        self.header.block_size = default_block_size(&bitmap_type, width as usize);
+        self.header.bitmap_type = bitmap_type.to_u16();
+        Ok(())
+    }
+
+    /// Consider storing files of at most `max_records` records in a single block inline.
+    ///
+    /// The only block of these files is stored without compressing its
+    /// flags and bitmap if that is smaller, see
+    /// [pack_records_inline](crate::compression::pack_records_inline).
+    /// Defaults to [INLINE_MAX_RECORDS], and 0 compresses every block.
+    /// Files with a [FlagsDictionary] are never stored inline.
+    pub fn set_inline_max_records(
+        &mut self,
+        max_records: usize,
+    ) {
+        self.inline_max_records = max_records;
+    }
+
+    /// Update `target_groups` in stored FileFlags.
+    ///
+    /// Should be called before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    ///
+    /// Errors with [InvalidTargetGroupsErr](crate::errors::InvalidTargetGroupsErr)
+    /// if a level does not assign every target to a group.
+    pub fn set_target_groups(
+        &mut self,
+        target_groups: Vec<TargetGroups>,
+    ) -> Result<(), E> {
+        target_groups.iter().try_for_each(|groups| groups.validate(self.header.n_targets as usize))?;
+        self.flags.target_groups = target_groups;
+        self.flags_bytes = encode_file_flags(&self.flags, &MetadataCompression::from_u8(self.header.metadata_compression)?)?;
+        self.header.flags_len = self.flags_bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Store fields added by other tools in FileFlags.
+    ///
+    /// The dictionary set with [set_flags_dictionary](Encoder::set_flags_dictionary)
+    /// and the width set with [set_target_chunks](Encoder::set_target_chunks)
+    /// are kept. Should be called before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    pub fn set_extensions(
+        &mut self,
+        mut extensions: Extensions,
+    ) -> Result<(), E> {
+        // The blocks can't be decoded without the dictionary
+        if let Some(dictionary) = &self.flags_dictionary {
+            extensions.insert(FLAGS_DICTIONARY_KEY, dictionary.as_bytes().to_vec());
+        }
+        if let Some(target_chunks) = &self.target_chunks {
+            extensions.insert_as(TARGET_CHUNKS_KEY, &target_chunks.width())?;
+        }
+        self.flags.extensions = Some(extensions);
+        self.flags_bytes = encode_file_flags(&self.flags, &MetadataCompression::from_u8(self.header.metadata_compression)?)?;
+        self.header.flags_len = self.flags_bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Cap the number of targets of each record, see [MaxHits].
+    ///
+    /// The cap is applied to the targets after merging them with
+    /// [set_target_aliases](Encoder::set_target_aliases) and stored in the
+    /// FileFlags extensions under [MAX_HITS_KEY]. Should be called after
+    /// [set_extensions](Encoder::set_extensions), which replaces the
+    /// extensions, and before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    pub fn set_max_hits(
+        &mut self,
+        max_hits: MaxHits,
+    ) -> Result<(), E> {
+        let mut extensions = self.flags.extensions.take().unwrap_or_default();
+        extensions.insert_as(MAX_HITS_KEY, &max_hits)?;
+        self.set_extensions(extensions)?;
+        self.max_hits = Some(max_hits);
+        Ok(())
+    }
+
+    /// Record the lanes that the queries were read from, see [Lanes].
+    ///
+    /// The lanes are stored in the FileFlags extensions under [LANES_KEY].
+    /// Should be called after [set_extensions](Encoder::set_extensions),
+    /// which replaces the extensions, and before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    pub fn set_lanes(
+        &mut self,
+        lanes: &Lanes,
+    ) -> Result<(), E> {
+        let mut extensions = self.flags.extensions.take().unwrap_or_default();
+        extensions.insert_as(LANES_KEY, lanes)?;
+        self.set_extensions(extensions)
+    }
+
+    /// Record the coverage of the targets, see [Coverage].
+    ///
+    /// The coverage is stored in the FileFlags extensions under [COVERAGE_KEY].
+    /// Should be called after [set_extensions](Encoder::set_extensions),
+    /// which replaces the extensions, and before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    pub fn set_coverage(
+        &mut self,
+        coverage: &Coverage,
+    ) -> Result<(), E> {
+        let mut extensions = self.flags.extensions.take().unwrap_or_default();
+        extensions.insert_as(COVERAGE_KEY, coverage)?;
+        self.set_extensions(extensions)
+    }
+
+    /// Record the [Panel] of the target names with an optional `index_version`.
+    ///
+    /// The panel is stored in the FileFlags extensions under [PANEL_KEY].
+    /// Should be called after [set_target_aliases](Encoder::set_target_aliases),
+    /// which changes the target names, and after [set_extensions](Encoder::set_extensions),
+    /// which replaces the extensions.
+    pub fn set_panel(
+        &mut self,
+        index_version: Option<Vec<u8>>,
+    ) -> Result<(), E> {
+        let panel = Panel::new(&self.flags.target_names, index_version);
+        let mut extensions = self.flags.extensions.take().unwrap_or_default();
+        extensions.insert_as(PANEL_KEY, &panel)?;
+        self.set_extensions(extensions)
+    }
+
+    /// Compress the block flags with `dictionary`, see [FlagsDictionary].
+    ///
+    /// The dictionary is stored in the FileFlags extensions under
+    /// [FLAGS_DICTIONARY_KEY] and kept by [set_extensions](Encoder::set_extensions).
+    /// Should be called after [set_target_aliases](Encoder::set_target_aliases),
+    /// which replaces the FileFlags, and before using [encode_file_header_and_flags](crate::headers::file::encode_file_header_and_flags) to obtain the bytes.
+    pub fn set_flags_dictionary(
+        &mut self,
+        dictionary: FlagsDictionary,
+    ) -> Result<(), E> {
+        let mut extensions = self.flags.extensions.take().unwrap_or_default();
+        extensions.insert(FLAGS_DICTIONARY_KEY, dictionary.as_bytes().to_vec());
+        self.set_extensions(extensions)?;
+        self.flags_dictionary = Some(dictionary);
+        Ok(())
+    }
+
+    /// Train a [FlagsDictionary] of at most `max_size` bytes on the query names of the first `n_blocks` blocks.
+    ///
+    /// Reads the records of the first `n_blocks` blocks ahead and calls
+    /// [set_flags_dictionary](Encoder::set_flags_dictionary) with the
+    /// trained dictionary. Returns false and leaves the block flags gzipped
+    /// if the records have too few query names to train a dictionary. The
+    /// same ordering rules as for [set_flags_dictionary](Encoder::set_flags_dictionary)
+    /// apply, and the block size should be set first.
+    ///
+    /// Errors with [MissingFeatureErr](crate::errors::MissingFeatureErr) if
+    /// ahda was compiled without the `zstd` feature.
+    pub fn train_flags_dictionary(
+        &mut self,
+        n_blocks: usize,
+        max_size: usize,
+    ) -> Result<bool, E> {
+        let n_records = n_blocks.saturating_mul(self.header.block_size as usize);
+        let pending: Vec<PseudoAln> = self.pending.by_ref().chain(self.records.by_ref()).take(n_records).collect();
+        let names: Vec<Vec<u8>> = pending.iter().filter_map(|x| x.query_name.clone()).collect();
+        self.pending = pending.into_iter();
+
+        let Some(dictionary) = FlagsDictionary::train(&names, max_size)? else {
+            return Ok(false)
+        };
+        log::info!("trained a {} byte flags dictionary on {} query names", dictionary.as_bytes().len(), names.len());
+        self.set_flags_dictionary(dictionary)?;
+        Ok(true)
+    }
+
+    /// Number of records encoded so far.
+    pub fn records_written(
+        &self,
+    ) -> usize {
+        self.records_written
+    }
+
+    /// Number of records encoded so far that exceeded the cap set with [set_max_hits](Encoder::set_max_hits).
+    pub fn records_capped(
+        &self,
+    ) -> usize {
+        self.records_capped
+    }
+
+    /// Merge the targets into the fewer targets in `target_aliases`.
+    ///
+    /// The targets given to [Encoder::new] are the aliases, eg. contigs, and
+    /// the records are encoded against `target_aliases.target_names`, eg.
+    /// genomes, instead. If `store` is true, the aliases are kept in the
+    /// stored FileFlags.
+    ///
+    /// Replaces the FileHeader and FileFlags, so this should be called before
+    /// [set_block_size](Encoder::set_block_size),
+    /// [set_target_groups](Encoder::set_target_groups), and
+    /// [set_extensions](Encoder::set_extensions).
+    ///
+    /// Errors with [InvalidTargetAliasesErr](crate::errors::InvalidTargetAliasesErr)
+    /// if the aliases are not the targets given to [Encoder::new] or an alias
+    /// is not assigned to a target.
+    pub fn set_target_aliases(
+        &mut self,
+        target_aliases: TargetAliases,
+        store: bool,
+    ) -> Result<(), E> {
+        target_aliases.validate(self.header.n_targets as usize)?;
+        if target_aliases.alias_names != self.flags.target_names {
+            return Err(Box::new(crate::errors::InvalidTargetAliasesErr{}))
+        }
+
+        let compression = MetadataCompression::from_u8(self.header.metadata_compression)?;
+        let (mut header, mut flags) = build_file_header_and_flags(&target_aliases.target_names, self.header.n_queries as usize, &self.flags.query_name, &compression)?;
+        header.fields_present = self.header.fields_present;
+        if store {
+            flags.target_aliases = Some(target_aliases.clone());
+        }
+        self.flags_bytes = encode_file_flags(&flags, &compression)?;
+        header.flags_len = self.flags_bytes.len() as u64;
+
+        self.header = header;
+        self.flags = flags;
+        self.target_aliases = Some(target_aliases);
+        Ok(())
     }
 }
 
 impl<I: Iterator> Iterator for Encoder<'_, I> where I: Iterator<Item=PseudoAln> {
-    type Item = Vec<u8>;
+    type Item = Result<Vec<u8>, E>;
 
     fn next(
         &mut self,
-    ) -> Option<Vec<u8>> {
+    ) -> Option<Result<Vec<u8>, E>> {
+        let block_start = Instant::now();
+
         self.block.clear();
-        self.block.extend(self.records.take(self.header.block_size as usize).map(|mut x| { x.ones_names = None; x } ));
+        self.block.extend(self.pending.by_ref().chain(self.records.by_ref()).take(self.header.block_size as usize).map(|mut x| { x.ones_names = None; x } ));
+        if let Some(aliases) = &self.target_aliases {
+            self.block.iter_mut().for_each(|x| x.ones = x.ones.as_ref().map(|ones| aliases.to_targets(ones)));
+        }
+        if let Some(max_hits) = &self.max_hits {
+            self.records_capped += self.block.iter_mut().map(|x| max_hits.apply(x)).filter(|x| *x).count();
+        }
 
         if self.block.is_empty() {
+            if let Some(started) = self.started.take() {
+                let secs = started.elapsed().as_secs_f64();
+                log::info!("encoded {} records in {} blocks in {:.2}s ({:.0} records/s)", self.records_written, self.blocks_written, secs, self.records_written as f64 / secs.max(f64::MIN_POSITIVE));
+                if let Some(max_hits) = self.max_hits.as_ref().filter(|_| self.records_capped > 0) {
+                    log::warn!("capped {} records that aligned to more than {} targets ({})", self.records_capped, max_hits.max_hits, max_hits.action);
+                }
+            }
             return None
         }
+        self.started.get_or_insert(block_start);
 
         self.block.sort_by_key(|x| x.query_id);
 
-        let out = pack_records(&self.header, std::mem::take(&mut self.block)).unwrap();
+        let n_records = self.block.len();
+        let (first_id, last_id) = (self.block[0].query_id, self.block[n_records - 1].query_id);
+        let follows_previous = first_id.is_some_and(|first| self.last_query_id.is_none_or(|last| first > last));
+        let extensions = if self.target_summary {
+            let mut extensions = Extensions::default();
+            if let Err(e) = TargetSummary::from_records(&self.block).insert_into(&mut extensions) {
+                return Some(Err(e))
+            }
+            Some(extensions)
+        } else {
+            None
+        };
+        let mark_sorted = |mut block: Vec<u8>| -> Result<Vec<u8>, E> {
+            if follows_previous {
+                let mut header = decode_block_header(&block[0..32])?;
+                header.placeholder1 |= BLOCK_QUERY_IDS_SORTED;
+                block[0..32].copy_from_slice(&encode_block_header(&header)?);
+            }
+            Ok(block)
+        };
+        let out = if let Some(target_chunks) = &self.target_chunks {
+            // Sub-blocks are written back to back and are never inline
+            let header = target_chunks.chunk_header(&self.header);
+            target_chunks.split(&std::mem::take(&mut self.block)).and_then(|chunks| {
+                chunks.into_iter().try_fold(Vec::new(), |mut out, records| {
+                    let block = pack_records_with_extensions(&header, records, self.compression_level, self.second_mates.as_ref(), self.query_filter_fpr, self.flags_dictionary.as_ref(), extensions.clone())?;
+                    out.extend(mark_sorted(block)?);
+                    Ok(out)
+                })
+            })
+        } else {
+            // A first block shorter than the block size is the only block
+            let try_inline = self.blocks_written == 0 && n_records < self.header.block_size as usize && n_records <= self.inline_max_records && self.flags_dictionary.is_none();
+            let inline = try_inline.then(|| pack_records_inline(&self.header, self.block.clone(), self.second_mates.as_ref(), self.query_filter_fpr, extensions.clone()));
+            let out = pack_records_with_extensions(&self.header, std::mem::take(&mut self.block), self.compression_level, self.second_mates.as_ref(), self.query_filter_fpr, self.flags_dictionary.as_ref(), extensions);
+            // Compressing saves more than the gzip headers take in all but the smallest files
+            let out = match (out, inline) {
+                (Ok(compressed), Some(Ok(inline))) if inline.len() < compressed.len() => Ok(inline),
+                (out, _) => out,
+            };
+            out.and_then(mark_sorted)
+        };
+        self.last_query_id = last_id.or(self.last_query_id);
+        log::debug!("encoded block {} with {} records in {:.3}s", self.blocks_written, n_records, block_start.elapsed().as_secs_f64());
 
         self.blocks_written += 1;
+        self.records_written += n_records;
 
         Some(out)
     }
@@ -273,7 +741,7 @@ mod tests {
             PseudoAln{ones_names: Some(vec!["plasmid.fasta".as_bytes().to_vec()]),  query_id: Some(3), ones: Some(vec![1]), query_name: Some("ERR4035126.7543".as_bytes().to_vec()) },
         ];
 
-        let expected = vec![97, 104, 100, 97, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97];
+        let expected = vec![97, 104, 100, 97, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
@@ -300,7 +768,7 @@ mod tests {
             PseudoAln{ones_names: Some(vec!["plasmid.fasta".as_bytes().to_vec()]),  query_id: Some(3), ones: Some(vec![1]), query_name: Some("ERR4035126.7543".as_bytes().to_vec()) },
         ];
 
-        let expected: Vec<u8> = vec![5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let expected: Vec<u8> = vec![5, 0, 0, 0, 0, 0, 0, 44, 26, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 16, 0, 0, 0, 0, 0, 2, 0, 4, 0, 5, 0, 7, 0];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
@@ -310,10 +778,12 @@ mod tests {
         let mut encoder = Encoder::new(&mut tmp, &targets, &query_name, queries.len());
         encoder.set_fields_present(3_u16);
         encoder.set_block_size(1000).unwrap();
+        // The expected bytes are a gzipped block
+        encoder.set_inline_max_records(0);
 
-        let got = encoder.next().unwrap();
+        let got = encoder.next().unwrap().unwrap();
 
-        assert_eq!(encoder.next(), None);
+        assert!(encoder.next().is_none());
         assert_eq!(got, expected);
     }
 
@@ -330,7 +800,7 @@ mod tests {
             PseudoAln{ones_names: Some(vec!["plasmid.fasta".as_bytes().to_vec()]),  query_id: Some(3), ones: Some(vec![1]), query_name: Some("ERR4035126.7543".as_bytes().to_vec()) },
         ];
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 2, 0, 0, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 37, 0, 0, 0, 49, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 18, 116, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 51, 53, 180, 52, 48, 230, 69, 18, 49, 52, 99, 100, 98, 98, 1, 0, 148, 139, 255, 106, 38, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 6, 6, 6, 22, 6, 86, 6, 0, 21, 37, 56, 88, 20, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 2, 0, 0, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 44, 20, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 16, 0, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 44, 20, 0, 0, 0, 49, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 18, 116, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 51, 53, 180, 52, 48, 230, 69, 18, 49, 52, 99, 100, 98, 98, 1, 0, 148, 139, 255, 106, 38, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 16, 0, 0, 0, 4, 0, 5, 0, 1, 0, 0, 0, 0, 0, 0, 12, 18, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 7, 0];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
@@ -344,9 +814,208 @@ mod tests {
         let mut got: Vec<u8> = Vec::new();
         got.append(&mut encoder.encode_file_header_and_flags().unwrap());
         for block in encoder.by_ref() {
-            got.append(&mut block.clone());
+            got.append(&mut block.unwrap());
         }
 
         assert_eq!(got, expected);
     }
+
+    #[test]
+    fn set_bitmap_type_roaring64_decodes_same() {
+        use super::Encoder;
+        use crate::compression::BitmapType;
+        use crate::decoder::Decoder;
+        use crate::simulate::Simulator;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        // Small stand-ins for files that are promoted to roaring64
+        for (seed, density) in [(0, 0.01), (1, 0.1), (2, 0.5), (3, 0.9)] {
+            let data: Vec<PseudoAln> = Simulator::new(1000, 37, density, seed).collect();
+            let targets = Simulator::new(1000, 37, density, seed).targets();
+
+            let mut decoded: Vec<Vec<PseudoAln>> = Vec::new();
+            for bitmap_type in [BitmapType::Roaring32, BitmapType::Roaring64] {
+                let mut records = data.clone().into_iter();
+                let mut encoder = Encoder::new(&mut records, &targets, b"sample", data.len());
+                encoder.set_fields_present(crate::MASK_QUERY_IDS);
+                encoder.set_bitmap_type(bitmap_type.clone()).unwrap();
+                encoder.set_block_size(128).unwrap();
+
+                let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+                for block in encoder.by_ref() {
+                    bytes.extend(block.unwrap());
+                }
+
+                let mut conn = Cursor::new(bytes);
+                let mut decoder = Decoder::new(&mut conn);
+                assert_eq!(BitmapType::from_u16(decoder.file_header().bitmap_type).unwrap(), bitmap_type);
+                decoded.push(decoder.by_ref().collect());
+            }
+
+            assert_eq!(decoded[0].len(), data.len());
+            assert_eq!(decoded[0], decoded[1]);
+        }
+    }
+
+    #[test]
+    fn set_bitmap_type_roaring32_too_small() {
+        use super::Encoder;
+        use crate::compression::BitmapType;
+        use crate::PseudoAln;
+
+        let targets: Vec<Vec<u8>> = (0..65536).map(|x: u32| x.to_string().into_bytes()).collect();
+        let mut records = std::iter::empty::<PseudoAln>();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 65536);
+        assert!(encoder.set_bitmap_type(BitmapType::Roaring32).is_err());
+        assert!(encoder.set_bitmap_type(BitmapType::Roaring64).is_ok());
+    }
+
+    #[test]
+    fn set_max_hits_caps_records_and_stores_flag() {
+        use super::Encoder;
+        use crate::PseudoAln;
+        use crate::decoder::Decoder;
+        use crate::max_hits::MaxHits;
+        use crate::max_hits::MaxHitsAction;
+        use std::io::Cursor;
+
+        let targets = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
+        let data = vec![
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1, 2]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![2]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![0, 2]), query_name: None },
+        ];
+        let cap = MaxHits{ max_hits: 1, action: MaxHitsAction::Truncate };
+
+        let mut records = data.into_iter();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 3);
+        encoder.set_fields_present(crate::MASK_QUERY_IDS);
+        encoder.set_max_hits(cap).unwrap();
+        let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+        for block in encoder.by_ref() {
+            bytes.extend(block.unwrap());
+        }
+        assert_eq!(encoder.records_capped(), 2);
+
+        let mut conn = Cursor::new(bytes);
+        let mut decoder = Decoder::new(&mut conn);
+        assert_eq!(MaxHits::from_flags(decoder.file_flags()).unwrap(), Some(cap));
+        let got: Vec<Vec<u32>> = decoder.by_ref().map(|x| x.ones.unwrap()).collect();
+        assert_eq!(got, vec![vec![0], vec![2], vec![0]]);
+    }
+
+    #[test]
+    fn set_coverage_stores_flag() {
+        use super::Encoder;
+        use crate::PseudoAln;
+        use crate::coverage::Coverage;
+        use crate::decoder::Decoder;
+        use std::io::Cursor;
+
+        let targets = vec![b"a".to_vec(), b"b".to_vec()];
+        let coverage = Coverage{ bucket_size: 100, lengths: vec![1000, 0], covered: vec![vec![0, 4], vec![]] };
+
+        let mut records = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None }].into_iter();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 1);
+        encoder.set_coverage(&coverage).unwrap();
+        let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+        for block in encoder.by_ref() {
+            bytes.extend(block.unwrap());
+        }
+
+        let mut conn = Cursor::new(bytes);
+        let decoder = Decoder::new(&mut conn);
+        assert_eq!(Coverage::from_flags(decoder.file_flags()).unwrap(), Some(coverage));
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn train_flags_dictionary_decodes_same() {
+        use super::Encoder;
+        use crate::PseudoAln;
+        use crate::compression::flags_dictionary::FlagsDictionary;
+        use crate::decode_from_read;
+        use crate::errors::MissingFlagsDictionaryErr;
+        use crate::headers::block::read_block_header_and_flags;
+        use crate::headers::file::read_file_header_and_flags;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data: Vec<PseudoAln> = (0..4000).map(|id: u32| {
+            PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: Some(format!("ERR4035126.{}", id * 7 + 1).into_bytes()) }
+        }).collect();
+
+        let mut encoded: Vec<Vec<u8>> = Vec::new();
+        for train in [false, true] {
+            let mut records = data.clone().into_iter();
+            let mut encoder = Encoder::new(&mut records, &targets, b"ERR4035126", data.len());
+            encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
+            encoder.set_block_size(32).unwrap();
+            if train {
+                assert!(encoder.train_flags_dictionary(20, 4096).unwrap());
+            }
+            let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+            for block in encoder.by_ref() {
+                bytes.extend(block.unwrap());
+            }
+            let (_, _, got) = decode_from_read(&mut bytes.as_slice()).unwrap();
+            assert_eq!(got.iter().map(|x| (x.query_name.clone(), x.ones.clone())).collect::<Vec<_>>(), data.iter().map(|x| (x.query_name.clone(), x.ones.clone())).collect::<Vec<_>>());
+            encoded.push(bytes);
+        }
+        assert!(encoded[1].len() < encoded[0].len());
+
+        // Block flags can't be read without the dictionary in the file flags
+        let mut conn = encoded[1].as_slice();
+        let (_, flags) = read_file_header_and_flags(&mut conn).unwrap();
+        assert!(FlagsDictionary::from_flags(&flags).is_some());
+        let got = read_block_header_and_flags(&mut conn).unwrap_err();
+        assert!(got.downcast_ref::<MissingFlagsDictionaryErr>().is_some());
+    }
+
+    #[test]
+    fn small_file_is_inline() {
+        use super::Encoder;
+        use crate::PseudoAln;
+        use crate::compression::BLOCK_FLAGS_RAW;
+        use crate::compression::BLOCK_PAYLOAD_RAW;
+        use crate::compression::INLINE_MAX_RECORDS;
+        use crate::decoder::Decoder;
+        use crate::headers::block::decode_block_header;
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let records = |n: u32| -> Vec<PseudoAln> {
+            (0..n).map(|i| PseudoAln{ ones_names: None, query_id: Some(i), ones: Some(vec![i % 2]), query_name: Some(format!("r{}", i).into_bytes()) }).collect()
+        };
+        let encode = |data: &[PseudoAln], block_size: usize, inline_max_records: usize| {
+            let mut records = data.iter().cloned();
+            let mut encoder = Encoder::new(&mut records, &targets, b"sample", data.len());
+            encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
+            encoder.set_block_size(block_size).unwrap();
+            encoder.set_inline_max_records(inline_max_records);
+            let header = encoder.encode_file_header_and_flags().unwrap();
+            let blocks: Vec<Vec<u8>> = encoder.by_ref().map(|x| x.unwrap()).collect();
+            (header, blocks)
+        };
+        let is_inline = |block: &[u8]| decode_block_header(&block[0..32]).unwrap().placeholder1 & BLOCK_FLAGS_RAW != 0;
+
+        let data = records(10);
+        let (header, inline) = encode(&data, 1000, INLINE_MAX_RECORDS);
+        let storage = BLOCK_FLAGS_RAW | BLOCK_PAYLOAD_RAW;
+        assert_eq!(decode_block_header(&inline[0][0..32]).unwrap().placeholder1 & storage, storage);
+        let (_, compressed) = encode(&data, 1000, 9);
+        assert!(!is_inline(&compressed[0]));
+        assert!(inline[0].len() < compressed[0].len());
+        // Only files with a single block are inline
+        let (_, several) = encode(&data, 6, INLINE_MAX_RECORDS);
+        assert!(several.iter().all(|x| !is_inline(x)));
+        // Larger blocks are smaller compressed
+        let (_, larger) = encode(&records(1000), 2000, INLINE_MAX_RECORDS);
+        assert!(!is_inline(&larger[0]));
+
+        let mut conn = Cursor::new([header, inline[0].clone()].concat());
+        let got: Vec<PseudoAln> = Decoder::new(&mut conn).map(|mut x| { x.ones_names = None; x }).collect();
+        assert_eq!(got, data);
+    }
 }
@@ -0,0 +1,313 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Sort records by query id before encoding.
+//!
+//! [Encoder](super::Encoder) sorts the records within each block, but it takes
+//! the records into blocks in the order they arrive. Aligners that don't
+//! write the reads in the order of the query file, such as Fulgor or a
+//! multithreaded SAM writer, produce blocks whose query id ranges overlap.
+//! These compress worse and can't be read one query range at a time.
+//!
+//! [ExternalSort] reads all records from an iterator and returns them sorted
+//! by query id. Records are held in memory until they take up
+//! [SortOpts::max_memory] bytes, after which each sorted run is written to a
+//! temporary file in [SortOpts::tmp_dir]. The runs are merged when the
+//! records are returned. The temporary files are removed when ExternalSort is
+//! dropped.
+//!
+//! Records without a query id are returned first, the same order that
+//! [Encoder](super::Encoder) uses within a block.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::PseudoAln;
+//! use ahda::encoder::sorted::ExternalSort;
+//! use ahda::encoder::sorted::SortOpts;
+//!
+//! let data: Vec<PseudoAln> = [3, 0, 2, 1].into_iter().map(|id| {
+//!     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+//! }).collect();
+//!
+//! // Spill every record to a temporary file to show the merge
+//! let opts = SortOpts{ max_memory: 1, ..Default::default() };
+//! let sorted = ExternalSort::new(data.into_iter(), &opts).unwrap();
+//! assert_eq!(sorted.n_runs(), 4);
+//!
+//! let got: Vec<u32> = sorted.map(|x| x.query_id.unwrap()).collect();
+//! assert_eq!(got, vec![0, 1, 2, 3]);
+//! ```
+//!
+
+use crate::PseudoAln;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+type E = Box<dyn std::error::Error>;
+
+// Numbers the temporary files of sorters in the same process
+static N_SORTERS: AtomicUsize = AtomicUsize::new(0);
+
+// Fields of a record in a temporary file
+type SpilledRecord = (Option<u32>, Option<Vec<u8>>, Option<Vec<u32>>, Option<Vec<Vec<u8>>>);
+
+/// Memory limit and temporary file location for [ExternalSort].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SortOpts {
+    /// Approximate number of bytes of records to hold in memory before writing them to a temporary file.
+    pub max_memory: usize,
+    /// Directory for the temporary files.
+    pub tmp_dir: PathBuf,
+}
+
+impl Default for SortOpts {
+    /// Default to these values:
+    /// ```rust
+    /// let mut opts = ahda::encoder::sorted::SortOpts::default();
+    /// opts.max_memory = 1 << 30;
+    /// opts.tmp_dir = std::env::temp_dir();
+    /// # let expected = ahda::encoder::sorted::SortOpts::default();
+    /// # assert_eq!(opts, expected);
+    /// ```
+    ///
+    fn default() -> SortOpts {
+        SortOpts {
+            max_memory: 1 << 30,
+            tmp_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+/// Approximate number of bytes that `record` takes up in memory.
+fn record_size(
+    record: &PseudoAln,
+) -> usize {
+    std::mem::size_of::<PseudoAln>()
+        + record.ones.as_ref().map_or(0, |x| x.len() * std::mem::size_of::<u32>())
+        + record.query_name.as_ref().map_or(0, |x| x.len())
+        + record.ones_names.as_ref().map_or(0, |x| x.iter().map(|name| name.len() + std::mem::size_of::<Vec<u8>>()).sum())
+}
+
+/// A sorted run of records in a temporary file.
+struct Run {
+    conn: BufReader<File>,
+    remaining: usize,
+}
+
+impl Run {
+    fn try_next(
+        &mut self,
+    ) -> Result<Option<PseudoAln>, E> {
+        if self.remaining == 0 {
+            return Ok(None)
+        }
+        self.remaining -= 1;
+        let (query_id, query_name, ones, ones_names): SpilledRecord = bincode::decode_from_std_read(&mut self.conn, bincode::config::standard())?;
+        Ok(Some(PseudoAln{ ones, ones_names, query_id, query_name }))
+    }
+}
+
+/// Iterator that returns [PseudoAln] records sorted by query id.
+///
+/// See the [module documentation](self) for details.
+pub struct ExternalSort {
+    // Used if everything fit in memory
+    in_memory: std::vec::IntoIter<PseudoAln>,
+
+    // Used if the records were written to temporary files
+    runs: Vec<Run>,
+    heads: Vec<Option<PseudoAln>>,
+    heap: BinaryHeap<Reverse<(Option<u32>, usize)>>,
+    paths: Vec<PathBuf>,
+}
+
+impl ExternalSort {
+    /// Read all records from `records` and sort them.
+    ///
+    /// Errors if a temporary file can't be created or written in
+    /// [SortOpts::tmp_dir].
+    pub fn new<I: Iterator<Item = PseudoAln>>(
+        records: I,
+        opts: &SortOpts,
+    ) -> Result<Self, E> {
+        let sorter_id = N_SORTERS.fetch_add(1, Ordering::Relaxed);
+        let mut ret = ExternalSort{ in_memory: Vec::new().into_iter(), runs: Vec::new(), heads: Vec::new(), heap: BinaryHeap::new(), paths: Vec::new() };
+
+        let mut chunk: Vec<PseudoAln> = Vec::new();
+        let mut chunk_size = 0_usize;
+        let mut n_records = 0_usize;
+        for record in records {
+            chunk_size += record_size(&record);
+            chunk.push(record);
+            n_records += 1;
+            if chunk_size >= opts.max_memory {
+                ret.spill(&mut chunk, opts, sorter_id)?;
+                chunk_size = 0;
+            }
+        }
+
+        if ret.runs.is_empty() {
+            chunk.sort_by_key(|x| x.query_id);
+            ret.in_memory = chunk.into_iter();
+        } else {
+            if !chunk.is_empty() {
+                ret.spill(&mut chunk, opts, sorter_id)?;
+            }
+            for run_idx in 0..ret.runs.len() {
+                let head = ret.runs[run_idx].try_next()?;
+                if let Some(record) = &head {
+                    ret.heap.push(Reverse((record.query_id, run_idx)));
+                }
+                ret.heads.push(head);
+            }
+        }
+        log::info!("sorted {} records in {} runs", n_records, ret.runs.len().max(1));
+
+        Ok(ret)
+    }
+
+    /// Number of sorted runs written to temporary files.
+    pub fn n_runs(
+        &self,
+    ) -> usize {
+        self.runs.len()
+    }
+
+    /// Sort `chunk` and write it to a new temporary file.
+    fn spill(
+        &mut self,
+        chunk: &mut Vec<PseudoAln>,
+        opts: &SortOpts,
+        sorter_id: usize,
+    ) -> Result<(), E> {
+        chunk.sort_by_key(|x| x.query_id);
+
+        let path = opts.tmp_dir.join(format!("ahda-sort-{}-{}-{}.tmp", std::process::id(), sorter_id, self.runs.len()));
+        let file = File::options().read(true).write(true).create_new(true).open(&path)?;
+        self.paths.push(path);
+
+        let mut conn = BufWriter::new(file);
+        for record in chunk.iter() {
+            bincode::encode_into_std_write((&record.query_id, &record.query_name, &record.ones, &record.ones_names), &mut conn, bincode::config::standard())?;
+        }
+        conn.flush()?;
+        let mut file = conn.into_inner()?;
+        std::io::Seek::rewind(&mut file)?;
+
+        self.runs.push(Run{ conn: BufReader::new(file), remaining: chunk.len() });
+        chunk.clear();
+        Ok(())
+    }
+
+    /// Return the next record, or an error if a temporary file can't be read.
+    pub fn try_next(
+        &mut self,
+    ) -> Option<Result<PseudoAln, E>> {
+        if self.runs.is_empty() {
+            return self.in_memory.next().map(Ok)
+        }
+
+        // Ties are broken by run index so that equal ids keep their input order
+        let Reverse((_, run_idx)) = self.heap.pop()?;
+        let next = match self.runs[run_idx].try_next() {
+            Ok(next) => next,
+            Err(e) => return Some(Err(e)),
+        };
+        if let Some(record) = &next {
+            self.heap.push(Reverse((record.query_id, run_idx)));
+        }
+        let record = std::mem::replace(&mut self.heads[run_idx], next)?;
+        Some(Ok(record))
+    }
+}
+
+impl Iterator for ExternalSort {
+    type Item = PseudoAln;
+
+    fn next(
+        &mut self,
+    ) -> Option<PseudoAln> {
+        Some(self.try_next()?.unwrap())
+    }
+}
+
+impl Drop for ExternalSort {
+    fn drop(
+        &mut self,
+    ) {
+        self.runs.clear();
+        for path in self.paths.iter() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn external_sort_merges_runs() {
+        use super::ExternalSort;
+        use super::SortOpts;
+        use crate::simulate::Simulator;
+        use crate::PseudoAln;
+
+        let mut data: Vec<PseudoAln> = Simulator::new(500, 8, 0.3, 7).collect();
+        data.iter_mut().filter(|x| x.query_id.unwrap() % 3 == 0).for_each(|x| x.query_name = Some(format!("r{}", x.query_id.unwrap()).into_bytes()));
+        // Interleave the ids like a multithreaded aligner would
+        let shuffled: Vec<PseudoAln> = (0..7).flat_map(|offset| data.iter().skip(offset).step_by(7).cloned().rev()).collect();
+
+        let dir = std::env::temp_dir().join(format!("ahda-sort-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let opts = SortOpts{ max_memory: 4096, tmp_dir: dir.clone() };
+
+        let sorted = ExternalSort::new(shuffled.into_iter(), &opts).unwrap();
+        assert!(sorted.n_runs() > 1);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), sorted.n_runs());
+
+        let got: Vec<PseudoAln> = sorted.collect();
+        assert_eq!(got, data);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn external_sort_in_memory() {
+        use super::ExternalSort;
+        use super::SortOpts;
+        use crate::PseudoAln;
+
+        let data = vec![
+            PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![0]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: None, ones: Some(vec![1]), query_name: Some(b"r".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![]), query_name: None },
+        ];
+
+        let sorted = ExternalSort::new(data.clone().into_iter(), &SortOpts::default()).unwrap();
+        assert_eq!(sorted.n_runs(), 0);
+
+        let got: Vec<PseudoAln> = sorted.collect();
+        assert_eq!(got, vec![data[1].clone(), data[2].clone(), data[0].clone()]);
+    }
+}
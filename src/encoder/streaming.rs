@@ -0,0 +1,236 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Push-based encoder for writing .ahda records one read at a time.
+//!
+//! [StreamingEncoder] accepts the target ids for each read as they are
+//! produced, eg. by an aligner, and returns the encoded bytes whenever a block
+//! is full. The caller does not need to know how the alignments are stored in
+//! the bitmap or when to start a new block.
+//!
+//! If `n_queries` is not known in advance it can be set to 0. This makes the
+//! encoder use a 64-bit address space for the whole record.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::decode_from_read;
+//! use ahda::encoder::streaming::StreamingEncoder;
+//! use std::io::Cursor;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let mut encoder = StreamingEncoder::new(&targets, b"sample", 3);
+//!
+//! let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+//! for (query_id, (name, target_ids)) in [(b"r1", vec![0]), (b"r2", vec![]), (b"r3", vec![0, 1])].iter().enumerate() {
+//!     if let Some(mut block) = encoder.push(*name, query_id as u32, target_ids).unwrap() {
+//!         bytes.append(&mut block);
+//!     }
+//! }
+//! if let Some(mut block) = encoder.finish().unwrap() {
+//!     bytes.append(&mut block);
+//! }
+//!
+//! let (_, _, mut alns) = decode_from_read(&mut Cursor::new(bytes)).unwrap();
+//! alns.sort_by_key(|x| x.query_id);
+//!
+//! assert_eq!(alns[2].ones, Some(vec![0, 1]));
+//! assert_eq!(alns[2].query_name, Some(b"r3".to_vec()));
+//! ```
+//!
+
+use crate::PseudoAln;
+use crate::compression::BitmapType;
+use crate::compression::MetadataCompression;
+use crate::compression::check_shape;
+use crate::compression::pack_records;
+use crate::headers::file::FileHeader;
+use crate::headers::file::FileFlags;
+use crate::headers::file::build_file_header_and_flags;
+use crate::headers::file::encode_file_header_and_flags;
+
+type E = Box<dyn std::error::Error>;
+
+pub struct StreamingEncoder {
+    // These are given as construtor parameters
+    header: FileHeader,
+    flags: FileFlags,
+
+    // Internals
+    block: Vec<PseudoAln>,
+}
+
+impl StreamingEncoder {
+    pub fn new(
+        targets: &[Vec<u8>],
+        sample_name: &[u8],
+        n_queries: usize,
+    ) -> Self {
+        Self::try_new(targets, sample_name, n_queries).unwrap()
+    }
+
+    /// Same as [new](StreamingEncoder::new) but returns an error if the file header can't be built.
+    ///
+    /// ## Errors and panics
+    ///
+    /// Errors if `n_queries` or the number of targets doesn't fit in a u32,
+    /// or if [build_file_header_and_flags] fails.
+    ///
+    pub fn try_new(
+        targets: &[Vec<u8>],
+        sample_name: &[u8],
+        n_queries: usize,
+    ) -> Result<Self, E> {
+        u32::try_from(n_queries)?;
+        u32::try_from(targets.len())?;
+        let (mut header, flags) = build_file_header_and_flags(targets, n_queries, sample_name, &MetadataCompression::default())?;
+        if n_queries == 0 {
+            // Query ids can be arbitrarily large
+            header.bitmap_type = BitmapType::Roaring64.to_u16();
+        }
+        header.fields_present = crate::MASK_QUERY_IDS | crate::MASK_QUERIES;
+
+        Ok(StreamingEncoder{
+            block: Vec::with_capacity(header.block_size as usize),
+            header, flags,
+        })
+    }
+
+    pub fn encode_file_header_and_flags(
+        &mut self,
+    ) -> Result<Vec<u8>, E> {
+        if let Some(warning) = check_shape(&self.header) {
+            log::warn!("{}, {}", warning, warning.suggestion());
+        }
+        encode_file_header_and_flags(&mut self.header, &self.flags)
+    }
+
+    /// Add the alignments for a single read.
+    ///
+    /// Returns the encoded block if it is full after adding this read.
+    ///
+    /// Errors with [TargetIndexOutOfRangeErr](crate::errors::TargetIndexOutOfRangeErr)
+    /// if a target id is not less than the number of targets, or with
+    /// [QueryIndexOutOfRangeErr](crate::errors::QueryIndexOutOfRangeErr) if
+    /// `query_id` is not less than `n_queries` given to [StreamingEncoder::new].
+    ///
+    pub fn push(
+        &mut self,
+        query_name: &[u8],
+        query_id: u32,
+        target_ids: &[u32],
+    ) -> Result<Option<Vec<u8>>, E> {
+        if target_ids.iter().any(|target_id| *target_id >= self.header.n_targets) {
+            return Err(Box::new(crate::errors::TargetIndexOutOfRangeErr{}))
+        }
+        if self.header.n_queries > 0 && query_id >= self.header.n_queries {
+            return Err(Box::new(crate::errors::QueryIndexOutOfRangeErr{}))
+        }
+
+        self.block.push(PseudoAln{
+            ones: Some(target_ids.to_vec()),
+            ones_names: None,
+            query_id: Some(query_id),
+            query_name: Some(query_name.to_vec()),
+        });
+
+        if self.block.len() >= self.header.block_size as usize {
+            self.flush()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Encode the reads that have not been written yet.
+    ///
+    /// Must be called after the last read has been added.
+    ///
+    pub fn finish(
+        &mut self,
+    ) -> Result<Option<Vec<u8>>, E> {
+        self.flush()
+    }
+
+    fn flush(
+        &mut self,
+    ) -> Result<Option<Vec<u8>>, E> {
+        if self.block.is_empty() {
+            return Ok(None)
+        }
+
+        let mut records = std::mem::replace(&mut self.block, Vec::with_capacity(self.header.block_size as usize));
+        records.sort_by_key(|x| x.query_id);
+
+        Ok(Some(pack_records(&self.header, records)?))
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn push_writes_full_blocks() {
+        use super::StreamingEncoder;
+        use crate::decode_from_read;
+        use crate::PseudoAln;
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let mut encoder = StreamingEncoder::new(&targets, b"sample", 0);
+        encoder.header.block_size = 2;
+
+        let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+        let mut n_blocks = 0;
+        for query_id in 0..5 {
+            if let Some(mut block) = encoder.push(format!("r{}", query_id).as_bytes(), query_id, &[query_id % 2]).unwrap() {
+                bytes.append(&mut block);
+                n_blocks += 1;
+            }
+        }
+        bytes.append(&mut encoder.finish().unwrap().unwrap());
+        n_blocks += 1;
+
+        assert_eq!(n_blocks, 3);
+        assert!(encoder.finish().unwrap().is_none());
+
+        let (_, _, got) = decode_from_read(&mut Cursor::new(bytes)).unwrap();
+        let expected: Vec<PseudoAln> = (0..5).map(|query_id| {
+            PseudoAln{ ones: Some(vec![query_id % 2]), ones_names: Some(vec![targets[(query_id % 2) as usize].clone()]), query_id: Some(query_id), query_name: Some(format!("r{}", query_id).as_bytes().to_vec()) }
+        }).collect();
+
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn try_new_with_too_many_queries_fails() {
+        use super::StreamingEncoder;
+
+        let targets = vec![b"chr.fasta".to_vec()];
+        assert!(StreamingEncoder::try_new(&targets, b"sample", u32::MAX as usize).is_ok());
+        assert!(StreamingEncoder::try_new(&targets, b"sample", u32::MAX as usize + 1).is_err());
+    }
+
+    #[test]
+    fn push_with_invalid_ids_fails() {
+        use super::StreamingEncoder;
+
+        let targets = vec![b"chr.fasta".to_vec()];
+        let mut encoder = StreamingEncoder::new(&targets, b"sample", 2);
+
+        assert!(encoder.push(b"r1", 0, &[1]).is_err());
+        assert!(encoder.push(b"r3", 2, &[0]).is_err());
+        assert!(encoder.push(b"r1", 0, &[0]).is_ok());
+    }
+}
@@ -51,7 +51,7 @@ impl std::error::Error for AhdaHeaderError {}
 pub struct SetBitsIteratorNotSortedErr;
 impl std::fmt::Display for SetBitsIteratorNotSortedErr {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "`set_bits` iterator given to BitmapEncoder::new() (argument #1) must be sorted.")
+        write!(f, "set bits or (query, target) pairs given to BitmapEncoder must be sorted.")
     }
 }
 impl std::error::Error for SetBitsIteratorNotSortedErr {}
@@ -182,6 +182,18 @@ impl std::fmt::Display for EncodeError {
 }
 impl std::error::Error for EncodeError {}
 
+/// A [PseudoAln](crate::PseudoAln) record given to the encoder doesn't know its targets, see [Hits::Missing](crate::Hits::Missing).
+#[derive(Debug, Clone)]
+pub struct MissingHitsErr {
+    pub query_id: u32,
+}
+impl std::fmt::Display for MissingHitsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Targets of query {} are not known, use an empty list of targets for queries that aligned to none.", self.query_id)
+    }
+}
+impl std::error::Error for MissingHitsErr {}
+
 /// Bifrost header line was not consumed before calling [read_bifrost](crate::parser::bifrost::read_bifrost).
 #[derive(Debug, Clone)]
 pub struct BifrostHeaderNotConsumedError;
@@ -221,3 +233,717 @@ impl std::fmt::Display for AhdaTSVHeaderNotConsumedError {
     }
 }
 impl std::error::Error for AhdaTSVHeaderNotConsumedError {}
+
+/// Block refers to the contents of a block that was not read before it.
+#[derive(Debug, Clone)]
+pub struct MissingBlockReferenceErr;
+impl std::fmt::Display for MissingBlockReferenceErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Block refers to contents that are not available.")
+    }
+}
+impl std::error::Error for MissingBlockReferenceErr {}
+
+/// Requested block index is not less than the number of blocks in the record.
+#[derive(Debug, Clone)]
+pub struct UnknownBlockErr;
+impl std::fmt::Display for UnknownBlockErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Block index is out of range for the number of blocks.")
+    }
+}
+impl std::error::Error for UnknownBlockErr {}
+
+/// Record aligns to a target index that is not less than the number of targets.
+#[derive(Debug, Clone)]
+pub struct TargetIndexOutOfRangeErr;
+impl std::fmt::Display for TargetIndexOutOfRangeErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Target index is out of range for the number of targets.")
+    }
+}
+impl std::error::Error for TargetIndexOutOfRangeErr {}
+
+/// Block query names and query ids have different lengths.
+#[derive(Debug, Clone)]
+pub struct BlockQueryCountErr;
+impl std::fmt::Display for BlockQueryCountErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Number of query names does not match the number of query ids.")
+    }
+}
+impl std::error::Error for BlockQueryCountErr {}
+
+/// Block bytes are shorter than promised by the block header.
+#[derive(Debug, Clone)]
+pub struct TruncatedBlockErr;
+impl std::fmt::Display for TruncatedBlockErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Block is shorter than `flags_len + block_len` in the block header.")
+    }
+}
+impl std::error::Error for TruncatedBlockErr {}
+
+/// Header refers to a bitmap type that this version of ahda does not support.
+#[derive(Debug, Clone)]
+pub struct UnknownBitmapTypeErr;
+impl std::fmt::Display for UnknownBitmapTypeErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Not a valid bitmap type.")
+    }
+}
+impl std::error::Error for UnknownBitmapTypeErr {}
+
+/// Header refers to a metadata compression method that this version of ahda does not support.
+#[derive(Debug, Clone)]
+pub struct UnknownMetadataCompressionErr;
+impl std::fmt::Display for UnknownMetadataCompressionErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Not a valid metadata compression method.")
+    }
+}
+impl std::error::Error for UnknownMetadataCompressionErr {}
+
+/// File header or flags are shorter than promised by the file header.
+#[derive(Debug, Clone)]
+pub struct TruncatedFileHeaderErr;
+impl std::fmt::Display for TruncatedFileHeaderErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "File header is shorter than 32 bytes or the file flags are shorter than `flags_len` in the file header.")
+    }
+}
+impl std::error::Error for TruncatedFileHeaderErr {}
+
+/// Record has a query index that is not less than the number of queries.
+#[derive(Debug, Clone)]
+pub struct QueryIndexOutOfRangeErr;
+impl std::fmt::Display for QueryIndexOutOfRangeErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Query index is out of range for the number of queries.")
+    }
+}
+impl std::error::Error for QueryIndexOutOfRangeErr {}
+
+/// Record refers to a query that is not in the query names or ids.
+#[derive(Debug, Clone)]
+pub struct UnknownQueryErr;
+impl std::fmt::Display for UnknownQueryErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Query is not in the list of query sequences.")
+    }
+}
+impl std::error::Error for UnknownQueryErr {}
+
+/// Record refers to a target that is not in the target sequence names.
+#[derive(Debug, Clone)]
+pub struct UnknownTargetErr;
+impl std::fmt::Display for UnknownTargetErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Target is not in the list of target sequences.")
+    }
+}
+impl std::error::Error for UnknownTargetErr {}
+
+/// Block does not contain the fields promised by the file header.
+#[derive(Debug, Clone)]
+pub struct MissingBlockFieldsErr;
+impl std::fmt::Display for MissingBlockFieldsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Block is missing fields required by the file header.")
+    }
+}
+impl std::error::Error for MissingBlockFieldsErr {}
+
+/// Target groups don't assign every target to a group.
+#[derive(Debug, Clone)]
+pub struct InvalidTargetGroupsErr;
+impl std::fmt::Display for InvalidTargetGroupsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Target groups must assign each target sequence to an existing group.")
+    }
+}
+impl std::error::Error for InvalidTargetGroupsErr {}
+
+/// Target aliases don't assign each alias to exactly one target.
+#[derive(Debug, Clone)]
+pub struct InvalidTargetAliasesErr;
+impl std::fmt::Display for InvalidTargetAliasesErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Target aliases must assign each target sequence to exactly one target.")
+    }
+}
+impl std::error::Error for InvalidTargetAliasesErr {}
+
+/// Requested target group level is not in the file flags.
+#[derive(Debug, Clone)]
+pub struct UnknownTargetGroupErr;
+impl std::fmt::Display for UnknownTargetGroupErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Target group level is not in the file flags.")
+    }
+}
+impl std::error::Error for UnknownTargetGroupErr {}
+
+/// Input does not store the query names.
+#[derive(Debug, Clone)]
+pub struct MissingQueryNamesErr;
+impl std::fmt::Display for MissingQueryNamesErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Input must store query names to match queries by name.")
+    }
+}
+impl std::error::Error for MissingQueryNamesErr {}
+
+/// Gzip compression level is not between 1 and 9.
+#[derive(Debug, Clone)]
+pub struct InvalidCompressionLevelErr;
+impl std::fmt::Display for InvalidCompressionLevelErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Compression level must be between 1 and 9.")
+    }
+}
+impl std::error::Error for InvalidCompressionLevelErr {}
+
+/// Sorting the records requires .ahda input.
+#[derive(Debug, Clone)]
+pub struct UnsortablePlainTextErr;
+impl std::fmt::Display for UnsortablePlainTextErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Sorting by rank requires .ahda input, encode the plain text first.")
+    }
+}
+impl std::error::Error for UnsortablePlainTextErr {}
+
+/// Output format can't store all fields in the input.
+#[derive(Debug, Clone)]
+pub struct LossyConversionErr;
+impl std::fmt::Display for LossyConversionErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Output format can't store all fields in the input.")
+    }
+}
+impl std::error::Error for LossyConversionErr {}
+
+/// Input could not be decoded while merging several inputs.
+#[derive(Debug, Clone)]
+pub struct MergeInputErr {
+    pub input: usize,
+    pub msg: String,
+}
+impl std::fmt::Display for MergeInputErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Input {} could not be merged: {}", self.input, self.msg)
+    }
+}
+impl std::error::Error for MergeInputErr {}
+
+/// Query name cache file is corrupted or was not written by ahda.
+#[derive(Debug, Clone)]
+pub struct InvalidQueryCacheErr;
+impl std::fmt::Display for InvalidQueryCacheErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Input is not a valid query name cache.")
+    }
+}
+impl std::error::Error for InvalidQueryCacheErr {}
+
+/// Operation needs a cargo feature that ahda was compiled without.
+#[derive(Debug, Clone)]
+pub struct MissingFeatureErr {
+    pub feature: &'static str,
+}
+impl std::fmt::Display for MissingFeatureErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "This operation requires compiling ahda with the `{}` feature.", self.feature)
+    }
+}
+impl std::error::Error for MissingFeatureErr {}
+
+/// Query name filter of a block does not match the query names in the block.
+#[derive(Debug, Clone)]
+pub struct QueryFilterMismatchErr;
+impl std::fmt::Display for QueryFilterMismatchErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Block query filter does not contain all query names in the block.")
+    }
+}
+impl std::error::Error for QueryFilterMismatchErr {}
+
+/// Query name appears more than once in the query names.
+#[derive(Debug, Clone)]
+pub struct DuplicateQueryNameErr {
+    pub name: String,
+    pub first: usize,
+    pub second: usize,
+}
+impl std::fmt::Display for DuplicateQueryNameErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Query name `{}` appears at both position {} and {} in the query names.", self.name, self.first, self.second)
+    }
+}
+impl std::error::Error for DuplicateQueryNameErr {}
+
+/// Query id in the range of queries has no stored name.
+#[derive(Debug, Clone)]
+pub struct MissingQueryNameErr {
+    pub query_id: u32,
+}
+impl std::fmt::Display for MissingQueryNameErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Query {} has no stored name.", self.query_id)
+    }
+}
+impl std::error::Error for MissingQueryNameErr {}
+
+/// Record given to [merge_records](crate::merge_records) is missing a field that the merge needs.
+#[derive(Debug, Clone)]
+pub struct IncompleteRecordErr {
+    pub input: usize,
+    pub record: usize,
+    pub field: String,
+}
+impl std::fmt::Display for IncompleteRecordErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Record {} in input {} has no {}.", self.record, self.input, self.field)
+    }
+}
+impl std::error::Error for IncompleteRecordErr {}
+
+/// Target rename map does not have two columns on every line.
+#[derive(Debug, Clone)]
+pub struct InvalidTargetRenamesErr;
+impl std::fmt::Display for InvalidTargetRenamesErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Target rename map must have the old and new name of a target on each line.")
+    }
+}
+impl std::error::Error for InvalidTargetRenamesErr {}
+
+/// Table of query sources is not valid, see [QuerySources::from_read](crate::self_hits::QuerySources::from_read).
+#[derive(Debug, Clone)]
+pub struct InvalidQuerySourcesErr;
+impl std::fmt::Display for InvalidQuerySourcesErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Query sources must have the name of a query and the name of its source target or target group on each line.")
+    }
+}
+impl std::error::Error for InvalidQuerySourcesErr {}
+
+/// Merge plan is not valid, see [MergePlan::from_read](crate::shard::MergePlan::from_read).
+#[derive(Debug, Clone)]
+pub struct InvalidMergePlanErr;
+impl std::fmt::Display for InvalidMergePlanErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Merge plan must have the shard index, output path, start query id, and number of records on each line.")
+    }
+}
+impl std::error::Error for InvalidMergePlanErr {}
+
+/// Shard is not in the [ShardPlan](crate::shard::ShardPlan).
+#[derive(Debug, Clone)]
+pub struct ShardOutOfRangeErr {
+    pub shard: usize,
+    pub n_shards: usize,
+}
+impl std::fmt::Display for ShardOutOfRangeErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Shard {} is out of range for the {} shards in the plan.", self.shard, self.n_shards)
+    }
+}
+impl std::error::Error for ShardOutOfRangeErr {}
+
+/// Two targets have the same name in the output.
+#[derive(Debug, Clone)]
+pub struct DuplicateTargetNameErr {
+    pub name: String,
+}
+impl std::fmt::Display for DuplicateTargetNameErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Target name `{}` appears more than once in the output.", self.name)
+    }
+}
+impl std::error::Error for DuplicateTargetNameErr {}
+
+/// Two files served by `ahda serve` have the same name.
+#[derive(Debug, Clone)]
+pub struct DuplicateFileNameErr {
+    pub name: String,
+}
+impl std::fmt::Display for DuplicateFileNameErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "More than one input file is named `{}`.", self.name)
+    }
+}
+impl std::error::Error for DuplicateFileNameErr {}
+
+/// Input is in a zstd-seekable container that this function can't read.
+#[derive(Debug, Clone)]
+pub struct ZstdContainerErr;
+impl std::fmt::Display for ZstdContainerErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Input is in a zstd-seekable container, read it with the Decoder or `ahda decode`.")
+    }
+}
+impl std::error::Error for ZstdContainerErr {}
+
+/// Input splits the targets of its blocks across sub-blocks, which this function can't read.
+#[derive(Debug, Clone)]
+pub struct TargetChunksErr;
+impl std::fmt::Display for TargetChunksErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Input splits the targets of its blocks across sub-blocks, read it with the Decoder or `ahda decode`.")
+    }
+}
+impl std::error::Error for TargetChunksErr {}
+
+/// Target chunks are 0 targets wide, or the sub-blocks of a block don't match.
+#[derive(Debug, Clone)]
+pub struct InvalidTargetChunksErr;
+impl std::fmt::Display for InvalidTargetChunksErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Target chunks must be at least 1 target wide and the sub-blocks of a block must store the same queries.")
+    }
+}
+impl std::error::Error for InvalidTargetChunksErr {}
+
+/// Zstd compression level is outside the range supported by the zstd library.
+#[derive(Debug, Clone)]
+pub struct InvalidZstdLevelErr {
+    pub min: i32,
+    pub max: i32,
+}
+impl std::fmt::Display for InvalidZstdLevelErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Zstd compression level must be between {} and {}.", self.min, self.max)
+    }
+}
+impl std::error::Error for InvalidZstdLevelErr {}
+
+/// Alignments don't fit in the 32-bit address space of a roaring32 bitmap.
+#[derive(Debug, Clone)]
+pub struct Roaring32OverflowErr;
+impl std::fmt::Display for Roaring32OverflowErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Alignments don't fit in a roaring32 bitmap, encode with roaring64 instead.")
+    }
+}
+impl std::error::Error for Roaring32OverflowErr {}
+
+/// Operation needs at least one input.
+#[derive(Debug, Clone)]
+pub struct NoInputsErr;
+impl std::fmt::Display for NoInputsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "No inputs given.")
+    }
+}
+impl std::error::Error for NoInputsErr {}
+
+/// Inputs can't be concatenated, see [Incompatibility](crate::Incompatibility).
+#[derive(Debug, Clone)]
+pub struct IncompatibleInputsErr {
+    pub incompatibilities: Vec<crate::Incompatibility>,
+}
+impl std::fmt::Display for IncompatibleInputsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let details: Vec<String> = self.incompatibilities.iter().map(|x| x.to_string()).collect();
+        write!(f, "Incompatible file headers: {}.", details.join("; "))
+    }
+}
+impl std::error::Error for IncompatibleInputsErr {}
+
+/// Dry run found problems, see [Plan](crate::dry_run::Plan).
+#[derive(Debug, Clone)]
+pub struct DryRunErr {
+    pub n_problems: usize,
+}
+impl std::fmt::Display for DryRunErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Dry run found {} problems.", self.n_problems)
+    }
+}
+impl std::error::Error for DryRunErr {}
+
+/// Targets can only be selected for plain text output, see [Pipeline::select_targets](crate::pipeline::Pipeline::select_targets).
+#[derive(Debug, Clone)]
+pub struct TargetSelectionNotSupportedErr;
+impl std::fmt::Display for TargetSelectionNotSupportedErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Selecting targets is only supported for plain text output.")
+    }
+}
+impl std::error::Error for TargetSelectionNotSupportedErr {}
+
+/// Records can only be split by target group to plain text, see [Pipeline::run_split](crate::pipeline::Pipeline::run_split).
+#[derive(Debug, Clone)]
+pub struct SplitNotSupportedErr;
+impl std::fmt::Display for SplitNotSupportedErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Splitting by target group is only supported for plain text output.")
+    }
+}
+impl std::error::Error for SplitNotSupportedErr {}
+
+/// No Themisto, Fulgor, or Bifrost index in the directory, see [find_index](crate::index::find_index).
+#[derive(Debug, Clone)]
+pub struct IndexNotFoundErr {
+    pub dir: std::path::PathBuf,
+}
+impl std::fmt::Display for IndexNotFoundErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "No Themisto, Fulgor, or Bifrost index in `{}`.", self.dir.display())
+    }
+}
+impl std::error::Error for IndexNotFoundErr {}
+
+/// More than one index in the directory, see [find_index](crate::index::find_index).
+#[derive(Debug, Clone)]
+pub struct MultipleIndexesErr {
+    pub prefixes: Vec<std::path::PathBuf>,
+}
+impl std::fmt::Display for MultipleIndexesErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let prefixes: Vec<String> = self.prefixes.iter().map(|x| x.display().to_string()).collect();
+        write!(f, "More than one index in the directory: {}.", prefixes.join(", "))
+    }
+}
+impl std::error::Error for MultipleIndexesErr {}
+
+/// Index has no target list next to it, see [read_index_targets](crate::index::read_index_targets).
+#[derive(Debug, Clone)]
+pub struct IndexTargetsNotFoundErr {
+    pub kind: crate::index::IndexKind,
+    pub path: std::path::PathBuf,
+}
+impl std::fmt::Display for IndexTargetsNotFoundErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} index has no target list, expected the reference names one per line in `{}`.", self.kind, self.path.display())
+    }
+}
+impl std::error::Error for IndexTargetsNotFoundErr {}
+
+/// `ahda decode --max-hits` needs .ahda input.
+#[derive(Debug, Clone)]
+pub struct PlainTextMaxHitsErr;
+impl std::fmt::Display for PlainTextMaxHitsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Capping the targets per query requires .ahda input, encode the plain text with `--max-hits` instead.")
+    }
+}
+impl std::error::Error for PlainTextMaxHitsErr {}
+
+/// Alignment refers to a query rank past the end of the query names.
+#[derive(Debug, Clone)]
+pub struct QueryRankOutOfRangeErr {
+    pub rank: u32,
+    pub n_queries: usize,
+}
+impl std::fmt::Display for QueryRankOutOfRangeErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Query rank {} is out of range for the {} query names, the query file may be subsampled; use `--allow-extra` to name the extra queries.", self.rank, self.n_queries)
+    }
+}
+impl std::error::Error for QueryRankOutOfRangeErr {}
+
+/// Directory or glob pattern given as input matches no files, see [expand_inputs](crate::inputs::expand_inputs).
+#[derive(Debug, Clone)]
+pub struct NoMatchingInputsErr {
+    pub path: std::path::PathBuf,
+}
+impl std::fmt::Display for NoMatchingInputsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "No input files match `{}`.", self.path.display())
+    }
+}
+impl std::error::Error for NoMatchingInputsErr {}
+
+/// Coverage was requested for an input format without alignment positions, see [Parser::set_coverage](crate::parser::Parser::set_coverage).
+#[derive(Debug, Clone)]
+pub struct CoverageNeedsPositionsErr {
+    pub format: Format,
+}
+impl std::fmt::Display for CoverageNeedsPositionsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Detected input format `{}` has no alignment positions, coverage requires SAM input.", self.format)
+    }
+}
+impl std::error::Error for CoverageNeedsPositionsErr {}
+
+/// Record given to a [Printer](crate::printer::Printer) lacks a field that the output format needs, see [MissingFields](crate::printer::MissingFields).
+#[derive(Debug, Clone)]
+pub struct MissingFieldErr {
+    pub index: usize,
+    pub field: &'static str,
+    pub format: Format,
+}
+impl std::fmt::Display for MissingFieldErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Record {} has no `{}`, which the `{}` output format requires.", self.index, self.field, self.format)
+    }
+}
+impl std::error::Error for MissingFieldErr {}
+
+/// Converted output doesn't contain the same alignments as the input, see [round_trip_check](crate::round_trip_check).
+#[derive(Debug, Clone)]
+pub struct RoundTripMismatchErr {
+    pub input_digest: [u8; 32],
+    pub output_digest: [u8; 32],
+}
+impl std::fmt::Display for RoundTripMismatchErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let hex = |x: &[u8; 32]| x.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        write!(f, "Converted output has digest {} but the input has digest {}, the conversion lost or changed alignments.", hex(&self.output_digest), hex(&self.input_digest))
+    }
+}
+impl std::error::Error for RoundTripMismatchErr {}
+
+/// Block flags are compressed with a zstd dictionary that was not given, see [FlagsDictionary](crate::compression::flags_dictionary::FlagsDictionary).
+#[derive(Debug, Clone)]
+pub struct MissingFlagsDictionaryErr;
+impl std::fmt::Display for MissingFlagsDictionaryErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Block flags are compressed with a zstd dictionary but the file flags have none.")
+    }
+}
+impl std::error::Error for MissingFlagsDictionaryErr {}
+
+/// Field separator given to [ParserBuilder](crate::parser::ParserBuilder) without an input format.
+#[derive(Debug, Clone)]
+pub struct SeparatorNeedsFormatErr;
+impl std::fmt::Display for SeparatorNeedsFormatErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "A custom field separator requires giving the input format, since the format is detected from the separators.")
+    }
+}
+impl std::error::Error for SeparatorNeedsFormatErr {}
+
+/// Field separator given to [ParserBuilder](crate::parser::ParserBuilder) can't be used with the input format.
+#[derive(Debug, Clone)]
+pub struct InvalidSeparatorErr {
+    pub separator: u8,
+    pub format: Format,
+}
+impl std::fmt::Display for InvalidSeparatorErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Field separator {:?} can't be used with input format `{}`.", self.separator as char, self.format)
+    }
+}
+impl std::error::Error for InvalidSeparatorErr {}
+
+/// File flags changed by [update_flags](crate::update_flags) no longer match the file header.
+#[derive(Debug, Clone)]
+pub struct TargetCountChangedErr {
+    pub n_targets: u32,
+    pub got: usize,
+}
+impl std::fmt::Display for TargetCountChangedErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Updated file flags have {} target names but the file has {} targets.", self.got, self.n_targets)
+    }
+}
+impl std::error::Error for TargetCountChangedErr {}
+
+/// Two files refer to different queries, see [check_consistency](crate::consistency::check_consistency).
+#[derive(Debug, Clone)]
+pub struct InconsistentQueriesErr {
+    pub n_mismatches: usize,
+}
+impl std::fmt::Display for InconsistentQueriesErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Inputs refer to different queries ({} mismatches).", self.n_mismatches)
+    }
+}
+impl std::error::Error for InconsistentQueriesErr {}
+
+/// Broad category of an error from the ahda library, see [categorize].
+///
+/// Used by the command-line interface to choose its exit code.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// Input data is not in a supported format, is corrupted, or doesn't
+    /// match the other inputs like the query or target names.
+    InvalidInput,
+    /// Valid inputs that can't be combined, eg. files with different targets
+    /// given to `ahda cat`.
+    Incompatible,
+    /// Reading or writing failed.
+    Io,
+    /// Any other error.
+    Other,
+}
+
+/// Find the [ErrorCategory] of `e`.
+///
+/// Errors that wrap another error are categorized by the first error in the
+/// [source](std::error::Error::source) chain that has a category other than
+/// [ErrorCategory::Other]. An [std::io::Error] of kind
+/// [UnexpectedEof](std::io::ErrorKind::UnexpectedEof) or
+/// [InvalidData](std::io::ErrorKind::InvalidData) is usually a truncated or
+/// corrupted input and is categorized as [ErrorCategory::InvalidInput].
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::errors::categorize;
+/// use ahda::errors::CorruptedInputErr;
+/// use ahda::errors::ErrorCategory;
+///
+/// let e: Box<dyn std::error::Error> = Box::new(CorruptedInputErr);
+/// assert_eq!(categorize(e.as_ref()), ErrorCategory::InvalidInput);
+///
+/// let e: Box<dyn std::error::Error> = Box::new(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+/// assert_eq!(categorize(e.as_ref()), ErrorCategory::Io);
+/// ```
+///
+pub fn categorize(
+    e: &(dyn std::error::Error + 'static),
+) -> ErrorCategory {
+    let mut next: Option<&(dyn std::error::Error + 'static)> = Some(e);
+    while let Some(e) = next {
+        let category = categorize_one(e);
+        if category != ErrorCategory::Other {
+            return category
+        }
+        next = e.source();
+    }
+    ErrorCategory::Other
+}
+
+fn categorize_one(
+    e: &(dyn std::error::Error + 'static),
+) -> ErrorCategory {
+    if let Some(e) = e.downcast_ref::<std::io::Error>() {
+        return match e.kind() {
+            std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::InvalidData => ErrorCategory::InvalidInput,
+            _ => ErrorCategory::Io,
+        }
+    }
+
+    let incompatible = e.is::<IncompatibleInputsErr>() || e.is::<IncompatibleFileHeadersErr>()
+        || e.is::<DuplicatedQueriesErr>() || e.is::<QueryFilterMismatchErr>() || e.is::<InconsistentQueriesErr>();
+    if incompatible {
+        return ErrorCategory::Incompatible
+    }
+
+    let invalid_input = e.is::<AhdaVersionErr>() || e.is::<AhdaFormatVersionErr>() || e.is::<AhdaHeaderError>()
+        || e.is::<UnrecognizedInputFormatErr>() || e.is::<AmbiguousInputFormatErr>() || e.is::<CorruptedInputErr>()
+        || e.is::<NeedTargetSequencesErr>() || e.is::<NeedQueryNamesErr>()
+        || e.is::<BifrostHeaderNotConsumedError>() || e.is::<AhdaTSVHeaderNotConsumedError>()
+        || e.is::<MissingBlockReferenceErr>() || e.is::<UnknownBlockErr>() || e.is::<TargetIndexOutOfRangeErr>()
+        || e.is::<BlockQueryCountErr>() || e.is::<TruncatedBlockErr>() || e.is::<UnknownBitmapTypeErr>()
+        || e.is::<UnknownMetadataCompressionErr>() || e.is::<TruncatedFileHeaderErr>() || e.is::<QueryIndexOutOfRangeErr>()
+        || e.is::<UnknownQueryErr>() || e.is::<UnknownTargetErr>() || e.is::<MissingBlockFieldsErr>()
+        || e.is::<InvalidTargetGroupsErr>() || e.is::<InvalidTargetAliasesErr>() || e.is::<UnknownTargetGroupErr>()
+        || e.is::<MissingQueryNamesErr>() || e.is::<InvalidQueryCacheErr>() || e.is::<DuplicateQueryNameErr>()
+        || e.is::<MissingQueryNameErr>() || e.is::<IncompleteRecordErr>() || e.is::<InvalidTargetRenamesErr>() || e.is::<InvalidQuerySourcesErr>() || e.is::<InvalidMergePlanErr>() || e.is::<MissingHitsErr>() || e.is::<ShardOutOfRangeErr>()
+        || e.is::<DuplicateTargetNameErr>() || e.is::<DuplicateFileNameErr>() || e.is::<ZstdContainerErr>() || e.is::<TargetChunksErr>() || e.is::<InvalidTargetChunksErr>() || e.is::<NoInputsErr>()
+        || e.is::<IndexNotFoundErr>() || e.is::<MultipleIndexesErr>() || e.is::<IndexTargetsNotFoundErr>()
+        || e.is::<QueryRankOutOfRangeErr>() || e.is::<NoMatchingInputsErr>() || e.is::<CoverageNeedsPositionsErr>()
+        || e.is::<MissingFieldErr>() || e.is::<MissingFlagsDictionaryErr>() || e.is::<bincode::error::DecodeError>() || e.is::<ahda_core::Error>();
+    if invalid_input {
+        return ErrorCategory::InvalidInput
+    }
+
+    ErrorCategory::Other
+}
@@ -0,0 +1,226 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Estimate the size of encoded data without writing it.
+//!
+//! Encoding a large alignment only to find out that another block size or
+//! compression level would have been better wastes most of the time spent.
+//! [estimate_encoded_size] encodes an evenly spaced sample of at most
+//! [SAMPLE_BLOCKS] blocks with the given [EncodeOpts] and scales their size
+//! to all records, and [estimate_encoded_size_from_bitmap] does the same for
+//! an alignment given as the set bits of the flattened alignment matrix, see
+//! [BitmapEncoder](crate::encoder::bitmap_encoder::BitmapEncoder).
+//!
+//! The estimate is exact if the records fit in [SAMPLE_BLOCKS] blocks. The
+//! zstd-seekable container of [EncodeOpts::zstd_level] is not included.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::estimate::estimate_encoded_size;
+//! use ahda::encode_to_write;
+//! use ahda::EncodeOpts;
+//! use ahda::PseudoAln;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let queries: Vec<Vec<u8>> = (0..1000).map(|i| format!("r{}", i).into_bytes()).collect();
+//! let data: Vec<PseudoAln> = (0..1000).map(|i| PseudoAln{ ones_names: None, query_id: Some(i), ones: Some(vec![i % 2]), query_name: None }).collect();
+//!
+//! let estimate = estimate_encoded_size(&targets, &queries, &data, &EncodeOpts::default()).unwrap();
+//!
+//! let mut bytes: Vec<u8> = Vec::new();
+//! encode_to_write(&targets, &queries, &data, &mut bytes, EncodeOpts::default()).unwrap();
+//! assert_eq!(estimate.total_bytes(), bytes.len() as u64);
+//! ```
+//!
+
+use crate::EncodeOpts;
+use crate::PseudoAln;
+use crate::encoder::Encoder;
+use crate::headers::file::decode_file_header;
+
+use roaring::RoaringTreemap;
+
+type E = Box<dyn std::error::Error>;
+
+/// Maximum number of blocks encoded by [estimate_encoded_size].
+pub const SAMPLE_BLOCKS: usize = 8;
+
+/// Estimated size of encoded data, see [estimate_encoded_size].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeEstimate {
+    /// Size of the file header and flags, exact.
+    pub header_bytes: u64,
+    /// Number of blocks, exact.
+    pub n_blocks: u64,
+    /// Number of blocks that were encoded to estimate their size.
+    pub n_sampled: u64,
+    /// Estimated size of the blocks including their headers and flags.
+    pub block_bytes: u64,
+}
+
+impl SizeEstimate {
+    /// Estimated size of the encoded data.
+    pub fn total_bytes(
+        &self,
+    ) -> u64 {
+        self.header_bytes + self.block_bytes
+    }
+
+    /// Check if the estimate is exact, ie. every block was encoded.
+    pub fn is_exact(
+        &self,
+    ) -> bool {
+        self.n_sampled == self.n_blocks
+    }
+}
+
+/// Estimate the size of `records` encoded with [encode_to_write](crate::encode_to_write).
+///
+/// The records are split into blocks in the order given, like
+/// [encode_to_write](crate::encode_to_write) does. At most [SAMPLE_BLOCKS]
+/// evenly spaced blocks are encoded and their size per record is scaled to
+/// all records.
+///
+/// ## Errors and panics
+/// Errors if `opts` can't be applied or a sampled block can't be encoded,
+/// like [encode_to_write](crate::encode_to_write).
+///
+pub fn estimate_encoded_size(
+    targets: &[Vec<u8>],
+    queries: &[Vec<u8>],
+    records: &[PseudoAln],
+    opts: &EncodeOpts,
+) -> Result<SizeEstimate, E> {
+    estimate_sampled_blocks(targets, queries, records.len(), opts, |start, end| records[start..end].to_vec())
+}
+
+/// Estimate the size of the alignment in `bits` encoded with a [BitmapEncoder](crate::encoder::bitmap_encoder::BitmapEncoder).
+///
+/// Bit `query_id * targets.len() + target_id` of `bits` is set if the query
+/// aligned to the target. Every query in `queries` is a record, and the
+/// query names are stored if [EncodeOpts::encode_query_names] is set.
+///
+/// ## Errors and panics
+/// Errors like [estimate_encoded_size] and with
+/// [QueryIndexOutOfRangeErr](crate::errors::QueryIndexOutOfRangeErr) if
+/// `bits` has a bit past the last query.
+///
+pub fn estimate_encoded_size_from_bitmap(
+    targets: &[Vec<u8>],
+    queries: &[Vec<u8>],
+    bits: &RoaringTreemap,
+    opts: &EncodeOpts,
+) -> Result<SizeEstimate, E> {
+    let n_targets = targets.len() as u64;
+    if bits.max().is_some_and(|x| x >= queries.len() as u64 * n_targets) {
+        return Err(Box::new(crate::errors::QueryIndexOutOfRangeErr{}))
+    }
+    estimate_sampled_blocks(targets, queries, queries.len(), opts, |start, end| {
+        let mut records: Vec<PseudoAln> = (start..end).map(|query_id| {
+            PseudoAln{ ones_names: None, query_id: Some(query_id as u32), ones: Some(Vec::new()), query_name: Some(queries[query_id].clone()) }
+        }).collect();
+        let (first, last) = (start as u64 * n_targets, end as u64 * n_targets);
+        for bit in bits.iter().skip_while(|x| *x < first).take_while(|x| *x < last) {
+            records[(bit / n_targets) as usize - start].ones.as_mut().unwrap().push((bit % n_targets) as u32);
+        }
+        records
+    })
+}
+
+// Encodes the sampled blocks of `n_records` records, the records from `start` to `end` are `block(start, end)`
+fn estimate_sampled_blocks<F: Fn(usize, usize) -> Vec<PseudoAln>>(
+    targets: &[Vec<u8>],
+    queries: &[Vec<u8>],
+    n_records: usize,
+    opts: &EncodeOpts,
+    block: F,
+) -> Result<SizeEstimate, E> {
+    let have_queries = !queries.is_empty();
+
+    // The block size depends on the options but not on the records
+    let probe_opts = EncodeOpts{ metadata_compression: Default::default(), ..opts.clone() };
+    let mut empty = std::iter::empty::<PseudoAln>();
+    let mut probe = Encoder::new(&mut empty, targets, &opts.accession, queries.len());
+    crate::configure_encoder(&mut probe, &probe_opts, have_queries)?;
+    let block_size = decode_file_header(&probe.encode_file_header_and_flags()?[0..32])?.block_size as usize;
+
+    let n_blocks = n_records.div_ceil(block_size);
+    let sampled: Vec<usize> = if n_blocks <= SAMPLE_BLOCKS {
+        (0..n_blocks).collect()
+    } else {
+        (0..SAMPLE_BLOCKS).map(|i| i * n_blocks / SAMPLE_BLOCKS).collect()
+    };
+    let mut sample: Vec<PseudoAln> = Vec::new();
+    for idx in sampled.iter() {
+        sample.extend(block(idx * block_size, ((idx + 1) * block_size).min(n_records)));
+    }
+    let n_sampled_records = sample.len();
+
+    let mut records = sample.into_iter();
+    let mut encoder = Encoder::new(&mut records, targets, &opts.accession, queries.len());
+    crate::configure_encoder(&mut encoder, opts, have_queries)?;
+    let header_bytes = encoder.encode_file_header_and_flags()?.len() as u64;
+    let mut sampled_bytes: u64 = 0;
+    for bytes in encoder.by_ref() {
+        sampled_bytes += bytes?.len() as u64;
+    }
+
+    let block_bytes = if n_sampled_records == n_records {
+        sampled_bytes
+    } else {
+        (sampled_bytes as f64 * n_records as f64 / n_sampled_records as f64).round() as u64
+    };
+    Ok(SizeEstimate{ header_bytes, n_blocks: n_blocks as u64, n_sampled: sampled.len() as u64, block_bytes })
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn estimate_sampled_blocks_close_to_encoded() {
+        use super::estimate_encoded_size;
+        use super::estimate_encoded_size_from_bitmap;
+        use super::SAMPLE_BLOCKS;
+        use crate::encoder::bitmap_encoder::BitmapEncoder;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+        let n_queries: u32 = 600_000;
+        let queries: Vec<Vec<u8>> = (0..n_queries).map(|i| format!("ERR4035126.{}", i).into_bytes()).collect();
+        let data: Vec<PseudoAln> = (0..n_queries).map(|i| PseudoAln{ ones_names: None, query_id: Some(i), ones: Some((0..3).filter(|x| (i + x) % 4 == 0).collect()), query_name: Some(queries[i as usize].clone()) }).collect();
+        let opts = EncodeOpts{ encode_query_names: true, ..Default::default() };
+
+        let got = estimate_encoded_size(&targets, &queries, &data, &opts).unwrap();
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &queries, &data, &mut bytes, opts.clone()).unwrap();
+        assert_eq!(got.n_sampled, SAMPLE_BLOCKS as u64);
+        assert!(!got.is_exact());
+        assert!((got.total_bytes() as f64 / bytes.len() as f64 - 1.0).abs() < 0.05);
+
+        // Same alignment as set bits
+        let bits: roaring::RoaringTreemap = data.iter().flat_map(|x| x.ones.as_ref().unwrap().iter().map(|y| x.query_id.unwrap() as u64 * 3 + *y as u64)).collect();
+        let got = estimate_encoded_size_from_bitmap(&targets, &queries, &bits, &opts).unwrap();
+        let mut set_bits = bits.iter();
+        let mut encoder = BitmapEncoder::new(&mut set_bits, &targets, &queries, b"");
+        let n_bytes: usize = encoder.encode_file_header_and_flags().unwrap().len() + encoder.by_ref().map(|x| x.unwrap().len()).sum::<usize>();
+        assert!((got.total_bytes() as f64 / n_bytes as f64 - 1.0).abs() < 0.05);
+
+        let mut out_of_range = bits.clone();
+        out_of_range.insert(n_queries as u64 * 3);
+        assert!(estimate_encoded_size_from_bitmap(&targets, &queries, &out_of_range, &opts).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Read query names from .fasta and .fastq files.
+//!
+//! The query names are needed to encode the formats that only store query
+//! ids, and to assign query ids to the formats that only store names. The
+//! order of the records in the query file that was aligned defines the
+//! query ids.
+//!
+//! Reads in .fastq files and assemblies or contigs in .fasta files are both
+//! queries. Sequences in .fasta files may span several lines and are
+//! counted as one query each. The name of a query is the first word of its
+//! header line, see [query_name], so that a read or contig is named the same
+//! whether the aligner kept the description or not.
+//!
+//! Requires the `fastx` feature.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::fastx::query_name;
+//!
+//! assert_eq!(query_name(b"ERR4035126.1 1/1"), b"ERR4035126.1");
+//! assert_eq!(query_name(b"contig_2\tlength=5000"), b"contig_2");
+//! ```
+//!
+
+use std::path::Path;
+
+type E = Box<dyn std::error::Error>;
+
+/// Name of a query with the header line `id`.
+///
+/// The name is `id` up to the first space, tab, or carriage return.
+pub fn query_name(
+    id: &[u8],
+) -> &[u8] {
+    let end = id.iter().position(|x| x.is_ascii_whitespace());
+    &id[0..end.unwrap_or(id.len())]
+}
+
+/// Iterator over the query names in a .fasta or .fastq file.
+///
+/// The name of a record is given by [query_name]. Compressed files are
+/// decompressed automatically.
+pub struct FastxNameReader {
+    reader: Box<dyn needletail::FastxReader>,
+}
+
+impl FastxNameReader {
+    pub fn new<P: AsRef<Path>>(
+        file: P,
+    ) -> Result<Self, E> {
+        let reader = needletail::parse_fastx_file(file)?;
+        Ok(Self{ reader })
+    }
+
+    /// Read the name of the next record.
+    ///
+    /// Returns None at the end of the file.
+    ///
+    /// ## Errors and panics
+    /// Errors if the record is not valid .fasta or .fastq.
+    ///
+    pub fn try_next(
+        &mut self,
+    ) -> Option<Result<Vec<u8>, E>> {
+        let record = self.reader.next()?;
+        Some(record.map(|x| query_name(x.id()).to_vec()).map_err(|e| Box::new(e) as E))
+    }
+
+    /// Iterate over the names and stop at the first error.
+    pub fn try_iter<'b>(
+        &'b mut self,
+    ) -> Box<dyn Iterator<Item=Result<Vec<u8>, E>> + 'b> {
+        Box::new(std::iter::from_fn(move || self.try_next()))
+    }
+}
+
+impl Iterator for FastxNameReader {
+    type Item = Vec<u8>;
+
+    /// Panics if the record is not valid, see [try_next](FastxNameReader::try_next).
+    fn next(
+        &mut self,
+    ) -> Option<Vec<u8>> {
+        self.try_next().map(|x| x.unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn read_names_from_fasta_and_fastq() {
+        use super::FastxNameReader;
+
+        let dir = std::env::temp_dir().join(format!("ahda-fastx-names-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let fasta = dir.join("contigs.fasta");
+        std::fs::write(&fasta, b">contig_1 length=8\nACGT\nACGT\n>contig_2\tlength=4\r\nACGT\r\n>contig_3\nAC\nGT\nAC\n").unwrap();
+        let got: Vec<Vec<u8>> = FastxNameReader::new(&fasta).unwrap().collect();
+        assert_eq!(got, vec![b"contig_1".to_vec(), b"contig_2".to_vec(), b"contig_3".to_vec()]);
+
+        let fastq = dir.join("reads.fastq");
+        std::fs::write(&fastq, b"@r1 1/1\nACGT\n+\nIIII\n@r2\nACGT\n+\nIIII\n").unwrap();
+        let got: Vec<Vec<u8>> = FastxNameReader::new(&fastq).unwrap().collect();
+        assert_eq!(got, vec![b"r1".to_vec(), b"r2".to_vec()]);
+
+        let broken = dir.join("broken.fastq");
+        std::fs::write(&broken, b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n").unwrap();
+        let got: Result<Vec<Vec<u8>>, _> = FastxNameReader::new(&broken).unwrap().try_iter().collect();
+        assert!(got.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -11,85 +11,34 @@
 // the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
 // at your option.
 //
+use crate::bloom::BloomFilter;
+use crate::compression::MetadataCompression;
+use crate::compression::flags_dictionary::FlagsDictionary;
 use crate::compression::gzwrapper::deflate_bytes;
-use crate::compression::gzwrapper::inflate_bytes;
+use crate::compression::gzwrapper::inflate_bytes_into;
+use crate::headers::extensions::Extensions;
+use crate::headers::file::decode_section;
+use crate::headers::file::decode_unknown_fields;
+use crate::headers::file::encode_section;
+use crate::headers::file::encode_sections;
+
+use ahda_core::block::BLOCK_FLAGS_RAW;
+use ahda_core::block::BLOCK_QUERY_IDS_U16;
+use ahda_core::block::decode_query_ids;
+use ahda_core::block::encode_query_ids;
+use ahda_core::block::query_ids_fit_u16;
 
 use std::io::Read;
 
 use bincode::{Encode, Decode};
 use bincode::encode_into_std_write;
 use bincode::decode_from_slice;
+use bincode::decode_from_slice_with_context;
 
 type E = Box<dyn std::error::Error>;
 
-/// Block header for encoded data
-///
-/// Always the first 32 bytes at the beginning of a .ahda block.
-///
-/// Must always conform to this format.
-///
-/// The BlockHeader contains the following data:
-/// - Number of records stored in this block.
-/// - The metadata compression method used for [BlockFlags].
-/// - Type of bitmap stored in this block.
-/// - A 1 byte unused placeholder value.
-/// - The number of bytes in the block contents that follow the [BlockFlags] bytes.
-/// - Number of bytes containing the [BlockFlags] that follow the header.
-/// - Fields that are present in the [BlockFlags].
-/// - A 2 byte unused placeholder value.
-/// - A 8 byte unused placeholder value.
-///
-#[derive(Clone, Debug, Decode, Encode, PartialEq)]
-pub struct BlockHeader {
-    /// Number of records (queries) stored in this block
-    pub num_records: u32,
-
-    /// Compression method used for [BlockFlags], see [MetadataCompression](crate::compression::MetadataCompression).
-    pub metadata_compression: u8,
-
-    /// Bitmap type used to encode this block, see [BitmapType](crate::compression::BitmapType) for details.
-    ///
-    /// The address space of the block bitmap type must fit within the file
-    /// bitmap type specified in [crate::headers::file::FileHeader]. Given that this is satisfied, the
-    /// block bitmap type may differ from the file bitmap type.
-    pub bitmap_type: u16,
-
-    /// Placeholder:
-    pub placeholder1: u8,
-
-    /// Number of bytes in the block contents that follow the flags bytes.
-    pub block_len: u32,
-
-    /// Number of bytes in [BlockFlags] that follow the header bytes.
-    pub flags_len: u64,
-
-    /// [BlockFlags] fields that are present for records in this block.
-    ///
-    /// Every block must contain at least the fields given in the [crate::headers::file::FileHeader]
-    /// `fields_present`. A block may also contain extra fields that are not
-    /// specified in [crate::headers::file::FileHeader].
-    pub fields_present: u16,
-
-    /// Placeholder
-    pub placeholder2: u16,
-
-    /// Placeholder
-    pub placeholder3: u64,
-}
-
-impl BlockHeader {
-    pub fn promises_query_names(
-        &self,
-    ) -> bool {
-        (self.fields_present & crate::MASK_QUERIES) != 0
-    }
-
-    pub fn promises_query_ids(
-        &self,
-    ) -> bool {
-        (self.fields_present & crate::MASK_QUERY_IDS) != 0
-    }
-}
+pub use ahda_core::block::BlockHeader;
+pub use ahda_core::block::BlockStats;
 
 /// Data about the records in this block
 ///
@@ -98,22 +47,128 @@ impl BlockHeader {
 /// Must contain these fields:
 /// - `queries`: Names of the query sequences in the original query file.
 /// - `query_ids`: Indexes of the query sequences in the original query file.
+///   Stored as u16 offsets from the smallest id when they fit and the block
+///   header has [BLOCK_QUERY_IDS_U16], see [BlockFlags::storage_flags].
+///
+/// May contain these fields:
+/// - `mates`: Which mate of a read pair each query is, see [BlockFlags::mate].
+/// - `query_filter`: [BloomFilter] of the names in `queries`, see [BlockFlags::might_contain].
+/// - `extensions`: Fields added by other tools, see [Extensions].
 ///
-/// `queries` and `query_ids` must be in the same order, ie. the data in both
-/// arrays at the same index corresponds to the same record.
+/// `queries`, `query_ids`, and `mates` must be in the same order, ie. the
+/// data in each array at the same index corresponds to the same record.
 ///
 /// The current implementation of ahda assumes that `queries` and `query_ids`
 /// are always present and filled.
 ///
-#[derive(Clone, Decode, Debug, Encode, PartialEq)]
+/// `mates`, `query_filter`, and `extensions` are each written as a
+/// length-prefixed section after `query_ids`, with an empty section in place
+/// of a missing field. Missing fields at the end are not written, so blocks
+/// without them are identical to blocks from versions that did not support
+/// them.
+///
+/// Sections after `extensions` from newer versions of ahda are kept as is in
+/// `unknown_fields` by versions that don't know them, like in
+/// [FileFlags](crate::headers::file::FileFlags).
+///
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct BlockFlags {
     /// Names of query records in the original query file.
     pub queries: Option<Vec<Vec<u8>>>,
     /// Indexes of the query records in the original query file.
     pub query_ids: Option<Vec<u32>>,
+    /// Bits marking the records that are the second mate of a read pair, 8 records per byte.
+    pub mates: Option<Vec<u8>>,
+    /// Bloom filter of the query names.
+    pub query_filter: Option<BloomFilter>,
+    /// Fields added by other tools
+    pub extensions: Option<Extensions>,
+    /// Encoded fields after `extensions` from a newer version of ahda, in order
+    pub unknown_fields: Vec<Vec<u8>>,
+}
+
+impl Encode for BlockFlags {
+    fn encode<En: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut En,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.encode_fields(false, encoder)
+    }
+}
+
+impl<Context> Decode<Context> for BlockFlags {
+    fn decode<D: bincode::de::Decoder<Context = Context>>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        BlockFlags::decode_fields(false, decoder)
+    }
+}
+bincode::impl_borrow_decode!(BlockFlags);
+
+/// [BlockFlags] with the query ids stored as u16 offsets if the bool is true.
+struct PackedFlags<'a>(&'a BlockFlags, bool);
+
+impl Encode for PackedFlags<'_> {
+    fn encode<En: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut En,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.0.encode_fields(self.1, encoder)
+    }
+}
+
+/// [BlockFlags] decoded with the query ids stored as u16 offsets if the context is true.
+struct UnpackedFlags(BlockFlags);
+
+impl Decode<bool> for UnpackedFlags {
+    fn decode<D: bincode::de::Decoder<Context = bool>>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let as_u16 = *decoder.context();
+        Ok(UnpackedFlags(BlockFlags::decode_fields(as_u16, decoder)?))
+    }
 }
 
 impl BlockFlags {
+    fn encode_fields<En: bincode::enc::Encoder>(
+        &self,
+        query_ids_u16: bool,
+        encoder: &mut En,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.queries.encode(encoder)?;
+        encode_query_ids(self.query_ids.as_deref(), query_ids_u16, encoder)?;
+        let sections = [
+            self.mates.as_ref().map(encode_section).transpose()?,
+            self.query_filter.as_ref().map(encode_section).transpose()?,
+            self.extensions.as_ref().filter(|x| !x.is_empty()).map(encode_section).transpose()?,
+        ];
+        encode_sections(&sections, &self.unknown_fields, encoder)
+    }
+
+    fn decode_fields<Context, D: bincode::de::Decoder<Context = Context>>(
+        query_ids_u16: bool,
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let queries = Option::<Vec<Vec<u8>>>::decode(decoder)?;
+        let query_ids = decode_query_ids(query_ids_u16, decoder)?;
+        let mates = decode_section::<Vec<u8>, _, _>(decoder)?;
+        let query_filter = decode_section::<BloomFilter, _, _>(decoder)?;
+        let extensions = decode_section::<Extensions, _, _>(decoder)?.filter(|x| !x.is_empty());
+        let unknown_fields = decode_unknown_fields(decoder)?;
+        Ok(BlockFlags{ queries, query_ids, mates, query_filter, extensions, unknown_fields })
+    }
+
+    /// Bits of `placeholder1` in the [BlockHeader] for storing these flags compactly.
+    ///
+    /// Has [BLOCK_QUERY_IDS_U16] if the query ids fit in u16 offsets, see
+    /// [query_ids_fit_u16]. Give the header with these bits to
+    /// [encode_block_flags_with_header].
+    pub fn storage_flags(
+        &self,
+    ) -> u8 {
+        if query_ids_fit_u16(self.query_ids.as_deref()) { BLOCK_QUERY_IDS_U16 } else { 0 }
+    }
+
     pub fn fields_present(
         &self,
     ) -> u16 {
@@ -124,27 +179,106 @@ impl BlockFlags {
         if self.query_ids.is_some() {
             flags |= crate::MASK_QUERY_IDS;
         }
+        if self.mates.is_some() {
+            flags |= crate::MASK_MATES;
+        }
+        if self.query_filter.is_some() {
+            flags |= crate::MASK_QUERY_FILTER;
+        }
         flags
     }
+
+    /// Check if a record named `query_name` may be in this block.
+    ///
+    /// Returns false only if the block has a query filter that does not
+    /// contain `query_name`. Blocks without a query filter may contain any
+    /// name.
+    pub fn might_contain(
+        &self,
+        query_name: &[u8],
+    ) -> bool {
+        self.query_filter.as_ref().is_none_or(|filter| filter.contains(query_name))
+    }
+
+    /// Get the mate (1 or 2) of the record at `index` in this block.
+    ///
+    /// Returns None if the block does not store mates.
+    pub fn mate(
+        &self,
+        index: usize,
+    ) -> Option<u8> {
+        let mates = self.mates.as_ref()?;
+        let second = mates.get(index / 8).is_some_and(|byte| byte & (1 << (index % 8)) != 0);
+        Some(if second { 2 } else { 1 })
+    }
+
+    /// Sort the records by query id.
+    ///
+    /// The query names and mates are permuted to keep them in the same order
+    /// as the ids, so the flags still describe the same bitmap. Returns false
+    /// if the ids were already sorted.
+    pub fn sort_by_query_id(
+        &mut self,
+    ) -> bool {
+        let Some(query_ids) = self.query_ids.as_ref().filter(|ids| !ids.is_sorted()) else {
+            return false
+        };
+        let mut order: Vec<usize> = (0..query_ids.len()).collect();
+        order.sort_by_key(|i| query_ids[*i]);
+
+        let query_ids = order.iter().map(|i| query_ids[*i]).collect();
+        let queries = self.queries.as_ref().map(|names| order.iter().filter_map(|i| names.get(*i).cloned()).collect());
+        let mates = self.mates.as_ref().map(|_| pack_mates(order.iter().map(|i| self.mate(*i) == Some(2))));
+        self.query_ids = Some(query_ids);
+        self.queries = queries;
+        self.mates = mates;
+        true
+    }
+
+    /// Index of the record with `query_id` in `query_ids`.
+    ///
+    /// Binary searches the ids if they are sorted, see
+    /// [sort_by_query_id](BlockFlags::sort_by_query_id), and scans them
+    /// otherwise. Returns None if the block does not contain `query_id`.
+    pub fn position(
+        &self,
+        query_id: u32,
+    ) -> Option<usize> {
+        let query_ids = self.query_ids.as_ref()?;
+        match query_ids.binary_search(&query_id) {
+            Ok(index) => Some(index),
+            Err(_) if query_ids.is_sorted() => None,
+            Err(_) => query_ids.iter().position(|id| *id == query_id),
+        }
+    }
+}
+
+/// Pack whether each record is the second mate of a read pair into bits for [BlockFlags].
+pub fn pack_mates<I: Iterator<Item = bool>>(
+    is_second_mate: I,
+) -> Vec<u8> {
+    let mut bits: Vec<u8> = Vec::new();
+    is_second_mate.enumerate().for_each(|(index, second)| {
+        if index % 8 == 0 {
+            bits.push(0);
+        }
+        if second {
+            *bits.last_mut().unwrap() |= 1 << (index % 8);
+        }
+    });
+    bits
 }
 
 pub fn encode_block_header(
     header: &BlockHeader,
 ) -> Result<Vec<u8>, E> {
-    let mut bytes: Vec<u8> = Vec::new();
-    let nbytes = encode_into_std_write(
-        header,
-        &mut bytes,
-        bincode::config::standard().with_fixed_int_encoding(),
-    )?;
-    assert_eq!(nbytes, 32);
-    Ok(bytes)
+    Ok(ahda_core::block::encode_block_header(header))
 }
 
 pub fn decode_block_header(
     header_bytes: &[u8],
 ) -> Result<BlockHeader, E> {
-    Ok(decode_from_slice(header_bytes, bincode::config::standard().with_fixed_int_encoding())?.0)
+    Ok(ahda_core::block::decode_block_header(header_bytes)?)
 }
 
 pub fn read_block_header<R: Read>(
@@ -160,12 +294,21 @@ pub fn read_block_flags<R: Read>(
     header: &BlockHeader,
     conn: &mut R,
 ) -> Result<BlockFlags, E> {
-    let mut flags_bytes: Vec<u8> = vec![0; header.flags_len as usize];
-    conn.read_exact(&mut flags_bytes).unwrap();
-    let res = decode_block_flags(&flags_bytes).unwrap();
+    let flags_bytes = crate::headers::read_len_bytes(conn, header.flags_len)?;
+    let res = decode_block_flags_with_dictionary(&flags_bytes, header, None, &mut Vec::new())?;
     Ok(res)
 }
 
+/// Same as [read_block_flags] but decompresses the flags with `dictionary` if needed, see [decode_block_flags_with_dictionary].
+pub fn read_block_flags_with_dictionary<R: Read>(
+    header: &BlockHeader,
+    dictionary: Option<&FlagsDictionary>,
+    conn: &mut R,
+) -> Result<BlockFlags, E> {
+    let flags_bytes = crate::headers::read_len_bytes(conn, header.flags_len)?;
+    decode_block_flags_with_dictionary(&flags_bytes, header, dictionary, &mut Vec::new())
+}
+
 pub fn read_block_header_and_flags<R: Read>(
     conn: &mut R,
 ) -> Result<(BlockHeader, BlockFlags), E> {
@@ -174,6 +317,11 @@ pub fn read_block_header_and_flags<R: Read>(
     Ok((header, flags))
 }
 
+/// Encode `flags` as gzipped bincode.
+///
+/// The query ids are written as an `Option<Vec<u32>>`, so the block header
+/// must not have [BLOCK_QUERY_IDS_U16] set. Use
+/// [encode_block_flags_with_header] to store them compactly.
 pub fn encode_block_flags(
     flags: &BlockFlags,
 ) -> Result<Vec<u8>, E> {
@@ -189,26 +337,159 @@ pub fn encode_block_flags(
     Ok(bytes)
 }
 
+/// Encode `flags` as bincode without compressing them.
+///
+/// The block header must have [BLOCK_FLAGS_RAW] set in `placeholder1`, see
+/// [pack_records_inline](crate::compression::pack_records_inline), and
+/// [BLOCK_QUERY_IDS_U16] unset like in [encode_block_flags].
+pub fn encode_block_flags_raw(
+    flags: &BlockFlags,
+) -> Result<Vec<u8>, E> {
+    Ok(bincode::encode_to_vec(flags, bincode::config::standard())?)
+}
+
+/// Decode gzipped block flags written by [encode_block_flags].
+///
+/// Flags of blocks with [BLOCK_QUERY_IDS_U16] or [BLOCK_FLAGS_RAW] need the
+/// block header, use [decode_block_flags_with_dictionary] for these.
 pub fn decode_block_flags(
     bytes: &[u8],
 ) -> Result<BlockFlags, E> {
-    let bytes = inflate_bytes(bytes)?;
-    let flags: BlockFlags = decode_from_slice(&bytes, bincode::config::standard())?.0;
+    decode_block_flags_with_buffer(bytes, &mut Vec::new())
+}
+
+/// Same as [decode_block_flags] but decompresses into `buffer`.
+///
+/// Errors with [MissingFlagsDictionaryErr](crate::errors::MissingFlagsDictionaryErr)
+/// if the flags are compressed with a [FlagsDictionary], use
+/// [decode_block_flags_with_dictionary] for these.
+pub fn decode_block_flags_with_buffer(
+    bytes: &[u8],
+    buffer: &mut Vec<u8>,
+) -> Result<BlockFlags, E> {
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        return Err(Box::new(crate::errors::MissingFlagsDictionaryErr{}))
+    }
+    inflate_bytes_into(bytes, buffer)?;
+    let flags: BlockFlags = decode_from_slice(buffer, bincode::config::standard().with_limit::<{ crate::headers::METADATA_LIMIT }>())?.0;
 
     Ok(flags)
 }
 
+// First bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Encode `flags` and compress them with zstd using `dictionary`.
+///
+/// The block header must have [MetadataCompression::ZstdDictionary] in
+/// `metadata_compression` and [BLOCK_QUERY_IDS_U16] unset, see
+/// [pack_block_roaring32_with_dictionary](crate::compression::roaring32::pack_block_roaring32_with_dictionary).
+pub fn encode_block_flags_with_dictionary(
+    flags: &BlockFlags,
+    dictionary: &FlagsDictionary,
+) -> Result<Vec<u8>, E> {
+    let bytes = bincode::encode_to_vec(flags, bincode::config::standard())?;
+    dictionary.compress(&bytes)
+}
+
+/// Decode the block flags of the block with `block_header` from `bytes`.
+///
+/// Flags written with [MetadataCompression::ZstdDictionary] are decompressed
+/// with `dictionary`, flags in blocks with [BLOCK_FLAGS_RAW] are decoded as
+/// is, and the others like in [decode_block_flags_with_buffer].
+///
+/// ## Errors and panics
+/// Errors with [MissingFlagsDictionaryErr](crate::errors::MissingFlagsDictionaryErr)
+/// if the flags need a dictionary and `dictionary` is None, and if the flags
+/// can't be decoded.
+///
+pub fn decode_block_flags_with_dictionary(
+    bytes: &[u8],
+    block_header: &BlockHeader,
+    dictionary: Option<&FlagsDictionary>,
+    buffer: &mut Vec<u8>,
+) -> Result<BlockFlags, E> {
+    let bincode_bytes = if block_header.placeholder1 & BLOCK_FLAGS_RAW != 0 {
+        bytes
+    } else if MetadataCompression::from_u8(block_header.metadata_compression)? != MetadataCompression::ZstdDictionary {
+        if bytes.starts_with(&ZSTD_MAGIC) {
+            return Err(Box::new(crate::errors::MissingFlagsDictionaryErr{}))
+        }
+        inflate_bytes_into(bytes, buffer)?;
+        buffer.as_slice()
+    } else {
+        let dictionary = dictionary.ok_or(crate::errors::MissingFlagsDictionaryErr{})?;
+        dictionary.decompress_into(bytes, buffer)?;
+        buffer.as_slice()
+    };
+    let as_u16 = block_header.placeholder1 & BLOCK_QUERY_IDS_U16 != 0;
+    let flags: UnpackedFlags = decode_from_slice_with_context(bincode_bytes, bincode::config::standard().with_limit::<{ crate::headers::METADATA_LIMIT }>(), as_u16)?.0;
+
+    Ok(flags.0)
+}
+
+/// Encode `flags` for the block with `block_header`.
+///
+/// The inverse of [decode_block_flags_with_dictionary]. The query ids are
+/// stored as u16 offsets if `placeholder1` has [BLOCK_QUERY_IDS_U16], see
+/// [BlockFlags::storage_flags]. The flags are stored as is if it has
+/// [BLOCK_FLAGS_RAW], compressed with `dictionary` if `metadata_compression`
+/// is [MetadataCompression::ZstdDictionary], and gzipped otherwise.
+///
+/// ## Errors and panics
+/// Errors with [MissingFlagsDictionaryErr](crate::errors::MissingFlagsDictionaryErr)
+/// if the flags need a dictionary and `dictionary` is None, and if the query
+/// ids don't fit in u16 offsets but the header has [BLOCK_QUERY_IDS_U16].
+///
+pub fn encode_block_flags_with_header(
+    flags: &BlockFlags,
+    block_header: &BlockHeader,
+    dictionary: Option<&FlagsDictionary>,
+) -> Result<Vec<u8>, E> {
+    let as_u16 = block_header.placeholder1 & BLOCK_QUERY_IDS_U16 != 0;
+    let bytes = bincode::encode_to_vec(PackedFlags(flags, as_u16), bincode::config::standard())?;
+    if block_header.placeholder1 & BLOCK_FLAGS_RAW != 0 {
+        return Ok(bytes)
+    }
+    match MetadataCompression::from_u8(block_header.metadata_compression)? {
+        MetadataCompression::ZstdDictionary => dictionary.ok_or(crate::errors::MissingFlagsDictionaryErr{})?.compress(&bytes),
+        _ => deflate_bytes(&bytes),
+    }
+}
+
 pub fn encode_block_header_and_flags(
     header: &BlockHeader,
     flags: &BlockFlags,
 ) -> Result<Vec<u8>, E> {
     let mut bytes = encode_block_header(header)?;
-    let mut flags_bytes = encode_block_flags(flags)?;
+    let mut flags_bytes = encode_block_flags_with_header(flags, header, None)?;
     assert_eq!(header.flags_len, flags_bytes.len() as u64);
     bytes.append(&mut flags_bytes);
     Ok(bytes)
 }
 
+/// Same as [encode_block_header_and_flags] but compresses `flags` with `dictionary`.
+///
+/// `flags_len`, `metadata_compression`, and the [storage flags](BlockFlags::storage_flags)
+/// of `header` are set to match the encoded flags, so the flags of a block
+/// can be compressed again with a different dictionary or without one.
+/// Flags stored without compression, see [BLOCK_FLAGS_RAW], are compressed
+/// too.
+pub fn encode_block_header_and_flags_with_dictionary(
+    header: &BlockHeader,
+    flags: &BlockFlags,
+    dictionary: Option<&FlagsDictionary>,
+) -> Result<Vec<u8>, E> {
+    let metadata_compression = if dictionary.is_some() { MetadataCompression::ZstdDictionary } else { MetadataCompression::default() };
+    let placeholder1 = (header.placeholder1 & !(BLOCK_FLAGS_RAW | BLOCK_QUERY_IDS_U16)) | flags.storage_flags();
+    let mut header = BlockHeader{ metadata_compression: metadata_compression.to_u8(), placeholder1, ..header.clone() };
+    let mut flags_bytes = encode_block_flags_with_header(flags, &header, dictionary)?;
+    header.flags_len = flags_bytes.len() as u64;
+    let mut bytes = encode_block_header(&header)?;
+    bytes.append(&mut flags_bytes);
+    Ok(bytes)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -256,7 +537,7 @@ mod tests {
         use super::encode_block_flags;
         use super::BlockFlags;
 
-        let data = BlockFlags{ queries: Some(vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()]), query_ids: Some(vec![1, 0, 2]) };
+        let data = BlockFlags{ queries: Some(vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()]), query_ids: Some(vec![1, 0, 2]), ..Default::default() };
         let expected: Vec<u8> = vec![31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 102, 76, 100, 76, 98, 76, 102, 100, 102, 100, 96, 2, 0, 249, 181, 108, 55, 13, 0, 0, 0];
 
         let got = encode_block_flags(&data).unwrap();
@@ -268,13 +549,175 @@ mod tests {
         use super::decode_block_flags;
         use super::BlockFlags;
 
-        let expected = BlockFlags{ queries: Some(vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()]), query_ids: Some(vec![1, 0, 2]) };
+        let expected = BlockFlags{ queries: Some(vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()]), query_ids: Some(vec![1, 0, 2]), ..Default::default() };
         let data: Vec<u8> = vec![31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 102, 76, 100, 76, 98, 76, 102, 100, 102, 100, 96, 2, 0, 249, 181, 108, 55, 13, 0, 0, 0];
 
         let got = decode_block_flags(&data).unwrap();
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn encode_and_decode_block_flags_with_mates() {
+        use super::decode_block_flags;
+        use super::encode_block_flags;
+        use super::pack_mates;
+        use super::BlockFlags;
+
+        let mates = pack_mates([false, true, false, false, false, false, false, false, true].into_iter());
+        assert_eq!(mates, vec![2, 1]);
+
+        let expected = BlockFlags{ queries: Some(vec![b"a".to_vec(); 9]), query_ids: Some((0..9).collect()), mates: Some(mates), ..Default::default() };
+        let got = decode_block_flags(&encode_block_flags(&expected).unwrap()).unwrap();
+        assert_eq!(got, expected);
+
+        assert_eq!(got.fields_present(), crate::MASK_QUERIES | crate::MASK_QUERY_IDS | crate::MASK_MATES);
+        assert_eq!((0..9).map(|index| got.mate(index).unwrap()).collect::<Vec<u8>>(), vec![1, 2, 1, 1, 1, 1, 1, 1, 2]);
+
+        let without = BlockFlags{ mates: None, ..expected };
+        assert!(decode_block_flags(&encode_block_flags(&without).unwrap()).unwrap().mate(0).is_none());
+    }
+
+    #[test]
+    fn sort_block_flags_by_query_id() {
+        use super::pack_mates;
+        use super::BlockFlags;
+
+        let mut flags = BlockFlags{ queries: Some(vec![b"c".to_vec(), b"a".to_vec(), b"b".to_vec()]), query_ids: Some(vec![7, 2, 5]), mates: Some(pack_mates([true, false, false].into_iter())), ..Default::default() };
+        assert_eq!(flags.position(5), Some(2));
+        assert_eq!(flags.position(3), None);
+
+        assert!(flags.sort_by_query_id());
+        assert_eq!(flags.query_ids, Some(vec![2, 5, 7]));
+        assert_eq!(flags.queries, Some(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]));
+        assert_eq!((0..3).map(|index| flags.mate(index).unwrap()).collect::<Vec<u8>>(), vec![1, 1, 2]);
+        assert!(!flags.sort_by_query_id());
+
+        assert_eq!(flags.position(5), Some(1));
+        assert_eq!(flags.position(3), None);
+        assert_eq!(flags.position(8), None);
+    }
+
+    #[test]
+    fn encode_and_decode_block_flags_with_query_filter() {
+        use super::decode_block_flags;
+        use super::encode_block_flags;
+        use super::BlockFlags;
+        use crate::bloom::BloomFilter;
+
+        let mut filter = BloomFilter::with_fpr(2, 0.001);
+        filter.insert(b"a");
+        filter.insert(b"b");
+
+        let expected = BlockFlags{ queries: Some(vec![b"a".to_vec(), b"b".to_vec()]), query_ids: Some(vec![0, 1]), query_filter: Some(filter), ..Default::default() };
+        let got = decode_block_flags(&encode_block_flags(&expected).unwrap()).unwrap();
+        assert_eq!(got, expected);
+        assert_eq!(got.fields_present(), crate::MASK_QUERIES | crate::MASK_QUERY_IDS | crate::MASK_QUERY_FILTER);
+        assert!(got.might_contain(b"a"));
+        assert!(!got.might_contain(b"c"));
+
+        let with_mates = BlockFlags{ mates: Some(vec![2]), ..expected.clone() };
+        assert_eq!(decode_block_flags(&encode_block_flags(&with_mates).unwrap()).unwrap(), with_mates);
+
+        let without = BlockFlags{ query_filter: None, ..expected };
+        assert!(decode_block_flags(&encode_block_flags(&without).unwrap()).unwrap().might_contain(b"c"));
+    }
+
+    #[test]
+    fn block_flags_keep_unknown_fields() {
+        use super::decode_block_flags;
+        use super::encode_block_flags;
+        use super::BlockFlags;
+
+        let known = BlockFlags{ queries: Some(vec![b"a".to_vec()]), query_ids: Some(vec![0]), ..Default::default() };
+        let newer = BlockFlags{ unknown_fields: vec![b"x".to_vec()], ..known.clone() };
+        assert_eq!(decode_block_flags(&encode_block_flags(&newer).unwrap()).unwrap(), newer);
+        assert_eq!(decode_block_flags(&encode_block_flags(&known).unwrap()).unwrap(), known);
+    }
+
+    #[test]
+    fn block_flags_sections() {
+        use super::encode_block_flags_raw;
+        use super::BlockFlags;
+        use crate::bloom::BloomFilter;
+
+        let decode = |bytes: &[u8]| bincode::decode_from_slice::<BlockFlags, _>(bytes, bincode::config::standard()).map(|x| x.0);
+
+        let known = BlockFlags{ queries: None, query_ids: Some(vec![0]), ..Default::default() };
+        let known_bytes = encode_block_flags_raw(&known).unwrap();
+
+        // Missing mates are an empty section before the query filter
+        let mut filter = BloomFilter::with_fpr(1, 0.01);
+        filter.insert(b"a");
+        let flags = BlockFlags{ query_filter: Some(filter), ..known.clone() };
+        let bytes = encode_block_flags_raw(&flags).unwrap();
+        assert_eq!(bytes[known_bytes.len()], 0);
+        assert_eq!(decode(&bytes).unwrap(), flags);
+
+        // Flags can only end between sections
+        assert_eq!(decode(&bytes[0..known_bytes.len() + 1]).unwrap(), known);
+        for len in known_bytes.len() + 2..bytes.len() {
+            assert!(decode(&bytes[0..len]).is_err());
+        }
+
+        // A field must fill its section
+        let mut padded = known_bytes.clone();
+        padded.extend_from_slice(&[2, 0, 0]);
+        assert!(decode(&padded).is_err());
+    }
+
+    #[test]
+    fn block_flags_with_u16_query_ids() {
+        use super::decode_block_flags_with_dictionary;
+        use super::encode_block_flags;
+        use super::encode_block_flags_with_header;
+        use super::BlockFlags;
+        use super::BlockHeader;
+        use super::BLOCK_QUERY_IDS_U16;
+
+        let ids: Vec<u32> = (1_000_000..1_065_536).collect();
+        let flags = BlockFlags{ queries: None, query_ids: Some(ids.clone()), ..Default::default() };
+        assert_eq!(flags.storage_flags(), BLOCK_QUERY_IDS_U16);
+        let header = BlockHeader{ num_records: 0, placeholder1: flags.storage_flags(), block_len: 0, flags_len: 0, fields_present: 0, placeholder2: 0, placeholder3: 0, bitmap_type: 0, metadata_compression: 0 };
+        let bytes = encode_block_flags_with_header(&flags, &header, None).unwrap();
+        assert_eq!(decode_block_flags_with_dictionary(&bytes, &header, None, &mut Vec::new()).unwrap(), flags);
+        assert!(bytes.len() < encode_block_flags(&flags).unwrap().len());
+
+        // Ids that don't fit in u16 offsets
+        let spread = BlockFlags{ query_ids: Some(vec![0, 1_000_000]), ..flags };
+        assert_eq!(spread.storage_flags(), 0);
+        assert!(encode_block_flags_with_header(&spread, &header, None).is_err());
+        let header = BlockHeader{ placeholder1: 0, ..header };
+        let bytes = encode_block_flags_with_header(&spread, &header, None).unwrap();
+        assert_eq!(decode_block_flags_with_dictionary(&bytes, &header, None, &mut Vec::new()).unwrap(), spread);
+    }
+
+    #[test]
+    fn encode_and_decode_block_flags_with_extensions() {
+        use super::decode_block_flags;
+        use super::encode_block_flags;
+        use super::BlockFlags;
+        use crate::bloom::BloomFilter;
+        use crate::headers::extensions::Extensions;
+
+        let mut extensions = Extensions::default();
+        extensions.insert(b"mytool.note", b"raw bytes".to_vec());
+
+        let expected = BlockFlags{ queries: Some(vec![b"a".to_vec(), b"b".to_vec()]), query_ids: Some(vec![0, 1]), extensions: Some(extensions), ..Default::default() };
+        let got = decode_block_flags(&encode_block_flags(&expected).unwrap()).unwrap();
+        assert_eq!(got, expected);
+        assert_eq!(got.fields_present(), crate::MASK_QUERIES | crate::MASK_QUERY_IDS);
+
+        let mut filter = BloomFilter::with_fpr(2, 0.001);
+        filter.insert(b"a");
+        let with_filter = BlockFlags{ mates: Some(vec![2]), query_filter: Some(filter), ..expected.clone() };
+        assert_eq!(decode_block_flags(&encode_block_flags(&with_filter).unwrap()).unwrap(), with_filter);
+
+        // Empty extensions are not written
+        let without = BlockFlags{ extensions: None, ..expected.clone() };
+        let empty = BlockFlags{ extensions: Some(Extensions::default()), ..expected };
+        assert_eq!(encode_block_flags(&empty).unwrap(), encode_block_flags(&without).unwrap());
+    }
+
     #[test]
     fn read_block_flags() {
         use super::read_block_flags;
@@ -283,7 +726,7 @@ mod tests {
 
         use std::io::Cursor;
 
-        let expected = BlockFlags{ queries: Some(vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()]), query_ids: Some(vec![1, 0, 2]) };
+        let expected = BlockFlags{ queries: Some(vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()]), query_ids: Some(vec![1, 0, 2]), ..Default::default() };
         let data_bytes: Vec<u8> = vec![31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 102, 76, 100, 76, 98, 76, 102, 100, 102, 100, 96, 2, 0, 249, 181, 108, 55, 13, 0, 0, 0];
         let header = BlockHeader{ num_records: 31, placeholder1: 0, block_len: 65511, flags_len: data_bytes.len() as u64, fields_present: 0, placeholder2: 0, placeholder3: 0, bitmap_type: 0, metadata_compression: 0 };
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_bytes);
@@ -305,10 +748,32 @@ mod tests {
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_bytes);
 
         let expected_header = BlockHeader{ num_records: 31, placeholder1: 0, block_len: 65511, flags_len: 33, fields_present: 0, placeholder2: 0, placeholder3: 0, bitmap_type: 0, metadata_compression: 0 };
-        let expected_flags = BlockFlags{ queries: Some(vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()]), query_ids: Some(vec![1, 0, 2]) };
+        let expected_flags = BlockFlags{ queries: Some(vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()]), query_ids: Some(vec![1, 0, 2]), ..Default::default() };
 
         let (got_header, got_flags) = read_block_header_and_flags(&mut data).unwrap();
         assert_eq!(got_header, expected_header);
         assert_eq!(got_flags, expected_flags);
     }
+
+    #[test]
+    fn block_stats_round_trip() {
+        use super::BlockHeader;
+        use super::BlockStats;
+        use super::decode_block_header;
+        use super::encode_block_header;
+        use crate::compression::BLOCK_PAYLOAD_RAW;
+
+        // Headers from older versions have no statistics
+        let old = BlockHeader{ num_records: 31, placeholder1: BLOCK_PAYLOAD_RAW, block_len: 65511, flags_len: 921, fields_present: 0, placeholder2: 0, placeholder3: 0, bitmap_type: 0, metadata_compression: 0 };
+        assert_eq!(old.stats(), None);
+
+        for stats in [BlockStats{ n_ones: 0, max_target: None }, BlockStats{ n_ones: 12, max_target: Some(0) }, BlockStats{ n_ones: u32::MAX, max_target: Some(u32::MAX - 1) }] {
+            let mut header = old.clone();
+            header.set_stats(&stats);
+            assert_eq!(header.placeholder1 & BLOCK_PAYLOAD_RAW, BLOCK_PAYLOAD_RAW);
+
+            let got = decode_block_header(&encode_block_header(&header).unwrap()).unwrap();
+            assert_eq!(got.stats(), Some(stats));
+        }
+    }
 }
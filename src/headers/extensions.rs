@@ -0,0 +1,130 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Fields added to the file and block flags by other tools.
+//!
+//! [FileFlags](crate::headers::file::FileFlags) and
+//! [BlockFlags](crate::headers::block::BlockFlags) both end with an optional
+//! [Extensions] map from a key to the encoded bytes of a field. ahda does not
+//! interpret the values but keeps them when it rewrites a file:
+//!
+//! - `ahda cat`, also with `--dedup`, keeps the file extensions of the first
+//!   input and the block extensions of every block.
+//! - `ahda set` keeps the file extensions of the input that the queries are
+//!   taken from, usually the first input. Block extensions are dropped since
+//!   the blocks are rebuilt from the merged records.
+//!
+//! Keys should be prefixed with the name of the tool that writes them, eg.
+//! `mytool.sample_date`, so that fields from different tools don't collide.
+//! Keys starting with `ahda.` are written by ahda itself, eg. the target cap
+//! in [MAX_HITS_KEY](crate::max_hits::MAX_HITS_KEY).
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::headers::extensions::Extensions;
+//!
+//! let mut extensions = Extensions::default();
+//! extensions.insert(b"mytool.note", b"raw bytes".to_vec());
+//! extensions.insert_as(b"mytool.read_length", &150_u32).unwrap();
+//!
+//! assert_eq!(extensions.get(b"mytool.note"), Some(b"raw bytes".as_slice()));
+//! assert_eq!(extensions.get_as::<u32>(b"mytool.read_length").unwrap().unwrap(), 150);
+//! assert!(extensions.get_as::<u32>(b"mytool.missing").is_none());
+//! ```
+//!
+
+use bincode::{Encode, Decode};
+use bincode::decode_from_slice;
+use bincode::encode_to_vec;
+
+use std::collections::BTreeMap;
+
+type E = Box<dyn std::error::Error>;
+
+/// Map from a key to the encoded bytes of a field
+///
+/// Values stored with [Extensions::insert_as] are encoded with the standard
+/// bincode configuration, so other tools can read them without ahda.
+///
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq)]
+pub struct Extensions {
+    /// Encoded value of each field
+    pub fields: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Extensions {
+    /// Check if there are no fields.
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Get the bytes of field `key` if present.
+    pub fn get(
+        &self,
+        key: &[u8],
+    ) -> Option<&[u8]> {
+        self.fields.get(key).map(|x| x.as_slice())
+    }
+
+    /// Decode field `key` as `T` if present.
+    ///
+    /// Returns an error if the field is present but can't be decoded as `T`.
+    pub fn get_as<T: Decode<()>>(
+        &self,
+        key: &[u8],
+    ) -> Option<Result<T, E>> {
+        let bytes = self.fields.get(key)?;
+        Some(decode_from_slice(bytes, bincode::config::standard()).map(|x| x.0).map_err(|e| e.into()))
+    }
+
+    /// Set field `key` to `value`, returning the old value if there was one.
+    pub fn insert(
+        &mut self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        self.fields.insert(key.to_vec(), value)
+    }
+
+    /// Encode `value` and store it in field `key`.
+    pub fn insert_as<T: Encode>(
+        &mut self,
+        key: &[u8],
+        value: &T,
+    ) -> Result<(), E> {
+        self.fields.insert(key.to_vec(), encode_to_vec(value, bincode::config::standard())?);
+        Ok(())
+    }
+
+    /// Remove field `key`, returning its value if there was one.
+    pub fn remove(
+        &mut self,
+        key: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.fields.remove(key)
+    }
+
+    /// Add the fields in `other` that are not in `self`.
+    pub fn merge_missing(
+        &mut self,
+        other: &Extensions,
+    ) {
+        other.fields.iter().for_each(|(key, value)| {
+            self.fields.entry(key.clone()).or_insert_with(|| value.clone());
+        });
+    }
+}
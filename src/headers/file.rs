@@ -15,10 +15,12 @@ use crate::AhdaVersion;
 use crate::AhdaFormatVersion;
 use crate::compression::BitmapType;
 use crate::compression::MetadataCompression;
+use crate::headers::extensions::Extensions;
 
 use std::io::Read;
 
 use bincode::{Encode, Decode};
+use bincode::de::read::Reader;
 use bincode::encode_into_std_write;
 use bincode::decode_from_slice;
 use crate::compression::gzwrapper::deflate_bytes;
@@ -26,99 +28,452 @@ use crate::compression::gzwrapper::inflate_bytes;
 
 type E = Box<dyn std::error::Error>;
 
-/// File header for encoded data
+pub use ahda_core::file::FileHeader;
+
+/// Data shared with all blocks
+///
+/// Variable length, use [FileHeader].flags_len to get size
 ///
-/// Always the first 32 bytes at the beginning of a .ahda file.
+/// Must contain these fields:
+/// - `query_name`: Name for the query file / sample.
+/// - `target_names`: Names of the alignment target sequences, in the order that they appear for the aligner.
 ///
-/// Must always conform to this format.
+/// May contain these fields:
+/// - `target_groups`: Groupings of the targets at one or more levels, see [TargetGroups].
+/// - `target_aliases`: Original target sequences that were merged into the targets, see [TargetAliases].
+/// - `provenance`: Operation and inputs that the file was derived from, see [Provenance].
+/// - `extensions`: Fields added by other tools, see [Extensions].
 ///
-/// The FileHeader contains the following data:
-/// - Bytes identifying the data as a .ahda file.
-/// - Bytes providing the ahda library version.
-/// - The metadata compression method used for [FileFlags].
-/// - Fields that must be present in every [crate::headers::block::BlockFlags] that follows.
-/// - Number of target sequences in the alignment.
-/// - Number of query sequences in the alignment. This may be 0 if the number was not known in advance.
-/// - Type of bitmap stored in the blocks. This may differ for each block if they were not generated with the ahda encode API.
-/// - Number of records stored in each block. This may be lower for each individual block.
-/// - The number of bytes containing the [FileFlags] that follow the header.
+/// The optional fields are each written as a length-prefixed section after
+/// `target_names`, with an empty section in place of a missing field.
+/// Missing fields at the end are not written, so records without optional
+/// fields are identical to records from versions that did not support them.
 ///
-#[derive(Clone, Debug, Decode, Encode, PartialEq)]
-pub struct FileHeader {
-    /// Ahda header, consists of four ASCII bytes spelling "ahda" and two bytes specifying the version.
-    ///
-    /// First four bytes can be used to check that a binary record is an ahda record.
-    /// Next two bytes can be used to check which version of ahda was used to generate this file.
-    pub ahda_header: [u8; 6], // = [97, 104, 100, 97, ...];
+/// Sections after `extensions` from newer versions of ahda are skipped by
+/// versions that don't know them. They are kept as is in `unknown_fields`
+/// and written back when the flags are encoded again, eg. by `ahda cat`.
+///
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileFlags {
+    /// Query file basename
+    pub query_name: Vec<u8>,
+    /// Name and index of target sequences
+    pub target_names: Vec<Vec<u8>>,
+    /// Groupings of the target sequences, eg. genome, species, and genus
+    pub target_groups: Vec<TargetGroups>,
+    /// Original target sequences, eg. contigs, if they were merged at encode time
+    pub target_aliases: Option<TargetAliases>,
+    /// How the file was derived from other .ahda files, eg. by `ahda set`
+    pub provenance: Option<Provenance>,
+    /// Fields added by other tools
+    pub extensions: Option<Extensions>,
+    /// Encoded fields after `extensions` from a newer version of ahda, in order
+    pub unknown_fields: Vec<Vec<u8>>,
+}
+
+impl Encode for FileFlags {
+    fn encode<En: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut En,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.query_name.encode(encoder)?;
+        self.target_names.encode(encoder)?;
+        let sections = [
+            Some(&self.target_groups).filter(|x| !x.is_empty()).map(encode_section).transpose()?,
+            self.target_aliases.as_ref().filter(|x| **x != TargetAliases::default()).map(encode_section).transpose()?,
+            self.provenance.as_ref().filter(|x| **x != Provenance::default()).map(encode_section).transpose()?,
+            self.extensions.as_ref().filter(|x| !x.is_empty()).map(encode_section).transpose()?,
+        ];
+        encode_sections(&sections, &self.unknown_fields, encoder)
+    }
+}
+
+impl<Context> Decode<Context> for FileFlags {
+    fn decode<D: bincode::de::Decoder<Context = Context>>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let query_name = Vec::<u8>::decode(decoder)?;
+        let target_names = Vec::<Vec<u8>>::decode(decoder)?;
+        let target_groups = decode_section::<Vec<TargetGroups>, _, _>(decoder)?.unwrap_or_default();
+        let target_aliases = decode_section::<TargetAliases, _, _>(decoder)?;
+        let provenance = decode_section::<Provenance, _, _>(decoder)?;
+        let extensions = decode_section::<Extensions, _, _>(decoder)?.filter(|x| !x.is_empty());
+        let unknown_fields = decode_unknown_fields(decoder)?;
+        Ok(FileFlags{ query_name, target_names, target_groups, target_aliases, provenance, extensions, unknown_fields })
+    }
+}
+bincode::impl_borrow_decode!(FileFlags);
+
+/// Check if `decoder` has no bytes left.
+///
+/// The optional fields of the flags may only end at a field boundary, a
+/// field that ends early is an error. Flags are always decoded from a slice,
+/// other readers are treated as empty.
+pub(crate) fn at_end<Context, D: bincode::de::Decoder<Context = Context>>(
+    decoder: &mut D,
+) -> bool {
+    decoder.reader().peek_read(1).is_none()
+}
+
+/// Encode an optional field of the flags as the contents of its section.
+pub(crate) fn encode_section<T: Encode>(
+    field: &T,
+) -> Result<Vec<u8>, bincode::error::EncodeError> {
+    bincode::encode_to_vec(field, bincode::config::standard())
+}
+
+/// Write the sections of the optional fields of the flags.
+///
+/// Each section is a length-prefixed byte string in the order of `sections`,
+/// and a missing field is written as an empty section. Missing fields at
+/// the end are not written unless `unknown_fields` follow them.
+pub(crate) fn encode_sections<En: bincode::enc::Encoder>(
+    sections: &[Option<Vec<u8>>],
+    unknown_fields: &[Vec<u8>],
+    encoder: &mut En,
+) -> Result<(), bincode::error::EncodeError> {
+    let n_written = if unknown_fields.is_empty() { sections.iter().rposition(|x| x.is_some()).map_or(0, |x| x + 1) } else { sections.len() };
+    for section in sections[0..n_written].iter() {
+        section.as_deref().unwrap_or_default().encode(encoder)?;
+    }
+    for field in unknown_fields.iter() {
+        field.encode(encoder)?;
+    }
+    Ok(())
+}
 
-    /// File format version, indicates (in)compatible versions of the file format.
-    pub file_format: u8,
+/// Decode the next section of the flags written by [encode_sections].
+///
+/// Returns None if the flags end before the section or if it is empty.
+///
+/// Errors if the flags end inside the section or if the field does not
+/// take up the whole section.
+pub(crate) fn decode_section<T: Decode<()>, Context, D: bincode::de::Decoder<Context = Context>>(
+    decoder: &mut D,
+) -> Result<Option<T>, bincode::error::DecodeError> {
+    if at_end(decoder) {
+        return Ok(None)
+    }
+    let bytes = Vec::<u8>::decode(decoder)?;
+    if bytes.is_empty() {
+        return Ok(None)
+    }
+    let (field, len) = decode_from_slice(&bytes, bincode::config::standard().with_limit::<{ crate::headers::METADATA_LIMIT }>())?;
+    if len != bytes.len() {
+        return Err(bincode::error::DecodeError::Other("flags field is shorter than its section"))
+    }
+    Ok(Some(field))
+}
+
+/// Decode the sections after the ones that this version knows.
+///
+/// Used to keep the fields written by newer versions of ahda after the
+/// last field that this version knows, see [FileFlags::unknown_fields] and
+/// [BlockFlags::unknown_fields](crate::headers::block::BlockFlags::unknown_fields).
+pub(crate) fn decode_unknown_fields<Context, D: bincode::de::Decoder<Context = Context>>(
+    decoder: &mut D,
+) -> Result<Vec<Vec<u8>>, bincode::error::DecodeError> {
+    let mut fields: Vec<Vec<u8>> = Vec::new();
+    while !at_end(decoder) {
+        fields.push(Vec::<u8>::decode(decoder)?);
+    }
+    Ok(fields)
+}
+
+impl FileFlags {
+    /// Get the [TargetGroups] for `level` if present.
+    pub fn target_group(
+        &self,
+        level: &[u8],
+    ) -> Option<&TargetGroups> {
+        self.target_groups.iter().find(|groups| groups.level == level)
+    }
+}
 
-    /// Compression method used for [FileFlags], see [MetadataCompression].
-    pub metadata_compression: u8,
+/// Assignment of target sequences to named groups
+///
+/// Each level assigns every target to exactly one group, eg. level `species`
+/// can map the targets `chr.fasta` and `plasmid.fasta` to the group `E. coli`.
+/// A hierarchy such as target → genome → species → genus is stored as one
+/// [TargetGroups] per level in [FileFlags].
+///
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq)]
+pub struct TargetGroups {
+    /// Name of the level, eg. `species`
+    pub level: Vec<u8>,
+    /// Names of the groups at this level
+    pub group_names: Vec<Vec<u8>>,
+    /// Index in `group_names` for each target sequence
+    pub membership: Vec<u32>,
+}
 
-    /// Fields of [crate::headers::block::BlockFlags] that must be present for all blocks in this file,
-    /// see [Format](crate::Format) for details.
+impl TargetGroups {
+    /// Check that every target is assigned to a group in `group_names`.
     ///
-    /// Every block must contain the fields specified here. Blocks may include
-    /// additional fields.
-    pub fields_present: u16,
+    /// Errors with [InvalidTargetGroupsErr](crate::errors::InvalidTargetGroupsErr)
+    /// if `membership` does not have `n_targets` values or refers to a group
+    /// that does not exist.
+    ///
+    pub fn validate(
+        &self,
+        n_targets: usize,
+    ) -> Result<(), E> {
+        if self.membership.len() != n_targets || self.membership.iter().any(|group| *group as usize >= self.group_names.len()) {
+            return Err(Box::new(crate::errors::InvalidTargetGroupsErr{}))
+        }
+        Ok(())
+    }
+}
 
-    /// Number of alignment targets, this must match the length of `target_names` in [FileFlags].
-    pub n_targets: u32,
+/// Operation that a file was derived with
+///
+/// Files written by `ahda set` record the operation and a digest of each
+/// input, so that a derived file can be told apart from a primary one and
+/// traced back to its inputs.
+///
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq)]
+pub struct Provenance {
+    /// Operation that produced the file, eg. `set union`
+    pub operation: Vec<u8>,
+    /// Inputs of the operation in the order they were given
+    pub inputs: Vec<ProvenanceInput>,
+    /// Time the file was written in seconds since the Unix epoch
+    pub created: u64,
+}
 
-    /// Number of query sequences, this should be greater or equal to the sum of all `num_records` in [BlockFlags](crate::headers::block::BlockFlags).
+/// Input file of a [Provenance]
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq)]
+pub struct ProvenanceInput {
+    /// File name as given to the operation, `-` for standard input
+    pub name: Vec<u8>,
+    /// Number of bytes read from the input
+    pub len: u64,
+    /// CRC32 of the bytes read from the input
+    pub crc32: u32,
+    /// Modification time of the input in seconds since the Unix epoch, if known
+    pub modified: Option<u64>,
+}
+
+/// Merging of target sequences into fewer targets at encode time
+///
+/// Draft assemblies consist of many contigs that are usually only of
+/// interest at the genome level. Each alias, eg. a contig, is replaced by
+/// the target it belongs to, eg. its genome, before the records are encoded,
+/// so the records only refer to `target_names`.
+///
+/// The aliases can be stored in [FileFlags] to record which sequences each
+/// target consisted of.
+///
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq)]
+pub struct TargetAliases {
+    /// Names of the targets that the aliases are merged into, eg. genomes
+    pub target_names: Vec<Vec<u8>>,
+    /// Names of the original target sequences, eg. contigs
+    pub alias_names: Vec<Vec<u8>>,
+    /// Index in `target_names` for each alias
+    pub membership: Vec<u32>,
+}
+
+impl TargetAliases {
+    /// Check that every alias is assigned to a target in `target_names`.
+    ///
+    /// Errors with [InvalidTargetAliasesErr](crate::errors::InvalidTargetAliasesErr)
+    /// if `membership` does not have a value for each of the `n_aliases`
+    /// aliases or refers to a target that does not exist.
     ///
-    /// Can be set to 0 if the number is not known in advance.
-    pub n_queries: u32,
+    pub fn validate(
+        &self,
+        n_aliases: usize,
+    ) -> Result<(), E> {
+        if self.alias_names.len() != n_aliases || self.membership.len() != n_aliases || self.membership.iter().any(|target| *target as usize >= self.target_names.len()) {
+            return Err(Box::new(crate::errors::InvalidTargetAliasesErr{}))
+        }
+        Ok(())
+    }
 
-    /// Bitmap type used to encode blocks in this file, see [BitmapType].
+    /// Replace the alias indexes in `ones` with the indexes of their targets.
     ///
-    /// The address space of the file bitmap type must be large enough to store
-    /// data from all blocks in this file. The block bitmap types may differ
-    /// from the file bitmap type.
-    pub bitmap_type: u16,
+    /// The output is sorted and has no duplicates. Indexes that are not
+    /// aliases become `target_names.len()`, so that encoding them fails like
+    /// any other index that is out of range.
+    ///
+    pub fn to_targets(
+        &self,
+        ones: &[u32],
+    ) -> Vec<u32> {
+        let mut out: Vec<u32> = ones.iter().map(|alias| self.membership.get(*alias as usize).copied().unwrap_or(self.target_names.len() as u32)).collect();
+        out.sort_unstable();
+        out.dedup();
+        out
+    }
+}
+
+/// Target sequences of one or more files
+///
+/// Files that were aligned against different indexes can only be combined
+/// after their targets are placed in a common target space.
+/// [TargetDict::merge] builds this space from the targets of each file and a
+/// [RemapTable] per file that moves its target indexes into the merged space.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TargetDict {
+    /// Names of the target sequences, the index of a name is the target index
+    pub target_names: Vec<Vec<u8>>,
+}
 
-    /// Block size (number of [crate::PseudoAln] records) used to encode blocks in this file. Actual number of records per block may be different.
-    pub block_size: u32,
+impl TargetDict {
+    /// Get the targets stored in `flags`.
+    pub fn from_file_flags(
+        flags: &FileFlags,
+    ) -> Self {
+        TargetDict{ target_names: flags.target_names.clone() }
+    }
 
-    /// Number of bytes in [FileFlags] that follow the header bytes.
-    pub flags_len: u64,
+    /// Merge the targets in `dicts` into one [TargetDict].
+    ///
+    /// Targets are matched by name. The merged targets are in the order of
+    /// their first appearance, so merging files that share the same targets
+    /// returns the same targets and identity [RemapTable]s. Returns the
+    /// merged targets and one [RemapTable] for each of `dicts`.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::headers::file::TargetDict;
+    ///
+    /// let dict_1 = TargetDict{ target_names: vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()] };
+    /// let dict_2 = TargetDict{ target_names: vec![b"virus.fasta".to_vec(), b"chr.fasta".to_vec()] };
+    ///
+    /// let (merged, tables) = TargetDict::merge(&[&dict_1, &dict_2]);
+    ///
+    /// assert_eq!(merged.target_names, vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()]);
+    /// assert!(tables[0].is_identity());
+    /// assert_eq!(tables[1].remap(&[0, 1]).unwrap(), vec![0, 2]);
+    /// ```
+    ///
+    pub fn merge(
+        dicts: &[&TargetDict],
+    ) -> (TargetDict, Vec<RemapTable>) {
+        let mut merged: indexmap::IndexSet<Vec<u8>> = indexmap::IndexSet::new();
+        let tables = dicts.iter().map(|dict| {
+            let new_index = dict.target_names.iter().map(|name| {
+                merged.insert_full(name.clone()).0 as u32
+            }).collect();
+            RemapTable{ new_index }
+        }).collect();
+
+        (TargetDict{ target_names: merged.into_iter().collect() }, tables)
+    }
 }
 
-impl FileHeader {
-    pub fn promises_query_names(
+/// Target indexes of one input in a merged [TargetDict]
+///
+/// Built by [TargetDict::merge].
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RemapTable {
+    /// Index in the merged targets for each target of the input
+    pub new_index: Vec<u32>,
+}
+
+impl RemapTable {
+    /// Check if the table maps every target to itself.
+    pub fn is_identity(
         &self,
     ) -> bool {
-        (self.fields_present & crate::MASK_QUERIES) != 0
+        self.new_index.iter().enumerate().all(|(idx, new_idx)| idx as u32 == *new_idx)
     }
 
-    pub fn promises_query_ids(
+    /// Replace the target indexes in `ones` with their indexes in the merged targets.
+    ///
+    /// The output is sorted.
+    ///
+    /// Errors with [UnknownTargetErr](crate::errors::UnknownTargetErr) if
+    /// `ones` contains an index that is not in the table.
+    ///
+    pub fn remap(
         &self,
-    ) -> bool {
-        (self.fields_present & crate::MASK_QUERY_IDS) != 0
+        ones: &[u32],
+    ) -> Result<Vec<u32>, E> {
+        let mut out = ones.iter().map(|target_idx| {
+            Ok(*self.new_index.get(*target_idx as usize).ok_or(crate::errors::UnknownTargetErr{})?)
+        }).collect::<Result<Vec<u32>, E>>()?;
+        out.sort_unstable();
+        Ok(out)
+    }
+
+    /// Replace the target indexes of the bits in `bitmap` with their indexes in the merged targets.
+    ///
+    /// The bits of `bitmap` are at `query_id * n + target`, where `n` is the
+    /// number of targets in the table, and the bits of the output at
+    /// `query_id * n_targets + target`, where `n_targets` is the number of
+    /// merged targets.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::headers::file::TargetDict;
+    /// use roaring::RoaringTreemap;
+    ///
+    /// let dict_1 = TargetDict{ target_names: vec![b"chr.fasta".to_vec()] };
+    /// let dict_2 = TargetDict{ target_names: vec![b"virus.fasta".to_vec(), b"chr.fasta".to_vec()] };
+    /// let (merged, tables) = TargetDict::merge(&[&dict_1, &dict_2]);
+    ///
+    /// // Query 1 aligns against virus.fasta in the second input
+    /// let bitmap = RoaringTreemap::from_iter([2]);
+    /// let got = tables[1].remap_bitmap(&bitmap, merged.target_names.len() as u32).unwrap();
+    /// assert_eq!(got.iter().collect::<Vec<u64>>(), vec![3]);
+    /// ```
+    ///
+    /// ## Errors and panics
+    /// Errors with [UnknownTargetErr](crate::errors::UnknownTargetErr) if
+    /// the table is empty but `bitmap` is not, and with
+    /// [QueryIndexOutOfRangeErr](crate::errors::QueryIndexOutOfRangeErr) if
+    /// a bit of the output doesn't fit in a u64.
+    ///
+    pub fn remap_bitmap(
+        &self,
+        bitmap: &roaring::RoaringTreemap,
+        n_targets: u32,
+    ) -> Result<roaring::RoaringTreemap, E> {
+        let n_targets_in = self.new_index.len() as u64;
+        if n_targets_in == 0 && !bitmap.is_empty() {
+            return Err(Box::new(crate::errors::UnknownTargetErr{}))
+        }
+        bitmap.iter().map(|bit| {
+            let new_target = self.new_index[(bit % n_targets_in) as usize] as u64;
+            (bit / n_targets_in).checked_mul(n_targets as u64).and_then(|x| x.checked_add(new_target)).ok_or(crate::errors::QueryIndexOutOfRangeErr{})
+        }).collect::<Result<roaring::RoaringTreemap, crate::errors::QueryIndexOutOfRangeErr>>().map_err(|e| e.into())
     }
 }
 
-/// Data shared with all blocks
+/// Build the [FileHeader] and [FileFlags] for records collapsed to `level`.
 ///
-/// Variable length, use [FileHeader].flags_len to get size
+/// The groups at `level` replace the target sequences. The returned header
+/// and flags describe the output of [Decoder::collapse](crate::decoder::Decoder::collapse)
+/// and can be given to [Printer](crate::printer::Printer) to print the group
+/// names.
 ///
-/// Must contain these fields:
-/// - `query_name`: Name for the query file / sample.
-/// - `target_names`: Names of the alignment target sequences, in the order that they appear for the aligner.
+/// Errors with [UnknownTargetGroupErr](crate::errors::UnknownTargetGroupErr)
+/// if `flags` has no groups for `level`.
 ///
-#[non_exhaustive]
-#[derive(Clone, Debug, Decode, Default, Encode, PartialEq)]
-pub struct FileFlags {
-    /// Query file basename
-    pub query_name: Vec<u8>,
-    /// Name and index of target sequences
-    pub target_names: Vec<Vec<u8>>,
+pub fn collapse_file_header_and_flags(
+    header: &FileHeader,
+    flags: &FileFlags,
+    level: &[u8],
+) -> Result<(FileHeader, FileFlags), E> {
+    let groups = flags.target_group(level).ok_or(crate::errors::UnknownTargetGroupErr{})?;
+
+    let mut new_header = header.clone();
+    new_header.n_targets = groups.group_names.len() as u32;
+    let new_flags = FileFlags{ query_name: flags.query_name.clone(), target_names: groups.group_names.clone(), ..Default::default() };
+    new_header.flags_len = encode_file_flags(&new_flags, &MetadataCompression::from_u8(header.metadata_compression)?)?.len() as u64;
+
+    Ok((new_header, new_flags))
 }
+
 pub fn build_ahda_header() -> [u8; 6] {
     const VERSION: &str = env!("CARGO_PKG_VERSION");
-    let mut header = [97_u8, 104, 100, 97, 0, 0];
+    let mut header = [crate::MAGIC[0], crate::MAGIC[1], crate::MAGIC[2], crate::MAGIC[3], 0, 0];
     let version: u16 = VERSION.parse::<AhdaVersion>().unwrap().to_u16();
     let version_bytes: [u8; 2] = version.to_le_bytes();
     header[4] = version_bytes[0];
@@ -129,11 +484,7 @@ pub fn build_ahda_header() -> [u8; 6] {
 pub fn check_ahda_header(
     bytes: [u8; 6],
 ) -> Result<String, E> {
-    let mut is_ahda = true;
-    is_ahda &= bytes[0] == 97;
-    is_ahda &= bytes[1] == 104;
-    is_ahda &= bytes[2] == 100;
-    is_ahda &= bytes[3] == 97;
+    let is_ahda = bytes[0..4] == crate::MAGIC;
 
     let version_bytes: [u8; 2] = [bytes[4], bytes[5]];
     let version: u16 = u16::from_le_bytes(version_bytes);
@@ -149,6 +500,31 @@ pub fn check_ahda_header(
     }
 }
 
+/// Number of queries per block for `bitmap_type` and `n_targets` targets.
+///
+/// [Roaring32](BitmapType::Roaring32) blocks fit as many queries as their
+/// 32-bit address space has room for, at most 65536, and
+/// [Roaring64](BitmapType::Roaring64) blocks always fit 262144 queries.
+pub fn default_block_size(
+    bitmap_type: &BitmapType,
+    n_targets: usize,
+) -> u32 {
+    // Adjust block size to fit within 32-bit address space if using RoaringBitmaps
+    //
+    // An empty target set is allowed for placeholder files. These can only
+    // contain queries with no alignments, so they get the same block size as
+    // single-target files.
+    match bitmap_type {
+        BitmapType::Roaring32 => {
+            let block_size = ((u32::MAX as u64) / (n_targets.max(1) as u64)).clamp(3_u64, 65537_u64) as u32;
+            block_size - 1
+        },
+        BitmapType::Roaring64 => {
+            262144_u32
+        },
+    }
+}
+
 pub fn build_file_header_and_flags(
     targets: &[Vec<u8>],
     n_queries: usize,
@@ -157,27 +533,15 @@ pub fn build_file_header_and_flags(
 ) -> Result<(FileHeader, FileFlags), E> {
     // Check if bitmap fits in 32-bit address space and adjust accordingly
     let n_targets = targets.len();
-    let bitmap_size = (n_targets as u64) * (n_queries as u64);
-    let bitmap_type = if bitmap_size < u32::MAX as u64 { BitmapType::Roaring32 } else { BitmapType::Roaring64 };
+    let bitmap_type = BitmapType::auto(n_targets, n_queries);
+    let block_size = default_block_size(&bitmap_type, n_targets);
 
-    // Adjust block size to fit within 32-bit address space if using RoaringBitmaps
-    let block_size: u32 = match bitmap_type {
-        BitmapType::Roaring32 => {
-            let mut block_size = ((u32::MAX as u64) / (n_targets as u64)).min(65537_u64) as u32;
-            block_size = if block_size == 1 { 2 } else { block_size - 1 };
-            block_size
-        },
-        BitmapType::Roaring64 => {
-            262144_u32
-        },
-    };
-
-    let flags = FileFlags{ target_names: targets.to_vec(), query_name: query_name.to_vec() };
+    let flags = FileFlags{ target_names: targets.to_vec(), query_name: query_name.to_vec(), ..Default::default() };
     let flags_bytes = encode_file_flags(&flags, flags_compression).unwrap();
 
     let header = FileHeader{
         ahda_header: build_ahda_header(),
-        file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+        file_format: AhdaFormatVersion::default().to_u8(),
         metadata_compression: flags_compression.to_u8(),
         fields_present: 0,
         n_targets: n_targets as u32,
@@ -206,29 +570,31 @@ pub fn encode_file_header_and_flags(
 pub fn decode_file_header_and_flags(
     bytes: &[u8],
 ) -> Result<(FileHeader, FileFlags), E> {
-    let header = decode_file_header(&bytes[0..32])?;
-    assert!(bytes.len() >= 32 + header.flags_len as usize);
-    let flags = decode_file_flags(&bytes[32..(header.flags_len as usize)], &MetadataCompression::from_u8(header.metadata_compression)?)?;
+    let header = decode_file_header(bytes.get(0..32).ok_or(crate::errors::TruncatedFileHeaderErr{})?)?;
+    if header.is_zstd_seekable() {
+        return Err(Box::new(crate::errors::ZstdContainerErr{}))
+    }
+    if header.has_target_chunks() {
+        return Err(Box::new(crate::errors::TargetChunksErr{}))
+    }
+    let flags_end = usize::try_from(header.flags_len).ok().and_then(|len| len.checked_add(32)).ok_or(crate::errors::TruncatedFileHeaderErr{})?;
+    let flags_bytes = bytes.get(32..flags_end).ok_or(crate::errors::TruncatedFileHeaderErr{})?;
+    let flags = decode_file_flags(flags_bytes, &MetadataCompression::from_u8(header.metadata_compression)?)?;
     Ok((header, flags))
 }
 
 pub fn encode_file_header(
     header: &FileHeader,
 ) -> Result<Vec<u8>, E> {
-    let mut bytes: Vec<u8> = Vec::with_capacity(32);
-    let nbytes = encode_into_std_write(
-        header,
-        &mut bytes,
-        bincode::config::standard().with_fixed_int_encoding(),
-    )?;
-    assert_eq!(nbytes, 32);
-    Ok(bytes)
+    Ok(ahda_core::file::encode_file_header(header))
 }
 
 pub fn decode_file_header(
     header_bytes: &[u8],
 ) -> Result<FileHeader, E> {
-    assert_eq!(header_bytes.len(), 32);
+    if header_bytes.len() != 32 {
+        return Err(Box::new(crate::errors::TruncatedFileHeaderErr{}))
+    }
     let mut bytes_start: [u8; 6] = [0; 6];
     bytes_start[0] = header_bytes[0];
     bytes_start[1] = header_bytes[1];
@@ -237,7 +603,10 @@ pub fn decode_file_header(
     bytes_start[4] = header_bytes[4];
     bytes_start[5] = header_bytes[5];
     let _ = check_ahda_header(bytes_start)?;
-    Ok(decode_from_slice(header_bytes, bincode::config::standard().with_fixed_int_encoding())?.0)
+    let header = ahda_core::file::decode_file_header(header_bytes)?;
+    // Files from newer versions may store the flags and blocks differently
+    AhdaFormatVersion::from_u8(header.file_format)?;
+    Ok(header)
 }
 
 pub fn read_file_header<R: Read>(
@@ -249,12 +618,24 @@ pub fn read_file_header<R: Read>(
     Ok(res)
 }
 
+/// Read the [FileFlags] that follow `header` from `conn`.
+///
+/// Errors with [ZstdContainerErr](crate::errors::ZstdContainerErr) if the
+/// flags are in a zstd-seekable container and with
+/// [TargetChunksErr](crate::errors::TargetChunksErr) if the blocks split
+/// their targets across sub-blocks, which only
+/// [Decoder](crate::decoder::Decoder) can read.
 pub fn read_file_flags<R: Read>(
     header: &FileHeader,
     conn: &mut R,
 ) -> Result<FileFlags, E> {
-    let mut flags_bytes: Vec<u8> = vec![0; header.flags_len as usize];
-    conn.read_exact(&mut flags_bytes)?;
+    if header.is_zstd_seekable() {
+        return Err(Box::new(crate::errors::ZstdContainerErr{}))
+    }
+    if header.has_target_chunks() {
+        return Err(Box::new(crate::errors::TargetChunksErr{}))
+    }
+    let flags_bytes = crate::headers::read_len_bytes(conn, header.flags_len)?;
     let res = decode_file_flags(&flags_bytes, &MetadataCompression::from_u8(header.metadata_compression)?)?;
     Ok(res)
 }
@@ -267,6 +648,28 @@ pub fn read_file_header_and_flags<R: Read>(
     Ok((header, flags))
 }
 
+/// Same as [read_file_flags] but also reads the flags of files whose blocks split their targets across sub-blocks.
+///
+/// Meant for callers that read only the headers or read the blocks with
+/// the [Decoder](crate::decoder::Decoder), see
+/// [target_chunks](crate::target_chunks).
+pub fn read_file_flags_any_layout<R: Read>(
+    header: &FileHeader,
+    conn: &mut R,
+) -> Result<FileFlags, E> {
+    let header = FileHeader{ fields_present: header.fields_present & !crate::compression::FILE_HAS_TARGET_CHUNKS, ..header.clone() };
+    read_file_flags(&header, conn)
+}
+
+/// Same as [read_file_header_and_flags] but with [read_file_flags_any_layout].
+pub fn read_file_header_and_flags_any_layout<R: Read>(
+    conn: &mut R,
+) -> Result<(FileHeader, FileFlags), E> {
+    let header = read_file_header(conn)?;
+    let flags = read_file_flags_any_layout(&header, conn)?;
+    Ok((header, flags))
+}
+
 pub fn encode_file_flags(
     flags: &FileFlags,
     compression: &MetadataCompression
@@ -289,6 +692,8 @@ pub fn encode_file_flags(
             )?;
             bytes = deflate_bytes(&bytes)?;
         },
+        // The dictionary is stored in the file flags
+        MetadataCompression::ZstdDictionary => return Err(Box::new(crate::errors::UnknownMetadataCompressionErr{})),
     }
 
     Ok(bytes)
@@ -302,16 +707,17 @@ pub fn decode_file_flags(
         MetadataCompression::BincodeStandard => {
             decode_from_slice(
                 bytes,
-                bincode::config::standard(),
+                bincode::config::standard().with_limit::<{ crate::headers::METADATA_LIMIT }>(),
             )?.0
         },
         MetadataCompression::Flate2 => {
             let inflated = inflate_bytes(bytes)?;
             decode_from_slice(
                 &inflated,
-                bincode::config::standard(),
+                bincode::config::standard().with_limit::<{ crate::headers::METADATA_LIMIT }>(),
             )?.0
         },
+        MetadataCompression::ZstdDictionary => return Err(Box::new(crate::errors::UnknownMetadataCompressionErr{})),
     };
 
     Ok(flags)
@@ -335,11 +741,11 @@ mod tests {
         let queries = vec!["1".as_bytes().to_vec(), "2".as_bytes().to_vec(), "3".as_bytes().to_vec(), "4".as_bytes().to_vec(), "5".as_bytes().to_vec()];
         let sample = "sample".as_bytes().to_vec();
 
-        let expected_flags = FileFlags { query_name: sample.clone(), target_names: targets.clone() };
+        let expected_flags = FileFlags { query_name: sample.clone(), target_names: targets.clone(), ..Default::default() };
         let nbytes = encode_file_flags(&expected_flags, &MetadataCompression::default()).unwrap().len();
         let expected_header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: targets.len() as u32,
@@ -355,6 +761,54 @@ mod tests {
         assert_eq!(got_flags, expected_flags);
     }
 
+    #[test]
+    fn merge_target_dicts() {
+        use super::FileFlags;
+        use super::RemapTable;
+        use super::TargetDict;
+
+        let flags = FileFlags{ target_names: vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()], ..Default::default() };
+        let dict_1 = TargetDict::from_file_flags(&flags);
+        let dict_2 = TargetDict{ target_names: vec![b"d".to_vec(), b"c".to_vec(), b"a".to_vec()] };
+        let dict_3 = TargetDict::default();
+
+        let (merged, tables) = TargetDict::merge(&[&dict_1, &dict_2, &dict_3]);
+
+        assert_eq!(merged.target_names, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+        assert_eq!(tables, vec![
+            RemapTable{ new_index: vec![0, 1, 2] },
+            RemapTable{ new_index: vec![3, 2, 0] },
+            RemapTable{ new_index: vec![] },
+        ]);
+        assert!(tables[0].is_identity());
+        assert!(!tables[1].is_identity());
+        assert!(tables[2].is_identity());
+
+        assert_eq!(tables[1].remap(&[0, 1, 2]).unwrap(), vec![0, 2, 3]);
+        assert!(tables[1].remap(&[3]).is_err());
+
+        let (merged, tables) = TargetDict::merge(&[]);
+        assert!(merged.target_names.is_empty());
+        assert!(tables.is_empty());
+    }
+
+    #[test]
+    fn build_file_header_and_flags_without_targets() {
+        use crate::compression::MetadataCompression;
+        use crate::compression::BitmapType;
+        use super::build_file_header_and_flags;
+
+        let targets: Vec<Vec<u8>> = Vec::new();
+        let sample = "sample".as_bytes().to_vec();
+
+        let (got_header, got_flags) = build_file_header_and_flags(&targets, 5, &sample, &MetadataCompression::default()).unwrap();
+
+        assert_eq!(got_header.n_targets, 0);
+        assert_eq!(got_header.bitmap_type, BitmapType::Roaring32.to_u16());
+        assert_eq!(got_header.block_size, 65536);
+        assert!(got_flags.target_names.is_empty());
+    }
+
     #[test]
     fn encode_file_header_and_flags() {
         use crate::AhdaFormatVersion;
@@ -370,11 +824,11 @@ mod tests {
         let queries = vec!["1".as_bytes().to_vec(), "2".as_bytes().to_vec(), "3".as_bytes().to_vec(), "4".as_bytes().to_vec(), "5".as_bytes().to_vec()];
         let sample = "sample";
 
-        let flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone() };
+        let flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone(), ..Default::default() };
         let nbytes = encode_file_flags(&flags, &MetadataCompression::default()).unwrap().len();
         let mut header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: targets.len() as u32,
@@ -384,7 +838,7 @@ mod tests {
             flags_len: nbytes as u64,
         };
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0, 6, 115, 97, 109, 112, 108, 101, 3, 1, 97, 1, 98, 1, 99];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0, 6, 115, 97, 109, 112, 108, 101, 3, 1, 97, 1, 98, 1, 99];
 
         let got = encode_file_header_and_flags(&mut header, &flags).unwrap();
 
@@ -406,11 +860,11 @@ mod tests {
         let queries = vec!["1".as_bytes().to_vec(), "2".as_bytes().to_vec(), "3".as_bytes().to_vec(), "4".as_bytes().to_vec(), "5".as_bytes().to_vec()];
         let sample = "sample";
 
-        let flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone() };
+        let flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone(), ..Default::default() };
         let nbytes = encode_file_flags(&flags, &MetadataCompression::default()).unwrap().len();
         let header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: targets.len() as u32,
@@ -420,7 +874,7 @@ mod tests {
             flags_len: nbytes as u64,
         };
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0];
 
         let got = encode_file_header(&header).unwrap();
 
@@ -436,7 +890,7 @@ mod tests {
         let targets = vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()];
         let sample = "sample";
 
-        let flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone() };
+        let flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone(), ..Default::default() };
 
         let expected: Vec<u8> = vec![6, 115, 97, 109, 112, 108, 101, 3, 1, 97, 1, 98, 1, 99];
 
@@ -454,7 +908,7 @@ mod tests {
         let targets = vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()];
         let sample = "sample";
 
-        let flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone() };
+        let flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone(), ..Default::default() };
 
         let expected: Vec<u8> = vec![31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 43, 78, 204, 45, 200, 73, 101, 102, 76, 100, 76, 98, 76, 6, 0, 217, 110, 76, 178, 14, 0, 0, 0];
 
@@ -472,11 +926,11 @@ mod tests {
         use super::decode_file_header;
         use super::FileHeader;
 
-        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0];
+        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0];
 
         let expected = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: 3_u32,
@@ -489,6 +943,15 @@ mod tests {
         let got = decode_file_header(&data).unwrap();
 
         assert_eq!(got, expected);
+
+        // Files from before the optional fields are still read
+        let mut old = data.clone();
+        old[6] = AhdaFormatVersion::V1_0_0.to_u8();
+        assert_eq!(decode_file_header(&old).unwrap().file_format, 0);
+
+        let mut newer = data;
+        newer[6] = crate::FORMAT_VERSION + 1;
+        assert!(decode_file_header(&newer).unwrap_err().is::<crate::errors::AhdaFormatVersionErr>());
     }
 
     #[test]
@@ -502,7 +965,7 @@ mod tests {
 
         let data: Vec<u8> = vec![6, 115, 97, 109, 112, 108, 101, 3, 1, 97, 1, 98, 1, 99];
 
-        let expected = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone() };
+        let expected = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone(), ..Default::default() };
 
         let got = decode_file_flags(&data, &MetadataCompression::default()).unwrap();
 
@@ -520,13 +983,157 @@ mod tests {
 
         let data: Vec<u8> = vec![31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 43, 78, 204, 45, 200, 73, 101, 102, 76, 100, 76, 98, 76, 6, 0, 217, 110, 76, 178, 14, 0, 0, 0];
 
-        let expected = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone() };
+        let expected = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone(), ..Default::default() };
 
         let got = decode_file_flags(&data, &MetadataCompression::Flate2).unwrap();
 
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn encode_and_decode_file_flags_with_target_groups() {
+        use crate::compression::MetadataCompression;
+        use super::decode_file_flags;
+        use super::encode_file_flags;
+        use super::FileFlags;
+        use super::TargetGroups;
+
+        let targets = vec!["a".as_bytes().to_vec(), "b".as_bytes().to_vec(), "c".as_bytes().to_vec()];
+        let groups = vec![
+            TargetGroups{ level: b"genome".to_vec(), group_names: vec![b"g1".to_vec(), b"g2".to_vec()], membership: vec![0, 0, 1] },
+            TargetGroups{ level: b"species".to_vec(), group_names: vec![b"s1".to_vec()], membership: vec![0, 0, 0] },
+        ];
+        let expected = FileFlags { query_name: b"sample".to_vec(), target_names: targets.clone(), target_groups: groups, ..Default::default() };
+
+        for compression in [MetadataCompression::BincodeStandard, MetadataCompression::Flate2] {
+            let bytes = encode_file_flags(&expected, &compression).unwrap();
+            let got = decode_file_flags(&bytes, &compression).unwrap();
+            assert_eq!(got, expected);
+        }
+
+        assert_eq!(expected.target_group(b"species").unwrap().membership, vec![0, 0, 0]);
+        assert!(expected.target_group(b"genus").is_none());
+    }
+
+    #[test]
+    fn encode_and_decode_file_flags_with_provenance() {
+        use crate::compression::MetadataCompression;
+        use super::decode_file_flags;
+        use super::encode_file_flags;
+        use super::FileFlags;
+        use super::Provenance;
+        use super::ProvenanceInput;
+
+        let inputs = vec![
+            ProvenanceInput{ name: b"a.ahda".to_vec(), len: 120, crc32: 0xdeadbeef, modified: Some(1760000000) },
+            ProvenanceInput{ name: b"-".to_vec(), len: 98, crc32: 0x01234567, modified: None },
+        ];
+        let provenance = Provenance{ operation: b"set union".to_vec(), inputs, created: 1760000100 };
+        let expected = FileFlags { query_name: b"sample".to_vec(), target_names: vec![b"g1".to_vec()], provenance: Some(provenance), ..Default::default() };
+
+        for compression in [MetadataCompression::BincodeStandard, MetadataCompression::Flate2] {
+            let bytes = encode_file_flags(&expected, &compression).unwrap();
+            let got = decode_file_flags(&bytes, &compression).unwrap();
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn file_flags_keep_unknown_fields() {
+        use crate::compression::MetadataCompression;
+        use super::decode_file_flags;
+        use super::encode_file_flags;
+        use super::FileFlags;
+
+        let known = FileFlags { query_name: b"sample".to_vec(), target_names: vec![b"g1".to_vec()], ..Default::default() };
+        let newer = FileFlags { unknown_fields: vec![b"x".to_vec(), Vec::new(), b"yz".to_vec()], ..known.clone() };
+
+        for compression in [MetadataCompression::BincodeStandard, MetadataCompression::Flate2] {
+            // Placeholders are written for the known fields before the unknown ones
+            let bytes = encode_file_flags(&newer, &compression).unwrap();
+            assert_eq!(decode_file_flags(&bytes, &compression).unwrap(), newer);
+        }
+
+        // Fields after the extensions from a newer version are each length-prefixed
+        let mut bytes = encode_file_flags(&newer, &MetadataCompression::BincodeStandard).unwrap();
+        bytes.truncate(bytes.len() - 6);
+        assert_eq!(decode_file_flags(&bytes, &MetadataCompression::BincodeStandard).unwrap(), known);
+        assert_eq!(&encode_file_flags(&newer, &MetadataCompression::BincodeStandard).unwrap()[bytes.len()..], &[1, b'x', 0, 2, b'y', b'z']);
+    }
+
+    #[test]
+    fn file_flags_truncated_inside_a_field() {
+        use crate::compression::MetadataCompression;
+        use super::decode_file_flags;
+        use super::encode_file_flags;
+        use super::FileFlags;
+        use super::TargetGroups;
+
+        let known = FileFlags { query_name: b"sample".to_vec(), target_names: vec![b"g1".to_vec()], ..Default::default() };
+        let groups = vec![TargetGroups{ level: b"species".to_vec(), group_names: vec![b"E. coli".to_vec()], membership: vec![0] }];
+        let flags = FileFlags { target_groups: groups, ..known.clone() };
+        let compression = MetadataCompression::BincodeStandard;
+
+        let bytes = encode_file_flags(&flags, &compression).unwrap();
+        let known_len = encode_file_flags(&known, &compression).unwrap().len();
+        // Only the end of a field is the end of the flags
+        assert_eq!(decode_file_flags(&bytes[0..known_len], &compression).unwrap(), known);
+        for len in known_len + 1..bytes.len() {
+            assert!(decode_file_flags(&bytes[0..len], &compression).is_err());
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_file_flags_with_extensions() {
+        use crate::compression::MetadataCompression;
+        use super::decode_file_flags;
+        use super::encode_file_flags;
+        use super::FileFlags;
+        use crate::headers::extensions::Extensions;
+
+        let mut extensions = Extensions::default();
+        extensions.insert_as(b"mytool.read_length", &150_u32).unwrap();
+        let expected = FileFlags { query_name: b"sample".to_vec(), target_names: vec![b"g1".to_vec()], extensions: Some(extensions), ..Default::default() };
+
+        for compression in [MetadataCompression::BincodeStandard, MetadataCompression::Flate2] {
+            let bytes = encode_file_flags(&expected, &compression).unwrap();
+            let got = decode_file_flags(&bytes, &compression).unwrap();
+            assert_eq!(got, expected);
+            assert_eq!(got.extensions.unwrap().get_as::<u32>(b"mytool.read_length").unwrap().unwrap(), 150);
+
+            // Empty extensions are not written
+            let without = FileFlags { extensions: None, ..expected.clone() };
+            let empty = FileFlags { extensions: Some(Extensions::default()), ..expected.clone() };
+            assert_eq!(encode_file_flags(&empty, &compression).unwrap(), encode_file_flags(&without, &compression).unwrap());
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_file_flags_with_target_aliases() {
+        use crate::compression::MetadataCompression;
+        use super::decode_file_flags;
+        use super::encode_file_flags;
+        use super::FileFlags;
+        use super::TargetAliases;
+
+        let targets = vec![b"g1".to_vec(), b"g2".to_vec()];
+        let aliases = TargetAliases{ target_names: targets.clone(), alias_names: vec![b"c1".to_vec(), b"c2".to_vec(), b"c3".to_vec()], membership: vec![0, 1, 0] };
+        let expected = FileFlags { query_name: b"sample".to_vec(), target_names: targets.clone(), target_aliases: Some(aliases.clone()), ..Default::default() };
+
+        for compression in [MetadataCompression::BincodeStandard, MetadataCompression::Flate2] {
+            let bytes = encode_file_flags(&expected, &compression).unwrap();
+            let got = decode_file_flags(&bytes, &compression).unwrap();
+            assert_eq!(got, expected);
+
+        }
+
+        assert!(aliases.validate(3).is_ok());
+        assert!(aliases.validate(2).is_err());
+        assert_eq!(aliases.to_targets(&[0, 2]), vec![0]);
+        assert_eq!(aliases.to_targets(&[2, 1, 0]), vec![0, 1]);
+        assert_eq!(aliases.to_targets(&[1, 3]), vec![1, 2]);
+    }
+
     #[test]
     fn read_file_header() {
         use crate::AhdaFormatVersion;
@@ -538,12 +1145,12 @@ mod tests {
 
         use std::io::Cursor;
 
-        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 16, 0, 0, 0, 0, 0, 0, 0];
+        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 16, 0, 0, 0, 0, 0, 0, 0];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_bytes);
 
         let expected = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: 3_u32,
@@ -575,7 +1182,7 @@ mod tests {
 
         let header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: 3_u32,
@@ -587,7 +1194,7 @@ mod tests {
         let data_bytes: Vec<u8> = vec![6, 115, 97, 109, 112, 108, 101, 3, 1, 97, 1, 98, 1, 99];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_bytes);
 
-        let expected = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone() };
+        let expected = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone(), ..Default::default() };
 
         let got = read_file_flags(&header, &mut data).unwrap();
 
@@ -610,10 +1217,10 @@ mod tests {
         let queries = vec!["1".as_bytes().to_vec(), "2".as_bytes().to_vec(), "3".as_bytes().to_vec(), "4".as_bytes().to_vec(), "5".as_bytes().to_vec()];
         let sample = "sample";
 
-        let expected_flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone() };
+        let expected_flags = FileFlags { query_name: sample.as_bytes().to_vec(), target_names: targets.clone(), ..Default::default() };
         let expected_header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: targets.len() as u32,
@@ -623,7 +1230,7 @@ mod tests {
             flags_len: 14_u64,
         };
 
-        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0, 6, 115, 97, 109, 112, 108, 101, 3, 1, 97, 1, 98, 1, 99];
+        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0, 6, 115, 97, 109, 112, 108, 101, 3, 1, 97, 1, 98, 1, 99];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_bytes);
 
         let (got_header, got_flags) = read_file_header_and_flags(&mut data).unwrap();
@@ -631,4 +1238,37 @@ mod tests {
         assert_eq!(got_header, expected_header);
         assert_eq!(got_flags, expected_flags);
     }
+
+    #[test]
+    fn decode_corrupted_file_header_and_flags_fails() {
+        use super::decode_file_header;
+        use super::decode_file_header_and_flags;
+        use super::read_file_header_and_flags;
+
+        use std::io::Cursor;
+
+        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0, 14, 0, 0, 0, 0, 0, 0, 0, 6, 115, 97, 109, 112, 108, 101, 3, 1, 97, 1, 98, 1, 99];
+        let (header, flags) = decode_file_header_and_flags(&data_bytes).unwrap();
+        assert_eq!(header.flags_len, 14);
+        assert_eq!(flags.target_names, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+
+        // Truncated header or flags
+        assert!(decode_file_header(&data_bytes[0..31]).is_err());
+        assert!(decode_file_header_and_flags(&data_bytes[0..20]).is_err());
+        assert!(decode_file_header_and_flags(&data_bytes[0..45]).is_err());
+
+        // `flags_len` longer than the input
+        let mut corrupted = data_bytes.clone();
+        corrupted[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decode_file_header_and_flags(&corrupted).is_err());
+        assert!(read_file_header_and_flags(&mut Cursor::new(corrupted)).is_err());
+
+        // Unknown metadata compression and a name longer than the flags
+        let mut corrupted = data_bytes.clone();
+        corrupted[7] = 0xff;
+        assert!(decode_file_header_and_flags(&corrupted).is_err());
+        let mut corrupted = data_bytes.clone();
+        corrupted[32] = 0xfc;
+        assert!(decode_file_header_and_flags(&corrupted).is_err());
+    }
 }
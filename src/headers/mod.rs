@@ -77,4 +77,42 @@
 //!
 
 pub mod block;
+pub mod extensions;
 pub mod file;
+
+use std::io::Read;
+
+type E = Box<dyn std::error::Error>;
+
+pub(crate) use ahda_core::METADATA_LIMIT;
+
+/// Read `len` bytes from `conn`.
+///
+/// Same as [Read::read_exact] into a buffer of `len` bytes but allocates the
+/// buffer as the bytes are read, so that a corrupted `len` in a header can't
+/// allocate more memory than the input contains.
+pub(crate) fn read_len_bytes<R: Read>(
+    conn: &mut R,
+    len: u64,
+) -> Result<Vec<u8>, E> {
+    let mut bytes: Vec<u8> = Vec::new();
+    read_len_bytes_into(conn, len, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read `len` bytes from `conn` to the end of `bytes`.
+///
+/// See [read_len_bytes].
+pub(crate) fn read_len_bytes_into<R: Read>(
+    conn: &mut R,
+    len: u64,
+    bytes: &mut Vec<u8>,
+) -> Result<(), E> {
+    bytes.reserve(len.min(1 << 16) as usize);
+    let start = bytes.len();
+    conn.take(len).read_to_end(bytes)?;
+    if ((bytes.len() - start) as u64) < len {
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)))
+    }
+    Ok(())
+}
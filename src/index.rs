@@ -0,0 +1,253 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Read the target names of a pseudoalignment index.
+//!
+//! Themisto, Fulgor, and Bifrost number the targets in the order of the
+//! reference list given to the index construction command. A target list that
+//! is maintained separately from the index can drift from this order without
+//! any error, so [read_index_targets] finds the index in a directory and reads
+//! the reference list stored next to it.
+//!
+//! The index files themselves are in formats that change between versions of
+//! the tools, so the names are read from a plain text sidecar
+//! `<prefix>.targets.txt` with one target per line, where `<prefix>` is the
+//! index path given to the construction command without the extension. The
+//! reference list can be copied there when building the index, for example
+//! with `themisto build -i refs.txt -o index/idx && cp refs.txt index/idx.targets.txt`.
+//!
+//! [target_list_digest] hashes the names one per line, so the digest of a
+//! list of names equals the SHA-256 checksum of the sidecar file and
+//! `sha256sum` can be used to check which index a file was encoded against.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::index::IndexKind;
+//! use ahda::index::read_index_targets;
+//!
+//! let dir = std::env::temp_dir().join("ahda-index-doc");
+//! std::fs::create_dir_all(&dir).unwrap();
+//! std::fs::write(dir.join("idx.tdbg"), b"").unwrap();
+//! std::fs::write(dir.join("idx.tcolors"), b"").unwrap();
+//! std::fs::write(dir.join("idx.targets.txt"), b"chr.fasta\nplasmid.fasta\n").unwrap();
+//!
+//! let (kind, targets) = read_index_targets(&dir).unwrap();
+//! assert_eq!(kind, IndexKind::Themisto);
+//! assert_eq!(targets, vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()]);
+//! # std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+//!
+
+use crate::errors::IndexNotFoundErr;
+use crate::errors::IndexTargetsNotFoundErr;
+use crate::errors::MultipleIndexesErr;
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+
+type E = Box<dyn std::error::Error>;
+
+/// Tools that build the indexes [find_index] recognizes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexKind {
+    /// `<prefix>.tdbg` and `<prefix>.tcolors`.
+    Themisto,
+    /// `<prefix>.fur`, or one of the `.hfur`, `.mfur`, and `.dfur` variants.
+    Fulgor,
+    /// `<prefix>.color.bfg` next to the `<prefix>.gfa` graph.
+    Bifrost,
+}
+
+impl std::fmt::Display for IndexKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IndexKind::Themisto => write!(f, "Themisto"),
+            IndexKind::Fulgor => write!(f, "Fulgor"),
+            IndexKind::Bifrost => write!(f, "Bifrost"),
+        }
+    }
+}
+
+/// Index found in a directory by [find_index].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexFiles {
+    pub kind: IndexKind,
+    /// Index path without the extension.
+    pub prefix: PathBuf,
+}
+
+impl IndexFiles {
+    /// Path of the sidecar file listing the targets.
+    pub fn targets_path(&self) -> PathBuf {
+        let mut path = self.prefix.clone().into_os_string();
+        path.push(".targets.txt");
+        PathBuf::from(path)
+    }
+}
+
+// File that identifies an index and the files that must accompany it
+fn index_kind(name: &str) -> Option<(IndexKind, &str, &'static [&'static str])> {
+    const KINDS: [(&str, IndexKind, &[&str]); 6] = [
+        (".tdbg", IndexKind::Themisto, &[".tcolors"]),
+        (".fur", IndexKind::Fulgor, &[]),
+        (".hfur", IndexKind::Fulgor, &[]),
+        (".mfur", IndexKind::Fulgor, &[]),
+        (".dfur", IndexKind::Fulgor, &[]),
+        (".color.bfg", IndexKind::Bifrost, &[]),
+    ];
+    KINDS.iter().find_map(|(ext, kind, required)| {
+        name.strip_suffix(ext).filter(|x| !x.is_empty()).map(|prefix| (*kind, prefix, *required))
+    })
+}
+
+/// Find the Themisto, Fulgor, or Bifrost index in a directory.
+///
+/// The index is identified by the file extensions listed in [IndexKind].
+///
+/// ## Errors and panics
+/// Returns an error if the directory can't be read, it contains no
+/// index, or it contains more than one index.
+///
+pub fn find_index(
+    dir: &Path,
+) -> Result<IndexFiles, E> {
+    let mut found: Vec<IndexFiles> = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if let Some((kind, prefix, required)) = index_kind(name) {
+            let prefix = dir.join(prefix);
+            let complete = required.iter().all(|ext| {
+                let mut path = prefix.clone().into_os_string();
+                path.push(ext);
+                Path::new(&path).is_file()
+            });
+            if complete {
+                found.push(IndexFiles{ kind, prefix });
+            }
+        }
+    }
+    found.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+
+    match found.len() {
+        0 => Err(Box::new(IndexNotFoundErr{ dir: dir.to_path_buf() })),
+        1 => Ok(found.pop().unwrap()),
+        _ => Err(Box::new(MultipleIndexesErr{ prefixes: found.into_iter().map(|x| x.prefix).collect() })),
+    }
+}
+
+/// Read the target names of the index in a directory in index order.
+///
+/// Finds the index with [find_index] and reads the names from
+/// [IndexFiles::targets_path], one per line. Empty lines are ignored.
+///
+/// ## Errors and panics
+/// Returns an error if [find_index] fails, the sidecar file doesn't
+/// exist, or it can't be read.
+///
+pub fn read_index_targets(
+    dir: &Path,
+) -> Result<(IndexKind, Vec<Vec<u8>>), E> {
+    let index = find_index(dir)?;
+    let path = index.targets_path();
+    if !path.is_file() {
+        return Err(Box::new(IndexTargetsNotFoundErr{ kind: index.kind, path }))
+    }
+
+    let reader = BufReader::new(std::fs::File::open(&path)?);
+    let mut targets: Vec<Vec<u8>> = Vec::new();
+    for line in reader.split(b'\n') {
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if !line.is_empty() {
+            targets.push(line);
+        }
+    }
+
+    Ok((index.kind, targets))
+}
+
+/// SHA-256 of the target names written one per line.
+///
+/// Equals the SHA-256 checksum of a target list file with a newline after
+/// each name.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::index::target_list_digest;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let hex: String = target_list_digest(&targets).iter().map(|x| format!("{:02x}", x)).collect();
+/// assert_eq!(hex.len(), 64);
+/// ```
+///
+pub fn target_list_digest(
+    targets: &[Vec<u8>],
+) -> [u8; 32] {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    targets.iter().for_each(|name| {
+        hasher.update(name);
+        hasher.update(b"\n");
+    });
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn find_index_kinds_and_errors() {
+        use super::find_index;
+        use super::IndexKind;
+
+        let dir = std::env::temp_dir().join(format!("ahda-find-index-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Themisto needs both files
+        std::fs::write(dir.join("idx.tdbg"), b"").unwrap();
+        assert!(find_index(&dir).is_err());
+        std::fs::write(dir.join("idx.tcolors"), b"").unwrap();
+        let got = find_index(&dir).unwrap();
+        assert_eq!(got.kind, IndexKind::Themisto);
+        assert_eq!(got.targets_path(), dir.join("idx.targets.txt"));
+
+        // Two indexes are ambiguous
+        std::fs::write(dir.join("other.hfur"), b"").unwrap();
+        assert!(find_index(&dir).is_err());
+
+        std::fs::remove_file(dir.join("idx.tdbg")).unwrap();
+        assert_eq!(find_index(&dir).unwrap().kind, IndexKind::Fulgor);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn target_list_digest_matches_file_checksum() {
+        use super::target_list_digest;
+
+        // sha256sum of "a\nb\n"
+        let expected = "911169ddaaf146aff539f58c26c489af3b892dff0fe283c1c264c65ae5aa59a2";
+        let got: String = target_list_digest(&[b"a".to_vec(), b"b".to_vec()]).iter().map(|x| format!("{:02x}", x)).collect();
+        assert_eq!(got, expected);
+    }
+}
@@ -0,0 +1,304 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Expand directories and glob patterns into ordered input files.
+//!
+//! Data split into chunks is usually named `chunk1.ahda`, `chunk2.ahda`, ...,
+//! `chunk10.ahda`. The shell expands `chunk*.ahda` in lexical order, which
+//! puts `chunk10.ahda` before `chunk2.ahda` and scrambles the concatenated
+//! output. [expand_inputs] instead lists the files of a directory or a quoted
+//! glob pattern in natural order, see [natural_cmp], and [OrderBy] can also
+//! sort all inputs by the query id of their first record.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::inputs::natural_cmp;
+//! use ahda::inputs::glob_match;
+//!
+//! let mut names = vec!["chunk10.ahda", "chunk2.ahda", "chunk1.ahda"];
+//! names.sort_by(|a, b| natural_cmp(a, b));
+//! assert_eq!(names, vec!["chunk1.ahda", "chunk2.ahda", "chunk10.ahda"]);
+//!
+//! assert!(glob_match("chunk*.ahda", "chunk10.ahda"));
+//! assert!(!glob_match("chunk?.ahda", "chunk10.ahda"));
+//! ```
+//!
+
+use crate::compression::dedup::BlockCache;
+use crate::compression::flags_dictionary::FlagsDictionary;
+use crate::headers::block::decode_block_flags_with_dictionary;
+use crate::headers::file::read_file_flags;
+use crate::headers::file::read_file_header;
+
+use std::cmp::Ordering;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+type E = Box<dyn std::error::Error>;
+
+/// Order of the inputs of `ahda cat`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrderBy {
+    /// Keep the order of the arguments. Directories and glob patterns are
+    /// expanded in natural order.
+    #[default]
+    Given,
+    /// Sort all inputs by their path in natural order.
+    Name,
+    /// Sort the inputs by the query id of the first record in their first
+    /// block, see [start_query_id].
+    StartIdx,
+}
+
+impl std::str::FromStr for OrderBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "given" => Ok(OrderBy::Given),
+            "name" => Ok(OrderBy::Name),
+            "start-idx" => Ok(OrderBy::StartIdx),
+            _ => Err(format!("'{}' is not a valid OrderBy", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OrderBy::Given => write!(f, "given"),
+            OrderBy::Name => write!(f, "name"),
+            OrderBy::StartIdx => write!(f, "start-idx"),
+        }
+    }
+}
+
+/// Compare `a` and `b` so that runs of digits are ordered by their value.
+///
+/// Numbers with leading zeros that have the same value are ordered by their
+/// length, and other characters are compared as is.
+pub fn natural_cmp(
+    a: &str,
+    b: &str,
+) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    while let (Some(x), Some(y)) = (a.first(), b.first()) {
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+            let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+            let a_num = trim_zeros(&a[..a_len]);
+            let b_num = trim_zeros(&b[..b_len]);
+            let ord = a_num.len().cmp(&b_num.len()).then(a_num.cmp(b_num)).then(a_len.cmp(&b_len));
+            if ord != Ordering::Equal {
+                return ord
+            }
+            a = &a[a_len..];
+            b = &b[b_len..];
+        } else {
+            if x != y {
+                return x.cmp(y)
+            }
+            a = &a[1..];
+            b = &b[1..];
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+fn trim_zeros(
+    digits: &[u8],
+) -> &[u8] {
+    let n_zeros = digits.iter().take_while(|c| **c == b'0').count();
+    &digits[n_zeros..]
+}
+
+/// Check if `name` matches the glob `pattern`.
+///
+/// `*` matches any number of characters and `?` matches one character.
+pub fn glob_match(
+    pattern: &str,
+    name: &str,
+) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    backtrack = Some((star_p, star_n + 1));
+                    p = star_p;
+                    n = star_n + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn is_glob(
+    path: &Path,
+) -> bool {
+    path.file_name().is_some_and(|x| x.to_string_lossy().contains(['*', '?']))
+}
+
+/// Files in `dir` whose name matches `pattern`, in natural order.
+fn list_dir(
+    dir: &Path,
+    pattern: &str,
+) -> Result<Vec<PathBuf>, E> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+        if path.is_file() && glob_match(pattern, &name) {
+            files.push(path);
+        }
+    }
+    files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    Ok(files)
+}
+
+/// Replace directories and glob patterns in `paths` with the files they contain.
+///
+/// A directory is replaced by the .ahda files in it and a path whose file
+/// name contains `*` or `?` by the files in its directory that match the
+/// pattern, both in natural order. Other paths are kept as is. Directories
+/// are not searched recursively.
+///
+/// ## Errors and panics
+/// Errors with [NoMatchingInputsErr](crate::errors::NoMatchingInputsErr) if a
+/// directory or pattern matches no files, and if a directory can't be read.
+///
+pub fn expand_inputs(
+    paths: &[PathBuf],
+) -> Result<Vec<PathBuf>, E> {
+    let mut expanded: Vec<PathBuf> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let files = if path.is_dir() {
+            list_dir(path, "*.ahda")?
+        } else if is_glob(path) && !path.exists() {
+            let dir = path.parent().filter(|x| !x.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            list_dir(dir, &path.file_name().unwrap().to_string_lossy())?
+        } else {
+            vec![path.clone()]
+        };
+        if files.is_empty() {
+            return Err(Box::new(crate::errors::NoMatchingInputsErr{ path: path.clone() }))
+        }
+        expanded.extend(files);
+    }
+    Ok(expanded)
+}
+
+/// Query id of the first record in the first block of the .ahda record in `conn`.
+///
+/// Returns None if the record has no blocks or the first block has no query
+/// ids. Consumes the file header, flags, and first block from `conn`.
+///
+/// ## Errors and panics
+/// Errors if the headers or the first block can't be read.
+///
+pub fn start_query_id<R: Read>(
+    conn: &mut R,
+) -> Result<Option<u32>, E> {
+    let header = read_file_header(conn)?;
+    let file_flags = read_file_flags(&header, conn)?;
+    let Some((block_header, bytes)) = BlockCache::new().try_read_block(conn)? else {
+        return Ok(None)
+    };
+    let dictionary = FlagsDictionary::from_flags(&file_flags);
+    let flags = decode_block_flags_with_dictionary(&bytes[..(block_header.flags_len as usize)], &block_header, dictionary.as_ref(), &mut Vec::new())?;
+    Ok(flags.query_ids.and_then(|ids| ids.first().copied()))
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn natural_cmp_orders_numbers_by_value() {
+        use super::natural_cmp;
+
+        let mut names = vec!["b.ahda", "chunk10.ahda", "chunk02.ahda", "chunk2.ahda", "chunk1.ahda", "chunk.ahda", "a100b2", "a100b10"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["a100b2", "a100b10", "b.ahda", "chunk.ahda", "chunk1.ahda", "chunk2.ahda", "chunk02.ahda", "chunk10.ahda"]);
+    }
+
+    #[test]
+    fn glob_match_with_wildcards() {
+        use super::glob_match;
+
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*.ahda", "chunk1.ahda"));
+        assert!(glob_match("c*k*.ahda", "chunk1.ahda"));
+        assert!(glob_match("chunk?.ahda", "chunk1.ahda"));
+        assert!(!glob_match("chunk?.ahda", "chunk.ahda"));
+        assert!(!glob_match("*.ahda", "chunk1.ahda.tmp"));
+        assert!(!glob_match("chunk", "chunk1"));
+    }
+
+    #[test]
+    fn expand_inputs_in_natural_order() {
+        use super::expand_inputs;
+        use std::path::PathBuf;
+
+        let dir = std::env::temp_dir().join(format!("ahda-expand-inputs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["chunk10.ahda", "chunk2.ahda", "chunk1.ahda", "notes.txt"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let expected: Vec<PathBuf> = ["chunk1.ahda", "chunk2.ahda", "chunk10.ahda"].iter().map(|x| dir.join(x)).collect();
+        assert_eq!(expand_inputs(std::slice::from_ref(&dir)).unwrap(), expected);
+        assert_eq!(expand_inputs(&[dir.join("chunk*.ahda")]).unwrap(), expected);
+        assert_eq!(expand_inputs(&[dir.join("chunk?.ahda")]).unwrap(), expected[0..2].to_vec());
+        assert_eq!(expand_inputs(&[dir.join("notes.txt")]).unwrap(), vec![dir.join("notes.txt")]);
+        assert!(expand_inputs(&[dir.join("*.fastq")]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn start_query_id_of_first_block() {
+        use super::start_query_id;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        let targets = ["chr.fasta".as_bytes().to_vec()];
+        let queries = ["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec()];
+        let opts = EncodeOpts{ accession: "sample".as_bytes().to_vec(), ..Default::default() };
+
+        let data = [PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![0]), query_name: None }];
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &queries, &data, &mut bytes, opts.clone()).unwrap();
+        assert_eq!(start_query_id(&mut bytes.as_slice()).unwrap(), Some(1));
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &queries, &[], &mut bytes, opts).unwrap();
+        assert_eq!(start_query_id(&mut bytes.as_slice()).unwrap(), None);
+    }
+}
@@ -0,0 +1,136 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Sequencing lanes that form one sample.
+//!
+//! A sample sequenced on several lanes is delivered as one .fastq file per
+//! lane. When the reads of all lanes are aligned as one sample, the query ids
+//! continue from one lane to the next: the reads of the first lane are
+//! numbered from 0, the reads of the second lane from the number of reads in
+//! the first lane, and so on. [Lanes] records the name of each lane and the
+//! query id of its first read, so that the reads can be traced back to their
+//! lane after encoding.
+//!
+//! [Encoder::set_lanes](crate::encoder::Encoder::set_lanes) stores the lanes
+//! in the file extensions under [LANES_KEY], see [Lanes::from_flags].
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::lanes::Lanes;
+//!
+//! let lanes = Lanes::new(vec![b"L001.fastq".to_vec(), b"L002.fastq".to_vec()], &[3, 2]).unwrap();
+//! assert_eq!(lanes.offsets, vec![0, 3]);
+//! assert_eq!(lanes.lane_of(2), Some(0));
+//! assert_eq!(lanes.lane_of(3), Some(1));
+//! assert_eq!(lanes.lane_of(5), None);
+//! ```
+//!
+
+use crate::headers::file::FileFlags;
+
+use bincode::{Encode, Decode};
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the [Lanes] stored in the file extensions.
+pub const LANES_KEY: &[u8] = b"ahda.lanes";
+
+/// Lanes whose reads are numbered one after the other.
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq, Eq)]
+pub struct Lanes {
+    /// Name of each lane, eg. the .fastq file name.
+    pub names: Vec<Vec<u8>>,
+    /// Query id of the first read of each lane.
+    pub offsets: Vec<u32>,
+    /// Number of reads in all lanes.
+    pub n_queries: u32,
+}
+
+impl Lanes {
+    /// Number the lanes in order from the number of reads in each lane.
+    ///
+    /// ## Errors and panics
+    /// Errors if the total number of reads doesn't fit in 32 bits.
+    ///
+    /// Panics if `names` and `n_reads` have different lengths.
+    ///
+    pub fn new(
+        names: Vec<Vec<u8>>,
+        n_reads: &[usize],
+    ) -> Result<Self, E> {
+        assert_eq!(names.len(), n_reads.len());
+        let mut offsets: Vec<u32> = Vec::with_capacity(n_reads.len());
+        let mut n_queries: usize = 0;
+        for n in n_reads {
+            offsets.push(u32::try_from(n_queries)?);
+            n_queries += n;
+        }
+        Ok(Lanes{ names, offsets, n_queries: u32::try_from(n_queries)? })
+    }
+
+    /// Number of reads in `lane`.
+    ///
+    /// ## Errors and panics
+    /// Panics if `lane` is out of bounds.
+    ///
+    pub fn n_reads(
+        &self,
+        lane: usize,
+    ) -> u32 {
+        self.offsets.get(lane + 1).copied().unwrap_or(self.n_queries) - self.offsets[lane]
+    }
+
+    /// Index of the lane that `query_id` belongs to.
+    ///
+    /// Returns None if `query_id` is past the last read of the last lane.
+    pub fn lane_of(
+        &self,
+        query_id: u32,
+    ) -> Option<usize> {
+        if query_id >= self.n_queries {
+            return None
+        }
+        Some(self.offsets.partition_point(|x| *x <= query_id) - 1)
+    }
+
+    /// Read the lanes stored in `flags`, if any.
+    ///
+    /// ## Errors and panics
+    /// Errors if the stored value can't be decoded.
+    ///
+    pub fn from_flags(
+        flags: &FileFlags,
+    ) -> Result<Option<Lanes>, E> {
+        flags.extensions.as_ref().and_then(|x| x.get_as::<Lanes>(LANES_KEY)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn lanes_with_empty_lane() {
+        use super::Lanes;
+
+        let lanes = Lanes::new(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()], &[2, 0, 3]).unwrap();
+        assert_eq!(lanes.offsets, vec![0, 2, 2]);
+        assert_eq!(lanes.n_queries, 5);
+        assert_eq!(lanes.n_reads(1), 0);
+        assert_eq!(lanes.n_reads(2), 3);
+
+        let got: Vec<Option<usize>> = (0..6).map(|x| lanes.lane_of(x)).collect();
+        assert_eq!(got, vec![Some(0), Some(0), Some(2), Some(2), Some(2), None]);
+    }
+}
@@ -19,6 +19,7 @@
 //!   - Converting between plain text pseudoalignment formats output by different tools.
 //!   - Concatenating non-overlapping binary alignment data.
 //!   - Performing set operations on compressed pseudoalignment data.
+//!   - Comparing compressed pseudoalignment data.
 //!
 //! The following plain text formats are supported:
 //!   - [Bifrost](https://github.com/pmelsted/bifrost)
@@ -45,6 +46,11 @@
 //!   - `ahda convert` convert between supported plain text formats.
 //!   - `ahda cat` concatenate binary data that doesn't contain duplicated queries.
 //!   - `ahda set` perform set operations on compressed pseudoalignment data.
+//!   - `ahda compare` compute the similarity of two compressed pseudoalignments.
+//!   - `ahda simulate` generate synthetic pseudoalignment data.
+//!
+//! `decode` and `convert` check whether the input is .ahda or plain text data
+//! and will either decode or convert it to the requested output format.
 //!
 //! Note that `encode` may need access to the .fastq input file and the names of
 //! the pseudoalignment targets. These are required to create an encoded record
@@ -78,13 +84,33 @@
 //! These structs can additionally be chained together to eg. read encoded data
 //! and print it in a plain text format, or to parse plain text data and encode
 //! it.
+//! `examples/api.rs` chains all of them and skips the records that can't be
+//! read, run it with `cargo run --example api`.
+//! [Pipeline](pipeline::Pipeline) chains them with filters and transforms
+//! in between in a single call.
+//!
+//! Programs that write or read single blocks, eg. aligners producing .ahda
+//! output directly, should use the stable interface in [blocks].
 //!
 //! See documentation for the appropriate functions or structs for usage examples.
 //!
+//! ### Cargo features
+//!
+//! Encoding and decoding .ahda data and the plain text formats other than SAM
+//! are always available. The other parts are behind cargo features:
+//! - `cli`: the `ahda` binary, enables `fastx`.
+//! - `fastx`: reading query names from .fasta and .fastq files, see [fastx].
+//! - `sam`: reading .sam files with noodles-sam.
+//! - `cxx-api`: the C++ API, `cxxbridge` is an alias for it.
+//! - `zlib-ng`: use zlib-ng instead of zlib-rs for gzip.
+//!
+//! `cli`, `fastx`, and `sam` are enabled by default. Build with
+//! `default-features = false` to only include the codec, eg. for wasm.
+//!
 //! ### C++ API
 //!
 //! ahda provides a C++ API for encoding and decoding pseudoalignment data into
-//! memory. The API is available with the feature `cxxbridge`.
+//! memory. The API is available with the feature `cxx-api`.
 //!
 //! Encoding requires converting the pseudoalignment to a flattened form
 //! and obtaining the indexes of the set bits (positive alignments) that should
@@ -109,7 +135,7 @@
 //! ### File header and flags
 //!
 //! - A [FileHeader] consisting of exactly 32 bytes.
-//!   The header must start with the bytes `97, 104, 100, 97` indicating that
+//!   The header must start with the [MAGIC] bytes `97, 104, 100, 97` indicating that
 //!   the binary file is an .ahda record, and must be followed by two bytes
 //!   indicating the [ahda library version](AhdaVersion). The library version is
 //!   followed by a single byte indicating the [ahda file format
@@ -128,6 +154,10 @@
 //! alignment target sequences must be provided in the order that they appear in
 //! the alignment.
 //!
+//! The FileFlags block may also contain [groupings](headers::file::TargetGroups)
+//! of the target sequences, eg. into genomes, species, and genera. These are
+//! written after the target names only if present.
+//!
 //! Each .ahda file must only have one FileHeader and FileFlags.
 //!
 //! ### Blocks
@@ -148,7 +178,10 @@
 //! - A block containing the (compressed) bitmap corresponding to the alignment
 //!   data. The [type of the bitmap](compression::BitmapType) must match the
 //!   `bitmap_type` field of BlockHeader. The number of bytes containing the
-//!   bitmap must be provided in the `block_len` field of BlockHeader.
+//!   bitmap must be provided in the `block_len` field of BlockHeader. The
+//!   serialized bitmap is gzipped unless the
+//!   [BLOCK_PAYLOAD_RAW](compression::BLOCK_PAYLOAD_RAW) flag is set in the
+//!   `placeholder1` field of BlockHeader.
 //!
 //! The BlockFlags block must contain the fields promised by the
 //! `fields_present` field of FileHeader. The BlockFlags may contain additional
@@ -158,13 +191,33 @@
 //! Each .ahda block must consist of exactly one BlockHeader and BlockFlags. An
 //! .ahda file may have any number of blocks.
 //!
+//! A block may omit its bitmap if the bitmap bytes are identical to an earlier
+//! block in the same file, see [dedup](compression::dedup) for details.
+//!
+//! Blocks written by the [Encoder](encoder::Encoder) store their query ids in
+//! ascending order and set
+//! [BLOCK_QUERY_IDS_SORTED](compression::BLOCK_QUERY_IDS_SORTED) if the ids
+//! also follow the ids of the previous block, which lets
+//! [BlockIndex](decoder::block_index::BlockIndex) binary search the blocks.
+//! Query ids that are close to each other are stored as u16 offsets from the
+//! smallest id, which is marked with
+//! [BLOCK_QUERY_IDS_U16](compression::BLOCK_QUERY_IDS_U16).
+//!
 //! A valid .ahda file ends with a block and must not include a footer.
 //!
+//! An .ahda file with no records consists of only the file header and flags,
+//! with zero blocks. The `n_queries` field of FileHeader may still be nonzero
+//! if the query names were known but none of them were recorded. Such files
+//! decode to an empty set of records and can be concatenated and merged with
+//! other files.
+//!
 
 use headers::file::FileHeader;
 use headers::file::FileFlags;
+use headers::file::TargetAliases;
+use headers::file::TargetGroups;
 use headers::block::BlockFlags;
-use headers::block::read_block_header;
+use headers::block::BlockStats;
 use headers::file::read_file_header;
 use headers::file::read_file_flags;
 use headers::file::build_file_header_and_flags;
@@ -172,30 +225,72 @@ use headers::file::encode_file_header;
 use headers::file::encode_file_flags;
 use compression::BitmapType;
 use compression::MetadataCompression;
-use compression::roaring32::unpack_block_roaring32;
-use compression::roaring64::unpack_block_roaring64;
+use compression::dedup::BlockCache;
+use parser::query_ids::QueryIdPolicy;
+use encoder::sorted::SortOpts;
+use compression::roaring32::unpack_block_roaring32_with_buffers;
+use compression::roaring64::unpack_block_roaring64_with_buffers;
 
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::io::Read;
 use std::io::Write;
 
+use roaring::RoaringBitmap;
 use roaring::treemap::RoaringTreemap;
 
-#[cfg(feature = "cxxbridge")]
+#[cfg(feature = "cxx-api")]
 pub mod cxx_api;
-
+#[cfg(feature = "fastx")]
+pub mod fastx;
+#[cfg(feature = "serve")]
+pub mod serve;
+
+pub mod bench;
+pub mod blocks;
+pub mod bloom;
 pub mod compression;
+pub mod consistency;
+pub mod coverage;
 pub mod headers;
+pub mod index;
+pub mod inputs;
+pub mod lanes;
+pub mod manifest;
+pub mod max_hits;
+pub mod output;
+pub mod panel;
 pub mod decoder;
+pub mod doctor;
+pub mod dry_run;
+pub mod estimate;
 pub mod encoder;
 pub mod errors;
 pub mod parser;
+pub mod pipeline;
 pub mod printer;
+pub mod query_cache;
+pub mod report;
+pub mod self_hits;
+pub mod shard;
+pub mod simulate;
+pub mod target_chunks;
+pub mod target_summary;
 
 type E = Box<dyn std::error::Error>;
 
-const MASK_QUERIES: u16 = 1 << 0;   // 0x0001
-const MASK_QUERY_IDS: u16 = 1 << 1; // 0x0002
+use ahda_core::file::MASK_QUERIES;
+use ahda_core::file::MASK_QUERY_IDS;
+use ahda_core::file::MASK_MATES;
+use ahda_core::file::MASK_QUERY_FILTER;
+
+pub use ahda_core::file::MAGIC;
+
+/// [File format version](AhdaFormatVersion) written by this version of ahda.
+///
+/// Stored in the `file_format` byte of [FileHeader], right after [MAGIC] and
+/// the two [library version](AhdaVersion) bytes.
+pub const FORMAT_VERSION: u8 = 1;
 
 /// Ahda library version
 ///
@@ -241,14 +336,21 @@ impl std::str::FromStr for AhdaVersion {
 /// Breaking changes should be avoided whenever possible.
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum AhdaFormatVersion {
-    #[default]
     V1_0_0,
+    /// Optional fields of the flags in length-prefixed sections and
+    /// [BLOCK_QUERY_IDS_U16](compression::BLOCK_QUERY_IDS_U16).
+    ///
+    /// Files without optional fields and u16 query ids are identical to
+    /// [V1_0_0](AhdaFormatVersion::V1_0_0) files, so both are read the same way.
+    #[default]
+    V1_1_0,
 }
 
 impl AhdaFormatVersion {
     pub fn from_u8(val: u8) -> Result<Self, E> {
         match val {
             0 => Ok(AhdaFormatVersion::V1_0_0),
+            1 => Ok(AhdaFormatVersion::V1_1_0),
             _ => Err(Box::new(errors::AhdaFormatVersionErr)),
         }
     }
@@ -256,10 +358,93 @@ impl AhdaFormatVersion {
     pub fn to_u8(&self) -> u8 {
         match &self {
             AhdaFormatVersion::V1_0_0 => 0,
+            AhdaFormatVersion::V1_1_0 => 1,
         }
     }
 }
 
+/// Capabilities of this build of ahda.
+///
+/// Returned by [compat_info]. The [Display](std::fmt::Display)
+/// implementation prints the library version on the first line followed by
+/// one `key: values` line per field, which is what `ahda --version` shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatInfo {
+    /// Library version.
+    pub version: &'static str,
+    /// [File format versions](AhdaFormatVersion) that can be read.
+    pub read_formats: Vec<u8>,
+    /// [File format version](AhdaFormatVersion) that is written.
+    pub write_format: u8,
+    /// Supported [bitmap types](compression::BitmapType).
+    pub bitmap_types: Vec<BitmapType>,
+    /// Supported [metadata compression methods](MetadataCompression).
+    pub metadata_compressions: Vec<MetadataCompression>,
+    /// Optional features and whether they are compiled in.
+    pub features: Vec<(&'static str, bool)>,
+}
+
+impl std::fmt::Display for CompatInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let read_formats: Vec<String> = self.read_formats.iter().map(|x| x.to_string()).collect();
+        let bitmap_types: Vec<String> = self.bitmap_types.iter().map(|x| x.to_string()).collect();
+        let metadata_compressions: Vec<&str> = self.metadata_compressions.iter().map(|x| match x {
+            MetadataCompression::BincodeStandard => "bincode-standard",
+            MetadataCompression::Flate2 => "flate2",
+            MetadataCompression::ZstdDictionary => "zstd-dictionary",
+        }).collect();
+        let features: Vec<String> = self.features.iter().map(|(name, enabled)| format!("{}{}", if *enabled { '+' } else { '-' }, name)).collect();
+
+        writeln!(f, "{}", self.version)?;
+        writeln!(f, "read formats: {}", read_formats.join(", "))?;
+        writeln!(f, "write format: {}", self.write_format)?;
+        writeln!(f, "bitmap types: {}", bitmap_types.join(", "))?;
+        writeln!(f, "metadata compression: {}", metadata_compressions.join(", "))?;
+        write!(f, "features: {}", features.join(" "))
+    }
+}
+
+/// Report the file formats and features supported by this build.
+///
+/// Features that ahda does not implement yet, such as memory-mapped input, and
+/// features that were left out of this build are listed as disabled so that the output has the
+/// same keys in every build.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::compat_info;
+/// use ahda::FORMAT_VERSION;
+///
+/// let info = compat_info();
+/// assert_eq!(info.write_format, FORMAT_VERSION);
+/// assert!(info.read_formats.contains(&FORMAT_VERSION));
+/// assert!(info.features.contains(&("threads", true)));
+/// ```
+///
+pub fn compat_info() -> CompatInfo {
+    CompatInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        read_formats: vec![AhdaFormatVersion::V1_0_0.to_u8(), AhdaFormatVersion::V1_1_0.to_u8()],
+        write_format: FORMAT_VERSION,
+        bitmap_types: vec![BitmapType::Roaring32, BitmapType::Roaring64],
+        metadata_compressions: if cfg!(feature = "zstd") {
+            vec![MetadataCompression::BincodeStandard, MetadataCompression::Flate2, MetadataCompression::ZstdDictionary]
+        } else {
+            vec![MetadataCompression::BincodeStandard, MetadataCompression::Flate2]
+        },
+        features: vec![
+            ("threads", true),
+            ("zstd", cfg!(feature = "zstd")),
+            ("mmap", false),
+            ("zlib-ng", cfg!(feature = "zlib-ng")),
+            ("fastx", cfg!(feature = "fastx")),
+            ("sam", cfg!(feature = "sam")),
+            ("cxx-api", cfg!(feature = "cxx-api")),
+            ("serve", cfg!(feature = "serve")),
+        ],
+    }
+}
+
 
 /// Supported plain text formats.
 #[non_exhaustive]
@@ -303,6 +488,52 @@ impl std::fmt::Display for Format {
     }
 }
 
+impl Format {
+    /// Query fields stored in this format as a `fields_present` mask.
+    ///
+    /// Uses the same bits as the `fields_present` field of [FileHeader]. All
+    /// formats store the alignment targets.
+    pub fn fields_present(&self) -> u16 {
+        match self {
+            Format::Themisto => MASK_QUERY_IDS,
+            Format::Bifrost | Format::Fulgor | Format::SAM => MASK_QUERIES,
+            Format::Metagraph | Format::AhdaTSV => MASK_QUERIES | MASK_QUERY_IDS,
+        }
+    }
+
+    /// Names of the query fields in `fields_present` that this format can't store.
+    ///
+    /// Returns an empty vector if converting records with `fields_present`
+    /// to this format does not lose information.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::Format;
+    ///
+    /// // Query names and ids
+    /// let fields_present = Format::AhdaTSV.fields_present();
+    ///
+    /// assert_eq!(Format::Themisto.lost_fields(fields_present), vec!["query names"]);
+    /// assert_eq!(Format::Bifrost.lost_fields(fields_present), vec!["query ids"]);
+    /// assert!(Format::Metagraph.lost_fields(fields_present).is_empty());
+    /// ```
+    ///
+    pub fn lost_fields(
+        &self,
+        fields_present: u16,
+    ) -> Vec<&'static str> {
+        let lost = fields_present & !self.fields_present();
+        let mut names: Vec<&'static str> = Vec::new();
+        if lost & MASK_QUERIES != 0 {
+            names.push("query names");
+        }
+        if lost & MASK_QUERY_IDS != 0 {
+            names.push("query ids");
+        }
+        names
+    }
+}
+
 /// Supported set operations for [decode_from_read_into_roaring].
 #[non_exhaustive]
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
@@ -328,6 +559,45 @@ impl std::str::FromStr for MergeOp {
     }
 }
 
+/// What to do with queries that align to no targets after a set operation, see [drop_empty_rows].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptyRows {
+    /// Write the queries as records without targets.
+    #[default]
+    Keep,
+    /// Remove the queries and renumber the rest.
+    Drop,
+    /// Remove the queries and record how many were removed under [EMPTY_ROWS_DROPPED_KEY].
+    Summary,
+}
+
+impl std::str::FromStr for EmptyRows {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(EmptyRows::Keep),
+            "drop" => Ok(EmptyRows::Drop),
+            "summary" => Ok(EmptyRows::Summary),
+            _ => Err(format!("'{}' is not keep, drop, or summary", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for EmptyRows {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EmptyRows::Keep => write!(f, "keep"),
+            EmptyRows::Drop => write!(f, "drop"),
+            EmptyRows::Summary => write!(f, "summary"),
+        }
+    }
+}
+
+/// Key of the number of queries removed by [EmptyRows::Summary] in the file extensions, stored as a u64.
+pub const EMPTY_ROWS_DROPPED_KEY: &[u8] = b"ahda.empty_rows_dropped";
+
 /// Options to functions that encode input data.
 #[non_exhaustive]
 #[derive(Clone, Debug, PartialEq)]
@@ -345,11 +615,76 @@ pub struct EncodeOpts {
 
     /// Force bitmap type to use, see [BitmapType].
     pub bitmap_type: Option<BitmapType>,
+    /// Split the targets of each block across sub-blocks of this many targets, see [target_chunks].
+    pub target_chunks: Option<u32>,
     /// Metadata compression method, see [compression].
+    ///
+    /// [MetadataCompression::ZstdDictionary] trains a dictionary on the query
+    /// names of the first blocks, see [compression::flags_dictionary].
     pub metadata_compression: MetadataCompression,
 
     /// Rename queries using the scheme `accession`.`query_id`
     pub rename_queries: bool,
+
+    /// Groupings of the target sequences to store in [FileFlags], see [read_target_groups].
+    pub target_groups: Vec<TargetGroups>,
+
+    /// Merge the target sequences into fewer targets, see [read_target_aliases].
+    pub target_aliases: Option<TargetAliases>,
+    /// Store `target_aliases` in [FileFlags].
+    pub store_target_aliases: bool,
+
+    /// Query ids of the second mates of paired-end reads, see [BlockFlags::mate].
+    pub second_mates: Option<RoaringBitmap>,
+
+    /// Gzip compression level for the blocks, from 1 (fastest) to 9 (smallest).
+    pub compression_level: u32,
+    /// Store files of at most this many records in a single uncompressed block, see [Encoder::set_inline_max_records](encoder::Encoder::set_inline_max_records).
+    pub inline_max_records: usize,
+
+    /// Store a Bloom filter of the query names in each block with this false positive rate, see [BlockFlags::might_contain].
+    pub query_filter_fpr: Option<f64>,
+
+    /// Store the targets that each block has hits to, see [target_summary].
+    pub target_summary: bool,
+
+    /// How to assign query ids to records that only have a name, see [QueryIdPolicy].
+    pub query_ids: QueryIdPolicy,
+
+    /// Sort plain text input by query id before encoding, see [ExternalSort](encoder::sorted::ExternalSort).
+    ///
+    /// Used by [encode_from_read] and [encode_from_read_to_write]. Records
+    /// given in memory are only sorted within each block.
+    pub sort_input: Option<SortOpts>,
+
+    /// Write the output in a zstd-seekable container with this zstd compression level, see [FILE_IS_ZSTD_SEEKABLE](compression::FILE_IS_ZSTD_SEEKABLE).
+    pub zstd_level: Option<i32>,
+
+    /// Cap the number of targets of each record, see [MaxHits](max_hits::MaxHits).
+    pub max_hits: Option<max_hits::MaxHits>,
+
+    /// Lanes that the queries were read from to store in [FileFlags], see [Lanes](lanes::Lanes).
+    pub lanes: Option<lanes::Lanes>,
+
+    /// Name the queries whose rank is past the end of the query names, see [Parser::set_allow_extra](parser::Parser::set_allow_extra).
+    ///
+    /// Used by [encode_from_read] and [encode_from_read_to_write], which
+    /// read the records into memory first to find the last rank.
+    pub allow_extra: bool,
+
+    /// Store the coverage of the targets in buckets of this many bases in [FileFlags], see [Coverage](coverage::Coverage).
+    ///
+    /// Requires SAM input. Used by [encode_from_read] and
+    /// [encode_from_read_to_write], which read the records into memory first
+    /// to know the coverage before writing the file flags.
+    pub coverage_bucket: Option<u32>,
+
+    /// Store the [Panel](panel::Panel) of the target names in [FileFlags].
+    ///
+    /// The panel is also stored if `index_version` is given.
+    pub store_panel: bool,
+    /// Version of the index that the input was aligned against, stored in the [Panel](panel::Panel).
+    pub index_version: Option<Vec<u8>>,
 }
 
 impl Default for EncodeOpts {
@@ -361,8 +696,26 @@ impl Default for EncodeOpts {
     /// opts.encode_query_names = false;
     /// opts.encode_target_names = false;
     /// opts.bitmap_type = None;
+/// opts.target_chunks = None;
     /// opts.metadata_compression = ahda::compression::MetadataCompression::BincodeStandard;
     /// opts.rename_queries = false;
+    /// opts.target_groups = Vec::new();
+    /// opts.target_aliases = None;
+    /// opts.store_target_aliases = false;
+    /// opts.second_mates = None;
+    /// opts.compression_level = 6;
+    /// opts.inline_max_records = ahda::compression::INLINE_MAX_RECORDS;
+    /// opts.query_filter_fpr = None;
+    /// opts.target_summary = false;
+    /// opts.query_ids = ahda::parser::query_ids::QueryIdPolicy::FromList;
+    /// opts.sort_input = None;
+    /// opts.zstd_level = None;
+    /// opts.max_hits = None;
+    /// opts.lanes = None;
+    /// opts.allow_extra = false;
+    /// opts.coverage_bucket = None;
+    /// opts.store_panel = false;
+    /// opts.index_version = None;
     /// # let expected = ahda::EncodeOpts::default();
     /// # assert_eq!(opts, expected);
     /// ```
@@ -374,8 +727,26 @@ impl Default for EncodeOpts {
             encode_query_names: false,
             encode_target_names: false,
             bitmap_type: None,
+            target_chunks: None,
             metadata_compression: MetadataCompression::default(),
             rename_queries: false,
+            target_groups: Vec::new(),
+            target_aliases: None,
+            store_target_aliases: false,
+            second_mates: None,
+            compression_level: compression::gzwrapper::DEFAULT_LEVEL,
+            inline_max_records: compression::INLINE_MAX_RECORDS,
+            query_filter_fpr: None,
+            target_summary: false,
+            query_ids: QueryIdPolicy::default(),
+            sort_input: None,
+            zstd_level: None,
+            max_hits: None,
+            lanes: None,
+            allow_extra: false,
+            coverage_bucket: None,
+            store_panel: false,
+            index_version: None,
         }
     }
 }
@@ -387,6 +758,12 @@ impl Default for EncodeOpts {
 /// ahda API, this data must be filled in to create a valid .ahda record from
 /// the encode API calls or with the [Encoder](crate::encoder::Encoder) class.
 ///
+/// An empty `ones` or `ones_names` means that the query aligned to no
+/// targets, and None that the targets are not known, see [PseudoAln::hits].
+/// The parsers return queries that are listed as unaligned, including
+/// unmapped SAM records, with empty targets. The encoders refuse records
+/// whose targets are not known instead of storing them as unaligned.
+///
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct PseudoAln{
     /// Indexes of positive alignment targets.
@@ -399,889 +776,3731 @@ pub struct PseudoAln{
     pub query_name: Option<Vec<u8>>,
 }
 
-/// Merge compressed data by concatenating all blocks.
-///
-/// This simply appends the blocks in input order using [std::io::copy], it does
-/// not check or modify the contents of the block header, block flags, or the
-/// block data.
-///
-/// Updates the `n_queries` and `flags_len` fields in [FileHeader] to match the
-/// new data.
-///
-/// Retains the `query_name` and `target_names` fields for [FileFlags] from the
-/// first input.
-///
-/// ## Errors and panics
-///
-/// Panics if the [file headers](FileHeader) have different
-/// number of targets or different target sequence names.
+/// Whether a [PseudoAln] aligned to any targets, see [PseudoAln::hits].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hits {
+    /// Aligned to at least one target.
+    Aligned,
+    /// Aligned to no targets.
+    Unaligned,
+    /// Neither the target indexes nor names are known.
+    Missing,
+}
+
+impl PseudoAln {
+    /// Whether the record aligned to any targets.
+    ///
+    /// `ones` is used if it is known and `ones_names` otherwise.
+    ///
+    /// ## Usage
+    ///
+    /// ```rust
+    /// use ahda::Hits;
+    /// use ahda::PseudoAln;
+    ///
+    /// let unaligned = PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![]), query_name: None };
+    /// let named = PseudoAln{ ones_names: Some(vec![b"chr.fasta".to_vec()]), query_id: Some(1), ones: None, query_name: None };
+    /// let unknown = PseudoAln{ ones_names: None, query_id: Some(2), ones: None, query_name: None };
+    ///
+    /// assert_eq!(unaligned.hits(), Hits::Unaligned);
+    /// assert_eq!(named.hits(), Hits::Aligned);
+    /// assert_eq!(unknown.hits(), Hits::Missing);
+    /// ```
+    ///
+    pub fn hits(
+        &self,
+    ) -> Hits {
+        let is_empty = match (&self.ones, &self.ones_names) {
+            (Some(ones), _) => ones.is_empty(),
+            (None, Some(names)) => names.is_empty(),
+            (None, None) => return Hits::Missing,
+        };
+        if is_empty { Hits::Unaligned } else { Hits::Aligned }
+    }
+}
+
+/// Check if the data in [Read] starts with the .ahda [MAGIC] bytes.
 ///
-/// Errors if the input files contain duplicated queries. In this case, use a
-/// set operation to merge the files with duplicates first.
+/// Consumes up to 4 bytes from `conn` and does not decode the rest of the
+/// [FileHeader], so this is cheap enough to use for guessing the input type.
 ///
 /// ## Usage
 ///
 /// ```rust
-/// use ahda::{concatenate_from_read_to_write, decode_from_read, encode_to_write};
+/// use ahda::{encode_to_write, is_ahda_file};
 /// use ahda::EncodeOpts;
 /// use ahda::PseudoAln;
-/// use std::io::{Cursor, Seek};
+/// use std::io::Cursor;
 ///
-/// // Set up mock inputs
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
-/// let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
-/// let name = "ERR4035126".as_bytes().to_vec();
+/// let targets = vec!["chr.fasta".as_bytes().to_vec()];
+/// let data = vec![PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None }];
 ///
-/// let data_1 = vec![
-///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("ERR4035126.1".as_bytes().to_vec()) },
-///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![0]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) },
-/// ];
-/// let data_2 = vec![
-///     PseudoAln{ones_names: Some(vec!["plasmid.fasta".as_bytes().to_vec()]),  query_id: Some(3), ones: Some(vec![1]), query_name: Some("ERR4035126.7543".as_bytes().to_vec()) },
-///     PseudoAln{ones_names: Some(vec![]),  query_id: Some(4), ones: Some(vec![]), query_name: Some("ERR4035126.16".as_bytes().to_vec()) },
-/// ];
+/// let mut encoded: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// encode_to_write(&targets, &[], &data, &mut encoded, EncodeOpts::default()).unwrap();
 ///
-/// let mut data_bytes_1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// let mut data_bytes_2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
-/// opts.encode_query_names = true;
+/// let mut plaintext: Cursor<Vec<u8>> = Cursor::new(b"0 0\n".to_vec());
 ///
-/// // Encode the mock inputs to data_bytes_1 and data_bytes_2.
-/// encode_to_write(&targets, &queries, &data_1, &mut data_bytes_1, opts.clone()).unwrap();
-/// encode_to_write(&targets, &queries, &data_2, &mut data_bytes_2, opts).unwrap();
+/// assert!(is_ahda_file(&mut Cursor::new(encoded.get_ref())));
+/// assert!(!is_ahda_file(&mut plaintext));
+/// ```
 ///
-/// data_bytes_1.rewind();
-/// data_bytes_2.rewind();
+pub fn is_ahda_file<R: Read>(
+    conn: &mut R,
+) -> bool {
+    let mut bytes: [u8; 4] = [0; 4];
+    conn.read_exact(&mut bytes).is_ok() && bytes == MAGIC
+}
+
+/// Check if the file at `path` is an .ahda file.
 ///
-/// // Concatenate the data
-/// let mut inputs = vec![data_bytes_1, data_bytes_2];
-/// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// Reads only the first 4 bytes, see [is_ahda_file].
 ///
-/// concatenate_from_read_to_write(&mut inputs, &mut output).unwrap();
-/// output.rewind();
+/// ## Errors and panics
 ///
-/// // output contains the alignments from data_1 and data_2
-/// let (file_header, _file_flags, data_both) = decode_from_read(&mut output).unwrap();
+/// Errors if the file can't be opened.
 ///
-/// assert_eq!(data_both[0..2], data_1);
-/// assert_eq!(data_both[2..4], data_2);
-/// ```
-pub fn concatenate_from_read_to_write<R: Read, W: Write>(
-    conns: &mut [R],
-    conn_out: &mut W,
-) -> Result<(), E> {
-    assert!(!conns.is_empty());
-
-    let headers_flags = conns.iter_mut().map(|conn_in| {
-        let header = read_file_header(conn_in).unwrap();
-        let flags = read_file_flags(&header, conn_in).unwrap();
-        (header, flags)
-    }).collect::<Vec<(FileHeader, FileFlags)>>();
-
-    let mut n_queries = 0_u32;
-    let n_targets = headers_flags[0].0.n_targets;
-    let target_names = headers_flags[0].1.target_names.clone();
-    let query_name = headers_flags[0].1.query_name.clone();
-    let fields_present = headers_flags[0].0.fields_present;
-
-    headers_flags.iter().try_for_each(|(header, flags)| {
-        n_queries += header.n_queries;
-        if (n_targets != header.n_targets) || (target_names != flags.target_names) || (fields_present != header.fields_present) {
-            Err(Box::new(errors::IncompatibleFileHeadersErr{}))
-        } else {
-            Ok(())
-        }
-    })?;
-
-    let (mut new_header, new_flags) = build_file_header_and_flags(&target_names, n_queries as usize, &query_name, &MetadataCompression::default())?;
-    new_header.fields_present = headers_flags[0].0.fields_present;
-    let new_flags_bytes = encode_file_flags(&new_flags, &MetadataCompression::from_u8(new_header.metadata_compression)?)?;
-    let new_header_bytes = encode_file_header(&new_header)?;
-    conn_out.write_all(&new_header_bytes)?;
-    conn_out.write_all(&new_flags_bytes)?;
-
-    let mut seen_query_ids: std::collections::HashSet<u32> = HashSet::with_capacity(new_header.n_queries as usize);
-    let ret: Result<(), E> = conns.iter_mut().try_for_each(|conn_in| {
-        let (block_header, block_flags) = headers::block::read_block_header_and_flags(conn_in)?;
-        let bytes = headers::block::encode_block_header_and_flags(&block_header, &block_flags)?;
-        let query_ids = block_flags.query_ids.unwrap();
-        query_ids.into_iter().try_for_each(|id| {
-            if !seen_query_ids.insert(id) {
-                return Err(Box::new(errors::DuplicatedQueriesErr{}))
-            }
-            Ok(())
-        })?;
-        conn_out.write_all(&bytes)?;
-        std::io::copy(conn_in, conn_out)?;
-        Ok(())
-    });
-    ret?;
-    conn_out.flush()?;
-    Ok(())
+pub fn sniff<P: AsRef<std::path::Path>>(
+    path: P,
+) -> Result<bool, E> {
+    let mut conn = std::fs::File::open(path)?;
+    Ok(is_ahda_file(&mut conn))
 }
 
-/// Convert plain text data from [Read] to plain text data to [Write].
-///
-/// Can read and write to any format supported by [Format].
+/// Read only the [FileHeader] and [FileFlags] of the .ahda file at `path`.
 ///
-/// ## Usage
+/// Reads the 32 header bytes and the `flags_len` bytes of the flags that
+/// follow them without buffering, so none of the blocks are read. Use this
+/// instead of a [Decoder](decoder::Decoder) when only the number of queries
+/// and targets or the target names are needed.
 ///
-/// ```rust
-/// use ahda::convert_from_read_to_write;
-/// use ahda::Format;
-/// use std::io::Cursor;
+/// Files in a zstd-seekable container are decompressed until the end of the
+/// flags. Like in [Decoder](decoder::Decoder), the returned header then has the
+/// [FILE_IS_ZSTD_SEEKABLE](compression::FILE_IS_ZSTD_SEEKABLE) bit cleared.
+/// Files whose blocks split their targets across sub-blocks keep the
+/// [FILE_HAS_TARGET_CHUNKS](compression::FILE_HAS_TARGET_CHUNKS) bit, see
+/// [target_chunks].
 ///
-/// // Mock themisto formatted data
+/// ## Errors and panics
 ///
-/// // Have this input
-/// //   3 0 2
-/// //   0 2
-/// //   4 0 1 2
-/// //   2
+/// Errors if the file can't be opened, if it's not an .ahda file, if the
+/// flags can't be decoded, and with [MissingFeatureErr](errors::MissingFeatureErr)
+/// if the file is in a zstd-seekable container and ahda was built without the
+/// `zstd` feature.
 ///
-/// let mut input_bytes: Vec<u8> = Vec::new();
-/// input_bytes.append(&mut b"3 0 2\n".to_vec());
-/// input_bytes.append(&mut b"0 2\n".to_vec());
-/// input_bytes.append(&mut b"4 0 1 2\n".to_vec());
-/// input_bytes.append(&mut b"2\n".to_vec());
-/// let mut input: Cursor<Vec<u8>> = Cursor::new(input_bytes);
+/// ## Usage
 ///
-/// // Mock inputs
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
-/// let queries = vec![b"1".to_vec(), b"2".to_vec(), b"651903".to_vec(), b"7543".to_vec(), b"16".to_vec()];
-/// let name = "sample".as_bytes().to_vec();
+/// ```rust
+/// use ahda::{encode_to_write, read_header_only};
+/// use ahda::EncodeOpts;
 ///
-/// // Convert to metagraph format
-/// let out_format = Format::Metagraph;
-/// let mut output: Vec<u8> = Vec::new();
-/// let mut it = queries.into_iter();
-/// let mut t_it = targets.into_iter();
-/// convert_from_read_to_write(Some(&mut t_it), Some(&mut it), &name, out_format, &mut input, &mut output).unwrap();
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &[b"r1".to_vec()], &[], &mut bytes, EncodeOpts::default()).unwrap();
 ///
-/// // Expect to get this output:
-/// //   3    7543    chr.fasta:virus.fasta
-/// //   0    1       virus.fasta
-/// //   4    16      chr.fasta:plasmid.fasta:virus.fasta
-/// //   2    651903
-/// //
-/// let mut expected: Vec<u8> = Vec::new();
-/// expected.append(&mut b"3\t7543\tchr.fasta:virus.fasta\n".to_vec());
-/// expected.append(&mut b"0\t1\tvirus.fasta\n".to_vec());
-/// expected.append(&mut b"4\t16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
-/// expected.append(&mut b"2\t651903\t\n".to_vec());
+/// let path = std::env::temp_dir().join(format!("ahda-read-header-only-doc-{}.ahda", std::process::id()));
+/// std::fs::write(&path, &bytes).unwrap();
 ///
-/// assert_eq!(output, expected);
+/// let (header, flags) = read_header_only(&path).unwrap();
+/// assert_eq!(header.n_targets, 2);
+/// assert_eq!(header.n_queries, 1);
+/// assert_eq!(flags.target_names, targets);
+/// # std::fs::remove_file(&path).unwrap();
 /// ```
 ///
-pub fn convert_from_read_to_write<R: Read, W: Write, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
-    targets: Option<&mut T>,
-    queries: Option<&mut Q>,
-    sample_name: &[u8],
-    format: Format,
-    conn_in: &mut R,
-    conn_out: &mut W,
-) -> Result<(), E> {
-    let mut reader = crate::parser::Parser::new(conn_in, queries, targets)?;
-    let n_queries = reader.len();
-
-    let targets = reader.get_targets().unwrap();
-    let mut writer = crate::printer::Printer::new(&mut reader, &targets, sample_name, n_queries, format);
-
-    for record in writer.by_ref() {
-        conn_out.write_all(&record)?;
+pub fn read_header_only<P: AsRef<std::path::Path>>(
+    path: P,
+) -> Result<(FileHeader, FileFlags), E> {
+    let mut conn = std::fs::File::open(path)?;
+    let header = read_file_header(&mut conn)?;
+    if header.is_zstd_seekable() {
+        #[cfg(feature = "zstd")]
+        {
+            let header = FileHeader{ fields_present: header.fields_present & !compression::FILE_IS_ZSTD_SEEKABLE, ..header };
+            let mut conn = compression::zstd_seekable::container_reader(conn)?;
+            let flags = headers::file::read_file_flags_any_layout(&header, &mut conn)?;
+            return Ok((header, flags))
+        }
+        #[cfg(not(feature = "zstd"))]
+        return Err(Box::new(errors::MissingFeatureErr{ feature: "zstd" }))
     }
-    Ok(())
+    let flags = headers::file::read_file_flags_any_layout(&header, &mut conn)?;
+    Ok((header, flags))
 }
 
-/// Encode from memory to something that implements [Write].
+/// Change the [FileFlags] of the .ahda file at `path` without re-encoding it.
 ///
-/// ## Errors and panics
+/// Calls `update` on the stored flags, eg. to fix the sample name in
+/// `query_name` or to add an extension, and writes the result back. If the
+/// new flags fit in the space of the old flags, only the flags are
+/// overwritten in place. Shorter gzipped flags are padded inside the gzip
+/// header and shorter uncompressed flags with the extension
+/// [FLAGS_PADDING_KEY]. Otherwise the file is rewritten to `<path>.tmp` with
+/// the new flags followed by the blocks copied as is, and renamed over
+/// `path`.
 ///
-/// Panics if the input `records` is empty
+/// Returns true if the flags were updated in place.
 ///
 /// ## Usage
+///
 /// ```rust
-/// use ahda::{encode_to_write, decode_from_read};
+/// use ahda::{encode_to_write, read_header_only, update_flags};
 /// use ahda::EncodeOpts;
-/// use ahda::PseudoAln;
-/// use std::io::{Cursor, Seek};
-///
-/// // Mock data
-/// let data = vec![
-///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("ERR4035126.1".as_bytes().to_vec()) },
-///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![0]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) },
-/// ];
 ///
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
-/// let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
-/// let name = "ERR4035126".as_bytes().to_vec();
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
 /// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
-/// opts.encode_query_names = true;
+/// opts.accession = b"sample_with_a_typo".to_vec();
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &[b"r1".to_vec()], &[], &mut bytes, opts).unwrap();
 ///
-/// // Encode to `output`
+/// let path = std::env::temp_dir().join(format!("ahda-update-flags-doc-{}.ahda", std::process::id()));
+/// std::fs::write(&path, &bytes).unwrap();
+///
+/// update_flags(&path, |flags| { flags.query_name = b"sample".to_vec(); Ok(()) }).unwrap();
+/// let (_, flags) = read_header_only(&path).unwrap();
+/// assert_eq!(flags.query_name, b"sample".to_vec());
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+///
+/// ## Errors and panics
+/// Errors with the error returned by `update`, with
+/// [TargetCountChangedErr](errors::TargetCountChangedErr) if `update`
+/// changed the number of target names, and with
+/// [ZstdContainerErr](errors::ZstdContainerErr) if the file is in a
+/// zstd-seekable container. The file is not changed if any of these occur.
+///
+pub fn update_flags<P: AsRef<std::path::Path>, F: FnOnce(&mut FileFlags) -> Result<(), E>>(
+    path: P,
+    update: F,
+) -> Result<bool, E> {
+    use std::io::Seek;
+
+    let path = path.as_ref();
+    let mut conn = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let mut header = read_file_header(&mut conn)?;
+    let mut flags = headers::file::read_file_flags_any_layout(&header, &mut conn)?;
+    remove_flags_padding(&mut flags);
+    update(&mut flags)?;
+    remove_flags_padding(&mut flags);
+    if flags.target_names.len() != header.n_targets as usize {
+        return Err(Box::new(errors::TargetCountChangedErr{ n_targets: header.n_targets, got: flags.target_names.len() }))
+    }
+
+    let compression = MetadataCompression::from_u8(header.metadata_compression)?;
+    let flags_bytes = encode_file_flags(&flags, &compression)?;
+    if let Some(bytes) = fit_file_flags(&flags, &flags_bytes, &compression, header.flags_len as usize)? {
+        conn.seek(std::io::SeekFrom::Start(32))?;
+        conn.write_all(&bytes)?;
+        conn.flush()?;
+        return Ok(true)
+    }
+
+    let tmp_path = std::path::PathBuf::from(path.to_string_lossy().to_string() + ".tmp");
+    let mut conn_out = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+    header.flags_len = flags_bytes.len() as u64;
+    conn_out.write_all(&encode_file_header(&header)?)?;
+    conn_out.write_all(&flags_bytes)?;
+    std::io::copy(&mut conn, &mut conn_out)?;
+    conn_out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(false)
+}
+
+/// Key of the extension that pads the file flags rewritten by [update_flags].
+pub const FLAGS_PADDING_KEY: &[u8] = b"ahda.padding";
+
+fn remove_flags_padding(
+    flags: &mut FileFlags,
+) {
+    if let Some(extensions) = flags.extensions.as_mut() {
+        extensions.remove(FLAGS_PADDING_KEY);
+    }
+    flags.extensions = flags.extensions.take().filter(|x| !x.is_empty());
+}
+
+/// Encoded `flags` padded to exactly `len` bytes, if possible.
+///
+/// `flags_bytes` are the encoded flags without padding.
+fn fit_file_flags(
+    flags: &FileFlags,
+    flags_bytes: &[u8],
+    compression: &MetadataCompression,
+    len: usize,
+) -> Result<Option<Vec<u8>>, E> {
+    if flags_bytes.len() == len {
+        return Ok(Some(flags_bytes.to_vec()))
+    }
+    match compression {
+        MetadataCompression::Flate2 => Ok(compression::gzwrapper::pad_deflated(flags_bytes, len)),
+        // Trailing bytes would be decoded as unknown fields, so the padding
+        // is an extension whose size is adjusted until the flags fit
+        MetadataCompression::BincodeStandard => {
+            let mut padded = flags.clone();
+            let mut encode_padded = |n_bytes: usize| -> Result<Vec<u8>, E> {
+                padded.extensions.get_or_insert_with(Default::default).insert(FLAGS_PADDING_KEY, vec![0; n_bytes]);
+                encode_file_flags(&padded, compression)
+            };
+            let Some(guess) = len.checked_sub(encode_padded(0)?.len()) else {
+                return Ok(None)
+            };
+            // The length prefix of the padding takes up to a few bytes
+            for n_bytes in (guess.saturating_sub(8)..=guess).rev() {
+                let bytes = encode_padded(n_bytes)?;
+                if bytes.len() == len {
+                    return Ok(Some(bytes))
+                }
+            }
+            Ok(None)
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Sort the block flags of the .ahda record in `conn_in` by query id and write the result to `conn_out`.
+///
+/// Only the block flags are rewritten, see
+/// [BlockFlags::sort_by_query_id]; the bitmaps do not depend on the order
+/// of the records and are copied as is. Every block whose ids follow the
+/// ids of the previous block is marked with
+/// [BLOCK_QUERY_IDS_SORTED](compression::BLOCK_QUERY_IDS_SORTED), so that
+/// [BlockIndex](decoder::block_index::BlockIndex) can binary search records
+/// from older versions of ahda or other encoders.
+///
+/// Returns the number of blocks whose flags were reordered.
+///
+/// ## Errors and panics
+/// Errors with [ZstdContainerErr](errors::ZstdContainerErr) if the record is
+/// in a zstd-seekable container, with [TargetChunksErr](errors::TargetChunksErr)
+/// if its blocks split their targets across sub-blocks, and if a block can't
+/// be read.
+///
+pub fn sort_block_flags<R: Read, W: Write>(
+    conn_in: &mut R,
+    conn_out: &mut W,
+) -> Result<usize, E> {
+    let header = read_file_header(conn_in)?;
+    if header.is_zstd_seekable() {
+        return Err(Box::new(errors::ZstdContainerErr{}))
+    }
+    if header.has_target_chunks() {
+        return Err(Box::new(errors::TargetChunksErr{}))
+    }
+    let flags_bytes = headers::read_len_bytes(conn_in, header.flags_len)?;
+    let flags = headers::file::decode_file_flags(&flags_bytes, &MetadataCompression::from_u8(header.metadata_compression)?)?;
+    output::write_all_vectored(conn_out, &[&encode_file_header(&header)?, &flags_bytes])?;
+
+    let dictionary = compression::flags_dictionary::FlagsDictionary::from_flags(&flags);
+    let mut last_query_id: Option<u32> = None;
+    let mut n_sorted = 0_usize;
+    loop {
+        // Records end after the file flags or the last block
+        let mut first_byte: Vec<u8> = Vec::with_capacity(1);
+        conn_in.by_ref().take(1).read_to_end(&mut first_byte)?;
+        if first_byte.is_empty() {
+            break
+        }
+        let mut conn_block = std::io::Cursor::new(first_byte).chain(conn_in.by_ref());
+
+        let mut block_header = headers::block::read_block_header(&mut conn_block)?;
+        let flags_bytes = headers::read_len_bytes(&mut conn_block, block_header.flags_len)?;
+        let contents = headers::read_len_bytes(&mut conn_block, block_header.block_len as u64)?;
+        let mut block_flags = headers::block::decode_block_flags_with_dictionary(&flags_bytes, &block_header, dictionary.as_ref(), &mut Vec::new())?;
+        let reordered = block_flags.sort_by_query_id();
+
+        let query_ids = block_flags.query_ids.as_ref().ok_or(errors::MissingBlockFieldsErr{})?;
+        let follows_previous = query_ids.first().is_some_and(|first| last_query_id.is_none_or(|last| *first > last));
+        last_query_id = query_ids.last().copied().or(last_query_id);
+        if follows_previous {
+            block_header.placeholder1 |= compression::BLOCK_QUERY_IDS_SORTED;
+        } else {
+            block_header.placeholder1 &= !compression::BLOCK_QUERY_IDS_SORTED;
+        }
+
+        if !reordered {
+            output::write_all_vectored(conn_out, &[&headers::block::encode_block_header(&block_header)?, &flags_bytes, &contents])?;
+            continue
+        }
+        let header_and_flags = if block_header.placeholder1 & compression::BLOCK_FLAGS_RAW != 0 {
+            block_header.placeholder1 = (block_header.placeholder1 & !compression::BLOCK_QUERY_IDS_U16) | block_flags.storage_flags();
+            let flags_bytes = headers::block::encode_block_flags_with_header(&block_flags, &block_header, None)?;
+            block_header.flags_len = flags_bytes.len() as u64;
+            [headers::block::encode_block_header(&block_header)?, flags_bytes].concat()
+        } else {
+            headers::block::encode_block_header_and_flags_with_dictionary(&block_header, &block_flags, dictionary.as_ref())?
+        };
+        output::write_all_vectored(conn_out, &[&header_and_flags, &contents])?;
+        n_sorted += 1;
+    }
+    conn_out.flush()?;
+    Ok(n_sorted)
+}
+
+/// Read groupings of the target sequences from a tab-separated table.
+///
+/// The first line names the levels and the following lines assign each target
+/// sequence to a group at each level:
+///
+/// ```text
+/// target         genome    species
+/// chr.fasta      ecoli1    E. coli
+/// plasmid.fasta  ecoli1    E. coli
+/// virus.fasta    phage1    Phage
+/// ```
+///
+/// The name of the first column is ignored. Group indexes are assigned in the
+/// order that the groups first appear in the table.
+///
+/// ## Errors and panics
+///
+/// Errors with [UnknownTargetErr](errors::UnknownTargetErr) if the table
+/// contains a target that is not in `targets`, and with
+/// [InvalidTargetGroupsErr](errors::InvalidTargetGroupsErr) if a line has the
+/// wrong number of columns or a target in `targets` is not assigned to a group.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::read_target_groups;
+/// use std::io::Cursor;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+/// let mut table = Cursor::new(b"target\tspecies\nchr.fasta\tE. coli\nplasmid.fasta\tE. coli\nvirus.fasta\tPhage\n".to_vec());
+///
+/// let groups = read_target_groups(&targets, &mut table).unwrap();
+///
+/// assert_eq!(groups[0].level, b"species".to_vec());
+/// assert_eq!(groups[0].group_names, vec![b"E. coli".to_vec(), b"Phage".to_vec()]);
+/// assert_eq!(groups[0].membership, vec![0, 0, 1]);
+/// ```
+///
+pub fn read_target_groups<R: Read>(
+    targets: &[Vec<u8>],
+    conn: &mut R,
+) -> Result<Vec<TargetGroups>, E> {
+    let mut bytes: Vec<u8> = Vec::new();
+    conn.read_to_end(&mut bytes)?;
+
+    let mut lines = bytes.split(|x| *x == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line)).filter(|line| !line.is_empty());
+    let Some(header_line) = lines.next() else {
+        return Ok(Vec::new())
+    };
+
+    let t_names: indexmap::IndexSet<&[u8]> = targets.iter().map(|x| x.as_slice()).collect();
+    let mut groups: Vec<(TargetGroups, indexmap::IndexSet<Vec<u8>>)> = header_line.split(|x| *x == b'\t').skip(1).map(|level| {
+        (TargetGroups{ level: level.to_vec(), group_names: Vec::new(), membership: vec![u32::MAX; targets.len()] }, indexmap::IndexSet::new())
+    }).collect();
+
+    lines.try_for_each(|line| {
+        let fields: Vec<&[u8]> = line.split(|x| *x == b'\t').collect();
+        if fields.len() != groups.len() + 1 {
+            return Err(Box::new(errors::InvalidTargetGroupsErr{}) as E)
+        }
+        let target_idx = t_names.get_index_of(fields[0]).ok_or(errors::UnknownTargetErr{})?;
+        groups.iter_mut().zip(fields.iter().skip(1)).for_each(|((level, names), group_name)| {
+            let (group_idx, _) = names.insert_full(group_name.to_vec());
+            level.membership[target_idx] = group_idx as u32;
+        });
+        Ok(())
+    })?;
+
+    groups.into_iter().map(|(mut level, names)| {
+        level.group_names = names.into_iter().collect();
+        level.validate(targets.len())?;
+        Ok(level)
+    }).collect()
+}
+
+/// Read a tab-separated table that merges target sequences into fewer targets.
+///
+/// Each line assigns an alias, eg. a contig in a draft assembly, to the
+/// target it belongs to, eg. its genome. The table has no header line:
+///
+/// ```text
+/// contig_1    genome_a
+/// contig_2    genome_a
+/// contig_3    genome_b
+/// ```
+///
+/// The aliases are the target sequences given to the aligner. Encoding with
+/// the returned [TargetAliases] in [EncodeOpts] sets the bits at the target
+/// level, which reduces `n_targets` by the number of aliases per target.
+/// Target indexes are assigned in the order that the targets first appear in
+/// the table.
+///
+/// ## Errors and panics
+///
+/// Errors with [UnknownTargetErr](errors::UnknownTargetErr) if the table
+/// contains an alias that is not in `aliases`, and with
+/// [InvalidTargetAliasesErr](errors::InvalidTargetAliasesErr) if a line does
+/// not have two columns or an alias in `aliases` is not assigned to a target.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::{decode_from_read, encode_to_write, read_target_aliases};
+/// use ahda::EncodeOpts;
+/// use ahda::PseudoAln;
+/// use std::io::Cursor;
+///
+/// let contigs = vec![b"contig_1".to_vec(), b"contig_2".to_vec(), b"contig_3".to_vec()];
+/// let mut table = Cursor::new(b"contig_1\tgenome_a\ncontig_2\tgenome_a\ncontig_3\tgenome_b\n".to_vec());
+///
+/// let mut opts = EncodeOpts::default();
+/// opts.target_aliases = Some(read_target_aliases(&contigs, &mut table).unwrap());
+/// opts.store_target_aliases = true;
+///
+/// let data = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: None }];
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&contigs, &[], &data, &mut bytes, opts).unwrap();
+///
+/// let (header, flags, alns) = decode_from_read(&mut Cursor::new(bytes)).unwrap();
+///
+/// assert_eq!(header.n_targets, 2);
+/// assert_eq!(flags.target_names, vec![b"genome_a".to_vec(), b"genome_b".to_vec()]);
+/// assert_eq!(flags.target_aliases.unwrap().alias_names, contigs);
+/// assert_eq!(alns[0].ones, Some(vec![0]));
+/// ```
+///
+pub fn read_target_aliases<R: Read>(
+    aliases: &[Vec<u8>],
+    conn: &mut R,
+) -> Result<TargetAliases, E> {
+    let mut bytes: Vec<u8> = Vec::new();
+    conn.read_to_end(&mut bytes)?;
+
+    let a_names: indexmap::IndexSet<&[u8]> = aliases.iter().map(|x| x.as_slice()).collect();
+    let mut t_names: indexmap::IndexSet<Vec<u8>> = indexmap::IndexSet::new();
+    let mut membership: Vec<u32> = vec![u32::MAX; aliases.len()];
+
+    bytes.split(|x| *x == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line)).filter(|line| !line.is_empty()).try_for_each(|line| {
+        let fields: Vec<&[u8]> = line.split(|x| *x == b'\t').collect();
+        if fields.len() != 2 {
+            return Err(Box::new(errors::InvalidTargetAliasesErr{}) as E)
+        }
+        let alias_idx = a_names.get_index_of(fields[0]).ok_or(errors::UnknownTargetErr{})?;
+        let (target_idx, _) = t_names.insert_full(fields[1].to_vec());
+        membership[alias_idx] = target_idx as u32;
+        Ok(())
+    })?;
+
+    let out = TargetAliases{ target_names: t_names.into_iter().collect(), alias_names: aliases.to_vec(), membership };
+    out.validate(aliases.len())?;
+    Ok(out)
+}
+
+/// Read a tab-separated table of new names for target sequences.
+///
+/// Each line has the old name of a target and the name to use for it in the
+/// output. The table has no header line:
+///
+/// ```text
+/// NC_000913.3    E. coli K-12
+/// NC_002695.2    E. coli O157:H7
+/// ```
+///
+/// The returned pairs are used in
+/// [TargetSelection](printer::TargetSelection) to rename the targets when
+/// printing.
+///
+/// ## Errors and panics
+///
+/// Errors with [InvalidTargetRenamesErr](errors::InvalidTargetRenamesErr) if
+/// a line does not have two columns.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::read_target_renames;
+/// use std::io::Cursor;
+///
+/// let mut table = Cursor::new(b"chr.fasta\tchromosome\nplasmid.fasta\tpOXA-48\n".to_vec());
+/// let renames = read_target_renames(&mut table).unwrap();
+///
+/// assert_eq!(renames, vec![(b"chr.fasta".to_vec(), b"chromosome".to_vec()), (b"plasmid.fasta".to_vec(), b"pOXA-48".to_vec())]);
+/// ```
+///
+#[allow(clippy::type_complexity)]
+pub fn read_target_renames<R: Read>(
+    conn: &mut R,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, E> {
+    let mut bytes: Vec<u8> = Vec::new();
+    conn.read_to_end(&mut bytes)?;
+
+    bytes.split(|x| *x == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line)).filter(|line| !line.is_empty()).map(|line| {
+        let fields: Vec<&[u8]> = line.split(|x| *x == b'\t').collect();
+        if fields.len() != 2 {
+            return Err(Box::new(errors::InvalidTargetRenamesErr{}) as E)
+        }
+        Ok((fields[0].to_vec(), fields[1].to_vec()))
+    }).collect()
+}
+
+/// Merge compressed data by concatenating all blocks.
+///
+/// This simply appends the blocks in input order using [std::io::copy], it does
+/// not check or modify the contents of the block header, block flags, or the
+/// block data.
+///
+/// The file headers and flags of all inputs are read and checked before
+/// anything is written. After that each input is read once, one block at a
+/// time, so the memory use doesn't depend on the size of the inputs.
+///
+/// Updates the `n_queries` and `flags_len` fields in [FileHeader] to match the
+/// new data.
+///
+/// Retains the `query_name`, `target_names`, `target_groups`, and `target_aliases` fields for
+/// [FileFlags] from the first input.
+///
+/// ## Errors and panics
+///
+/// Errors with [IncompatibleInputsErr](errors::IncompatibleInputsErr) listing
+/// every [Incompatibility] if the [file headers](FileHeader) have a different
+/// file format, bitmap type, stored fields, number of targets or target
+/// sequence names than the first input, or if the inputs were aligned against
+/// different [panels](panel::Panel).
+///
+/// Errors if the input files contain duplicated queries. In this case, use a
+/// set operation to merge the files with duplicates first, or renumber the
+/// queries with [CatOpts::renumber_queries] if the inputs are different reads
+/// that were numbered from 0.
+///
+/// Errors with [NoInputsErr](errors::NoInputsErr) if `conns` is empty.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::{try_concatenate_from_read_to_write, decode_from_read, encode_to_write};
+/// use ahda::EncodeOpts;
+/// use ahda::PseudoAln;
+/// use std::io::{Cursor, Seek};
+///
+/// // Set up mock inputs
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
+/// let name = "ERR4035126".as_bytes().to_vec();
+///
+/// let data_1 = vec![
+///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("ERR4035126.1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![0]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) },
+/// ];
+/// let data_2 = vec![
+///     PseudoAln{ones_names: Some(vec!["plasmid.fasta".as_bytes().to_vec()]),  query_id: Some(3), ones: Some(vec![1]), query_name: Some("ERR4035126.7543".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: Some(vec![]),  query_id: Some(4), ones: Some(vec![]), query_name: Some("ERR4035126.16".as_bytes().to_vec()) },
+/// ];
+///
+/// let mut data_bytes_1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut data_bytes_2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = true;
+///
+/// // Encode the mock inputs to data_bytes_1 and data_bytes_2.
+/// encode_to_write(&targets, &queries, &data_1, &mut data_bytes_1, opts.clone()).unwrap();
+/// encode_to_write(&targets, &queries, &data_2, &mut data_bytes_2, opts).unwrap();
+///
+/// data_bytes_1.rewind();
+/// data_bytes_2.rewind();
+///
+/// // Concatenate the data
+/// let mut inputs = vec![data_bytes_1, data_bytes_2];
 /// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// encode_to_write(&targets, &queries, &data, &mut output, opts).unwrap();
 ///
-/// // `output` can be decoded to get the original data back
+/// try_concatenate_from_read_to_write(&mut inputs, &mut output).unwrap();
 /// output.rewind();
-/// let (header, flags, decoded_data) = decode_from_read(&mut output).unwrap();
 ///
-/// assert_eq!(decoded_data, data);
+/// // output contains the alignments from data_1 and data_2
+/// let (file_header, _file_flags, data_both) = decode_from_read(&mut output).unwrap();
+///
+/// assert_eq!(data_both[0..2], data_1);
+/// assert_eq!(data_both[2..4], data_2);
 /// ```
+pub fn try_concatenate_from_read_to_write<R: Read, W: Write>(
+    conns: &mut [R],
+    conn_out: &mut W,
+) -> Result<(), E> {
+    try_concatenate_from_read_to_write_with_opts(conns, conn_out, &CatOpts::default())
+}
+
+/// Options to [try_concatenate_from_read_to_write_with_opts].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatOpts {
+    /// Check that the inputs were aligned against the same [Panel](panel::Panel).
+    pub check_panel: bool,
+    /// Store this [Manifest](manifest::Manifest) of the inputs in the output
+    /// file flags. A manifest copied from the first input is dropped if this
+    /// is None, since it would not describe the output.
+    pub manifest: Option<manifest::Manifest>,
+    /// Add the total number of queries in the earlier inputs to the query
+    /// ids of each input, so that inputs that number their queries from 0
+    /// don't collide. Inputs with fewer queries in their [FileHeader] than
+    /// their largest query id, eg. 0 if the number is not known, count as
+    /// ending after the largest query id. The output is
+    /// [Roaring64](compression::BitmapType::Roaring64) if the renumbered
+    /// ids may not fit in a roaring32 bitmap. The blocks of all but the
+    /// first input, or of all inputs if the bitmap type changes, are
+    /// decoded and encoded again, see
+    /// [renumber_block](compression::renumber_block).
+    pub renumber_queries: bool,
+    /// Concatenate inputs with different targets. The output has the
+    /// targets of all inputs, see [TargetDict::merge](headers::file::TargetDict::merge),
+    /// and the blocks of inputs whose targets differ from the output are
+    /// decoded and encoded again, see [remap_block](compression::remap_block).
+    /// The target groups, aliases, and panel of the first input are dropped
+    /// if the inputs add targets to it.
+    pub merge_targets: bool,
+}
+
+impl Default for CatOpts {
+    /// Default to these values:
+    /// ```rust
+    /// let mut opts = ahda::CatOpts::default();
+    /// opts.check_panel = true;
+    /// opts.manifest = None;
+    /// opts.renumber_queries = false;
+    /// opts.merge_targets = false;
+    /// # let expected = ahda::CatOpts::default();
+    /// # assert_eq!(opts, expected);
+    /// ```
+    ///
+    fn default() -> CatOpts {
+        CatOpts {
+            check_panel: true,
+            manifest: None,
+            renumber_queries: false,
+            merge_targets: false,
+        }
+    }
+}
+
+/// Merge compressed data by concatenating all blocks with options.
 ///
-pub fn encode_to_write<W: Write>(
-    targets: &[Vec<u8>],
-    queries: &[Vec<u8>],
-    records: &[PseudoAln],
+/// Same as [try_concatenate_from_read_to_write] but the checks can be
+/// changed with `opts`, see [CatOpts].
+pub fn try_concatenate_from_read_to_write_with_opts<R: Read, W: Write>(
+    conns: &mut [R],
     conn_out: &mut W,
-    opts: EncodeOpts,
+    opts: &CatOpts,
 ) -> Result<(), E> {
-    assert!(!records.is_empty());
-    let have_queries = !queries.is_empty();
+    if conns.is_empty() {
+        return Err(Box::new(errors::NoInputsErr{}))
+    }
 
-    let mut records_iter = records.iter().cloned();
-    let mut encoder = encoder::Encoder::new(&mut records_iter, targets, &opts.accession, queries.len());
-    if opts.encode_query_names && have_queries {
-        encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
-    } else {
-        encoder.set_fields_present(crate::MASK_QUERY_IDS);
+    let headers_flags = conns.iter_mut().map(|conn_in| {
+        let header = read_file_header(conn_in)?;
+        let flags = read_file_flags(&header, conn_in)?;
+        Ok((header, flags))
+    }).collect::<Result<Vec<(FileHeader, FileFlags)>, E>>()?;
+
+    let incompatibilities = concatenation_incompatibilities(&headers_flags, opts)?;
+    if !incompatibilities.is_empty() {
+        return Err(Box::new(errors::IncompatibleInputsErr{ incompatibilities }))
+    }
+
+    let mut n_queries = headers_flags.iter().try_fold(0_u32, |acc, (header, _)| acc.checked_add(header.n_queries)).ok_or(errors::QueryIndexOutOfRangeErr{})?;
+    if opts.renumber_queries && headers_flags.iter().any(|(header, _)| header.n_queries == 0) {
+        // The renumbered ids of the later inputs are not known before the blocks are read
+        n_queries = 0;
+    }
+    // Inputs with the same targets as the output have identity tables
+    let dicts: Vec<headers::file::TargetDict> = headers_flags.iter().map(|(_, flags)| headers::file::TargetDict::from_file_flags(flags)).collect();
+    let (merged_targets, tables) = headers::file::TargetDict::merge(&dicts.iter().collect::<Vec<&headers::file::TargetDict>>());
+    let remap: Vec<bool> = tables.iter().map(|table| !table.is_identity() || table.new_index.len() != merged_targets.target_names.len()).collect();
+    let target_names = &merged_targets.target_names;
+    let query_name = &headers_flags[0].1.query_name;
+
+    let (mut new_header, mut new_flags) = build_file_header_and_flags(target_names, n_queries as usize, query_name, &MetadataCompression::default())?;
+    new_header.fields_present = headers_flags[0].0.fields_present;
+    // The blocks are copied as is so they keep the bitmap type of the inputs
+    new_header.bitmap_type = headers_flags[0].0.bitmap_type;
+    if (opts.renumber_queries || remap[0]) && (n_queries == 0 || BitmapType::auto(target_names.len(), n_queries as usize) == BitmapType::Roaring64) {
+        // All blocks are promoted to roaring64 if the renumbered bits may not fit in 32 bits
+        new_header.bitmap_type = BitmapType::Roaring64.to_u16();
+    }
+    new_flags.extensions = headers_flags[0].1.extensions.clone();
+    if !remap[0] {
+        new_flags.target_groups = headers_flags[0].1.target_groups.clone();
+        new_flags.target_aliases = headers_flags[0].1.target_aliases.clone();
+    } else if let Some(extensions) = new_flags.extensions.as_mut() {
+        // The panel of the first input doesn't describe the merged targets
+        extensions.remove(panel::PANEL_KEY);
+    }
+    if let Some(manifest) = &opts.manifest {
+        new_flags.extensions.get_or_insert_default().insert_as(manifest::MANIFEST_KEY, manifest)?;
+    } else if let Some(extensions) = new_flags.extensions.as_mut() {
+        extensions.remove(manifest::MANIFEST_KEY);
+    }
+    new_flags.unknown_fields = headers_flags[0].1.unknown_fields.clone();
+    let new_flags_bytes = encode_file_flags(&new_flags, &MetadataCompression::from_u8(new_header.metadata_compression)?)?;
+    new_header.flags_len = new_flags_bytes.len() as u64;
+    let new_header_bytes = encode_file_header(&new_header)?;
+    output::write_all_vectored(conn_out, &[&new_header_bytes, &new_flags_bytes])?;
+
+    // The output keeps the flags dictionary of the first input, if any
+    let dictionaries: Vec<Option<compression::flags_dictionary::FlagsDictionary>> = headers_flags.iter().map(|(_, flags)| compression::flags_dictionary::FlagsDictionary::from_flags(flags)).collect();
+    let mut seen_query_ids: std::collections::HashSet<u32> = HashSet::with_capacity(new_header.n_queries as usize);
+    let file_bitmap_type = BitmapType::from_u16(new_header.bitmap_type)?;
+    // Query ids of the current input are shifted by `offset` if renumbering
+    let mut offset: u32 = 0;
+    for (input, (conn_in, dictionary)) in conns.iter_mut().zip(dictionaries.iter()).enumerate() {
+        // Inputs that don't know their number of queries end after their largest query id
+        let mut end = offset.checked_add(headers_flags[input].0.n_queries).ok_or(errors::QueryIndexOutOfRangeErr{})?;
+        let mut update_end = |query_ids: Option<&Vec<u32>>| -> Result<(), E> {
+            if let Some(max) = query_ids.filter(|_| opts.renumber_queries).and_then(|x| x.iter().max()) {
+                end = end.max(max.checked_add(1).ok_or(errors::QueryIndexOutOfRangeErr{})?);
+            }
+            Ok(())
+        };
+
+        if remap[input] || headers_flags[input].0.bitmap_type != new_header.bitmap_type || (opts.renumber_queries && offset > 0) {
+            let shift = if opts.renumber_queries { offset } else { 0 };
+            let mut cache = compression::dedup::BlockCache::new();
+            let mut buffers = compression::BlockBuffers::default();
+            buffers.set_flags_dictionary(dictionary.clone());
+            let mut bytes: Vec<u8> = Vec::new();
+            let mut first_block = true;
+            while let Some(mut block_header) = cache.try_read_block_into(conn_in, &mut bytes)? {
+                if first_block && input > 0 {
+                    // The ids of the previous input are not checked
+                    block_header.placeholder1 &= !compression::BLOCK_QUERY_IDS_SORTED;
+                }
+                let (block, block_flags) = if remap[input] {
+                    compression::remap_block(&bytes, &block_header, shift, &tables[input], new_header.n_targets, &file_bitmap_type, &mut buffers, dictionaries[0].as_ref())?
+                } else {
+                    compression::renumber_block(&bytes, &block_header, shift, new_header.n_targets, &file_bitmap_type, &mut buffers, dictionaries[0].as_ref())?
+                };
+                if first_block {
+                    let query_ids = block_flags.query_ids.as_ref().ok_or(errors::MissingBlockFieldsErr{})?;
+                    if !query_ids.iter().all(|id| seen_query_ids.insert(*id)) {
+                        return Err(Box::new(errors::DuplicatedQueriesErr{}))
+                    }
+                    first_block = false;
+                }
+                update_end(block_flags.query_ids.as_ref())?;
+                conn_out.write_all(&block)?;
+            }
+            offset = end;
+            continue
+        }
+
+        let mut first_block = true;
+        loop {
+            // Inputs end after the file flags or the last block
+            let mut first_byte: Vec<u8> = Vec::with_capacity(1);
+            conn_in.by_ref().take(1).read_to_end(&mut first_byte)?;
+            if first_byte.is_empty() {
+                break
+            }
+            let mut conn_block = std::io::Cursor::new(first_byte).chain(conn_in.by_ref());
+
+            let mut block_header = headers::block::read_block_header(&mut conn_block)?;
+            let flags_bytes = headers::read_len_bytes(&mut conn_block, block_header.flags_len)?;
+            let block_flags = headers::block::decode_block_flags_with_dictionary(&flags_bytes, &block_header, dictionary.as_ref(), &mut Vec::new())?;
+            if first_block {
+                let query_ids = block_flags.query_ids.as_ref().ok_or(errors::MissingBlockFieldsErr{})?;
+                if !query_ids.iter().all(|id| seen_query_ids.insert(*id)) {
+                    return Err(Box::new(errors::DuplicatedQueriesErr{}))
+                }
+                if input > 0 {
+                    // The ids of the previous input are not checked
+                    block_header.placeholder1 &= !compression::BLOCK_QUERY_IDS_SORTED;
+                }
+                first_block = false;
+            }
+
+            if dictionary == &dictionaries[0] {
+                let header_bytes = headers::block::encode_block_header(&block_header)?;
+                output::write_all_vectored(conn_out, &[&header_bytes, &flags_bytes])?;
+                if !opts.renumber_queries {
+                    std::io::copy(&mut conn_block, conn_out)?;
+                    break
+                }
+                // The next offset depends on the query ids of every block
+                update_end(block_flags.query_ids.as_ref())?;
+                std::io::copy(&mut conn_block.take(block_header.block_len as u64), conn_out)?;
+                continue
+            }
+            // Flags compressed with another dictionary are transcoded one block at a time
+            update_end(block_flags.query_ids.as_ref())?;
+            let bytes = headers::block::encode_block_header_and_flags_with_dictionary(&block_header, &block_flags, dictionaries[0].as_ref())?;
+            conn_out.write_all(&bytes)?;
+            std::io::copy(&mut conn_block.take(block_header.block_len as u64), conn_out)?;
+        }
+        offset = end;
+    }
+    conn_out.flush()?;
+    Ok(())
+}
+
+/// Merge compressed data by concatenating all blocks.
+///
+/// Same as [try_concatenate_from_read_to_write].
+#[deprecated(note = "use try_concatenate_from_read_to_write, which also reports every incompatible input")]
+pub fn concatenate_from_read_to_write<R: Read, W: Write>(
+    conns: &mut [R],
+    conn_out: &mut W,
+) -> Result<(), E> {
+    try_concatenate_from_read_to_write(conns, conn_out)
+}
+
+/// A difference between the [FileHeader] and [FileFlags] of an input and the first input.
+///
+/// Reported by [try_concatenate_from_read_to_write] in an
+/// [IncompatibleInputsErr](errors::IncompatibleInputsErr). `input` is the
+/// index of the input in `conns`.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Incompatibility {
+    /// Different `file_format` in [FileHeader].
+    FileFormat{ input: usize, expected: u8, got: u8 },
+    /// Different `bitmap_type` in [FileHeader].
+    BitmapType{ input: usize, expected: u16, got: u16 },
+    /// Different `fields_present` in [FileHeader].
+    FieldsPresent{ input: usize, expected: u16, got: u16 },
+    /// Different `n_targets` in [FileHeader].
+    NTargets{ input: usize, expected: u32, got: u32 },
+    /// Same number of targets but different `target_names` in [FileFlags],
+    /// `target` is the index of the first name that differs.
+    TargetNames{ input: usize, target: usize },
+    /// Different [Panel](panel::Panel) in [FileFlags], see [check_panels](panel::check_panels).
+    Panel{ input: usize, expected: panel::Panel, got: panel::Panel },
+}
+
+impl std::fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Incompatibility::FileFormat{ input, expected, got } => write!(f, "input {} has file format {}, expected {}", input, got, expected),
+            Incompatibility::BitmapType{ input, expected, got } => {
+                let name = |x: &u16| BitmapType::from_u16(*x).map(|x| x.to_string()).unwrap_or(x.to_string());
+                write!(f, "input {} has bitmap type {}, expected {}", input, name(got), name(expected))
+            },
+            Incompatibility::FieldsPresent{ input, expected, got } => write!(f, "input {} has fields {:#06x}, expected {:#06x}", input, got, expected),
+            Incompatibility::NTargets{ input, expected, got } => write!(f, "input {} has {} targets, expected {}", input, got, expected),
+            Incompatibility::TargetNames{ input, target } => write!(f, "input {} has a different name for target {}", input, target),
+            Incompatibility::Panel{ input, expected, got } => write!(f, "input {} was aligned against {}, expected {}", input, got, expected),
+        }
+    }
+}
+
+/// Every [Incompatibility] that prevents concatenating the inputs with `opts`.
+///
+/// The panels are compared only if `opts.check_panel` is true, and the
+/// targets only if `opts.merge_targets` is false.
+pub(crate) fn concatenation_incompatibilities(
+    headers_flags: &[(FileHeader, FileFlags)],
+    opts: &CatOpts,
+) -> Result<Vec<Incompatibility>, E> {
+    let mut incompatibilities = check_concatenable(headers_flags);
+    if opts.merge_targets {
+        incompatibilities.retain(|x| !matches!(x, Incompatibility::NTargets{ .. } | Incompatibility::TargetNames{ .. }));
+    }
+    if opts.check_panel {
+        let panels = headers_flags.iter().map(|(_, flags)| panel::Panel::from_flags(flags)).collect::<Result<Vec<Option<panel::Panel>>, E>>()?;
+        incompatibilities.extend(panel::check_panels(&panels));
+    }
+    Ok(incompatibilities)
+}
+
+/// Compare the headers and flags of each input to the first input.
+fn check_concatenable(
+    headers_flags: &[(FileHeader, FileFlags)],
+) -> Vec<Incompatibility> {
+    let Some((first_header, first_flags)) = headers_flags.first() else {
+        return Vec::new()
+    };
+    let mut incompatibilities: Vec<Incompatibility> = Vec::new();
+    for (input, (header, flags)) in headers_flags.iter().enumerate().skip(1) {
+        if header.file_format != first_header.file_format {
+            incompatibilities.push(Incompatibility::FileFormat{ input, expected: first_header.file_format, got: header.file_format });
+        }
+        if header.bitmap_type != first_header.bitmap_type {
+            incompatibilities.push(Incompatibility::BitmapType{ input, expected: first_header.bitmap_type, got: header.bitmap_type });
+        }
+        if header.fields_present != first_header.fields_present {
+            incompatibilities.push(Incompatibility::FieldsPresent{ input, expected: first_header.fields_present, got: header.fields_present });
+        }
+        if header.n_targets != first_header.n_targets {
+            incompatibilities.push(Incompatibility::NTargets{ input, expected: first_header.n_targets, got: header.n_targets });
+        } else if let Some(target) = flags.target_names.iter().zip(first_flags.target_names.iter()).position(|(x, y)| x != y) {
+            incompatibilities.push(Incompatibility::TargetNames{ input, target });
+        } else if flags.target_names.len() != first_flags.target_names.len() {
+            incompatibilities.push(Incompatibility::TargetNames{ input, target: flags.target_names.len().min(first_flags.target_names.len()) });
+        }
+    }
+    incompatibilities
+}
+
+/// Convert plain text data from [Read] to plain text data to [Write].
+///
+/// Can read and write to any format supported by [Format].
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::convert_from_read_to_write;
+/// use ahda::Format;
+/// use std::io::Cursor;
+///
+/// // Mock themisto formatted data
+///
+/// // Have this input
+/// //   3 0 2
+/// //   0 2
+/// //   4 0 1 2
+/// //   2
+///
+/// let mut input_bytes: Vec<u8> = Vec::new();
+/// input_bytes.append(&mut b"3 0 2\n".to_vec());
+/// input_bytes.append(&mut b"0 2\n".to_vec());
+/// input_bytes.append(&mut b"4 0 1 2\n".to_vec());
+/// input_bytes.append(&mut b"2\n".to_vec());
+/// let mut input: Cursor<Vec<u8>> = Cursor::new(input_bytes);
+///
+/// // Mock inputs
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+/// let queries = vec![b"1".to_vec(), b"2".to_vec(), b"651903".to_vec(), b"7543".to_vec(), b"16".to_vec()];
+/// let name = "sample".as_bytes().to_vec();
+///
+/// // Convert to metagraph format
+/// let out_format = Format::Metagraph;
+/// let mut output: Vec<u8> = Vec::new();
+/// let mut it = queries.into_iter();
+/// let mut t_it = targets.into_iter();
+/// convert_from_read_to_write(Some(&mut t_it), Some(&mut it), &name, out_format, &mut input, &mut output).unwrap();
+///
+/// // Expect to get this output:
+/// //   3    7543    chr.fasta:virus.fasta
+/// //   0    1       virus.fasta
+/// //   4    16      chr.fasta:plasmid.fasta:virus.fasta
+/// //   2    651903
+/// //
+/// let mut expected: Vec<u8> = Vec::new();
+/// expected.append(&mut b"3\t7543\tchr.fasta:virus.fasta\n".to_vec());
+/// expected.append(&mut b"0\t1\tvirus.fasta\n".to_vec());
+/// expected.append(&mut b"4\t16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
+/// expected.append(&mut b"2\t651903\t\n".to_vec());
+///
+/// assert_eq!(output, expected);
+/// ```
+///
+pub fn convert_from_read_to_write<R: Read, W: Write, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
+    targets: Option<&mut T>,
+    queries: Option<&mut Q>,
+    sample_name: &[u8],
+    format: Format,
+    conn_in: &mut R,
+    conn_out: &mut W,
+) -> Result<(), E> {
+    convert_stream(targets, queries, sample_name, format, &printer::TargetSelection::default(), None, CONVERT_CHUNK_SIZE, conn_in, conn_out)?;
+    Ok(())
+}
+
+/// Number of records that [convert_from_read_to_write] writes at a time.
+pub const CONVERT_CHUNK_SIZE: usize = 65536;
+
+/// Convert plain text data from [Read] to [Write] in chunks of records.
+///
+/// Parses and converts `chunk_size` records at a time and flushes `conn_out`
+/// after each chunk, so at most one chunk is held in memory and each record is
+/// written at most `chunk_size` records after it was read. Use a small
+/// `chunk_size` when the output is read while the input is still being
+/// written, eg. from a pipe. A `chunk_size` of 0 is treated as 1.
+///
+/// Only the targets in `selection` are written, see
+/// [TargetSelection](printer::TargetSelection). If `program` is given, it is
+/// recorded in the header of formats that have one, see
+/// [Program](printer::Program).
+///
+/// Returns the number of records that were converted.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::convert_stream;
+/// use ahda::Format;
+/// use ahda::printer::TargetSelection;
+///
+/// let mut input: &[u8] = b"0 0\n1 1 2\n2\n";
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+///
+/// let mut output: Vec<u8> = Vec::new();
+/// let n_records = convert_stream(Some(&mut targets.into_iter()), Some(&mut queries.into_iter()), b"sample", Format::Metagraph, &TargetSelection::default(), None, 2, &mut input, &mut output).unwrap();
+///
+/// assert_eq!(n_records, 3);
+/// assert_eq!(output, b"0\tr1\tchr.fasta\n1\tr2\tplasmid.fasta:virus.fasta\n2\tr3\t\n");
+/// ```
+///
+/// ## Errors and panics
+///
+/// Errors if the input can't be parsed, `selection` contains targets that
+/// are not in the input, or the output can't be written. The chunks before
+/// the failing record have already been written to `conn_out`.
+///
+#[allow(clippy::too_many_arguments)]
+pub fn convert_stream<R: Read, W: Write, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
+    targets: Option<&mut T>,
+    queries: Option<&mut Q>,
+    sample_name: &[u8],
+    format: Format,
+    selection: &printer::TargetSelection,
+    program: Option<&printer::Program>,
+    chunk_size: usize,
+    conn_in: &mut R,
+    conn_out: &mut W,
+) -> Result<usize, E> {
+    let have_queries = queries.is_some();
+    let mut reader = crate::parser::Parser::new(conn_in, queries, targets)?;
+    reader.fill_query_name(have_queries);
+    let n_queries = reader.len();
+
+    // Records come from the parser, the printer only formats them
+    let targets = reader.get_targets().unwrap();
+    let mut no_records = std::iter::empty::<PseudoAln>();
+    let mut writer = crate::printer::Printer::new(&mut no_records, &targets, sample_name, n_queries, format);
+    if !selection.is_empty() {
+        writer.select_targets(selection)?;
+    }
+    writer.set_program(program.cloned());
+
+    let chunk_size = chunk_size.max(1);
+    let mut chunk: Vec<u8> = writer.print_header().unwrap_or_default();
+    let mut n_records: usize = 0;
+    let mut in_chunk: usize = 0;
+    while let Some(record) = reader.try_next() {
+        writer.format_record(&record?, &mut chunk)?;
+        n_records += 1;
+        in_chunk += 1;
+        if in_chunk == chunk_size {
+            conn_out.write_all(&chunk)?;
+            conn_out.flush()?;
+            chunk.clear();
+            in_chunk = 0;
+        }
+    }
+    conn_out.write_all(&chunk)?;
+    conn_out.flush()?;
+    log::info!("converted {} records", n_records);
+
+    Ok(n_records)
+}
+
+/// Encode from memory to something that implements [Write].
+///
+/// Empty `records` are written as a file header and flags with no blocks.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::{encode_to_write, decode_from_read};
+/// use ahda::EncodeOpts;
+/// use ahda::PseudoAln;
+/// use std::io::{Cursor, Seek};
+///
+/// // Mock data
+/// let data = vec![
+///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("ERR4035126.1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![0]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) },
+/// ];
+///
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
+/// let name = "ERR4035126".as_bytes().to_vec();
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = true;
+///
+/// // Encode to `output`
+/// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// encode_to_write(&targets, &queries, &data, &mut output, opts).unwrap();
+///
+/// // `output` can be decoded to get the original data back
+/// output.rewind();
+/// let (header, flags, decoded_data) = decode_from_read(&mut output).unwrap();
+///
+/// assert_eq!(decoded_data, data);
+/// ```
+///
+pub fn encode_to_write<W: Write>(
+    targets: &[Vec<u8>],
+    queries: &[Vec<u8>],
+    records: &[PseudoAln],
+    conn_out: &mut W,
+    mut opts: EncodeOpts,
+) -> Result<(), E> {
+    if let Some(level) = opts.zstd_level.take() {
+        return encode_to_zstd_container(conn_out, level, |mut conn| encode_to_write(targets, queries, records, &mut conn, opts))
+    }
+
+    let have_queries = !queries.is_empty();
+
+    let mut records_iter = records.iter().cloned();
+    let mut encoder = encoder::Encoder::new(&mut records_iter, targets, &opts.accession, queries.len());
+    configure_encoder(&mut encoder, &opts, have_queries)?;
+
+    let bytes = encoder.encode_file_header_and_flags()?;
+    conn_out.write_all(&bytes)?;
+    for block in encoder.by_ref() {
+        conn_out.write_all(&block?)?;
+    }
+
+    Ok(())
+}
+
+/// Apply the options in `opts` that [Encoder](encoder::Encoder) handles.
+///
+/// Query names are stored only if `have_queries` is true.
+fn configure_encoder<I: Iterator<Item=PseudoAln>>(
+    encoder: &mut encoder::Encoder<'_, I>,
+    opts: &EncodeOpts,
+    have_queries: bool,
+) -> Result<(), E> {
+    if opts.encode_query_names && have_queries {
+        encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
+    } else {
+        encoder.set_fields_present(crate::MASK_QUERY_IDS);
+    }
+    if let Some(second_mates) = &opts.second_mates {
+        encoder.set_second_mates(second_mates.clone());
+    }
+    if let Some(fpr) = opts.query_filter_fpr {
+        encoder.set_query_filter(fpr)?;
+    }
+    if opts.target_summary {
+        encoder.set_target_summary();
+    }
+    if let Some(aliases) = &opts.target_aliases {
+        encoder.set_target_aliases(aliases.clone(), opts.store_target_aliases)?;
+    }
+    if !opts.target_groups.is_empty() {
+        encoder.set_target_groups(opts.target_groups.clone())?;
+    }
+    if let Some(width) = opts.target_chunks {
+        encoder.set_target_chunks(width)?;
+    }
+    if let Some(bitmap_type) = &opts.bitmap_type {
+        encoder.set_bitmap_type(bitmap_type.clone())?;
+    }
+    if let Some(max_hits) = opts.max_hits {
+        encoder.set_max_hits(max_hits)?;
+    }
+    if let Some(lanes) = &opts.lanes {
+        encoder.set_lanes(lanes)?;
+    }
+    if opts.store_panel || opts.index_version.is_some() {
+        encoder.set_panel(opts.index_version.clone())?;
+    }
+    encoder.set_compression_level(opts.compression_level)?;
+    encoder.set_inline_max_records(opts.inline_max_records);
+    if opts.metadata_compression == MetadataCompression::ZstdDictionary {
+        // Last so that the names are read ahead with the other settings in place
+        let trained = encoder.train_flags_dictionary(compression::flags_dictionary::DEFAULT_TRAINING_BLOCKS, compression::flags_dictionary::DEFAULT_DICTIONARY_SIZE)?;
+        if !trained {
+            log::warn!("too few query names to train a flags dictionary, using gzip");
+        }
+    }
+    Ok(())
+}
+
+/// Run `encode` on a zstd-seekable container around `conn_out`, see [EncodeOpts::zstd_level].
+fn encode_to_zstd_container<W: Write, F: FnOnce(&mut dyn Write) -> Result<(), E>>(
+    conn_out: &mut W,
+    level: i32,
+    encode: F,
+) -> Result<(), E> {
+    #[cfg(feature = "zstd")]
+    {
+        // Through `dyn Write` so that the encoders are instantiated once for any `W`
+        let conn: &mut dyn Write = conn_out;
+        let mut container = compression::zstd_seekable::SeekableWriter::new(conn, level)?;
+        encode(&mut container)?;
+        container.finish()?;
+        Ok(())
+    }
+    #[cfg(not(feature = "zstd"))]
+    {
+        let _ = (conn_out, level, encode);
+        Err(Box::new(crate::errors::MissingFeatureErr{ feature: "zstd" }))
+    }
+}
+
+/// Parse all plain-text pseudoalignments from [Read] and encode to memory.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::{encode_from_read, decode_from_read_to_write};
+/// use ahda::EncodeOpts;
+/// use ahda::Format;
+/// use std::io::{Cursor, Seek};
+///
+/// // Mock inputs
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
+/// let name = "sample".as_bytes().to_vec();
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = true;
+///
+/// // Have this input data:
+/// //   3    r7543    chr.fasta:virus.fasta
+/// //   0    r1       virus.fasta
+/// //   4    r16      chr.fasta:plasmid.fasta:virus.fasta
+/// //   2    r651903
+/// //
+/// let mut input_bytes: Vec<u8> = Vec::new();
+/// input_bytes.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
+/// input_bytes.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
+/// input_bytes.append(&mut b"4\tr16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
+/// input_bytes.append(&mut b"2\tr651903\t\n".to_vec());
+///
+/// let mut input: Cursor<Vec<u8>> = Cursor::new(input_bytes.clone());
+/// let mut it = queries.into_iter();
+/// let mut t_it = targets.into_iter();
+/// let output = encode_from_read(Some(&mut t_it), Some(&mut it), &mut input, opts).unwrap();
+///
+/// // `output` can be decoded to get the original data back
+/// let mut encoded: Cursor<Vec<u8>> = Cursor::new(output);
+/// let mut decoded: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// decode_from_read_to_write(Format::Metagraph, &mut encoded, &mut decoded).unwrap();
+///
+/// assert_eq!(decoded.get_ref(), &input_bytes);
+/// ```
+///
+pub fn encode_from_read<R: Read, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
+    targets: Option<&mut T>,
+    queries: Option<&mut Q>,
+    conn_in: &mut R,
+    opts: EncodeOpts,
+) -> Result<Vec<u8>, E> {
+    let have_queries = queries.is_some();
+    let queries_listed = have_queries;
+    let mut reader = if let Some(format) = opts.format.clone() {
+        crate::parser::Parser::new_with_format(conn_in, queries, targets, format)?
+    } else {
+        crate::parser::Parser::new(conn_in, queries, targets)?
+    };
+
+    // Names in the input are enough if the ids don't come from the query names
+    let have_queries = have_queries || (opts.query_ids != QueryIdPolicy::FromList && reader.format.fields_present() & MASK_QUERIES != 0);
+    reader.set_query_id_policy(opts.query_ids.clone());
+    reader.fill_target_names(opts.encode_target_names);
+    reader.fill_query_name(opts.encode_query_names && have_queries);
+
+    if !have_queries && reader.format != Format::Metagraph && reader.format != Format::Themisto && reader.format != Format::AhdaTSV {
+        return Err(Box::new(crate::errors::NeedQueryNamesErr{ format: reader.format }))
+    }
+
+    let targets = reader.get_targets().unwrap();
+    let parse_err: RefCell<Option<E>> = RefCell::new(None);
+    let (mut records, n_queries, coverage) = parsed_records(&mut reader, &opts, &parse_err)?;
+    let mut encoder = encoder::Encoder::new(&mut records, &targets, &opts.accession, n_queries);
+    configure_encoder(&mut encoder, &opts, have_queries)?;
+    if let Some(coverage) = &coverage {
+        encoder.set_coverage(coverage)?;
+    }
+
+    let mut bytes = encoder.encode_file_header_and_flags()?;
+    for block in encoder.by_ref() {
+        bytes.append(&mut block?);
+    }
+    if let Some(e) = parse_err.take() {
+        return Err(e)
+    }
+    if queries_listed {
+        check_record_count(encoder.records_written(), n_queries);
+    }
+    Ok(bytes)
+}
+
+/// Warn if fewer records were encoded than there are names in the query file.
+///
+/// The aligners also report the queries that didn't align, so missing
+/// records usually mean that the alignment was made from a different .fasta
+/// or .fastq file, or that unaligned queries were filtered out.
+fn check_record_count(
+    n_records: usize,
+    n_queries: usize,
+) {
+    if n_records < n_queries {
+        log::warn!("encoded {} records but the query file has {} queries, check that the alignment is of this query file", n_records, n_queries);
+    }
+}
+
+/// Records of `reader` to encode with `opts` and the number of queries.
+///
+/// With [EncodeOpts::allow_extra] the records are parsed into memory first,
+/// so that the queries past the end of the query names are named before the
+/// file header is written. [EncodeOpts::coverage_bucket] does the same to
+/// return the coverage of all records. Otherwise the records are parsed while encoding
+/// and parsing stops at the first error, which is stored in `parse_err`.
+#[allow(clippy::type_complexity)]
+fn parsed_records<'b, R: Read>(
+    reader: &'b mut crate::parser::Parser<'_, R>,
+    opts: &EncodeOpts,
+    parse_err: &'b RefCell<Option<E>>,
+) -> Result<(Box<dyn Iterator<Item = PseudoAln> + 'b>, usize, Option<coverage::Coverage>), E> {
+    if let Some(bucket_size) = opts.coverage_bucket {
+        reader.set_coverage(bucket_size)?;
+    }
+    let mut coverage = None;
+    let (records, n_queries): (Box<dyn Iterator<Item = PseudoAln> + 'b>, usize) = if opts.allow_extra || opts.coverage_bucket.is_some() {
+        reader.set_allow_extra(opts.allow_extra);
+        let records = reader.try_iter().collect::<Result<Vec<PseudoAln>, E>>()?;
+        if reader.n_extra() > 0 {
+            log::warn!("named {} queries past the end of the query names as `extra.<rank>`", reader.n_extra());
+        }
+        coverage = reader.coverage();
+        let n_queries = reader.len();
+        (Box::new(records.into_iter()), n_queries)
+    } else {
+        let n_queries = reader.len();
+        let records = std::iter::from_fn(move || match reader.try_next()? {
+            Ok(record) => Some(record),
+            Err(e) => {
+                parse_err.replace(Some(e));
+                None
+            },
+        });
+        (Box::new(records), n_queries)
+    };
+
+    match &opts.sort_input {
+        Some(sort_opts) => {
+            let sorted = encoder::sorted::ExternalSort::new(records, sort_opts)?;
+            if let Some(e) = parse_err.take() {
+                return Err(e)
+            }
+            Ok((Box::new(sorted), n_queries, coverage))
+        },
+        None => Ok((records, n_queries, coverage)),
+    }
+}
+
+/// Parse all plain-text pseudoalignments from [Read] and encode to [Write].
+///
+/// ## Usage
+/// ```rust
+/// use ahda::{encode_from_read_to_write, decode_from_read_to_write};
+/// use ahda::Format;
+/// use ahda::EncodeOpts;
+/// use std::io::{Cursor, Seek};
+///
+/// // Mock inputs
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
+/// let name = "sample".as_bytes().to_vec();
+///
+/// // Have this input data:
+/// //   3    r7543    chr.fasta:virus.fasta
+/// //   0    r1       virus.fasta
+/// //   4    r16      chr.fasta:plasmid.fasta:virus.fasta
+/// //   2    r651903
+/// //
+/// let mut input_bytes: Vec<u8> = Vec::new();
+/// input_bytes.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
+/// input_bytes.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
+/// input_bytes.append(&mut b"4\tr16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
+/// input_bytes.append(&mut b"2\tr651903\t\n".to_vec());
+///
+/// let mut input: Cursor<Vec<u8>> = Cursor::new(input_bytes.clone());
+///
+/// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut it = queries.into_iter();
+/// let mut t_it = targets.into_iter();
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = true;
+/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut input, &mut output, opts).unwrap();
+///
+/// // `output` can be decoded to get the original data back
+/// output.rewind();
+/// let mut decoded: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// decode_from_read_to_write(Format::Metagraph, &mut output, &mut decoded).unwrap();
+///
+/// assert_eq!(decoded.get_ref(), &input_bytes);
+/// ```
+///
+pub fn encode_from_read_to_write<R: Read, W: Write, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
+    targets: Option<&mut T>,
+    queries: Option<&mut Q>,
+    conn_in: &mut R,
+    conn_out: &mut W,
+    mut opts: EncodeOpts,
+) -> Result<(), E> {
+    if let Some(level) = opts.zstd_level.take() {
+        return encode_to_zstd_container(conn_out, level, |mut conn| encode_from_read_to_write(targets, queries, conn_in, &mut conn, opts))
+    }
+
+    let have_queries = queries.is_some();
+    let queries_listed = have_queries;
+
+    let mut reader = if let Some(format) = opts.format.clone() {
+        crate::parser::Parser::new_with_format(conn_in, queries, targets, format)?
+    } else {
+        crate::parser::Parser::new(conn_in, queries, targets)?
+    };
+    // Names in the input are enough if the ids don't come from the query names
+    let have_queries = have_queries || (opts.query_ids != QueryIdPolicy::FromList && reader.format.fields_present() & MASK_QUERIES != 0);
+    reader.set_query_id_policy(opts.query_ids.clone());
+    reader.fill_target_names(opts.encode_target_names);
+    reader.fill_query_name(opts.encode_query_names && have_queries && !opts.rename_queries);
+
+    if !have_queries && reader.format != Format::Metagraph && reader.format != Format::Themisto && reader.format != Format::AhdaTSV {
+        return Err(Box::new(crate::errors::NeedQueryNamesErr{ format: reader.format }))
+    }
+
+    let targets = reader.get_targets().unwrap();
+    let parse_err: RefCell<Option<E>> = RefCell::new(None);
+    let (mut records, n_queries, coverage) = parsed_records(&mut reader, &opts, &parse_err)?;
+    let mut encoder = encoder::Encoder::new(&mut records, &targets, &opts.accession, n_queries);
+    configure_encoder(&mut encoder, &opts, have_queries)?;
+    if let Some(coverage) = &coverage {
+        encoder.set_coverage(coverage)?;
+    }
+
+    let bytes = encoder.encode_file_header_and_flags()?;
+    conn_out.write_all(&bytes)?;
+    for block in encoder.by_ref() {
+        conn_out.write_all(&block?)?;
+        conn_out.flush()?;
+    }
+    if let Some(e) = parse_err.take() {
+        return Err(e)
+    }
+    if queries_listed {
+        check_record_count(encoder.records_written(), n_queries);
+    }
+
+    Ok(())
+}
+
+/// Decode all pseudoalignments from [Read] and format to [Write].
+///
+/// ## Usage
+/// ```rust
+/// use ahda::{decode_from_read_to_write, encode_from_read_to_write};
+/// use ahda::Format;
+/// use ahda::EncodeOpts;
+/// use std::io::{Cursor, Seek};
+///
+/// // Set up mock inputs
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
+/// let name = "sample".as_bytes().to_vec();
+///
+/// let mut plaintext_bytes: Vec<u8> = Vec::new();
+/// plaintext_bytes.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
+/// plaintext_bytes.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
+/// plaintext_bytes.append(&mut b"4\tr16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
+/// plaintext_bytes.append(&mut b"2\tr651903\t\n".to_vec());
+///
+/// // Encode mock data
+/// let mut plaintext: Cursor<Vec<u8>> = Cursor::new(plaintext_bytes.clone());
+/// let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut it = queries.into_iter();
+/// let mut t_it = targets.into_iter();
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = true;
+/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut plaintext, &mut input, opts).unwrap();
+/// input.rewind();
+///
+/// // Decode all alignments and compare against the original inputs
+/// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// decode_from_read_to_write(Format::Metagraph, &mut input, &mut output).unwrap();
+///
+/// assert_eq!(output.get_ref(), plaintext.get_ref());
+/// ```
+///
+pub fn decode_from_read_to_write<R: Read, W: Write>(
+    out_format: Format,
+    conn_in: &mut R,
+    conn_out: &mut W,
+) -> Result<(), E> {
+    let mut decoder = decoder::Decoder::try_new(conn_in)?;
+
+    match out_format {
+        Format::Themisto => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_name(false);
+        },
+        Format::Fulgor => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Bifrost => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::SAM => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Metagraph => {
+            decoder.fill_target_ids(false);
+        },
+        Format::AhdaTSV => {
+            decoder.fill_target_names(false);
+        },
+    }
+
+    let header = decoder.file_header().clone();
+    let flags = decoder.file_flags().clone();
+    let mut error: Option<E> = None;
+    let mut records = decoder.try_iter().map_while(|record| record.map_err(|e| error = Some(e)).ok());
+    let mut printer = printer::Printer::new_from_header_and_flags(&mut records, header, flags, out_format.clone());
+    let mut conn_out = output::buffered(conn_out, output::DEFAULT_BUFFER_SIZE);
+    while let Some(line) = printer.try_next() {
+        conn_out.write_all(&line?)?;
+    }
+    conn_out.flush()?;
+    drop(records);
+
+    if let Some(e) = error {
+        return Err(e)
+    }
+    Ok(())
+}
+
+/// Decode the pseudoalignments of queries named in `names` from [Read] and format to [Write].
+///
+/// Blocks whose query filter rules out all `names` are skipped without
+/// decompressing them, see [Decoder::grep](decoder::Decoder::grep). Files
+/// encoded without [EncodeOpts::query_filter_fpr] are searched block by block.
+///
+/// Returns the number of blocks that were skipped.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::encode_to_write;
+/// use ahda::grep_from_read_to_write;
+/// use ahda::EncodeOpts;
+/// use ahda::Format;
+/// use ahda::PseudoAln;
+/// use std::collections::HashSet;
+/// use std::io::Cursor;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+/// let data = vec![
+///     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some(b"r1".to_vec()) },
+///     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: Some(b"r2".to_vec()) },
+/// ];
+///
+/// let mut opts = EncodeOpts::default();
+/// opts.encode_query_names = true;
+/// opts.query_filter_fpr = Some(0.01);
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
+///
+/// let names = HashSet::from([b"r2".to_vec()]);
+/// let mut output: Vec<u8> = Vec::new();
+/// grep_from_read_to_write(&names, Format::Themisto, &mut Cursor::new(bytes), &mut output).unwrap();
+/// assert_eq!(output, b"1 1\n");
+/// ```
+///
+/// ## Errors and panics
+/// Errors with [MissingQueryNamesErr](errors::MissingQueryNamesErr) if the
+/// input does not store the query names.
+///
+pub fn grep_from_read_to_write<R: Read, W: Write>(
+    names: &HashSet<Vec<u8>>,
+    out_format: Format,
+    conn_in: &mut R,
+    conn_out: &mut W,
+) -> Result<usize, E> {
+    let mut decoder = decoder::Decoder::try_new(conn_in)?;
+
+    match out_format {
+        Format::Themisto => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_name(false);
+        },
+        Format::Fulgor => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Bifrost => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::SAM => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Metagraph => {
+            decoder.fill_target_ids(false);
+        },
+        Format::AhdaTSV => {
+            decoder.fill_target_names(false);
+        },
+    }
+
+    let header = decoder.file_header().clone();
+    let flags = decoder.file_flags().clone();
+    let mut records = decoder.grep(names)?.into_iter();
+    let mut printer = printer::Printer::new_from_header_and_flags(&mut records, header, flags, out_format);
+    let mut conn_out = output::buffered(conn_out, output::DEFAULT_BUFFER_SIZE);
+    while let Some(line) = printer.try_next() {
+        conn_out.write_all(&line?)?;
+    }
+    conn_out.flush()?;
+
+    Ok(decoder.blocks_skipped())
+}
+
+/// Decode the pseudoalignments of queries that aligned to any target in `target_names` from [Read] and format to [Write].
+///
+/// Blocks whose target summary has no hits to the targets are skipped
+/// without decompressing them, see [Decoder::slice](decoder::Decoder::slice).
+/// Files encoded without [EncodeOpts::target_summary] are searched block by
+/// block.
+///
+/// Returns the number of blocks that were skipped.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::encode_to_write;
+/// use ahda::slice_from_read_to_write;
+/// use ahda::EncodeOpts;
+/// use ahda::Format;
+/// use ahda::PseudoAln;
+/// use std::io::Cursor;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let data = vec![
+///     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None },
+///     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![0, 1]), query_name: None },
+/// ];
+///
+/// let mut opts = EncodeOpts::default();
+/// opts.target_summary = true;
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &[b"r1".to_vec(), b"r2".to_vec()], &data, &mut bytes, opts).unwrap();
+///
+/// let mut output: Vec<u8> = Vec::new();
+/// slice_from_read_to_write(&[b"plasmid.fasta".to_vec()], Format::Themisto, &mut Cursor::new(bytes), &mut output).unwrap();
+/// assert_eq!(output, b"1 0 1\n");
+/// ```
+///
+/// ## Errors and panics
+/// Errors with [UnknownTargetErr](errors::UnknownTargetErr) if a name in
+/// `target_names` is not a target of the input.
+///
+pub fn slice_from_read_to_write<R: Read, W: Write>(
+    target_names: &[Vec<u8>],
+    out_format: Format,
+    conn_in: &mut R,
+    conn_out: &mut W,
+) -> Result<usize, E> {
+    let mut decoder = decoder::Decoder::try_new(conn_in)?;
+
+    match out_format {
+        Format::Themisto => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_name(false);
+        },
+        Format::Fulgor => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Bifrost => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::SAM => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Metagraph => {
+            decoder.fill_target_ids(false);
+        },
+        Format::AhdaTSV => {
+            decoder.fill_target_names(false);
+        },
+    }
+
+    let header = decoder.file_header().clone();
+    let flags = decoder.file_flags().clone();
+    let targets = target_names.iter().map(|name| {
+        flags.target_names.iter().position(|x| x == name).map(|idx| idx as u32).ok_or(errors::UnknownTargetErr{})
+    }).collect::<Result<RoaringBitmap, _>>()?;
+    let mut records = decoder.slice(&targets)?.into_iter();
+    let mut printer = printer::Printer::new_from_header_and_flags(&mut records, header, flags, out_format);
+    let mut conn_out = output::buffered(conn_out, output::DEFAULT_BUFFER_SIZE);
+    while let Some(line) = printer.try_next() {
+        conn_out.write_all(&line?)?;
+    }
+    conn_out.flush()?;
+
+    Ok(decoder.blocks_skipped())
+}
+
+/// Decode `n` pseudoalignments picked at random from [Read] and format to [Write].
+///
+/// Blocks that none of the picked records fall in are skipped without
+/// decompressing them, see [Decoder::sample](decoder::Decoder::sample). The
+/// records are written in the order they are stored in.
+///
+/// Returns the number of blocks that were skipped.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::encode_to_write;
+/// use ahda::sample_from_read_to_write;
+/// use ahda::EncodeOpts;
+/// use ahda::Format;
+/// use ahda::PseudoAln;
+/// use std::io::Cursor;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let queries: Vec<Vec<u8>> = (0..100).map(|id| format!("r{}", id).into_bytes()).collect();
+/// let data: Vec<PseudoAln> = (0..100).map(|id| {
+///     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+/// }).collect();
+///
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &queries, &data, &mut bytes, EncodeOpts::default()).unwrap();
+///
+/// let mut output: Vec<u8> = Vec::new();
+/// sample_from_read_to_write(3, 42, Format::Themisto, &mut Cursor::new(bytes), &mut output).unwrap();
+/// assert_eq!(output.iter().filter(|x| **x == b'\n').count(), 3);
+/// ```
+///
+/// ## Errors and panics
+/// Errors if a block can't be read.
+///
+pub fn sample_from_read_to_write<R: Read, W: Write>(
+    n: usize,
+    seed: u64,
+    out_format: Format,
+    conn_in: &mut R,
+    conn_out: &mut W,
+) -> Result<usize, E> {
+    let mut decoder = decoder::Decoder::try_new(conn_in)?;
+
+    match out_format {
+        Format::Themisto => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_name(false);
+        },
+        Format::Fulgor => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Bifrost => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::SAM => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Metagraph => {
+            decoder.fill_target_ids(false);
+        },
+        Format::AhdaTSV => {
+            decoder.fill_target_names(false);
+        },
+    }
+
+    let header = decoder.file_header().clone();
+    let flags = decoder.file_flags().clone();
+    let mut records = decoder.sample(n, seed)?.into_iter();
+    let mut printer = printer::Printer::new_from_header_and_flags(&mut records, header, flags, out_format);
+    let mut conn_out = output::buffered(conn_out, output::DEFAULT_BUFFER_SIZE);
+    while let Some(line) = printer.try_next() {
+        conn_out.write_all(&line?)?;
+    }
+    conn_out.flush()?;
+
+    Ok(decoder.blocks_skipped())
+}
+
+/// Decode all pseudoalignments from [Read] to memory.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::{decode_from_read, encode_to_write};
+/// use ahda::EncodeOpts;
+/// use ahda::PseudoAln;
+/// use std::io::{Cursor, Seek};
+///
+/// // Mock data
+/// let data = vec![
+///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("ERR4035126.1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![0]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) },
+/// ];
+///
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
+/// let name = "ERR4035126".as_bytes().to_vec();
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = true;
+///
+/// // Encode mock data
+/// let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// encode_to_write(&targets, &queries, &data, &mut input, opts).unwrap();
+/// input.rewind();
+///
+/// // Decode to recover the original data
+/// let (_file_header, _file_flags, alns) = decode_from_read(&mut input).unwrap();
+///
+/// assert_eq!(alns, data);
+///
+pub fn decode_from_read<R: Read>(
+    conn_in: &mut R,
+) -> Result<(FileHeader, FileFlags, Vec<PseudoAln>), E> {
+    let mut decoder = decoder::Decoder::try_new(conn_in)?;
+
+    let header = decoder.file_header().clone();
+    let flags = decoder.file_flags().clone();
+
+    let mut alns: Vec<PseudoAln> = Vec::with_capacity(header.n_queries as usize);
+    alns.extend(decoder.by_ref());
+    if let Some(e) = decoder.take_error() {
+        return Err(e)
+    }
+
+    Ok((header, flags, alns))
+}
+
+/// Decode from memory and format to [Write].
+///
+/// ## Usage
+/// ```rust
+/// use ahda::{decode_to_write, encode_to_write};
+/// use ahda::{Format, PseudoAln, EncodeOpts};
+/// use std::io::{Cursor, Seek};
+///
+/// // Mock data
+/// let data = vec![
+///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("ERR4035126.1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![0]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) },
+/// ];
+///
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
+/// let name = "ERR4035126".as_bytes().to_vec();
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = false;
+///
+/// // Encode mock data
+/// let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// encode_to_write(&targets, &queries, &data, &mut input, opts).unwrap();
+///
+/// // Decode to recover the original data
+/// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// decode_to_write(Format::Metagraph, input.get_ref(), &mut output).unwrap();
+///
+/// // Expect this output data:
+/// //   0    ERR4035126.1    chr.fasta
+/// //   1    ERR4035126.2    chr.fasta
+/// let mut expected: Vec<u8> = Vec::new();
+/// expected.append(&mut b"0\tERR4035126.1\tchr.fasta\n".to_vec());
+/// expected.append(&mut b"1\tERR4035126.2\tchr.fasta\n".to_vec());
+///
+/// assert_eq!(output.get_ref(), &expected);
+///
+pub fn decode_to_write<W: Write>(
+    out_format: Format,
+    records: &[u8],
+    conn_out: &mut W,
+) -> Result<(), E> {
+    let mut tmp = std::io::Cursor::new(&records);
+    let mut decoder = decoder::Decoder::try_new(&mut tmp)?;
+
+    match out_format {
+        Format::Themisto => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_name(false);
+        },
+        Format::Fulgor => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Bifrost => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::SAM => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_id(false);
+        },
+        Format::Metagraph => {
+            decoder.fill_target_ids(false);
+        },
+        Format::AhdaTSV => {
+            decoder.fill_target_names(false);
+        },
+    }
+
+    let header = decoder.file_header().clone();
+    let flags = decoder.file_flags().clone();
+    let mut printer = printer::Printer::new_from_header_and_flags(&mut decoder, header.clone(), flags.clone(), out_format.clone());
+    let mut conn_out = output::buffered(conn_out, output::DEFAULT_BUFFER_SIZE);
+    while let Some(line) = printer.try_next() {
+        conn_out.write_all(&line?)?;
+    }
+    conn_out.flush()?;
+
+    Ok(())
+}
+
+/// Reads the full bitmap and combined block flags from a file
+///
+/// Returns the [RoaringBitmap](roaring::RoaringBitmap) containing all
+/// alignments from every block in the input, and the corresponding file header,
+/// file flags, and block flags for a single block containing all the data.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::{decode_from_read_to_roaring, encode_from_read_to_write};
+/// use ahda::Format;
+/// use ahda::EncodeOpts;
+/// use ahda::headers::file::{FileHeader, FileFlags};
+/// use ahda::headers::block::{BlockHeader, BlockFlags};
+/// use roaring::RoaringTreemap;
+/// use std::io::{Cursor, Seek};
+///
+/// // Set up mock inputs
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
+/// let name = "sample".as_bytes().to_vec();
+///
+/// let mut plaintext_bytes: Vec<u8> = Vec::new();
+/// plaintext_bytes.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
+/// plaintext_bytes.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
+/// plaintext_bytes.append(&mut b"4\tr16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
+/// plaintext_bytes.append(&mut b"2\tr651903\t\n".to_vec());
+///
+/// // Encode mock data
+/// let mut plaintext: Cursor<Vec<u8>> = Cursor::new(plaintext_bytes.clone());
+/// let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut it = queries.into_iter();
+/// let mut t_it = targets.into_iter();
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = true;
+/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut plaintext, &mut input, opts).unwrap();
+/// input.rewind();
+///
+/// // Decode all alignments and compare against the original inputs
+/// let (bitmap, file_header, file_flags, block_flags) = decode_from_read_to_roaring(&mut input).unwrap();
+///
+/// // Expect these outputs:
+/// //   RoaringTreemap<[2, 9, 11, 12, 13, 14]>
+/// // FileHeader{
+/// //                                    n_targets: 3_u32,
+/// //                                   n_queries: 5_u32,
+/// //                                   ahda_header: ahda::headers::file::build_ahda_header(),
+/// //                                   file_format: ahda::AhdaFormatVersion::V1_1_0.to_u8(),
+/// //                                   metadata_compression: ahda::compression::MetadataCompression::default().to_u8(),
+/// //                                   fields_present: 3,
+/// //                                   bitmap_type: ahda::compression::BitmapType::Roaring32.to_u16(),
+/// //                                   block_size: 65536_u32,
+/// //                                   flags_len: 46_u64,
+/// //                                 }
+/// //   FileFlags    { query_name: "sample", target_names: vec!["chr.fasta", "plasmid.fasta", "virus.fasta"] }
+/// //   BlockFlags   { queries: Some(["r1", "r651903", "r7543", "r16"]), query_ids: Some([0, 2, 3, 4]) }
+///
+/// assert_eq!(bitmap, RoaringTreemap::from([2, 9, 11, 12, 13, 14]));
+/// assert_eq!(file_header, FileHeader{
+///                                      n_targets: 3_u32,
+///                                      n_queries: 5_u32,
+///                                      ahda_header: ahda::headers::file::build_ahda_header(),
+///                                      file_format: ahda::AhdaFormatVersion::V1_1_0.to_u8(),
+///                                      metadata_compression: ahda::compression::MetadataCompression::default().to_u8(),
+///                                      fields_present: 3,
+///                                      bitmap_type: ahda::compression::BitmapType::Roaring32.to_u16(),
+///                                      block_size: 65536_u32,
+///                                      flags_len: 44_u64,
+///                                    });
+/// let mut expected_flags = FileFlags::default();
+/// expected_flags.query_name = "sample".as_bytes().to_vec();
+/// expected_flags.target_names = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+/// assert_eq!(file_flags, expected_flags);
+/// assert_eq!(block_flags, BlockFlags{ queries: Some(vec!["r1".as_bytes().to_vec(), "r651903".as_bytes().to_vec(), "r7543".as_bytes().to_vec(), "r16".as_bytes().to_vec()]), query_ids: Some(vec![0, 2, 3, 4]), ..Default::default() });
+///
+pub fn decode_from_read_to_roaring<R: Read>(
+    conn_in: &mut R,
+) -> Result<(RoaringTreemap, FileHeader, FileFlags, BlockFlags), E> {
+    let mut bitmap_out = RoaringTreemap::new();
+    let header = crate::headers::file::read_file_header(conn_in)?;
+    let flags = crate::headers::file::read_file_flags(&header, conn_in)?;
+
+    let mut queries: Vec<Vec<u8>> = Vec::new();
+    let mut query_ids: Vec<u32> = Vec::new();
+
+    let mut cache = BlockCache::new();
+    let mut block_bytes: Vec<u8> = Vec::new();
+    let mut buffers = compression::BlockBuffers::default();
+    while let Ok(block_header) = cache.read_block_into(conn_in, &mut block_bytes) {
+
+        let (bitmap, mut block_flags) = match BitmapType::from_u16(header.bitmap_type)? {
+            BitmapType::Roaring32 => {
+                let (bitmap, block_flags) = unpack_block_roaring32_with_buffers(&block_bytes, &block_header, &mut buffers)?;
+                (RoaringTreemap::from_bitmaps([(0, bitmap)]), block_flags)
+            },
+            BitmapType::Roaring64 => {
+                unpack_block_roaring64_with_buffers(&block_bytes, &block_header, &mut buffers)?
+            },
+        };
+
+        queries.append(block_flags.queries.as_mut().ok_or(errors::MissingBlockFieldsErr{})?);
+        query_ids.append(block_flags.query_ids.as_mut().ok_or(errors::MissingBlockFieldsErr{})?);
+
+        bitmap_out |= bitmap;
+    }
+
+    let mut both: Vec<(u32, &Vec<u8>)> = queries.iter().zip(query_ids.iter()).map(|(name, idx)| (*idx, name)).collect();
+    both.sort_by_key(|x| x.0);
+    let queries: Option<Vec<Vec<u8>>> = Some(both.iter().map(|x| x.1.clone()).collect::<Vec<Vec<u8>>>());
+    let query_ids: Option<Vec<u32>> = Some(both.iter().map(|x| x.0).collect());
+
+    Ok((bitmap_out, header, flags, BlockFlags{ queries, query_ids, ..Default::default() }))
+}
+
+/// Merge bitmap from Read to an existing bitmap with Union
+///
+/// Doesn't check that the encoded data was created for compatible data, this
+/// just merges the bitmaps.
+///
+/// Blocks whose [BlockStats] show no set bits are skipped without
+/// decompressing them, except for [MergeOp::Intersection] which has to
+/// decode the whole input.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::{decode_from_read_into_roaring, decode_from_read_to_roaring, encode_from_read_to_write};
+/// use ahda::MergeOp;
+/// use ahda::EncodeOpts;
+/// use roaring::RoaringTreemap;
+/// use std::io::{Cursor, Seek};
+///
+/// // Set up mock inputs
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
+/// let name = "sample".as_bytes().to_vec();
+///
+/// // Have this input data in two files:
+/// //     0    r1    virus.fasta
+/// //     3    r7543 chr.fasta:virus.fasta
+/// //
+/// //     0    r1    plasmid.fasta:virus.fasta
+/// //     3    r7543 plasmid.fasta
+/// //
+/// // ...and want to compute the intersection
+///
+/// let mut plaintext_bytes_1: Vec<u8> = Vec::new();
+/// plaintext_bytes_1.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
+/// plaintext_bytes_1.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
+///
+/// let mut plaintext_bytes_2: Vec<u8> = Vec::new();
+/// plaintext_bytes_2.append(&mut b"0\tr1\tplasmid.fasta:virus.fasta\n".to_vec());
+/// plaintext_bytes_2.append(&mut b"3\tr7543\tplasmid.fasta\n".to_vec());
+///
+/// // Encode mock data
+/// let mut plaintext_1: Cursor<Vec<u8>> = Cursor::new(plaintext_bytes_1.clone());
+/// let mut input_1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut t_it = targets.iter().cloned();
+/// let mut it = queries.iter().cloned();
+/// let mut opts = EncodeOpts::default();
+/// opts.accession = name;
+/// opts.encode_query_names = true;
+/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut plaintext_1, &mut input_1, opts.clone()).unwrap();
+/// input_1.rewind();
+///
+/// let mut plaintext_2: Cursor<Vec<u8>> = Cursor::new(plaintext_bytes_2.clone());
+/// let mut input_2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut it = queries.into_iter();
+/// let mut t_it = targets.into_iter();
+/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut plaintext_2, &mut input_2, opts).unwrap();
+/// input_2.rewind();
+///
+/// // Decode data from `input_1`
+/// let (mut bitmap, _file_header, _file_flags, _block_flags) = decode_from_read_to_roaring(&mut input_1).unwrap();
+///
+/// // Intersect data from `input_2` with the decoded `bitmap`
+/// decode_from_read_into_roaring(&mut input_2, &MergeOp::Intersection, &mut bitmap).unwrap();
+///
+/// // Expect these outputs:
+/// //     RoaringBitmap<[2]>
+/// // ...ie the alignment in the intersection is:
+/// //     0    r1    virus.fasta
+///
+/// assert_eq!(bitmap, RoaringTreemap::from([2]));
+///
+pub fn decode_from_read_into_roaring<R: Read>(
+    conn_in: &mut R,
+    merge_op: &MergeOp,
+    bitmap_out: &mut RoaringTreemap,
+) -> Result<(), E> {
+    match merge_op {
+        MergeOp::Intersection => {
+            // Have to read in the whole bitmap to perform intersection
+            let (bitmap_b, _, _, _) = decode_from_read_to_roaring(conn_in)?;
+            *bitmap_out &= bitmap_b;
+        },
+        _ => {
+            let header = crate::headers::file::read_file_header(conn_in)?;
+            let _ = crate::headers::file::read_file_flags(&header, conn_in)?;
+
+            let mut cache = BlockCache::new();
+            let mut block_bytes: Vec<u8> = Vec::new();
+            let mut buffers = compression::BlockBuffers::default();
+            while let Ok(block_header) = cache.read_block_into(conn_in, &mut block_bytes) {
+                // Blocks without set bits don't change the result
+                if block_header.stats().is_some_and(|x| x.n_ones == 0) {
+                    continue
+                }
+
+                let bitmap_b = match BitmapType::from_u16(header.bitmap_type)? {
+                    BitmapType::Roaring32 => {
+                        let (bitmap, _) = unpack_block_roaring32_with_buffers(&block_bytes, &block_header, &mut buffers)?;
+                        RoaringTreemap::from_bitmaps([(0, bitmap)])
+                    },
+                    BitmapType::Roaring64 => {
+                        unpack_block_roaring64_with_buffers(&block_bytes, &block_header, &mut buffers)?.0
+                    },
+                };
+
+                match merge_op {
+                    MergeOp::Union => {
+                        *bitmap_out |= bitmap_b;
+                    },
+                    MergeOp::Xor => {
+                        *bitmap_out ^= bitmap_b;
+                    },
+                    MergeOp::Diff => {
+                        *bitmap_out -= bitmap_b;
+                    },
+                    MergeOp::Intersection => {
+                        panic!("MergeOp::Intersection cannot be performed block-wise.");
+                    }
+                }
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Merge bitmap from Read to an existing bitmap matching the queries by name.
+///
+/// Works like [decode_from_read_into_roaring] but aligns the queries in
+/// `conn_in` to `block_flags_out` by their names instead of their query ids.
+/// Use this when the inputs list the same reads in a different order.
+///
+/// Queries that are not in `block_flags_out` are added to it with new query
+/// ids for [MergeOp::Union] and [MergeOp::Xor], and ignored for the other
+/// operations.
+///
+/// This reads all of `conn_in` into memory and keeps a hash table of the
+/// query names, so it uses considerably more memory than merging by position.
+///
+/// ## Errors and panics
+///
+/// Errors with [MissingQueryNamesErr](errors::MissingQueryNamesErr) if either
+/// input does not store the query names. Like [decode_from_read_into_roaring],
+/// doesn't check that the inputs have the same target sequences.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::{decode_from_read_into_roaring_by_name, decode_from_read_to_roaring, encode_to_write};
+/// use ahda::{EncodeOpts, MergeOp, PseudoAln};
+/// use roaring::RoaringTreemap;
+/// use std::io::Cursor;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let mut opts = EncodeOpts::default();
+/// opts.encode_query_names = true;
+///
+/// // Same reads in a different order
+/// let data_1 = vec![
+///     PseudoAln{ ones: Some(vec![0]), ones_names: None, query_id: Some(0), query_name: Some(b"r1".to_vec()) },
+///     PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(1), query_name: Some(b"r2".to_vec()) },
+/// ];
+/// let data_2 = vec![
+///     PseudoAln{ ones: Some(vec![0, 1]), ones_names: None, query_id: Some(0), query_name: Some(b"r2".to_vec()) },
+///     PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(1), query_name: Some(b"r1".to_vec()) },
+/// ];
+///
+/// let mut input_1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut input_2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// encode_to_write(&targets, &[b"r1".to_vec(), b"r2".to_vec()], &data_1, &mut input_1, opts.clone()).unwrap();
+/// encode_to_write(&targets, &[b"r2".to_vec(), b"r1".to_vec()], &data_2, &mut input_2, opts).unwrap();
+/// input_1.set_position(0);
+/// input_2.set_position(0);
+///
+/// let (mut bitmap, _, _, mut block_flags) = decode_from_read_to_roaring(&mut input_1).unwrap();
+/// decode_from_read_into_roaring_by_name(&mut input_2, &MergeOp::Intersection, &mut bitmap, &mut block_flags).unwrap();
+///
+/// // r2 aligns to plasmid.fasta in both inputs
+/// assert_eq!(bitmap, RoaringTreemap::from_iter([3]));
+/// ```
+///
+pub fn decode_from_read_into_roaring_by_name<R: Read>(
+    conn_in: &mut R,
+    merge_op: &MergeOp,
+    bitmap_out: &mut RoaringTreemap,
+    block_flags_out: &mut BlockFlags,
+) -> Result<(), E> {
+    let (bitmap_b, header_b, _, block_flags_b) = decode_from_read_to_roaring(conn_in)?;
+    let n_targets = header_b.n_targets as u64;
+
+    let (Some(queries_out), Some(query_ids_out)) = (block_flags_out.queries.as_mut(), block_flags_out.query_ids.as_mut()) else {
+        return Err(Box::new(errors::MissingQueryNamesErr{}))
+    };
+    let (Some(queries_b), Some(query_ids_b)) = (block_flags_b.queries, block_flags_b.query_ids) else {
+        return Err(Box::new(errors::MissingQueryNamesErr{}))
+    };
+    if queries_out.len() != query_ids_out.len() || queries_b.len() != query_ids_b.len() {
+        return Err(Box::new(errors::MissingQueryNamesErr{}))
+    }
+
+    // Hash join from query ids in `conn_in` to query ids in `block_flags_out`
+    let mut ids_by_name: std::collections::HashMap<Vec<u8>, u32> = queries_out.iter().cloned().zip(query_ids_out.iter().cloned()).collect();
+    let add_missing = matches!(merge_op, MergeOp::Union | MergeOp::Xor);
+    let mut next_id = query_ids_out.iter().max().map_or(0, |x| x + 1);
+    let mut new_ids: std::collections::HashMap<u32, u32> = std::collections::HashMap::with_capacity(query_ids_b.len());
+    queries_b.into_iter().zip(query_ids_b).for_each(|(name, id_b)| {
+        if let Some(id_out) = ids_by_name.get(&name) {
+            new_ids.insert(id_b, *id_out);
+        } else if add_missing {
+            new_ids.insert(id_b, next_id);
+            ids_by_name.insert(name.clone(), next_id);
+            queries_out.push(name);
+            query_ids_out.push(next_id);
+            next_id += 1;
+        }
+    });
+
+    let mut remapped = RoaringTreemap::new();
+    if n_targets > 0 {
+        remapped.extend(bitmap_b.iter().filter_map(|idx| {
+            let new_id = new_ids.get(&((idx / n_targets) as u32))?;
+            Some(*new_id as u64 * n_targets + idx % n_targets)
+        }));
+    }
+
+    match merge_op {
+        MergeOp::Union => *bitmap_out |= remapped,
+        MergeOp::Intersection => *bitmap_out &= remapped,
+        MergeOp::Xor => *bitmap_out ^= remapped,
+        MergeOp::Diff => *bitmap_out -= remapped,
+    }
+
+    Ok(())
+}
+
+/// Merge the bitmaps from several [Read]s using `n_threads` threads.
+///
+/// Computes the same result as reading the first input with
+/// [decode_from_read_to_roaring] and merging the rest into it one at a time
+/// with [decode_from_read_into_roaring], but decodes the inputs after the
+/// first in parallel. Each thread merges the inputs it reads into its own
+/// bitmap, and the bitmaps from the threads are then merged pairwise.
+///
+/// Inputs with no records at the start are skipped for [MergeOp::Union] and
+/// [MergeOp::Xor], so that the queries in the output come from the first
+/// input that has some.
+///
+/// For [MergeOp::Diff] the other inputs are merged with union and the result
+/// is removed from the first input.
+///
+/// Returns the merged bitmap and the headers and flags of the first input.
+///
+/// ## Errors and panics
+///
+/// Errors with [MergeInputErr](errors::MergeInputErr) containing the index of
+/// the input that could not be decoded.
+///
+/// Panics if `conns_in` is empty.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::{decode_from_read_to_roaring, merge_from_reads_to_roaring, encode_to_write};
+/// use ahda::{EncodeOpts, MergeOp, PseudoAln};
+/// use roaring::RoaringTreemap;
+/// use std::io::Cursor;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+///
+/// // One alignment per input
+/// let inputs: Vec<Cursor<Vec<u8>>> = (0..4).map(|idx| {
+///     let data = vec![
+///         PseudoAln{ ones: Some(vec![idx % 2]), ones_names: None, query_id: Some(0), query_name: None },
+///         PseudoAln{ ones: Some(vec![]), ones_names: None, query_id: Some(1), query_name: None },
+///     ];
+///     let mut bytes: Vec<u8> = Vec::new();
+///     encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+///     Cursor::new(bytes)
+/// }).collect();
+///
+/// let (bitmap, _file_header, _file_flags, _block_flags) = merge_from_reads_to_roaring(inputs, &MergeOp::Union, 2).unwrap();
+///
+/// assert_eq!(bitmap, RoaringTreemap::from([0, 1]));
+/// ```
+///
+pub fn merge_from_reads_to_roaring<R: Read + Send>(
+    conns_in: Vec<R>,
+    merge_op: &MergeOp,
+    n_threads: usize,
+) -> Result<(RoaringTreemap, FileHeader, FileFlags, BlockFlags), E> {
+    assert!(!conns_in.is_empty());
+    let started = std::time::Instant::now();
+
+    // Queries in the output come from the first input, or from the first
+    // input with records if merging into an empty input adds them.
+    let n_inputs = conns_in.len();
+    let mut conns_in = conns_in.into_iter();
+    let mut first_idx = 0;
+    let (mut bitmap, header, flags, block_flags) = loop {
+        let mut conn = conns_in.next().unwrap();
+        let first = decode_from_read_to_roaring(&mut conn).map_err(|e| errors::MergeInputErr{ input: first_idx, msg: e.to_string() })?;
+        let is_empty = first.0.is_empty() && first.3.query_ids.as_ref().is_none_or(|x| x.is_empty());
+        if is_empty && matches!(merge_op, MergeOp::Union | MergeOp::Xor) && first_idx + 1 < n_inputs {
+            first_idx += 1;
+            continue
+        }
+        break first
+    };
+    let rest: Vec<std::sync::Mutex<Option<R>>> = conns_in.map(|conn| std::sync::Mutex::new(Some(conn))).collect();
+
+    // Inputs after the first are combined with this operation
+    let combine_op = match merge_op {
+        MergeOp::Diff => MergeOp::Union,
+        op => op.clone(),
+    };
+
+    let next_input = std::sync::atomic::AtomicUsize::new(0);
+    let n_threads = n_threads.clamp(1, rest.len().max(1));
+    let mut merged = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..n_threads).map(|_| s.spawn(|| {
+            let mut merged: Option<RoaringTreemap> = None;
+            loop {
+                let idx = next_input.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let Some(conn) = rest.get(idx) else { return Ok(merged) };
+                let mut conn = conn.lock().unwrap().take().unwrap();
+
+                let input_start = std::time::Instant::now();
+                let ret = match merged.as_mut() {
+                    Some(bitmap) => decode_from_read_into_roaring(&mut conn, &combine_op, bitmap),
+                    None => decode_from_read_to_roaring(&mut conn).map(|x| { merged = Some(x.0) }),
+                };
+                if let Err(e) = ret {
+                    return Err(errors::MergeInputErr{ input: first_idx + idx + 1, msg: e.to_string() })
+                }
+                log::debug!("merged input {} in {:.3}s", first_idx + idx + 1, input_start.elapsed().as_secs_f64());
+            }
+        })).collect();
+
+        let mut merged: Vec<RoaringTreemap> = Vec::with_capacity(n_threads);
+        for worker in workers {
+            if let Some(bitmap) = worker.join().unwrap()? {
+                merged.push(bitmap);
+            }
+        }
+        Ok::<_, errors::MergeInputErr>(merged)
+    })?;
+
+    // Merge the bitmaps from the workers pairwise
+    while merged.len() > 1 {
+        let mut pairs: Vec<(RoaringTreemap, Option<RoaringTreemap>)> = Vec::with_capacity(merged.len().div_ceil(2));
+        let mut it = merged.into_iter();
+        while let Some(a) = it.next() {
+            pairs.push((a, it.next()));
+        }
+        merged = std::thread::scope(|s| {
+            let handles: Vec<_> = pairs.into_iter().map(|(mut a, b)| s.spawn(|| {
+                if let Some(b) = b {
+                    match combine_op {
+                        MergeOp::Intersection => a &= b,
+                        MergeOp::Xor => a ^= b,
+                        _ => a |= b,
+                    }
+                }
+                a
+            })).collect();
+            handles.into_iter().map(|x| x.join().unwrap()).collect()
+        });
+    }
+
+    if let Some(other) = merged.pop() {
+        match merge_op {
+            MergeOp::Union => bitmap |= other,
+            MergeOp::Intersection => bitmap &= other,
+            MergeOp::Xor => bitmap ^= other,
+            MergeOp::Diff => bitmap -= other,
+        }
+    }
+    log::info!("merged {} inputs using {} threads in {:.2}s", n_inputs, n_threads, started.elapsed().as_secs_f64());
+
+    Ok((bitmap, header, flags, block_flags))
+}
+
+/// Remove the queries without set bits from a flattened pseudoalignment.
+///
+/// `bitmap` contains the bit `query_id * n_targets + target_id` for each
+/// alignment, like the output of [merge_from_reads_to_roaring]. The queries
+/// that have at least one alignment are renumbered from 0 in their original
+/// order.
+///
+/// Returns the renumbered bitmap, the names of the remaining queries, and
+/// the number of queries that were removed.
+///
+/// ## Errors and panics
+/// Panics if `bitmap` contains a query id that is not less than `queries.len()`.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::drop_empty_rows;
+/// use roaring::RoaringTreemap;
+///
+/// // Three queries and two targets, the second query has no alignments
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+/// let bitmap = RoaringTreemap::from([1, 4, 5]);
+///
+/// let (bitmap, queries, n_dropped) = drop_empty_rows(&bitmap, 2, &queries);
+///
+/// assert_eq!(bitmap, RoaringTreemap::from([1, 2, 3]));
+/// assert_eq!(queries, vec![b"r1".to_vec(), b"r3".to_vec()]);
+/// assert_eq!(n_dropped, 1);
+/// ```
+///
+pub fn drop_empty_rows(
+    bitmap: &RoaringTreemap,
+    n_targets: usize,
+    queries: &[Vec<u8>],
+) -> (RoaringTreemap, Vec<Vec<u8>>, usize) {
+    if n_targets == 0 {
+        return (RoaringTreemap::new(), Vec::new(), queries.len())
+    }
+    let n_targets = n_targets as u64;
+
+    let mut kept_bitmap = RoaringTreemap::new();
+    let mut kept_queries: Vec<Vec<u8>> = Vec::new();
+    let mut prev_query: Option<u64> = None;
+    for bit in bitmap.iter() {
+        let query_id = bit / n_targets;
+        if prev_query != Some(query_id) {
+            kept_queries.push(queries[query_id as usize].clone());
+            prev_query = Some(query_id);
+        }
+        kept_bitmap.insert((kept_queries.len() as u64 - 1) * n_targets + bit % n_targets);
+    }
+
+    let n_dropped = queries.len() - kept_queries.len();
+    (kept_bitmap, kept_queries, n_dropped)
+}
+
+/// Key for matching the same query in different inputs to [merge_records].
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum QueryKey {
+    Id(u32),
+    Name(Vec<u8>),
+}
+
+/// Perform a set operation on records that are already in memory.
+///
+/// Works like [merge_from_reads_to_roaring] on the records in `inputs`
+/// without encoding them first. The records are matched by
+/// [query_id](PseudoAln::query_id), or by [query_name](PseudoAln::query_name)
+/// if the record has no query id, and the set operation is performed on the
+/// alignment targets of the matched records. Records with the same query in
+/// one input are merged with union.
+///
+/// The output contains one record per query in the first input, in the order
+/// that they appear in. For [MergeOp::Union] and [MergeOp::Xor] the queries
+/// that are only in the other inputs are added after them. The output records
+/// keep the query id and name of the first record of the query and their
+/// [ones](PseudoAln::ones) are sorted; [ones_names](PseudoAln::ones_names) is
+/// not set.
+///
+/// For [MergeOp::Diff] the targets in any of the other inputs are removed
+/// from the first input.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::{merge_records, MergeOp, PseudoAln};
+///
+/// let first = vec![
+///     PseudoAln{ ones: Some(vec![0, 1]), ones_names: None, query_id: Some(0), query_name: None },
+///     PseudoAln{ ones: Some(vec![2]), ones_names: None, query_id: Some(1), query_name: None },
+/// ];
+/// let second = vec![
+///     PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(0), query_name: None },
+///     PseudoAln{ ones: Some(vec![0]), ones_names: None, query_id: Some(2), query_name: None },
+/// ];
+///
+/// let merged = merge_records(&[&first, &second], &MergeOp::Intersection).unwrap();
+///
+/// assert_eq!(merged, vec![
+///     PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(0), query_name: None },
+///     PseudoAln{ ones: Some(vec![]), ones_names: None, query_id: Some(1), query_name: None },
+/// ]);
+/// ```
+///
+/// ## Errors and panics
+///
+/// Errors with [IncompleteRecordErr](errors::IncompleteRecordErr) if a record
+/// has neither a query id nor a query name, or has no
+/// [ones](PseudoAln::ones).
+///
+pub fn merge_records(
+    inputs: &[&[PseudoAln]],
+    merge_op: &MergeOp,
+) -> Result<Vec<PseudoAln>, E> {
+    let mut merged: indexmap::IndexMap<QueryKey, (PseudoAln, RoaringBitmap)> = indexmap::IndexMap::new();
+    for (input, records) in inputs.iter().enumerate() {
+        // Alignments of each query in this input
+        let mut bitmaps: indexmap::IndexMap<QueryKey, (PseudoAln, RoaringBitmap)> = indexmap::IndexMap::new();
+        for (idx, record) in records.iter().enumerate() {
+            let key = match (record.query_id, record.query_name.as_ref()) {
+                (Some(query_id), _) => QueryKey::Id(query_id),
+                (None, Some(query_name)) => QueryKey::Name(query_name.clone()),
+                (None, None) => return Err(Box::new(errors::IncompleteRecordErr{ input, record: idx, field: "query id or query name".to_string() })),
+            };
+            let Some(ones) = record.ones.as_ref() else {
+                return Err(Box::new(errors::IncompleteRecordErr{ input, record: idx, field: "alignment targets".to_string() }))
+            };
+            let (_, bitmap) = bitmaps.entry(key).or_insert_with(|| {
+                (PseudoAln{ ones: None, ones_names: None, query_id: record.query_id, query_name: record.query_name.clone() }, RoaringBitmap::new())
+            });
+            bitmap.extend(ones.iter().copied());
+        }
+
+        if input == 0 {
+            merged = bitmaps;
+            continue
+        }
+
+        match merge_op {
+            MergeOp::Union | MergeOp::Xor => {
+                for (key, (record, bitmap)) in bitmaps {
+                    let (_, out) = merged.entry(key).or_insert_with(|| (record, RoaringBitmap::new()));
+                    if *merge_op == MergeOp::Union {
+                        *out |= bitmap;
+                    } else {
+                        *out ^= bitmap;
+                    }
+                }
+            },
+            MergeOp::Intersection => {
+                for (key, (_, out)) in merged.iter_mut() {
+                    match bitmaps.get(key) {
+                        Some((_, bitmap)) => *out &= bitmap,
+                        None => out.clear(),
+                    }
+                }
+            },
+            MergeOp::Diff => {
+                for (key, (_, out)) in merged.iter_mut() {
+                    if let Some((_, bitmap)) = bitmaps.get(key) {
+                        *out -= bitmap;
+                    }
+                }
+            },
+        }
     }
 
-    let bytes = encoder.encode_file_header_and_flags().unwrap();
-    conn_out.write_all(&bytes)?;
-    for block in encoder.by_ref() {
-        conn_out.write_all(&block)?;
-    }
+    Ok(merged.into_values().map(|(mut record, bitmap)| {
+        record.ones = Some(bitmap.into_iter().collect());
+        record
+    }).collect())
+}
 
-    Ok(())
+/// Similarity of the alignments stored in two .ahda records.
+///
+/// Counts are given as the number of set bits, ie. the number of (query,
+/// target) pairs that align, in the flattened pseudoalignments.
+///
+/// The `target_*` fields contain one value per target sequence, in the order
+/// of `target_names`. These are the targets of the first input followed by
+/// the targets that are only in the second input, see
+/// [TargetDict::merge](headers::file::TargetDict::merge).
+///
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Comparison {
+    /// Jaccard similarity of the set bits, `shared / (n_a + n_b - shared)`.
+    pub jaccard: f64,
+    /// Number of alignments in the first input.
+    pub n_a: u64,
+    /// Number of alignments in the second input.
+    pub n_b: u64,
+    /// Number of alignments present in both inputs.
+    pub n_shared: u64,
+    /// Number of queries aligned against each target in the first input.
+    pub target_a: Vec<u64>,
+    /// Number of queries aligned against each target in the second input.
+    pub target_b: Vec<u64>,
+    /// Number of queries aligned against each target in both inputs.
+    pub target_shared: Vec<u64>,
+    /// Names of the target sequences.
+    pub target_names: Vec<Vec<u8>>,
 }
 
-/// Parse all plain-text pseudoalignments from [Read] and encode to memory.
+/// Compare the alignments in two .ahda records from [Read].
+///
+/// Computes the Jaccard similarity of the set bits in the two records and
+/// counts the alignments per target without writing a merged record. Useful
+/// for checking concordance between two aligners run against the same index.
+///
+/// Inputs with different target sequences are compared in their merged
+/// targets, where targets with the same name are the same target.
+///
+/// If neither input contains any alignments, the Jaccard similarity is 1.0.
 ///
 /// ## Usage
+///
 /// ```rust
-/// use ahda::{encode_from_read, decode_from_read_to_write};
+/// use ahda::{compare_from_read, encode_to_write};
 /// use ahda::EncodeOpts;
-/// use ahda::Format;
+/// use ahda::PseudoAln;
 /// use std::io::{Cursor, Seek};
 ///
-/// // Mock inputs
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
-/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
-/// let name = "sample".as_bytes().to_vec();
+/// // Set up mock inputs
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec()];
+///
+/// let data_a = vec![
+///     PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some("r1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![0]), query_name: Some("r2".as_bytes().to_vec()) },
+/// ];
+/// let data_b = vec![
+///     PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: Some("r1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: Some("r2".as_bytes().to_vec()) },
+/// ];
+///
+/// let mut input_a: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+/// let mut input_b: Cursor<Vec<u8>> = Cursor::new(Vec::new());
 /// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
-/// opts.encode_query_names = true;
+/// opts.accession = "sample".as_bytes().to_vec();
+/// encode_to_write(&targets, &queries, &data_a, &mut input_a, opts.clone()).unwrap();
+/// encode_to_write(&targets, &queries, &data_b, &mut input_b, opts).unwrap();
+/// input_a.rewind();
+/// input_b.rewind();
 ///
-/// // Have this input data:
-/// //   3    r7543    chr.fasta:virus.fasta
-/// //   0    r1       virus.fasta
-/// //   4    r16      chr.fasta:plasmid.fasta:virus.fasta
-/// //   2    r651903
-/// //
-/// let mut input_bytes: Vec<u8> = Vec::new();
-/// input_bytes.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
-/// input_bytes.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
-/// input_bytes.append(&mut b"4\tr16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
-/// input_bytes.append(&mut b"2\tr651903\t\n".to_vec());
+/// let got = compare_from_read(&mut input_a, &mut input_b).unwrap();
 ///
-/// let mut input: Cursor<Vec<u8>> = Cursor::new(input_bytes.clone());
-/// let mut it = queries.into_iter();
-/// let mut t_it = targets.into_iter();
-/// let output = encode_from_read(Some(&mut t_it), Some(&mut it), &mut input, opts).unwrap();
+/// // Only `r1 -> chr.fasta` is shared out of 4 distinct alignments
+/// assert_eq!(got.n_shared, 1);
+/// assert_eq!(got.jaccard, 0.25);
+/// assert_eq!(got.target_a, vec![2, 1]);
+/// assert_eq!(got.target_b, vec![1, 1]);
+/// assert_eq!(got.target_shared, vec![1, 0]);
+/// ```
 ///
-/// // `output` can be decoded to get the original data back
-/// let mut encoded: Cursor<Vec<u8>> = Cursor::new(output);
-/// let mut decoded: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// decode_from_read_to_write(Format::Metagraph, &mut encoded, &mut decoded).unwrap();
+pub fn compare_from_read<R: Read, S: Read>(
+    conn_a: &mut R,
+    conn_b: &mut S,
+) -> Result<Comparison, E> {
+    let (bitmap_a, _, flags_a, _) = decode_from_read_to_roaring(conn_a)?;
+    let (bitmap_b, _, flags_b, _) = decode_from_read_to_roaring(conn_b)?;
+
+    let (targets, tables) = headers::file::TargetDict::merge(&[&headers::file::TargetDict::from_file_flags(&flags_a), &headers::file::TargetDict::from_file_flags(&flags_b)]);
+    let n_targets = targets.target_names.len() as u64;
+    let mut bitmaps = [bitmap_a, bitmap_b];
+    for (bitmap, table) in bitmaps.iter_mut().zip(tables.iter()) {
+        if !table.is_identity() || table.new_index.len() as u64 != n_targets {
+            *bitmap = table.remap_bitmap(bitmap, n_targets as u32)?;
+        }
+    }
+    let [bitmap_a, bitmap_b] = bitmaps;
+
+    let count_targets = |bitmap: &RoaringTreemap| -> Vec<u64> {
+        let mut counts: Vec<u64> = vec![0; n_targets as usize];
+        bitmap.iter().for_each(|bit| counts[(bit % n_targets) as usize] += 1);
+        counts
+    };
+
+    let n_a = bitmap_a.len();
+    let n_b = bitmap_b.len();
+    let n_shared = bitmap_a.intersection_len(&bitmap_b);
+    let n_union = n_a + n_b - n_shared;
+    let jaccard = if n_union > 0 { n_shared as f64 / n_union as f64 } else { 1.0 };
+
+    Ok(Comparison{
+        jaccard, n_a, n_b, n_shared,
+        target_a: count_targets(&bitmap_a),
+        target_b: count_targets(&bitmap_b),
+        target_shared: count_targets(&(&bitmap_a & &bitmap_b)),
+        target_names: targets.target_names,
+    })
+}
+
+/// Digest of the alignments stored in an .ahda record from [Read].
 ///
-/// assert_eq!(decoded.get_ref(), &input_bytes);
+/// Computes a SHA-256 hash over the target names and, for each query that
+/// aligns against at least one target in increasing query id order, the query
+/// id and its sorted target ids. The digest does not depend on how the record
+/// is split into blocks, how the blocks are compressed, or the order of the
+/// records in the input, so two files with the same digest contain the same
+/// alignments. The sample name, query names, and other metadata are not
+/// included.
+///
+/// The hashed bytes are `ahda-digest-v1\0`, the number of targets, the length
+/// and bytes of each target name, and for each query its id, number of
+/// targets, and the target ids, with the integers written as little-endian
+/// u64 except for the target ids, which are u32.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::digest_from_read;
+/// use ahda::encoder::Encoder;
+/// use ahda::PseudoAln;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let encode = |ids: Vec<u32>, block_size: usize| {
+///     let data: Vec<PseudoAln> = ids.into_iter().map(|id| {
+///         PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+///     }).collect();
+///     let mut iter = data.into_iter();
+///     let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 4);
+///     encoder.set_block_size(block_size).unwrap();
+///     let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+///     for block in encoder.by_ref() {
+///         bytes.append(&mut block.unwrap());
+///     }
+///     bytes
+/// };
+///
+/// // Same alignments in a different order and block layout
+/// let a = digest_from_read(&mut encode(vec![0, 1, 2, 3], 4).as_slice()).unwrap();
+/// let b = digest_from_read(&mut encode(vec![3, 1, 2, 0], 1).as_slice()).unwrap();
+/// assert_eq!(a, b);
+///
+/// let c = digest_from_read(&mut encode(vec![0, 1, 2], 4).as_slice()).unwrap();
+/// assert_ne!(a, c);
 /// ```
 ///
-pub fn encode_from_read<R: Read, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
-    targets: Option<&mut T>,
-    queries: Option<&mut Q>,
+pub fn digest_from_read<R: Read>(
     conn_in: &mut R,
-    opts: EncodeOpts,
-) -> Result<Vec<u8>, E> {
-    let have_queries = queries.is_some();
-    let mut reader = if let Some(format) = opts.format {
-        crate::parser::Parser::new_with_format(conn_in, queries, targets, format)?
-    } else {
-        crate::parser::Parser::new(conn_in, queries, targets)?
-    };
+) -> Result<[u8; 32], E> {
+    let (bitmap, header, flags, _) = decode_from_read_to_roaring(conn_in)?;
+    Ok(hash_alignments(&flags.target_names, header.n_targets.max(1) as u64, &bitmap))
+}
 
-    reader.fill_target_names(opts.encode_target_names);
-    reader.fill_query_name(opts.encode_query_names && have_queries);
-    let n_queries = reader.len();
+// Hash the bits `query_id * n_targets + target_id` as described in digest_from_read
+fn hash_alignments(
+    target_names: &[Vec<u8>],
+    n_targets: u64,
+    bitmap: &RoaringTreemap,
+) -> [u8; 32] {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(b"ahda-digest-v1\0");
+    hasher.update((target_names.len() as u64).to_le_bytes());
+    target_names.iter().for_each(|name| {
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name);
+    });
 
-    if !have_queries && reader.format != Format::Metagraph && reader.format != Format::Themisto && reader.format != Format::AhdaTSV {
-        return Err(Box::new(crate::errors::NeedQueryNamesErr{ format: reader.format }))
+    // Bits are sorted by query id and then by target id
+    let mut query: Option<u64> = None;
+    let mut ones: Vec<u32> = Vec::new();
+    let hash_query = |hasher: &mut sha2::Sha256, query_id: u64, ones: &mut Vec<u32>| {
+        hasher.update(query_id.to_le_bytes());
+        hasher.update((ones.len() as u64).to_le_bytes());
+        ones.drain(..).for_each(|target| hasher.update(target.to_le_bytes()));
+    };
+    for bit in bitmap.iter() {
+        let query_id = bit / n_targets;
+        if query.is_some_and(|x| x != query_id) {
+            hash_query(&mut hasher, query.unwrap(), &mut ones);
+        }
+        query = Some(query_id);
+        ones.push((bit % n_targets) as u32);
     }
+    if let Some(query_id) = query {
+        hash_query(&mut hasher, query_id, &mut ones);
+    }
+
+    hasher.finalize().into()
+}
 
+// Collect the target names and the alignments of the records from `reader`
+fn parse_to_roaring<R: Read>(
+    reader: &mut parser::Parser<R>,
+) -> Result<(Vec<Vec<u8>>, RoaringTreemap), E> {
+    reader.fill_query_name(false);
+    reader.fill_target_names(false);
     let targets = reader.get_targets().unwrap();
-    let mut encoder = encoder::Encoder::new(&mut reader, &targets, &opts.accession, n_queries);
-    if opts.encode_query_names && have_queries {
-        encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
-    } else {
-        encoder.set_fields_present(crate::MASK_QUERY_IDS);
-    }
+    let n_targets = targets.len().max(1) as u64;
 
-    let mut bytes = encoder.encode_file_header_and_flags().unwrap();
-    for mut block in encoder.by_ref() {
-        bytes.append(&mut block);
+    let mut bitmap = RoaringTreemap::new();
+    while let Some(record) = reader.try_next() {
+        let record = record?;
+        let query_id = record.query_id.ok_or(errors::UnknownQueryErr{})? as u64;
+        bitmap.extend(record.ones.unwrap_or_default().iter().map(|target| query_id * n_targets + *target as u64));
     }
-    Ok(bytes)
+    Ok((targets, bitmap))
 }
 
-/// Parse all plain-text pseudoalignments from [Read] and encode to [Write].
-///
-/// ## Usage
-/// ```rust
-/// use ahda::{encode_from_read_to_write, decode_from_read_to_write};
-/// use ahda::Format;
-/// use ahda::EncodeOpts;
-/// use std::io::{Cursor, Seek};
-///
-/// // Mock inputs
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
-/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
-/// let name = "sample".as_bytes().to_vec();
+/// Digest of the alignments in plain text data from [Read].
 ///
-/// // Have this input data:
-/// //   3    r7543    chr.fasta:virus.fasta
-/// //   0    r1       virus.fasta
-/// //   4    r16      chr.fasta:plasmid.fasta:virus.fasta
-/// //   2    r651903
-/// //
-/// let mut input_bytes: Vec<u8> = Vec::new();
-/// input_bytes.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
-/// input_bytes.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
-/// input_bytes.append(&mut b"4\tr16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
-/// input_bytes.append(&mut b"2\tr651903\t\n".to_vec());
+/// Parses the records like [convert_stream] and hashes them like
+/// [digest_from_read], so the digest of a plain text file equals the digest
+/// of the .ahda file encoded from it with the same target and query names.
 ///
-/// let mut input: Cursor<Vec<u8>> = Cursor::new(input_bytes.clone());
+/// ## Usage
 ///
-/// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// let mut it = queries.into_iter();
-/// let mut t_it = targets.into_iter();
-/// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
-/// opts.encode_query_names = true;
-/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut input, &mut output, opts).unwrap();
+/// ```rust
+/// use ahda::digest_plain_text_from_read;
 ///
-/// // `output` can be decoded to get the original data back
-/// output.rewind();
-/// let mut decoded: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// decode_from_read_to_write(Format::Metagraph, &mut output, &mut decoded).unwrap();
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let themisto = b"0 1\n1 0 1\n".to_vec();
+/// let reordered = b"1 1 0\n0 1\n".to_vec();
 ///
-/// assert_eq!(decoded.get_ref(), &input_bytes);
+/// let a = digest_plain_text_from_read(Some(&mut targets.iter().cloned()), None::<&mut std::iter::Empty<Vec<u8>>>, &mut themisto.as_slice()).unwrap();
+/// let b = digest_plain_text_from_read(Some(&mut targets.iter().cloned()), None::<&mut std::iter::Empty<Vec<u8>>>, &mut reordered.as_slice()).unwrap();
+/// assert_eq!(a, b);
 /// ```
 ///
-pub fn encode_from_read_to_write<R: Read, W: Write, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
+/// ## Errors and panics
+/// Errors if the input format can't be detected, if the targets are not
+/// given and the input has no header with them, and if a record can't be
+/// parsed or has no query id.
+///
+pub fn digest_plain_text_from_read<R: Read, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
     targets: Option<&mut T>,
     queries: Option<&mut Q>,
     conn_in: &mut R,
-    conn_out: &mut W,
-    opts: EncodeOpts,
-) -> Result<(), E> {
-    let have_queries = queries.is_some();
+) -> Result<[u8; 32], E> {
+    let mut reader = parser::Parser::new(conn_in, queries, targets)?;
+    let (target_names, bitmap) = parse_to_roaring(&mut reader)?;
+    Ok(hash_alignments(&target_names, target_names.len().max(1) as u64, &bitmap))
+}
 
-    let mut reader = if let Some(format) = opts.format {
-        crate::parser::Parser::new_with_format(conn_in, queries, targets, format)?
+/// Check that `output` converted from `input` contains the same alignments.
+///
+/// `input` is either an .ahda record or plain text, and `output` is plain
+/// text in any format that the [Parser](parser::Parser) reads. Plain text
+/// input is parsed with `targets` and `queries` like in [convert_stream],
+/// and the output is parsed with the target names of the input and the same
+/// query names. The query names of .ahda input are read from its records.
+/// Returns the digest of both, see [digest_from_read].
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::convert_stream;
+/// use ahda::round_trip_check;
+/// use ahda::Format;
+/// use ahda::printer::TargetSelection;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+/// let input = b"0 1\n1 0 1\n".to_vec();
+///
+/// let mut output: Vec<u8> = Vec::new();
+/// convert_stream(Some(&mut targets.iter().cloned()), Some(&mut queries.iter().cloned()), b"sample", Format::Fulgor, &TargetSelection::default(), None, 1000, &mut input.as_slice(), &mut output).unwrap();
+/// assert!(round_trip_check(&input, &output, Some(&targets), Some(&queries)).is_ok());
+///
+/// // One alignment went missing
+/// let lossy = b"r1\t1\t1\nr2\t1\t0\n".to_vec();
+/// assert!(round_trip_check(&input, &lossy, Some(&targets), Some(&queries)).is_err());
+/// ```
+///
+/// ## Errors and panics
+/// Errors with [RoundTripMismatchErr](errors::RoundTripMismatchErr) if the
+/// digests differ, and if either input can't be parsed.
+///
+pub fn round_trip_check(
+    input: &[u8],
+    output: &[u8],
+    targets: Option<&[Vec<u8>]>,
+    queries: Option<&[Vec<u8>]>,
+) -> Result<[u8; 32], E> {
+    let (target_names, input_bitmap, policy) = if is_ahda_file(&mut &input[..]) {
+        let (_, flags, records) = decode_from_read(&mut &input[..])?;
+        let n_targets = flags.target_names.len().max(1) as u64;
+        let mut bitmap = RoaringTreemap::new();
+        let mut query_ids: std::collections::HashMap<Vec<u8>, u32> = std::collections::HashMap::new();
+        for record in records {
+            let query_id = record.query_id.ok_or(errors::UnknownQueryErr{})?;
+            if let Some(name) = record.query_name {
+                query_ids.insert(name, query_id);
+            }
+            bitmap.extend(record.ones.unwrap_or_default().iter().map(|target| query_id as u64 * n_targets + *target as u64));
+        }
+        (flags.target_names, bitmap, QueryIdPolicy::Map(query_ids))
     } else {
-        crate::parser::Parser::new(conn_in, queries, targets)?
+        let mut conn_in = input;
+        let mut reader = parser::Parser::new(&mut conn_in, queries.map(|x| x.iter().cloned()).as_mut(), targets.map(|x| x.iter().cloned()).as_mut())?;
+        let (target_names, bitmap) = parse_to_roaring(&mut reader)?;
+        (target_names, bitmap, QueryIdPolicy::FromList)
     };
-    reader.fill_target_names(opts.encode_target_names);
-    reader.fill_query_name(opts.encode_query_names && have_queries && !opts.rename_queries);
-    let n_queries = reader.len();
-
-    if !have_queries && reader.format != Format::Metagraph && reader.format != Format::Themisto && reader.format != Format::AhdaTSV {
-        return Err(Box::new(crate::errors::NeedQueryNamesErr{ format: reader.format }))
-    }
-
-    let targets = reader.get_targets().unwrap();
+    let input_digest = hash_alignments(&target_names, target_names.len().max(1) as u64, &input_bitmap);
 
-    // TODO remove unwrap
-    let mut encoder = encoder::Encoder::new(&mut reader, &targets, &opts.accession, n_queries);
-    if opts.encode_query_names && have_queries {
-        encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
-    } else {
-        encoder.set_fields_present(crate::MASK_QUERY_IDS);
-    }
+    let output_queries = if policy == QueryIdPolicy::FromList { queries } else { None };
+    let mut conn_out = output;
+    let mut reader = parser::Parser::new(&mut conn_out, output_queries.map(|x| x.iter().cloned()).as_mut(), Some(&mut target_names.iter().cloned()))?;
+    reader.set_query_id_policy(policy);
+    let (_, output_bitmap) = parse_to_roaring(&mut reader)?;
+    let output_digest = hash_alignments(&target_names, target_names.len().max(1) as u64, &output_bitmap);
 
-    let bytes = encoder.encode_file_header_and_flags().unwrap();
-    conn_out.write_all(&bytes)?;
-    for block in encoder.by_ref() {
-        conn_out.write_all(&block)?;
-        conn_out.flush().unwrap();
+    if input_digest != output_digest {
+        return Err(Box::new(errors::RoundTripMismatchErr{ input_digest, output_digest }))
     }
+    Ok(input_digest)
+}
 
-    Ok(())
+/// Key for matching the mates of a paired-end read.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum MateKey {
+    Name(Vec<u8>),
+    Id(u32),
 }
 
-/// Decode all pseudoalignments from [Read] and format to [Write].
+/// Merge the alignments of the two mates of paired-end reads from [Read] and encode to [Write].
+///
+/// Aligners that align the mates of paired-end reads separately produce one
+/// file per mate with identical read names. The records in `conn_first` and
+/// `conn_second` are matched by query name, ignoring a `/1` or `/2` suffix,
+/// or by query id if the inputs don't contain the query names.
+///
+/// If `keep_mates` is false, the output contains one record per read with the
+/// union of the targets of its mates. Otherwise both mates are kept as separate
+/// records, so the output records which mate aligned against which target. The
+/// second mates are numbered after all of the first mates and marked in the
+/// block flags (see [BlockFlags::mate]), replacing `opts.second_mates`. A
+/// mate that is missing from its input does not align against any target.
+/// If the inputs have different target sequences, the output has the
+/// targets of both, see [TargetDict::merge](headers::file::TargetDict::merge).
+///
+/// The reads are numbered from 0 in the order of their query ids in
+/// `conn_first`, followed by the reads that are only in `conn_second`.
 ///
 /// ## Usage
+///
 /// ```rust
-/// use ahda::{decode_from_read_to_write, encode_from_read_to_write};
-/// use ahda::Format;
+/// use ahda::{decode_from_read, encode_to_write, merge_mates_from_reads_to_write};
 /// use ahda::EncodeOpts;
-/// use std::io::{Cursor, Seek};
-///
-/// // Set up mock inputs
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
-/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
-/// let name = "sample".as_bytes().to_vec();
-///
-/// let mut plaintext_bytes: Vec<u8> = Vec::new();
-/// plaintext_bytes.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
-/// plaintext_bytes.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
-/// plaintext_bytes.append(&mut b"4\tr16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
-/// plaintext_bytes.append(&mut b"2\tr651903\t\n".to_vec());
+/// use ahda::PseudoAln;
+/// use std::io::Cursor;
 ///
-/// // Encode mock data
-/// let mut plaintext: Cursor<Vec<u8>> = Cursor::new(plaintext_bytes.clone());
-/// let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// let mut it = queries.into_iter();
-/// let mut t_it = targets.into_iter();
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec()];
 /// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
+/// opts.accession = "sample".as_bytes().to_vec();
 /// opts.encode_query_names = true;
-/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut plaintext, &mut input, opts).unwrap();
-/// input.rewind();
 ///
-/// // Decode all alignments and compare against the original inputs
-/// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// decode_from_read_to_write(Format::Metagraph, &mut input, &mut output).unwrap();
+/// let data_first = vec![
+///     PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: Some("r1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![]), query_name: Some("r2".as_bytes().to_vec()) },
+/// ];
+/// let data_second = vec![
+///     PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![1]), query_name: Some("r1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![0]), query_name: Some("r2".as_bytes().to_vec()) },
+/// ];
+/// let mut first: Vec<u8> = Vec::new();
+/// let mut second: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &queries, &data_first, &mut first, opts.clone()).unwrap();
+/// encode_to_write(&targets, &queries, &data_second, &mut second, opts.clone()).unwrap();
 ///
-/// assert_eq!(output.get_ref(), plaintext.get_ref());
+/// let mut merged: Vec<u8> = Vec::new();
+/// merge_mates_from_reads_to_write(&mut first.as_slice(), &mut second.as_slice(), &mut merged, false, opts).unwrap();
+///
+/// let (_, _, mut alns) = decode_from_read(&mut Cursor::new(merged)).unwrap();
+/// alns.sort_by_key(|x| x.query_id);
+/// assert_eq!(alns[0].ones, Some(vec![0, 1]));
+/// assert_eq!(alns[1].ones, Some(vec![0]));
 /// ```
 ///
-pub fn decode_from_read_to_write<R: Read, W: Write>(
-    out_format: Format,
-    conn_in: &mut R,
+pub fn merge_mates_from_reads_to_write<R: Read, S: Read, W: Write>(
+    conn_first: &mut R,
+    conn_second: &mut S,
     conn_out: &mut W,
+    keep_mates: bool,
+    mut opts: EncodeOpts,
 ) -> Result<(), E> {
-    let mut decoder = decoder::Decoder::new(conn_in);
+    let (_, flags_first, mut first) = decode_from_read(conn_first)?;
+    let (_, flags_second, mut second) = decode_from_read(conn_second)?;
+    let (targets, tables) = headers::file::TargetDict::merge(&[&headers::file::TargetDict::from_file_flags(&flags_first), &headers::file::TargetDict::from_file_flags(&flags_second)]);
+    for (records, table) in [&mut first, &mut second].into_iter().zip(tables.iter()) {
+        if !table.is_identity() {
+            records.iter_mut().try_for_each(|record| {
+                record.ones = record.ones.as_deref().map(|ones| table.remap(ones)).transpose()?;
+                Ok::<(), E>(())
+            })?;
+        }
+    }
+    first.sort_by_key(|x| x.query_id);
+    second.sort_by_key(|x| x.query_id);
 
-    match out_format {
-        Format::Themisto => {
-            decoder.fill_target_names(false);
-            decoder.fill_query_name(false);
-        },
-        Format::Fulgor => {
-            decoder.fill_target_names(false);
-            decoder.fill_query_id(false);
-        },
-        Format::Bifrost => {
-            decoder.fill_target_names(false);
-            decoder.fill_query_id(false);
-        },
-        Format::SAM => {
-            decoder.fill_target_names(false);
-            decoder.fill_query_id(false);
-        },
-        Format::Metagraph => {
-            decoder.fill_target_ids(false);
-        },
-        Format::AhdaTSV => {
-            decoder.fill_target_names(false);
-        },
+    let key = |record: &PseudoAln| -> Result<MateKey, E> {
+        match &record.query_name {
+            Some(name) => Ok(MateKey::Name(crate::printer::split_mate_suffix(name).0.to_vec())),
+            None => Ok(MateKey::Id(record.query_id.ok_or(errors::UnknownQueryErr{})?)),
+        }
+    };
+
+    let mut reads: indexmap::IndexMap<MateKey, [Option<Vec<u32>>; 2]> = indexmap::IndexMap::new();
+    for (mate, records) in [first, second].into_iter().enumerate() {
+        for record in records {
+            let read = key(&record)?;
+            reads.entry(read).or_default()[mate] = record.ones;
+        }
     }
 
-    let header = decoder.file_header().clone();
-    let flags = decoder.file_flags().clone();
-    let printer = printer::Printer::new_from_header_and_flags(&mut decoder, header.clone(), flags.clone(), out_format.clone());
-    for line in printer {
-        conn_out.write_all(&line)?;
+    let n_reads = reads.len() as u32;
+    let have_names = reads.keys().all(|x| matches!(x, MateKey::Name(_)));
+    opts.encode_query_names &= have_names;
+
+    let mut records: Vec<PseudoAln> = Vec::with_capacity(reads.len() * if keep_mates { 2 } else { 1 });
+    let mut queries: Vec<Vec<u8>> = Vec::with_capacity(records.capacity());
+    let mut second_names: Vec<Vec<u8>> = Vec::new();
+    for (query_id, (read, mates)) in reads.into_iter().enumerate() {
+        let name = match read {
+            MateKey::Name(name) => name,
+            MateKey::Id(_) => Vec::new(),
+        };
+
+        if keep_mates {
+            let [ones_first, ones_second] = mates;
+            let (mut name_first, mut name_second) = (name.clone(), name);
+            name_first.extend_from_slice(b"/1");
+            name_second.extend_from_slice(b"/2");
+            records.push(PseudoAln{ ones_names: None, query_id: Some(query_id as u32), ones: Some(ones_first.unwrap_or_default()), query_name: have_names.then(|| name_first.clone()) });
+            records.push(PseudoAln{ ones_names: None, query_id: Some(n_reads + query_id as u32), ones: Some(ones_second.unwrap_or_default()), query_name: have_names.then(|| name_second.clone()) });
+            queries.push(name_first);
+            second_names.push(name_second);
+        } else {
+            let mut ones: Vec<u32> = mates.into_iter().flatten().flatten().collect();
+            ones.sort_unstable();
+            ones.dedup();
+            records.push(PseudoAln{ ones_names: None, query_id: Some(query_id as u32), ones: Some(ones), query_name: have_names.then(|| name.clone()) });
+            queries.push(name);
+        }
     }
-    conn_out.flush().unwrap();
+    queries.append(&mut second_names);
 
-    Ok(())
+    opts.second_mates = if keep_mates {
+        let mut second_mates = RoaringBitmap::new();
+        second_mates.insert_range(n_reads..(2 * n_reads));
+        Some(second_mates)
+    } else {
+        None
+    };
+
+    encode_to_write(&targets.target_names, &queries, &records, conn_out, opts)
 }
 
-/// Decode all pseudoalignments from [Read] to memory.
+/// Build Bloom filters of the names of the aligned queries in an .ahda record from [Read].
+///
+/// Returns one filter named `aligned` that contains the queries that aligned
+/// against any target. If `level` is given, returns one filter per target
+/// group at `level` instead, named after the group and containing the queries
+/// that aligned against any target in the group.
+///
+/// Each filter is sized for the number of names inserted into it so that
+/// names that were not inserted are found with probability `fpr`. The names
+/// of the aligned queries are held in memory while building the filters.
 ///
 /// ## Usage
+///
 /// ```rust
-/// use ahda::{decode_from_read, encode_to_write};
+/// use ahda::{encode_to_write, export_filter_from_read};
 /// use ahda::EncodeOpts;
 /// use ahda::PseudoAln;
-/// use std::io::{Cursor, Seek};
 ///
-/// // Mock data
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec()];
 /// let data = vec![
-///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("ERR4035126.1".as_bytes().to_vec()) },
-///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![0]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![1]), query_name: Some("r1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![]), query_name: Some("r2".as_bytes().to_vec()) },
 /// ];
-///
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
-/// let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
-/// let name = "ERR4035126".as_bytes().to_vec();
 /// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
 /// opts.encode_query_names = true;
 ///
-/// // Encode mock data
-/// let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// encode_to_write(&targets, &queries, &data, &mut input, opts).unwrap();
-/// input.rewind();
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
 ///
-/// // Decode to recover the original data
-/// let (_file_header, _file_flags, alns) = decode_from_read(&mut input).unwrap();
+/// let filters = export_filter_from_read(&mut bytes.as_slice(), 0.001, None).unwrap();
+/// assert_eq!(filters.names, vec![b"aligned".to_vec()]);
+/// assert!(filters.filters[0].contains(b"r1"));
+/// ```
 ///
-/// assert_eq!(alns, data);
+/// ## Errors and panics
 ///
-pub fn decode_from_read<R: Read>(
+/// Errors with [MissingQueryNamesErr](errors::MissingQueryNamesErr) if the
+/// record does not contain the query names and with
+/// [UnknownTargetGroupErr](errors::UnknownTargetGroupErr) if there are no
+/// target groups at `level`.
+///
+pub fn export_filter_from_read<R: Read>(
     conn_in: &mut R,
-) -> Result<(FileHeader, FileFlags, Vec<PseudoAln>), E> {
-    let decoder = decoder::Decoder::new(conn_in);
-
-    let header = decoder.file_header().clone();
-    let flags = decoder.file_flags().clone();
+    fpr: f64,
+    level: Option<&[u8]>,
+) -> Result<bloom::FilterSet, E> {
+    let mut decoder = decoder::Decoder::try_new(conn_in)?;
+    let group_names: Vec<Vec<u8>> = match level {
+        Some(level) => decoder.collapse(level)?.1.target_names,
+        None => vec![b"aligned".to_vec()],
+    };
+    decoder.fill_target_names(false);
+
+    // Indexes in `names` of the queries in each filter
+    let mut names: Vec<Vec<u8>> = Vec::new();
+    let mut members: Vec<Vec<u32>> = vec![Vec::new(); group_names.len()];
+    for record in decoder.try_iter() {
+        let record = record?;
+        let ones = record.ones.unwrap_or_default();
+        if ones.is_empty() {
+            continue
+        }
+        names.push(record.query_name.ok_or(errors::MissingQueryNamesErr{})?);
+        let idx = (names.len() - 1) as u32;
+        match level {
+            Some(_) => ones.iter().for_each(|group| members[*group as usize].push(idx)),
+            None => members[0].push(idx),
+        }
+    }
 
-    let mut alns: Vec<PseudoAln> = Vec::with_capacity(header.n_queries as usize);
-    alns.extend(decoder);
+    let filters = members.iter().map(|indexes| {
+        let mut filter = bloom::BloomFilter::with_fpr(indexes.len(), fpr);
+        indexes.iter().for_each(|idx| filter.insert(&names[*idx as usize]));
+        filter
+    }).collect();
 
-    Ok((header, flags, alns))
+    Ok(bloom::FilterSet{ names: group_names, filters })
 }
 
-/// Decode from memory and format to [Write].
+/// List the names of the queries aligned to each target in an .ahda record from [Read].
+///
+/// Returns the target names and the names of the queries that aligned to
+/// them in order of query id. Targets that no query aligned to are included
+/// with an empty list. If `level` is given, the queries are listed for each
+/// target group at that level instead.
+///
+/// The record is read once with [Decoder::iter_targets_with_query_names](decoder::Decoder::iter_targets_with_query_names).
 ///
 /// ## Usage
+///
 /// ```rust
-/// use ahda::{decode_to_write, encode_to_write};
-/// use ahda::{Format, PseudoAln, EncodeOpts};
-/// use std::io::{Cursor, Seek};
+/// use ahda::{encode_to_write, export_reads_from_read};
+/// use ahda::EncodeOpts;
+/// use ahda::PseudoAln;
 ///
-/// // Mock data
+/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+/// let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec()];
 /// let data = vec![
-///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("ERR4035126.1".as_bytes().to_vec()) },
-///     PseudoAln{ones_names: Some(vec!["chr.fasta".as_bytes().to_vec()]),  query_id: Some(1), ones: Some(vec![0]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![1]), query_name: Some("r1".as_bytes().to_vec()) },
+///     PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![0, 1]), query_name: Some("r2".as_bytes().to_vec()) },
 /// ];
-///
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
-/// let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
-/// let name = "ERR4035126".as_bytes().to_vec();
 /// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
-/// opts.encode_query_names = false;
+/// opts.encode_query_names = true;
 ///
-/// // Encode mock data
-/// let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// encode_to_write(&targets, &queries, &data, &mut input, opts).unwrap();
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
 ///
-/// // Decode to recover the original data
-/// let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// decode_to_write(Format::Metagraph, input.get_ref(), &mut output).unwrap();
+/// let got = export_reads_from_read(&mut bytes.as_slice(), None).unwrap();
+/// assert_eq!(got[0], (b"chr.fasta".to_vec(), vec![b"r2".to_vec()]));
+/// assert_eq!(got[1], (b"plasmid.fasta".to_vec(), vec![b"r1".to_vec(), b"r2".to_vec()]));
+/// ```
 ///
-/// // Expect this output data:
-/// //   0    ERR4035126.1    chr.fasta
-/// //   1    ERR4035126.2    chr.fasta
-/// let mut expected: Vec<u8> = Vec::new();
-/// expected.append(&mut b"0\tERR4035126.1\tchr.fasta\n".to_vec());
-/// expected.append(&mut b"1\tERR4035126.2\tchr.fasta\n".to_vec());
+/// ## Errors and panics
 ///
-/// assert_eq!(output.get_ref(), &expected);
+/// Errors with [MissingQueryNamesErr](errors::MissingQueryNamesErr) if the
+/// record does not contain the query names and with
+/// [UnknownTargetGroupErr](errors::UnknownTargetGroupErr) if there are no
+/// target groups at `level`.
 ///
-pub fn decode_to_write<W: Write>(
-    out_format: Format,
-    records: &[u8],
-    conn_out: &mut W,
-) -> Result<(), E> {
-    let mut tmp = std::io::Cursor::new(&records);
-    let mut decoder = decoder::Decoder::new(&mut tmp);
+#[allow(clippy::type_complexity)]
+pub fn export_reads_from_read<R: Read>(
+    conn_in: &mut R,
+    level: Option<&[u8]>,
+) -> Result<Vec<(Vec<u8>, Vec<Vec<u8>>)>, E> {
+    let mut decoder = decoder::Decoder::try_new(conn_in)?;
+    let target_names: Vec<Vec<u8>> = match level {
+        Some(level) => decoder.collapse(level)?.1.target_names,
+        None => decoder.file_flags().target_names.clone(),
+    };
 
-    match out_format {
-        Format::Themisto => {
-            decoder.fill_target_names(false);
-            decoder.fill_query_name(false);
-        },
-        Format::Fulgor => {
-            decoder.fill_target_names(false);
-            decoder.fill_query_id(false);
-        },
-        Format::Bifrost => {
-            decoder.fill_target_names(false);
-            decoder.fill_query_id(false);
-        },
-        Format::SAM => {
-            decoder.fill_target_names(false);
-            decoder.fill_query_id(false);
-        },
-        Format::Metagraph => {
-            decoder.fill_target_ids(false);
-        },
-        Format::AhdaTSV => {
-            decoder.fill_target_names(false);
-        },
-    }
+    let (targets, names) = decoder.iter_targets_with_query_names()?;
+    targets.map(|(target_idx, query_ids)| {
+        let query_names = query_ids.iter().map(|query_id| names.get(&query_id).cloned().ok_or(errors::MissingQueryNamesErr{})).collect::<Result<Vec<Vec<u8>>, _>>()?;
+        Ok((target_names[target_idx as usize].clone(), query_names))
+    }).collect()
+}
 
-    let header = decoder.file_header().clone();
-    let flags = decoder.file_flags().clone();
-    let printer = printer::Printer::new_from_header_and_flags(&mut decoder, header.clone(), flags.clone(), out_format.clone());
-    for line in printer {
-        conn_out.write_all(&line)?;
+/// How the contents of a block are stored, see [BlockInfo].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockCodec {
+    /// Gzipped bitmap.
+    Gzip,
+    /// Uncompressed bitmap, see [BLOCK_PAYLOAD_RAW](compression::BLOCK_PAYLOAD_RAW).
+    Raw,
+    /// Same contents as the block at this index, see [dedup](compression::dedup).
+    Reference(usize),
+}
+
+impl std::fmt::Display for BlockCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockCodec::Gzip => write!(f, "gzip"),
+            BlockCodec::Raw => write!(f, "raw"),
+            BlockCodec::Reference(index) => write!(f, "ref:{}", index),
+        }
     }
-    conn_out.flush().unwrap();
+}
 
-    Ok(())
+/// Layout of a block in an .ahda record, see [block_info_from_read].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockInfo {
+    /// Position of the block in the record, starting from 0.
+    pub index: usize,
+    /// Byte offset of the block header from the start of the record.
+    pub offset: u64,
+    /// Number of bytes in the block, including the block header and flags.
+    pub len: u64,
+    /// Smallest query id in the block, or None if the block has no query ids.
+    pub start_query_id: Option<u32>,
+    /// Number of records in the block.
+    pub num_records: u32,
+    /// Number of bytes in the stored bitmap, 0 for references.
+    pub compressed_len: u64,
+    /// Number of bytes in the serialized bitmap after decompression.
+    pub raw_len: u64,
+    /// How the bitmap is stored.
+    pub codec: BlockCodec,
+    /// Set bits in the block, or None if the block was written without them.
+    pub stats: Option<BlockStats>,
 }
 
-/// Reads the full bitmap and combined block flags from a file
+/// List the blocks of an .ahda record from [Read].
 ///
-/// Returns the [RoaringBitmap](roaring::RoaringBitmap) containing all
-/// alignments from every block in the input, and the corresponding file header,
-/// file flags, and block flags for a single block containing all the data.
+/// Only the block headers and flags are decoded. The block contents are
+/// skipped without decompressing them, the decompressed size of gzipped
+/// contents is read from the gzip trailer.
 ///
 /// ## Usage
 ///
 /// ```rust
-/// use ahda::{decode_from_read_to_roaring, encode_from_read_to_write};
-/// use ahda::Format;
-/// use ahda::EncodeOpts;
-/// use ahda::headers::file::{FileHeader, FileFlags};
-/// use ahda::headers::block::{BlockHeader, BlockFlags};
-/// use roaring::RoaringTreemap;
-/// use std::io::{Cursor, Seek};
-///
-/// // Set up mock inputs
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
-/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
-/// let name = "sample".as_bytes().to_vec();
+/// use ahda::{block_info_from_read, BlockCodec};
+/// use ahda::encoder::Encoder;
+/// use ahda::PseudoAln;
 ///
-/// let mut plaintext_bytes: Vec<u8> = Vec::new();
-/// plaintext_bytes.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
-/// plaintext_bytes.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
-/// plaintext_bytes.append(&mut b"4\tr16\tchr.fasta:plasmid.fasta:virus.fasta\n".to_vec());
-/// plaintext_bytes.append(&mut b"2\tr651903\t\n".to_vec());
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let data: Vec<PseudoAln> = (0..5).map(|id| {
+///     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+/// }).collect();
 ///
-/// // Encode mock data
-/// let mut plaintext: Cursor<Vec<u8>> = Cursor::new(plaintext_bytes.clone());
-/// let mut input: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// let mut it = queries.into_iter();
-/// let mut t_it = targets.into_iter();
-/// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
-/// opts.encode_query_names = true;
-/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut plaintext, &mut input, opts).unwrap();
-/// input.rewind();
+/// // Encode in blocks of 2 queries
+/// let mut iter = data.into_iter();
+/// let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 5);
+/// encoder.set_block_size(2).unwrap();
+/// let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+/// for block in encoder.by_ref() {
+///     bytes.append(&mut block.unwrap());
+/// }
 ///
-/// // Decode all alignments and compare against the original inputs
-/// let (bitmap, file_header, file_flags, block_flags) = decode_from_read_to_roaring(&mut input).unwrap();
+/// let blocks = block_info_from_read(&mut bytes.as_slice()).unwrap();
+/// assert_eq!(blocks.len(), 3);
+/// assert_eq!(blocks[2].start_query_id, Some(4));
+/// assert_eq!(blocks[2].num_records, 1);
+/// assert_eq!(blocks[2].offset + blocks[2].len, bytes.len() as u64);
+/// assert!(matches!(blocks[0].codec, BlockCodec::Gzip | BlockCodec::Raw));
+/// ```
 ///
-/// // Expect these outputs:
-/// //   RoaringTreemap<[2, 9, 11, 12, 13, 14]>
-/// // FileHeader{
-/// //                                    n_targets: 3_u32,
-/// //                                   n_queries: 5_u32,
-/// //                                   ahda_header: ahda::headers::file::build_ahda_header(),
-/// //                                   file_format: ahda::AhdaFormatVersion::V1_0_0.to_u8(),
-/// //                                   metadata_compression: ahda::compression::MetadataCompression::default().to_u8(),
-/// //                                   fields_present: 3,
-/// //                                   bitmap_type: ahda::compression::BitmapType::Roaring32.to_u16(),
-/// //                                   block_size: 65536_u32,
-/// //                                   flags_len: 46_u64,
-/// //                                 }
-/// //   FileFlags    { query_name: "sample", target_names: vec!["chr.fasta", "plasmid.fasta", "virus.fasta"] }
-/// //   BlockFlags   { queries: Some(["r1", "r651903", "r7543", "r16"]), query_ids: Some([0, 2, 3, 4]) }
+/// ## Errors and panics
 ///
-/// assert_eq!(bitmap, RoaringTreemap::from([2, 9, 11, 12, 13, 14]));
-/// assert_eq!(file_header, FileHeader{
-///                                      n_targets: 3_u32,
-///                                      n_queries: 5_u32,
-///                                      ahda_header: ahda::headers::file::build_ahda_header(),
-///                                      file_format: ahda::AhdaFormatVersion::V1_0_0.to_u8(),
-///                                      metadata_compression: ahda::compression::MetadataCompression::default().to_u8(),
-///                                      fields_present: 3,
-///                                      bitmap_type: ahda::compression::BitmapType::Roaring32.to_u16(),
-///                                      block_size: 65536_u32,
-///                                      flags_len: 44_u64,
-///                                    });
-/// let mut expected_flags = FileFlags::default();
-/// expected_flags.query_name = "sample".as_bytes().to_vec();
-/// expected_flags.target_names = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
-/// assert_eq!(file_flags, expected_flags);
-/// assert_eq!(block_flags, BlockFlags{ queries: Some(vec!["r1".as_bytes().to_vec(), "r651903".as_bytes().to_vec(), "r7543".as_bytes().to_vec(), "r16".as_bytes().to_vec()]), query_ids: Some(vec![0, 2, 3, 4]) });
+/// Errors with [TruncatedBlockErr](errors::TruncatedBlockErr) if the last
+/// block is incomplete and with
+/// [MissingBlockReferenceErr](errors::MissingBlockReferenceErr) if a block
+/// refers to a block that is not in the record.
 ///
-pub fn decode_from_read_to_roaring<R: Read>(
+pub fn block_info_from_read<R: Read>(
     conn_in: &mut R,
-) -> Result<(RoaringTreemap, FileHeader, FileFlags, BlockFlags), E> {
-    let mut bitmap_out = RoaringTreemap::new();
-    let header = crate::headers::file::read_file_header(conn_in)?;
-    let flags = crate::headers::file::read_file_flags(&header, conn_in)?;
+) -> Result<Vec<BlockInfo>, E> {
+    let file_header = read_file_header(conn_in)?;
+    let file_flags = read_file_flags(&file_header, conn_in)?;
+    let dictionary = compression::flags_dictionary::FlagsDictionary::from_flags(&file_flags);
+
+    let mut offset: u64 = 32 + file_header.flags_len;
+    let mut blocks: Vec<BlockInfo> = Vec::new();
+    let mut header_bytes: Vec<u8> = Vec::with_capacity(32);
+    loop {
+        header_bytes.clear();
+        conn_in.by_ref().take(32).read_to_end(&mut header_bytes)?;
+        if header_bytes.is_empty() {
+            break
+        } else if header_bytes.len() < 32 {
+            return Err(Box::new(errors::TruncatedBlockErr{}))
+        }
+        let header = headers::block::decode_block_header(&header_bytes)?;
+        let flags = headers::block::read_block_flags_with_dictionary(&header, dictionary.as_ref(), conn_in)?;
+
+        let (codec, raw_len) = if header.placeholder1 & compression::dedup::BLOCK_IS_REFERENCE != 0 {
+            // A block can't refer to itself
+            let target = blocks.len().checked_sub(header.placeholder2 as usize).filter(|_| header.placeholder2 > 0).ok_or(errors::MissingBlockReferenceErr{})?;
+            (BlockCodec::Reference(target), blocks.get(target).ok_or(errors::MissingBlockReferenceErr{})?.raw_len)
+        } else if header.placeholder1 & compression::BLOCK_PAYLOAD_RAW != 0 {
+            (BlockCodec::Raw, header.block_len as u64)
+        } else {
+            (BlockCodec::Gzip, 0)
+        };
 
-    let mut queries: Vec<Vec<u8>> = Vec::new();
-    let mut query_ids: Vec<u32> = Vec::new();
+        // Skip the contents, keeping the gzip trailer with the decompressed size
+        let trailer_len = if codec == BlockCodec::Gzip { 4.min(header.block_len as u64) } else { 0 };
+        let skipped = std::io::copy(&mut conn_in.by_ref().take(header.block_len as u64 - trailer_len), &mut std::io::sink())?;
+        let mut trailer: Vec<u8> = Vec::with_capacity(4);
+        conn_in.by_ref().take(trailer_len).read_to_end(&mut trailer)?;
+        if skipped + trailer.len() as u64 != header.block_len as u64 {
+            return Err(Box::new(errors::TruncatedBlockErr{}))
+        }
+        let raw_len = if trailer.len() == 4 { u32::from_le_bytes(trailer.try_into().unwrap()) as u64 } else { raw_len };
+
+        let len = 32 + header.flags_len + header.block_len as u64;
+        blocks.push(BlockInfo{
+            index: blocks.len(),
+            offset,
+            len,
+            start_query_id: flags.query_ids.as_ref().and_then(|ids| ids.iter().min().copied()),
+            num_records: flags.query_ids.as_ref().map_or(header.num_records, |ids| ids.len() as u32),
+            compressed_len: header.block_len as u64,
+            raw_len,
+            codec,
+            stats: header.stats(),
+        });
+        offset += len;
+    }
 
-    while let Ok(block_header) = read_block_header(conn_in) {
-        let deflated_len: usize = ((block_header.flags_len) + (block_header.block_len as u64)).try_into()?;
-        let mut block_bytes: Vec<u8> = vec![0; deflated_len];
-        conn_in.read_exact(&mut block_bytes)?;
+    Ok(blocks)
+}
 
-        let (bitmap, mut block_flags) = match BitmapType::from_u16(header.bitmap_type)? {
-            BitmapType::Roaring32 => {
-                let (bitmap, block_flags) = unpack_block_roaring32(&block_bytes, &block_header)?;
-                (RoaringTreemap::from_bitmaps([(0, bitmap)]), block_flags)
-            },
-            BitmapType::Roaring64 => {
-                unpack_block_roaring64(&block_bytes, &block_header)?
-            },
-        };
+/// Count the set bits in the blocks remaining in `conn_in`.
+///
+/// Call after reading the file header and flags. The count is summed from
+/// the [BlockStats] in the block headers and the block contents are skipped
+/// without decompressing them.
+///
+/// Returns None if a block was written without statistics or has more set
+/// bits than [BlockStats] can record.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::{count_ones_from_read, encode_to_write, EncodeOpts, PseudoAln};
+/// use ahda::headers::file::read_file_header_and_flags;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let data = vec![
+///     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: None },
+///     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: None },
+/// ];
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+///
+/// let mut conn = bytes.as_slice();
+/// read_file_header_and_flags(&mut conn).unwrap();
+/// assert_eq!(count_ones_from_read(&mut conn).unwrap(), Some(3));
+/// ```
+///
+/// ## Errors and panics
+///
+/// Errors with [TruncatedBlockErr](errors::TruncatedBlockErr) if the last
+/// block is incomplete.
+///
+pub fn count_ones_from_read<R: Read>(
+    conn_in: &mut R,
+) -> Result<Option<u64>, E> {
+    let mut n_ones: Option<u64> = Some(0);
+    let mut header_bytes: Vec<u8> = Vec::with_capacity(32);
+    loop {
+        header_bytes.clear();
+        conn_in.by_ref().take(32).read_to_end(&mut header_bytes)?;
+        if header_bytes.is_empty() {
+            break
+        } else if header_bytes.len() < 32 {
+            return Err(Box::new(errors::TruncatedBlockErr{}))
+        }
+        let header = headers::block::decode_block_header(&header_bytes)?;
+        let len = header.flags_len + header.block_len as u64;
+        if std::io::copy(&mut conn_in.by_ref().take(len), &mut std::io::sink())? != len {
+            return Err(Box::new(errors::TruncatedBlockErr{}))
+        }
 
-        queries.append(block_flags.queries.as_mut().unwrap());
-        query_ids.append(block_flags.query_ids.as_mut().unwrap());
+        let block_ones = header.stats().map(|x| x.n_ones).filter(|x| *x < u32::MAX);
+        n_ones = n_ones.zip(block_ones).map(|(total, block)| total + block as u64);
+    }
 
-        bitmap_out |= bitmap;
+    Ok(n_ones)
+}
+
+/// Calls `f` with the flags of each block remaining in `conn_in`.
+///
+/// The block contents are skipped without decompressing them.
+pub(crate) fn try_for_each_block_flags<R: Read, F: FnMut(BlockFlags) -> Result<(), E>>(
+    conn_in: &mut R,
+    file_flags: &FileFlags,
+    mut f: F,
+) -> Result<(), E> {
+    let dictionary = compression::flags_dictionary::FlagsDictionary::from_flags(file_flags);
+    let mut header_bytes: Vec<u8> = Vec::with_capacity(32);
+    loop {
+        header_bytes.clear();
+        conn_in.by_ref().take(32).read_to_end(&mut header_bytes)?;
+        if header_bytes.is_empty() {
+            return Ok(())
+        } else if header_bytes.len() < 32 {
+            return Err(Box::new(errors::TruncatedBlockErr{}))
+        }
+        let header = headers::block::decode_block_header(&header_bytes)?;
+        let flags = headers::block::read_block_flags_with_dictionary(&header, dictionary.as_ref(), conn_in)?;
+        let skipped = std::io::copy(&mut conn_in.by_ref().take(header.block_len as u64), &mut std::io::sink())?;
+        if skipped != header.block_len as u64 {
+            return Err(Box::new(errors::TruncatedBlockErr{}))
+        }
+        f(flags)?;
     }
+}
+
+/// Read the extension fields of an .ahda record from [Read].
+///
+/// Returns the [Extensions](headers::extensions::Extensions) in the file
+/// flags and in the flags of each block, in the order that the blocks appear
+/// in the record. The block contents are skipped without decompressing them.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::extensions_from_read;
+/// use ahda::encoder::Encoder;
+/// use ahda::headers::extensions::Extensions;
+/// use ahda::PseudoAln;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let data: Vec<PseudoAln> = (0..3).map(|id| {
+///     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+/// }).collect();
+///
+/// let mut extensions = Extensions::default();
+/// extensions.insert(b"mytool.note", b"raw bytes".to_vec());
+///
+/// let mut iter = data.into_iter();
+/// let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 3);
+/// encoder.set_extensions(extensions.clone()).unwrap();
+/// let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+/// for block in encoder.by_ref() {
+///     bytes.append(&mut block.unwrap());
+/// }
+///
+/// let (file_extensions, block_extensions) = extensions_from_read(&mut bytes.as_slice()).unwrap();
+/// assert_eq!(file_extensions, Some(extensions));
+/// assert_eq!(block_extensions, vec![None]);
+/// ```
+///
+/// ## Errors and panics
+///
+/// Errors with [TruncatedBlockErr](errors::TruncatedBlockErr) if the last
+/// block is incomplete.
+///
+pub fn extensions_from_read<R: Read>(
+    conn_in: &mut R,
+) -> Result<(Option<headers::extensions::Extensions>, Vec<Option<headers::extensions::Extensions>>), E> {
+    let file_header = read_file_header(conn_in)?;
+    let file_flags = read_file_flags(&file_header, conn_in)?;
 
-    let mut both: Vec<(u32, &Vec<u8>)> = queries.iter().zip(query_ids.iter()).map(|(name, idx)| (*idx, name)).collect();
-    both.sort_by_key(|x| x.0);
-    let queries: Option<Vec<Vec<u8>>> = Some(both.iter().map(|x| x.1.clone()).collect::<Vec<Vec<u8>>>());
-    let query_ids: Option<Vec<u32>> = Some(both.iter().map(|x| x.0).collect());
+    let mut blocks: Vec<Option<headers::extensions::Extensions>> = Vec::new();
+    try_for_each_block_flags(conn_in, &file_flags, |flags| {
+        blocks.push(flags.extensions);
+        Ok(())
+    })?;
 
-    Ok((bitmap_out, header, flags, BlockFlags{ queries, query_ids }))
+    Ok((file_flags.extensions, blocks))
 }
 
-/// Merge bitmap from Read to an existing bitmap with Union
+/// Read the query names of an .ahda record from [Read] in query id order.
 ///
-/// Doesn't check that the encoded data was created for compatible data, this
-/// just merges the bitmaps.
+/// The names are read from the block flags, the block contents are skipped
+/// without decompressing them. The returned names can be given as the query
+/// names when encoding related data to get the same query ids.
 ///
 /// ## Usage
 ///
 /// ```rust
-/// use ahda::{decode_from_read_into_roaring, decode_from_read_to_roaring, encode_from_read_to_write};
-/// use ahda::MergeOp;
-/// use ahda::EncodeOpts;
-/// use roaring::RoaringTreemap;
-/// use std::io::{Cursor, Seek};
-///
-/// // Set up mock inputs
-/// let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
-/// let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r651903".to_vec(), b"r7543".to_vec(), b"r16".to_vec()];
-/// let name = "sample".as_bytes().to_vec();
-///
-/// // Have this input data in two files:
-/// //     0    r1    virus.fasta
-/// //     3    r7543 chr.fasta:virus.fasta
-/// //
-/// //     0    r1    plasmid.fasta:virus.fasta
-/// //     3    r7543 plasmid.fasta
-/// //
-/// // ...and want to compute the intersection
-///
-/// let mut plaintext_bytes_1: Vec<u8> = Vec::new();
-/// plaintext_bytes_1.append(&mut b"0\tr1\tvirus.fasta\n".to_vec());
-/// plaintext_bytes_1.append(&mut b"3\tr7543\tchr.fasta:virus.fasta\n".to_vec());
-///
-/// let mut plaintext_bytes_2: Vec<u8> = Vec::new();
-/// plaintext_bytes_2.append(&mut b"0\tr1\tplasmid.fasta:virus.fasta\n".to_vec());
-/// plaintext_bytes_2.append(&mut b"3\tr7543\tplasmid.fasta\n".to_vec());
-///
-/// // Encode mock data
-/// let mut plaintext_1: Cursor<Vec<u8>> = Cursor::new(plaintext_bytes_1.clone());
-/// let mut input_1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// let mut t_it = targets.iter().cloned();
-/// let mut it = queries.iter().cloned();
-/// let mut opts = EncodeOpts::default();
-/// opts.accession = name;
-/// opts.encode_query_names = true;
-/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut plaintext_1, &mut input_1, opts.clone()).unwrap();
-/// input_1.rewind();
+/// use ahda::query_names_from_read;
+/// use ahda::encoder::Encoder;
+/// use ahda::PseudoAln;
 ///
-/// let mut plaintext_2: Cursor<Vec<u8>> = Cursor::new(plaintext_bytes_2.clone());
-/// let mut input_2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-/// let mut it = queries.into_iter();
-/// let mut t_it = targets.into_iter();
-/// encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut plaintext_2, &mut input_2, opts).unwrap();
-/// input_2.rewind();
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let names = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+/// let data: Vec<PseudoAln> = [2, 0, 1].into_iter().map(|id| {
+///     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: Some(names[id as usize].clone()) }
+/// }).collect();
 ///
-/// // Decode data from `input_1`
-/// let (mut bitmap, _file_header, _file_flags, _block_flags) = decode_from_read_to_roaring(&mut input_1).unwrap();
+/// let mut iter = data.into_iter();
+/// let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 3);
+/// // Store both the query ids and names
+/// encoder.set_fields_present(3);
+/// encoder.set_block_size(2).unwrap();
+/// let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+/// for block in encoder.by_ref() {
+///     bytes.append(&mut block.unwrap());
+/// }
 ///
-/// // Intersect data from `input_2` with the decoded `bitmap`
-/// decode_from_read_into_roaring(&mut input_2, &MergeOp::Intersection, &mut bitmap).unwrap();
+/// assert_eq!(query_names_from_read(&mut bytes.as_slice()).unwrap(), names);
+/// ```
 ///
-/// // Expect these outputs:
-/// //     RoaringBitmap<[2]>
-/// // ...ie the alignment in the intersection is:
-/// //     0    r1    virus.fasta
+/// ## Errors and panics
 ///
-/// assert_eq!(bitmap, RoaringTreemap::from([2]));
+/// Errors with [MissingQueryNamesErr](errors::MissingQueryNamesErr) if the
+/// record does not store query names, with
+/// [MissingQueryNameErr](errors::MissingQueryNameErr) if a query id has no
+/// name, and with [QueryIndexOutOfRangeErr](errors::QueryIndexOutOfRangeErr)
+/// if a query id is not less than the number of queries in the header.
 ///
-pub fn decode_from_read_into_roaring<R: Read>(
+pub fn query_names_from_read<R: Read>(
     conn_in: &mut R,
-    merge_op: &MergeOp,
-    bitmap_out: &mut RoaringTreemap,
-) -> Result<(), E> {
-    match merge_op {
-        MergeOp::Intersection => {
-            // Have to read in the whole bitmap to perform intersection
-            let (bitmap_b, _, _, _) = decode_from_read_to_roaring(conn_in)?;
-            *bitmap_out &= bitmap_b;
-        },
-        _ => {
-            let header = crate::headers::file::read_file_header(conn_in)?;
-            let _ = crate::headers::file::read_file_flags(&header, conn_in)?;
-
-            while let Ok(block_header) = read_block_header(conn_in) {
-                let deflated_len: usize = ((block_header.flags_len) + (block_header.block_len as u64)).try_into()?;
-                let mut block_bytes: Vec<u8> = vec![0; deflated_len];
-                conn_in.read_exact(&mut block_bytes)?;
-
-                let bitmap_b = match BitmapType::from_u16(header.bitmap_type)? {
-                    BitmapType::Roaring32 => {
-                        let (bitmap, _) = unpack_block_roaring32(&block_bytes, &block_header)?;
-                        RoaringTreemap::from_bitmaps([(0, bitmap)])
-                    },
-                    BitmapType::Roaring64 => {
-                        unpack_block_roaring64(&block_bytes, &block_header)?.0
-                    },
-                };
-
-                match merge_op {
-                    MergeOp::Union => {
-                        *bitmap_out |= bitmap_b;
-                    },
-                    MergeOp::Xor => {
-                        *bitmap_out ^= bitmap_b;
-                    },
-                    MergeOp::Diff => {
-                        *bitmap_out -= bitmap_b;
-                    },
-                    MergeOp::Intersection => {
-                        panic!("MergeOp::Intersection cannot be performed block-wise.");
-                    }
-                }
-            }
-        },
+) -> Result<Vec<Vec<u8>>, E> {
+    let file_header = read_file_header(conn_in)?;
+    let file_flags = read_file_flags(&file_header, conn_in)?;
+    if !file_header.promises_query_names() {
+        return Err(Box::new(errors::MissingQueryNamesErr{}))
     }
 
-    Ok(())
+    let mut names: Vec<Option<Vec<u8>>> = vec![None; file_header.n_queries as usize];
+    try_for_each_block_flags(conn_in, &file_flags, |flags| {
+        let (Some(queries), Some(query_ids)) = (flags.queries, flags.query_ids) else {
+            return Err(Box::new(errors::MissingQueryNamesErr{}))
+        };
+        for (name, query_id) in queries.into_iter().zip(query_ids) {
+            *names.get_mut(query_id as usize).ok_or(errors::QueryIndexOutOfRangeErr{})? = Some(name);
+        }
+        Ok(())
+    })?;
+
+    names.into_iter().enumerate().map(|(query_id, name)| {
+        name.ok_or(Box::new(errors::MissingQueryNameErr{ query_id: query_id as u32 }) as E)
+    }).collect()
 }
 
 #[cfg(test)]
 mod tests {
 
     #[test]
+    fn is_ahda_file() {
+        use super::is_ahda_file;
+        use super::encode_to_write;
+        use super::EncodeOpts;
+
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let data = vec![
+            PseudoAln{ones_names: None,  query_id: Some(0), ones: Some(vec![0]), query_name: None },
+        ];
+        let targets = vec!["chr.fasta".as_bytes().to_vec()];
+
+        let mut encoded: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut encoded, EncodeOpts::default()).unwrap();
+
+        assert!(is_ahda_file(&mut Cursor::new(&encoded)));
+        assert!(!is_ahda_file(&mut Cursor::new(b"0\tr1\tchr.fasta\n")));
+        assert!(!is_ahda_file(&mut Cursor::new(b"ahd")));
+        assert!(!is_ahda_file(&mut Cursor::new(Vec::<u8>::new())));
+    }
+
+    #[test]
+    fn unaligned_queries_survive_conversions() {
+        use super::decode_from_read_to_write;
+        use super::encode_from_read_to_write;
+        use super::encode_to_write;
+        use super::errors::MissingHitsErr;
+        use super::parser::Parser;
+        use super::EncodeOpts;
+        use super::Format;
+        use super::Hits;
+        use super::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+        // r2 aligned to no targets
+        let metagraph = b"0\tr1\tchr.fasta\n1\tr2\t\n2\tr3\tchr.fasta:plasmid.fasta\n".to_vec();
+        let opts = EncodeOpts{ accession: b"sample".to_vec(), encode_query_names: true, ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_from_read_to_write(Some(&mut targets.clone().into_iter()), Some(&mut queries.clone().into_iter()), &mut metagraph.as_slice(), &mut bytes, opts).unwrap();
+
+        let expected = vec![(b"r1".to_vec(), Hits::Aligned), (b"r2".to_vec(), Hits::Unaligned), (b"r3".to_vec(), Hits::Aligned)];
+        for format in [Format::Themisto, Format::Fulgor, Format::Metagraph, Format::Bifrost, Format::AhdaTSV] {
+            let mut text: Vec<u8> = Vec::new();
+            decode_from_read_to_write(format.clone(), &mut bytes.as_slice(), &mut text).unwrap();
+            let mut conn = text.as_slice();
+            let mut parser = Parser::new_with_format(&mut conn, Some(&mut queries.clone().into_iter()), Some(&mut targets.clone().into_iter()), format.clone()).unwrap();
+            let mut got: Vec<(Vec<u8>, Hits)> = parser.try_iter().map(|x| x.map(|x| (x.query_name.clone().unwrap(), x.hits()))).collect::<Result<_, _>>().unwrap();
+            got.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(got, expected, "{}", format);
+        }
+
+        // Unknown targets are not encoded as unaligned
+        let data = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: None, query_name: None }];
+        assert!(encode_to_write(&targets, &queries, &data, &mut Vec::new(), EncodeOpts::default()).unwrap_err().is::<MissingHitsErr>());
+    }
+
+    #[test]
+    fn compat_info_lists_current_version() {
+        use super::compat_info;
+        use super::AhdaVersion;
+
+        let info = compat_info();
+        assert!(info.version.parse::<AhdaVersion>().is_ok());
+
+        let got = info.to_string();
+        assert!(got.starts_with(env!("CARGO_PKG_VERSION")));
+        assert!(got.contains("bitmap types: roaring32, roaring64"));
+        assert!(got.contains(if cfg!(feature = "zstd") { "\nfeatures: +threads +zstd -mmap" } else { "\nfeatures: +threads -zstd -mmap" }));
+    }
+
+    #[test]
+    fn compat_info_lists_read_formats() {
+        use super::compat_info;
+        use super::AhdaFormatVersion;
+
+        let info = compat_info();
+        assert_eq!(info.read_formats, vec![AhdaFormatVersion::V1_0_0.to_u8(), AhdaFormatVersion::V1_1_0.to_u8()]);
+        assert!(info.to_string().contains("\nread formats: 0, 1\n"));
+    }
+
+    #[test]
+    fn format_version_matches_header() {
+        use super::FORMAT_VERSION;
+        use super::MAGIC;
+        use super::AhdaFormatVersion;
+        use crate::headers::file::build_ahda_header;
+
+        assert_eq!(AhdaFormatVersion::default().to_u8(), FORMAT_VERSION);
+        assert_eq!(build_ahda_header()[0..4], MAGIC);
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn concatenate_from_read_to_write() {
         use super::concatenate_from_read_to_write;
 
         use std::io::Cursor;
 
-        let data_bytes_1: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
-        let data_bytes_2: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 37, 0, 0, 0, 49, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 18, 116, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 51, 53, 180, 52, 48, 230, 69, 18, 49, 52, 99, 100, 98, 98, 1, 0, 148, 139, 255, 106, 38, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 6, 6, 6, 22, 6, 86, 6, 0, 21, 37, 56, 88, 20, 0, 0, 0];
-        let data_bytes_3: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
+        let data_bytes_1: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
+        let data_bytes_2: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 37, 0, 0, 0, 49, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 18, 116, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 51, 53, 180, 52, 48, 230, 69, 18, 49, 52, 99, 100, 98, 98, 1, 0, 148, 139, 255, 106, 38, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 6, 6, 6, 22, 6, 86, 6, 0, 21, 37, 56, 88, 20, 0, 0, 0];
+        let data_bytes_3: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
         let data_1 = Cursor::new(data_bytes_1);
         let data_2 = Cursor::new(data_bytes_2);
         let data_3 = Cursor::new(data_bytes_3);
         let mut data = vec![data_1, data_2, data_3];
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 37, 0, 0, 0, 49, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 18, 116, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 51, 53, 180, 52, 48, 230, 69, 18, 49, 52, 99, 100, 98, 98, 1, 0, 148, 139, 255, 106, 38, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 6, 6, 6, 22, 6, 86, 6, 0, 21, 37, 56, 88, 20, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 37, 0, 0, 0, 49, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 18, 116, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 51, 53, 180, 52, 48, 230, 69, 18, 49, 52, 99, 100, 98, 98, 1, 0, 148, 139, 255, 106, 38, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 6, 6, 6, 22, 6, 86, 6, 0, 21, 37, 56, 88, 20, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
 
         let mut bytes_got: Cursor<Vec<u8>> = Cursor::new(Vec::new());
         concatenate_from_read_to_write(&mut data, &mut bytes_got).unwrap();
@@ -1292,14 +4511,267 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
+    fn concatenate_from_read_to_write_keeps_extensions() {
+        use super::concatenate_from_read_to_write;
+        use super::extensions_from_read;
+        use crate::encoder::Encoder;
+        use crate::headers::extensions::Extensions;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let mut extensions = Extensions::default();
+        extensions.insert_as(b"mytool.read_length", &150_u32).unwrap();
+
+        let mut data: Vec<Cursor<Vec<u8>>> = [0..2, 2..4].into_iter().enumerate().map(|(idx, ids)| {
+            let records: Vec<PseudoAln> = ids.map(|id| PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }).collect();
+            let mut iter = records.into_iter();
+            let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 4);
+            if idx == 0 {
+                encoder.set_extensions(extensions.clone()).unwrap();
+            }
+            let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+            for block in encoder.by_ref() {
+                bytes.append(&mut block.unwrap());
+            }
+            Cursor::new(bytes)
+        }).collect();
+
+        let mut bytes_got: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        concatenate_from_read_to_write(&mut data, &mut bytes_got).unwrap();
+
+        let (got, blocks) = extensions_from_read(&mut bytes_got.get_ref().as_slice()).unwrap();
+        assert_eq!(got, Some(extensions));
+        assert_eq!(blocks, vec![None, None]);
+    }
+
+    #[test]
+    fn try_concatenate_embeds_manifest() {
+        use super::encode_to_write;
+        use super::try_concatenate_from_read_to_write_with_opts;
+        use super::CatOpts;
+        use super::EncodeOpts;
+        use crate::headers::file::read_file_header_and_flags;
+        use crate::manifest::Manifest;
+        use crate::manifest::ManifestEntry;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec()];
+        let inputs: Vec<Vec<u8>> = [(b"s1", 0..2), (b"s2", 2..5)].into_iter().map(|(sample, ids)| {
+            let data: Vec<PseudoAln> = ids.map(|id| PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![0]), query_name: None }).collect();
+            let opts = EncodeOpts{ accession: sample.to_vec(), ..Default::default() };
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+            bytes
+        }).collect();
+        let entries = inputs.iter().enumerate().map(|(idx, bytes)| ManifestEntry::from_read(format!("s{}.ahda", idx + 1).as_bytes(), bytes.as_slice())).collect::<Result<Vec<ManifestEntry>, _>>().unwrap();
+        assert_eq!(entries.iter().map(|x| (x.sample.clone(), x.first_query_id, x.last_query_id, x.n_records)).collect::<Vec<_>>(), vec![(b"s1".to_vec(), Some(0), Some(1), 2), (b"s2".to_vec(), Some(2), Some(4), 3)]);
+        let manifest = Manifest{ entries };
+
+        let opts = CatOpts{ manifest: Some(manifest.clone()), ..Default::default() };
+        let mut merged: Vec<u8> = Vec::new();
+        try_concatenate_from_read_to_write_with_opts(&mut [inputs[0].as_slice(), inputs[1].as_slice()], &mut merged, &opts).unwrap();
+        let (_, flags) = read_file_header_and_flags(&mut merged.as_slice()).unwrap();
+        assert_eq!(Manifest::from_flags(&flags).unwrap(), Some(manifest));
+
+        // The manifest of the first input doesn't describe the output
+        let mut bytes: Vec<u8> = Vec::new();
+        try_concatenate_from_read_to_write_with_opts(&mut [merged.as_slice()], &mut bytes, &CatOpts::default()).unwrap();
+        let (_, flags) = read_file_header_and_flags(&mut bytes.as_slice()).unwrap();
+        assert_eq!(Manifest::from_flags(&flags).unwrap(), None);
+    }
+
+    #[test]
+    fn try_concatenate_renumbers_queries() {
+        use super::decode_from_read;
+        use super::encode_to_write;
+        use super::try_concatenate_from_read_to_write_with_opts;
+        use super::CatOpts;
+        use super::EncodeOpts;
+        use crate::compression::BitmapType;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries = [b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+        // Every input numbers its queries from 0 and the second one doesn't know how many it has
+        let inputs: Vec<Vec<u8>> = [(3, vec![(0, vec![0]), (2, vec![0, 1])]), (0, vec![(0, vec![1]), (1, vec![])]), (3, vec![(1, vec![0, 1])])].into_iter().map(|(n_queries, records)| {
+            let data: Vec<PseudoAln> = records.into_iter().map(|(id, ones)| PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(ones), query_name: None }).collect();
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(&targets, &queries[0..n_queries], &data, &mut bytes, EncodeOpts::default()).unwrap();
+            bytes
+        }).collect();
+
+        let mut bytes: Vec<u8> = Vec::new();
+        let got = try_concatenate_from_read_to_write_with_opts(&mut [inputs[0].as_slice(), inputs[1].as_slice(), inputs[2].as_slice()], &mut bytes, &CatOpts::default());
+        assert!(got.is_err());
+
+        let opts = CatOpts{ renumber_queries: true, ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        try_concatenate_from_read_to_write_with_opts(&mut [inputs[0].as_slice(), inputs[1].as_slice(), inputs[2].as_slice()], &mut bytes, &opts).unwrap();
+
+        let (header, _, got) = decode_from_read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header.n_queries, 0);
+        let expected = vec![(0, vec![0]), (2, vec![0, 1]), (3, vec![1]), (4, vec![]), (6, vec![0, 1])];
+        assert_eq!(got.into_iter().map(|x| (x.query_id.unwrap(), x.ones.unwrap())).collect::<Vec<(u32, Vec<u32>)>>(), expected);
+
+        // Renumbered ids that don't fit in roaring32 blocks
+        let inputs: Vec<Vec<u8>> = [2_000_000_000, 500_000_000].into_iter().map(|id| {
+            let data = vec![PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![1]), query_name: None }];
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+            assert_eq!(crate::headers::file::decode_file_header(&bytes[0..32]).unwrap().bitmap_type, BitmapType::Roaring32.to_u16());
+            bytes
+        }).collect();
+        let mut bytes: Vec<u8> = Vec::new();
+        try_concatenate_from_read_to_write_with_opts(&mut [inputs[0].as_slice(), inputs[1].as_slice()], &mut bytes, &opts).unwrap();
+        let (header, _, got) = decode_from_read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header.bitmap_type, BitmapType::Roaring64.to_u16());
+        assert_eq!(got.into_iter().map(|x| (x.query_id.unwrap(), x.ones.unwrap())).collect::<Vec<(u32, Vec<u32>)>>(), vec![(2_000_000_000, vec![1]), (2_500_000_001, vec![1])]);
+    }
+
+    #[test]
+    fn try_concatenate_merges_targets() {
+        use super::decode_from_read;
+        use super::encode_to_write;
+        use super::try_concatenate_from_read_to_write_with_opts;
+        use super::CatOpts;
+        use super::EncodeOpts;
+        use crate::PseudoAln;
+
+        let encode = |targets: &[Vec<u8>], data: &[PseudoAln]| {
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(targets, &[], data, &mut bytes, EncodeOpts::default()).unwrap();
+            bytes
+        };
+        let input_1 = encode(&[b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()], &[
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: None },
+        ]);
+        let input_2 = encode(&[b"virus.fasta".to_vec(), b"chr.fasta".to_vec()], &[
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![0, 1]), query_name: None },
+        ]);
+
+        let mut bytes: Vec<u8> = Vec::new();
+        assert!(try_concatenate_from_read_to_write_with_opts(&mut [input_1.as_slice(), input_2.as_slice()], &mut bytes, &CatOpts{ renumber_queries: true, ..Default::default() }).is_err());
+
+        let opts = CatOpts{ renumber_queries: true, merge_targets: true, ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        try_concatenate_from_read_to_write_with_opts(&mut [input_1.as_slice(), input_2.as_slice()], &mut bytes, &opts).unwrap();
+
+        let (header, flags, got) = decode_from_read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header.n_targets, 3);
+        assert_eq!(flags.target_names, vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()]);
+        let expected = vec![(0, vec![0, 1]), (1, vec![1]), (2, vec![2]), (3, vec![0, 2])];
+        assert_eq!(got.into_iter().map(|x| (x.query_id.unwrap(), x.ones.unwrap())).collect::<Vec<(u32, Vec<u32>)>>(), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn try_concatenate_with_flags_dictionary() {
+        use super::decode_from_read;
+        use super::try_concatenate_from_read_to_write;
+        use crate::encoder::Encoder;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data: Vec<PseudoAln> = (0..2048).map(|id: u32| {
+            PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: Some(format!("ERR4035126.{}", id * 7 + 1).into_bytes()) }
+        }).collect();
+
+        // Only the second half has a dictionary
+        let inputs: Vec<Vec<u8>> = data.chunks(1024).enumerate().map(|(idx, chunk)| {
+            let mut records = chunk.iter().cloned();
+            let mut encoder = Encoder::new(&mut records, &targets, b"ERR4035126", data.len());
+            encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
+            encoder.set_block_size(32).unwrap();
+            if idx == 1 {
+                assert!(encoder.train_flags_dictionary(16, 4096).unwrap());
+            }
+            let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+            for block in encoder.by_ref() {
+                bytes.append(&mut block.unwrap());
+            }
+            bytes
+        }).collect();
+
+        for order in [[0, 1], [1, 0]] {
+            let mut conns: Vec<&[u8]> = order.iter().map(|idx| inputs[*idx].as_slice()).collect();
+            let mut bytes: Vec<u8> = Vec::new();
+            try_concatenate_from_read_to_write(&mut conns, &mut bytes).unwrap();
+
+            // Records come back in the order of the inputs
+            let (_, _, got) = decode_from_read(&mut bytes.as_slice()).unwrap();
+            let expected = order.iter().flat_map(|idx| data[(idx * 1024)..((idx + 1) * 1024)].iter());
+            assert!(got.iter().map(|x| (&x.query_name, &x.ones)).eq(expected.map(|x| (&x.query_name, &x.ones))));
+        }
+    }
+
+    #[test]
+    fn query_names_from_read_with_missing_names_fails() {
+        use super::query_names_from_read;
+        use crate::encoder::Encoder;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let encode = |ids: Vec<u32>, fields_present: u16| {
+            let data: Vec<PseudoAln> = ids.into_iter().map(|id| PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![0]), query_name: Some(format!("r{}", id).into_bytes()) }).collect();
+            let mut iter = data.into_iter();
+            let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 3);
+            encoder.set_fields_present(fields_present);
+            let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+            for block in encoder.by_ref() {
+                bytes.append(&mut block.unwrap());
+            }
+            bytes
+        };
+
+        let bytes = encode(vec![0, 1, 2], crate::MASK_QUERY_IDS);
+        let got = query_names_from_read(&mut bytes.as_slice()).unwrap_err();
+        assert!(got.downcast_ref::<crate::errors::MissingQueryNamesErr>().is_some());
+
+        let bytes = encode(vec![0, 2], crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
+        let got = query_names_from_read(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(got.downcast_ref::<crate::errors::MissingQueryNameErr>().unwrap().query_id, 1);
+    }
+
+    #[test]
+    fn convert_stream_flushes_each_chunk() {
+        use super::convert_stream;
+        use crate::Format;
+        use crate::printer::TargetSelection;
+
+        use std::io::Write;
+
+        // Records the size of the output at each flush
+        struct Flushes { bytes: Vec<u8>, at: Vec<usize> }
+        impl Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.bytes.write(buf) }
+            fn flush(&mut self) -> std::io::Result<()> { self.at.push(self.bytes.len()); Ok(()) }
+        }
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let mut input: &[u8] = b"0 0\n1 1\n2 0 1\n3\n4 1\n";
+        let mut output = Flushes{ bytes: Vec::new(), at: Vec::new() };
+        let n_records = convert_stream(Some(&mut targets.into_iter()), None::<&mut std::iter::Empty<Vec<u8>>>, b"sample", Format::Themisto, &TargetSelection::default(), None, 2, &mut input, &mut output).unwrap();
+
+        assert_eq!(n_records, 5);
+        assert_eq!(output.bytes, b"0 0\n1 1\n2 0 1\n3\n4 1\n");
+        assert_eq!(output.at, vec![8, 16, 20]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn concatenate_from_read_to_write_with_duplicated_queries_fails() {
         use super::concatenate_from_read_to_write;
 
         use std::io::Cursor;
 
-        let data_bytes_1: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
-        let data_bytes_2: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
-        let data_bytes_3: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
+        let data_bytes_1: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
+        let data_bytes_2: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
+        let data_bytes_3: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
         let data_1 = Cursor::new(data_bytes_1);
         let data_2 = Cursor::new(data_bytes_2);
         let data_3 = Cursor::new(data_bytes_3);
@@ -1312,23 +4784,139 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn concatenate_from_read_to_write_with_incompatible_headers_fails() {
         use super::concatenate_from_read_to_write;
 
         use std::io::Cursor;
 
-        let data_bytes_1: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
-        let data_bytes_2: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
-        let data_bytes_3: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
+        let data_bytes_1: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
+        let data_bytes_2: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 2, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 2, 0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 226, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 49, 50, 49, 48, 2, 0, 26, 63, 239, 0, 32, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 70, 6, 1, 48, 205, 196, 0, 0, 133, 36, 27, 152, 20, 0, 0, 0];
+        let data_bytes_3: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 1, 0, 0, 0, 0, 0, 0, 0, 33, 0, 0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 228, 119, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 55, 53, 49, 102, 100, 100, 6, 0, 66, 122, 30, 150, 21, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 128, 0, 1, 6, 6, 6, 118, 6, 0, 71, 48, 17, 238, 18, 0, 0, 0];
         let data_1 = Cursor::new(data_bytes_1);
         let data_2 = Cursor::new(data_bytes_2);
         let data_3 = Cursor::new(data_bytes_3);
         let mut data = vec![data_1, data_2, data_3];
 
-        let mut bytes_got: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-        let got = concatenate_from_read_to_write(&mut data, &mut bytes_got);
+        let mut bytes_got: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let got = concatenate_from_read_to_write(&mut data, &mut bytes_got);
+
+        assert!(got.is_err());
+    }
+
+    #[test]
+    fn try_concatenate_from_read_to_write_reports_every_incompatibility() {
+        use super::try_concatenate_from_read_to_write;
+        use super::encode_to_write;
+        use super::EncodeOpts;
+        use super::Incompatibility;
+        use crate::compression::BitmapType;
+        use crate::errors::IncompatibleInputsErr;
+        use crate::errors::NoInputsErr;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let data = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None }];
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let renamed = vec![b"chr.fasta".to_vec(), b"virus.fasta".to_vec()];
+        let more = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+
+        let encode = |targets: &[Vec<u8>], opts: EncodeOpts| {
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(targets, &[], &data, &mut bytes, opts).unwrap();
+            Cursor::new(bytes)
+        };
+        let roaring64 = EncodeOpts{ bitmap_type: Some(BitmapType::Roaring64), ..Default::default() };
+        let mut inputs = vec![
+            encode(&targets, EncodeOpts::default()),
+            encode(&targets, EncodeOpts::default()),
+            encode(&renamed, roaring64),
+            encode(&more, EncodeOpts::default()),
+        ];
+
+        let mut bytes_got: Vec<u8> = Vec::new();
+        let got = try_concatenate_from_read_to_write(&mut inputs, &mut bytes_got).unwrap_err();
+        let got = got.downcast_ref::<IncompatibleInputsErr>().unwrap();
+
+        let expected = vec![
+            Incompatibility::BitmapType{ input: 2, expected: 0, got: 1 },
+            Incompatibility::TargetNames{ input: 2, target: 1 },
+            Incompatibility::NTargets{ input: 3, expected: 2, got: 3 },
+        ];
+        assert_eq!(got.incompatibilities, expected);
+        assert!(got.to_string().contains("input 2 has bitmap type roaring64, expected roaring32"));
+        assert!(bytes_got.is_empty());
+
+        let mut inputs: Vec<Cursor<Vec<u8>>> = Vec::new();
+        let got = try_concatenate_from_read_to_write(&mut inputs, &mut bytes_got).unwrap_err();
+        assert!(got.downcast_ref::<NoInputsErr>().is_some());
+    }
+
+    #[test]
+    fn read_header_only_skips_blocks() {
+        use super::encode_to_write;
+        use super::read_header_only;
+        use super::EncodeOpts;
+        use crate::headers::file::read_file_header_and_flags;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![1]), query_name: None }];
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[b"r1".to_vec()], &data, &mut bytes, EncodeOpts::default()).unwrap();
+        let expected = read_file_header_and_flags(&mut bytes.as_slice()).unwrap();
+
+        // Corrupt the blocks that follow the flags
+        let flags_end = 32 + expected.0.flags_len as usize;
+        bytes.truncate(flags_end + 3);
+        bytes[flags_end..].fill(255);
+
+        let path = std::env::temp_dir().join(format!("ahda-read-header-only-{}.ahda", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let got = read_header_only(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(got, expected);
+        assert!(read_header_only(std::env::temp_dir().join("ahda-read-header-only-missing.ahda")).is_err());
+    }
+
+    #[test]
+    fn try_concatenate_checks_index_versions() {
+        use super::try_concatenate_from_read_to_write;
+        use super::try_concatenate_from_read_to_write_with_opts;
+        use super::encode_to_write;
+        use super::CatOpts;
+        use super::EncodeOpts;
+        use super::Incompatibility;
+        use crate::errors::IncompatibleInputsErr;
+        use crate::panel::Panel;
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let encode = |query_id: u32, index_version: Option<&[u8]>| {
+            let data = vec![PseudoAln{ ones_names: None, query_id: Some(query_id), ones: Some(vec![0]), query_name: None }];
+            let opts = EncodeOpts{ store_panel: true, index_version: index_version.map(|x| x.to_vec()), ..Default::default() };
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+            Cursor::new(bytes)
+        };
+
+        // Inputs without a version can be combined with any version
+        let mut inputs = vec![encode(0, Some(b"v1")), encode(1, None), encode(2, Some(b"v2"))];
+        let mut bytes_got: Vec<u8> = Vec::new();
+        let got = try_concatenate_from_read_to_write(&mut inputs, &mut bytes_got).unwrap_err();
+        let got = got.downcast_ref::<IncompatibleInputsErr>().unwrap();
 
-        assert!(got.is_err());
+        let expected = vec![Incompatibility::Panel{ input: 2, expected: Panel::new(&targets, Some(b"v1".to_vec())), got: Panel::new(&targets, Some(b"v2".to_vec())) }];
+        assert_eq!(got.incompatibilities, expected);
+
+        let mut inputs = vec![encode(0, Some(b"v1")), encode(1, None), encode(2, Some(b"v2"))];
+        let opts = CatOpts{ check_panel: false, ..Default::default() };
+        try_concatenate_from_read_to_write_with_opts(&mut inputs, &mut bytes_got, &opts).unwrap();
+        assert!(!bytes_got.is_empty());
     }
 
     #[test]
@@ -1380,13 +4968,12 @@ mod tests {
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
         let sample = "ERR4035126".as_bytes().to_vec();
-        let mut opts = EncodeOpts::default();
-        opts.accession = sample;
-        opts.encode_query_names = true;
+        // The expected bytes have a gzipped block
+        let opts = EncodeOpts{ inline_max_records: 0, accession: sample, encode_query_names: true, ..Default::default() };
 
         encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 44, 26, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 16, 0, 0, 0, 0, 0, 2, 0, 4, 0, 5, 0, 7, 0];
 
         assert_eq!(*bytes.get_ref(), expected);
     }
@@ -1411,16 +4998,104 @@ mod tests {
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let sample = "ERR4035126".as_bytes().to_vec();
-        let mut opts = EncodeOpts::default();
-        opts.accession = sample;
+        // The expected bytes have a gzipped block
+        let opts = EncodeOpts{ inline_max_records: 0, accession: sample, ..Default::default() };
 
         encode_to_write(&targets, &Vec::new(), &data, &mut bytes, opts).unwrap();
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 0, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 29, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 96, 100, 101, 96, 100, 98, 102, 1, 0, 59, 190, 176, 144, 9, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 0, 0, 0, 0, 0, 0, 0, 44, 26, 0, 0, 0, 29, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 96, 100, 101, 96, 100, 98, 102, 1, 0, 59, 190, 176, 144, 9, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 16, 0, 0, 0, 0, 0, 2, 0, 4, 0, 5, 0, 7, 0];
 
         assert_eq!(*bytes.get_ref(), expected);
     }
 
+    #[test]
+    fn encode_to_write_with_one_target() {
+        use super::encode_to_write;
+        use super::decode_from_read;
+        use super::EncodeOpts;
+
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+        use std::io::Seek;
+
+        let data = vec![
+            PseudoAln{ones_names: Some(vec!["phiX.fasta".as_bytes().to_vec()]),  query_id: Some(0), ones: Some(vec![0]), query_name: Some("r1".as_bytes().to_vec()) },
+            PseudoAln{ones_names: Some(vec![]),  query_id: Some(1), ones: Some(vec![]), query_name: Some("r2".as_bytes().to_vec()) },
+            PseudoAln{ones_names: Some(vec!["phiX.fasta".as_bytes().to_vec()]),  query_id: Some(2), ones: Some(vec![0]), query_name: Some("r3".as_bytes().to_vec()) },
+        ];
+        let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+
+        let targets = vec!["phiX.fasta".as_bytes().to_vec()];
+        let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec(), "r3".as_bytes().to_vec()];
+        let opts = EncodeOpts{ encode_query_names: true, ..Default::default() };
+
+        encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
+        bytes.rewind().unwrap();
+
+        let (header, _, mut got) = decode_from_read(&mut bytes).unwrap();
+        got.sort_by_key(|x| x.query_id);
+
+        assert_eq!(header.n_targets, 1);
+        assert_eq!(got, data);
+    }
+
+    #[test]
+    fn encode_to_write_without_targets() {
+        use super::encode_to_write;
+        use super::decode_from_read;
+        use super::EncodeOpts;
+
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+        use std::io::Seek;
+
+        let data = vec![
+            PseudoAln{ones_names: Some(vec![]),  query_id: Some(0), ones: Some(vec![]), query_name: Some("r1".as_bytes().to_vec()) },
+            PseudoAln{ones_names: Some(vec![]),  query_id: Some(1), ones: Some(vec![]), query_name: Some("r2".as_bytes().to_vec()) },
+        ];
+        let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+
+        let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec()];
+        let opts = EncodeOpts{ encode_query_names: true, ..Default::default() };
+
+        encode_to_write(&[], &queries, &data, &mut bytes, opts).unwrap();
+        bytes.rewind().unwrap();
+
+        let (header, flags, got) = decode_from_read(&mut bytes).unwrap();
+
+        assert_eq!(header.n_targets, 0);
+        assert!(flags.target_names.is_empty());
+        assert_eq!(got, data);
+    }
+
+    #[test]
+    fn encode_to_write_with_target_out_of_range_fails() {
+        use super::encode_to_write;
+        use super::EncodeOpts;
+
+        use crate::PseudoAln;
+
+        use std::io::Cursor;
+
+        let data = vec![
+            PseudoAln{ones_names: None,  query_id: Some(0), ones: Some(vec![0]), query_name: None },
+            PseudoAln{ones_names: None,  query_id: Some(1), ones: Some(vec![1]), query_name: None },
+        ];
+        let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+
+        let targets = vec!["phiX.fasta".as_bytes().to_vec()];
+
+        let got = encode_to_write(&targets, &Vec::new(), &data, &mut bytes, EncodeOpts::default());
+
+        assert!(got.is_err());
+
+        let got = encode_to_write(&[], &Vec::new(), &data, &mut bytes, EncodeOpts::default());
+
+        assert!(got.is_err());
+    }
+
     #[test]
     fn encode_from_read() {
         use super::encode_from_read;
@@ -1446,14 +5121,13 @@ mod tests {
 
         let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 44, 26, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 16, 0, 0, 0, 0, 0, 2, 0, 4, 0, 5, 0, 7, 0];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec![b"ERR4035126.1".to_vec(), b"ERR4035126.2".to_vec(), b"ERR4035126.651903".to_vec(), b"ERR4035126.7543".to_vec(), b"ERR4035126.16".to_vec()];
         let query_name ="ERR4035126".as_bytes().to_vec();
-        let mut opts = EncodeOpts::default();
-        opts.accession = query_name.clone();
-        opts.encode_query_names = true;
+        // The expected bytes have a gzipped block
+        let opts = EncodeOpts{ inline_max_records: 0, accession: query_name.clone(), encode_query_names: true, ..Default::default() };
 
         let (header, flags) = build_file_header_and_flags(&targets, queries.len(), &query_name, &MetadataCompression::default()).unwrap();
         let format = Format::Metagraph;
@@ -1497,14 +5171,13 @@ mod tests {
 
         let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 0, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 29, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 96, 100, 101, 96, 100, 98, 102, 1, 0, 59, 190, 176, 144, 9, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 0, 0, 0, 0, 0, 0, 0, 44, 26, 0, 0, 0, 29, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 96, 100, 101, 96, 100, 98, 102, 1, 0, 59, 190, 176, 144, 9, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 16, 0, 0, 0, 0, 0, 2, 0, 4, 0, 5, 0, 7, 0];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec![b"ERR4035126.1".to_vec(), b"ERR4035126.2".to_vec(), b"ERR4035126.651903".to_vec(), b"ERR4035126.7543".to_vec(), b"ERR4035126.16".to_vec()];
         let query_name ="ERR4035126".as_bytes().to_vec();
-        let mut opts = EncodeOpts::default();
-        opts.accession = query_name.clone();
-        opts.encode_query_names = true;
+        // The expected bytes have a gzipped block
+        let opts = EncodeOpts{ inline_max_records: 0, accession: query_name.clone(), encode_query_names: true, ..Default::default() };
 
         let (header, flags) = build_file_header_and_flags(&targets, queries.len(), &query_name, &MetadataCompression::default()).unwrap();
         let format = Format::Themisto;
@@ -1532,7 +5205,7 @@ mod tests {
         let data_bytes: Vec<u8> = vec![49, 32, 48, 10, 48, 32, 48, 10, 50, 32, 48, 32, 49, 10, 52, 10, 51, 32, 49, 10];
         let mut data = Cursor::new(data_bytes);
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 44, 26, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 16, 0, 0, 0, 0, 0, 2, 0, 4, 0, 5, 0, 7, 0];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec![b"ERR4035126.1".to_vec(), b"ERR4035126.2".to_vec(), b"ERR4035126.651903".to_vec(), b"ERR4035126.7543".to_vec(), b"ERR4035126.16".to_vec()];
@@ -1541,9 +5214,8 @@ mod tests {
         let mut bytes_got: Cursor<Vec<u8>> = Cursor::new(Vec::new());
         let mut it = queries.into_iter();
         let mut t_it = targets.into_iter();
-        let mut opts = EncodeOpts::default();
-        opts.encode_query_names = true;
-        opts.accession = query_name;
+        // The expected bytes have a gzipped block
+        let opts = EncodeOpts{ inline_max_records: 0, encode_query_names: true, accession: query_name, ..Default::default() };
         encode_from_read_to_write(Some(&mut t_it), Some(&mut it), &mut data, &mut bytes_got, opts).unwrap();
         let got = bytes_got.get_ref();
 
@@ -1560,15 +5232,15 @@ mod tests {
         let data_bytes: Vec<u8> = vec![49, 9, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 46, 50, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 10, 48, 9, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 46, 49, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 10, 50, 9, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 46, 54, 53, 49, 57, 48, 51, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 58, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 10, 52, 9, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 46, 49, 54, 9, 10, 51, 9, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 46, 55, 53, 52, 51, 9, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 10];
         let mut data = Cursor::new(data_bytes);
 
-        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 0, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 29, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 96, 100, 101, 96, 100, 98, 102, 1, 0, 59, 190, 176, 144, 9, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let expected: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 0, 0, 0, 0, 0, 0, 0, 44, 26, 0, 0, 0, 29, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 96, 100, 101, 96, 100, 98, 102, 1, 0, 59, 190, 176, 144, 9, 0, 0, 0, 58, 48, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 16, 0, 0, 0, 0, 0, 2, 0, 4, 0, 5, 0, 7, 0];
 
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let query_name ="ERR4035126".as_bytes().to_vec();
 
         let mut bytes_got: Cursor<Vec<u8>> = Cursor::new(Vec::new());
         let mut t_it = targets.into_iter();
-        let mut opts = EncodeOpts::default();
-        opts.accession = query_name;
+        // The expected bytes have a gzipped block
+        let opts = EncodeOpts{ inline_max_records: 0, accession: query_name, ..Default::default() };
         encode_from_read_to_write(Some(&mut t_it), None::<&mut std::iter::Empty<Vec<u8>>>, &mut data, &mut bytes_got, opts).unwrap();
         let got = bytes_got.get_ref();
 
@@ -1595,7 +5267,7 @@ mod tests {
         let (mut expected_header, expected_flags) = build_file_header_and_flags(&vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], 5_usize, &"ERR4035126".as_bytes().to_vec(), &MetadataCompression::default()).unwrap();
         expected_header.fields_present = 3_u16;
 
-        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut bytes: Cursor<Vec<u8>> = Cursor::new(data);
 
         let (file_header, file_flags, mut got) = decode_from_read(&mut bytes).unwrap();
@@ -1626,7 +5298,7 @@ mod tests {
         let (mut expected_header, expected_flags) = build_file_header_and_flags(&vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], 0_usize, &"ERR4035126".as_bytes().to_vec(), &MetadataCompression::default()).unwrap();
         expected_header.fields_present = 2_u16;
 
-        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 0, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 29, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 96, 100, 101, 96, 100, 98, 102, 1, 0, 59, 190, 176, 144, 9, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 0, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 29, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 96, 100, 101, 96, 100, 98, 102, 1, 0, 59, 190, 176, 144, 9, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut bytes: Cursor<Vec<u8>> = Cursor::new(data);
 
         let (file_header, file_flags, mut got) = decode_from_read(&mut bytes).unwrap();
@@ -1645,7 +5317,7 @@ mod tests {
         use std::io::Cursor;
 
         let expected = b"0\tERR4035126.1\tchr.fasta\n1\tERR4035126.2\tchr.fasta\n2\tERR4035126.651903\tchr.fasta:plasmid.fasta\n3\tERR4035126.7543\tplasmid.fasta\n4\tERR4035126.16\t\n";
-        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let format = Format::Metagraph;
 
         let mut got_bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
@@ -1664,7 +5336,7 @@ mod tests {
         use std::io::Cursor;
 
         let expected = b"0\tERR4035126.1\tchr.fasta\n1\tERR4035126.2\tchr.fasta\n2\tERR4035126.3\tchr.fasta:plasmid.fasta\n3\tERR4035126.4\tplasmid.fasta\n4\tERR4035126.5\t\n";
-        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let format = Format::Metagraph;
 
         let mut got_bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
@@ -1683,7 +5355,7 @@ mod tests {
         use std::io::Cursor;
 
         let expected = b"0\tERR4035126.1\tchr.fasta\n1\tERR4035126.2\tchr.fasta\n2\tERR4035126.651903\tchr.fasta:plasmid.fasta\n3\tERR4035126.7543\tplasmid.fasta\n4\tERR4035126.16\t\n";
-        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_bytes);
         let format = Format::Metagraph;
 
@@ -1705,7 +5377,7 @@ mod tests {
         use std::io::Cursor;
 
         let expected = b"0\tERR4035126.1\tchr.fasta\n1\tERR4035126.2\tchr.fasta\n2\tERR4035126.3\tchr.fasta:plasmid.fasta\n3\tERR4035126.4\tplasmid.fasta\n4\tERR4035126.5\t\n";
-        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_bytes);
         let format = Format::Metagraph;
 
@@ -1741,11 +5413,11 @@ mod tests {
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
         let query_ids = vec![0, 1, 2, 3, 4];
-        let expected_block_flags = BlockFlags { queries: Some(queries.clone()), query_ids: Some(query_ids) };
+        let expected_block_flags = BlockFlags { queries: Some(queries.clone()), query_ids: Some(query_ids), ..Default::default() };
         let (mut expected_header, expected_flags) = build_file_header_and_flags(&targets, queries.len(), &"ERR4035126".as_bytes().to_vec(), &MetadataCompression::default()).unwrap();
         expected_header.fields_present = 3_u16;
 
-        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut bytes: Cursor<Vec<u8>> = Cursor::new(data);
 
         let (got, file_header, file_flags, block_flags) = decode_from_read_to_roaring(&mut bytes).unwrap();
@@ -1778,11 +5450,11 @@ mod tests {
         let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
         let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.651903".as_bytes().to_vec(), "ERR4035126.7543".as_bytes().to_vec(), "ERR4035126.16".as_bytes().to_vec()];
         let query_ids = vec![0, 1, 2, 3, 4];
-        let expected_block_flags = BlockFlags { queries: Some(queries.clone()), query_ids: Some(query_ids) };
+        let expected_block_flags = BlockFlags { queries: Some(queries.clone()), query_ids: Some(query_ids), ..Default::default() };
         let (mut expected_header, expected_flags) = build_file_header_and_flags(&targets, 0, &"ERR4035126".as_bytes().to_vec(), &MetadataCompression::default()).unwrap();
         expected_header.fields_present = 2_u16;
 
-        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut bytes: Cursor<Vec<u8>> = Cursor::new(data);
 
         let (got, file_header, file_flags, block_flags) = decode_from_read_to_roaring(&mut bytes).unwrap();
@@ -1802,7 +5474,7 @@ mod tests {
 
         use roaring::RoaringTreemap;
 
-        let data_right: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data_right: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_right);
 
         let mut data_left = RoaringTreemap::new();
@@ -1833,7 +5505,7 @@ mod tests {
 
         use roaring::RoaringTreemap;
 
-        let data_right: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data_right: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_right);
 
         let mut data_left = RoaringTreemap::new();
@@ -1859,7 +5531,7 @@ mod tests {
 
         use roaring::RoaringTreemap;
 
-        let data_right: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data_right: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_right);
 
         let mut data_left = RoaringTreemap::new();
@@ -1887,7 +5559,7 @@ mod tests {
 
         use roaring::RoaringTreemap;
 
-        let data_right: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 0, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let data_right: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
         let mut data: Cursor<Vec<u8>> = Cursor::new(data_right);
 
         let mut data_left = RoaringTreemap::new();
@@ -1907,4 +5579,706 @@ mod tests {
 
         assert_eq!(data_left, expected);
     }
+
+    #[test]
+    fn compare_from_read_identical() {
+        use super::compare_from_read;
+
+        use std::io::Cursor;
+
+        let data_bytes: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let mut data_1: Cursor<Vec<u8>> = Cursor::new(data_bytes.clone());
+        let mut data_2: Cursor<Vec<u8>> = Cursor::new(data_bytes);
+
+        let got = compare_from_read(&mut data_1, &mut data_2).unwrap();
+
+        assert_eq!(got.jaccard, 1.0);
+        assert_eq!(got.n_a, got.n_shared);
+        assert_eq!(got.n_b, got.n_shared);
+        assert_eq!(got.target_a, got.target_shared);
+    }
+
+    #[test]
+    fn compare_from_read_merges_targets() {
+        use super::compare_from_read;
+
+        use std::io::Cursor;
+
+        let data_bytes_1: Vec<u8> = vec![97, 104, 100, 97, 0, 0, 1, 0, 3, 0, 2, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 36, 0, 0, 0, 0, 0, 0, 0, 10, 69, 82, 82, 52, 48, 51, 53, 49, 50, 54, 2, 9, 99, 104, 114, 46, 102, 97, 115, 116, 97, 13, 112, 108, 97, 115, 109, 105, 100, 46, 102, 97, 115, 116, 97, 5, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 65, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 99, 100, 229, 113, 13, 10, 50, 49, 48, 54, 53, 52, 50, 211, 51, 68, 230, 24, 9, 34, 113, 204, 76, 13, 45, 13, 140, 249, 145, 68, 204, 77, 77, 140, 121, 145, 245, 154, 49, 178, 50, 48, 50, 49, 179, 0, 0, 22, 232, 102, 239, 83, 0, 0, 0, 31, 139, 8, 0, 0, 0, 0, 0, 0, 255, 179, 50, 96, 96, 96, 100, 0, 1, 22, 6, 1, 48, 205, 196, 192, 194, 192, 202, 192, 206, 0, 0, 47, 109, 177, 38, 26, 0, 0, 0];
+        let mut data_bytes_2: Vec<u8> = data_bytes_1.clone();
+        // Rename `chr.fasta` to `chr.fastq`
+        data_bytes_2[53] = b'q';
+        let mut data_1: Cursor<Vec<u8>> = Cursor::new(data_bytes_1);
+        let mut data_2: Cursor<Vec<u8>> = Cursor::new(data_bytes_2);
+
+        let got = compare_from_read(&mut data_1, &mut data_2).unwrap();
+
+        assert_eq!(got.target_names, vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"chr.fastq".to_vec()]);
+        assert_eq!(got.target_a[2], 0);
+        assert_eq!(got.target_b[0], 0);
+        assert_eq!(got.target_b[2], got.target_a[0]);
+        assert_eq!(got.target_shared, vec![0, got.target_a[1], 0]);
+    }
+
+    #[test]
+    fn decode_from_read_into_roaring_by_name_adds_missing_queries() {
+        use super::decode_from_read_into_roaring_by_name;
+        use super::decode_from_read_to_roaring;
+        use super::encode_to_write;
+        use super::EncodeOpts;
+        use super::MergeOp;
+        use super::PseudoAln;
+
+        use roaring::RoaringTreemap;
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let opts = EncodeOpts{ encode_query_names: true, ..Default::default() };
+
+        let data_1 = vec![
+            PseudoAln{ ones: Some(vec![0]), ones_names: None, query_id: Some(0), query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(1), query_name: Some(b"r2".to_vec()) },
+        ];
+        let data_2 = vec![
+            PseudoAln{ ones: Some(vec![0]), ones_names: None, query_id: Some(0), query_name: Some(b"r3".to_vec()) },
+            PseudoAln{ ones: Some(vec![0]), ones_names: None, query_id: Some(1), query_name: Some(b"r2".to_vec()) },
+        ];
+
+        let mut input_1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let mut input_2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        encode_to_write(&targets, &[b"r1".to_vec(), b"r2".to_vec()], &data_1, &mut input_1, opts.clone()).unwrap();
+        encode_to_write(&targets, &[b"r3".to_vec(), b"r2".to_vec()], &data_2, &mut input_2, opts).unwrap();
+        input_1.set_position(0);
+        input_2.set_position(0);
+
+        let (mut bitmap, _, _, mut block_flags) = decode_from_read_to_roaring(&mut input_1).unwrap();
+        decode_from_read_into_roaring_by_name(&mut input_2, &MergeOp::Union, &mut bitmap, &mut block_flags).unwrap();
+
+        assert_eq!(bitmap, RoaringTreemap::from_iter([0, 2, 3, 4]));
+        assert_eq!(block_flags.queries, Some(vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()]));
+        assert_eq!(block_flags.query_ids, Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn merge_from_reads_to_roaring_matches_sequential_merge() {
+        use super::decode_from_read_into_roaring;
+        use super::decode_from_read_to_roaring;
+        use super::encode_to_write;
+        use super::merge_from_reads_to_roaring;
+        use super::EncodeOpts;
+        use super::MergeOp;
+        use super::PseudoAln;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+        let inputs: Vec<Vec<u8>> = (0..7).map(|idx: u32| {
+            let data: Vec<PseudoAln> = (0..20).map(|query_id: u32| {
+                let ones: Vec<u32> = (0..3).filter(|target| !(query_id + idx * target).is_multiple_of(3)).collect();
+                PseudoAln{ ones: Some(ones), ones_names: None, query_id: Some(query_id), query_name: None }
+            }).collect();
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+            bytes
+        }).collect();
+
+        for merge_op in [MergeOp::Union, MergeOp::Intersection, MergeOp::Xor, MergeOp::Diff] {
+            let (mut expected, _, _, _) = decode_from_read_to_roaring(&mut Cursor::new(&inputs[0])).unwrap();
+            for input in inputs.iter().skip(1) {
+                decode_from_read_into_roaring(&mut Cursor::new(input), &merge_op, &mut expected).unwrap();
+            }
+
+            for n_threads in [1, 3, 8] {
+                let conns: Vec<Cursor<&Vec<u8>>> = inputs.iter().map(Cursor::new).collect();
+                let (got, _, _, _) = merge_from_reads_to_roaring(conns, &merge_op, n_threads).unwrap();
+                assert_eq!(got, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_records_matches_merge_from_reads() {
+        use super::encode_to_write;
+        use super::merge_from_reads_to_roaring;
+        use super::merge_records;
+        use super::EncodeOpts;
+        use super::MergeOp;
+        use super::PseudoAln;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+        let inputs: Vec<Vec<PseudoAln>> = (0..4).map(|idx: u32| {
+            (0..20).map(|query_id: u32| {
+                let ones: Vec<u32> = (0..3).filter(|target| !(query_id + idx * target).is_multiple_of(3)).collect();
+                PseudoAln{ ones: Some(ones), ones_names: None, query_id: Some(query_id), query_name: None }
+            }).collect()
+        }).collect();
+        let encoded: Vec<Vec<u8>> = inputs.iter().map(|data| {
+            let mut bytes: Vec<u8> = Vec::new();
+            encode_to_write(&targets, &[], data, &mut bytes, EncodeOpts::default()).unwrap();
+            bytes
+        }).collect();
+
+        let records: Vec<&[PseudoAln]> = inputs.iter().map(|x| x.as_slice()).collect();
+        for merge_op in [MergeOp::Union, MergeOp::Intersection, MergeOp::Xor, MergeOp::Diff] {
+            let conns: Vec<Cursor<&Vec<u8>>> = encoded.iter().map(Cursor::new).collect();
+            let (expected, _, _, _) = merge_from_reads_to_roaring(conns, &merge_op, 1).unwrap();
+
+            let got = merge_records(&records, &merge_op).unwrap();
+            let got: Vec<u64> = got.iter().flat_map(|x| {
+                let query_id = x.query_id.unwrap() as u64;
+                x.ones.as_ref().unwrap().iter().map(move |target| query_id * 3 + *target as u64)
+            }).collect();
+
+            assert_eq!(got, expected.into_iter().collect::<Vec<u64>>());
+        }
+    }
+
+    #[test]
+    fn merge_records_by_name() {
+        use super::merge_records;
+        use super::MergeOp;
+        use super::PseudoAln;
+        use crate::errors::IncompleteRecordErr;
+
+        let first = vec![
+            PseudoAln{ ones: Some(vec![0]), ones_names: None, query_id: None, query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: None, query_name: Some(b"r2".to_vec()) },
+        ];
+        let second = vec![
+            PseudoAln{ ones: Some(vec![2, 1]), ones_names: None, query_id: None, query_name: Some(b"r3".to_vec()) },
+            PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: None, query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ ones: Some(vec![2]), ones_names: None, query_id: None, query_name: Some(b"r1".to_vec()) },
+        ];
+
+        let got = merge_records(&[&first, &second], &MergeOp::Union).unwrap();
+        let expected = vec![
+            PseudoAln{ ones: Some(vec![0, 1, 2]), ones_names: None, query_id: None, query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: None, query_name: Some(b"r2".to_vec()) },
+            PseudoAln{ ones: Some(vec![1, 2]), ones_names: None, query_id: None, query_name: Some(b"r3".to_vec()) },
+        ];
+        assert_eq!(got, expected);
+
+        let got = merge_records(&[&first, &second], &MergeOp::Diff).unwrap();
+        assert_eq!(got, first);
+
+        let incomplete = vec![PseudoAln{ ones: Some(vec![0]), ones_names: None, query_id: None, query_name: None }];
+        let got = merge_records(&[&first, &incomplete], &MergeOp::Union).unwrap_err();
+        let got = got.downcast_ref::<IncompleteRecordErr>().unwrap();
+        assert_eq!((got.input, got.record), (1, 0));
+    }
+
+    #[test]
+    fn merge_from_reads_to_roaring_reports_bad_input() {
+        use super::encode_to_write;
+        use super::merge_from_reads_to_roaring;
+        use super::EncodeOpts;
+        use super::MergeOp;
+        use super::PseudoAln;
+        use crate::errors::MergeInputErr;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec()];
+        let data = vec![PseudoAln{ ones: Some(vec![0]), ones_names: None, query_id: Some(0), query_name: None }];
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+
+        let conns = vec![Cursor::new(bytes.clone()), Cursor::new(bytes), Cursor::new(b"not ahda data".to_vec())];
+        let got = merge_from_reads_to_roaring(conns, &MergeOp::Union, 2).unwrap_err();
+
+        assert_eq!(got.downcast_ref::<MergeInputErr>().unwrap().input, 2);
+    }
+
+    #[test]
+    fn encode_decode_and_concatenate_empty_records() {
+        use super::try_concatenate_from_read_to_write;
+        use super::decode_from_read;
+        use super::decode_from_read_to_write;
+        use super::encode_to_write;
+        use super::merge_from_reads_to_roaring;
+        use super::EncodeOpts;
+        use super::Format;
+        use super::MergeOp;
+        use super::PseudoAln;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data = vec![
+            PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(0), query_name: None },
+        ];
+
+        let mut empty: Vec<u8> = Vec::new();
+        let mut nonempty: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &[], &mut empty, EncodeOpts::default()).unwrap();
+        encode_to_write(&targets, &[], &data, &mut nonempty, EncodeOpts::default()).unwrap();
+
+        let (header, _, records) = decode_from_read(&mut Cursor::new(&empty)).unwrap();
+        assert_eq!(header.n_queries, 0);
+        assert!(records.is_empty());
+
+        // Header line is written even without records
+        let mut output: Vec<u8> = Vec::new();
+        decode_from_read_to_write(Format::AhdaTSV, &mut Cursor::new(&empty), &mut output).unwrap();
+        assert_eq!(output, b"query_index\tquery_name\tchr.fasta\tplasmid.fasta\n".to_vec());
+
+        let mut inputs = vec![Cursor::new(empty.clone()), Cursor::new(nonempty.clone()), Cursor::new(empty.clone())];
+        let mut output: Vec<u8> = Vec::new();
+        try_concatenate_from_read_to_write(&mut inputs, &mut output).unwrap();
+        let (_, _, records) = decode_from_read(&mut Cursor::new(&output)).unwrap();
+        assert_eq!(records.into_iter().map(|x| x.ones).collect::<Vec<_>>(), vec![Some(vec![1])]);
+
+        let mut inputs = vec![Cursor::new(empty.clone()), Cursor::new(empty.clone())];
+        let mut output: Vec<u8> = Vec::new();
+        try_concatenate_from_read_to_write(&mut inputs, &mut output).unwrap();
+        assert_eq!(output, empty);
+
+        // Merging into an empty input takes the queries from the next input
+        let mut named: Vec<u8> = Vec::new();
+        let opts = EncodeOpts{ encode_query_names: true, ..Default::default() };
+        let named_data = vec![
+            PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(0), query_name: Some(b"r1".to_vec()) },
+        ];
+        encode_to_write(&targets, &[b"r1".to_vec()], &named_data, &mut named, opts).unwrap();
+        let inputs = vec![Cursor::new(empty.clone()), Cursor::new(named)];
+        let (bitmap, _, _, block_flags) = merge_from_reads_to_roaring(inputs, &MergeOp::Union, 2).unwrap();
+        assert_eq!(bitmap.len(), 1);
+        assert_eq!(block_flags.queries, Some(vec![b"r1".to_vec()]));
+
+        let inputs = vec![Cursor::new(empty.clone()), Cursor::new(nonempty)];
+        let (bitmap, _, _, _) = merge_from_reads_to_roaring(inputs, &MergeOp::Intersection, 2).unwrap();
+        assert!(bitmap.is_empty());
+    }
+
+    #[test]
+    fn encode_from_read_to_write_with_target_aliases() {
+        use super::decode_from_read;
+        use super::encode_from_read_to_write;
+        use super::read_target_aliases;
+        use super::read_target_groups;
+        use super::EncodeOpts;
+        use super::Format;
+
+        use std::io::Cursor;
+
+        let contigs = vec![b"a_1".to_vec(), b"a_2".to_vec(), b"b_1".to_vec(), b"c_1".to_vec()];
+        let aliases = read_target_aliases(&contigs, &mut Cursor::new(b"a_1\tA\r\na_2\tA\r\nb_1\tB\r\nc_1\tC\r\n".to_vec())).unwrap();
+        let groups = read_target_groups(&aliases.target_names, &mut Cursor::new(b"genome\tspecies\nA\tX\nB\tX\nC\tY\n".to_vec())).unwrap();
+
+        let input = b"0\tr1\ta_1:a_2\n1\tr2\ta_2:c_1\n2\tr3\t\n".to_vec();
+        let mut output: Vec<u8> = Vec::new();
+        let mut opts = EncodeOpts{ format: Some(Format::Metagraph), target_aliases: Some(aliases.clone()), target_groups: groups, ..Default::default() };
+        encode_from_read_to_write(Some(&mut contigs.clone().into_iter()), None::<&mut std::vec::IntoIter<Vec<u8>>>, &mut Cursor::new(&input), &mut output, opts.clone()).unwrap();
+
+        let (header, flags, records) = decode_from_read(&mut Cursor::new(&output)).unwrap();
+        assert_eq!(header.n_targets, 3);
+        assert_eq!(flags.target_names, vec![b"A".to_vec(), b"B".to_vec(), b"C".to_vec()]);
+        assert_eq!(flags.target_group(b"species").unwrap().membership, vec![0, 0, 1]);
+        assert!(flags.target_aliases.is_none());
+        assert_eq!(records.into_iter().map(|x| x.ones.unwrap()).collect::<Vec<_>>(), vec![vec![0], vec![0, 2], vec![]]);
+
+        opts.store_target_aliases = true;
+        let mut output: Vec<u8> = Vec::new();
+        encode_from_read_to_write(Some(&mut contigs.clone().into_iter()), None::<&mut std::vec::IntoIter<Vec<u8>>>, &mut Cursor::new(&input), &mut output, opts).unwrap();
+        let (_, flags, _) = decode_from_read(&mut Cursor::new(&output)).unwrap();
+        assert_eq!(flags.target_aliases, Some(aliases));
+
+        // Every contig must belong to a genome
+        assert!(read_target_aliases(&contigs, &mut Cursor::new(b"a_1\tA\n".to_vec())).is_err());
+        assert!(read_target_aliases(&contigs, &mut Cursor::new(b"x_1\tA\n".to_vec())).is_err());
+    }
+
+    #[test]
+    fn encode_and_decode_with_second_mates() {
+        use super::decode_from_read;
+        use super::encode_to_write;
+        use super::EncodeOpts;
+        use super::PseudoAln;
+        use crate::decoder::Decoder;
+
+        use roaring::RoaringBitmap;
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r1".to_vec(), b"r2/2".to_vec()];
+        let data: Vec<PseudoAln> = (0..4).map(|id| {
+            PseudoAln{ ones: Some(vec![id % 2]), ones_names: None, query_id: Some(id), query_name: Some(queries[id as usize].clone()) }
+        }).collect();
+
+        let mut second_mates = RoaringBitmap::new();
+        second_mates.insert_range(2..);
+        let mut bytes: Vec<u8> = Vec::new();
+        let opts = EncodeOpts{ encode_query_names: true, second_mates: Some(second_mates), ..Default::default() };
+        // Blocks of 2 records keep the duplicate names in separate blocks
+        let mut records = data.clone().into_iter();
+        let mut encoder = crate::encoder::Encoder::new(&mut records, &targets, b"sample", queries.len());
+        encoder.set_fields_present(crate::MASK_QUERIES | crate::MASK_QUERY_IDS);
+        encoder.set_second_mates(opts.second_mates.clone().unwrap());
+        encoder.set_block_size(2).unwrap();
+        bytes.append(&mut encoder.encode_file_header_and_flags().unwrap());
+        for block in encoder.by_ref() {
+            bytes.append(&mut block.unwrap());
+        }
+
+        let (header, _, records) = decode_from_read(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(header.fields_present & crate::MASK_MATES, crate::MASK_MATES);
+        let names: Vec<Vec<u8>> = records.into_iter().map(|x| x.query_name.unwrap()).collect();
+        assert_eq!(names, vec![b"r1/1".to_vec(), b"r2/1".to_vec(), b"r1/2".to_vec(), b"r2/2".to_vec()]);
+
+        let mut conn = Cursor::new(&bytes);
+        let mut decoder = Decoder::new(&mut conn);
+        decoder.fill_mate_suffix(false);
+        let names: Vec<Vec<u8>> = decoder.map(|x| x.query_name.unwrap()).collect();
+        assert_eq!(names, queries);
+
+        // Same as encoding without mates if the option is not set
+        let mut with_mates: Vec<u8> = Vec::new();
+        let mut without_mates: Vec<u8> = Vec::new();
+        let unique = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec(), b"r4".to_vec()];
+        let data: Vec<PseudoAln> = data.into_iter().map(|mut x| { x.query_name = Some(unique[x.query_id.unwrap() as usize].clone()); x }).collect();
+        encode_to_write(&targets, &unique, &data, &mut with_mates, opts).unwrap();
+        encode_to_write(&targets, &unique, &data, &mut without_mates, EncodeOpts{ encode_query_names: true, ..Default::default() }).unwrap();
+        assert!(with_mates.len() > without_mates.len());
+        let (_, _, records) = decode_from_read(&mut Cursor::new(&without_mates)).unwrap();
+        assert_eq!(records.into_iter().map(|x| x.query_name).collect::<Vec<_>>(), data.into_iter().map(|x| x.query_name).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn merge_mates_keeps_mates_separate() {
+        use super::decode_from_read;
+        use super::encode_to_write;
+        use super::merge_mates_from_reads_to_write;
+        use super::EncodeOpts;
+        use super::PseudoAln;
+
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let opts = EncodeOpts{ encode_query_names: true, ..Default::default() };
+
+        // r2 is missing from the second mates and r3 from the first mates
+        let first = vec![
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: Some(b"r2/1".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: Some(b"r1/1".to_vec()) },
+        ];
+        let second = vec![
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![]), query_name: Some(b"r3".to_vec()) },
+        ];
+        let mut bytes_first: Vec<u8> = Vec::new();
+        let mut bytes_second: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[b"r1/1".to_vec(), b"r2/1".to_vec()], &first, &mut bytes_first, opts.clone()).unwrap();
+        encode_to_write(&targets, &[b"r1".to_vec(), b"r3".to_vec()], &second, &mut bytes_second, opts.clone()).unwrap();
+
+        let mut merged: Vec<u8> = Vec::new();
+        merge_mates_from_reads_to_write(&mut bytes_first.as_slice(), &mut bytes_second.as_slice(), &mut merged, true, opts.clone()).unwrap();
+        let (header, _, mut got) = decode_from_read(&mut Cursor::new(&merged)).unwrap();
+        got.sort_by_key(|x| x.query_id);
+
+        assert_eq!(header.n_queries, 6);
+        assert_eq!(header.fields_present & crate::MASK_MATES, crate::MASK_MATES);
+        let got: Vec<(u32, Vec<u8>, Vec<u32>)> = got.into_iter().map(|x| (x.query_id.unwrap(), x.query_name.unwrap(), x.ones.unwrap())).collect();
+        let expected: Vec<(u32, Vec<u8>, Vec<u32>)> = vec![
+            (0, b"r1/1".to_vec(), vec![0]),
+            (1, b"r2/1".to_vec(), vec![1]),
+            (2, b"r3/1".to_vec(), vec![]),
+            (3, b"r1/2".to_vec(), vec![0, 1]),
+            (4, b"r2/2".to_vec(), vec![]),
+            (5, b"r3/2".to_vec(), vec![]),
+        ];
+        assert_eq!(got, expected);
+
+        // Union of the mates
+        let mut merged: Vec<u8> = Vec::new();
+        merge_mates_from_reads_to_write(&mut bytes_first.as_slice(), &mut bytes_second.as_slice(), &mut merged, false, opts.clone()).unwrap();
+        let (header, _, mut got) = decode_from_read(&mut Cursor::new(&merged)).unwrap();
+        got.sort_by_key(|x| x.query_id);
+        assert_eq!(header.n_queries, 3);
+        let got: Vec<(Vec<u8>, Vec<u32>)> = got.into_iter().map(|x| (x.query_name.unwrap(), x.ones.unwrap())).collect();
+        assert_eq!(got, vec![(b"r1".to_vec(), vec![0, 1]), (b"r2".to_vec(), vec![1]), (b"r3".to_vec(), vec![])]);
+
+        // Different targets
+        let mut other: Vec<u8> = Vec::new();
+        let other_data = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![1]), query_name: Some(b"r1".to_vec()) }];
+        encode_to_write(&[b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()], &[b"r1".to_vec()], &other_data, &mut other, opts.clone()).unwrap();
+        let mut merged: Vec<u8> = Vec::new();
+        merge_mates_from_reads_to_write(&mut bytes_first.as_slice(), &mut other.as_slice(), &mut merged, false, opts).unwrap();
+        let (_, flags, mut got) = decode_from_read(&mut Cursor::new(&merged)).unwrap();
+        got.sort_by_key(|x| x.query_id);
+        assert_eq!(flags.target_names, vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()]);
+        assert_eq!(got[0].ones, Some(vec![0, 2]));
+    }
+
+    #[test]
+    fn block_info_resolves_references() {
+        use super::block_info_from_read;
+        use super::try_concatenate_from_read_to_write;
+        use super::encode_to_write;
+        use super::BlockCodec;
+        use super::EncodeOpts;
+        use super::PseudoAln;
+        use crate::compression::dedup::deduplicate_blocks;
+
+        use std::io::Cursor;
+        use std::io::Seek;
+
+        let targets = ["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let queries = ["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec(), "r3".as_bytes().to_vec()];
+        let opts = EncodeOpts{ accession: "sample".as_bytes().to_vec(), ..Default::default() };
+
+        let data = [
+            PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![]), query_name: None },
+            PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![0, 1]), query_name: None },
+            PseudoAln{ones_names: None, query_id: Some(2), ones: Some(vec![]), query_name: None },
+        ];
+        let mut inputs: Vec<Cursor<Vec<u8>>> = data.iter().map(|record| {
+            let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+            encode_to_write(&targets, &queries, std::slice::from_ref(record), &mut bytes, opts.clone()).unwrap();
+            bytes.rewind().unwrap();
+            bytes
+        }).collect();
+
+        let mut concatenated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        try_concatenate_from_read_to_write(&mut inputs, &mut concatenated).unwrap();
+        concatenated.rewind().unwrap();
+        let mut deduplicated: Vec<u8> = Vec::new();
+        deduplicate_blocks(&mut concatenated, &mut deduplicated).unwrap();
+
+        let got = block_info_from_read(&mut deduplicated.as_slice()).unwrap();
+
+        assert_eq!(got.iter().map(|x| x.start_query_id).collect::<Vec<Option<u32>>>(), vec![Some(0), Some(1), Some(2)]);
+        assert_eq!(got[2].codec, BlockCodec::Reference(0));
+        assert_eq!(got[2].raw_len, got[0].raw_len);
+        assert_eq!(got[2].compressed_len, 0);
+        assert_eq!(got[1].offset, got[0].offset + got[0].len);
+        assert!(got[1].raw_len > 0);
+
+        // Truncated contents
+        assert!(block_info_from_read(&mut &deduplicated[..(deduplicated.len() - 1)]).is_err());
+
+        // Reference to the block itself
+        let start = got[2].offset as usize;
+        let mut header = crate::headers::block::decode_block_header(&deduplicated[start..(start + 32)]).unwrap();
+        header.placeholder2 = 0;
+        deduplicated.splice(start..(start + 32), crate::headers::block::encode_block_header(&header).unwrap());
+        assert!(block_info_from_read(&mut deduplicated.as_slice()).is_err());
+    }
+
+    #[test]
+    fn export_reads_of_target_groups() {
+        use super::encode_to_write;
+        use super::export_reads_from_read;
+        use super::EncodeOpts;
+        use super::PseudoAln;
+        use crate::headers::file::TargetGroups;
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec(), "virus.fasta".as_bytes().to_vec()];
+        let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+        let data = vec![
+            PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![2]), query_name: Some(b"r2".to_vec()) },
+            PseudoAln{ones_names: None, query_id: Some(2), ones: Some(vec![]), query_name: Some(b"r3".to_vec()) },
+        ];
+        let groups = TargetGroups{ level: b"species".to_vec(), group_names: vec![b"E. coli".to_vec(), b"Phage".to_vec()], membership: vec![0, 0, 1] };
+        let opts = EncodeOpts{ target_groups: vec![groups], encode_query_names: true, ..Default::default() };
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &queries, &data, &mut bytes, opts.clone()).unwrap();
+
+        let got = export_reads_from_read(&mut bytes.as_slice(), Some(b"species")).unwrap();
+        let expected = vec![(b"E. coli".to_vec(), vec![b"r1".to_vec()]), (b"Phage".to_vec(), vec![b"r2".to_vec()])];
+        assert_eq!(got, expected);
+
+        // Without query names
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &queries, &data, &mut bytes, EncodeOpts{ encode_query_names: false, ..opts }).unwrap();
+        assert!(export_reads_from_read(&mut bytes.as_slice(), None).is_err());
+    }
+    #[test]
+    fn core_codec_decodes_encoded_record() {
+        use super::try_concatenate_from_read_to_write;
+        use super::encode_to_write;
+        use super::EncodeOpts;
+        use super::PseudoAln;
+        use crate::compression::dedup::deduplicate_blocks;
+        use ahda_core::file::decode_names;
+        use ahda_core::split_record;
+
+        use std::io::Cursor;
+        use std::io::Seek;
+
+        let targets = ["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let queries = ["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec(), "r3".as_bytes().to_vec()];
+        let opts = EncodeOpts{ accession: "sample".as_bytes().to_vec(), ..Default::default() };
+
+        let data = [
+            PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![]), query_name: None },
+            PseudoAln{ones_names: None, query_id: Some(1), ones: Some(vec![0, 1]), query_name: None },
+            PseudoAln{ones_names: None, query_id: Some(2), ones: Some(vec![]), query_name: None },
+        ];
+        let mut inputs: Vec<Cursor<Vec<u8>>> = data.iter().map(|record| {
+            let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+            encode_to_write(&targets, &queries, std::slice::from_ref(record), &mut bytes, opts.clone()).unwrap();
+            bytes.rewind().unwrap();
+            bytes
+        }).collect();
+
+        let mut concatenated: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        try_concatenate_from_read_to_write(&mut inputs, &mut concatenated).unwrap();
+        concatenated.rewind().unwrap();
+        let mut deduplicated: Vec<u8> = Vec::new();
+        deduplicate_blocks(&mut concatenated, &mut deduplicated).unwrap();
+
+        let (header, flags, blocks) = split_record(&deduplicated).unwrap();
+        assert_eq!(decode_names(flags, header.metadata_compression).unwrap(), (b"sample".to_vec(), targets.to_vec()));
+
+        let mut got: Vec<(Vec<u32>, Vec<u64>)> = Vec::new();
+        for block in blocks {
+            let block = block.unwrap();
+            let (_, query_ids) = block.queries().unwrap();
+            got.push((query_ids.unwrap(), block.bits().unwrap()));
+        }
+        // The last block refers to the contents of the first block
+        assert_eq!(got, vec![(vec![0], vec![]), (vec![1], vec![2, 3]), (vec![2], vec![])]);
+    }
+
+    #[test]
+    fn round_trip_check_of_conversions() {
+        use super::decode_from_read_to_write;
+        use super::digest_from_read;
+        use super::encode_to_write;
+        use super::round_trip_check;
+        use super::EncodeOpts;
+        use super::Format;
+        use crate::errors::RoundTripMismatchErr;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+        let data = vec![
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![1]), query_name: Some(b"r3".to_vec()) },
+        ];
+        let opts = EncodeOpts{ accession: b"sample".to_vec(), encode_query_names: true, ..Default::default() };
+        let mut encoded: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &queries, &data, &mut encoded, opts).unwrap();
+
+        // Query ids of name-only output come from the names in the .ahda records
+        let mut fulgor: Vec<u8> = Vec::new();
+        decode_from_read_to_write(Format::Fulgor, &mut encoded.as_slice(), &mut fulgor).unwrap();
+        let digest = round_trip_check(&encoded, &fulgor, None, None).unwrap();
+        assert_eq!(digest, digest_from_read(&mut encoded.as_slice()).unwrap());
+
+        let themisto = b"0 0 1\n1\n2 1\n".to_vec();
+        assert_eq!(round_trip_check(&themisto, &fulgor, Some(&targets), Some(&queries)).unwrap(), digest);
+
+        let lossy = b"0 0\n2 1\n".to_vec();
+        let got = round_trip_check(&encoded, &lossy, None, None).unwrap_err();
+        let got = got.downcast_ref::<RoundTripMismatchErr>().unwrap();
+        assert_eq!(got.input_digest, digest);
+        assert_ne!(got.output_digest, digest);
+    }
+
+    #[test]
+    fn update_flags_in_place_or_rewrite() {
+        use super::decode_from_read;
+        use super::encode_to_write;
+        use super::update_flags;
+        use super::EncodeOpts;
+        use crate::compression::MetadataCompression;
+        use crate::errors::TargetCountChangedErr;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data: Vec<PseudoAln> = (0..3).map(|id| PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }).collect();
+        let opts = EncodeOpts{ accession: b"sample_with_a_long_typo_in_its_name".to_vec(), ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+
+        let path = std::env::temp_dir().join(format!("ahda-update-flags-{}.ahda", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+
+        // Shorter flags are padded
+        assert!(update_flags(&path, |flags| { flags.query_name = b"sample".to_vec(); Ok(()) }).unwrap());
+        let got = std::fs::read(&path).unwrap();
+        assert_eq!(got.len(), bytes.len());
+        let (header, flags, records) = decode_from_read(&mut got.as_slice()).unwrap();
+        assert_eq!(flags.query_name, b"sample".to_vec());
+        assert_eq!(records.iter().map(|x| x.ones.clone()).collect::<Vec<_>>(), data.iter().map(|x| x.ones.clone()).collect::<Vec<_>>());
+        // ahda_core skips the padding too
+        let (query_name, _) = ahda_core::file::decode_names(&got[32..(32 + header.flags_len as usize)], header.metadata_compression).unwrap();
+        assert_eq!(query_name, b"sample".to_vec());
+
+        // Longer flags rewrite the file
+        let long_name: Vec<u8> = (0..4096).map(|i| b'a' + (i * 7 % 26) as u8).collect();
+        assert!(!update_flags(&path, |flags| { flags.query_name = long_name.clone(); Ok(()) }).unwrap());
+        let got = std::fs::read(&path).unwrap();
+        let (_, flags, records) = decode_from_read(&mut got.as_slice()).unwrap();
+        assert_eq!(flags.query_name, long_name);
+        assert_eq!(records.len(), data.len());
+
+        let got = update_flags(&path, |flags| { flags.target_names.pop(); Ok(()) }).unwrap_err();
+        assert!(got.downcast_ref::<TargetCountChangedErr>().is_some());
+
+        // Gzipped flags are padded in the gzip header
+        let opts = EncodeOpts{ accession: b"sample_with_a_long_typo_in_its_name".to_vec(), metadata_compression: MetadataCompression::Flate2, ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(update_flags(&path, |flags| { flags.query_name = b"sample".to_vec(); Ok(()) }).unwrap());
+        let got = std::fs::read(&path).unwrap();
+        assert_eq!(got.len(), bytes.len());
+        let (header, flags, _) = decode_from_read(&mut got.as_slice()).unwrap();
+        assert_eq!(flags.query_name, b"sample".to_vec());
+        let (query_name, _) = ahda_core::file::decode_names(&got[32..(32 + header.flags_len as usize)], header.metadata_compression).unwrap();
+        assert_eq!(query_name, b"sample".to_vec());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sort_block_flags_of_unsorted_blocks() {
+        use super::decode_from_read;
+        use super::sort_block_flags;
+        use crate::compression::roaring32::pack_block_roaring32;
+        use crate::compression::BLOCK_QUERY_IDS_SORTED;
+        use crate::decoder::block_index::BlockIndex;
+        use crate::encoder::Encoder;
+        use crate::PseudoAln;
+        use roaring::RoaringBitmap;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let mut records = std::iter::empty::<PseudoAln>();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 4);
+        let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+        // Queries 1 and 3 align to the plasmid
+        bytes.append(&mut pack_block_roaring32(&[b"r1".to_vec(), b"r0".to_vec()], &[1, 0], RoaringBitmap::from_iter([0, 3])).unwrap());
+        bytes.append(&mut pack_block_roaring32(&[b"r2".to_vec(), b"r3".to_vec()], &[2, 3], RoaringBitmap::from_iter([4, 7])).unwrap());
+
+        let index = BlockIndex::new(&bytes).unwrap();
+        assert_eq!(index.find_query(0).unwrap(), Some((0, 1)));
+
+        let mut sorted: Vec<u8> = Vec::new();
+        assert_eq!(sort_block_flags(&mut bytes.as_slice(), &mut sorted).unwrap(), 1);
+        let index = BlockIndex::new(&sorted).unwrap();
+        assert_eq!(index.block_flags(0).unwrap().queries, Some(vec![b"r0".to_vec(), b"r1".to_vec()]));
+        let mut conn = sorted.as_slice();
+        crate::headers::file::read_file_header_and_flags(&mut conn).unwrap();
+        for _ in 0..2 {
+            let block_header = crate::headers::block::read_block_header(&mut conn).unwrap();
+            assert_ne!(block_header.placeholder1 & BLOCK_QUERY_IDS_SORTED, 0);
+            crate::headers::read_len_bytes(&mut conn, block_header.flags_len + block_header.block_len as u64).unwrap();
+        }
+        assert_eq!(index.find_query(0).unwrap(), Some((0, 0)));
+        assert_eq!(index.find_query(3).unwrap(), Some((1, 1)));
+        assert_eq!(index.find_query(4).unwrap(), None);
+
+        let (_, _, mut expected) = decode_from_read(&mut bytes.as_slice()).unwrap();
+        let (_, _, mut got) = decode_from_read(&mut sorted.as_slice()).unwrap();
+        expected.sort_by_key(|x| x.query_id);
+        got.sort_by_key(|x| x.query_id);
+        assert_eq!(got, expected);
+    }
 }
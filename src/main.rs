@@ -12,21 +12,57 @@
 // at your option.
 //
 use ahda::EncodeOpts;
+use ahda::fastx::FastxNameReader;
+use ahda::parser::follow::FollowReader;
+use ahda::report::Report;
+use ahda::report::ReportFormat;
+use ahda::report::Value;
 
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Write;
 use std::io::IsTerminal;
+use std::io::Seek;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 
 use clap::Parser;
 
 mod cli;
 
+/// Set by `--quiet` to suppress everything but errors on stderr.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Prints a message that is not an error to stderr unless `--quiet` was given.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Exit code for errors not covered by the other codes.
+const EXIT_OTHER: u8 = 1;
+/// Exit code for [InvalidInput](ahda::errors::ErrorCategory::InvalidInput) errors. Usage errors exit with 2.
+const EXIT_INVALID_INPUT: u8 = 3;
+/// Exit code for [Incompatible](ahda::errors::ErrorCategory::Incompatible) errors.
+const EXIT_INCOMPATIBLE: u8 = 4;
+/// Exit code for [Io](ahda::errors::ErrorCategory::Io) errors.
+const EXIT_IO: u8 = 5;
+
 /// Initializes the logger with verbosity given in `log_max_level`.
+///
+/// Only errors are logged with `--quiet`.
 fn init_log(log_max_level: usize) {
+    let log_max_level = if QUIET.load(Ordering::Relaxed) { 0 } else { log_max_level };
     stderrlog::new()
     .module(module_path!())
     .quiet(false)
@@ -38,51 +74,547 @@ fn init_log(log_max_level: usize) {
 
 type E = Box<dyn std::error::Error>;
 
-struct FastxNameReader {
-    reader: Box<dyn needletail::FastxReader>,
+/// Number of bytes and their CRC32.
+type Checksum = Arc<Mutex<(u64, flate2::Crc)>>;
+
+/// Computes the length and CRC32 of the bytes read from `inner`.
+///
+/// The checksum is shared so that it can be read after the reader has been
+/// moved into a function that consumes it.
+struct ChecksumReader<R: Read> {
+    inner: R,
+    crc: Checksum,
 }
 
-impl FastxNameReader {
+impl<R: Read> ChecksumReader<R> {
     pub fn new(
-        file: &PathBuf,
-    ) -> Result<Self, E> {
-        let reader = needletail::parse_fastx_file(file)?;
-        Ok(Self{ reader })
+        inner: R,
+    ) -> (Self, Checksum) {
+        let crc = Arc::new(Mutex::new((0, flate2::Crc::new())));
+        (Self{ inner, crc: crc.clone() }, crc)
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> std::io::Result<usize> {
+        let nbytes = self.inner.read(buf)?;
+        let mut crc = self.crc.lock().unwrap();
+        crc.0 += nbytes as u64;
+        crc.1.update(&buf[0..nbytes]);
+        Ok(nbytes)
     }
 }
 
-impl Iterator for FastxNameReader {
-    type Item = Vec<u8>;
+/// Writes a copy of the bytes read from `inner` to a shared writer.
+///
+/// The writer is shared so that it can be finished after the reader has been
+/// moved into a function that consumes it.
+struct TeeReader<R: Read, W: Write> {
+    inner: R,
+    copy: Arc<Mutex<W>>,
+}
+
+impl<R: Read, W: Write> TeeReader<R, W> {
+    pub fn new(
+        inner: R,
+        copy: W,
+    ) -> (Self, Arc<Mutex<W>>) {
+        let copy = Arc::new(Mutex::new(copy));
+        (Self{ inner, copy: copy.clone() }, copy)
+    }
+}
 
-    fn next(
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(
         &mut self,
-    ) -> Option<Vec<u8>> {
-        let record = self.reader.next()?;
-        let query_info = record.unwrap();
-        let end = query_info.id().iter().position(|x| x == &b' ');
-        Some(query_info.id()[0..end.unwrap_or(query_info.id().len())].to_vec())
+        buf: &mut [u8],
+    ) -> std::io::Result<usize> {
+        let nbytes = self.inner.read(buf)?;
+        self.copy.lock().unwrap().write_all(&buf[0..nbytes])?;
+        Ok(nbytes)
+    }
+}
+
+/// Seconds since the Unix epoch at `time`.
+fn unix_seconds(
+    time: std::time::SystemTime,
+) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0)
+}
+
+/// Reads the query names from `query_file` or from an up to date `cache_file`.
+///
+/// If `cache_file` doesn't exist or was built from a different query file,
+/// the names are parsed from `query_file` and written to `cache_file`. If
+/// `checksum` is true, the cache must also match the CRC32 of `query_file`.
+/// If `rename_duplicates` is true, repeated names get an occurrence suffix.
+fn read_query_names(
+    query_file: &PathBuf,
+    cache_file: Option<&PathBuf>,
+    checksum: bool,
+    rename_duplicates: bool,
+    verbose: bool,
+) -> Result<Box<dyn Iterator<Item = Vec<u8>>>, E> {
+    let names = read_query_names_or_cache(query_file, cache_file, checksum, verbose)?;
+    if rename_duplicates {
+        return Ok(Box::new(ahda::parser::query_ids::RenameDuplicates::new(names)))
+    }
+    Ok(names)
+}
+
+/// Reads the query names from `query_file` or from an up to date `cache_file`.
+///
+/// If `cache_file` doesn't exist or was built from a different query file,
+/// the names are parsed from `query_file` and written to `cache_file`. The
+/// cache is matched on the metadata of `query_file`, and also on its CRC32
+/// if `checksum` is true.
+fn read_query_names_or_cache(
+    query_file: &PathBuf,
+    cache_file: Option<&PathBuf>,
+    checksum: bool,
+    verbose: bool,
+) -> Result<Box<dyn Iterator<Item = Vec<u8>>>, E> {
+    let Some(cache_file) = cache_file else {
+        return Ok(Box::new(FastxNameReader::new(query_file)?))
+    };
+
+    let stamp = ahda::query_cache::fastx_stamp(query_file, checksum)?;
+    let cache = File::open(cache_file).ok().and_then(|mut f| ahda::query_cache::QueryCache::read_from(&mut f).ok());
+    if let Some(cache) = cache.filter(|x| x.matches(&stamp)) {
+        if verbose {
+            note!("ahda: read {} query names from `{}`", cache.n_queries, cache_file.to_string_lossy());
+        }
+        return Ok(Box::new(cache.names.into_iter()))
+    }
+
+    let names: Vec<Vec<u8>> = FastxNameReader::new(query_file)?.try_iter().collect::<Result<Vec<Vec<u8>>, E>>()?;
+    let cache = ahda::query_cache::QueryCache::new(names, stamp);
+    // The names were read, so failing to cache them is not an error
+    if let Err(e) = File::create(cache_file).map_err(|e| Box::new(e) as E).and_then(|mut f| cache.write_to(&mut f)) {
+        note!("ahda: can't write query cache `{}`: {}", cache_file.to_string_lossy(), e);
+    } else if verbose {
+        note!("ahda: wrote {} query names to `{}`", cache.n_queries, cache_file.to_string_lossy());
+    }
+    Ok(Box::new(cache.names.into_iter()))
+}
+
+/// Returns true if `path` is a named pipe, process substitution, character device, or socket.
+///
+/// These have no name to derive an output file from and must not be removed
+/// after reading, so they are treated like stdin. Symlinks are followed, so
+/// `/dev/stdin` redirected from a regular file is a regular file.
+#[cfg(unix)]
+fn is_piped(
+    path: &std::path::Path,
+) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|x| x.file_type().is_fifo() || x.file_type().is_char_device() || x.file_type().is_socket())
+}
+
+#[cfg(not(unix))]
+fn is_piped(
+    _path: &std::path::Path,
+) -> bool {
+    false
+}
+
+/// Removes the input file `path` after it was read.
+///
+/// Symlinks are kept, since they can point to a file the user didn't name,
+/// like `/dev/stdin` redirected from a regular file.
+fn remove_input(
+    path: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    if std::fs::symlink_metadata(path)?.file_type().is_symlink() {
+        note!("ahda: keeping input file `{}` because it is a symlink", path.to_string_lossy());
+        return Ok(())
+    }
+    std::fs::remove_file(path)
+}
+
+/// Reads the file header and flags of .ahda data without consuming them.
+///
+/// Returns the reader with the bytes chained back in front, so that inputs
+/// that can't seek, like named pipes, can still be read from the start.
+fn peek_file_flags(
+    mut conn: Box<dyn Read>,
+) -> Result<(Box<dyn Read>, ahda::headers::file::FileFlags), E> {
+    let mut prefix: Vec<u8> = Vec::new();
+    conn.by_ref().take(32).read_to_end(&mut prefix)?;
+    if prefix.len() < 32 {
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)))
+    }
+    let header = ahda::headers::file::decode_file_header(&prefix)?;
+    conn.by_ref().take(header.flags_len).read_to_end(&mut prefix)?;
+    let flags = ahda::headers::file::read_file_flags_any_layout(&header, &mut &prefix[32..])?;
+    Ok((Box::new(Cursor::new(prefix).chain(conn)), flags))
+}
+
+/// Opens `input_file` or stdin and checks if it contains .ahda data.
+///
+/// The bytes read while sniffing are chained back in front of the returned
+/// reader, so it can be passed as-is to either the decoder or the parser.
+fn open_input(
+    input_file: Option<&PathBuf>,
+) -> Result<(Box<dyn Read + Send>, bool), std::io::Error> {
+    let mut conn: Box<dyn Read + Send> = if let Some(file) = input_file {
+        Box::new(File::open(file)?)
+    } else {
+        Box::new(std::io::stdin())
+    };
+
+    let mut prefix: Vec<u8> = Vec::with_capacity(ahda::MAGIC.len());
+    conn.by_ref().take(ahda::MAGIC.len() as u64).read_to_end(&mut prefix)?;
+    let is_ahda = ahda::is_ahda_file(&mut prefix.as_slice());
+
+    Ok((Box::new(Cursor::new(prefix).chain(conn)), is_ahda))
+}
+
+/// Reads which query fields the input stores without consuming it.
+///
+/// Returns the reader with the bytes chained back in front and the
+/// `fields_present` mask of the input, or None if it can't be determined.
+fn peek_input_fields(
+    mut conn: Box<dyn Read + Send>,
+    is_ahda: bool,
+) -> Result<(Box<dyn Read + Send>, Option<u16>), std::io::Error> {
+    let mut prefix: Vec<u8> = Vec::new();
+    let fields_present = if is_ahda {
+        conn.by_ref().take(32).read_to_end(&mut prefix)?;
+        if prefix.len() == 32 { ahda::headers::file::decode_file_header(&prefix).ok().map(|x| x.fields_present) } else { None }
+    } else {
+        conn.by_ref().take(4096).read_to_end(&mut prefix)?;
+        ahda::parser::guess_format(&prefix).ok().map(|x| x.fields_present())
+    };
+
+    Ok((Box::new(Cursor::new(prefix).chain(conn)), fields_present))
+}
+
+/// Warns about query fields in the input that `format` can't store.
+///
+/// Errors instead if `strict` is true.
+fn check_lost_fields(
+    fields_present: Option<u16>,
+    format: &ahda::Format,
+    strict: bool,
+) -> Result<(), E> {
+    let lost = fields_present.map(|x| format.lost_fields(x)).unwrap_or_default();
+    for field in lost.iter() {
+        eprintln!("ahda: output format `{}` can't store the {} in the input", format, field);
+    }
+    if strict && !lost.is_empty() {
+        return Err(Box::new(ahda::errors::LossyConversionErr{}))
+    }
+    Ok(())
+}
+
+/// Options of `ahda convert` that apply to every input.
+struct ConvertOpts {
+    format: ahda::Format,
+    sample_name: Option<Vec<u8>>,
+    strict: bool,
+    stdout: bool,
+    force: bool,
+    keep: bool,
+    selection: ahda::printer::TargetSelection,
+    program: Option<ahda::printer::Program>,
+    round_trip_check: bool,
+    chunk_size: usize,
+    buffer_size: usize,
+    verbose: bool,
+}
+
+/// Wraps an output connection in a buffer of `buffer_size` KiB.
+fn buffered_output<W: Write + 'static>(
+    conn_out: W,
+    buffer_size: usize,
+) -> Box<dyn Write> {
+    Box::new(ahda::output::buffered(conn_out, buffer_size * 1024))
+}
+
+/// Output path of `ahda decode` for `input_file`, its name without the extension.
+///
+/// Errors if the path is the input itself, so that creating the output
+/// doesn't truncate the input before it is read.
+fn decoded_path(
+    input_file: &std::path::Path,
+) -> Result<PathBuf, std::io::Error> {
+    let out_path = PathBuf::from(input_file.file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_default());
+    if out_path.file_name().is_none() || std::fs::canonicalize(&out_path).ok().is_some_and(|x| std::fs::canonicalize(input_file).is_ok_and(|y| x == y)) {
+        return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "output would replace the input, use `--stdout`"))
+    }
+    Ok(out_path)
+}
+
+/// Replaces `{dir}`, `{name}`, and `{stem}` in `template` with the parts of `input_file`.
+fn output_from_template(
+    template: &str,
+    input_file: &std::path::Path,
+) -> PathBuf {
+    let dir = input_file.parent().map(|x| x.to_string_lossy().to_string()).filter(|x| !x.is_empty()).unwrap_or(".".to_string());
+    let name = input_file.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    let stem = input_file.file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    PathBuf::from(template.replace("{dir}", &dir).replace("{name}", &name).replace("{stem}", &stem))
+}
+
+/// Reads the headers of `input_files`, preceded by stdin if `read_stdin` is true.
+///
+/// Inputs that can't be opened or read are listed as problems instead.
+fn plan_inputs(
+    input_files: &[PathBuf],
+    read_stdin: bool,
+) -> (Vec<ahda::dry_run::PlannedInput>, Vec<String>) {
+    let mut inputs: Vec<ahda::dry_run::PlannedInput> = Vec::with_capacity(input_files.len() + 1);
+    let mut problems: Vec<String> = Vec::new();
+    if read_stdin {
+        match ahda::dry_run::PlannedInput::from_read(b"-", &mut std::io::stdin(), None) {
+            Ok(input) => inputs.push(input),
+            Err(e) => problems.push(format!("can't read input from stdin: {}", e)),
+        }
+    }
+    for file in input_files {
+        let len = if is_piped(file) { None } else { std::fs::metadata(file).ok().map(|x| x.len()) };
+        match File::open(file).map_err(|e| e.into()).and_then(|mut conn| ahda::dry_run::PlannedInput::from_read(file.to_string_lossy().as_bytes(), &mut conn, len)) {
+            Ok(input) => inputs.push(input),
+            Err(e) => problems.push(format!("can't read input file `{}`: {}", file.to_string_lossy(), e)),
+        }
+    }
+    (inputs, problems)
+}
+
+/// Prints `plan` to stdout and errors if the command would not run.
+fn print_plan(
+    plan: &ahda::dry_run::Plan,
+) -> Result<(), E> {
+    let is_terminal = std::io::stdout().is_terminal();
+    let format = ReportFormat::Auto.resolve(is_terminal);
+    let color = format == ReportFormat::Pretty && ahda::report::use_color(is_terminal);
+    let mut conn_out = std::io::stdout();
+    plan.report().write(&mut conn_out, format, color)?;
+    conn_out.flush()?;
+    if let Err(e) = plan.check() {
+        eprintln!("ahda: dry run failed: {}", e);
+        return Err(e)
+    }
+    Ok(())
+}
+
+/// Converts `input_file` or stdin to `output_file`.
+///
+/// Without `output_file` the input file is replaced with the output, unless
+/// writing to stdout. Prints a message before returning an error.
+fn convert_input<Q: Iterator<Item=Vec<u8>>>(
+    input_file: Option<&PathBuf>,
+    output_file: Option<&PathBuf>,
+    targets: Option<&[Vec<u8>]>,
+    queries: Option<Q>,
+    opts: &ConvertOpts,
+) -> Result<(), E> {
+    let in_name = input_file.map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+
+    let (conn_in, is_ahda) = match open_input(input_file) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+            return Err(Box::new(e))
+        },
+    };
+
+    let (mut conn_in, mut fields_present) = match peek_input_fields(conn_in, is_ahda) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("ahda: can't read input file `{}`: {}", in_name, e);
+            return Err(Box::new(e))
+        },
+    };
+    if !is_ahda && queries.is_some() {
+        // Query names and ids come from the query file
+        fields_present = Some(ahda::Format::AhdaTSV.fields_present());
+    }
+    if let Err(e) = check_lost_fields(fields_present, &opts.format, opts.strict) {
+        eprintln!("ahda: can't convert input file `{}`: {}", in_name, e);
+        return Err(e)
+    }
+
+    if !is_ahda && opts.sample_name.is_none() {
+        eprintln!("ahda: use `--name` to supply the sample name");
+        return Ok(())
+    }
+
+    let piped = input_file.is_some_and(|x| is_piped(x));
+
+    // Plain text inputs are converted in place, .ahda inputs are decoded like `ahda decode --keep`
+    let in_place = !is_ahda && output_file.is_none();
+    let mut outputs: Vec<Box<dyn Write>> = Vec::new();
+    if let Some(input_file) = input_file.filter(|_| !opts.stdout && (output_file.is_some() || !piped)) {
+        let out_path = if let Some(out) = output_file {
+            out.clone()
+        } else if in_place {
+            PathBuf::from(input_file.to_string_lossy().to_string() + ".tmp")
+        } else {
+            match decoded_path(input_file) {
+                Ok(out_path) => out_path,
+                Err(e) => {
+                    eprintln!("ahda: can't create output file for `{}`: {}", in_name, e);
+                    return Err(Box::new(e))
+                },
+            }
+        };
+
+        match if opts.force { File::create(out_path.clone()) } else { File::create_new(out_path.clone()) } {
+            Ok(conn_out) => {
+                outputs.push(buffered_output(conn_out, opts.buffer_size));
+            },
+            Err(e) => {
+                eprintln!("ahda: can't create output file `{}`: {}", out_path.to_string_lossy(), e);
+                return Err(Box::new(e))
+            },
+        }
+    }
+
+    if opts.stdout || ((input_file.is_none() || piped) && output_file.is_none()) {
+        outputs.push(buffered_output(std::io::stdout(), opts.buffer_size));
+    }
+
+    if opts.round_trip_check {
+        let queries: Option<Vec<Vec<u8>>> = queries.map(|x| x.collect());
+        let mut input: Vec<u8> = Vec::new();
+        let mut output: Vec<u8> = Vec::new();
+        let ret = conn_in.read_to_end(&mut input).map_err(|e| Box::new(e) as E).and_then(|_| {
+            convert_one(&mut input.as_slice(), &mut output, is_ahda, targets, queries.as_ref().map(|x| x.iter().cloned()), &in_name, opts)
+        });
+        if let Err(e) = ret {
+            eprintln!("ahda: can't convert input file `{}`: {}", in_name, e);
+            return Err(e)
+        }
+        if let Err(e) = ahda::round_trip_check(&input, &output, targets, queries.as_deref()) {
+            eprintln!("ahda: round-trip check of input file `{}` failed: {}", in_name, e);
+            return Err(e)
+        }
+        if opts.verbose {
+            note!("ahda: round-trip check of input `{}` passed", in_name);
+        }
+        if let Err(e) = outputs[0].write_all(&output).and_then(|_| outputs[0].flush()) {
+            eprintln!("ahda: can't write output of input file `{}`: {}", in_name, e);
+            return Err(Box::new(e))
+        }
+    } else if let Err(e) = convert_one(&mut conn_in, &mut outputs[0], is_ahda, targets, queries, &in_name, opts).and_then(|_| Ok(outputs[0].flush()?)) {
+        eprintln!("ahda: can't convert input file `{}`: {}", in_name, e);
+        return Err(e)
+    }
+    // The output replaces the input only after it is written
+    drop(outputs);
+
+    if let Some(input_file) = input_file.filter(|_| !opts.keep && !opts.stdout && !piped && in_place) {
+        let tmp_path = PathBuf::from(input_file.to_string_lossy().to_string() + ".tmp");
+        match std::fs::rename(tmp_path.clone(), input_file) {
+            Ok(()) => (),
+            Err(e) => {
+                eprintln!("ahda: can't rename output file `{}` to `{}`: {}", tmp_path.to_string_lossy(), in_name, e);
+                return Err(Box::new(e))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts or decodes `conn_in` to `conn_out` in the output format of `opts`.
+fn convert_one<R: Read + Send, W: Write, Q: Iterator<Item=Vec<u8>>>(
+    conn_in: &mut R,
+    conn_out: &mut W,
+    is_ahda: bool,
+    targets: Option<&[Vec<u8>]>,
+    queries: Option<Q>,
+    in_name: &str,
+    opts: &ConvertOpts,
+) -> Result<(), E> {
+    #[allow(clippy::manual_map)]
+    let t_it = if let Some(t) = targets { Some(&mut t.iter().cloned()) } else { None };
+    if is_ahda {
+        // Encoded input, decode to the output format instead
+        if opts.verbose {
+            note!("ahda: input `{}` is .ahda data, decoding", in_name);
+        }
+        ahda::pipeline::Pipeline::new().select_targets(opts.selection.clone()).program(opts.program.clone()).output(ahda::pipeline::Output::Text(opts.format.clone())).run(conn_in, conn_out).map(|_| ())
+    } else if let Some(mut q_it) = queries {
+        ahda::convert_stream(t_it, Some(&mut q_it), opts.sample_name.as_ref().unwrap(), opts.format.clone(), &opts.selection, opts.program.as_ref(), opts.chunk_size, conn_in, conn_out).map(|_| ())
+    } else {
+        ahda::convert_stream(t_it, None::<&mut std::iter::Empty<Vec<u8>>>, opts.sample_name.as_ref().unwrap(), opts.format.clone(), &opts.selection, opts.program.as_ref(), opts.chunk_size, conn_in, conn_out).map(|_| ())
     }
 }
 
-fn main() -> Result<(),  Box<dyn std::error::Error>> {
+fn main() -> std::process::ExitCode {
     let cli = cli::Cli::parse();
+    QUIET.store(cli.quiet, Ordering::Relaxed);
+
+    match run(&cli) {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            let code = match ahda::errors::categorize(e.as_ref()) {
+                ahda::errors::ErrorCategory::InvalidInput => EXIT_INVALID_INPUT,
+                ahda::errors::ErrorCategory::Incompatible => EXIT_INCOMPATIBLE,
+                ahda::errors::ErrorCategory::Io => EXIT_IO,
+                _ => EXIT_OTHER,
+            };
+            std::process::ExitCode::from(code)
+        },
+    }
+}
 
+/// Runs the subcommand in `cli`.
+fn run(
+    cli: &cli::Cli,
+) -> Result<(), E> {
     // Subcommands:
     match &cli.command {
         // Encode
         Some(cli::Commands::Encode {
             input_file,
             input_format,
+            follow,
+            sort_input,
+            sort_memory,
+            tmp_dir,
             query_file,
+            mate_file,
+            lanes,
+            query_cache,
+            query_cache_checksum,
+            rename_duplicates,
+            allow_extra,
+            query_ids,
+            query_id_map,
             target_list,
+            index_dir,
+            target_groups,
+            target_aliases,
+            store_aliases,
             sample_name,
             stdout,
             force,
             keep,
+            level,
+            no_inline,
+            query_filter,
+            target_summary,
+            zstd_seekable,
+            flags_dictionary,
+            bitmap,
+            target_chunks,
+            max_hits,
+            max_hits_action,
+            coverage_bucket,
+            index_version,
+            tee,
             rename,
             verbose,
         }) => {
-            init_log(if *verbose { 2 } else { 1 });
+            init_log(1 + *verbose as usize);
 
             let mut targets = None;
             if let Some(target_list) = target_list {
@@ -97,9 +629,55 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
                     },
                 }
             }
+            if let Some(index_dir) = index_dir {
+                match ahda::index::read_index_targets(index_dir) {
+                    Ok((kind, names)) => {
+                        let digest: String = ahda::index::target_list_digest(&names).iter().map(|x| format!("{:02x}", x)).collect();
+                        log::info!("Read {} targets from {} index in {} (sha256 {})", names.len(), kind, index_dir.to_string_lossy(), digest);
+                        targets = Some(names);
+                    },
+                    Err(e) => {
+                        eprintln!("ahda: can't read targets from index directory `{}`: {}", index_dir.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            }
+
+            let mut aliases = None;
+            if let Some(aliases_file) = target_aliases {
+                let res = match File::open(aliases_file) {
+                    Ok(mut f) => ahda::read_target_aliases(targets.as_ref().unwrap(), &mut f),
+                    Err(e) => Err(Box::new(e) as E),
+                };
+                match res {
+                    Ok(res) => aliases = Some(res),
+                    Err(e) => {
+                        eprintln!("ahda: can't read target aliases from `{}`: {}", aliases_file.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            }
+
+            let mut groups = Vec::new();
+            if let Some(groups_file) = target_groups {
+                // Groups refer to the merged targets if there are aliases
+                let group_targets = aliases.as_ref().map_or(targets.as_ref().unwrap(), |x| &x.target_names);
+                let res = match File::open(groups_file) {
+                    Ok(mut f) => ahda::read_target_groups(group_targets, &mut f),
+                    Err(e) => Err(Box::new(e) as E),
+                };
+                match res {
+                    Ok(res) => groups = res,
+                    Err(e) => {
+                        eprintln!("ahda: can't read target groups from `{}`: {}", groups_file.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            }
 
-            let queries: Option<FastxNameReader> = if let Some(query_file) = query_file {
-                match FastxNameReader::new(query_file) {
+            let mut queries: Option<Box<dyn Iterator<Item = Vec<u8>>>> = if let Some(query_file) = query_file {
+                // Duplicates across lanes are renamed after reading all lanes
+                match read_query_names(query_file, query_cache.as_ref(), *query_cache_checksum, *rename_duplicates && lanes.is_empty(), *verbose > 0) {
                     Ok(reader) => Some(reader),
                     Err(e) => {
                         eprintln!("ahda: can't open input file `{}`: {}", query_file.to_string_lossy(), e);
@@ -110,11 +688,88 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
                 None
             };
 
+            // Reads of the other lanes are numbered after the query file
+            // so that the lanes are encoded as one sample.
+            let mut sample_lanes = None;
+            if let Some(query_file) = query_file.as_ref().filter(|_| !lanes.is_empty()) {
+                let mut names: Vec<Vec<u8>> = queries.take().unwrap().collect();
+                let mut lane_names: Vec<Vec<u8>> = vec![query_file.to_string_lossy().as_bytes().to_vec()];
+                let mut n_reads: Vec<usize> = vec![names.len()];
+                for lane in lanes {
+                    let before = names.len();
+                    match FastxNameReader::new(lane).and_then(|mut reader| reader.try_iter().collect::<Result<Vec<Vec<u8>>, E>>()) {
+                        Ok(lane_queries) => names.extend(lane_queries),
+                        Err(e) => {
+                            eprintln!("ahda: can't read input file `{}`: {}", lane.to_string_lossy(), e);
+                            return Err(e)
+                        },
+                    }
+                    lane_names.push(lane.to_string_lossy().as_bytes().to_vec());
+                    n_reads.push(names.len() - before);
+                }
+                match ahda::lanes::Lanes::new(lane_names, &n_reads) {
+                    Ok(res) => {
+                        log::info!("Read {} queries from {} lanes", res.n_queries, res.names.len());
+                        sample_lanes = Some(res);
+                    },
+                    Err(e) => {
+                        eprintln!("ahda: can't number the queries of {} lanes: {}", n_reads.len(), e);
+                        return Err(e)
+                    },
+                }
+                queries = if *rename_duplicates {
+                    Some(Box::new(ahda::parser::query_ids::RenameDuplicates::new(names.into_iter())))
+                } else {
+                    Some(Box::new(names.into_iter()))
+                };
+            }
+
+            // Second mates are numbered after the first mates. Both mates
+            // often have the same name, so the names get a /1 or /2 suffix
+            // to tell them apart.
+            let mut second_mates = None;
+            if let Some(mate_file) = mate_file {
+                let mates = match FastxNameReader::new(mate_file).and_then(|mut reader| reader.try_iter().collect::<Result<Vec<Vec<u8>>, E>>()) {
+                    Ok(names) => names,
+                    Err(e) => {
+                        eprintln!("ahda: can't read input file `{}`: {}", mate_file.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                };
+                let add_suffix = |mut name: Vec<u8>, suffix: &[u8]| { if !name.ends_with(suffix) { name.extend_from_slice(suffix) }; name };
+                let first: Vec<Vec<u8>> = queries.take().unwrap().map(|name| add_suffix(name, b"/1")).collect();
+                if first.len() != mates.len() {
+                    log::warn!("{} first mates but {} second mates in `{}`, the reads may not be paired", first.len(), mates.len(), mate_file.to_string_lossy());
+                }
+                let mut ids = roaring::RoaringBitmap::new();
+                ids.insert_range((first.len() as u32)..);
+                second_mates = Some(ids);
+                queries = Some(Box::new(first.into_iter().chain(mates.into_iter().map(move |name| add_suffix(name, b"/2")))));
+            }
+
             let mut inputs: Vec<Box<dyn Read>> = Vec::new();
             let mut outputs: Vec<Box<dyn Write>> = Vec::new();
             let mut force_stdout: bool = false;
             if let Some(input_file) = input_file {
                 match File::open(input_file) {
+                    Ok(conn_in) if *follow => {
+                        // Ctrl-C ends the input, a second Ctrl-C aborts
+                        let stop = Arc::new(AtomicBool::new(false));
+                        let handler_stop = stop.clone();
+                        let res = ctrlc::set_handler(move || {
+                            if handler_stop.swap(true, Ordering::Relaxed) {
+                                std::process::exit(130);
+                            }
+                        });
+                        if let Err(e) = res {
+                            eprintln!("ahda: can't install Ctrl-C handler: {}", e);
+                            return Err(Box::new(e))
+                        }
+                        if *verbose > 0 {
+                            note!("ahda: following `{}`, press Ctrl-C to finish", input_file.to_string_lossy());
+                        }
+                        inputs.push(Box::new(FollowReader::new(conn_in, stop)));
+                    },
                     Ok(conn_in) => inputs.push(Box::new(conn_in)),
                     Err(e) => {
                         eprintln!("ahda: can't open input file `{}`: {}", input_file.to_string_lossy(), e);
@@ -124,10 +779,16 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
 
                 let out_path = PathBuf::from(input_file.to_string_lossy().to_string() + ".ahda");
 
-                if !*stdout {
+                if is_piped(input_file) {
+                    if !*stdout && !*force && std::io::stdout().is_terminal() {
+                        eprintln!("ahda: refusing to write binary data to terminal, use `--force` to override");
+                        return Ok(());
+                    }
+                    force_stdout = true;
+                } else if !*stdout {
                     match if *force { File::create(out_path.clone()) } else { File::create_new(out_path.clone()) } {
                         Ok(conn_out) => {
-                            outputs.push(Box::new(conn_out));
+                            outputs.push(buffered_output(conn_out, cli.buffer_size as usize));
                         },
                         Err(e) => {
                             eprintln!("ahda: can't create output file `{}`: {}", out_path.to_string_lossy(), e);
@@ -146,13 +807,74 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
             }
 
             if *stdout || force_stdout {
-                outputs.push(Box::new(std::io::stdout()));
+                outputs.push(buffered_output(std::io::stdout(), cli.buffer_size as usize));
+            }
+
+            // Copy of the plain text input, written as it is read
+            let mut tee_out = None;
+            if let Some(tee_file) = tee {
+                match if *force { File::create(tee_file) } else { File::create_new(tee_file) } {
+                    Ok(f) => {
+                        let conn_tee = flate2::write::GzEncoder::new(buffered_output(f, cli.buffer_size as usize), flate2::Compression::new(*level));
+                        let (reader, copy) = TeeReader::new(inputs.pop().unwrap(), conn_tee);
+                        inputs.push(Box::new(reader));
+                        tee_out = Some(copy);
+                    },
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", tee_file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
             }
 
             let mut opts = EncodeOpts::default();
+            if let Some(map_file) = query_id_map {
+                let res = match File::open(map_file) {
+                    Ok(mut f) => ahda::parser::query_ids::read_query_id_map(&mut f),
+                    Err(e) => Err(Box::new(e) as E),
+                };
+                match res {
+                    Ok(res) => opts.query_ids = res,
+                    Err(e) => {
+                        eprintln!("ahda: can't read query ids from `{}`: {}", map_file.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            } else if let Some(policy) = query_ids {
+                opts.query_ids = policy.clone();
+            }
             opts.format = input_format.clone();
-            opts.encode_query_names = query_file.is_some() && !*rename;
+            opts.encode_query_names = (query_file.is_some() || opts.query_ids != ahda::parser::query_ids::QueryIdPolicy::FromList) && !*rename;
             opts.rename_queries = *rename;
+            opts.target_groups = groups;
+            opts.target_aliases = aliases;
+            opts.store_target_aliases = *store_aliases;
+            opts.second_mates = second_mates;
+            opts.compression_level = *level;
+            if *no_inline {
+                opts.inline_max_records = 0;
+            }
+            opts.query_filter_fpr = *query_filter;
+            opts.target_summary = *target_summary;
+            opts.zstd_level = *zstd_seekable;
+            if *flags_dictionary {
+                opts.metadata_compression = ahda::compression::MetadataCompression::ZstdDictionary;
+            }
+            opts.max_hits = max_hits.map(|max_hits| ahda::max_hits::MaxHits{ max_hits, action: *max_hits_action });
+            opts.lanes = sample_lanes;
+            opts.allow_extra = *allow_extra;
+            opts.coverage_bucket = *coverage_bucket;
+            opts.store_panel = true;
+            opts.index_version = index_version.as_ref().map(|x| x.as_bytes().to_vec());
+            if *sort_input {
+                let defaults = ahda::encoder::sorted::SortOpts::default();
+                opts.sort_input = Some(ahda::encoder::sorted::SortOpts{
+                    max_memory: sort_memory.saturating_mul(1 << 20),
+                    tmp_dir: tmp_dir.clone().unwrap_or(defaults.tmp_dir),
+                });
+            }
+            opts.bitmap_type = bitmap.clone();
+            opts.target_chunks = *target_chunks;
             opts.accession = if let Some(name) = sample_name {
                 name.as_bytes().to_vec()
             } else {
@@ -178,8 +900,21 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
                 return Err(e)
             }
 
-            if !*keep && !*stdout && input_file.is_some() {
-                match std::fs::remove_file(input_file.as_ref().unwrap()) {
+            if let Some(copy) = tee_out {
+                // The parser may stop before the end of the input, eg. at trailing empty lines
+                let res = std::io::copy(conn_in, &mut std::io::sink()).and_then(|_| {
+                    let mut copy = copy.lock().unwrap();
+                    copy.try_finish()?;
+                    copy.get_mut().flush()
+                });
+                if let Err(e) = res {
+                    eprintln!("ahda: can't write output file `{}`: {}", tee.as_ref().unwrap().to_string_lossy(), e);
+                    return Err(Box::new(e))
+                }
+            }
+
+            if !*keep && !*stdout && !*follow && !force_stdout && input_file.is_some() {
+                match remove_input(input_file.as_ref().unwrap()) {
                     Ok(()) => (),
                     Err(e) => {
                         eprintln!("ahda: can't remove input file `{}`: {}", input_file.as_ref().unwrap().to_string_lossy(), e);
@@ -195,55 +930,177 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
         Some(cli::Commands::Decode {
             input_file,
             format,
+            collapse,
+            sorted_by_rank,
+            split_by_group,
+            strict,
+            max_hits,
+            max_hits_action,
+            skip_corrupt,
             stdout,
             force,
             keep,
             verbose,
         }) => {
-            init_log(if *verbose { 2 } else { 1 });
+            init_log(1 + *verbose as usize);
 
-            let mut conn_in: Vec<Box<dyn Read>> = Vec::new();
-            let mut conn_out: Vec<Box<dyn Write>> = Vec::new();
-            if let Some(file) = input_file {
-                if *stdout {
-                    conn_out.push(Box::new(std::io::stdout()));
-                } else {
-                    let out_name = file.file_stem().unwrap().to_string_lossy();
-                    let out_path = PathBuf::from(out_name.to_string());
+            if input_file.is_none() && !*force  && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, use `--force` to ignore");
+                return Ok(());
+            }
 
-                    if !*stdout {
-                        match if *force { File::create(out_path.clone()) } else { File::create_new(out_path.clone()) } {
-                            Ok(out) => {
-                                conn_out.push(Box::new(out));
-                            },
-                            Err(e) => {
-                                eprintln!("ahda: can't create output file `{}`: {}", out_path.to_string_lossy(), e);
-                                return Err(Box::new(e))
-                            },
-                        }
-                    }
+            let (conn_in, is_ahda) = match open_input(input_file.as_ref()) {
+                Ok(input) => input,
+                Err(e) => {
+                    eprintln!("ahda: can't open input file `{}`: {}", input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string()), e);
+                    return Err(Box::new(e))
+                },
+            };
+
+            let (mut conn_in, fields_present) = match peek_input_fields(conn_in, is_ahda) {
+                Ok(input) => input,
+                Err(e) => {
+                    eprintln!("ahda: can't read input file `{}`: {}", input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string()), e);
+                    return Err(Box::new(e))
+                },
+            };
+            if let Err(e) = check_lost_fields(fields_present, &format.clone().unwrap_or_default(), *strict) {
+                eprintln!("ahda: can't decode input file `{}`: {}", input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string()), e);
+                return Err(e)
+            }
+
+            let piped = input_file.as_ref().is_some_and(|x| is_piped(x));
 
+            if let Some(groups_file) = split_by_group {
+                let in_name = input_file.as_ref().unwrap().to_string_lossy().to_string();
+                if !is_ahda {
+                    eprintln!("ahda: can't split input file `{}`: `--split-by-group` needs .ahda data", in_name);
+                    return Err(Box::new(std::io::Error::from(std::io::ErrorKind::InvalidInput)))
                 }
-                match File::open(file) {
-                    Ok(conn) => conn_in.push(Box::new(conn)),
+                let mut groups_table = match File::open(groups_file) {
+                    Ok(conn) => conn,
                     Err(e) => {
-                        eprintln!("ahda: can't open input file `{}`: {}", file.to_string_lossy(), e);
+                        eprintln!("ahda: can't open input file `{}`: {}", groups_file.to_string_lossy(), e);
                         return Err(Box::new(e))
                     },
+                };
+
+                // Outputs are named `<input stem>.<group>`
+                let out_name = input_file.as_ref().unwrap().file_stem().unwrap().to_string_lossy().to_string();
+                let mut conn_in = ahda::decoder::prefetch::PrefetchReader::new(conn_in, 4);
+                let pipeline = ahda::pipeline::Pipeline::new().output(ahda::pipeline::Output::Text(format.clone().unwrap_or_default()));
+                let ret = pipeline.run_split(&mut conn_in, &mut groups_table, |group| {
+                    let group = String::from_utf8_lossy(group).replace(std::path::MAIN_SEPARATOR, "_");
+                    let out_path = PathBuf::from(format!("{}.{}", out_name, group));
+                    match if *force { File::create(out_path.clone()) } else { File::create_new(out_path.clone()) } {
+                        Ok(out) => Ok(out),
+                        Err(e) => {
+                            eprintln!("ahda: can't create output file `{}`: {}", out_path.to_string_lossy(), e);
+                            Err(Box::new(e) as E)
+                        },
+                    }
+                });
+                match ret {
+                    Ok(counts) => {
+                        if *verbose > 0 {
+                            note!("ahda: wrote {} records to {} groups", counts.iter().sum::<usize>(), counts.len());
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("ahda: can't split input file `{}`: {}", in_name, e);
+                        return Err(e)
+                    },
                 }
-            } else {
-                if !*force  && std::io::stdin().is_terminal() {
-                    eprintln!("ahda: standard input is a terminal, use `--force` to ignore");
-                    return Ok(());
+
+                if !*keep && !piped {
+                    if let Err(e) = remove_input(input_file.as_ref().unwrap()) {
+                        eprintln!("ahda: can't remove input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    }
                 }
-                conn_in.push(Box::new(std::io::stdin()));
-                conn_out.push(Box::new(std::io::stdout()));
+                return Ok(())
+            }
+
+            let mut conn_out: Vec<Box<dyn Write>> = Vec::new();
+            if let Some(file) = input_file.as_ref().filter(|_| !piped) {
+                if *stdout {
+                    conn_out.push(buffered_output(std::io::stdout(), cli.buffer_size as usize));
+                } else {
+                    let out_path = match decoded_path(file) {
+                        Ok(out_path) => out_path,
+                        Err(e) => {
+                            eprintln!("ahda: can't create output file for `{}`: {}", file.to_string_lossy(), e);
+                            return Err(Box::new(e))
+                        },
+                    };
+                    match if *force { File::create(out_path.clone()) } else { File::create_new(out_path.clone()) } {
+                        Ok(out) => {
+                            conn_out.push(buffered_output(out, cli.buffer_size as usize));
+                        },
+                        Err(e) => {
+                            eprintln!("ahda: can't create output file `{}`: {}", out_path.to_string_lossy(), e);
+                            return Err(Box::new(e))
+                        },
+                    }
+                }
+            } else {
+                conn_out.push(buffered_output(std::io::stdout(), cli.buffer_size as usize));
             }
 
-            ahda::decode_from_read_to_write(format.clone().unwrap_or_default(), &mut conn_in[0], &mut conn_out[0]).unwrap();
+            let ret = if is_ahda {
+                // Read the next blocks while decoding
+                let mut conn_in = ahda::decoder::prefetch::PrefetchReader::new(conn_in, 4);
+                let check = if *strict { ahda::decoder::QueryIdCheck::Strict } else { ahda::decoder::QueryIdCheck::Extend };
+                let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+                let mut pipeline = ahda::pipeline::Pipeline::new().query_id_check(check).skip_corrupt(*skip_corrupt).output(ahda::pipeline::Output::Text(format.clone().unwrap_or_default()));
+                if let Some(level) = collapse {
+                    pipeline = pipeline.collapse(level.as_bytes());
+                }
+                if let Some(max_hits) = max_hits {
+                    pipeline = pipeline.max_hits(ahda::max_hits::MaxHits{ max_hits: *max_hits, action: *max_hits_action });
+                }
+                if *sorted_by_rank {
+                    pipeline = pipeline.sort_by_query_id();
+                }
+                pipeline.run_with_summary(&mut conn_in, &mut conn_out[0]).map(|summary| {
+                    if summary.records_capped > 0 {
+                        note!("ahda: capped {} queries in input file `{}` that aligned to more than {} targets ({})", summary.records_capped, in_name, max_hits.unwrap_or_default(), max_hits_action);
+                    }
+                    if !summary.corrupt.blocks.is_empty() {
+                        let blocks: Vec<String> = summary.corrupt.blocks.iter().map(|x| x.to_string()).collect();
+                        note!("ahda: skipped {} corrupt blocks with {} records in input file `{}`: blocks {}", summary.corrupt.blocks.len(), summary.corrupt.n_records, in_name, blocks.join(", "));
+                    }
+                    if let Some(n_queries) = summary.original_n_queries {
+                        note!("ahda: input file `{}` has query ids that don't fit in its {} queries, use `--strict` to fail instead", in_name, n_queries);
+                    }
+                })
+            } else if collapse.is_some() {
+                // Plain text input has no target groups
+                Err(Box::new(ahda::errors::UnknownTargetGroupErr{}) as E)
+            } else if *sorted_by_rank {
+                // Plain text input is converted as it is read
+                Err(Box::new(ahda::errors::UnsortablePlainTextErr{}) as E)
+            } else if max_hits.is_some() {
+                // Plain text input is converted as it is read
+                Err(Box::new(ahda::errors::PlainTextMaxHitsErr{}) as E)
+            } else {
+                // Plain text input, convert to the output format instead
+                if *verbose > 0 {
+                    note!("ahda: input is not .ahda data, converting from plain text");
+                }
+                let sample = input_file.as_ref().map(|x| x.file_stem().unwrap().to_string_lossy().as_bytes().to_vec()).unwrap_or_default();
+                ahda::convert_from_read_to_write(None::<&mut std::iter::Empty<Vec<u8>>>, None::<&mut std::iter::Empty<Vec<u8>>>, &sample, format.clone().unwrap_or_default(), &mut conn_in, &mut conn_out[0])
+            };
+            // The input is removed only after the output is written
+            if let Err(e) = ret.and_then(|_| Ok(conn_out[0].flush()?)) {
+                eprintln!("ahda: can't decode input file `{}`: {}", input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string()), e);
+                return Err(e)
+            }
+            drop(conn_out);
 
-            if !*keep && !*stdout && input_file.is_some() {
-                match std::fs::remove_file(input_file.as_ref().unwrap()) {
+            // Plain text inputs were converted, not decoded, so they are kept
+            if !*keep && !*stdout && !piped && is_ahda && input_file.is_some() {
+                match remove_input(input_file.as_ref().unwrap()) {
                     Ok(()) => (),
                     Err(e) => {
                         eprintln!("ahda: can't remove input file `{}`: {}", input_file.as_ref().unwrap().to_string_lossy(), e);
@@ -258,13 +1115,81 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
         // Cat
         Some(cli::Commands::Cat {
             input_files,
+            order_by,
+            merge_plan,
             output_file,
+            dedup,
+            tmp_dir,
+            manifest,
+            embed_manifest,
+            ignore_panel,
+            renumber_queries,
+            merge_targets,
+            dry_run,
             stdout,
             force,
             verbose,
         }) => {
-            init_log(if *verbose { 2 } else { 1 });
-            assert!(!input_files.is_empty());
+            init_log(1 + *verbose as usize);
+
+            let mut input_files = if let Some(file) = merge_plan {
+                match File::open(file).map_err(|e| e.into()).and_then(|mut conn| ahda::shard::MergePlan::from_read(&mut conn)) {
+                    Ok(plan) => plan.paths(),
+                    Err(e) => {
+                        eprintln!("ahda: can't read merge plan `{}`: {}", file.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            } else {
+                assert!(!input_files.is_empty());
+                match ahda::inputs::expand_inputs(input_files) {
+                    Ok(files) => files,
+                    Err(e) => {
+                        eprintln!("ahda: can't find input files: {}", e);
+                        return Err(e)
+                    },
+                }
+            };
+            match order_by {
+                ahda::inputs::OrderBy::Name => {
+                    input_files.sort_by(|a, b| ahda::inputs::natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+                },
+                ahda::inputs::OrderBy::StartIdx => {
+                    let mut start_ids: Vec<(Option<u32>, PathBuf)> = Vec::with_capacity(input_files.len());
+                    for file in input_files {
+                        match File::open(&file).map_err(|e| e.into()).and_then(|mut conn| ahda::inputs::start_query_id(&mut conn)) {
+                            Ok(start_id) => start_ids.push((start_id, file)),
+                            Err(e) => {
+                                eprintln!("ahda: can't read the first block of `{}`: {}", file.to_string_lossy(), e);
+                                return Err(e)
+                            },
+                        }
+                    }
+                    // Inputs without blocks go last
+                    start_ids.sort_by_key(|(start_id, _)| start_id.unwrap_or(u32::MAX));
+                    input_files = start_ids.into_iter().map(|(_, file)| file).collect();
+                },
+                _ => (),
+            }
+
+            if *dry_run {
+                let (inputs, problems) = plan_inputs(&input_files, !std::io::stdin().is_terminal());
+                let mut opts = ahda::CatOpts::default();
+                opts.check_panel = !*ignore_panel;
+                opts.renumber_queries = *renumber_queries;
+                opts.merge_targets = *merge_targets;
+                let mut plan = ahda::dry_run::plan_concatenate(inputs, &opts)?;
+                plan.problems.extend(problems);
+                if let Some(file) = output_file.as_ref().filter(|_| !*stdout) {
+                    plan.add_output(file, *force);
+                } else if !*force && std::io::stdout().is_terminal() {
+                    plan.problems.push("refusing to write binary data to terminal, use `--force` to override".to_string());
+                }
+                if let Some(file) = manifest {
+                    plan.add_output(file, *force);
+                }
+                return print_plan(&plan)
+            }
 
             let mut conn_in: Vec<Box<dyn Read>> = Vec::new();
 
@@ -273,7 +1198,7 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
                 conn_in.push(Box::new(std::io::stdin()));
             }
 
-            for file in input_files {
+            for file in input_files.iter() {
                 match File::open(file) {
                     Ok(conn) => conn_in.push(Box::new(conn)),
                     Err(e) => {
@@ -283,13 +1208,58 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
                 }
             }
 
-            let mut conn_out: Vec<Box<dyn Write>> = Vec::new();
-
-            if let Some(file) = output_file {
-                if !*stdout {
+            // The inputs are read twice when the manifest is needed
+            let manifest_inputs = if manifest.is_some() || *embed_manifest {
+                let mut entries: Vec<ahda::manifest::ManifestEntry> = Vec::with_capacity(conn_in.len());
+                let mut n_queries: Vec<u32> = Vec::with_capacity(conn_in.len());
+                if conn_in.len() > input_files.len() {
+                    // Stdin is kept in memory for the second pass
+                    let mut bytes: Vec<u8> = Vec::new();
+                    match conn_in[0].read_to_end(&mut bytes).map_err(|e| e.into()).and_then(|_| {
+                        n_queries.push(ahda::headers::file::read_file_header(&mut bytes.as_slice())?.n_queries);
+                        ahda::manifest::ManifestEntry::from_read(b"-", bytes.as_slice())
+                    }) {
+                        Ok(entry) => entries.push(entry),
+                        Err(e) => {
+                            eprintln!("ahda: can't read input from stdin: {}", e);
+                            return Err(e)
+                        },
+                    }
+                    conn_in[0] = Box::new(Cursor::new(bytes));
+                }
+                for file in input_files.iter() {
+                    match File::open(file).map_err(|e| e.into()).and_then(|mut conn| {
+                        n_queries.push(ahda::headers::file::read_file_header(&mut conn)?.n_queries);
+                        conn.rewind()?;
+                        ahda::manifest::ManifestEntry::from_read(file.to_string_lossy().as_bytes(), conn)
+                    }) {
+                        Ok(entry) => entries.push(entry),
+                        Err(e) => {
+                            eprintln!("ahda: can't read input file `{}`: {}", file.to_string_lossy(), e);
+                            return Err(e)
+                        },
+                    }
+                }
+                let mut manifest = ahda::manifest::Manifest{ entries };
+                if *renumber_queries {
+                    // The manifest describes the query ids in the output
+                    if let Err(e) = manifest.renumber_queries(&n_queries) {
+                        eprintln!("ahda: can't renumber the queries in the manifest: {}", e);
+                        return Err(e)
+                    }
+                }
+                Some(manifest)
+            } else {
+                None
+            };
+
+            let mut conn_out: Vec<Box<dyn Write>> = Vec::new();
+
+            if let Some(file) = output_file {
+                if !*stdout {
                     match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
                         Ok(out) => {
-                            conn_out.push(Box::new(out));
+                            conn_out.push(buffered_output(out, cli.buffer_size as usize));
                         },
                         Err(e) => {
                             eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
@@ -300,14 +1270,61 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
             }
 
             if (*stdout || (output_file.is_none())) && *force {
-                conn_out.push(Box::new(std::io::stdout()));
+                conn_out.push(buffered_output(std::io::stdout(), cli.buffer_size as usize));
             } else if !*force && std::io::stdout().is_terminal() {
                 eprintln!("ahda: refusing to write binary data to terminal, use `--force` to override");
                 return Ok(());
             }
 
-            match ahda::concatenate_from_read_to_write(&mut conn_in, &mut conn_out[0]) {
-                Ok(_) => Ok(()),
+            let mut opts = ahda::CatOpts::default();
+            opts.check_panel = !*ignore_panel;
+            opts.renumber_queries = *renumber_queries;
+            opts.merge_targets = *merge_targets;
+            if *embed_manifest {
+                opts.manifest = manifest_inputs.clone();
+            }
+            let ret = if *dedup {
+                // Deduplication needs two passes over the concatenated data, which is kept in a temporary file
+                let tmp_path = tmp_dir.clone().unwrap_or(std::env::temp_dir()).join(format!("ahda-cat-{}.tmp", std::process::id()));
+                match File::options().read(true).write(true).create_new(true).open(&tmp_path) {
+                    Ok(file) => {
+                        let mut concatenated = std::io::BufWriter::new(file);
+                        let ret = ahda::try_concatenate_from_read_to_write_with_opts(&mut conn_in, &mut concatenated, &opts).and_then(|_| {
+                            let mut concatenated = std::io::BufReader::new(concatenated.into_inner()?);
+                            concatenated.rewind()?;
+                            let n_refs = ahda::compression::dedup::deduplicate_blocks(&mut concatenated, &mut conn_out[0])?;
+                            if *verbose > 0 {
+                                note!("ahda: replaced {} duplicated blocks with references", n_refs);
+                            }
+                            Ok(())
+                        });
+                        if let Err(e) = std::fs::remove_file(&tmp_path) {
+                            log::warn!("can't remove temporary file `{}`: {}", tmp_path.to_string_lossy(), e);
+                        }
+                        ret
+                    },
+                    Err(e) => {
+                        eprintln!("ahda: can't create temporary file `{}`: {}", tmp_path.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                ahda::try_concatenate_from_read_to_write_with_opts(&mut conn_in, &mut conn_out[0], &opts)
+            };
+
+            match ret {
+                Ok(_) => {
+                    if let (Some(file), Some(manifest_inputs)) = (manifest, manifest_inputs) {
+                        let ret = if *force { File::create(file) } else { File::create_new(file) }.map_err(|e| e.into()).and_then(|mut conn| {
+                            manifest_inputs.write_tsv(&mut conn)
+                        });
+                        if let Err(e) = ret {
+                            eprintln!("ahda: can't write manifest to `{}`: {}", file.to_string_lossy(), e);
+                            return Err(e)
+                        }
+                    }
+                    Ok(())
+                },
                 Err(e) => {
                     let mut msg =  input_files.iter().map(|x| x.to_string_lossy() + " ").collect::<String>();
                     msg.remove(msg.len() - 1);
@@ -319,18 +1336,30 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
 
         // Convert
         Some(cli::Commands::Convert {
-            input_file,
+            input_files,
             query_file,
+            query_cache,
+            query_cache_checksum,
+            rename_duplicates,
             target_list,
             output_file,
+            output_template,
             format,
             sample_name,
+            strict,
+            keep_targets,
+            rename_map,
+            no_program_record,
+            round_trip_check,
+            chunk_size,
+            threads,
+            dry_run,
             stdout,
             force,
             keep,
             verbose,
         }) => {
-            init_log(if *verbose { 2 } else { 1 });
+            init_log(1 + *verbose as usize);
 
             let mut targets = None;
             if let Some(target_list) = target_list {
@@ -346,8 +1375,8 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
                 }
             }
 
-            let queries: Option<FastxNameReader> = if let Some(query_file) = query_file {
-                match FastxNameReader::new(query_file) {
+            let queries: Option<Box<dyn Iterator<Item = Vec<u8>>>> = if let Some(query_file) = query_file {
+                match read_query_names(query_file, query_cache.as_ref(), *query_cache_checksum, *rename_duplicates, *verbose > 0) {
                     Ok(reader) => Some(reader),
                     Err(e) => {
                         eprintln!("ahda: can't open input file `{}`: {}", query_file.to_string_lossy(), e);
@@ -358,70 +1387,134 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
                 None
             };
 
-            let mut inputs: Vec<Box<dyn Read>> = Vec::new();
-            let mut outputs: Vec<Box<dyn Write>> = Vec::new();
-            if let Some(input_file) = input_file {
-                match File::open(input_file) {
-                    Ok(conn_in) => inputs.push(Box::new(conn_in)),
+            let mut selection = ahda::printer::TargetSelection::default();
+            if let Some(keep_targets) = keep_targets {
+                match std::fs::read(keep_targets) {
+                    Ok(bytes) => {
+                        selection.keep = Some(bytes.split(|x| *x == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line)).filter(|line| !line.is_empty()).map(|line| line.to_vec()).collect());
+                    },
                     Err(e) => {
-                        eprintln!("ahda: can't open input file `{}`: {}", input_file.to_string_lossy(), e);
+                        eprintln!("ahda: can't open input file `{}`: {}", keep_targets.to_string_lossy(), e);
                         return Err(Box::new(e))
                     },
                 }
+            }
+            if let Some(rename_map) = rename_map {
+                match File::open(rename_map).map_err(|e| Box::new(e) as E).and_then(|mut conn| ahda::read_target_renames(&mut conn)) {
+                    Ok(renames) => selection.rename = renames,
+                    Err(e) => {
+                        eprintln!("ahda: can't read target rename map `{}`: {}", rename_map.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            }
+
+            if input_files.is_empty() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
 
-                let out_path = if let Some(out) = output_file { out.clone() } else { PathBuf::from(input_file.to_string_lossy().to_string() + ".tmp") };
+            if input_files.len() > 1 && output_file.is_some() {
+                eprintln!("ahda: `--output` takes a single input, use `--output-template` with several inputs");
+                return Err(Box::new(std::io::Error::from(std::io::ErrorKind::InvalidInput)))
+            }
 
-                if !*stdout {
-                    match if *force { File::create(out_path.clone()) } else { File::create_new(out_path.clone()) } {
-                        Ok(conn_out) => {
-                            outputs.push(Box::new(conn_out));
-                        },
-                        Err(e) => {
-                            eprintln!("ahda: can't create output file `{}`: {}", out_path.to_string_lossy(), e);
-                            return Err(Box::new(e))
+            if *dry_run {
+                let (inputs, problems) = plan_inputs(input_files, input_files.is_empty());
+                let mut plan = ahda::dry_run::Plan{ inputs, problems, ..Default::default() };
+                let out_format = format.clone().unwrap_or_default();
+                for input in plan.inputs.clone().iter() {
+                    let name = String::from_utf8_lossy(&input.path).to_string();
+                    let is_ahda = input.headers.is_some();
+                    let fields_present = match (&input.headers, &input.format) {
+                        (Some((header, _)), _) => Some(header.fields_present),
+                        (None, Some(_)) if query_file.is_some() => Some(ahda::Format::AhdaTSV.fields_present()),
+                        (None, Some(x)) => x.parse::<ahda::Format>().ok().map(|x| x.fields_present()),
+                        (None, None) => {
+                            plan.problems.push(format!("can't recognize the format of input `{}`", name));
+                            None
                         },
+                    };
+                    let lost = fields_present.map(|x| out_format.lost_fields(x)).unwrap_or_default();
+                    if *strict && !lost.is_empty() {
+                        plan.problems.push(format!("output format `{}` can't store the {} in input `{}`", out_format, lost.join(", "), name));
+                    }
+                    if !is_ahda && sample_name.is_none() && query_file.is_none() {
+                        plan.problems.push(format!("input `{}` needs `--name` to supply the sample name", name));
                     }
                 }
-            } else {
-                if std::io::stdin().is_terminal() {
-                    eprintln!("ahda: standard input is a terminal, ignoring");
-                    return Ok(());
+                if let Some(file) = output_file.as_ref().filter(|_| !*stdout) {
+                    plan.add_output(file, *force);
+                } else if !*stdout {
+                    for input_file in input_files.iter() {
+                        let is_ahda = plan.inputs.iter().any(|x| x.path == input_file.to_string_lossy().as_bytes() && x.headers.is_some());
+                        if let Some(template) = output_template {
+                            plan.add_output(&output_from_template(template, input_file), *force);
+                        } else if is_ahda {
+                            match decoded_path(input_file) {
+                                Ok(out_path) => plan.add_output(&out_path, *force),
+                                Err(e) => plan.problems.push(format!("can't decode input `{}`: {}", input_file.to_string_lossy(), e)),
+                            }
+                        } else if !is_piped(input_file) {
+                            // Converted in place through a temporary file
+                            plan.add_output(&PathBuf::from(input_file.to_string_lossy().to_string() + ".tmp"), *force);
+                        }
+                    }
                 }
-                inputs.push(Box::new(std::io::stdin()));
+                return print_plan(&plan)
             }
 
-            if *stdout || (input_file.is_none() && output_file.is_none()) {
-                outputs.push(Box::new(std::io::stdout()));
+            let opts = ConvertOpts{
+                format: format.clone().unwrap_or_default(),
+                sample_name: if let Some(name) = sample_name { Some(name.as_bytes().to_vec()) } else { query_file.as_ref().map(|x| x.to_string_lossy().as_bytes().to_vec()) },
+                strict: *strict,
+                stdout: *stdout,
+                force: *force,
+                keep: *keep,
+                selection,
+                program: if *no_program_record { None } else { Some(ahda::printer::Program::ahda(&std::env::args().collect::<Vec<String>>().join(" "))) },
+                round_trip_check: *round_trip_check,
+                chunk_size: *chunk_size,
+                buffer_size: cli.buffer_size as usize,
+                verbose: *verbose > 0,
+            };
+
+            if input_files.len() <= 1 {
+                let out_path = output_file.clone().or(output_template.as_ref().zip(input_files.first()).map(|(template, input)| output_from_template(template, input)));
+                return convert_input(input_files.first(), out_path.as_ref(), targets.as_deref(), queries, &opts)
             }
 
-            let conn_in = &mut inputs[0];
-            let conn_out = &mut outputs[0];
-            #[allow(clippy::manual_map)]
-            let t_it = if let Some(t) = targets { Some(&mut t.into_iter()) } else { None };
-            let ret = if let Some(mut q_it) = queries {
-                let sample = if let Some(name) = sample_name { name.as_bytes().to_vec() } else { query_file.as_ref().unwrap().to_string_lossy().as_bytes().to_vec() };
-                ahda::convert_from_read_to_write(t_it, Some(&mut q_it), &sample, format.as_ref().unwrap().clone(), conn_in, conn_out)
-            } else {
-                let sample = if let Some(name) = sample_name { name.as_bytes().to_vec() } else {
-                    eprintln!("ahda: use `--name` to supply the sample name");
-                    return Ok(())
-                };
-                ahda::convert_from_read_to_write(t_it, None::<&mut std::iter::Empty<Vec<u8>>>, &sample, format.as_ref().unwrap().clone(), conn_in, conn_out)
-            };
-            if let Err(e) = ret {
-                eprintln!("ahda: can't convert input file `{}`: {}", input_file.as_ref().unwrap().to_string_lossy(), e);
-                return Err(e)
+            // The query and target names are shared by all inputs, read them only once
+            let queries: Option<Vec<Vec<u8>>> = queries.map(|x| x.collect());
+
+            // Outputs written to stdout would be interleaved
+            let n_threads = if *stdout { 1 } else { threads.unwrap_or(std::thread::available_parallelism().map(|x| x.get()).unwrap_or(1)) };
+            let n_threads = n_threads.clamp(1, input_files.len());
+            if *verbose > 0 {
+                note!("ahda: converting {} inputs using {} threads", input_files.len(), n_threads);
             }
 
-            if !*keep && !*stdout && input_file.is_some() && output_file.is_none() {
-                let tmp_path = PathBuf::from(input_file.as_ref().unwrap().to_string_lossy().to_string() + ".tmp");
-                match std::fs::rename(tmp_path.clone(), input_file.as_ref().unwrap()) {
-                    Ok(()) => (),
-                    Err(e) => {
-                        eprintln!("ahda: can't rename output file `{}` to `{}`: {}", input_file.as_ref().unwrap().to_string_lossy(), tmp_path.to_string_lossy(), e);
-                        return Err(Box::new(e))
+            let next_input = std::sync::atomic::AtomicUsize::new(0);
+            let n_failed = std::thread::scope(|s| {
+                let workers: Vec<_> = (0..n_threads).map(|_| s.spawn(|| {
+                    let mut n_failed = 0;
+                    loop {
+                        let idx = next_input.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        let Some(input_file) = input_files.get(idx) else { return n_failed };
+                        let out_path = output_template.as_ref().map(|template| output_from_template(template, input_file));
+                        let q_it = queries.as_ref().map(|x| x.iter().cloned());
+                        // Errors are printed by convert_input
+                        if convert_input(Some(input_file), out_path.as_ref(), targets.as_deref(), q_it, &opts).is_err() {
+                            n_failed += 1;
+                        }
                     }
-                }
+                })).collect();
+                workers.into_iter().map(|x| x.join().unwrap()).sum::<usize>()
+            });
+
+            if n_failed > 0 {
+                eprintln!("ahda: can't convert {} of {} input files", n_failed, input_files.len());
+                return Err(Box::new(std::io::Error::other("conversion failed")))
             }
 
             Ok(())
@@ -432,23 +1525,60 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
             input_files,
             output_file,
             operation,
+            by_name,
+            format,
+            output_empty_rows,
+            ignore_panel,
+            threads,
+            dry_run,
             stdout,
             force,
             verbose,
         }) => {
-            init_log(if *verbose { 2 } else { 1 });
+            init_log(1 + *verbose as usize);
             assert!(!input_files.is_empty());
 
-            let mut conn_in: Vec<Box<dyn Read>> = Vec::new();
+            if *dry_run {
+                let (inputs, problems) = plan_inputs(input_files, !std::io::stdin().is_terminal());
+                let mut plan = ahda::dry_run::plan_set(inputs, operation.as_ref().unwrap(), !*ignore_panel)?;
+                plan.problems.extend(problems);
+                if format.is_some() {
+                    // Plain text output has no size estimate
+                    plan.estimated_bytes = None;
+                }
+                if let Some(file) = output_file.as_ref().filter(|_| !*stdout) {
+                    plan.add_output(file, *force);
+                }
+                return print_plan(&plan)
+            }
+
+            if *by_name && *verbose > 0 {
+                note!("ahda: matching queries by name, each input is read into memory");
+            }
+
+            let mut conn_in: Vec<Box<dyn Read + Send>> = Vec::new();
+            let mut in_names: Vec<String> = Vec::new();
+            let mut in_checksums: Vec<Checksum> = Vec::new();
+            let mut in_modified: Vec<Option<u64>> = Vec::new();
 
             // Read first file from stdin if data is being piped in
             if !std::io::stdin().is_terminal() {
-                conn_in.push(Box::new(std::io::stdin()));
+                let (conn, crc) = ChecksumReader::new(std::io::stdin());
+                conn_in.push(Box::new(conn));
+                in_names.push("-".to_string());
+                in_checksums.push(crc);
+                in_modified.push(None);
             }
 
             for file in input_files {
                 match File::open(file) {
-                    Ok(conn) => conn_in.push(Box::new(conn)),
+                    Ok(conn) => {
+                        in_modified.push(conn.metadata().and_then(|x| x.modified()).ok().map(unix_seconds));
+                        let (conn, crc) = ChecksumReader::new(conn);
+                        conn_in.push(Box::new(conn));
+                        in_names.push(file.to_string_lossy().to_string());
+                        in_checksums.push(crc);
+                    },
                     Err(e) => {
                         eprintln!("ahda: can't open input file `{}`: {}", file.to_string_lossy(), e);
                         return Err(Box::new(e))
@@ -456,13 +1586,40 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
                 }
             }
 
+            if !*ignore_panel {
+                // The panels are read before the output is created, so that
+                // a mismatch doesn't leave an empty output file behind
+                let mut panels: Vec<Option<ahda::panel::Panel>> = Vec::with_capacity(conn_in.len());
+                let mut peeked: Vec<Box<dyn Read + Send>> = Vec::with_capacity(conn_in.len());
+                for (idx, conn) in conn_in.into_iter().enumerate() {
+                    match ahda::panel::peek_panel(conn) {
+                        Ok((panel, conn)) => {
+                            panels.push(panel);
+                            peeked.push(Box::new(conn));
+                        },
+                        Err(e) => {
+                            eprintln!("ahda: can't read input file `{}`: {}", in_names[idx], e);
+                            return Err(e)
+                        },
+                    }
+                }
+                let incompatibilities = ahda::panel::check_panels(&panels);
+                if !incompatibilities.is_empty() {
+                    let e = ahda::errors::IncompatibleInputsErr{ incompatibilities };
+                    eprintln!("ahda: can't merge the input files: {}", e);
+                    eprintln!("ahda: use `--ignore-panel` to merge them anyway");
+                    return Err(Box::new(e))
+                }
+                conn_in = peeked;
+            }
+
             let mut conn_out: Vec<Box<dyn Write>> = Vec::new();
 
             if let Some(file) = output_file {
                 if !*stdout {
                     match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
                         Ok(out) => {
-                            conn_out.push(Box::new(out));
+                            conn_out.push(buffered_output(out, cli.buffer_size as usize));
                         },
                         Err(e) => {
                             eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
@@ -473,37 +1630,1603 @@ fn main() -> Result<(),  Box<dyn std::error::Error>> {
             }
 
             if *stdout || (output_file.is_none()) {
-                conn_out.push(Box::new(std::io::stdout()));
+                conn_out.push(buffered_output(std::io::stdout(), cli.buffer_size as usize));
             }
 
-            // Read first bitmap
-            let (mut bitmap_a, _, flags_a, block_flags) = ahda::decode_from_read_to_roaring(&mut conn_in[0])?;
+            let (bitmap_a, flags_a, block_flags) = if *by_name {
+                // Read first bitmap
+                let (mut bitmap_a, _, flags_a, mut block_flags) = ahda::decode_from_read_to_roaring(&mut conn_in[0])?;
 
-            // Read the remainning bitmaps and perform requested operation.
-            // Intersection requires reading the entire other bitmaps into memory.
-            // Other operations are performed block-wise.
-            for (idx, conn) in conn_in.iter_mut().skip(1).enumerate() {
-                match ahda::decode_from_read_into_roaring(conn, operation.as_ref().unwrap(), &mut bitmap_a) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        let file = input_files[idx].clone();
-                        eprintln!("ahda: could not decode bitmap from input file `{}`: {}", file.to_string_lossy(), e);
+                // Matching by name reads the inputs one at a time
+                for (idx, conn) in conn_in.iter_mut().enumerate().skip(1) {
+                    if let Err(e) = ahda::decode_from_read_into_roaring_by_name(conn, operation.as_ref().unwrap(), &mut bitmap_a, &mut block_flags) {
+                        eprintln!("ahda: could not decode bitmap from input file `{}`: {}", in_names[idx], e);
                         return Err(e)
                     }
                 }
+                (bitmap_a, flags_a, block_flags)
+            } else {
+                // Decode the inputs in parallel and merge the results pairwise.
+                // Intersection requires reading the entire other bitmaps into memory.
+                // Other operations are performed block-wise.
+                let n_threads = threads.unwrap_or(std::thread::available_parallelism().map(|x| x.get()).unwrap_or(1));
+                if *verbose > 0 {
+                    note!("ahda: merging {} inputs using {} threads", conn_in.len(), n_threads);
+                }
+                match ahda::merge_from_reads_to_roaring(conn_in, operation.as_ref().unwrap(), n_threads) {
+                    Ok((bitmap_a, _, flags_a, block_flags)) => (bitmap_a, flags_a, block_flags),
+                    Err(e) => {
+                        let (file, msg) = match e.downcast_ref::<ahda::errors::MergeInputErr>() {
+                            Some(x) => (in_names[x.input].clone(), x.msg.clone()),
+                            None => ("-".to_string(), e.to_string()),
+                        };
+                        eprintln!("ahda: could not decode bitmap from input file `{}`: {}", file, msg);
+                        return Err(e)
+                    },
+                }
+            };
+
+            let mut bitmap_a = bitmap_a;
+            let mut queries = block_flags.queries.unwrap();
+            let mut extensions = flags_a.extensions.clone();
+            if *output_empty_rows != ahda::EmptyRows::Keep {
+                let n_dropped;
+                (bitmap_a, queries, n_dropped) = ahda::drop_empty_rows(&bitmap_a, flags_a.target_names.len(), &queries);
+                if *output_empty_rows == ahda::EmptyRows::Summary {
+                    note!("ahda: dropped {} queries that align to no targets", n_dropped);
+                    // Files that were already summarized keep a running total
+                    let mut fields = extensions.take().unwrap_or_default();
+                    let total = fields.get_as::<u64>(ahda::EMPTY_ROWS_DROPPED_KEY).transpose()?.unwrap_or(0) + n_dropped as u64;
+                    fields.insert_as(ahda::EMPTY_ROWS_DROPPED_KEY, &total)?;
+                    extensions = Some(fields);
+                } else if *verbose > 0 {
+                    note!("ahda: dropped {} queries that align to no targets", n_dropped);
+                }
             }
 
             let mut iter = bitmap_a.into_iter();
-            let mut encoder = ahda::encoder::bitmap_encoder::BitmapEncoder::new(&mut iter, &flags_a.target_names, &block_flags.queries.unwrap(), &flags_a.query_name);
+            let mut encoder = ahda::encoder::bitmap_encoder::BitmapEncoder::new(&mut iter, &flags_a.target_names, &queries, &flags_a.query_name);
             encoder.set_fields_present(3_u16);
-            conn_out[0].write_all(&encoder.encode_file_header_and_flags()?)?;
+            encoder.set_target_groups(flags_a.target_groups.clone())?;
+            if let Some(extensions) = extensions {
+                encoder.set_extensions(extensions)?;
+            }
+
+            let inputs = in_names.iter().zip(in_checksums.iter()).zip(in_modified.iter()).map(|((name, crc), modified)| {
+                let (len, crc) = &*crc.lock().unwrap();
+                ahda::headers::file::ProvenanceInput{ name: name.as_bytes().to_vec(), len: *len, crc32: crc.sum(), modified: *modified }
+            }).collect();
+            let operation = format!("set {}", format!("{:?}", operation.as_ref().unwrap()).to_lowercase());
+            encoder.set_provenance(ahda::headers::file::Provenance{ operation: operation.into_bytes(), inputs, created: unix_seconds(std::time::SystemTime::now()) })?;
+
+            // Plain text is decoded from the encoded result, so that both
+            // outputs have the same records
+            let mut bytes: Vec<u8> = Vec::new();
+            let conn_encoded: &mut dyn Write = if format.is_some() { &mut bytes } else { &mut conn_out[0] };
+            conn_encoded.write_all(&encoder.encode_file_header_and_flags()?)?;
             for block in encoder {
-                conn_out[0].write_all(&block?)?;
+                conn_encoded.write_all(&block?)?;
+            }
+            if let Some(format) = format {
+                if let Err(e) = ahda::decode_from_read_to_write(format.clone(), &mut Cursor::new(bytes), &mut conn_out[0]) {
+                    eprintln!("ahda: can't write plain text output: {}", e);
+                    return Err(e)
+                }
             }
             conn_out[0].flush()?;
             Ok(())
 
         },
+
+        // Compare
+        Some(cli::Commands::Compare {
+            input_a,
+            input_b,
+            output_file,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            let mut conn_in: Vec<Box<dyn Read>> = Vec::new();
+            for file in [input_a, input_b] {
+                match File::open(file) {
+                    Ok(conn) => conn_in.push(Box::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            }
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let (conn_a, conn_b) = conn_in.split_at_mut(1);
+            let res = match ahda::compare_from_read(&mut conn_a[0], &mut conn_b[0]) {
+                Ok(res) => res,
+                Err(e) => {
+                    eprintln!("ahda: can't compare input files `{}` and `{}`: {}", input_a.to_string_lossy(), input_b.to_string_lossy(), e);
+                    return Err(e)
+                },
+            };
+
+            let jaccard = |a: u64, b: u64, shared: u64| -> f64 {
+                if a + b - shared > 0 { shared as f64 / (a + b - shared) as f64 } else { 1.0 }
+            };
+
+            conn_out.write_all(b"target\tn_a\tn_b\tn_shared\tjaccard\n")?;
+            conn_out.write_all(format!("*\t{}\t{}\t{}\t{}\n", res.n_a, res.n_b, res.n_shared, res.jaccard).as_bytes())?;
+            for (idx, name) in res.target_names.iter().enumerate() {
+                let (a, b, shared) = (res.target_a[idx], res.target_b[idx], res.target_shared[idx]);
+                conn_out.write_all(name)?;
+                conn_out.write_all(format!("\t{}\t{}\t{}\t{}\n", a, b, shared, jaccard(a, b, shared)).as_bytes())?;
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Check consistency
+        Some(cli::Commands::CheckConsistency {
+            input_a,
+            input_b,
+            max_mismatches,
+            output_file,
+            output_format,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            let mut query_sets: Vec<ahda::consistency::QuerySet> = Vec::with_capacity(2);
+            for file in [input_a, input_b] {
+                let res = File::open(file).map_err(|e| Box::new(e) as E).and_then(|conn| ahda::consistency::QuerySet::from_read(&mut BufReader::new(conn)));
+                match res {
+                    Ok(query_set) => query_sets.push(query_set),
+                    Err(e) => {
+                        eprintln!("ahda: can't read input file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            }
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            // Colors and aligned columns are only for a terminal
+            let is_terminal = output_file.is_none() && std::io::stdout().is_terminal();
+            let format = output_format.resolve(is_terminal);
+            let color = format == ReportFormat::Pretty && ahda::report::use_color(is_terminal);
+
+            let consistency = ahda::consistency::check_consistency(&query_sets[0], &query_sets[1]);
+            if !consistency.names_checked {
+                note!("ahda: the inputs don't both store the query names, only the number of queries was compared");
+            }
+            let mut report = Report::default();
+            report.field("consistency", Value::text(consistency.consistency));
+            report.row("n_queries", vec![Value::number(query_sets[0].n_queries), Value::number(query_sets[1].n_queries)]);
+            report.field("n_shared", Value::number(consistency.n_shared));
+            report.field("n_mismatches", Value::number(consistency.mismatches.len()));
+            let n_listed = if *max_mismatches == 0 { consistency.mismatches.len() } else { *max_mismatches };
+            for mismatch in consistency.mismatches.iter().take(n_listed) {
+                let (kind, values) = mismatch.report_row();
+                report.row(kind, values);
+            }
+            report.write(&mut conn_out, format, color)?;
+            conn_out.flush()?;
+
+            if consistency.consistency == ahda::consistency::Consistency::Inconsistent {
+                let e = ahda::errors::InconsistentQueriesErr{ n_mismatches: consistency.mismatches.len() };
+                eprintln!("ahda: input files `{}` and `{}` are not consistent: {}", input_a.to_string_lossy(), input_b.to_string_lossy(), e);
+                return Err(Box::new(e))
+            }
+
+            Ok(())
+        },
+
+        // Digest
+        Some(cli::Commands::Digest {
+            input_files,
+            output_file,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_files.is_empty() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let inputs: Vec<Option<&PathBuf>> = if input_files.is_empty() { vec![None] } else { input_files.iter().map(Some).collect() };
+            for input_file in inputs {
+                let in_name = input_file.map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+                let mut conn_in: Box<dyn Read> = match input_file {
+                    Some(file) => match File::open(file) {
+                        Ok(conn) => Box::new(BufReader::new(conn)),
+                        Err(e) => {
+                            eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                            return Err(Box::new(e))
+                        },
+                    },
+                    None => Box::new(std::io::stdin()),
+                };
+
+                let digest = match ahda::digest_from_read(&mut conn_in) {
+                    Ok(digest) => digest,
+                    Err(e) => {
+                        eprintln!("ahda: can't compute digest of input file `{}`: {}", in_name, e);
+                        return Err(e)
+                    },
+                };
+                let hex: String = digest.iter().map(|x| format!("{:02x}", x)).collect();
+                writeln!(conn_out, "{}  {}", hex, in_name)?;
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Doctor
+        Some(cli::Commands::Doctor {
+            input_file,
+            query_file,
+            target_list,
+            index_dir,
+            input_format,
+            output_file,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(conn),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let queries: Option<Vec<Vec<u8>>> = match query_file {
+                Some(query_file) => match FastxNameReader::new(query_file).and_then(|mut reader| reader.try_iter().collect::<Result<Vec<Vec<u8>>, E>>()) {
+                    Ok(names) => Some(names),
+                    Err(e) => {
+                        eprintln!("ahda: can't read input file `{}`: {}", query_file.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                },
+                None => None,
+            };
+
+            let mut targets: Option<Vec<Vec<u8>>> = None;
+            if let Some(target_list) = target_list {
+                match File::open(target_list) {
+                    Ok(f) => {
+                        let reader = BufReader::new(f);
+                        targets = Some(reader.split(b'\n').map(|x| x.unwrap()).collect::<Vec<Vec<u8>>>());
+                    },
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", target_list.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            }
+            if let Some(index_dir) = index_dir {
+                match ahda::index::read_index_targets(index_dir) {
+                    Ok((_, names)) => targets = Some(names),
+                    Err(e) => {
+                        eprintln!("ahda: can't read targets from index directory `{}`: {}", index_dir.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            }
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let report = match ahda::doctor::check(&mut conn_in, queries.as_deref(), targets.as_deref(), input_format.clone()) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("ahda: can't read input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+
+            let or_dash = |x: Option<String>| x.unwrap_or("-".to_string());
+            writeln!(conn_out, "format\t{}", or_dash(report.format.as_ref().map(|x| x.to_string())))?;
+            writeln!(conn_out, "records\t{}", report.n_records)?;
+            writeln!(conn_out, "queries\t{}", or_dash(report.n_queries.map(|x| x.to_string())))?;
+            writeln!(conn_out, "targets\t{}", or_dash(report.n_targets.map(|x| x.to_string())))?;
+            for issue in report.issues.iter() {
+                writeln!(conn_out, "issue\t{}\tfix: {}", issue, issue.suggestion())?;
+            }
+            conn_out.flush()?;
+
+            if !report.issues.is_empty() {
+                eprintln!("ahda: found {} issues in input file `{}`", report.issues.len(), in_name);
+                std::process::exit(EXIT_INVALID_INPUT as i32);
+            }
+
+            Ok(())
+        },
+
+        // BenchFile
+        Some(cli::Commands::BenchFile {
+            input_file,
+            query_file,
+            target_list,
+            input_format,
+            level,
+            output_file,
+            output_format,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            let in_name = input_file.to_string_lossy().to_string();
+            let input = match std::fs::read(input_file) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    eprintln!("ahda: can't read input file `{}`: {}", in_name, e);
+                    return Err(Box::new(e))
+                },
+            };
+
+            let queries: Option<Vec<Vec<u8>>> = match query_file {
+                Some(query_file) => match FastxNameReader::new(query_file).and_then(|mut reader| reader.try_iter().collect::<Result<Vec<Vec<u8>>, E>>()) {
+                    Ok(names) => Some(names),
+                    Err(e) => {
+                        eprintln!("ahda: can't read input file `{}`: {}", query_file.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                },
+                None => None,
+            };
+
+            let targets: Option<Vec<Vec<u8>>> = match target_list {
+                Some(target_list) => match File::open(target_list) {
+                    Ok(f) => Some(BufReader::new(f).split(b'\n').map(|x| x.unwrap()).collect::<Vec<Vec<u8>>>()),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", target_list.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => None,
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            // Colors and aligned columns are only for a terminal
+            let is_terminal = output_file.is_none() && std::io::stdout().is_terminal();
+            let format = output_format.resolve(is_terminal);
+            let color = format == ReportFormat::Pretty && ahda::report::use_color(is_terminal);
+
+            let mut opts = ahda::EncodeOpts::default();
+            opts.format = input_format.clone();
+            opts.encode_query_names = query_file.is_some();
+            opts.compression_level = *level;
+            opts.accession = in_name.as_bytes().to_vec();
+
+            let bench = match ahda::bench::bench_file(&input, targets.as_deref(), queries.as_deref(), opts) {
+                Ok(bench) => bench,
+                Err(e) => {
+                    eprintln!("ahda: can't benchmark input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+            bench.report().write(&mut conn_out, format, color)?;
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Stats
+        Some(cli::Commands::Stats {
+            input_file,
+            output_file,
+            output_format,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            // Colors and aligned columns are only for a terminal
+            let is_terminal = output_file.is_none() && std::io::stdout().is_terminal();
+            let format = output_format.resolve(is_terminal);
+            let color = format == ReportFormat::Pretty && ahda::report::use_color(is_terminal);
+
+            let (header, flags) = match ahda::headers::file::read_file_header_and_flags_any_layout(&mut conn_in) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("ahda: can't read header from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+            let target_chunks = ahda::target_chunks::TargetChunks::from_header_and_flags(&header, &flags)?;
+
+            let bitmap_type = ahda::compression::BitmapType::from_u16(header.bitmap_type)?;
+            // Bitmaps that don't match the automatic choice were forced with `ahda encode --bitmap`,
+            // which is made for the targets of one sub-block if the blocks are split
+            let auto_targets = target_chunks.map_or(header.n_targets, |x| x.width()) as usize;
+            let bitmap_auto = ahda::compression::BitmapType::auto(auto_targets, header.n_queries as usize);
+            let mut report = Report::default();
+            report.field("sample", Value::text(String::from_utf8_lossy(&flags.query_name)));
+            report.field("targets", Value::number(header.n_targets));
+            let targets_digest: String = ahda::index::target_list_digest(&flags.target_names).iter().map(|x| format!("{:02x}", x)).collect();
+            report.field("targets_sha256", Value::text(targets_digest));
+            report.field("queries", Value::number(header.n_queries));
+            report.field("bitmap", Value::text(bitmap_type));
+            report.field("bitmap_auto", Value::text(&bitmap_auto));
+            report.field("block_size", Value::number(header.block_size));
+            report.field("block_size_auto", Value::number(ahda::headers::file::default_block_size(&bitmap_auto, auto_targets)));
+            report.field("target_chunks", target_chunks.map_or(Value::Missing, |x| Value::number(x.width())));
+            if let Some(warning) = ahda::compression::check_shape(&header) {
+                report.row("warning", vec![Value::text(&warning), Value::text(warning.suggestion())]);
+            }
+            // Blocks are compressed inside a zstd-seekable container
+            let is_container = header.fields_present & ahda::compression::FILE_IS_ZSTD_SEEKABLE != 0;
+            let n_ones = if is_container { None } else {
+                match ahda::count_ones_from_read(&mut conn_in) {
+                    Ok(n_ones) => n_ones,
+                    Err(e) => {
+                        eprintln!("ahda: can't read blocks from input file `{}`: {}", in_name, e);
+                        return Err(e)
+                    },
+                }
+            };
+            match n_ones {
+                Some(n_ones) => {
+                    let n_bits = header.n_queries as f64 * header.n_targets as f64;
+                    report.field("ones", Value::number(n_ones));
+                    report.field("density", Value::number(format!("{:.6}", if n_bits > 0.0 { n_ones as f64 / n_bits } else { 0.0 })));
+                },
+                None => {
+                    report.field("ones", Value::Missing);
+                    report.field("density", Value::Missing);
+                },
+            }
+            for groups in flags.target_groups.iter() {
+                report.row("target_groups", vec![Value::text(String::from_utf8_lossy(&groups.level)), Value::number(groups.group_names.len())]);
+            }
+            if let Some(aliases) = &flags.target_aliases {
+                report.field("target_aliases", Value::number(aliases.alias_names.len()));
+            }
+            match ahda::max_hits::MaxHits::from_flags(&flags) {
+                Ok(Some(max_hits)) => report.row("max_hits", vec![Value::number(max_hits.max_hits), Value::text(max_hits.action)]),
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("ahda: can't read the target cap from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+            match ahda::lanes::Lanes::from_flags(&flags) {
+                Ok(Some(lanes)) => {
+                    for (lane, name) in lanes.names.iter().enumerate() {
+                        report.row("lane", vec![Value::text(String::from_utf8_lossy(name)), Value::number(lanes.offsets[lane]), Value::number(lanes.n_reads(lane))]);
+                    }
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("ahda: can't read the lanes from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+            match ahda::panel::Panel::from_flags(&flags) {
+                Ok(Some(panel)) => {
+                    let version = panel.index_version.as_ref().map(|x| Value::text(String::from_utf8_lossy(x)));
+                    report.field("index_version", Value::from(version));
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("ahda: can't read the target panel from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+            match ahda::manifest::Manifest::from_flags(&flags) {
+                Ok(Some(manifest)) => {
+                    for entry in manifest.entries.iter() {
+                        let first = entry.first_query_id.map(Value::number);
+                        let last = entry.last_query_id.map(Value::number);
+                        report.row("manifest", vec![Value::text(String::from_utf8_lossy(&entry.path)), Value::text(String::from_utf8_lossy(&entry.sample)), Value::from(first), Value::from(last), Value::number(entry.n_records)]);
+                    }
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("ahda: can't read the manifest from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+            match ahda::coverage::Coverage::from_flags(&flags) {
+                Ok(Some(coverage)) => {
+                    for (target, name) in flags.target_names.iter().enumerate().take(coverage.lengths.len()) {
+                        let breadth = coverage.breadth(target).map(|x| Value::number(format!("{:.4}", x)));
+                        report.row("coverage", vec![Value::text(String::from_utf8_lossy(name)), Value::number(coverage.covered_bases(target)), Value::number(coverage.lengths[target]), Value::from(breadth)]);
+                    }
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("ahda: can't read the coverage from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+            match flags.extensions.as_ref().and_then(|x| x.get_as::<u64>(ahda::EMPTY_ROWS_DROPPED_KEY)).transpose() {
+                Ok(Some(n_dropped)) => report.field("empty_rows_dropped", Value::number(n_dropped)),
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("ahda: can't read the number of dropped queries from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+            match ahda::self_hits::SelfHits::from_flags(&flags) {
+                Ok(Some(self_hits)) => report.field("self_hits_excluded", Value::number(self_hits.n_queries)),
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("ahda: can't read the self-hit exclusion from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+            match &flags.provenance {
+                Some(provenance) => {
+                    report.field("derived", Value::text(String::from_utf8_lossy(&provenance.operation)));
+                    report.field("created", Value::number(provenance.created));
+                    for input in provenance.inputs.iter() {
+                        let modified = input.modified.map(Value::number);
+                        report.row("input", vec![Value::text(String::from_utf8_lossy(&input.name)), Value::number(input.len), Value::text(format!("{:08x}", input.crc32)), Value::from(modified)]);
+                    }
+                },
+                None => report.field("derived", Value::text("no")),
+            }
+            report.write(&mut conn_out, format, color)?;
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Targets
+        Some(cli::Commands::Header {
+            input_file,
+            output_file,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let res = match input_file {
+                Some(file) => ahda::read_header_only(file),
+                None => ahda::headers::file::read_file_header_and_flags_any_layout(&mut std::io::stdin()),
+            };
+            let (header, flags) = match res {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("ahda: can't read header from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let bitmap_type = ahda::compression::BitmapType::from_u16(header.bitmap_type).map(|x| x.to_string()).unwrap_or(header.bitmap_type.to_string());
+            writeln!(conn_out, "file_format\t{}", header.file_format)?;
+            writeln!(conn_out, "metadata_compression\t{}", header.metadata_compression)?;
+            writeln!(conn_out, "fields_present\t{:#06x}", header.fields_present)?;
+            writeln!(conn_out, "n_targets\t{}", header.n_targets)?;
+            writeln!(conn_out, "n_queries\t{}", header.n_queries)?;
+            writeln!(conn_out, "bitmap\t{}", bitmap_type)?;
+            writeln!(conn_out, "block_size\t{}", header.block_size)?;
+            writeln!(conn_out, "flags_len\t{}", header.flags_len)?;
+            writeln!(conn_out, "sample\t{}", String::from_utf8_lossy(&flags.query_name))?;
+            writeln!(conn_out, "target_groups\t{}", flags.target_groups.len())?;
+            writeln!(conn_out, "target_aliases\t{}", if flags.target_aliases.is_some() { "yes" } else { "no" })?;
+            writeln!(conn_out, "extensions\t{}", flags.extensions.as_ref().map_or(0, |x| x.fields.len()))?;
+            conn_out.flush()?;
+            Ok(())
+        },
+
+        Some(cli::Commands::Targets {
+            input_file,
+            aliases,
+            output_file,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let (_, flags) = match ahda::headers::file::read_file_header_and_flags(&mut conn_in) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("ahda: can't read header from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+
+            let names = if *aliases {
+                match &flags.target_aliases {
+                    Some(target_aliases) => &target_aliases.alias_names,
+                    None => {
+                        eprintln!("ahda: input file `{}` does not store target aliases", in_name);
+                        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::InvalidInput)))
+                    },
+                }
+            } else {
+                &flags.target_names
+            };
+            for name in names.iter() {
+                conn_out.write_all(name)?;
+                conn_out.write_all(b"\n")?;
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Queries
+        Some(cli::Commands::Queries {
+            input_file,
+            output_file,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let names = match ahda::query_names_from_read(&mut conn_in) {
+                Ok(names) => names,
+                Err(e) => {
+                    eprintln!("ahda: can't read query names from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+            for name in names.iter() {
+                conn_out.write_all(name)?;
+                conn_out.write_all(b"\n")?;
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Info blocks
+        Some(cli::Commands::Info {
+            command: cli::InfoCommands::Blocks {
+                input_file,
+                output_file,
+                force,
+                verbose,
+            },
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let blocks = match ahda::block_info_from_read(&mut conn_in) {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    eprintln!("ahda: can't read blocks from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+
+            writeln!(conn_out, "block\toffset\tsize\tstart_query_id\trecords\tcompressed_size\traw_size\tcodec\tones\tmax_target")?;
+            for block in blocks.iter() {
+                let start = block.start_query_id.map(|x| x.to_string()).unwrap_or("-".to_string());
+                // Blocks from older versions don't record their set bits
+                let ones = block.stats.as_ref().map(|x| x.n_ones.to_string()).unwrap_or("-".to_string());
+                let max_target = block.stats.as_ref().and_then(|x| x.max_target).map(|x| x.to_string()).unwrap_or("-".to_string());
+                writeln!(conn_out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", block.index, block.offset, block.len, start, block.num_records, block.compressed_len, block.raw_len, block.codec, ones, max_target)?;
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Info schema
+        Some(cli::Commands::Info {
+            command: cli::InfoCommands::Schema {
+                input_file,
+                output_file,
+                force,
+                verbose,
+            },
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let (file_extensions, block_extensions) = match ahda::extensions_from_read(&mut conn_in) {
+                Ok(extensions) => extensions,
+                Err(e) => {
+                    eprintln!("ahda: can't read extensions from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+
+            writeln!(conn_out, "section\tkey\tbytes")?;
+            let sections = std::iter::once(("file".to_string(), file_extensions))
+                .chain(block_extensions.into_iter().enumerate().map(|(idx, x)| (format!("block:{}", idx), x)));
+            for (section, extensions) in sections {
+                for (key, value) in extensions.iter().flat_map(|x| x.fields.iter()) {
+                    writeln!(conn_out, "{}\t{}\t{}", section, String::from_utf8_lossy(key), value.len())?;
+                }
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Merge mates
+        Some(cli::Commands::MergeMates {
+            input_first,
+            input_second,
+            output_file,
+            keep_mates,
+            sample_name,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            let mut conn_in: Vec<Box<dyn Read>> = Vec::new();
+            for file in [input_first, input_second] {
+                match File::open(file) {
+                    Ok(conn) => conn_in.push(Box::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            }
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                if !*force && std::io::stdout().is_terminal() {
+                    eprintln!("ahda: refusing to write binary data to terminal, use `--force` to override");
+                    return Ok(());
+                }
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let mut opts = EncodeOpts::default();
+            opts.encode_query_names = true;
+            opts.accession = if let Some(name) = sample_name {
+                name.as_bytes().to_vec()
+            } else {
+                match peek_file_flags(conn_in.remove(0)) {
+                    Ok((conn, flags)) => {
+                        conn_in.insert(0, conn);
+                        flags.query_name
+                    },
+                    Err(e) => {
+                        eprintln!("ahda: can't read input file `{}`: {}", input_first.to_string_lossy(), e);
+                        return Err(e)
+                    },
+                }
+            };
+
+            let (conn_first, conn_second) = conn_in.split_at_mut(1);
+            if let Err(e) = ahda::merge_mates_from_reads_to_write(&mut conn_first[0], &mut conn_second[0], &mut conn_out, *keep_mates, opts) {
+                eprintln!("ahda: can't merge input files `{}` and `{}`: {}", input_first.to_string_lossy(), input_second.to_string_lossy(), e);
+                return Err(e)
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Shard plan
+        Some(cli::Commands::ShardPlan {
+            input_file,
+            n_shards,
+            output_file,
+            extract,
+            merge_plan,
+            prefix,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let plan = match ahda::shard::plan_shards(&mut conn_in, *n_shards as usize) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    eprintln!("ahda: can't plan shards of input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+            if *verbose > 0 {
+                note!("ahda: planned {} shard(s) of `{}`", plan.shards.len(), in_name);
+            }
+
+            if let Some(file) = merge_plan {
+                let written = match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(conn) => ahda::shard::MergePlan::new(&plan, prefix, ".ahda").write_tsv(&mut buffered_output(conn, cli.buffer_size as usize)),
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = written {
+                    eprintln!("ahda: can't write merge plan `{}`: {}", file.to_string_lossy(), e);
+                    return Err(e)
+                }
+            }
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                if extract.is_some() && !*force && std::io::stdout().is_terminal() {
+                    eprintln!("ahda: refusing to write binary data to terminal, use `--force` to override");
+                    return Ok(());
+                }
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            if let Some(shard_idx) = extract {
+                let Some(shard) = plan.shards.get(*shard_idx) else {
+                    let e = ahda::errors::ShardOutOfRangeErr{ shard: *shard_idx, n_shards: plan.shards.len() };
+                    eprintln!("ahda: can't extract shard from input file `{}`: {}", in_name, e);
+                    return Err(Box::new(e))
+                };
+                // Checked by clap
+                let mut conn = File::open(input_file.as_ref().unwrap())?;
+                if let Err(e) = ahda::shard::extract_shard(&mut conn, shard, &mut conn_out) {
+                    eprintln!("ahda: can't extract shard {} of input file `{}`: {}", shard_idx, in_name, e);
+                    return Err(e)
+                }
+            } else {
+                plan.write_tsv(&mut conn_out)?;
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Exclude self-hits
+        Some(cli::Commands::ExcludeSelfHits {
+            input_file,
+            sources,
+            output_file,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(conn),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            // The target names are needed to read the sources
+            let (mut conn_in, flags) = match peek_file_flags(conn_in) {
+                Ok(input) => input,
+                Err(e) => {
+                    eprintln!("ahda: can't read input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+            let query_sources = match File::open(sources).map_err(|e| e.into()).and_then(|mut conn| ahda::self_hits::QuerySources::from_read(&mut conn, &flags.target_names, &flags.target_groups)) {
+                Ok(query_sources) => query_sources,
+                Err(e) => {
+                    eprintln!("ahda: can't read query sources `{}`: {}", sources.to_string_lossy(), e);
+                    return Err(e)
+                },
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                if !*force && std::io::stdout().is_terminal() {
+                    eprintln!("ahda: refusing to write binary data to terminal, use `--force` to override");
+                    return Ok(());
+                }
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            match ahda::self_hits::exclude_self_hits_from_read_to_write(&mut conn_in, &query_sources, &mut conn_out, &EncodeOpts::default()) {
+                Ok(n_removed) => {
+                    if *verbose > 0 {
+                        note!("ahda: removed {} self-hits of {} reads with a source", n_removed, query_sources.len());
+                    }
+                },
+                Err(e) => {
+                    eprintln!("ahda: can't remove self-hits from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Export filter
+        Some(cli::Commands::ExportFilter {
+            input_file,
+            output_file,
+            fpr,
+            level,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                if !*force && std::io::stdout().is_terminal() {
+                    eprintln!("ahda: refusing to write binary data to terminal, use `--force` to override");
+                    return Ok(());
+                }
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let filters = match ahda::export_filter_from_read(&mut conn_in, *fpr, level.as_ref().map(|x| x.as_bytes())) {
+                Ok(filters) => filters,
+                Err(e) => {
+                    eprintln!("ahda: can't build filter from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+            if *verbose > 0 {
+                for (name, filter) in filters.names.iter().zip(filters.filters.iter()) {
+                    note!("ahda: filter `{}` has {} bits and {} hash functions", String::from_utf8_lossy(name), filter.n_bits(), filter.n_hashes);
+                }
+            }
+            filters.write_to(&mut conn_out)?;
+
+            Ok(())
+        },
+
+        // Export reads
+        Some(cli::Commands::ExportReads {
+            input_file,
+            output_file,
+            output_dir,
+            level,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let reads = match ahda::export_reads_from_read(&mut conn_in, level.as_ref().map(|x| x.as_bytes())) {
+                Ok(reads) => reads,
+                Err(e) => {
+                    eprintln!("ahda: can't read alignments from input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            };
+
+            if let Some(dir) = output_dir {
+                if let Err(e) = std::fs::create_dir_all(dir) {
+                    eprintln!("ahda: can't create output directory `{}`: {}", dir.to_string_lossy(), e);
+                    return Err(Box::new(e))
+                }
+                for (target, queries) in reads.iter() {
+                    // Target names can be paths
+                    let file = dir.join(String::from_utf8_lossy(target).replace('/', "_") + ".txt");
+                    let mut conn_out = match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                        Ok(out) => std::io::BufWriter::new(out),
+                        Err(e) => {
+                            eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                            return Err(Box::new(e))
+                        },
+                    };
+                    for query in queries.iter() {
+                        conn_out.write_all(query)?;
+                        conn_out.write_all(b"\n")?;
+                    }
+                    conn_out.flush()?;
+                }
+                if *verbose > 0 {
+                    note!("ahda: wrote {} files to `{}`", reads.len(), dir.to_string_lossy());
+                }
+                return Ok(())
+            }
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+            for (target, queries) in reads.iter() {
+                for query in queries.iter() {
+                    conn_out.write_all(target)?;
+                    conn_out.write_all(b"\t")?;
+                    conn_out.write_all(query)?;
+                    conn_out.write_all(b"\n")?;
+                }
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Grep
+        Some(cli::Commands::Grep {
+            query_name,
+            input_file,
+            format,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let names = HashSet::from([query_name.as_bytes().to_vec()]);
+            let out_format = format.clone().unwrap_or(ahda::Format::Themisto);
+            let mut conn_out = std::io::stdout();
+            match ahda::grep_from_read_to_write(&names, out_format, &mut conn_in, &mut conn_out) {
+                Ok(skipped) => {
+                    if *verbose > 0 {
+                        note!("ahda: skipped {} blocks using their query filters", skipped);
+                    }
+                },
+                Err(e) => {
+                    eprintln!("ahda: can't search input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+
+            Ok(())
+        },
+
+        // Slice
+        Some(cli::Commands::Slice {
+            targets,
+            input_file,
+            format,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let target_names: Vec<Vec<u8>> = targets.iter().map(|x| x.as_bytes().to_vec()).collect();
+            let out_format = format.clone().unwrap_or(ahda::Format::Themisto);
+            let mut conn_out = std::io::stdout();
+            match ahda::slice_from_read_to_write(&target_names, out_format, &mut conn_in, &mut conn_out) {
+                Ok(skipped) => {
+                    if *verbose > 0 {
+                        note!("ahda: skipped {} blocks using their target summaries", skipped);
+                    }
+                },
+                Err(e) => {
+                    eprintln!("ahda: can't search input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+
+            Ok(())
+        },
+
+        // PeekRandom
+        Some(cli::Commands::PeekRandom {
+            input_file,
+            n_records,
+            seed,
+            format,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            if input_file.is_none() && std::io::stdin().is_terminal() {
+                eprintln!("ahda: standard input is a terminal, ignoring");
+                return Ok(());
+            }
+            let in_name = input_file.as_ref().map(|x| x.to_string_lossy().to_string()).unwrap_or("-".to_string());
+            let mut conn_in: Box<dyn Read> = match input_file {
+                Some(file) => match File::open(file) {
+                    Ok(conn) => Box::new(BufReader::new(conn)),
+                    Err(e) => {
+                        eprintln!("ahda: can't open input file `{}`: {}", in_name, e);
+                        return Err(Box::new(e))
+                    },
+                },
+                None => Box::new(std::io::stdin()),
+            };
+
+            let out_format = format.clone().unwrap_or(ahda::Format::Themisto);
+            let mut conn_out = std::io::stdout();
+            match ahda::sample_from_read_to_write(*n_records, *seed, out_format, &mut conn_in, &mut conn_out) {
+                Ok(skipped) => {
+                    if *verbose > 0 {
+                        note!("ahda: skipped {} blocks without picked queries", skipped);
+                    }
+                },
+                Err(e) => {
+                    eprintln!("ahda: can't sample input file `{}`: {}", in_name, e);
+                    return Err(e)
+                },
+            }
+
+            Ok(())
+        },
+
+        // Simulate
+        Some(cli::Commands::Simulate {
+            n_queries,
+            n_targets,
+            density,
+            seed,
+            query_prefix,
+            target_prefix,
+            output_file,
+            format,
+            binary,
+            force,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                if *binary && !*force && std::io::stdout().is_terminal() {
+                    eprintln!("ahda: refusing to write binary data to terminal, use `--force` to override");
+                    return Ok(());
+                }
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+
+            let mut simulator = ahda::simulate::Simulator::new(*n_queries, *n_targets, *density, *seed);
+            simulator.set_query_prefix(query_prefix.as_bytes());
+            simulator.set_target_prefix(target_prefix.as_bytes());
+            let targets = simulator.targets();
+            let queries = simulator.queries();
+            let name = query_prefix.as_bytes().to_vec();
+
+            if *binary {
+                let records: Vec<ahda::PseudoAln> = simulator.collect();
+                let mut opts = EncodeOpts::default();
+                opts.accession = name;
+                opts.encode_query_names = true;
+                ahda::encode_to_write(&targets, &queries, &records, &mut conn_out, opts)?;
+            } else {
+                let mut printer = ahda::printer::Printer::new(&mut simulator, &targets, &name, queries.len(), format.clone().unwrap_or_default());
+                while let Some(line) = printer.try_next() {
+                    conn_out.write_all(&line?)?;
+                }
+            }
+            conn_out.flush()?;
+
+            Ok(())
+        },
+
+        // Serve
+        #[cfg(feature = "serve")]
+        Some(cli::Commands::Serve {
+            input_files,
+            bind,
+            threads,
+            timeout,
+            verbose,
+        }) => {
+            init_log(1 + *verbose as usize);
+
+            let input_files = match ahda::inputs::expand_inputs(input_files) {
+                Ok(files) => files,
+                Err(e) => {
+                    eprintln!("ahda: can't list input files: {}", e);
+                    return Err(e)
+                },
+            };
+            // The inputs are mapped so that only the blocks used by the requests are read
+            let mut contents: Vec<(String, memmap2::Mmap)> = Vec::with_capacity(input_files.len());
+            for file in input_files.iter() {
+                let name = file.file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+                // SAFETY: the mapping is only read. Changing an input while it is served is not supported.
+                match File::open(file).and_then(|x| unsafe { memmap2::Mmap::map(&x) }) {
+                    Ok(bytes) => contents.push((name, bytes)),
+                    Err(e) => {
+                        eprintln!("ahda: can't read input file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            }
+
+            let store = match ahda::serve::Store::new(contents.iter().map(|(name, bytes)| (name.clone(), &bytes[..])).collect()) {
+                Ok(store) => store,
+                Err(e) => {
+                    eprintln!("ahda: can't index input files: {}", e);
+                    return Err(e)
+                },
+            };
+            let listener = match std::net::TcpListener::bind(bind) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("ahda: can't listen on `{}`: {}", bind, e);
+                    return Err(Box::new(e))
+                },
+            };
+            note!("ahda: serving {} file(s) on http://{}", contents.len(), bind);
+            let opts = ahda::serve::ServeOpts{
+                threads: *threads,
+                timeout: if *timeout > 0 { Some(std::time::Duration::from_secs(*timeout)) } else { None },
+                ..Default::default()
+            };
+            ahda::serve::serve(listener, &store, &opts)
+        },
+        // Completions
+        Some(cli::Commands::Completions {
+            shell,
+            output_file,
+            force,
+        }) => {
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+            clap_complete::generate(*shell, &mut cli::command(), "ahda", &mut conn_out);
+            conn_out.flush()?;
+            Ok(())
+        },
+
+        // Man page
+        Some(cli::Commands::Man {
+            output_dir,
+            output_file,
+            force,
+        }) => {
+            if let Some(dir) = output_dir {
+                let page = dir.join("ahda.1");
+                if !*force && page.exists() {
+                    eprintln!("ahda: can't write man pages to `{}`: `{}` exists, use --force to overwrite", dir.to_string_lossy(), page.to_string_lossy());
+                    return Err(Box::new(std::io::Error::from(std::io::ErrorKind::AlreadyExists)))
+                }
+                if let Err(e) = std::fs::create_dir_all(dir).and_then(|_| clap_mangen::generate_to(cli::command(), dir)) {
+                    eprintln!("ahda: can't write man pages to `{}`: {}", dir.to_string_lossy(), e);
+                    return Err(Box::new(e))
+                }
+                return Ok(())
+            }
+
+            let mut conn_out: Box<dyn Write> = if let Some(file) = output_file {
+                match if *force { File::create(file.clone()) } else { File::create_new(file.clone()) } {
+                    Ok(out) => buffered_output(out, cli.buffer_size as usize),
+                    Err(e) => {
+                        eprintln!("ahda: can't create output file `{}`: {}", file.to_string_lossy(), e);
+                        return Err(Box::new(e))
+                    },
+                }
+            } else {
+                buffered_output(std::io::stdout(), cli.buffer_size as usize)
+            };
+            clap_mangen::Man::new(cli::command()).render(&mut conn_out)?;
+            conn_out.flush()?;
+            Ok(())
+        },
         None => { eprintln!("ahda: Try 'ahda --help' for more information."); Ok(()) },
     }
 }
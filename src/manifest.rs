@@ -0,0 +1,241 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Record which inputs were merged by `ahda cat`.
+//!
+//! Concatenating the files of several samples loses track of which records
+//! came from which sample. A [Manifest] lists each input with its sample
+//! name, the range of its query ids, its number of records, and the MD5 of
+//! the input file, so that the concatenated file can be split back into
+//! samples by query id.
+//!
+//! `ahda cat --manifest` writes the manifest as a TSV, see
+//! [Manifest::write_tsv], and `ahda cat --embed-manifest` stores it in the
+//! file extensions of the output under [MANIFEST_KEY], see
+//! [CatOpts::manifest](crate::CatOpts::manifest).
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::manifest::Manifest;
+//! use ahda::manifest::ManifestEntry;
+//! use ahda::encode_to_write;
+//! use ahda::EncodeOpts;
+//! use ahda::PseudoAln;
+//!
+//! let targets = vec![b"chr.fasta".to_vec()];
+//! let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+//! let data = vec![
+//!     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None },
+//!     PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![]), query_name: None },
+//! ];
+//! let mut opts = EncodeOpts::default();
+//! opts.accession = b"sample1".to_vec();
+//! let mut bytes: Vec<u8> = Vec::new();
+//! encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
+//!
+//! let entry = ManifestEntry::from_read(b"sample1.ahda", bytes.as_slice()).unwrap();
+//! assert_eq!(entry.sample, b"sample1".to_vec());
+//! assert_eq!((entry.first_query_id, entry.last_query_id, entry.n_records), (Some(0), Some(2), 2));
+//!
+//! let manifest = Manifest{ entries: vec![entry] };
+//! let mut tsv: Vec<u8> = Vec::new();
+//! manifest.write_tsv(&mut tsv).unwrap();
+//! assert!(tsv.starts_with(b"path\tsample\tfirst_query_id\tlast_query_id\tn_records\tmd5\nsample1.ahda\tsample1\t0\t2\t2\t"));
+//! ```
+//!
+
+use crate::headers::file::FileFlags;
+use crate::headers::file::read_file_flags;
+use crate::headers::file::read_file_header;
+
+use std::io::Read;
+use std::io::Write;
+
+use bincode::{Encode, Decode};
+use md5::Digest;
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the [Manifest] stored in the file extensions.
+pub const MANIFEST_KEY: &[u8] = b"ahda.manifest";
+
+/// One input of a concatenated file.
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Path of the input as given to `ahda cat`.
+    pub path: Vec<u8>,
+    /// Sample name in the [FileFlags] of the input.
+    pub sample: Vec<u8>,
+    /// Smallest query id in the input, None if it has no records.
+    pub first_query_id: Option<u32>,
+    /// Largest query id in the input, None if it has no records.
+    pub last_query_id: Option<u32>,
+    /// Number of records in the input.
+    pub n_records: u64,
+    /// MD5 of the bytes of the input.
+    pub md5: [u8; 16],
+}
+
+impl ManifestEntry {
+    /// Read the .ahda record in `conn` and describe it as the input at `path`.
+    ///
+    /// Reads all of `conn`. The block contents are skipped without
+    /// decompressing them.
+    ///
+    /// ## Errors and panics
+    /// Errors if the headers or the block flags can't be read.
+    ///
+    pub fn from_read<R: Read>(
+        path: &[u8],
+        conn: R,
+    ) -> Result<Self, E> {
+        let mut conn = Md5Reader{ inner: conn, hasher: md5::Md5::new() };
+        let header = read_file_header(&mut conn)?;
+        let flags = read_file_flags(&header, &mut conn)?;
+
+        let mut entry = ManifestEntry{ path: path.to_vec(), sample: flags.query_name.clone(), ..Default::default() };
+        crate::try_for_each_block_flags(&mut conn, &flags, |block_flags| {
+            let query_ids = block_flags.query_ids.ok_or(crate::errors::MissingBlockFieldsErr{})?;
+            entry.n_records += query_ids.len() as u64;
+            if let (Some(min), Some(max)) = (query_ids.iter().min(), query_ids.iter().max()) {
+                entry.first_query_id = Some(entry.first_query_id.map_or(*min, |x| x.min(*min)));
+                entry.last_query_id = Some(entry.last_query_id.map_or(*max, |x| x.max(*max)));
+            }
+            Ok(())
+        })?;
+        // Anything after the last block is hashed too
+        std::io::copy(&mut conn, &mut std::io::sink())?;
+        entry.md5 = conn.hasher.finalize().into();
+        Ok(entry)
+    }
+}
+
+/// Inputs of a concatenated file in the order they were merged.
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq, Eq)]
+pub struct Manifest {
+    /// One entry per input.
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Read the manifest stored in `flags`, if any.
+    ///
+    /// ## Errors and panics
+    /// Errors if the stored value can't be decoded.
+    ///
+    pub fn from_flags(
+        flags: &FileFlags,
+    ) -> Result<Option<Manifest>, E> {
+        flags.extensions.as_ref().and_then(|x| x.get_as::<Manifest>(MANIFEST_KEY)).transpose()
+    }
+
+    /// Shift the query ids of the entries like [CatOpts::renumber_queries](crate::CatOpts::renumber_queries).
+    ///
+    /// `n_queries` has the number of queries in the file header of each
+    /// input in the order of the entries. An input ends after its largest
+    /// query id if the number in its file header is smaller.
+    ///
+    /// ## Errors and panics
+    /// Errors with [QueryIndexOutOfRangeErr](crate::errors::QueryIndexOutOfRangeErr)
+    /// if a shifted query id doesn't fit in a u32.
+    ///
+    pub fn renumber_queries(
+        &mut self,
+        n_queries: &[u32],
+    ) -> Result<(), E> {
+        let mut offset: u32 = 0;
+        for (entry, n) in self.entries.iter_mut().zip(n_queries.iter()) {
+            let shift = |id: Option<u32>| id.map(|x| x.checked_add(offset).ok_or(crate::errors::QueryIndexOutOfRangeErr{})).transpose();
+            entry.first_query_id = shift(entry.first_query_id)?;
+            entry.last_query_id = shift(entry.last_query_id)?;
+            let end = offset.checked_add(*n).zip(entry.last_query_id.map_or(Some(0), |x| x.checked_add(1))).map(|(x, y)| x.max(y));
+            offset = end.ok_or(crate::errors::QueryIndexOutOfRangeErr{})?;
+        }
+        Ok(())
+    }
+
+    /// Write the manifest to `conn` as a TSV with a header line.
+    ///
+    /// Missing query ids of inputs without records are written as `-` and
+    /// the MD5 as lowercase hex.
+    pub fn write_tsv<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        writeln!(conn, "path\tsample\tfirst_query_id\tlast_query_id\tn_records\tmd5")?;
+        for entry in self.entries.iter() {
+            let first = entry.first_query_id.map(|x| x.to_string()).unwrap_or("-".to_string());
+            let last = entry.last_query_id.map(|x| x.to_string()).unwrap_or("-".to_string());
+            let md5: String = entry.md5.iter().map(|x| format!("{:02x}", x)).collect();
+            writeln!(conn, "{}\t{}\t{}\t{}\t{}\t{}", String::from_utf8_lossy(&entry.path), String::from_utf8_lossy(&entry.sample), first, last, entry.n_records, md5)?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the MD5 of the bytes read from `inner`.
+struct Md5Reader<R: Read> {
+    inner: R,
+    hasher: md5::Md5,
+}
+
+impl<R: Read> Read for Md5Reader<R> {
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> std::io::Result<usize> {
+        let nbytes = self.inner.read(buf)?;
+        self.hasher.update(&buf[0..nbytes]);
+        Ok(nbytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn manifest_entry_of_empty_record() {
+        use super::ManifestEntry;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+
+        let opts = EncodeOpts{ accession: b"sample".to_vec(), ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&[b"chr.fasta".to_vec()], &[], &[], &mut bytes, opts).unwrap();
+
+        let got = ManifestEntry::from_read(b"empty.ahda", bytes.as_slice()).unwrap();
+        assert_eq!((got.first_query_id, got.last_query_id, got.n_records), (None, None, 0));
+        // MD5 of the whole input
+        let expected: [u8; 16] = {
+            use md5::Digest;
+            md5::Md5::digest(&bytes).into()
+        };
+        assert_eq!(got.md5, expected);
+    }
+
+    #[test]
+    fn renumber_queries_shifts_entries() {
+        use super::Manifest;
+        use super::ManifestEntry;
+
+        let entry = |first: Option<u32>, last: Option<u32>| ManifestEntry{ first_query_id: first, last_query_id: last, ..Default::default() };
+        let mut manifest = Manifest{ entries: vec![entry(Some(0), Some(4)), entry(None, None), entry(Some(1), Some(2)), entry(Some(0), Some(1))] };
+        // The third input doesn't know its number of queries
+        manifest.renumber_queries(&[5, 3, 0, 2]).unwrap();
+        assert_eq!(manifest.entries, vec![entry(Some(0), Some(4)), entry(None, None), entry(Some(9), Some(10)), entry(Some(11), Some(12))]);
+
+        assert!(manifest.renumber_queries(&[u32::MAX, 1, 1]).is_err());
+    }
+}
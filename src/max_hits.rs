@@ -0,0 +1,131 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Cap the number of targets that a query aligns to.
+//!
+//! Low-complexity reads can align to thousands of targets, which makes every
+//! output line that lists the targets of such a read very long. [MaxHits]
+//! either truncates the targets of these records to the first `max_hits`
+//! target ids or drops all of their alignments, so that the read is reported
+//! as unaligned.
+//!
+//! [Encoder::set_max_hits](crate::encoder::Encoder::set_max_hits) stores the
+//! cap in the file extensions under [MAX_HITS_KEY], so that tools reading the
+//! file know that the data was filtered, see [MaxHits::from_flags].
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::PseudoAln;
+//! use ahda::max_hits::MaxHits;
+//! use ahda::max_hits::MaxHitsAction;
+//!
+//! let cap = MaxHits{ max_hits: 2, action: MaxHitsAction::Truncate };
+//!
+//! let mut record = PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 3, 5]), query_name: None };
+//! assert!(cap.apply(&mut record));
+//! assert_eq!(record.ones, Some(vec![0, 3]));
+//!
+//! let cap = MaxHits{ max_hits: 2, action: MaxHitsAction::Drop };
+//! let mut record = PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 3, 5]), query_name: None };
+//! assert!(cap.apply(&mut record));
+//! assert_eq!(record.ones, Some(vec![]));
+//! ```
+//!
+
+use crate::PseudoAln;
+use crate::headers::file::FileFlags;
+
+use bincode::{Encode, Decode};
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the [MaxHits] stored in the file extensions.
+pub const MAX_HITS_KEY: &[u8] = b"ahda.max_hits";
+
+/// What to do with records that align to more than `max_hits` targets.
+#[derive(Clone, Copy, Debug, Decode, Default, Encode, PartialEq, Eq)]
+pub enum MaxHitsAction {
+    /// Keep the first `max_hits` targets.
+    #[default]
+    Truncate,
+    /// Remove all targets.
+    Drop,
+}
+
+impl std::str::FromStr for MaxHitsAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(MaxHitsAction::Truncate),
+            "drop" => Ok(MaxHitsAction::Drop),
+            _ => Err(format!("'{}' is not truncate or drop", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for MaxHitsAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MaxHitsAction::Truncate => write!(f, "truncate"),
+            MaxHitsAction::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+/// Cap on the number of targets per record.
+#[derive(Clone, Copy, Debug, Decode, Encode, PartialEq, Eq)]
+pub struct MaxHits {
+    pub max_hits: u32,
+    pub action: MaxHitsAction,
+}
+
+impl MaxHits {
+    /// Apply the cap to `record`.
+    ///
+    /// Both the target ids and names are capped. Returns true if the record
+    /// aligned to more than `max_hits` targets.
+    pub fn apply(
+        &self,
+        record: &mut PseudoAln,
+    ) -> bool {
+        let n_hits = record.ones.as_ref().map(|x| x.len()).max(record.ones_names.as_ref().map(|x| x.len())).unwrap_or(0);
+        if n_hits <= self.max_hits as usize {
+            return false
+        }
+        let keep = match self.action {
+            MaxHitsAction::Truncate => self.max_hits as usize,
+            MaxHitsAction::Drop => 0,
+        };
+        if let Some(ones) = record.ones.as_mut() {
+            ones.truncate(keep);
+        }
+        if let Some(ones_names) = record.ones_names.as_mut() {
+            ones_names.truncate(keep);
+        }
+        true
+    }
+
+    /// Read the cap stored in `flags`, if any.
+    ///
+    /// ## Errors and panics
+    /// Errors if the stored value can't be decoded.
+    ///
+    pub fn from_flags(
+        flags: &FileFlags,
+    ) -> Result<Option<MaxHits>, E> {
+        flags.extensions.as_ref().and_then(|x| x.get_as::<MaxHits>(MAX_HITS_KEY)).transpose()
+    }
+}
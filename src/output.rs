@@ -0,0 +1,110 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Buffered output for the functions that write to [Write].
+//!
+//! The `_to_write` functions in the crate root write one line or block at a
+//! time. Functions that write lines wrap `conn_out` in a [BufWriter] of
+//! [DEFAULT_BUFFER_SIZE] bytes and flush it before returning, so they can be
+//! given unbuffered writers like [std::io::Stdout] or [std::fs::File]
+//! directly. Functions that write blocks write the headers of each block
+//! together with [write_all_vectored].
+//!
+//! [buffered] wraps a writer in the same buffer, for writing the output of
+//! several calls or the blocks of an [Encoder](crate::encoder::Encoder).
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::output::buffered;
+//! use ahda::output::write_all_vectored;
+//! use std::io::Write;
+//!
+//! let mut bytes: Vec<u8> = Vec::new();
+//! let mut conn_out = buffered(&mut bytes, 1024);
+//! write_all_vectored(&mut conn_out, &[b"header", b"flags"]).unwrap();
+//! conn_out.flush().unwrap();
+//! drop(conn_out);
+//!
+//! assert_eq!(bytes, b"headerflags");
+//! ```
+//!
+
+use std::io::BufWriter;
+use std::io::IoSlice;
+use std::io::Write;
+
+/// Size of the buffers used by the functions that write lines.
+pub const DEFAULT_BUFFER_SIZE: usize = 1 << 16;
+
+/// Wrap `conn_out` in a [BufWriter] of `capacity` bytes.
+///
+/// A `capacity` of 0 is treated as 1.
+///
+pub fn buffered<W: Write>(
+    conn_out: W,
+    capacity: usize,
+) -> BufWriter<W> {
+    BufWriter::with_capacity(capacity.max(1), conn_out)
+}
+
+/// Write all of `bufs` to `conn_out` in order.
+///
+/// Uses [Write::write_vectored] so that writers that support it receive the
+/// buffers in as few calls as possible.
+///
+/// ## Errors and panics
+/// Errors if writing to `conn_out` fails or it stops accepting data.
+///
+pub fn write_all_vectored<W: Write>(
+    conn_out: &mut W,
+    bufs: &[&[u8]],
+) -> std::io::Result<()> {
+    let mut slices: Vec<IoSlice> = bufs.iter().filter(|x| !x.is_empty()).map(|x| IoSlice::new(x)).collect();
+    let mut slices = slices.as_mut_slice();
+    while !slices.is_empty() {
+        match conn_out.write_vectored(slices) {
+            Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::WriteZero)),
+            Ok(n) => IoSlice::advance_slices(&mut slices, n),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn write_all_vectored_short_writes() {
+        use super::write_all_vectored;
+        use std::io::Write;
+
+        // Accepts at most 3 bytes per call
+        struct Short(Vec<u8>);
+        impl Write for Short {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+
+        let mut conn_out = Short(Vec::new());
+        write_all_vectored(&mut conn_out, &[b"abcde", b"", b"fg", b"hijkl"]).unwrap();
+        assert_eq!(conn_out.0, b"abcdefghijkl");
+    }
+}
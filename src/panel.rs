@@ -0,0 +1,205 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Record which index the alignments were made against.
+//!
+//! Rebuilding an index with an updated reference panel often keeps the
+//! number of targets or even their names, so files aligned against different
+//! versions of the index can be concatenated or merged without any error
+//! while the target ids mean different sequences. [Panel] stores the
+//! SHA-256 of the target names, see [target_list_digest], and an optional
+//! version string of the index in the file extensions under [PANEL_KEY].
+//!
+//! `ahda cat` and `ahda set` compare the panels of their inputs with
+//! [check_panels] and refuse to combine files from different panels. Files
+//! without a stored panel, eg. from older versions of ahda, are not checked.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::panel::check_panels;
+//! use ahda::panel::Panel;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let v1 = Panel::new(&targets, Some(b"refseq-2024".to_vec()));
+//! let v2 = Panel::new(&targets, Some(b"refseq-2025".to_vec()));
+//! let unversioned = Panel::new(&targets, None);
+//!
+//! assert!(v1.is_compatible(&unversioned));
+//! assert!(!v1.is_compatible(&v2));
+//! assert_eq!(check_panels(&[Some(v1), None, Some(unversioned), Some(v2)]).len(), 1);
+//! ```
+//!
+
+use crate::Incompatibility;
+use crate::compression::MetadataCompression;
+use crate::headers::file::FileFlags;
+use crate::headers::file::decode_file_flags;
+use crate::headers::file::decode_file_header;
+use crate::index::target_list_digest;
+
+use std::io::Cursor;
+use std::io::Read;
+
+use bincode::{Encode, Decode};
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the [Panel] stored in the file extensions.
+pub const PANEL_KEY: &[u8] = b"ahda.panel";
+
+/// Reader that yields the bytes read by [peek_panel] before the rest of the input.
+pub type Peeked<R> = std::io::Chain<Cursor<Vec<u8>>, R>;
+
+/// Target panel and index version that the alignments were made against.
+#[derive(Clone, Debug, Decode, Default, Encode, PartialEq, Eq)]
+pub struct Panel {
+    /// SHA-256 of the target names, see [target_list_digest].
+    pub targets_sha256: Vec<u8>,
+    /// Version of the index given by the user.
+    pub index_version: Option<Vec<u8>>,
+}
+
+impl Panel {
+    /// Panel of `target_names` in index order with an optional `index_version`.
+    pub fn new(
+        target_names: &[Vec<u8>],
+        index_version: Option<Vec<u8>>,
+    ) -> Self {
+        Panel{ targets_sha256: target_list_digest(target_names).to_vec(), index_version }
+    }
+
+    /// Check if files with this panel and `other` can be combined.
+    ///
+    /// The target digests must be equal. The index versions must also be
+    /// equal if both panels have one.
+    pub fn is_compatible(
+        &self,
+        other: &Panel,
+    ) -> bool {
+        let same_version = match (&self.index_version, &other.index_version) {
+            (Some(x), Some(y)) => x == y,
+            _ => true,
+        };
+        self.targets_sha256 == other.targets_sha256 && same_version
+    }
+
+    /// Read the panel stored in `flags`, if any.
+    ///
+    /// ## Errors and panics
+    /// Errors if the stored value can't be decoded.
+    ///
+    pub fn from_flags(
+        flags: &FileFlags,
+    ) -> Result<Option<Panel>, E> {
+        flags.extensions.as_ref().and_then(|x| x.get_as::<Panel>(PANEL_KEY)).transpose()
+    }
+}
+
+impl std::fmt::Display for Panel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let digest: String = self.targets_sha256.iter().take(6).map(|x| format!("{:02x}", x)).collect();
+        match &self.index_version {
+            Some(version) => write!(f, "index version {} (targets sha256 {}...)", String::from_utf8_lossy(version), digest),
+            None => write!(f, "targets sha256 {}...", digest),
+        }
+    }
+}
+
+/// Compare the panels of the inputs to the first input that has one.
+///
+/// `panels` has the panel of each input in order, or None if the input
+/// doesn't have one. Returns an [Incompatibility::Panel] for each input
+/// that is not [compatible](Panel::is_compatible) with the first panel.
+pub fn check_panels(
+    panels: &[Option<Panel>],
+) -> Vec<Incompatibility> {
+    let mut panels = panels.iter().enumerate().filter_map(|(input, panel)| panel.as_ref().map(|x| (input, x)));
+    let Some((_, expected)) = panels.next() else {
+        return Vec::new()
+    };
+    panels.filter(|(_, got)| !expected.is_compatible(got)).map(|(input, got)| {
+        Incompatibility::Panel{ input, expected: expected.clone(), got: got.clone() }
+    }).collect()
+}
+
+/// Read the [Panel] of the .ahda record in `conn` without consuming it.
+///
+/// Returns the panel and a reader that yields all of `conn` from the start.
+/// Records in a zstd-seekable container are not checked and have no panel.
+///
+/// ## Errors and panics
+/// Errors if the file header or flags can't be read.
+///
+pub fn peek_panel<R: Read>(
+    mut conn: R,
+) -> Result<(Option<Panel>, Peeked<R>), E> {
+    let mut bytes: Vec<u8> = vec![0; 32];
+    conn.read_exact(&mut bytes)?;
+    let header = decode_file_header(&bytes)?;
+    if header.is_zstd_seekable() {
+        return Ok((None, Cursor::new(bytes).chain(conn)))
+    }
+    let flags_bytes = crate::headers::read_len_bytes(&mut conn, header.flags_len)?;
+    let flags = decode_file_flags(&flags_bytes, &MetadataCompression::from_u8(header.metadata_compression)?)?;
+    bytes.extend_from_slice(&flags_bytes);
+    Ok((Panel::from_flags(&flags)?, Cursor::new(bytes).chain(conn)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn check_panels_against_first() {
+        use super::check_panels;
+        use super::Panel;
+        use crate::Incompatibility;
+
+        let v1 = Panel::new(&[b"a".to_vec()], Some(b"v1".to_vec()));
+        let other_targets = Panel::new(&[b"b".to_vec()], Some(b"v1".to_vec()));
+        let unversioned = Panel::new(&[b"a".to_vec()], None);
+
+        assert!(check_panels(&[None, None]).is_empty());
+        assert!(check_panels(&[None, Some(v1.clone()), Some(unversioned.clone())]).is_empty());
+
+        let got = check_panels(&[Some(v1.clone()), Some(other_targets.clone()), None]);
+        assert_eq!(got[0].to_string(), format!("input 1 was aligned against {}, expected {}", other_targets, v1));
+        assert_eq!(got, vec![Incompatibility::Panel{ input: 1, expected: v1, got: other_targets }]);
+    }
+
+    #[test]
+    fn peek_panel_keeps_input() {
+        use super::peek_panel;
+        use super::Panel;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use std::io::Read;
+
+        let targets = [b"chr.fasta".to_vec()];
+        let opts = EncodeOpts{ index_version: Some(b"v1".to_vec()), ..Default::default() };
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &[], &mut bytes, opts).unwrap();
+
+        let (panel, mut conn) = peek_panel(bytes.as_slice()).unwrap();
+        assert_eq!(panel, Some(Panel::new(&targets, Some(b"v1".to_vec()))));
+        let mut got: Vec<u8> = Vec::new();
+        conn.read_to_end(&mut got).unwrap();
+        assert_eq!(got, bytes);
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &[], &mut bytes, EncodeOpts::default()).unwrap();
+        assert_eq!(peek_panel(bytes.as_slice()).unwrap().0, None);
+    }
+}
@@ -0,0 +1,155 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Follow a file that is still being written.
+//!
+//! [FollowReader] waits for more data when the file it reads from runs out,
+//! like `tail -f`, so that the output of a running aligner can be parsed and
+//! encoded while the alignment is still going on. End of input is signalled
+//! through a shared flag, after which the data written so far is read out and
+//! the next read at the end of the file returns 0 bytes.
+//!
+//! Incomplete lines at the end of the file are not a problem for
+//! [Parser](super::Parser): it keeps waiting until the rest of the line or the
+//! end of input arrives.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::parser::follow::FollowReader;
+//! use std::io::Read;
+//! use std::sync::Arc;
+//! use std::sync::atomic::AtomicBool;
+//!
+//! // Input that has already ended, eg. after Ctrl-C
+//! let stop = Arc::new(AtomicBool::new(true));
+//!
+//! let input: &[u8] = b"0 1\n1 0\n";
+//! let mut reader = FollowReader::new(input, stop.clone());
+//!
+//! let mut contents = Vec::new();
+//! reader.read_to_end(&mut contents).unwrap();
+//! assert_eq!(contents, b"0 1\n1 0\n");
+//! ```
+//!
+
+use std::io::Read;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// Reads from `inner` and waits for more data at the end until `stop` is set.
+pub struct FollowReader<R: Read> {
+    inner: R,
+    stop: Arc<AtomicBool>,
+    poll_interval: Duration,
+}
+
+impl<R: Read> FollowReader<R> {
+    /// Follows `inner` until `stop` is set, checking for new data every 200 ms.
+    pub fn new(
+        inner: R,
+        stop: Arc<AtomicBool>,
+    ) -> Self {
+        FollowReader {
+            inner,
+            stop,
+            poll_interval: Duration::from_millis(200),
+        }
+    }
+
+    /// Sets how long to wait before checking for new data again.
+    pub fn set_poll_interval(
+        &mut self,
+        poll_interval: Duration,
+    ) {
+        self.poll_interval = poll_interval;
+    }
+
+    /// Returns true if the end of input has been signalled.
+    pub fn stopped(
+        &self,
+    ) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> std::io::Result<usize> {
+        loop {
+            // Check the flag before reading so that data written just before
+            // the end of input was signalled is not lost.
+            let stopped = self.stopped();
+            let n = self.inner.read(buf)?;
+            if n > 0 || buf.is_empty() || stopped {
+                return Ok(n)
+            }
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn follow_until_stopped() {
+        use super::FollowReader;
+        use std::collections::VecDeque;
+        use std::io::Read;
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::atomic::Ordering;
+        use std::time::Duration;
+
+        // Empty chunks are reads at the current end of a growing file
+        struct Growing {
+            chunks: VecDeque<Vec<u8>>,
+            stop: Arc<AtomicBool>,
+        }
+        impl Read for Growing {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                match self.chunks.pop_front() {
+                    Some(chunk) => {
+                        buf[..chunk.len()].copy_from_slice(&chunk);
+                        Ok(chunk.len())
+                    },
+                    None => {
+                        self.stop.store(true, Ordering::Relaxed);
+                        Ok(0)
+                    },
+                }
+            }
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let chunks = vec![b"0 1\n1".to_vec(), Vec::new(), Vec::new(), b" 0\n".to_vec(), Vec::new(), b"2 1\n".to_vec()];
+        let inner = Growing { chunks: VecDeque::from(chunks), stop: stop.clone() };
+
+        let mut reader = FollowReader::new(inner, stop.clone());
+        reader.set_poll_interval(Duration::from_millis(1));
+
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).unwrap();
+
+        let expected = b"0 1\n1 0\n2 1\n".to_vec();
+        assert_eq!(got, expected);
+        assert!(reader.stopped());
+    }
+}
@@ -56,6 +56,7 @@
 use std::io::Read;
 
 use crate::PseudoAln;
+use crate::errors::CorruptedInputErr;
 
 type E = Box<dyn std::error::Error>;
 
@@ -74,12 +75,12 @@ pub fn read_metagraph<R: Read>(
 
     let mut records = contents.split(separator);
 
-    let query_id: u32 = records.next().unwrap().parse::<u32>().unwrap();
-    let query_name = records.next().unwrap().as_bytes().to_vec();
+    let query_id: u32 = records.next().ok_or(CorruptedInputErr)?.parse::<u32>()?;
+    let query_name = records.next().ok_or(CorruptedInputErr)?.as_bytes().to_vec();
 
     let mut ones_names: Vec<Vec<u8>> = Vec::new();
 
-    let ones_records = records.next().unwrap().split(':');
+    let ones_records = records.next().ok_or(CorruptedInputErr)?.split(':');
 
     for record in ones_records {
         if !record.is_empty() {
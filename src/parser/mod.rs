@@ -74,19 +74,31 @@
 // Format specific implementations
 pub mod ahda_tsv;
 pub mod bifrost;
+pub mod follow;
 pub mod fulgor;
 pub mod metagraph;
+pub mod query_ids;
+#[cfg(feature = "sam")]
 pub mod sam;
 pub mod themisto;
 
 use crate::Format;
 use crate::PseudoAln;
 
+use crate::coverage::Coverage;
+use crate::coverage::CoverageBuilder;
+use crate::coverage::read_sam_span;
+use crate::coverage::read_sam_target_lengths;
+
 use crate::parser::ahda_tsv::read_ahda_tsv;
 use crate::parser::bifrost::read_bifrost;
 use crate::parser::fulgor::read_fulgor;
 use crate::parser::metagraph::read_metagraph;
+use crate::parser::query_ids::QueryIdPolicy;
+#[cfg(feature = "sam")]
 use crate::parser::sam::read_sam;
+#[cfg(feature = "sam")]
+use crate::parser::sam::read_sam_target_names;
 use crate::parser::themisto::read_themisto;
 
 use indexmap::IndexSet;
@@ -99,13 +111,89 @@ use std::io::Read;
 
 type E = Box<dyn std::error::Error>;
 
+#[cfg(not(feature = "sam"))]
+fn read_sam<R: Read>(
+    _conn: &mut R,
+) -> Result<PseudoAln, E> {
+    Err(Box::new(crate::errors::MissingFeatureErr{ feature: "sam" }))
+}
+
+#[cfg(not(feature = "sam"))]
+fn read_sam_target_names<R: Read>(
+    _conn: &mut R,
+) -> Result<Vec<Vec<u8>>, E> {
+    Err(Box::new(crate::errors::MissingFeatureErr{ feature: "sam" }))
+}
+
+/// Reads one line of `format` without filling in the missing fields.
+pub(crate) fn read_record<R: Read>(
+    format: &Format,
+    conn: &mut R,
+) -> Result<PseudoAln, E> {
+    match format {
+        Format::Themisto => read_themisto(conn),
+        Format::Fulgor => read_fulgor(conn),
+        Format::Metagraph => read_metagraph(conn),
+        Format::Bifrost => read_bifrost(conn),
+        Format::SAM => read_sam(conn),
+        Format::AhdaTSV => read_ahda_tsv(conn),
+    }
+}
+
+/// Assigns each query name its position in `names`.
+///
+/// Errors with [DuplicateQueryNameErr](crate::errors::DuplicateQueryNameErr)
+/// if a name appears twice, since the later records with the name would get
+/// the query id of the first one. Use
+/// [RenameDuplicates](crate::parser::query_ids::RenameDuplicates) to keep the
+/// names apart.
+fn index_query_names<Q: Iterator<Item=Vec<u8>>>(
+    names: &mut Q,
+) -> Result<IndexSet<Vec<u8>>, E> {
+    let mut query_to_pos: IndexSet<Vec<u8>> = IndexSet::new();
+    for name in names {
+        let (first, inserted) = query_to_pos.insert_full(name);
+        if !inserted {
+            let name = String::from_utf8_lossy(&query_to_pos[first]).to_string();
+            return Err(Box::new(crate::errors::DuplicateQueryNameErr{ name, first, second: query_to_pos.len() }))
+        }
+    }
+    Ok(query_to_pos)
+}
+
+/// Reads the first line of `reader` into `buf`.
+///
+/// Skips the `#PG` lines that `ahda convert` writes before the header, see
+/// [Program](crate::printer::Program).
+fn read_first_line<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+) -> Result<(), E> {
+    loop {
+        buf.clear();
+        reader.read_until(b'\n', buf)?;
+        if !buf.starts_with(b"#PG\t") {
+            return Ok(())
+        }
+    }
+}
+
 pub struct Parser<'a, R: Read> {
     reader: BufReader<&'a mut R>,
     buf: Cursor<Vec<u8>>,
     pub format: Format,
+    // Separator in the input and the separator of `format` that replaces it
+    separator: Option<(u8, u8)>,
 
     query_to_pos: IndexSet<Vec<u8>>,
     target_to_pos: IndexSet<Vec<u8>>,
+    query_ids: QueryIdPolicy,
+    allow_extra: bool,
+    n_extra: usize,
+
+    // Target lengths from the SAM header and the covered buckets
+    sam_target_lengths: Vec<(Vec<u8>, u64)>,
+    coverage: Option<CoverageBuilder>,
 
     // What values to fill in the records
     fill_query_id: bool,
@@ -121,60 +209,198 @@ impl<'a, R: Read> Parser<'a, R> {
         conn_query_names: Option<&mut Q>,
         targets: Option<&mut T>,
     ) -> Result<Self, E> {
-        // Guess the input format
-        let mut reader = BufReader::new(conn_pseudoalns);
-        let mut buf = Cursor::new(Vec::<u8>::new());
-        reader.read_until(b'\n', buf.get_mut())?;
-        let format = guess_format(buf.get_ref())?;
+        ParserBuilder::new().build(conn_pseudoalns, conn_query_names, targets)
+    }
 
-        let mut ret = Self {
-            reader, buf, format,
-            query_to_pos: IndexSet::new(),
-            target_to_pos: IndexSet::new(),
-            fill_query_id: true,
-            fill_query_name: true,
-            fill_target_ids: true,
-            fill_target_names: true,
-        };
+    pub fn new_with_format<T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
+        conn_pseudoalns: &'a mut R,
+        conn_query_names: Option<&mut Q>,
+        targets: Option<&mut T>,
+        format: Format,
+    ) -> Result<Self, E> {
+        ParserBuilder::new().format(format).build(conn_pseudoalns, conn_query_names, targets)
+    }
+}
 
-        let targets_from_header = ret.read_header()?;
-        if let Some(targets) = targets {
-            ret.target_to_pos = IndexSet::<Vec<u8>>::from_iter(targets);
-        } else if let Some(targets) = targets_from_header {
-            ret.target_to_pos = IndexSet::<Vec<u8>>::from_iter(targets);
-        } else {
-            return Err(Box::new(crate::errors::NeedTargetSequencesErr{ format: ret.format }))
-        }
+/// Builds a [Parser] for inputs that don't quite follow their format.
+///
+/// [Parser::new] detects the format with [guess_format] and expects the
+/// separators and header lines of that format. Some tools write
+/// slightly different dialects, eg. Fulgor output with spaces instead of
+/// tabs or Themisto output with a line of column names, which these options
+/// can read:
+///
+/// - [format](ParserBuilder::format) skips the format detection.
+/// - [separator](ParserBuilder::separator) reads fields separated by another
+///   byte than the format uses. Requires giving the format.
+/// - [header](ParserBuilder::header) sets whether the input has a header
+///   line, eg. for Bifrost output without the target names or Metagraph
+///   output with column names. Without a header the target names must be given.
+/// - [skip_lines](ParserBuilder::skip_lines) skips lines at the start of the
+///   input, eg. comments or log output.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::parser::ParserBuilder;
+/// use ahda::Format;
+///
+/// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+/// let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+///
+/// // Fulgor output with spaces and a line of column names
+/// let input = b"# written by some tool\nquery n ids\nr1 2 0 1\nr2 0\n".to_vec();
+///
+/// let mut conn = input.as_slice();
+/// let mut parser = ParserBuilder::new()
+///     .format(Format::Fulgor)
+///     .separator(b' ')
+///     .header(true)
+///     .skip_lines(1)
+///     .build(&mut conn, Some(&mut queries.into_iter()), Some(&mut targets.into_iter()))
+///     .unwrap();
+///
+/// let got: Vec<Vec<u32>> = parser.by_ref().map(|x| x.ones.unwrap()).collect();
+/// assert_eq!(got, vec![vec![0, 1], vec![]]);
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParserBuilder {
+    format: Option<Format>,
+    separator: Option<u8>,
+    header: Option<bool>,
+    skip_lines: usize,
+}
 
-        if let Some(conn_query_names) = conn_query_names {
-            ret.query_to_pos = IndexSet::<Vec<u8>>::from_iter(conn_query_names);
-        }
+impl ParserBuilder {
+    /// Detect the format and read it as is, same as [Parser::new].
+    pub fn new() -> Self {
+        ParserBuilder::default()
+    }
 
-        Ok(ret)
+    /// Read the input as `format` instead of detecting it.
+    pub fn format(
+        mut self,
+        format: Format,
+    ) -> Self {
+        self.format = Some(format);
+        self
     }
 
-    pub fn new_with_format<T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
+    /// Read fields separated by `separator` instead of the separator of the format.
+    ///
+    /// Themisto separates the fields with spaces and the other formats with
+    /// tabs. The separator replaces these but not the `:` between the
+    /// targets in Metagraph output.
+    pub fn separator(
+        mut self,
+        separator: u8,
+    ) -> Self {
+        self.separator = Some(separator);
+        self
+    }
+
+    /// Set whether the input starts with a header line.
+    ///
+    /// Defaults to the format: Bifrost and ahda .tsv have a header line with
+    /// the target names and SAM has the `@` lines, while Themisto, Fulgor,
+    /// and Metagraph have no header. A header given to a format without one
+    /// is skipped.
+    pub fn header(
+        mut self,
+        present: bool,
+    ) -> Self {
+        self.header = Some(present);
+        self
+    }
+
+    /// Skip `n_lines` lines at the start of the input.
+    ///
+    /// The lines are skipped before the format is detected and before the
+    /// `#PG` lines written by `ahda convert`.
+    pub fn skip_lines(
+        mut self,
+        n_lines: usize,
+    ) -> Self {
+        self.skip_lines = n_lines;
+        self
+    }
+
+    /// Build a [Parser] that reads from `conn_pseudoalns`.
+    ///
+    /// The query and target names are used as in [Parser::new].
+    ///
+    /// ## Errors and panics
+    /// Errors with [SeparatorNeedsFormatErr](crate::errors::SeparatorNeedsFormatErr)
+    /// if a separator was given without the format, with
+    /// [InvalidSeparatorErr](crate::errors::InvalidSeparatorErr) if the
+    /// separator is a line break, any separator for SAM, or `:` for
+    /// Metagraph, and with [NeedTargetSequencesErr](crate::errors::NeedTargetSequencesErr)
+    /// if the target names were not given and there is no header to read
+    /// them from. Format detection errors as in [guess_format].
+    ///
+    pub fn build<'a, R: Read, T: Iterator<Item=Vec<u8>>, Q: Iterator<Item=Vec<u8>>>(
+        &self,
         conn_pseudoalns: &'a mut R,
         conn_query_names: Option<&mut Q>,
         targets: Option<&mut T>,
-        format: Format,
-    ) -> Result<Self, E> {
-        // Guess the input format
+    ) -> Result<Parser<'a, R>, E> {
+        if self.separator.is_some() && self.format.is_none() {
+            return Err(Box::new(crate::errors::SeparatorNeedsFormatErr{}))
+        }
+
         let mut reader = BufReader::new(conn_pseudoalns);
         let mut buf = Cursor::new(Vec::<u8>::new());
-        reader.read_until(b'\n', buf.get_mut())?;
+        for _ in 0..self.skip_lines {
+            buf.get_mut().clear();
+            reader.read_until(b'\n', buf.get_mut())?;
+        }
+        read_first_line(&mut reader, buf.get_mut())?;
+        let format = match &self.format {
+            Some(format) => format.clone(),
+            None => guess_format(buf.get_ref())?,
+        };
 
-        let mut ret = Self {
+        // Custom separators are replaced with the separator of the format
+        let separator = match self.separator {
+            Some(separator) => {
+                let invalid = separator == b'\n' || format == Format::SAM || (format == Format::Metagraph && separator == b':');
+                if invalid {
+                    return Err(Box::new(crate::errors::InvalidSeparatorErr{ separator, format }))
+                }
+                let native = if format == Format::Themisto { b' ' } else { b'\t' };
+                Some((separator, native))
+            },
+            None => None,
+        };
+        if let Some((from, to)) = separator {
+            replace_separator(buf.get_mut(), from, to);
+        }
+
+        let mut ret = Parser {
             reader, buf, format,
+            separator,
             query_to_pos: IndexSet::new(),
             target_to_pos: IndexSet::new(),
+            query_ids: QueryIdPolicy::default(),
+            allow_extra: false,
+            n_extra: 0,
+            sam_target_lengths: Vec::new(),
+            coverage: None,
             fill_query_id: true,
             fill_query_name: true,
             fill_target_ids: true,
             fill_target_names: true,
         };
 
-        let targets_from_header = ret.read_header()?;
+        let targets_from_header = match (self.header, &ret.format) {
+            (Some(false), _) => None,
+            (Some(true), Format::Themisto | Format::Fulgor | Format::Metagraph) => {
+                ret.buf.get_mut().clear();
+                None
+            },
+            _ => ret.read_header()?,
+        };
         if let Some(targets) = targets {
             ret.target_to_pos = IndexSet::<Vec<u8>>::from_iter(targets);
         } else if let Some(targets) = targets_from_header {
@@ -184,13 +410,21 @@ impl<'a, R: Read> Parser<'a, R> {
         }
 
         if let Some(conn_query_names) = conn_query_names {
-            ret.query_to_pos = IndexSet::<Vec<u8>>::from_iter(conn_query_names);
+            ret.query_to_pos = index_query_names(conn_query_names)?;
         }
 
         Ok(ret)
     }
 }
 
+fn replace_separator(
+    line: &mut [u8],
+    from: u8,
+    to: u8,
+) {
+    line.iter_mut().filter(|x| **x == from).for_each(|x| *x = to);
+}
+
 impl<R: Read> Parser<'_, R> {
     /// Consumes the header line and returns the target sequence names.
     ///
@@ -240,10 +474,8 @@ impl<R: Read> Parser<'_, R> {
                         break;
                     }
                 }
-                let mut reader = noodles_sam::io::reader::Builder::default().build_from_reader(&mut header_contents)?;
-                let header = reader.read_header()?;
-                let target_names: Vec<Vec<u8>> = header.reference_sequences().iter().map(|x| x.0.to_vec()).collect();
-                Ok(Some(target_names))
+                self.sam_target_lengths = read_sam_target_lengths(header_contents.get_ref());
+                Ok(Some(read_sam_target_names(&mut header_contents)?))
             },
             Format::AhdaTSV => {
                 let separator: char = '\t';
@@ -267,6 +499,8 @@ impl<R: Read> Parser<'_, R> {
     }
 
     /// Returns the number of query records in the input fastX file
+    ///
+    /// With [QueryIdPolicy::Sequential] this includes the names read so far.
     pub fn len(
         &self,
     ) -> usize {
@@ -289,29 +523,44 @@ impl<R: Read> Parser<'_, R> {
     fn fill_record(
         &mut self,
         record: &mut PseudoAln,
-    ) {
+    ) -> Result<(), E> {
         if record.query_id.is_none() && self.fill_query_id {
-            let key: Vec<u8> = record.query_name.as_ref().unwrap().to_vec();
-            let query_index = self.query_to_pos.get_index_of(&key).unwrap();
-            record.query_id = Some(query_index as u32);
+            let key = record.query_name.as_ref().ok_or(crate::errors::UnknownQueryErr{})?;
+            let query_index = match &self.query_ids {
+                QueryIdPolicy::FromList => self.query_to_pos.get_index_of(key).map(|x| x as u32),
+                QueryIdPolicy::Sequential => Some(self.query_to_pos.insert_full(key.clone()).0 as u32),
+                policy => policy.query_id(key),
+            };
+            record.query_id = Some(query_index.ok_or(crate::errors::UnknownQueryErr{})?);
+        }
+
+        // Unnamed ranks past the end of the query names, eg. from a subsampled query file
+        if let (Some(query_id), None) = (record.query_id, &record.query_name) {
+            if self.query_ids == QueryIdPolicy::FromList && !self.query_to_pos.is_empty() && query_id as usize >= self.query_to_pos.len() {
+                if !self.allow_extra {
+                    return Err(Box::new(crate::errors::QueryRankOutOfRangeErr{ rank: query_id, n_queries: self.query_to_pos.len() }))
+                }
+                self.add_extra_queries(query_id)?;
+            }
         }
 
         if record.query_name.is_none() && self.fill_query_name {
-            let query_name = self.query_to_pos.get_index(record.query_id.unwrap() as usize).unwrap();
+            let query_id = record.query_id.ok_or(crate::errors::UnknownQueryErr{})?;
+            let query_name = self.query_to_pos.get_index(query_id as usize).ok_or(crate::errors::UnknownQueryErr{})?;
             record.query_name = Some(query_name.to_vec());
         }
 
         if record.ones_names.is_none() && record.ones.is_some() && self.fill_target_names {
             let ones_names = record.ones.as_ref().unwrap().iter().map(|target_idx| {
-                self.target_to_pos.get_index(*target_idx as usize).unwrap().clone()
-            }).collect::<Vec<Vec<u8>>>();
+                Ok(self.target_to_pos.get_index(*target_idx as usize).ok_or(crate::errors::UnknownTargetErr{})?.clone())
+            }).collect::<Result<Vec<Vec<u8>>, E>>()?;
             record.ones_names = Some(ones_names);
         }
 
         if record.ones_names.is_some() && record.ones.is_none() && self.fill_target_ids{
             let ones = record.ones_names.as_ref().unwrap().iter().map(|target_name| {
-                self.target_to_pos.get_index_of(target_name).unwrap() as u32
-            }).collect::<Vec<u32>>();
+                Ok(self.target_to_pos.get_index_of(target_name).ok_or(crate::errors::UnknownTargetErr{})? as u32)
+            }).collect::<Result<Vec<u32>, E>>()?;
             record.ones = Some(ones);
         }
 
@@ -327,6 +576,135 @@ impl<R: Read> Parser<'_, R> {
         if !self.fill_target_names {
             record.ones_names = None;
         }
+
+        Ok(())
+    }
+
+    // Name the queries up to `query_id` that are missing from the query names
+    fn add_extra_queries(
+        &mut self,
+        query_id: u32,
+    ) -> Result<(), E> {
+        while self.query_to_pos.len() <= query_id as usize {
+            let name = format!("extra.{}", self.query_to_pos.len()).into_bytes();
+            let (first, inserted) = self.query_to_pos.insert_full(name);
+            if !inserted {
+                let name = String::from_utf8_lossy(&self.query_to_pos[first]).to_string();
+                return Err(Box::new(crate::errors::DuplicateQueryNameErr{ name, first, second: self.query_to_pos.len() }))
+            }
+            self.n_extra += 1;
+        }
+        Ok(())
+    }
+
+    /// Parse the next record, returning an error if it can't be read.
+    ///
+    /// Returns `None` at the end of the input. A line that can't be parsed is
+    /// returned as an error and parsing can continue from the next line.
+    pub fn try_next(
+        &mut self,
+    ) -> Option<Result<PseudoAln, E>> {
+        if self.buf.get_ref().is_empty() {
+            if let Err(e) = self.reader.read_until(b'\n', self.buf.get_mut()) {
+                return Some(Err(Box::new(e)))
+            }
+            if self.buf.get_ref().is_empty() {
+                return None
+            }
+            self.buf.rewind().unwrap();
+        }
+        self.buf.get_mut().pop();
+        if let Some((from, to)) = self.separator {
+            replace_separator(self.buf.get_mut(), from, to);
+        }
+
+        if let Some(coverage) = self.coverage.as_mut() {
+            if let Some((target, start, n_bases)) = read_sam_span(self.buf.get_ref()) {
+                if let Some(target) = self.target_to_pos.get_index_of(target) {
+                    coverage.add(target, start, n_bases);
+                }
+            }
+        }
+
+        let record = read_record(&self.format, &mut self.buf);
+
+        self.buf.get_mut().clear();
+
+        Some(record.and_then(|mut record| {
+            self.fill_record(&mut record)?;
+            Ok(record)
+        }))
+    }
+
+    /// Iterate over the records returning an error for lines that can't be parsed.
+    pub fn try_iter<'b>(
+        &'b mut self,
+    ) -> Box<dyn Iterator<Item=Result<PseudoAln, E>> + 'b> {
+        Box::new(std::iter::from_fn(move || self.try_next()))
+    }
+
+    /// Set how query ids are assigned to records that only have a name.
+    ///
+    /// See [QueryIdPolicy]. Defaults to [QueryIdPolicy::FromList].
+    pub fn set_query_id_policy(
+        &mut self,
+        policy: QueryIdPolicy,
+    ) {
+        self.query_ids = policy;
+    }
+
+    /// Name the queries whose rank is past the end of the query names.
+    ///
+    /// Records from a query file with fewer reads than the alignment, eg. a
+    /// subsampled file, are otherwise returned as
+    /// [QueryRankOutOfRangeErr](crate::errors::QueryRankOutOfRangeErr). If
+    /// `val` is true, the query names are extended up to the rank with the
+    /// names `extra.<rank>` and [len](Parser::len) grows as the records are
+    /// parsed. Only applies to [QueryIdPolicy::FromList].
+    pub fn set_allow_extra(
+        &mut self,
+        val: bool,
+    ) {
+        self.allow_extra = val;
+    }
+
+    /// Number of query names added by [set_allow_extra](Parser::set_allow_extra) so far.
+    pub fn n_extra(
+        &self,
+    ) -> usize {
+        self.n_extra
+    }
+
+    /// Record the buckets of `bucket_size` bases that the alignments cover, see [Coverage].
+    ///
+    /// The target lengths are read from the @SQ lines of the SAM header.
+    /// Records that can't be parsed don't add to the coverage.
+    ///
+    /// ## Errors and panics
+    /// Errors with [CoverageNeedsPositionsErr](crate::errors::CoverageNeedsPositionsErr)
+    /// if the input is not SAM, since the other formats have no positions.
+    ///
+    /// Panics if `bucket_size` is 0.
+    ///
+    pub fn set_coverage(
+        &mut self,
+        bucket_size: u32,
+    ) -> Result<(), E> {
+        if self.format != Format::SAM {
+            return Err(Box::new(crate::errors::CoverageNeedsPositionsErr{ format: self.format.clone() }))
+        }
+        let lengths = self.target_to_pos.iter().map(|name| {
+            self.sam_target_lengths.iter().find(|x| x.0 == *name).map(|x| x.1).unwrap_or(0)
+        }).collect();
+        self.coverage = Some(CoverageBuilder::new(bucket_size, lengths));
+        Ok(())
+    }
+
+    /// Coverage of the records parsed so far, see [set_coverage](Parser::set_coverage).
+    pub fn coverage(
+        &self,
+    ) -> Option<Coverage> {
+        self.coverage.as_ref().map(|x| x.finish())
     }
 
     pub fn fill_query_id(
@@ -364,33 +742,14 @@ impl<R: Read> Iterator for Parser<'_, R> {
     fn next(
         &mut self,
     ) -> Option<PseudoAln> {
-        if self.buf.get_ref().is_empty() {
-            let ret = self.reader.read_until(b'\n', self.buf.get_mut());
-            if ret.is_err() || self.buf.get_ref().is_empty() {
-                return None
-            }
-            self.buf.rewind().unwrap();
-        }
-        self.buf.get_mut().pop();
-
-        let mut record = match self.format {
-            Format::Themisto => read_themisto(&mut self.buf).unwrap(),
-            Format::Fulgor => read_fulgor(&mut self.buf).unwrap(),
-            Format::Metagraph => read_metagraph(&mut self.buf).unwrap(),
-            Format::Bifrost => read_bifrost(&mut self.buf).unwrap(),
-            Format::SAM => read_sam(&mut self.buf).unwrap(),
-            Format::AhdaTSV => read_ahda_tsv(&mut self.buf).unwrap(),
-        };
-
-        self.buf.get_mut().clear();
-
-        self.fill_record(&mut record);
-        Some(record)
+        Some(self.try_next()?.unwrap())
     }
 }
 
 /// Guess the input format from plaintext bytes
 ///
+/// Skips the `#PG` lines written before the header by `ahda convert`.
+///
 /// Supports:
 /// - SAM
 /// - Themisto
@@ -411,6 +770,11 @@ impl<R: Read> Iterator for Parser<'_, R> {
 pub fn guess_format(
     bytes: &[u8],
 ) -> Result<Format, E> {
+    let mut bytes = bytes;
+    while bytes.starts_with(b"#PG\t") {
+        let linebreak = bytes.iter().position(|x| *x == b'\n').map(|x| x + 1).unwrap_or(bytes.len());
+        bytes = &bytes[linebreak..];
+    }
     let first_line: Vec<u8> = if bytes.contains(&b'\n') {
         let linebreak = bytes.iter().position(|x| *x == b'\n').unwrap();
         bytes[0..linebreak].to_vec()
@@ -480,6 +844,56 @@ mod tests {
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn parser_skips_program_lines() {
+        use crate::Format;
+        use super::guess_format;
+        use super::Parser;
+
+        let data: Vec<u8> = b"#PG\tID:ahda\tPN:ahda\tVN:0.1.0\tCL:ahda convert\nquery_name\tchr.fasta\tplasmid.fasta\nr1\t0\t1\n".to_vec();
+        assert_eq!(guess_format(&data).unwrap(), Format::Bifrost);
+
+        let mut cursor = std::io::Cursor::new(data);
+        let mut queries = vec![b"r1".to_vec()].into_iter();
+        let mut reader = Parser::new(&mut cursor, Some(&mut queries), None::<&mut std::iter::Empty<Vec<u8>>>).unwrap();
+        assert_eq!(reader.get_targets().unwrap(), vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()]);
+        assert_eq!(reader.next().unwrap().ones, Some(vec![1]));
+    }
+
+    #[test]
+    fn parser_builder_options() {
+        use crate::Format;
+        use crate::errors::InvalidSeparatorErr;
+        use crate::errors::NeedTargetSequencesErr;
+        use crate::errors::SeparatorNeedsFormatErr;
+        use super::ParserBuilder;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+        let no_targets = None::<&mut std::iter::Empty<Vec<u8>>>;
+
+        // Bifrost without the header line
+        let data = b"r1\t0\t1\nr2\t1\t1\n".to_vec();
+        let builder = ParserBuilder::new().format(Format::Bifrost).header(false);
+        let mut conn = data.as_slice();
+        let mut parser = builder.build(&mut conn, Some(&mut queries.clone().into_iter()), Some(&mut targets.clone().into_iter())).unwrap();
+        let got: Vec<Vec<u32>> = parser.by_ref().map(|x| x.ones.unwrap()).collect();
+        assert_eq!(got, vec![vec![1], vec![0, 1]]);
+        let got = builder.build(&mut data.as_slice(), Some(&mut queries.clone().into_iter()), no_targets).err().unwrap();
+        assert!(got.downcast_ref::<NeedTargetSequencesErr>().is_some());
+
+        // Metagraph with commas
+        let data = b"1,r2,chr.fasta:plasmid.fasta\n".to_vec();
+        let mut conn = data.as_slice();
+        let mut parser = ParserBuilder::new().format(Format::Metagraph).separator(b',').build(&mut conn, Some(&mut queries.clone().into_iter()), Some(&mut targets.clone().into_iter())).unwrap();
+        assert_eq!(parser.next().unwrap().ones, Some(vec![0, 1]));
+
+        let got = ParserBuilder::new().separator(b',').build(&mut data.as_slice(), Some(&mut queries.clone().into_iter()), Some(&mut targets.clone().into_iter())).err().unwrap();
+        assert!(got.downcast_ref::<SeparatorNeedsFormatErr>().is_some());
+        let got = ParserBuilder::new().format(Format::Metagraph).separator(b':').build(&mut data.as_slice(), Some(&mut queries.clone().into_iter()), Some(&mut targets.clone().into_iter())).err().unwrap();
+        assert!(got.downcast_ref::<InvalidSeparatorErr>().is_some());
+    }
+
     #[test]
     fn guess_format_fulgor() {
         use crate::Format;
@@ -565,6 +979,29 @@ mod tests {
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn try_iter_reports_bad_lines() {
+        use super::Parser;
+        use crate::Format;
+        use std::io::Cursor;
+
+        let data: Vec<u8> = b"0 5\n1 0 1\n2\n".to_vec();
+        let mut cursor = Cursor::new(data);
+
+        let targets = vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()];
+        let queries = vec!["r1".as_bytes().to_vec(), "r2".as_bytes().to_vec(), "r3".as_bytes().to_vec()];
+        let mut it = queries.into_iter();
+        let mut t_it = targets.into_iter();
+        let mut reader = Parser::new_with_format(&mut cursor, Some(&mut it), Some(&mut t_it), Format::Themisto).unwrap();
+
+        let got: Vec<_> = reader.try_iter().collect();
+
+        assert_eq!(got.len(), 3);
+        assert!(got[0].is_err());
+        assert_eq!(got[1].as_ref().unwrap().ones, Some(vec![0, 1]));
+        assert_eq!(got[2].as_ref().unwrap().query_name, Some("r3".as_bytes().to_vec()));
+    }
+
     #[test]
     fn read_bifrost_header() {
         use super::Parser;
@@ -588,6 +1025,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sam")]
     fn read_sam_header() {
         use super::Parser;
         use std::io::Cursor;
@@ -614,6 +1052,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sam")]
     fn consume_sam_header_with_next() {
         use super::Parser;
         use crate::PseudoAln;
@@ -641,6 +1080,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sam")]
     fn read_sam_header_and_first_line() {
         use super::Parser;
         use crate::PseudoAln;
@@ -671,6 +1111,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sam")]
     fn read_sam_multiple() {
         use super::Parser;
         use crate::PseudoAln;
@@ -1053,6 +1494,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sam")]
     fn parse_sam_output() {
         use super::Parser;
 
@@ -1094,7 +1536,7 @@ mod tests {
             PseudoAln{ query_id: Some(8), query_name: Some("ERR4035126.973529".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
             PseudoAln{ query_id: Some(8), query_name: Some("ERR4035126.973529".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
             PseudoAln{ query_id: Some(9), query_name: Some("ERR4035126.621281".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
-            PseudoAln{ query_id: Some(10), query_name: Some("ERR4035126.1178767".as_bytes().to_vec()), ones_names: None, ones: None },
+            PseudoAln{ query_id: Some(10), query_name: Some("ERR4035126.1178767".as_bytes().to_vec()), ones_names: Some(vec![]), ones: Some(vec![]) },
             PseudoAln{ query_id: Some(9), query_name: Some("ERR4035126.621281".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
             PseudoAln{ query_id: Some(9), query_name: Some("ERR4035126.621281".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
         ];
@@ -1134,6 +1576,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sam")]
     fn parse_sam_output_with_targets_from_data() {
         use super::Parser;
 
@@ -1175,7 +1618,7 @@ mod tests {
             PseudoAln{ query_id: Some(8), query_name: Some("ERR4035126.973529".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
             PseudoAln{ query_id: Some(8), query_name: Some("ERR4035126.973529".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
             PseudoAln{ query_id: Some(9), query_name: Some("ERR4035126.621281".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
-            PseudoAln{ query_id: Some(10), query_name: Some("ERR4035126.1178767".as_bytes().to_vec()), ones_names: None, ones: None },
+            PseudoAln{ query_id: Some(10), query_name: Some("ERR4035126.1178767".as_bytes().to_vec()), ones_names: Some(vec![]), ones: Some(vec![]) },
             PseudoAln{ query_id: Some(9), query_name: Some("ERR4035126.621281".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
             PseudoAln{ query_id: Some(9), query_name: Some("ERR4035126.621281".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
         ];
@@ -1212,4 +1655,74 @@ mod tests {
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn parser_ranks_past_query_names() {
+        use super::Parser;
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+
+        let mut input = Cursor::new(b"0 0\n3 1\n".to_vec());
+        let mut q_it = queries.clone().into_iter();
+        let mut t_it = targets.clone().into_iter();
+        let mut parser = Parser::new(&mut input, Some(&mut q_it), Some(&mut t_it)).unwrap();
+        assert!(parser.try_next().unwrap().is_ok());
+        let got = parser.try_next().unwrap().unwrap_err();
+        assert!(got.downcast_ref::<crate::errors::QueryRankOutOfRangeErr>().is_some());
+
+        let mut input = Cursor::new(b"0 0\n3 1\n".to_vec());
+        let mut q_it = queries.into_iter();
+        let mut t_it = targets.into_iter();
+        let mut parser = Parser::new(&mut input, Some(&mut q_it), Some(&mut t_it)).unwrap();
+        parser.set_allow_extra(true);
+        let got: Vec<Vec<u8>> = parser.try_iter().map(|x| x.unwrap().query_name.unwrap()).collect();
+        assert_eq!(got, vec![b"r1".to_vec(), b"extra.3".to_vec()]);
+        assert_eq!(parser.n_extra(), 2);
+        assert_eq!(parser.len(), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "sam")]
+    fn sam_coverage_from_positions() {
+        use super::Parser;
+        use std::io::Cursor;
+
+        let mut data: Vec<u8> = b"@HD\tVN:1.5\tSO:unsorted\tGO:query\n".to_vec();
+        data.append(&mut b"@SQ\tSN:OZ038621.1\tLN:5535987\n".to_vec());
+        data.append(&mut b"@SQ\tSN:OZ038622.1\tLN:104814\n".to_vec());
+        data.append(&mut b"ERR4035126.1\t16\tOZ038621.1\t4541508\t60\t151M\t*\t0\t0\t*\t*\n".to_vec());
+        data.append(&mut b"ERR4035126.2\t0\tOZ038622.1\t951\t60\t100M\t*\t0\t0\t*\t*\n".to_vec());
+        data.append(&mut b"ERR4035126.3\t4\t*\t0\t0\t*\t*\t0\t0\t*\t*\n".to_vec());
+
+        let mut cursor = Cursor::new(data);
+
+        let queries = vec!["ERR4035126.1".as_bytes().to_vec(), "ERR4035126.2".as_bytes().to_vec(), "ERR4035126.3".as_bytes().to_vec()];
+        let mut it = queries.into_iter();
+        let mut reader = Parser::new(&mut cursor, Some(&mut it), None::<&mut std::iter::Empty<Vec<u8>>>).unwrap();
+        reader.set_coverage(1000).unwrap();
+        assert_eq!(reader.by_ref().count(), 3);
+
+        let coverage = reader.coverage().unwrap();
+        assert_eq!(coverage.lengths, vec![5535987, 104814]);
+        assert_eq!(coverage.covered, vec![vec![4541], vec![0, 1]]);
+        assert_eq!(coverage.covered_bases(1), 2000);
+    }
+
+    #[test]
+    fn coverage_needs_positions() {
+        use super::Parser;
+        use crate::Format;
+        use std::io::Cursor;
+
+        let mut cursor = Cursor::new(b"0 0\n".to_vec());
+        let targets = vec!["chr.fasta".as_bytes().to_vec()];
+        let queries = vec!["r1".as_bytes().to_vec()];
+        let mut it = queries.into_iter();
+        let mut t_it = targets.into_iter();
+        let mut reader = Parser::new_with_format(&mut cursor, Some(&mut it), Some(&mut t_it), Format::Themisto).unwrap();
+
+        assert!(reader.set_coverage(100).is_err());
+        assert!(reader.coverage().is_none());
+    }
 }
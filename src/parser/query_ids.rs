@@ -0,0 +1,289 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Assign query ids to records that only contain the query name.
+//!
+//! Bifrost, Fulgor, and SAM output identify the queries by name. By default
+//! [Parser](super::Parser) finds the query id by looking up the name in the
+//! query names given to it, which are usually read from the .fastX file that
+//! was aligned. The other [QueryIdPolicy] variants assign the ids without
+//! access to the .fastX file.
+//!
+//! The policy only applies to records without a query id. Filling in the
+//! query names of records that only have an id still requires the query
+//! names, except for names that [QueryIdPolicy::Sequential] has already seen.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::parser::Parser;
+//! use ahda::parser::query_ids::QueryIdPolicy;
+//!
+//! let mut input: &[u8] = b"ERR4035126.2\t1\nERR4035126.1\t0\n";
+//! let mut t_it = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()].into_iter();
+//!
+//! let mut parser = Parser::new(&mut input, None::<&mut std::iter::Empty<Vec<u8>>>, Some(&mut t_it)).unwrap();
+//! parser.set_query_id_policy(QueryIdPolicy::NameSuffix);
+//!
+//! let ids: Vec<u32> = parser.map(|x| x.query_id.unwrap()).collect();
+//! assert_eq!(ids, vec![1, 0]);
+//! ```
+//!
+//! ## Duplicate query names
+//!
+//! [Parser](super::Parser) errors with
+//! [DuplicateQueryNameErr](crate::errors::DuplicateQueryNameErr) if a name
+//! appears more than once in the query names, since records with the name
+//! can't be attributed to the right query. Malformed .fastX files with
+//! repeated names can still be encoded by passing the names through
+//! [RenameDuplicates], which appends the occurrence number to the second and
+//! later copies of a name, eg. `r1`, `r1_2`, `r1_3`. This keeps the query ids
+//! in the order of the .fastX file. Formats that identify the queries by
+//! name assign the records of a duplicated name to its first occurrence.
+//!
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+
+type E = Box<dyn std::error::Error>;
+
+/// How [Parser](super::Parser) assigns query ids to records that only have a name.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum QueryIdPolicy {
+    /// Position of the name in the query names given to the parser.
+    #[default]
+    FromList,
+    /// Order in which the names first appear in the input, starting from 0.
+    Sequential,
+    /// Integer after the last `.` in the name minus 1.
+    ///
+    /// Reads downloaded from the SRA are numbered from 1, so `ERR4035126.17`
+    /// gets the query id 16.
+    NameSuffix,
+    /// Ids from a map of query names to ids, see [read_query_id_map].
+    Map(HashMap<Vec<u8>, u32>),
+}
+
+impl std::str::FromStr for QueryIdPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "list" => Ok(QueryIdPolicy::FromList),
+            "sequential" => Ok(QueryIdPolicy::Sequential),
+            "name-suffix" => Ok(QueryIdPolicy::NameSuffix),
+            _ => Err(format!("'{}' is not a valid QueryIdPolicy", s)),
+        }
+    }
+}
+
+impl QueryIdPolicy {
+    /// Query id of `name` for the policies that don't need other names.
+    ///
+    /// Returns None for [FromList](QueryIdPolicy::FromList) and
+    /// [Sequential](QueryIdPolicy::Sequential), and if `name` has no id.
+    pub fn query_id(
+        &self,
+        name: &[u8],
+    ) -> Option<u32> {
+        match self {
+            QueryIdPolicy::NameSuffix => {
+                let start = name.iter().rposition(|x| *x == b'.')? + 1;
+                let suffix = std::str::from_utf8(&name[start..]).ok()?;
+                if !suffix.bytes().all(|x| x.is_ascii_digit()) {
+                    return None
+                }
+                suffix.parse::<u32>().ok()?.checked_sub(1)
+            },
+            QueryIdPolicy::Map(ids) => ids.get(name).copied(),
+            _ => None,
+        }
+    }
+}
+
+/// Appends the occurrence number to repeated query names.
+///
+/// The first occurrence of a name is kept as is and the `n`th occurrence
+/// becomes `name_n`. If `name_n` is already taken, `n` is incremented until
+/// the name is unique.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::parser::query_ids::RenameDuplicates;
+///
+/// let names = vec![b"r1".to_vec(), b"r2".to_vec(), b"r1".to_vec(), b"r1".to_vec()];
+/// let got: Vec<Vec<u8>> = RenameDuplicates::new(names.into_iter()).collect();
+///
+/// assert_eq!(got, vec![b"r1".to_vec(), b"r2".to_vec(), b"r1_2".to_vec(), b"r1_3".to_vec()]);
+/// ```
+///
+pub struct RenameDuplicates<I: Iterator<Item=Vec<u8>>> {
+    names: I,
+    seen: HashSet<Vec<u8>>,
+    occurrences: HashMap<Vec<u8>, usize>,
+    renamed: usize,
+}
+
+impl<I: Iterator<Item=Vec<u8>>> RenameDuplicates<I> {
+    pub fn new(
+        names: I,
+    ) -> Self {
+        RenameDuplicates{ names, seen: HashSet::new(), occurrences: HashMap::new(), renamed: 0 }
+    }
+
+    /// Number of names that have been renamed so far.
+    pub fn renamed(
+        &self,
+    ) -> usize {
+        self.renamed
+    }
+}
+
+impl<I: Iterator<Item=Vec<u8>>> Iterator for RenameDuplicates<I> {
+    type Item = Vec<u8>;
+
+    fn next(
+        &mut self,
+    ) -> Option<Vec<u8>> {
+        let name = self.names.next()?;
+        if self.seen.insert(name.clone()) {
+            return Some(name)
+        }
+
+        let occurrence = self.occurrences.entry(name.clone()).or_insert(1);
+        let renamed = loop {
+            *occurrence += 1;
+            let mut candidate = name.clone();
+            candidate.extend_from_slice(format!("_{}", occurrence).as_bytes());
+            if !self.seen.contains(&candidate) {
+                break candidate
+            }
+        };
+        self.seen.insert(renamed.clone());
+        self.renamed += 1;
+        Some(renamed)
+    }
+}
+
+/// Read a [QueryIdPolicy::Map] from a tab-separated file.
+///
+/// Each line of the input contains a query name and its query id separated
+/// by a tab. Empty lines are skipped.
+///
+/// ## Usage
+/// ```rust
+/// use ahda::parser::query_ids::read_query_id_map;
+///
+/// let mut input: &[u8] = b"r1\t1\nr2\t0\n";
+/// let policy = read_query_id_map(&mut input).unwrap();
+///
+/// assert_eq!(policy.query_id(b"r2"), Some(0));
+/// assert_eq!(policy.query_id(b"r3"), None);
+/// ```
+///
+/// ## Errors and panics
+/// Errors with [CorruptedInputErr](crate::errors::CorruptedInputErr) if a
+/// line does not contain a name and an integer id.
+///
+pub fn read_query_id_map<R: Read>(
+    conn: &mut R,
+) -> Result<QueryIdPolicy, E> {
+    let mut ids: HashMap<Vec<u8>, u32> = HashMap::new();
+    for line in BufReader::new(conn).split(b'\n') {
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if line.is_empty() {
+            continue
+        }
+
+        let sep = line.iter().position(|x| *x == b'\t').ok_or(crate::errors::CorruptedInputErr{})?;
+        let id = std::str::from_utf8(&line[(sep + 1)..])?.parse::<u32>().map_err(|_| crate::errors::CorruptedInputErr{})?;
+        line.truncate(sep);
+        ids.insert(line, id);
+    }
+    Ok(QueryIdPolicy::Map(ids))
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn name_suffix_query_ids() {
+        use super::QueryIdPolicy;
+
+        let policy = QueryIdPolicy::NameSuffix;
+        assert_eq!(policy.query_id(b"ERR4035126.1262953"), Some(1262952));
+        assert_eq!(policy.query_id(b"ERR4035126.1"), Some(0));
+        assert_eq!(policy.query_id(b"ERR4035126.0"), None);
+        assert_eq!(policy.query_id(b"ERR4035126.17/1"), None);
+        assert_eq!(policy.query_id(b"ERR4035126."), None);
+        assert_eq!(policy.query_id(b"r1"), None);
+        assert_eq!(QueryIdPolicy::Sequential.query_id(b"r.1"), None);
+    }
+
+    #[test]
+    fn parser_with_duplicate_query_names_fails() {
+        use super::RenameDuplicates;
+        use crate::parser::Parser;
+
+        let names = vec![b"r1".to_vec(), b"r2".to_vec(), b"r1".to_vec(), b"r3".to_vec()];
+        let mut t_it = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()].into_iter();
+
+        let mut input: &[u8] = b"0 0\n3 1\n";
+        let got = Parser::new(&mut input, Some(&mut names.clone().into_iter()), Some(&mut t_it.clone()));
+        let err = got.err().unwrap();
+        let err = err.downcast_ref::<crate::errors::DuplicateQueryNameErr>().unwrap();
+        assert_eq!((err.name.as_str(), err.first, err.second), ("r1", 0, 2));
+
+        // Renamed duplicates keep the positions of the names
+        let mut input: &[u8] = b"0 0\n2 1\n3 1\n";
+        let mut q_it = RenameDuplicates::new(names.into_iter());
+        let parser = Parser::new(&mut input, Some(&mut q_it), Some(&mut t_it)).unwrap();
+        let got: Vec<(u32, Vec<u8>)> = parser.map(|x| (x.query_id.unwrap(), x.query_name.unwrap())).collect();
+        assert_eq!(got, vec![(0, b"r1".to_vec()), (2, b"r1_2".to_vec()), (3, b"r3".to_vec())]);
+    }
+
+    #[test]
+    fn rename_duplicates_skips_taken_names() {
+        use super::RenameDuplicates;
+
+        let names = vec![b"r1".to_vec(), b"r1_2".to_vec(), b"r1".to_vec(), b"r1_2".to_vec()];
+        let mut renamed = RenameDuplicates::new(names.into_iter());
+        let got: Vec<Vec<u8>> = renamed.by_ref().collect();
+        assert_eq!(got, vec![b"r1".to_vec(), b"r1_2".to_vec(), b"r1_3".to_vec(), b"r1_2_2".to_vec()]);
+        assert_eq!(renamed.renamed(), 2);
+    }
+
+    #[test]
+    fn parser_assigns_sequential_query_ids() {
+        use super::QueryIdPolicy;
+        use crate::parser::Parser;
+
+        let mut input: &[u8] = b"r3\t0\nr1\t1\nr3\t1\n";
+        let mut t_it = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()].into_iter();
+        let mut parser = Parser::new(&mut input, None::<&mut std::iter::Empty<Vec<u8>>>, Some(&mut t_it)).unwrap();
+        parser.set_query_id_policy(QueryIdPolicy::Sequential);
+
+        let ids: Vec<u32> = parser.by_ref().map(|x| x.query_id.unwrap()).collect();
+        assert_eq!(ids, vec![0, 1, 0]);
+        assert_eq!(parser.len(), 2);
+    }
+}
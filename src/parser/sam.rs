@@ -22,17 +22,26 @@
 use std::io::Read;
 
 use noodles_sam as sam;
-use noodles_sam::alignment::record::Flags;
 
 use crate::PseudoAln;
 
 type E = Box<dyn std::error::Error>;
 
+/// Read the target sequence names from the @SQ lines of a SAM header.
+pub fn read_sam_target_names<R: Read>(
+    conn: &mut R,
+) -> Result<Vec<Vec<u8>>, E> {
+    let mut reader = sam::io::reader::Builder::default().build_from_reader(conn)?;
+    let header = reader.read_header()?;
+    Ok(header.reference_sequences().iter().map(|x| x.0.to_vec()).collect())
+}
+
 /// Parse a line from a [SAM](https://samtools.github.io/hts-specs/SAMv1.pdf) file.
 ///
 /// Reads a pseudoalignment line stored in the *SAM* format.
 ///
-/// Returns the [pseudoalignment](PseudoAln) on the line.
+/// Returns the [pseudoalignment](PseudoAln) on the line. Unmapped records
+/// have empty `ones_names`.
 ///
 pub fn read_sam<R: Read>(
     conn: &mut R,
@@ -44,8 +53,9 @@ pub fn read_sam<R: Read>(
 
     let query_name = record.name().unwrap().to_vec();
 
-    if record.flags().is_ok() && *record.flags().as_ref().unwrap() == Flags::UNMAPPED {
-        return Ok(PseudoAln{query_id: None, ones: None, query_name: Some(query_name), ones_names: None });
+    // Unmapped mates may have the position of the other mate
+    if record.flags().is_ok_and(|flags| flags.is_unmapped()) {
+        return Ok(PseudoAln{query_id: None, ones: None, query_name: Some(query_name), ones_names: Some(Vec::new()) });
     }
 
     let target = record.reference_sequence_name().unwrap().to_vec();
@@ -80,4 +90,16 @@ mod tests {
 
         assert_eq!(got, expected);
     }
+
+    #[test]
+    fn read_sam_unmapped_mate() {
+        use crate::PseudoAln;
+        use super::read_sam;
+
+        // Unmapped mate placed at the position of the other mate
+        let data: Vec<u8> = b"ERR4035126.2\t69\tOZ038621.1\t4541508\t0\t*\t=\t4541508\t0\tAGTATTTAGT\tFJ<<JJFJAA".to_vec();
+        let expected = PseudoAln{ones_names: Some(vec![]), query_id: None, ones: None, query_name: Some("ERR4035126.2".as_bytes().to_vec()) };
+
+        assert_eq!(read_sam(&mut data.as_slice()).unwrap(), expected);
+    }
 }
@@ -0,0 +1,1055 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Read, transform, and write records in one call.
+//!
+//! The `X_from_read_to_write` functions each cover one combination of input,
+//! processing, and output. [Pipeline] composes the same parts instead:
+//!
+//! - The input is decoded with [Decoder](crate::decoder::Decoder) if it is
+//!   .ahda data and parsed with [Parser](crate::parser::Parser) otherwise.
+//!   [Pipeline::query_id_check], [Pipeline::skip_corrupt],
+//!   [Pipeline::max_hits], and [Pipeline::collapse] configure the decoder.
+//! - The records pass through the steps added with [Pipeline::filter],
+//!   [Pipeline::map], and [Pipeline::filter_map], in the order they were
+//!   added.
+//! - The records are sorted by query id if [Pipeline::sort_by_query_id] is
+//!   set.
+//! - The records are formatted with [Printer](crate::printer::Printer), or
+//!   encoded with [Encoder](crate::encoder::Encoder) if the output is
+//!   [Output::Ahda]. [Pipeline::run_split] writes them to one output per
+//!   target group instead.
+//!
+//! [Pipeline::run] buffers the input and output, calls the progress callback
+//! every [Pipeline::chunk_size] records, and with [Pipeline::threads] above 1
+//! reads the input on a separate thread.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::pipeline::Output;
+//! use ahda::pipeline::Pipeline;
+//! use ahda::{EncodeOpts, Format};
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+//! let input: &[u8] = b"0 0\n1 1 2\n2\n";
+//!
+//! // Encode the queries that align to something
+//! let mut encoded: Vec<u8> = Vec::new();
+//! let n_records = Pipeline::new()
+//!     .targets(targets)
+//!     .filter(|record| record.ones.as_ref().is_some_and(|x| !x.is_empty()))
+//!     .output(Output::Ahda(EncodeOpts::default()))
+//!     .run(&mut &input[..], &mut encoded)
+//!     .unwrap();
+//! assert_eq!(n_records, 2);
+//!
+//! // Decode them to Themisto format, dropping the first target
+//! let mut decoded: Vec<u8> = Vec::new();
+//! Pipeline::new()
+//!     .map(|mut record| { record.ones.as_mut().unwrap().retain(|x| *x > 0); record })
+//!     .output(Output::Text(Format::Themisto))
+//!     .run(&mut encoded.as_slice(), &mut decoded)
+//!     .unwrap();
+//! assert_eq!(decoded, b"0\n1 1 2\n");
+//! ```
+//!
+
+use crate::EncodeOpts;
+use crate::Format;
+use crate::PseudoAln;
+use crate::configure_encoder;
+use crate::encode_to_zstd_container;
+use crate::decoder::CorruptBlocks;
+use crate::decoder::QueryIdCheck;
+use crate::decoder::sorted::RankSorted;
+use crate::headers::file::FileFlags;
+use crate::headers::file::FileHeader;
+use crate::headers::file::build_file_header_and_flags;
+use crate::max_hits::MaxHits;
+use crate::parser::query_ids::QueryIdPolicy;
+use crate::printer::Program;
+use crate::printer::TargetSelection;
+use crate::read_target_groups;
+
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::sync_channel;
+
+type E = Box<dyn std::error::Error>;
+
+/// Where [Pipeline] writes the records.
+#[non_exhaustive]
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Output {
+    /// Plain text in this format.
+    Text(Format),
+    /// .ahda data encoded with these options.
+    Ahda(EncodeOpts),
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output::Text(Format::AhdaTSV)
+    }
+}
+
+/// A step that transforms a record or drops it.
+type Step<'a> = Box<dyn FnMut(PseudoAln) -> Option<PseudoAln> + 'a>;
+
+/// Input settings that the reading thread needs.
+struct InputOpts {
+    targets: Option<Vec<Vec<u8>>>,
+    queries: Option<Vec<Vec<u8>>>,
+    format: Option<Format>,
+    sample_name: Vec<u8>,
+    buffer_size: usize,
+    encode: Option<EncodeOpts>,
+    check: QueryIdCheck,
+    skip_corrupt: bool,
+    max_hits: Option<MaxHits>,
+    collapse: Option<Vec<u8>>,
+    // Decode only the fields that are printed in this format
+    print_only: Option<Format>,
+    keep_query_ids: bool,
+    keep_target_ids: bool,
+}
+
+/// What [Pipeline::run_with_summary] wrote and found in the input.
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of records written.
+    pub n_records: usize,
+    /// `n_queries` stored in .ahda input if it was extended to fit the query
+    /// ids, see [Decoder::original_n_queries](crate::decoder::Decoder::original_n_queries).
+    pub original_n_queries: Option<u32>,
+    /// Number of records that exceeded the cap set with [Pipeline::max_hits].
+    pub records_capped: usize,
+    /// Blocks skipped with [Pipeline::skip_corrupt].
+    pub corrupt: CorruptBlocks,
+}
+
+/// Header and flags of the input and whether its records have query names.
+struct InputMeta {
+    header: FileHeader,
+    flags: FileFlags,
+    have_queries: bool,
+}
+
+/// Messages from the reading thread.
+enum Message {
+    Meta(Box<InputMeta>),
+    Records(Vec<Result<PseudoAln, String>>),
+}
+
+/// Steps and output settings, used on the thread that writes the output.
+struct Sink<'a> {
+    steps: Vec<Step<'a>>,
+    selection: TargetSelection,
+    program: Option<Program>,
+    sort: bool,
+    output: Output,
+    chunk_size: usize,
+    progress: Option<Box<dyn FnMut(usize) + 'a>>,
+}
+
+/// Builder that reads, transforms, and writes records, see the [module documentation](self).
+pub struct Pipeline<'a> {
+    input: InputOpts,
+    sink: Sink<'a>,
+    n_threads: usize,
+}
+
+impl Default for Pipeline<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Pipeline<'a> {
+    /// Create a pipeline that converts any input to [Format::AhdaTSV].
+    pub fn new() -> Self {
+        Pipeline{
+            input: InputOpts{
+                targets: None, queries: None, format: None, sample_name: Vec::new(), buffer_size: crate::output::DEFAULT_BUFFER_SIZE, encode: None,
+                check: QueryIdCheck::default(), skip_corrupt: false, max_hits: None, collapse: None, print_only: None, keep_query_ids: false, keep_target_ids: false,
+            },
+            sink: Sink{ steps: Vec::new(), selection: TargetSelection::default(), program: None, sort: false, output: Output::default(), chunk_size: crate::CONVERT_CHUNK_SIZE, progress: None },
+            n_threads: 1,
+        }
+    }
+
+    /// Names of the alignment targets in plain text input.
+    ///
+    /// Required for formats that don't list the targets, see
+    /// [Parser](crate::parser::Parser). Ignored for .ahda input.
+    pub fn targets(
+        mut self,
+        targets: Vec<Vec<u8>>,
+    ) -> Self {
+        self.input.targets = Some(targets);
+        self
+    }
+
+    /// Names of the queries in plain text input, in the order of the query file.
+    ///
+    /// Ignored for .ahda input.
+    pub fn queries(
+        mut self,
+        queries: Vec<Vec<u8>>,
+    ) -> Self {
+        self.input.queries = Some(queries);
+        self
+    }
+
+    /// Parse plain text input in `format` instead of guessing it.
+    pub fn input_format(
+        mut self,
+        format: Format,
+    ) -> Self {
+        self.input.format = Some(format);
+        self
+    }
+
+    /// Sample name of plain text input written to plain text output.
+    ///
+    /// Encoded output uses [EncodeOpts::accession] instead.
+    pub fn sample_name(
+        mut self,
+        sample_name: &[u8],
+    ) -> Self {
+        self.input.sample_name = sample_name.to_vec();
+        self
+    }
+
+    /// Handle query ids that don't fit in `n_queries` of .ahda input according to `check`.
+    ///
+    /// See [Decoder::set_query_id_check](crate::decoder::Decoder::set_query_id_check).
+    /// [Summary::original_n_queries] tells if `n_queries` was extended.
+    pub fn query_id_check(
+        mut self,
+        check: QueryIdCheck,
+    ) -> Self {
+        self.input.check = check;
+        self
+    }
+
+    /// Log and skip the blocks of .ahda input that can't be unpacked.
+    ///
+    /// See [Decoder::set_skip_corrupt](crate::decoder::Decoder::set_skip_corrupt).
+    /// The skipped blocks are listed in [Summary::corrupt].
+    pub fn skip_corrupt(
+        mut self,
+        skip_corrupt: bool,
+    ) -> Self {
+        self.input.skip_corrupt = skip_corrupt;
+        self
+    }
+
+    /// Cap the number of targets per record of .ahda input, see [MaxHits].
+    ///
+    /// The cap is applied before the steps. [Summary::records_capped] counts
+    /// the records that exceeded it.
+    pub fn max_hits(
+        mut self,
+        max_hits: MaxHits,
+    ) -> Self {
+        self.input.max_hits = Some(max_hits);
+        self
+    }
+
+    /// Collapse the targets of .ahda input to the target groups at `level`.
+    ///
+    /// See [Decoder::collapse](crate::decoder::Decoder::collapse). The steps
+    /// and the output see the groups as the targets.
+    pub fn collapse(
+        mut self,
+        level: &[u8],
+    ) -> Self {
+        self.input.collapse = Some(level.to_vec());
+        self
+    }
+
+    /// Write the records in the order of their query ids, see [RankSorted].
+    pub fn sort_by_query_id(
+        mut self,
+    ) -> Self {
+        self.sink.sort = true;
+        self
+    }
+
+    /// Record `program` in the header of plain text output, see [Program].
+    pub fn program(
+        mut self,
+        program: Option<Program>,
+    ) -> Self {
+        self.sink.program = program;
+        self
+    }
+
+    /// Keep only the records for which `f` returns true.
+    pub fn filter<F: FnMut(&PseudoAln) -> bool + 'a>(
+        mut self,
+        mut f: F,
+    ) -> Self {
+        self.sink.steps.push(Box::new(move |record| f(&record).then_some(record)));
+        self
+    }
+
+    /// Replace each record with the output of `f`.
+    pub fn map<F: FnMut(PseudoAln) -> PseudoAln + 'a>(
+        mut self,
+        mut f: F,
+    ) -> Self {
+        self.sink.steps.push(Box::new(move |record| Some(f(record))));
+        self
+    }
+
+    /// Replace each record with the output of `f`, dropping it if `f` returns None.
+    pub fn filter_map<F: FnMut(PseudoAln) -> Option<PseudoAln> + 'a>(
+        mut self,
+        f: F,
+    ) -> Self {
+        self.sink.steps.push(Box::new(f));
+        self
+    }
+
+    /// Only write the targets in `selection`, see [TargetSelection].
+    ///
+    /// Only supported for [Output::Text].
+    pub fn select_targets(
+        mut self,
+        selection: TargetSelection,
+    ) -> Self {
+        self.sink.selection = selection;
+        self
+    }
+
+    /// Write the records to `output`, defaults to [Format::AhdaTSV].
+    pub fn output(
+        mut self,
+        output: Output,
+    ) -> Self {
+        self.sink.output = output;
+        self
+    }
+
+    /// Read the input on a separate thread if `n_threads` is above 1.
+    ///
+    /// Decoding or parsing the input then runs at the same time as the steps
+    /// and the output. Errors from the input are returned as their message.
+    pub fn threads(
+        mut self,
+        n_threads: usize,
+    ) -> Self {
+        self.n_threads = n_threads.max(1);
+        self
+    }
+
+    /// Size of the buffers around the input and output connections in bytes.
+    pub fn buffer_size(
+        mut self,
+        buffer_size: usize,
+    ) -> Self {
+        self.input.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// Number of records between flushing plain text output and calling the progress callback.
+    ///
+    /// Defaults to [CONVERT_CHUNK_SIZE](crate::CONVERT_CHUNK_SIZE).
+    pub fn chunk_size(
+        mut self,
+        chunk_size: usize,
+    ) -> Self {
+        self.sink.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Call `f` with the number of records written so far after each chunk and at the end.
+    pub fn on_progress<F: FnMut(usize) + 'a>(
+        mut self,
+        f: F,
+    ) -> Self {
+        self.sink.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Read the records from `conn_in`, transform them, and write them to `conn_out`.
+    ///
+    /// Returns the number of records written.
+    ///
+    /// ## Errors and panics
+    ///
+    /// Errors if the input can't be decoded or parsed, the output can't be
+    /// written, or with
+    /// [TargetSelectionNotSupportedErr](crate::errors::TargetSelectionNotSupportedErr)
+    /// if targets are selected for [Output::Ahda]. Plain text input errors
+    /// with [UnknownTargetGroupErr](crate::errors::UnknownTargetGroupErr) if
+    /// [collapsed](Pipeline::collapse) and with
+    /// [PlainTextMaxHitsErr](crate::errors::PlainTextMaxHitsErr) if
+    /// [capped](Pipeline::max_hits). Plain text output before the failing
+    /// record has already been written to `conn_out`.
+    ///
+    pub fn run<R: Read + Send, W: Write>(
+        self,
+        conn_in: &mut R,
+        conn_out: &mut W,
+    ) -> Result<usize, E> {
+        Ok(self.run_with_summary(conn_in, conn_out)?.n_records)
+    }
+
+    /// Same as [run](Pipeline::run) but returns a [Summary] of the input.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::pipeline::Output;
+    /// use ahda::pipeline::Pipeline;
+    /// use ahda::{encode_to_write, EncodeOpts, Format, PseudoAln};
+    /// use ahda::max_hits::MaxHits;
+    /// use ahda::max_hits::MaxHitsAction;
+    ///
+    /// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+    /// let data = vec![
+    ///     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![0, 1, 2]), query_name: None },
+    ///     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![1]), query_name: None },
+    /// ];
+    /// let mut bytes: Vec<u8> = Vec::new();
+    /// encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+    ///
+    /// let mut output: Vec<u8> = Vec::new();
+    /// let summary = Pipeline::new()
+    ///     .max_hits(MaxHits{ max_hits: 2, action: MaxHitsAction::Drop })
+    ///     .sort_by_query_id()
+    ///     .output(Output::Text(Format::Themisto))
+    ///     .run_with_summary(&mut bytes.as_slice(), &mut output)
+    ///     .unwrap();
+    ///
+    /// assert_eq!(summary.n_records, 2);
+    /// assert_eq!(summary.records_capped, 1);
+    /// assert_eq!(output, b"0 1\n1\n");
+    /// ```
+    ///
+    /// ## Errors and panics
+    /// Errors like [run](Pipeline::run).
+    ///
+    pub fn run_with_summary<R: Read + Send, W: Write>(
+        self,
+        conn_in: &mut R,
+        conn_out: &mut W,
+    ) -> Result<Summary, E> {
+        let Pipeline{ mut input, mut sink, n_threads } = self;
+        if let Output::Ahda(opts) = &mut sink.output {
+            if !sink.selection.is_empty() {
+                return Err(Box::new(crate::errors::TargetSelectionNotSupportedErr{}))
+            }
+            if let Some(level) = opts.zstd_level.take() {
+                let pipeline = Pipeline{ input, sink, n_threads };
+                let mut summary = Summary::default();
+                encode_to_zstd_container(conn_out, level, |mut conn| { summary = pipeline.run_with_summary(conn_in, &mut conn)?; Ok(()) })?;
+                return Ok(summary)
+            }
+            input.encode = Some(opts.clone());
+        }
+        if let Output::Text(format) = &sink.output {
+            if sink.steps.is_empty() {
+                input.print_only = Some(format.clone());
+            }
+        }
+        input.keep_query_ids = sink.sort;
+
+        let mut conn_out = BufWriter::with_capacity(input.buffer_size, conn_out);
+        let summary = if n_threads > 1 {
+            let input = &input;
+            let chunk_size = sink.chunk_size;
+            std::thread::scope(|s| {
+                let (tx, rx) = sync_channel::<Result<Message, String>>(4);
+                let reader = s.spawn(move || {
+                    let ret = read_records(conn_in, input, |meta, records| {
+                        tx.send(Ok(Message::Meta(Box::new(meta))))?;
+                        let mut chunk: Vec<Result<PseudoAln, String>> = Vec::with_capacity(chunk_size);
+                        for record in records {
+                            chunk.push(record.map_err(|e| e.to_string()));
+                            if chunk.len() == chunk_size {
+                                tx.send(Ok(Message::Records(std::mem::take(&mut chunk))))?;
+                            }
+                        }
+                        tx.send(Ok(Message::Records(chunk)))?;
+                        Ok(())
+                    });
+                    match ret {
+                        Ok(summary) => Some(summary),
+                        Err(e) => {
+                            // Fails only if the output side already stopped on its own error
+                            let _ = tx.send(Err(e.to_string()));
+                            None
+                        },
+                    }
+                });
+                let n_records = sink.write_from_channel(rx, &mut conn_out)?;
+                let summary = reader.join().unwrap_or_else(|e| std::panic::resume_unwind(e)).unwrap_or_default();
+                Ok::<Summary, E>(Summary{ n_records, ..summary })
+            })?
+        } else {
+            let mut n_records = 0;
+            let summary = read_records(conn_in, &input, |meta, records| {
+                n_records = sink.write_records(meta, records, &mut conn_out)?;
+                Ok(())
+            })?;
+            Summary{ n_records, ..summary }
+        };
+        conn_out.flush()?;
+        log::info!("pipeline wrote {} records", summary.n_records);
+
+        Ok(summary)
+    }
+
+    /// Read the records from `conn_in`, transform them, and write them to one output per target group.
+    ///
+    /// Reads the target groups from the tab-separated table in `groups_table`,
+    /// see [read_target_groups], and splits the records by the groups at the
+    /// first level in the table. `open_output` is called with the name of each
+    /// group, in the order that the groups appear in the table, before writing
+    /// any records. The records are formatted in the [Output::Text] format.
+    ///
+    /// A record is written to each group that contains at least one of its
+    /// targets, with all of its alignments. Records that don't align to any
+    /// target are not written. The input is read once, on the calling thread.
+    ///
+    /// Returns the number of records written to each output.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::pipeline::Output;
+    /// use ahda::pipeline::Pipeline;
+    /// use ahda::{encode_to_write, EncodeOpts, Format, PseudoAln};
+    ///
+    /// let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+    /// let data = vec![
+    ///     PseudoAln{ ones: Some(vec![0, 1]), ones_names: None, query_id: Some(0), query_name: None },
+    ///     PseudoAln{ ones: Some(vec![1, 2]), ones_names: None, query_id: Some(1), query_name: None },
+    ///     PseudoAln{ ones: Some(vec![]), ones_names: None, query_id: Some(2), query_name: None },
+    /// ];
+    /// let mut input: Vec<u8> = Vec::new();
+    /// encode_to_write(&targets, &[], &data, &mut input, EncodeOpts::default()).unwrap();
+    ///
+    /// let mut table: &[u8] = b"target\tspecies\nchr.fasta\tE. coli\nplasmid.fasta\tE. coli\nvirus.fasta\tPhage\n";
+    /// let mut groups: Vec<Vec<u8>> = Vec::new();
+    /// let counts = Pipeline::new().output(Output::Text(Format::Themisto)).run_split(&mut input.as_slice(), &mut table, |group| {
+    ///     // Open a file named after `group` here
+    ///     groups.push(group.to_vec());
+    ///     Ok(std::io::sink())
+    /// }).unwrap();
+    ///
+    /// assert_eq!(groups, vec![b"E. coli".to_vec(), b"Phage".to_vec()]);
+    /// assert_eq!(counts, vec![2, 1]);
+    /// ```
+    ///
+    /// ## Errors and panics
+    ///
+    /// Errors with [SplitNotSupportedErr](crate::errors::SplitNotSupportedErr)
+    /// for [Output::Ahda], if the table can't be read, see
+    /// [read_target_groups], or if it has no levels, with
+    /// [InvalidTargetGroupsErr](crate::errors::InvalidTargetGroupsErr).
+    /// Errors from `open_output` are returned as they are, and other errors
+    /// like [run](Pipeline::run).
+    ///
+    pub fn run_split<R: Read, T: Read, W: Write, F: FnMut(&[u8]) -> Result<W, E>>(
+        self,
+        conn_in: &mut R,
+        groups_table: &mut T,
+        mut open_output: F,
+    ) -> Result<Vec<usize>, E> {
+        let Pipeline{ mut input, mut sink, .. } = self;
+        let format = match &sink.output {
+            Output::Text(format) => format.clone(),
+            Output::Ahda(_) => return Err(Box::new(crate::errors::SplitNotSupportedErr{})),
+        };
+        if sink.steps.is_empty() {
+            input.print_only = Some(format.clone());
+        }
+        input.keep_query_ids = sink.sort;
+        // Target ids are always needed to find the groups
+        input.keep_target_ids = true;
+
+        let mut counts: Vec<usize> = Vec::new();
+        read_records(conn_in, &input, |meta, records| {
+            counts = sink.write_split(meta, records, format, groups_table, &mut open_output)?;
+            Ok(())
+        })?;
+        log::info!("pipeline wrote {} records to {} groups", counts.iter().sum::<usize>(), counts.len());
+
+        Ok(counts)
+    }
+}
+
+impl Sink<'_> {
+    /// Write the records sent from the reading thread.
+    fn write_from_channel<W: Write>(
+        &mut self,
+        rx: Receiver<Result<Message, String>>,
+        conn_out: &mut W,
+    ) -> Result<usize, E> {
+        let meta = match rx.recv() {
+            Ok(Ok(Message::Meta(meta))) => meta,
+            Ok(Err(e)) => return Err(e.into()),
+            _ => unreachable!("the reading thread sends the header first"),
+        };
+        let mut error: Option<E> = None;
+        let mut records = rx.iter().map_while(|message| match message {
+            Ok(Message::Records(chunk)) => Some(chunk),
+            Ok(Message::Meta(_)) => unreachable!("the reading thread sends the header once"),
+            Err(e) => { error = Some(e.into()); None },
+        }).flatten().map(|record| record.map_err(|e| e.into()));
+        let n_records = self.write_records(*meta, &mut records, conn_out)?;
+        drop(records);
+
+        if let Some(e) = error {
+            return Err(e)
+        }
+        Ok(n_records)
+    }
+
+    /// Apply the steps to `records` and write them to `conn_out`.
+    fn write_records<W: Write>(
+        &mut self,
+        meta: InputMeta,
+        records: &mut dyn Iterator<Item = Result<PseudoAln, E>>,
+        conn_out: &mut W,
+    ) -> Result<usize, E> {
+        let mut n_records: usize = 0;
+        let chunk_size = self.chunk_size;
+        let steps = &mut self.steps;
+        let progress = &mut self.progress;
+        let mut error: Option<E> = None;
+
+        // Stop at the first error and count the records that pass the steps
+        let records = records.map_while(|record| record.map_err(|e| error = Some(e)).ok()).filter_map(|record| {
+            let record = steps.iter_mut().try_fold(record, |record, step| step(record))?;
+            n_records += 1;
+            if n_records.is_multiple_of(chunk_size) {
+                if let Some(f) = progress.as_mut() {
+                    f(n_records);
+                }
+            }
+            Some(record)
+        });
+        let mut records: Box<dyn Iterator<Item = PseudoAln> + '_> = if self.sort { Box::new(RankSorted::new(records)) } else { Box::new(records) };
+
+        match &self.output {
+            Output::Text(format) => {
+                let mut no_records = std::iter::empty::<PseudoAln>();
+                let mut printer = crate::printer::Printer::new_from_header_and_flags(&mut no_records, meta.header, meta.flags, format.clone());
+                if !self.selection.is_empty() {
+                    printer.select_targets(&self.selection)?;
+                }
+                printer.set_program(self.program.clone());
+
+                let mut chunk: Vec<u8> = printer.print_header().unwrap_or_default();
+                let mut in_chunk: usize = 0;
+                for record in records.by_ref() {
+                    printer.format_record(&record, &mut chunk)?;
+                    in_chunk += 1;
+                    if in_chunk == chunk_size {
+                        conn_out.write_all(&chunk)?;
+                        conn_out.flush()?;
+                        chunk.clear();
+                        in_chunk = 0;
+                    }
+                }
+                conn_out.write_all(&chunk)?;
+            },
+            Output::Ahda(opts) => {
+                let mut records: Box<dyn Iterator<Item = PseudoAln> + '_> = match &opts.sort_input {
+                    Some(sort_opts) => Box::new(crate::encoder::sorted::ExternalSort::new(records.by_ref(), sort_opts)?),
+                    None => Box::new(records.by_ref()),
+                };
+                let mut encoder = crate::encoder::Encoder::new(&mut records, &meta.flags.target_names, &opts.accession, meta.header.n_queries as usize);
+                configure_encoder(&mut encoder, opts, meta.have_queries)?;
+
+                conn_out.write_all(&encoder.encode_file_header_and_flags()?)?;
+                for block in encoder.by_ref() {
+                    conn_out.write_all(&block?)?;
+                }
+            },
+        }
+        drop(records);
+
+        if let Some(e) = error {
+            return Err(e)
+        }
+        // The last chunk, unless it was full
+        if !n_records.is_multiple_of(chunk_size) || n_records == 0 {
+            if let Some(f) = self.progress.as_mut() {
+                f(n_records);
+            }
+        }
+        Ok(n_records)
+    }
+
+    /// Apply the steps to `records` and write them to the outputs of their target groups.
+    fn write_split<T: Read, W: Write, F: FnMut(&[u8]) -> Result<W, E>>(
+        &mut self,
+        meta: InputMeta,
+        records: &mut dyn Iterator<Item = Result<PseudoAln, E>>,
+        format: Format,
+        groups_table: &mut T,
+        open_output: &mut F,
+    ) -> Result<Vec<usize>, E> {
+        let groups = read_target_groups(&meta.flags.target_names, groups_table)?.into_iter().next().ok_or(crate::errors::InvalidTargetGroupsErr{})?;
+
+        let mut no_records = std::iter::empty::<PseudoAln>();
+        let mut printer = crate::printer::Printer::new_from_header_and_flags(&mut no_records, meta.header, meta.flags, format);
+        if !self.selection.is_empty() {
+            printer.select_targets(&self.selection)?;
+        }
+        printer.set_program(self.program.clone());
+        let mut conns_out: Vec<BufWriter<W>> = Vec::with_capacity(groups.group_names.len());
+        for group in groups.group_names.iter() {
+            let mut conn_out = BufWriter::new(open_output(group)?);
+            if let Some(header) = printer.print_header() {
+                conn_out.write_all(&header)?;
+            }
+            conns_out.push(conn_out);
+        }
+
+        let steps = &mut self.steps;
+        let mut error: Option<E> = None;
+        let records = records.map_while(|record| record.map_err(|e| error = Some(e)).ok()).filter_map(|record| {
+            steps.iter_mut().try_fold(record, |record, step| step(record))
+        });
+        let records: Box<dyn Iterator<Item = PseudoAln> + '_> = if self.sort { Box::new(RankSorted::new(records)) } else { Box::new(records) };
+
+        let mut counts: Vec<usize> = vec![0; conns_out.len()];
+        let mut n_records: usize = 0;
+        let mut line: Vec<u8> = Vec::new();
+        let mut hits: Vec<u32> = Vec::new();
+        for record in records {
+            hits.clear();
+            hits.extend(record.ones.iter().flatten().map(|target| groups.membership[*target as usize]));
+            if hits.is_empty() {
+                continue
+            }
+            hits.sort_unstable();
+            hits.dedup();
+
+            line.clear();
+            printer.format_record(&record, &mut line)?;
+            for group in hits.iter() {
+                conns_out[*group as usize].write_all(&line)?;
+                counts[*group as usize] += 1;
+            }
+            n_records += 1;
+            if n_records.is_multiple_of(self.chunk_size) {
+                if let Some(f) = self.progress.as_mut() {
+                    f(n_records);
+                }
+            }
+        }
+
+        if let Some(e) = error {
+            return Err(e)
+        }
+        for conn_out in conns_out.iter_mut() {
+            conn_out.flush()?;
+        }
+        if !n_records.is_multiple_of(self.chunk_size) || n_records == 0 {
+            if let Some(f) = self.progress.as_mut() {
+                f(n_records);
+            }
+        }
+        Ok(counts)
+    }
+}
+
+/// Skip decoding the fields of `decoder` that `format` doesn't print.
+fn skip_unprinted_fields<R: Read>(
+    decoder: &mut crate::decoder::Decoder<'_, R>,
+    format: &Format,
+    keep_query_ids: bool,
+    keep_target_ids: bool,
+) {
+    match format {
+        Format::Themisto => {
+            decoder.fill_target_names(false);
+            decoder.fill_query_name(false);
+        },
+        Format::Fulgor | Format::Bifrost | Format::SAM => {
+            decoder.fill_target_names(false);
+            if !keep_query_ids {
+                decoder.fill_query_id(false);
+            }
+        },
+        Format::Metagraph => {
+            if !keep_target_ids {
+                decoder.fill_target_ids(false);
+            }
+        },
+        Format::AhdaTSV => {
+            decoder.fill_target_names(false);
+        },
+    }
+}
+
+/// Open `conn_in` as .ahda or plain text data and call `f` with its records.
+///
+/// Returns what the decoder found in the input, with `n_records` left at 0.
+fn read_records<R: Read, F: FnOnce(InputMeta, &mut dyn Iterator<Item = Result<PseudoAln, E>>) -> Result<(), E>>(
+    conn_in: &mut R,
+    opts: &InputOpts,
+    f: F,
+) -> Result<Summary, E> {
+    let mut conn_in = BufReader::with_capacity(opts.buffer_size, conn_in);
+    let mut magic: Vec<u8> = Vec::with_capacity(crate::MAGIC.len());
+    conn_in.by_ref().take(crate::MAGIC.len() as u64).read_to_end(&mut magic)?;
+    let is_ahda = magic == crate::MAGIC;
+    let mut conn_in = Cursor::new(magic).chain(conn_in);
+
+    if is_ahda {
+        let mut decoder = crate::decoder::Decoder::try_new(&mut conn_in)?;
+        decoder.set_query_id_check(opts.check.clone());
+        decoder.set_skip_corrupt(opts.skip_corrupt);
+        if let Some(max_hits) = opts.max_hits {
+            decoder.set_max_hits(max_hits);
+        }
+        let (header, flags) = match &opts.collapse {
+            Some(level) => decoder.collapse(level)?,
+            None => (decoder.file_header().clone(), decoder.file_flags().clone()),
+        };
+        if let Some(format) = &opts.print_only {
+            skip_unprinted_fields(&mut decoder, format, opts.keep_query_ids, opts.keep_target_ids);
+        }
+
+        let have_queries = header.fields_present & crate::MASK_QUERIES != 0;
+        let mut records = std::iter::from_fn(|| decoder.try_next());
+        f(InputMeta{ header, flags, have_queries }, &mut records)?;
+        return Ok(Summary{ n_records: 0, original_n_queries: decoder.original_n_queries(), records_capped: decoder.records_capped(), corrupt: decoder.corrupt_blocks().clone() })
+    }
+
+    if opts.collapse.is_some() {
+        // Plain text input has no target groups
+        return Err(Box::new(crate::errors::UnknownTargetGroupErr{}))
+    }
+    if opts.max_hits.is_some() {
+        return Err(Box::new(crate::errors::PlainTextMaxHitsErr{}))
+    }
+
+    let mut targets = opts.targets.clone().map(|x| x.into_iter());
+    let mut queries = opts.queries.clone().map(|x| x.into_iter());
+    let format = opts.format.clone().or(opts.encode.as_ref().and_then(|x| x.format.clone()));
+    let mut reader = match format {
+        Some(format) => crate::parser::Parser::new_with_format(&mut conn_in, queries.as_mut(), targets.as_mut(), format)?,
+        None => crate::parser::Parser::new(&mut conn_in, queries.as_mut(), targets.as_mut())?,
+    };
+
+    let mut have_queries = opts.queries.is_some();
+    let sample_name = match &opts.encode {
+        Some(encode) => {
+            // Same as encode_from_read_to_write
+            have_queries = have_queries || (encode.query_ids != QueryIdPolicy::FromList && reader.format.fields_present() & crate::MASK_QUERIES != 0);
+            reader.set_query_id_policy(encode.query_ids.clone());
+            reader.fill_target_names(encode.encode_target_names);
+            reader.fill_query_name(encode.encode_query_names && have_queries && !encode.rename_queries);
+            if !have_queries && reader.format != Format::Metagraph && reader.format != Format::Themisto && reader.format != Format::AhdaTSV {
+                return Err(Box::new(crate::errors::NeedQueryNamesErr{ format: reader.format }))
+            }
+            encode.accession.clone()
+        },
+        None => {
+            reader.fill_query_name(have_queries);
+            opts.sample_name.clone()
+        },
+    };
+
+    let (header, flags) = build_file_header_and_flags(&reader.get_targets().unwrap(), reader.len(), &sample_name, &crate::compression::MetadataCompression::default())?;
+    let mut records = std::iter::from_fn(|| reader.try_next());
+    f(InputMeta{ header, flags, have_queries }, &mut records)?;
+    Ok(Summary::default())
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn pipeline_threads_match_single_thread() {
+        use super::Output;
+        use super::Pipeline;
+        use crate::encode_to_write;
+        use crate::simulate::Simulator;
+        use crate::EncodeOpts;
+        use crate::Format;
+        use crate::PseudoAln;
+
+        let mut simulator = Simulator::new(1000, 16, 0.2, 3);
+        let targets = simulator.targets();
+        let data: Vec<PseudoAln> = simulator.by_ref().collect();
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+
+        let mut expected: Vec<u8> = Vec::new();
+        let mut expected_progress: Vec<usize> = Vec::new();
+        let n_expected = Pipeline::new()
+            .filter(|x| x.query_id.unwrap() % 3 != 0)
+            .output(Output::Text(Format::Themisto))
+            .chunk_size(100)
+            .on_progress(|n| expected_progress.push(n))
+            .run(&mut bytes.as_slice(), &mut expected)
+            .unwrap();
+
+        let mut got: Vec<u8> = Vec::new();
+        let mut got_progress: Vec<usize> = Vec::new();
+        let n_got = Pipeline::new()
+            .filter(|x| x.query_id.unwrap() % 3 != 0)
+            .output(Output::Text(Format::Themisto))
+            .chunk_size(100)
+            .threads(2)
+            .on_progress(|n| got_progress.push(n))
+            .run(&mut bytes.as_slice(), &mut got)
+            .unwrap();
+
+        assert_eq!(n_expected, 666);
+        assert_eq!(n_got, n_expected);
+        assert_eq!(got, expected);
+        assert_eq!(got_progress, expected_progress);
+        assert_eq!(got_progress.last(), Some(&666));
+    }
+
+    #[test]
+    fn pipeline_reports_input_errors() {
+        use super::Output;
+        use super::Pipeline;
+        use crate::EncodeOpts;
+        use crate::printer::TargetSelection;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let input: &[u8] = b"0 0\nnot a record\n";
+
+        for n_threads in [1, 2] {
+            let mut output: Vec<u8> = Vec::new();
+            let got = Pipeline::new().targets(targets.clone()).threads(n_threads).output(Output::Ahda(EncodeOpts::default())).run(&mut &input[..], &mut output);
+            assert!(got.is_err());
+        }
+
+        let selection = TargetSelection{ keep: Some(vec![b"chr.fasta".to_vec()]), rename: Vec::new() };
+        let mut output: Vec<u8> = Vec::new();
+        let got = Pipeline::new().targets(targets).select_targets(selection).output(Output::Ahda(EncodeOpts::default())).run(&mut &input[..], &mut output);
+        assert!(got.is_err());
+    }
+
+    #[test]
+    fn pipeline_configures_the_decoder() {
+        use super::Output;
+        use super::Pipeline;
+        use crate::decoder::QueryIdCheck;
+        use crate::encode_to_write;
+        use crate::encoder::Encoder;
+        use crate::read_target_groups;
+        use crate::EncodeOpts;
+        use crate::Format;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+        let data = vec![
+            PseudoAln{ ones: Some(vec![1, 2]), ones_names: None, query_id: Some(1), query_name: None },
+            PseudoAln{ ones: Some(vec![0, 1]), ones_names: None, query_id: Some(0), query_name: None },
+        ];
+        let mut table: &[u8] = b"target\tspecies\nchr.fasta\tE. coli\nplasmid.fasta\tE. coli\nvirus.fasta\tPhage\n";
+        let opts = EncodeOpts{ accession: b"sample".to_vec(), target_groups: read_target_groups(&targets, &mut table).unwrap(), ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+
+        let mut got: Vec<u8> = Vec::new();
+        Pipeline::new().collapse(b"species").sort_by_query_id().output(Output::Text(Format::Metagraph)).run(&mut bytes.as_slice(), &mut got).unwrap();
+        assert_eq!(got, b"0\tsample.1\tE. coli\n1\tsample.2\tE. coli:Phage\n".to_vec());
+
+        let mut got: Vec<u8> = Vec::new();
+        let res = Pipeline::new().collapse(b"genus").run(&mut bytes.as_slice(), &mut got).unwrap_err();
+        assert!(res.downcast_ref::<crate::errors::UnknownTargetGroupErr>().is_some());
+
+        // Query id 3 in a file that claims to have 2 queries
+        let mut records = vec![PseudoAln{ ones_names: None, query_id: Some(3), ones: Some(vec![1]), query_name: None }].into_iter();
+        let mut encoder = Encoder::new(&mut records, &targets, b"sample", 2);
+        let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+        encoder.for_each(|block| bytes.extend(block.unwrap()));
+
+        let mut got: Vec<u8> = Vec::new();
+        let summary = Pipeline::new().query_id_check(QueryIdCheck::Extend).output(Output::Text(Format::Themisto)).run_with_summary(&mut bytes.as_slice(), &mut got).unwrap();
+        assert_eq!(summary.original_n_queries, Some(2));
+        assert_eq!(got, b"3 1\n");
+
+        let mut got: Vec<u8> = Vec::new();
+        assert!(Pipeline::new().query_id_check(QueryIdCheck::Strict).run(&mut bytes.as_slice(), &mut got).is_err());
+    }
+
+    #[test]
+    fn pipeline_run_split_writes_each_group() {
+        use super::Output;
+        use super::Pipeline;
+        use crate::encode_to_write;
+        use crate::EncodeOpts;
+        use crate::Format;
+        use crate::PseudoAln;
+
+        use std::cell::RefCell;
+        use std::io::Cursor;
+        use std::io::Write;
+        use std::rc::Rc;
+
+        // Output that can be read after the function returns
+        struct Shared(Rc<RefCell<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.borrow_mut().write(buf) }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+        let data = vec![
+            PseudoAln{ ones: Some(vec![0, 1]), ones_names: None, query_id: Some(0), query_name: None },
+            PseudoAln{ ones: Some(vec![1, 2]), ones_names: None, query_id: Some(1), query_name: None },
+            PseudoAln{ ones: Some(vec![]), ones_names: None, query_id: Some(2), query_name: None },
+            PseudoAln{ ones: Some(vec![2]), ones_names: None, query_id: Some(3), query_name: None },
+        ];
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+
+        // Groups in the table that no query aligns to get an empty output
+        let table = b"target\tspecies\tgenus\nchr.fasta\tE. coli\tEscherichia\nplasmid.fasta\tE. coli\tEscherichia\nvirus.fasta\tPhage\tPhage\n";
+        let mut groups: Vec<Vec<u8>> = Vec::new();
+        let mut outputs: Vec<Rc<RefCell<Vec<u8>>>> = Vec::new();
+        let counts = Pipeline::new().output(Output::Text(Format::Bifrost)).run_split(&mut Cursor::new(bytes.clone()), &mut table.as_slice(), |group| {
+            groups.push(group.to_vec());
+            outputs.push(Rc::new(RefCell::new(Vec::new())));
+            Ok(Shared(outputs.last().unwrap().clone()))
+        }).unwrap();
+
+        assert_eq!(counts, vec![2, 2]);
+        assert_eq!(groups, vec![b"E. coli".to_vec(), b"Phage".to_vec()]);
+        assert_eq!(*outputs[0].borrow(), b"query_name\tchr.fasta\tplasmid.fasta\tvirus.fasta\n.1\t1\t1\t0\n.2\t0\t1\t1\n".to_vec());
+        assert_eq!(*outputs[1].borrow(), b"query_name\tchr.fasta\tplasmid.fasta\tvirus.fasta\n.2\t0\t1\t1\n.4\t0\t0\t1\n".to_vec());
+
+        // Table without levels
+        let got = Pipeline::new().output(Output::Text(Format::Bifrost)).run_split(&mut Cursor::new(bytes.clone()), &mut b"target\n".as_slice(), |_| Ok(std::io::sink())).unwrap_err();
+        assert!(got.downcast_ref::<crate::errors::InvalidTargetGroupsErr>().is_some());
+
+        // Only plain text output can be split
+        let got = Pipeline::new().output(Output::Ahda(EncodeOpts::default())).run_split(&mut Cursor::new(bytes), &mut table.as_slice(), |_| Ok(std::io::sink())).unwrap_err();
+        assert!(got.downcast_ref::<crate::errors::SplitNotSupportedErr>().is_some());
+    }
+}
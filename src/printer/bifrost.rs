@@ -42,7 +42,9 @@ pub fn format_bifrost_line<W: Write>(
 
     let ones: &Vec<u32> = aln.ones.as_ref().unwrap();
     let mut ones_bits: Vec<bool> = vec![false; n_targets];
-    ones.iter().for_each(|is_set_idx| ones_bits[*is_set_idx as usize] = true);
+    for is_set_idx in ones {
+        *ones_bits.get_mut(*is_set_idx as usize).ok_or(crate::errors::TargetIndexOutOfRangeErr{})? = true;
+    }
 
     ones_bits.iter().for_each(|is_set| {
         formatted += &separator.to_string();
@@ -20,7 +20,8 @@
 //! Returns 1 line at a time using next().
 //!
 //! If the desired output format has header lines, this can be formatted by
-//! Printer using [print_header](Printer::print_header).
+//! Printer using [print_header](Printer::print_header). Formats with a header
+//! can also record the program that wrote them, see [Program].
 //!
 //! ## Usage
 //!
@@ -96,8 +97,8 @@
 //! encoder.set_fields_present(3_u16); // 3: Have query_names and query_ids
 //!
 //! let mut bytes = encoder.encode_file_header_and_flags().unwrap();
-//! for mut data in encoder.by_ref() {
-//!     bytes.append(&mut data);
+//! for data in encoder.by_ref() {
+//!     bytes.append(&mut data.unwrap());
 //! }
 //!
 //! // Decode from `bytes` to Metagraph plaintext format
@@ -140,8 +141,11 @@ use ahda_tsv::format_ahda_tsv_line;
 use bifrost::format_bifrost_line;
 use fulgor::format_fulgor_line;
 use metagraph::format_metagraph_line;
+#[cfg(feature = "sam")]
 use sam::build_sam_header;
+#[cfg(feature = "sam")]
 use sam::format_sam_line;
+#[cfg(feature = "sam")]
 use sam::format_sam_header;
 use themisto::format_themisto_line;
 
@@ -150,9 +154,188 @@ pub mod ahda_tsv;
 pub mod bifrost;
 pub mod fulgor;
 pub mod metagraph;
+#[cfg(feature = "sam")]
 pub mod sam;
 pub mod themisto;
 
+type E = Box<dyn std::error::Error>;
+
+/// Split the `/1` or `/2` mate suffix from a query name
+///
+/// The [Decoder](crate::decoder::Decoder) appends the suffixes to the query
+/// names of paired-end reads. SAM stores the mate in the FLAG field instead,
+/// so the suffix is removed from the name and returned as the paired (0x1)
+/// and first (0x40) or last (0x80) segment flags.
+///
+/// Only call this for files that store the mates, ie. that have
+/// [MASK_MATES](ahda_core::file::MASK_MATES) set in `fields_present`, as
+/// other query names can end in `/1` or `/2` too.
+///
+/// Returns `name` and 0 if there is no suffix.
+///
+pub fn split_mate_suffix(
+    name: &[u8],
+) -> (&[u8], u16) {
+    match name.strip_suffix(b"/1") {
+        Some(stripped) => (stripped, 0x1 | 0x40),
+        None => match name.strip_suffix(b"/2") {
+            Some(stripped) => (stripped, 0x1 | 0x80),
+            None => (name, 0),
+        },
+    }
+}
+
+/// Targets to keep and rename in the output of a [Printer].
+///
+/// The kept targets are numbered from 0 in the order that they appear in the
+/// input, so that the output looks like it came from an index that only has
+/// the kept targets.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TargetSelection {
+    /// Names of the targets to keep, or None to keep all targets.
+    pub keep: Option<Vec<Vec<u8>>>,
+    /// Old and new name of the targets to rename.
+    pub rename: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl TargetSelection {
+    /// Check if the selection keeps and names all targets as they are.
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        self.keep.is_none() && self.rename.is_empty()
+    }
+}
+
+/// Program that wrote a plain text output, printed in the header.
+///
+/// SAM output gets a `@PG` line and Bifrost and AhdaTSV output a `#PG` line
+/// with the same fields before the header line. Formats without a header
+/// don't record the program. The [Parser](crate::parser::Parser) skips the
+/// `#PG` lines.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    /// Name of the program, used as both the ID and PN fields.
+    pub name: String,
+    /// Version of the program.
+    pub version: String,
+    /// Command line that the program was run with.
+    pub command_line: String,
+}
+
+impl Program {
+    /// This version of ahda run with `command_line`.
+    pub fn ahda(
+        command_line: &str,
+    ) -> Self {
+        Program{ name: "ahda".to_string(), version: env!("CARGO_PKG_VERSION").to_string(), command_line: command_line.to_string() }
+    }
+
+    /// Format as a header line starting with `tag`, eg. `@PG`.
+    ///
+    /// Tabs and line breaks in the fields are replaced with spaces.
+    pub fn format_line(
+        &self,
+        tag: &str,
+    ) -> String {
+        let clean = |x: &str| x.replace(['\t', '\n', '\r'], " ");
+        format!("{}\tID:{}\tPN:{}\tVN:{}\tCL:{}\n", tag, clean(&self.name), clean(&self.name), clean(&self.version), clean(&self.command_line))
+    }
+}
+
+/// What a [Printer] does with records that lack a field the output format needs.
+///
+/// Themisto needs the query id and target ids, Fulgor, Bifrost, and SAM the
+/// query name and target ids, AhdaTSV the query id, name, and target ids,
+/// and Metagraph the query id, name, and target names. Target ids and names
+/// are first filled in from each other using the target names of the
+/// Printer, so only records with neither count as missing the targets.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingFields {
+    /// Fail with [MissingFieldErr](crate::errors::MissingFieldErr) naming the record and field.
+    #[default]
+    Error,
+    /// Don't print the record and warn about the first skipped record, see [Printer::n_skipped].
+    Skip,
+    /// Print the record with the record index as the query id,
+    /// `<sample>.<query_id>` as the query name, and no targets.
+    Placeholder,
+}
+
+impl std::str::FromStr for MissingFields {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(MissingFields::Error),
+            "skip" => Ok(MissingFields::Skip),
+            "placeholder" => Ok(MissingFields::Placeholder),
+            _ => Err(format!("'{}' is not a valid MissingFields", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for MissingFields {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MissingFields::Error => write!(f, "error"),
+            MissingFields::Skip => write!(f, "skip"),
+            MissingFields::Placeholder => write!(f, "placeholder"),
+        }
+    }
+}
+
+/// Fields of a [PseudoAln] that a [Format] prints.
+struct RequiredFields {
+    query_id: bool,
+    query_name: bool,
+    ones: bool,
+    ones_names: bool,
+}
+
+impl RequiredFields {
+    fn of(
+        format: &Format,
+    ) -> Self {
+        let (query_id, query_name, ones, ones_names) = match format {
+            Format::Themisto => (true, false, true, false),
+            Format::Fulgor => (false, true, true, false),
+            Format::Metagraph => (true, true, false, true),
+            Format::Bifrost => (false, true, true, false),
+            Format::SAM => (false, true, true, false),
+            Format::AhdaTSV => (true, true, true, false),
+        };
+        RequiredFields{ query_id, query_name, ones, ones_names }
+    }
+
+    /// Name of the first required field that `record` lacks.
+    fn missing(
+        &self,
+        record: &PseudoAln,
+    ) -> Option<&'static str> {
+        if self.query_id && record.query_id.is_none() {
+            Some("query_id")
+        } else if self.query_name && record.query_name.is_none() {
+            Some("query_name")
+        } else if self.ones && record.ones.is_none() {
+            Some("ones")
+        } else if self.ones_names && record.ones_names.is_none() {
+            Some("ones_names")
+        } else {
+            None
+        }
+    }
+}
+
+/// Output index of each input target, see [TargetSelection].
+struct TargetMap {
+    input_names: indexmap::IndexSet<Vec<u8>>,
+    indexes: Vec<Option<u32>>,
+}
+
 pub struct Printer<'a, I: Iterator> where I: Iterator<Item=PseudoAln> {
     // Inputs
     records: &'a mut I,
@@ -160,8 +343,15 @@ pub struct Printer<'a, I: Iterator> where I: Iterator<Item=PseudoAln> {
     header: FileHeader,
     flags: FileFlags,
 
+    #[cfg(feature = "sam")]
     sam_header: Option<noodles_sam::Header>,
 
+    target_map: Option<TargetMap>,
+    program: Option<Program>,
+
+    missing_fields: MissingFields,
+    n_skipped: usize,
+
     index: usize,
     pub format: Format,
 }
@@ -184,10 +374,7 @@ impl<'a, I: Iterator> Printer<'a, I> where I: Iterator<Item=PseudoAln> {
         flags: FileFlags,
         format: Format,
     ) -> Self {
-        if format == Format::SAM {
-            todo!("printing .sam plain text data.")
-        }
-
+        #[cfg(feature = "sam")]
         let sam_header = if format == Format::SAM {
             Some(sam::build_sam_header(&flags.target_names).unwrap())
         } else {
@@ -197,13 +384,229 @@ impl<'a, I: Iterator> Printer<'a, I> where I: Iterator<Item=PseudoAln> {
         Printer{
             records,
             header, flags,
-            sam_header, index: 0,
+            #[cfg(feature = "sam")]
+            sam_header,
+            target_map: None,
+            program: None,
+            missing_fields: MissingFields::default(),
+            n_skipped: 0,
+            index: 0,
             format,
         }
     }
 }
 
 impl<'a, I: Iterator> Printer<'a, I> where I: Iterator<Item=PseudoAln> {
+    /// Only print the targets in `selection`, renamed.
+    ///
+    /// Replaces the target names in the header and drops the alignments to
+    /// the targets that are not kept from the records. Call before printing
+    /// anything.
+    ///
+    /// ## Errors and panics
+    ///
+    /// Errors with [UnknownTargetErr](crate::errors::UnknownTargetErr) if
+    /// `selection` contains a name that is not a target, and with
+    /// [DuplicateTargetNameErr](crate::errors::DuplicateTargetNameErr) if two
+    /// kept targets have the same name after renaming.
+    ///
+    pub fn select_targets(
+        &mut self,
+        selection: &TargetSelection,
+    ) -> Result<(), E> {
+        let input_names: indexmap::IndexSet<Vec<u8>> = self.flags.target_names.iter().cloned().collect();
+
+        let mut keep: Vec<bool> = vec![selection.keep.is_none(); input_names.len()];
+        for name in selection.keep.iter().flatten() {
+            let idx = input_names.get_index_of(name).ok_or(crate::errors::UnknownTargetErr{})?;
+            keep[idx] = true;
+        }
+
+        let mut names: Vec<Vec<u8>> = input_names.iter().cloned().collect();
+        for (old, new) in selection.rename.iter() {
+            let idx = input_names.get_index_of(old).ok_or(crate::errors::UnknownTargetErr{})?;
+            names[idx] = new.clone();
+        }
+
+        let mut output_names: indexmap::IndexSet<Vec<u8>> = indexmap::IndexSet::new();
+        let mut indexes: Vec<Option<u32>> = Vec::with_capacity(names.len());
+        for (name, keep) in names.into_iter().zip(keep) {
+            if !keep {
+                indexes.push(None);
+                continue
+            }
+            let (idx, new) = output_names.insert_full(name);
+            if !new {
+                let name = String::from_utf8_lossy(output_names.get_index(idx).unwrap()).to_string();
+                return Err(Box::new(crate::errors::DuplicateTargetNameErr{ name }))
+            }
+            indexes.push(Some(idx as u32));
+        }
+
+        self.flags.target_names = output_names.into_iter().collect();
+        self.header.n_targets = self.flags.target_names.len() as u32;
+        self.target_map = Some(TargetMap{ input_names, indexes });
+        Ok(())
+    }
+
+    /// Record `program` in the header, see [Program].
+    ///
+    /// Defaults to None. Call before printing anything.
+    pub fn set_program(
+        &mut self,
+        program: Option<Program>,
+    ) {
+        self.program = program;
+    }
+
+    /// Set what to do with records that lack a field the output format needs.
+    ///
+    /// See [MissingFields]. Defaults to [MissingFields::Error].
+    pub fn set_missing_fields(
+        &mut self,
+        policy: MissingFields,
+    ) {
+        self.missing_fields = policy;
+    }
+
+    /// Number of records skipped with [MissingFields::Skip] so far.
+    pub fn n_skipped(
+        &self,
+    ) -> usize {
+        self.n_skipped
+    }
+
+    /// Fill in the fields of `record` that the output format needs, see [MissingFields].
+    ///
+    /// Returns None if the record should be skipped.
+    fn complete_record(
+        &mut self,
+        record: &PseudoAln,
+    ) -> Result<Option<PseudoAln>, E> {
+        let required = RequiredFields::of(&self.format);
+        let mut record = record.clone();
+        if required.ones && record.ones.is_none() {
+            if let Some(names) = &record.ones_names {
+                record.ones = Some(names.iter().map(|name| {
+                    self.flags.target_names.iter().position(|x| x == name).map(|x| x as u32).ok_or(crate::errors::UnknownTargetErr{})
+                }).collect::<Result<Vec<u32>, _>>()?);
+            }
+        }
+        if required.ones_names && record.ones_names.is_none() {
+            if let Some(ones) = &record.ones {
+                record.ones_names = Some(ones.iter().map(|x| {
+                    self.flags.target_names.get(*x as usize).cloned().ok_or(crate::errors::TargetIndexOutOfRangeErr{})
+                }).collect::<Result<Vec<Vec<u8>>, _>>()?);
+            }
+        }
+
+        let Some(field) = required.missing(&record) else {
+            return Ok(Some(record))
+        };
+        match self.missing_fields {
+            MissingFields::Error => Err(Box::new(crate::errors::MissingFieldErr{ index: self.index, field, format: self.format.clone() })),
+            MissingFields::Skip => {
+                if self.n_skipped == 0 {
+                    log::warn!("skipping records without `{}` in the {} output, first at record {}", field, self.format, self.index);
+                }
+                self.n_skipped += 1;
+                Ok(None)
+            },
+            MissingFields::Placeholder => {
+                let query_id = *record.query_id.get_or_insert(self.index as u32);
+                if record.query_name.is_none() {
+                    record.query_name = Some(format!("{}.{}", String::from_utf8_lossy(&self.flags.query_name), query_id).into_bytes());
+                }
+                if record.ones.is_none() && record.ones_names.is_none() {
+                    record.ones = Some(Vec::new());
+                    record.ones_names = Some(Vec::new());
+                }
+                Ok(Some(record))
+            },
+        }
+    }
+
+    /// Drop the targets that are not kept from `record` and renumber the rest.
+    fn map_targets(
+        &self,
+        record: &PseudoAln,
+    ) -> Result<PseudoAln, E> {
+        let map = self.target_map.as_ref().unwrap();
+        let ones: Option<Vec<u32>> = match (record.ones.as_ref(), record.ones_names.as_ref()) {
+            (Some(ones), _) => Some(ones.iter().map(|x| {
+                map.indexes.get(*x as usize).copied().ok_or(crate::errors::TargetIndexOutOfRangeErr{})
+            }).collect::<Result<Vec<Option<u32>>, _>>()?.into_iter().flatten().collect()),
+            (None, Some(names)) => Some(names.iter().map(|x| {
+                map.input_names.get_index_of(x).map(|idx| map.indexes[idx]).ok_or(crate::errors::UnknownTargetErr{})
+            }).collect::<Result<Vec<Option<u32>>, _>>()?.into_iter().flatten().collect()),
+            (None, None) => None,
+        };
+        let ones_names = record.ones_names.as_ref().map(|_| {
+            ones.iter().flatten().map(|x| self.flags.target_names[*x as usize].clone()).collect()
+        });
+
+        Ok(PseudoAln{
+            ones: if record.ones.is_some() { ones } else { None },
+            ones_names,
+            query_id: record.query_id,
+            query_name: record.query_name.clone(),
+        })
+    }
+
+    /// Append `record` to `out` in the output format.
+    ///
+    /// Used to print records that don't come from the iterator given to
+    /// [Printer::new]. Call [Printer::print_header] first for formats that
+    /// have a header.
+    ///
+    /// ## Errors and panics
+    /// Errors with [MissingFieldErr](crate::errors::MissingFieldErr) if
+    /// `record` lacks a field that the output format needs, unless set
+    /// otherwise with [set_missing_fields](Printer::set_missing_fields), and
+    /// if a target index is out of range.
+    ///
+    pub fn format_record(
+        &mut self,
+        record: &PseudoAln,
+        out: &mut Vec<u8>,
+    ) -> Result<(), E> {
+        let mapped;
+        let record = if self.target_map.is_some() {
+            mapped = self.map_targets(record)?;
+            &mapped
+        } else {
+            record
+        };
+        let completed;
+        let record = if RequiredFields::of(&self.format).missing(record).is_none() {
+            record
+        } else {
+            match self.complete_record(record)? {
+                Some(x) => {
+                    completed = x;
+                    &completed
+                },
+                None => {
+                    self.index += 1;
+                    return Ok(())
+                },
+            }
+        };
+        match self.format {
+            Format::Themisto => format_themisto_line(record, out)?,
+            Format::Fulgor => format_fulgor_line(record, out)?,
+            Format::Metagraph => format_metagraph_line(record, out)?,
+            Format::Bifrost => format_bifrost_line(record, self.header.n_targets as usize, out)?,
+            #[cfg(not(feature = "sam"))]
+            Format::SAM => unreachable!("printing .sam requires the `sam` feature"),
+            #[cfg(feature = "sam")]
+            Format::SAM => format_sam_line(record, self.sam_header.as_ref().unwrap(), self.header.fields_present & crate::MASK_MATES != 0, out)?,
+            Format::AhdaTSV => format_ahda_tsv_line(record, self.header.n_targets as usize, out)?,
+        }
+        self.index += 1;
+        Ok(())
+    }
+
     pub fn print_header(
         &mut self,
     ) -> Option<Vec<u8>> {
@@ -213,15 +616,27 @@ impl<'a, I: Iterator> Printer<'a, I> where I: Iterator<Item=PseudoAln> {
             Format::Fulgor => None,
             Format::Metagraph => None,
             Format::Bifrost => {
+                if let Some(program) = &self.program {
+                    out.extend_from_slice(program.format_line("#PG").as_bytes());
+                }
                 format_bifrost_header(&self.flags.target_names, &mut out).unwrap();
                 Some(out)
             },
+            #[cfg(not(feature = "sam"))]
+            Format::SAM => unreachable!("printing .sam requires the `sam` feature"),
+            #[cfg(feature = "sam")]
             Format::SAM => {
                 self.sam_header = Some(build_sam_header(&self.flags.target_names).unwrap());
                 format_sam_header(self.sam_header.as_ref().unwrap(), &mut out).unwrap();
+                if let Some(program) = &self.program {
+                    out.extend_from_slice(program.format_line("@PG").as_bytes());
+                }
                 Some(out)
             },
             Format::AhdaTSV => {
+                if let Some(program) = &self.program {
+                    out.extend_from_slice(program.format_line("#PG").as_bytes());
+                }
                 format_ahda_tsv_header(&self.flags.target_names, &mut out).unwrap();
                 Some(out)
             }
@@ -229,12 +644,15 @@ impl<'a, I: Iterator> Printer<'a, I> where I: Iterator<Item=PseudoAln> {
     }
 }
 
-impl<'a, I: Iterator> Iterator for Printer<'a, I> where I: Iterator<Item=PseudoAln> {
-    type Item = Vec<u8>;
-
-    fn next(
+impl<'a, I: Iterator> Printer<'a, I> where I: Iterator<Item=PseudoAln> {
+    /// Format the next record, returning an error if it can't be formatted.
+    ///
+    /// The first call also returns the header. Records skipped with
+    /// [MissingFields::Skip] are passed over. Returns `None` when there are
+    /// no more records.
+    pub fn try_next(
         &mut self,
-    ) -> Option<Vec<u8>> {
+    ) -> Option<Result<Vec<u8>, E>> {
         let mut out: Vec<u8> = Vec::new();
         if self.index == 0 {
             if let Some(mut header) = self.print_header() {
@@ -242,27 +660,224 @@ impl<'a, I: Iterator> Iterator for Printer<'a, I> where I: Iterator<Item=PseudoA
             }
         }
 
-        if let Some(record) = self.records.next() {
-            match self.format {
-                Format::Themisto => format_themisto_line(&record, &mut out).unwrap(),
-                Format::Fulgor => format_fulgor_line(&record, &mut out).unwrap(),
-                Format::Metagraph => format_metagraph_line(&record, &mut out).unwrap(),
-                Format::Bifrost => format_bifrost_line(&record, self.header.n_targets as usize, &mut out).unwrap(),
-                Format::SAM => format_sam_line(&record, self.sam_header.as_ref().unwrap(), &mut out).unwrap(),
-                Format::AhdaTSV => format_ahda_tsv_line(&record, self.header.n_targets as usize, &mut out).unwrap(),
+        while let Some(record) = self.records.next() {
+            if let Err(e) = self.format_record(&record, &mut out) {
+                return Some(Err(e))
+            }
+            if !out.is_empty() {
+                return Some(Ok(out))
             }
+        }
+        if !out.is_empty() {
+            // No records, only print the header
             self.index += 1;
-            Some(out)
+            Some(Ok(out))
         } else {
             None
         }
     }
+}
+
+impl<'a, I: Iterator> Iterator for Printer<'a, I> where I: Iterator<Item=PseudoAln> {
+    type Item = Vec<u8>;
+
+    /// Format the next record.
+    ///
+    /// ## Errors and panics
+    /// Panics if the record can't be formatted, use
+    /// [try_next](Printer::try_next) to get the error instead.
+    ///
+    fn next(
+        &mut self,
+    ) -> Option<Vec<u8>> {
+        self.try_next().map(|x| x.unwrap())
+    }
 
 }
 
 // Tests
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn split_mate_suffix() {
+        use super::split_mate_suffix;
+
+        assert_eq!(split_mate_suffix(b"ERR4035126.1/1"), (b"ERR4035126.1".as_slice(), 0x41));
+        assert_eq!(split_mate_suffix(b"ERR4035126.1/2"), (b"ERR4035126.1".as_slice(), 0x81));
+        assert_eq!(split_mate_suffix(b"ERR4035126.12"), (b"ERR4035126.12".as_slice(), 0));
+    }
+
+    #[test]
+    #[cfg(feature = "sam")]
+    fn print_sam_mate_flags() {
+        use super::Printer;
+
+        use crate::Format;
+        use crate::PseudoAln;
+        use crate::compression::MetadataCompression;
+        use crate::headers::file::build_file_header_and_flags;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data = vec![
+            PseudoAln{ ones: Some(vec![0, 1]), ones_names: None, query_id: Some(0), query_name: Some(b"r1/1".to_vec()) },
+            PseudoAln{ ones: Some(vec![]), ones_names: None, query_id: Some(1), query_name: Some(b"r1/2".to_vec()) },
+        ];
+        let sam_header: &[u8] = b"@HD\tVN:1.6\n@SQ\tSN:chr.fasta\tLN:1\n@SQ\tSN:plasmid.fasta\tLN:1\n";
+
+        // Paired (0x1), unmapped (0x4), and first (0x40) or last (0x80) segment
+        let (mut header, flags) = build_file_header_and_flags(&targets, 2, b"sample", &MetadataCompression::default()).unwrap();
+        header.fields_present |= crate::MASK_MATES;
+        let mut iter = data.clone().into_iter();
+        let mut printer = Printer::new_from_header_and_flags(&mut iter, header, flags, Format::SAM);
+        let got: Vec<u8> = printer.by_ref().flatten().collect();
+        assert_eq!(got, [sam_header, b"r1\t69\tchr.fasta\t0\t255\t*\t*\t0\t0\t*\t*\nr1\t69\tplasmid.fasta\t0\t255\t*\t*\t0\t0\t*\t*\nr1\t133\t*\t0\t255\t*\t*\t0\t0\t*\t*\n"].concat());
+
+        // The suffixes are part of the names if the mates are not stored
+        let mut iter = data.into_iter();
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 2, Format::SAM);
+        let got: Vec<u8> = printer.by_ref().flatten().collect();
+        assert_eq!(got, [sam_header, b"r1/1\t4\tchr.fasta\t0\t255\t*\t*\t0\t0\t*\t*\nr1/1\t4\tplasmid.fasta\t0\t255\t*\t*\t0\t0\t*\t*\nr1/2\t4\t*\t0\t255\t*\t*\t0\t0\t*\t*\n"].concat());
+    }
+
+    #[test]
+    fn select_targets_keeps_and_renames() {
+        use super::Printer;
+        use super::TargetSelection;
+
+        use crate::Format;
+        use crate::PseudoAln;
+        use crate::errors::DuplicateTargetNameErr;
+        use crate::errors::UnknownTargetErr;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()];
+        let selection = TargetSelection{
+            keep: Some(vec![b"virus.fasta".to_vec(), b"chr.fasta".to_vec()]),
+            rename: vec![(b"virus.fasta".to_vec(), b"phage".to_vec()), (b"plasmid.fasta".to_vec(), b"dropped".to_vec())],
+        };
+
+        // Records with target ids, and with only target names
+        let data = vec![
+            PseudoAln{ ones: Some(vec![2, 1, 0]), ones_names: None, query_id: Some(0), query_name: None },
+            PseudoAln{ ones: None, ones_names: Some(vec![b"plasmid.fasta".to_vec(), b"virus.fasta".to_vec()]), query_id: Some(1), query_name: Some(b"r2".to_vec()) },
+        ];
+        let mut iter = data.clone().into_iter();
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 2, Format::Themisto);
+        printer.select_targets(&selection).unwrap();
+        assert_eq!(printer.next().unwrap(), b"0 1 0\n");
+
+        let mut iter = data.into_iter().skip(1);
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 2, Format::Metagraph);
+        printer.select_targets(&selection).unwrap();
+        assert_eq!(printer.next().unwrap(), b"1\tr2\tphage\n");
+
+        let mut iter = std::iter::empty::<PseudoAln>();
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 2, Format::Bifrost);
+        printer.select_targets(&selection).unwrap();
+        assert_eq!(printer.print_header().unwrap(), b"query_name\tchr.fasta\tphage\n");
+
+        let unknown = TargetSelection{ keep: Some(vec![b"chr".to_vec()]), rename: Vec::new() };
+        let got = printer.select_targets(&unknown).unwrap_err();
+        assert!(got.downcast_ref::<UnknownTargetErr>().is_some());
+
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 2, Format::Bifrost);
+        let duplicate = TargetSelection{ keep: None, rename: vec![(b"virus.fasta".to_vec(), b"chr.fasta".to_vec())] };
+        let got = printer.select_targets(&duplicate).unwrap_err();
+        assert_eq!(got.downcast_ref::<DuplicateTargetNameErr>().unwrap().name, "chr.fasta");
+    }
+
+    #[test]
+    fn program_record_in_header() {
+        use super::Printer;
+        use super::Program;
+
+        use crate::Format;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec()];
+        let program = Program{ name: "ahda".to_string(), version: "0.1.0".to_string(), command_line: "ahda convert\tin.txt".to_string() };
+        assert_eq!(program.format_line("@PG"), "@PG\tID:ahda\tPN:ahda\tVN:0.1.0\tCL:ahda convert in.txt\n");
+
+        let mut iter = std::iter::empty::<PseudoAln>();
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 0, Format::Bifrost);
+        printer.set_program(Some(program.clone()));
+        assert_eq!(printer.print_header().unwrap(), b"#PG\tID:ahda\tPN:ahda\tVN:0.1.0\tCL:ahda convert in.txt\nquery_name\tchr.fasta\n");
+
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 0, Format::Themisto);
+        printer.set_program(Some(program));
+        assert_eq!(printer.print_header(), None);
+    }
+
+    #[test]
+    fn missing_fields_policy() {
+        use super::MissingFields;
+        use super::Printer;
+
+        use crate::Format;
+        use crate::PseudoAln;
+        use crate::errors::MissingFieldErr;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data = vec![
+            PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(0), query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ ones: None, ones_names: Some(vec![b"chr.fasta".to_vec()]), query_id: Some(1), query_name: None },
+            PseudoAln{ ones: None, ones_names: None, query_id: None, query_name: Some(b"r3".to_vec()) },
+        ];
+
+        // Target names are filled in from the target ids
+        let mut iter = data.clone().into_iter().take(1);
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 3, Format::Metagraph);
+        assert_eq!(printer.try_next().unwrap().unwrap(), b"0\tr1\tplasmid.fasta\n");
+
+        let mut iter = data.clone().into_iter();
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 3, Format::Metagraph);
+        assert!(printer.try_next().unwrap().is_ok());
+        let got = printer.try_next().unwrap().unwrap_err();
+        let got = got.downcast_ref::<MissingFieldErr>().unwrap();
+        assert_eq!((got.index, got.field), (1, "query_name"));
+
+        let mut iter = data.clone().into_iter();
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 3, Format::Metagraph);
+        printer.set_missing_fields(MissingFields::Skip);
+        let got: Vec<Vec<u8>> = printer.by_ref().collect();
+        assert_eq!(got, vec![b"0\tr1\tplasmid.fasta\n".to_vec()]);
+        assert_eq!(printer.n_skipped(), 2);
+
+        let mut iter = data.clone().into_iter();
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 3, Format::AhdaTSV);
+        printer.set_missing_fields(MissingFields::Placeholder);
+        let got: Vec<u8> = printer.by_ref().flatten().collect();
+        assert_eq!(got, b"query_index\tquery_name\tchr.fasta\tplasmid.fasta\n0\tr1\t0\t1\n1\tsample.1\t1\t0\n2\tr3\t0\t0\n");
+
+        #[cfg(feature = "sam")]
+        {
+            let mut iter = data.clone().into_iter();
+            let mut printer = Printer::new(&mut iter, &targets, b"sample", 3, Format::SAM);
+            assert!(printer.try_next().unwrap().is_ok());
+            let got = printer.try_next().unwrap().unwrap_err();
+            let got = got.downcast_ref::<MissingFieldErr>().unwrap();
+            assert_eq!((got.index, got.field), (1, "query_name"));
+
+            let mut iter = data.into_iter();
+            let mut printer = Printer::new(&mut iter, &targets, b"sample", 3, Format::SAM);
+            printer.set_missing_fields(MissingFields::Placeholder);
+            let got: Vec<u8> = printer.by_ref().flatten().collect();
+            assert_eq!(got, b"@HD\tVN:1.6\n@SQ\tSN:chr.fasta\tLN:1\n@SQ\tSN:plasmid.fasta\tLN:1\nr1\t4\tplasmid.fasta\t0\t255\t*\t*\t0\t0\t*\t*\nsample.1\t4\tchr.fasta\t0\t255\t*\t*\t0\t0\t*\t*\nr3\t4\t*\t0\t255\t*\t*\t0\t0\t*\t*\n");
+        }
+    }
+
+    #[test]
+    fn target_out_of_range_is_an_error() {
+        use super::Printer;
+
+        use crate::Format;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec()];
+        let mut iter = vec![PseudoAln{ ones: Some(vec![1]), ones_names: None, query_id: Some(0), query_name: Some(b"r1".to_vec()) }].into_iter();
+        let mut printer = Printer::new(&mut iter, &targets, b"sample", 1, Format::Bifrost);
+        assert!(printer.try_next().unwrap().is_err());
+    }
+
     #[test]
     fn print_themisto_output() {
         use super::Printer;
@@ -287,10 +902,10 @@ mod tests {
             PseudoAln{ones_names: None,  query_id: Some(1),   ones: Some(vec![4, 2, 9, 7]), ..Default::default()},
         ];
 
-        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()] };
+        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], ..Default::default() };
         let header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: 2_u32,
@@ -353,10 +968,10 @@ mod tests {
             PseudoAln{ones_names: None,  query_id: None, ones: Some(vec![0, 1]), query_name: Some("ERR4035126.651965".as_bytes().to_vec()) },
         ];
 
-        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()] };
+        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], ..Default::default() };
         let header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: 2_u32,
@@ -430,10 +1045,10 @@ mod tests {
             PseudoAln{ query_name: Some("ERR4035126.824748".as_bytes().to_vec()), ones: Some(vec![0]), ones_names: None, query_id: None },
         ];
 
-        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()] };
+        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], ..Default::default() };
         let header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: 2_u32,
@@ -499,10 +1114,10 @@ mod tests {
             PseudoAln{ones_names: Some(vec!["plasmid.fasta".as_bytes().to_vec()]),  query_id: Some(15084), ones: Some(vec![]), query_name: Some("ERR4035126.7543".as_bytes().to_vec()) },
         ];
 
-        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()] };
+        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], ..Default::default() };
         let header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: 2_u32,
@@ -532,7 +1147,6 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "not yet implemented")]
     fn print_sam_output() {
         use super::Printer;
 
@@ -566,10 +1180,10 @@ mod tests {
             PseudoAln{ query_id: None, query_name: Some("ERR4035126.621281".as_bytes().to_vec()), ones_names: Some(vec!["OZ038621.1".as_bytes().to_vec()]), ones: Some(vec![0]) },
         ];
 
-        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()] };
+        let flags = FileFlags { query_name: "ERR4035126".as_bytes().to_vec(), target_names: vec!["chromosome.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], ..Default::default() };
         let header = FileHeader {
             ahda_header: build_ahda_header(),
-            file_format: AhdaFormatVersion::V1_0_0.to_u8(),
+            file_format: AhdaFormatVersion::V1_1_0.to_u8(),
             metadata_compression: MetadataCompression::default().to_u8(),
             fields_present: 0,
             n_targets: 2_u32,
@@ -580,23 +1194,23 @@ mod tests {
         };
 
         let mut expected: Vec<u8> = b"@HD\tVN:1.6\n".to_vec();
-        expected.append(&mut b"@SQ\tSN:OZ038621.1\tLN:1\n".to_vec());
-        expected.append(&mut b"@SQ\tSN:OZ038622.1\tLN:1\n".to_vec());
-        expected.append(&mut b"ERR4035126.1\t16\tOZ038621.1\t4541508\t60\t151M\t*\t0\t0\tAGTATTTAGTGACCTAAGTCAATAAAATTTTAATTTACTCACGGCAGGTAACCAGTTCAGAAGCTGCTATCAGACACTCTTTTTTTAATCCACACAGAGACATATTGCCCGTTGCAGTCAGAATGAAAAGCTGAAAATCACTTACTAAGGC\tFJ<<JJFJAA<-JFAJFAF<JFFJJJJJJJFJFJJA<A<AJJAAAFFJJJJFJJFJFJAJJ7JJJJJFJJJJJFFJFFJFJJJJJJFJ7FFJAJJJJJJJJFJJFJJFJFJJJJFJJFJJJJJJJJJFFJJJJJJJJJJJJJFJJJFFAAA\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.2\t16\tOZ038621.1\t4541557\t60\t151M\t*\t0\t0\tAACCAGTTCAGAAGCTGCTATCAGACACTCTTTTTTTAATCCACACAGAGACATATTGCCCGTTGCAGTCAGAATGAAAAGCTGAAAATCACTTACTAAGGCGTTTTTTATTTGGTGATATTTTTTTCAATATCATGCAGCAAACGGTGCA\tJAFJFJJJFFJFAJJJJJJJJJJFFA<JJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJFF-FFFAA\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.3\t16\tOZ038621.1\t4541521\t60\t151M\t*\t0\t0\tCTAAGTCAATAAAATTTTAATTTACTCACGGCAGGTAACCAGTTCAGAAGCTGCTATCAGACACTCTTTTTTTAATCCACACAGAGACATATTGCCCGTTGCAGTCAGAATGAAAAGCTGAAAATCACTTACTAAGGCGTTTTTTATTTGG\tJJJJJJJFJFFFJJJJJJAJJJF7JJJJJ<JJFFJJJJJJJFJJJJJJJJJFFFJJJFJJJJJJJJJJJJJJJJAJFJJJJFJJJJJJJJJJJJJJJJJJJJJJAJJJJJJJJJJJJJJJJJAJFJFJJJJJJJJJJJJJJJJJFJFAFAA\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.1261584\t16\tOZ038622.1\t66398\t60\t151M\t*\t0\t0\tGCCGCTGTCTGAACCATGATATTGGCGGAACCGATGCCCATGATGGATGCGCCCCACAGCATGACCAGTTGCGCCAGACTCCAGCCGGAAGCGGTGGGCACAATCATCAAAAATCCACTCACGACACTGAGTATGCCGACGACGTCCCGTC\tFFJJJJFFJFJFFFJJJJJJJJJJJ7FA<JJ<JFJJFJJJJF-FJJA<FJJJJAJJJJJJJJJJJJJJJJJJJJFFJJJFJJJJJJJJJJJJJJJJJJFJFJJJJJJJJJFJJJJJFJJFJFJFJJJJJJJJJJJJJJJJJJJJJJFFFAA\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.1213410\t16\tOZ038622.1\t3996\t60\t151M\t*\t0\t0\tGCTGGCGCTTCGGGGATATGTGTTTCGACGGCAGATGAATTTATTCCGGCGGGGGCTGATTCTGCCGTCTGTTCAGTAAATACAGGTGCGATAATATCTGTTTTTTCGGATAAGGACGGTGGCGAAAAAGTACGACGTTTTTTCACCACAA\tJJJJJJJJJJJJJJJJJJJJJJJJJJJFJFJFFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFFJJJJJJJJJJJFFAAA\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.1213410\t16\tOZ038621.1\t3996\t60\t151M\t*\t0\t0\tGCTGGCGCTTCGGGGATATGTGTTTCGACGGCAGATGAATTTATTCCGGCGGGGGCTGATTCTGCCGTCTGTTCAGTAAATACAGGTGCGATAATATCTGTTTTTTCGGATAAGGACGGTGGCGAAAAAGTACGACGTTTTTTCACCACAA\tJJJJJJJJJJJJJJJJJJJJJJJJJJJFJFJFFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFFJJJJJJJJJJJFFAAA\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.4\t0\tOZ038621.1\t4541351\t60\t151M\t*\t0\t0\tAGGTGCGGGCTTTTTTCTGTGTTTCCTGTACGCGTCAGCCCGCACCGTTACCTGTGGTAATGGTGATGGTGGTGGTAATGGTGGTGCTAATGCGTTTCATGGATGTTGTGTACTCTGTAATTTTTATCTGTCTGTGCGCTATGCCTATATT\tAAFFFJJJJJJJJJJJJJJJJJJJJJFFJJJJJJJJJJJJJJJJJJJFFJJJJJJJJFJJJJJJJJJJJ<JFJJJJJJJJJJJAJJJFJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJFFFJFAFJJJJF<FFFJJJJ\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.5\t16\tOZ038621.1\t4541533\t60\t151M\t*\t0\t0\tAATTTTAATTTACTCACGGCAGGTAACCAGTTCAGAAGCTGCTATCAGACACTCTTTTTTTAATCCACACAGAGACATATTGCCCGTTGCAGTCAGAATGAAAAGCTGAAAATCACTTACTAAGGCGTTTTTTATTTGGTGATATTTTTTT\tFJJJJJJJJFJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJFFFAA\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.6\t0\tOZ038621.1\t4541261\t60\t151M\t*\t0\t0\tTCTGCATTTGCCACTGATGTACCGCCGAACTTCAACACTCGCATGGTTGTTACCTCGTTACCTTTGGTCGAAAAAAAAGCCCGCACTGTCAGGTGCGGGCTTTTTTCTGTGTTTCCTGTACGCGTCAGCCCGCACCGTTACCTGTGGTAAT\tAAAFFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJFJJJJJJJJ<FJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJFJJJJF7FJJJJJJJFJFJJJJJJJJFJJJJJJJJAJJJJFJFFFJFJF\tNM:i:0\tMD:Z:151\tAS:i:151\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.973529\t16\tOZ038621.1\t3695316\t60\t66S85M\t*\t0\t0\tGGAGATGATTTCGTGTTTCTTCTCCGGGATGACCATGTCATCGATACCAACAGATGCACCAGAACGCGCCAAGTCGGGCAATCTGGTGAACTGGAAAGCCGGGGCGCTGTATCACCTGACGGAAAACGGCAATGTCTATATTAACTATGCC\tJJFJFF7-FFJJJA-FJFFFJJFAJJJJJJJJJJJJJJJJFJJJJJJJJFJJJJFJ<JJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFFJFJJJJJJJJFJJJJJJJJFAJJJJJJJJJJJJJFJJJJJFFJJJFJJJJJJFJJFFFAA\tNM:i:0\tMD:Z:85\tAS:i:85\tXS:i:0\tSA:Z:OZ038621.1,5194124,-,69M82S,60,0;\n".to_vec());
-        expected.append(&mut b"ERR4035126.973529\t2064\tOZ038621.1\t5194124\t60\t69M82H\t*\t0\t0\tGGAGATGATTTCGTGTTTCTTCTCCGGGATGACCATGTCATCGATACCAACAGATGCACCAGAACGCGC\tJJFJFF7-FFJJJA-FJFFFJJFAJJJJJJJJJJJJJJJJFJJJJJJJJFJJJJFJ<JJJJJJJJJJJJ\tNM:i:0\tMD:Z:69\tAS:i:69\tXS:i:0\tSA:Z:OZ038621.1,3695316,-,66S85M,60,0;\n".to_vec());
-        expected.append(&mut b"ERR4035126.621281\t16\tOZ038621.1\t1040569\t60\t39S86M26S\t*\t0\t0\tGCTCGACCGCGTCCCAGTTGAAATGCAACTCCCCAGCCAACTCGATAAACACGATGATTAACACGGCAGTCATGGTCAGAATGGAAACGGGATCGAAAATCGGCATACCAAATGACATCGGCGTGCCACAGCACAAACTGGACGCCCTGGC\tAFAJJJJJJJJJJJJFJJJJJJJJJJJJJJJJFJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFFFAA\tNM:i:0\tMD:Z:86\tAS:i:86\tXS:i:0\tSA:Z:OZ038621.1,3172373,-,46M105S,60,0;OZ038621.1,1301509,+,33M118S,60,0;\n".to_vec());
-        expected.append(&mut b"ERR4035126.1178767\t4\t*\t0\t0\t*\t*\t0\t0\tACTTGGCTCATGTTCCGTCAATGCCGGAGAGACAATTGAAGTTGATTTAGGTGATGTCTTCGCTGCCAATTTCCGTGTTGTAGGGCATAAACCTCTTGGGGCCAGAACGGCAGAACTTGCAATTCCAGTCAGGTGTAACACGGGAAACGCG\tAAFFFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJFJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJ\tAS:i:0\tXS:i:0\n".to_vec());
-        expected.append(&mut b"ERR4035126.621281\t2064\tOZ038621.1\t3172373\t60\t46M105H\t*\t0\t0\tGCTCGACCGCGTCCCAGTTGAAATGCAACTCCCCAGCCAACTCGAT\tAFAJJJJJJJJJJJJFJJJJJJJJJJJJJJJJFJFJJJJJJJJJJJ\tNM:i:0\tMD:Z:46\tAS:i:46\tXS:i:0\tSA:Z:OZ038621.1,1040569,-,39S86M26S,60,0;OZ038621.1,1301509,+,33M118S,60,0;\n".to_vec());
-        expected.append(&mut b"ERR4035126.621281\t2048\tOZ038621.1\t1301509\t60\t33M118H\t*\t0\t0\tGCCAGGGCGTCCAGTTTGTGCTGTGGCACGCCG\tAAFFFJJJJJJJJJJJJJJJJJJJJJJJJJJJJ\tNM:i:0\tMD:Z:33\tAS:i:33\tXS:i:0\tSA:Z:OZ038621.1,1040569,-,39S86M26S,60,0;OZ038621.1,3172373,-,46M105S,60,0;\n".to_vec());
+        expected.append(&mut b"@SQ\tSN:chromosome.fasta\tLN:1\n".to_vec());
+        expected.append(&mut b"@SQ\tSN:plasmid.fasta\tLN:1\n".to_vec());
+        expected.append(&mut b"ERR4035126.1\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.2\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.3\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.1261584\t4\tplasmid.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.1213410\t4\tplasmid.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.1213410\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.4\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.5\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.6\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.973529\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.973529\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.621281\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.1178767\t4\t*\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.621281\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
+        expected.append(&mut b"ERR4035126.621281\t4\tchromosome.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec());
 
         let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
 
@@ -608,8 +1222,6 @@ mod tests {
 
         let got = cursor.get_ref();
 
-        eprintln!("{}", got.iter().map(|x| *x as char).collect::<String>());
-        eprintln!("{}", expected.iter().map(|x| *x as char).collect::<String>());
         assert_eq!(got, &expected);
     }
 }
@@ -22,40 +22,55 @@ use noodles_sam::{
 
 use crate::PseudoAln;
 
+pub use super::split_mate_suffix;
+
 type E = Box<dyn std::error::Error>;
 
 /// Format a single pseudoalignment in Sam format
 ///
-/// Writes bytes containing the formatted line containing the contents of
-/// `aln` to `conn`.
+/// Writes bytes containing the formatted lines containing the contents of
+/// `aln` to `conn`, one line per target in `ones` or a single line with `*`
+/// as RNAME if `aln` did not align to any target. Pseudoalignments have no
+/// position, so all lines are flagged unmapped (0x4) and placed on their
+/// target.
+///
+/// If `mates` is true, the query names end in the `/1` or `/2` mate suffix
+/// from the [Decoder](crate::decoder::Decoder), which is moved to the FLAG
+/// field with [split_mate_suffix].
 ///
 /// Terminates with a [SamPrinterError](crate::errors::SamPrinterError)
-/// if the `query_id` field of [PseudoAln] or the `ones` field
+/// if the `query_name` field of [PseudoAln] or the `ones` field
 /// of [PseudoAln] is None.
 ///
-#[allow(unreachable_code, unused_variables)]
 pub fn format_sam_line<W: std::io::Write>(
     aln: &PseudoAln,
     header: &sam::Header,
+    mates: bool,
     conn: &mut W,
 ) -> Result<(), E> {
-    todo!("Print PseudoAln in SAM format");
     // TODO Better error messages from formatting SAM lines.
-    if aln.query_name.is_none() {
-        return Err(Box::new(crate::errors::SamPrinterError{}))
-    }
-    if aln.ones.is_none() || (aln.ones.is_none() && aln.ones.as_ref().unwrap().is_empty()) {
+    if aln.query_name.is_none() || aln.ones.is_none() {
         return Err(Box::new(crate::errors::SamPrinterError{}))
     }
 
     let mut writer = noodles_sam::io::Writer::new(Vec::new());
+    let name = aln.query_name.as_ref().unwrap();
+    let (name, flags) = if mates { split_mate_suffix(name) } else { (name.as_slice(), 0) };
+    let flags = sam::alignment::record::Flags::from(flags) | sam::alignment::record::Flags::UNMAPPED;
 
+    let ones = aln.ones.as_ref().unwrap();
+    if ones.is_empty() {
+        let record = sam::alignment::RecordBuf::builder()
+            .set_name(name.to_vec())
+            .set_flags(flags)
+            .build();
+        writer.write_alignment_record(header, &record)?;
+    }
 
-    // TODO Print unaligned records when formatting SAM lines.
-
-    for target_id in aln.ones.as_ref().unwrap() {
+    for target_id in ones {
         let record = sam::alignment::RecordBuf::builder()
-            .set_name(aln.query_name.clone().unwrap())
+            .set_name(name.to_vec())
+            .set_flags(flags)
             .set_reference_sequence_id(*target_id as usize)
             .build();
         writer.write_alignment_record(header, &record)?;
@@ -105,7 +120,6 @@ pub fn format_sam_header<W: std::io::Write>(
 mod tests {
 
     #[test]
-    #[should_panic(expected = "not yet implemented")]
     fn format_sam_line_aligned() {
         // use crate::headers::file::FileHeader;
         use crate::headers::file::FileFlags;
@@ -115,7 +129,7 @@ mod tests {
 
         // Build header
         // let fheader = FileHeader { n_targets: 2, ..Default::default() };
-        let fflags = FileFlags { target_names: vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], query_name: "test.fastq".as_bytes().to_vec() };
+        let fflags = FileFlags { target_names: vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], query_name: "test.fastq".as_bytes().to_vec(), ..Default::default() };
         let mut expected: Vec<u8> = b"@HD\tVN:1.6\n".to_vec();
         expected.append(&mut b"@SQ\tSN:chr.fasta\tLN:1\n".to_vec());
         expected.append(&mut b"@SQ\tSN:plasmid.fasta\tLN:1\n".to_vec());
@@ -128,11 +142,43 @@ mod tests {
         let expected: Vec<u8> =b"ERR4035126.1\t4\tplasmid.fasta\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec();
 
         let mut got: Vec<u8> = Vec::new();
-        format_sam_line(&data, &header, &mut got).unwrap();
+        format_sam_line(&data, &header, false, &mut got).unwrap();
 
         assert_eq!(got.iter().map(|x| *x as char).collect::<String>(), expected.iter().map(|x| *x as char).collect::<String>())
     }
 
+    #[test]
+    fn format_sam_line_unaligned() {
+        use super::build_sam_header;
+        use super::format_sam_line;
+        use crate::PseudoAln;
+
+        let header = build_sam_header(&["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()]).unwrap();
+
+        let data = PseudoAln{ones_names: None, query_id: None, ones: Some(vec![]), query_name: Some("ERR4035126.2".as_bytes().to_vec()) };
+
+        let expected: Vec<u8> = b"ERR4035126.2\t4\t*\t0\t255\t*\t*\t0\t0\t*\t*\n".to_vec();
+
+        let mut got: Vec<u8> = Vec::new();
+        format_sam_line(&data, &header, false, &mut got).unwrap();
+
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn format_sam_line_missing_query_name() {
+        use super::build_sam_header;
+        use super::format_sam_line;
+        use crate::PseudoAln;
+
+        let header = build_sam_header(&["chr.fasta".as_bytes().to_vec()]).unwrap();
+
+        let data = PseudoAln{ones_names: None, query_id: Some(0), ones: Some(vec![0]), query_name: None };
+
+        let mut got: Vec<u8> = Vec::new();
+        assert!(format_sam_line(&data, &header, false, &mut got).is_err());
+    }
+
     #[test]
     fn build_sam_header() {
         // use crate::headers::file::FileHeader;
@@ -141,7 +187,7 @@ mod tests {
         use super::format_sam_header;
 
         // let fheader = FileHeader { n_targets: 2, ..Default::default() };
-        let fflags = FileFlags { target_names: vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], query_name: "test.fastq".as_bytes().to_vec() };
+        let fflags = FileFlags { target_names: vec!["chr.fasta".as_bytes().to_vec(), "plasmid.fasta".as_bytes().to_vec()], query_name: "test.fastq".as_bytes().to_vec(), ..Default::default() };
 
         let mut expected: Vec<u8> = b"@HD\tVN:1.6\n".to_vec();
         expected.append(&mut b"@SQ\tSN:chr.fasta\tLN:1\n".to_vec());
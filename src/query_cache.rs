@@ -0,0 +1,264 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Cache the query names read from a .fastX file.
+//!
+//! Parsing the query names from a large .fastX file often takes longer than
+//! encoding or converting the alignment itself. A [QueryCache] stores the
+//! names in a compact sidecar file together with their count and a
+//! [FastxStamp] of the .fastX file, so later runs can load the names instead
+//! of parsing the .fastX file again.
+//!
+//! The stamp is the length, modification time, and inode of the .fastX file
+//! as reported by the file system, so checking it does not read the file. A
+//! CRC32 of the file can be added to the stamp with [fastx_checksum] to also
+//! catch files that were rewritten without changing their metadata. A cache
+//! is stale if its stamp does not match the .fastX file and should then be
+//! rebuilt.
+//!
+//! The sidecar file contains [QUERY_CACHE_MAGIC] followed by the QueryCache
+//! encoded with bincode and compressed with deflate.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::query_cache::FastxStamp;
+//! use ahda::query_cache::QueryCache;
+//! use ahda::query_cache::fastx_checksum;
+//! use std::io::Cursor;
+//!
+//! let fastq = b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nIIII\n".to_vec();
+//! let (len, crc32) = fastx_checksum(&mut fastq.as_slice()).unwrap();
+//! let stamp = FastxStamp{ len, crc32: Some(crc32), ..Default::default() };
+//!
+//! let cache = QueryCache::new(vec![b"r1".to_vec(), b"r2".to_vec()], stamp.clone());
+//! let mut sidecar: Vec<u8> = Vec::new();
+//! cache.write_to(&mut sidecar).unwrap();
+//!
+//! let got = QueryCache::read_from(&mut Cursor::new(sidecar)).unwrap();
+//! assert!(got.matches(&stamp));
+//! assert_eq!(got.names, vec![b"r1".to_vec(), b"r2".to_vec()]);
+//! ```
+//!
+
+use crate::compression::gzwrapper::deflate_bytes;
+use crate::compression::gzwrapper::inflate_bytes;
+
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use bincode::{Encode, Decode};
+use bincode::decode_from_slice;
+use bincode::encode_to_vec;
+
+type E = Box<dyn std::error::Error>;
+
+/// First bytes of a query name cache file.
+pub const QUERY_CACHE_MAGIC: [u8; 8] = *b"AHDAQNC2";
+
+/// Identity of a .fastX file on disk.
+#[derive(Encode, Decode, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FastxStamp {
+    /// Length of the file in bytes.
+    pub len: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub mtime_ns: u64,
+    /// Inode of the file, 0 on platforms without inodes.
+    pub inode: u64,
+    /// CRC32 of the file, if it was computed.
+    pub crc32: Option<u32>,
+}
+
+/// Query names read from a .fastX file.
+#[derive(Encode, Decode, Clone, Debug, Default, PartialEq)]
+pub struct QueryCache {
+    /// Number of queries.
+    pub n_queries: u64,
+    /// Stamp of the .fastX file the names were read from.
+    pub fastx: FastxStamp,
+    /// Query names in the order they appear in the .fastX file.
+    pub names: Vec<Vec<u8>>,
+}
+
+impl QueryCache {
+    pub fn new(
+        names: Vec<Vec<u8>>,
+        fastx: FastxStamp,
+    ) -> Self {
+        QueryCache{ n_queries: names.len() as u64, fastx, names }
+    }
+
+    /// Check if the cache was built from a .fastX file with this stamp.
+    ///
+    /// The CRC32 is only compared if `fastx` has one. A cache without a
+    /// CRC32 never matches a stamp with one.
+    pub fn matches(
+        &self,
+        fastx: &FastxStamp,
+    ) -> bool {
+        self.fastx.len == fastx.len && self.fastx.mtime_ns == fastx.mtime_ns && self.fastx.inode == fastx.inode &&
+            (fastx.crc32.is_none() || self.fastx.crc32 == fastx.crc32)
+    }
+
+    /// Write the cache to `conn`.
+    pub fn write_to<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        let bytes = encode_to_vec(self, bincode::config::standard())?;
+        conn.write_all(&QUERY_CACHE_MAGIC)?;
+        conn.write_all(&deflate_bytes(&bytes)?)?;
+        conn.flush()?;
+        Ok(())
+    }
+
+    /// Read a cache written by [write_to](QueryCache::write_to) from `conn`.
+    ///
+    /// Errors with [InvalidQueryCacheErr](crate::errors::InvalidQueryCacheErr)
+    /// if `conn` does not contain a query name cache or if the number of
+    /// names does not match the stored count.
+    ///
+    pub fn read_from<R: Read>(
+        conn: &mut R,
+    ) -> Result<Self, E> {
+        let mut bytes: Vec<u8> = Vec::new();
+        conn.read_to_end(&mut bytes)?;
+        if !bytes.starts_with(&QUERY_CACHE_MAGIC) {
+            return Err(Box::new(crate::errors::InvalidQueryCacheErr{}))
+        }
+
+        let inflated = inflate_bytes(&bytes[QUERY_CACHE_MAGIC.len()..])?;
+        let cache: QueryCache = decode_from_slice(&inflated, bincode::config::standard())?.0;
+        if cache.n_queries != cache.names.len() as u64 {
+            return Err(Box::new(crate::errors::InvalidQueryCacheErr{}))
+        }
+        Ok(cache)
+    }
+}
+
+/// Reads the stamp of the .fastX file at `path` from its metadata.
+///
+/// If `checksum` is true, the file is also read to compute its CRC32.
+///
+/// ## Errors and panics
+///
+/// Returns an error if the metadata can't be read, or if `checksum` is true
+/// and the file can't be read or changed length while it was read.
+///
+pub fn fastx_stamp(
+    path: &Path,
+    checksum: bool,
+) -> Result<FastxStamp, E> {
+    let metadata = std::fs::metadata(path)?;
+    let mtime_ns = metadata.modified().ok()
+        .and_then(|x| x.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or(0);
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+
+    let mut stamp = FastxStamp{ len: metadata.len(), mtime_ns, inode, crc32: None };
+    if checksum {
+        let (len, crc32) = fastx_checksum(&mut std::fs::File::open(path)?)?;
+        if len != stamp.len {
+            return Err(Box::new(std::io::Error::other(format!("{} changed while it was read", path.display()))))
+        }
+        stamp.crc32 = Some(crc32);
+    }
+    Ok(stamp)
+}
+
+/// Computes the length and CRC32 of the bytes in `conn`.
+pub fn fastx_checksum<R: Read>(
+    conn: &mut R,
+) -> Result<(u64, u32), E> {
+    let mut crc = flate2::Crc::new();
+    let mut len: u64 = 0;
+    let mut buf: Vec<u8> = vec![0; 1 << 16];
+    loop {
+        let nbytes = match conn.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Box::new(e)),
+        };
+        crc.update(&buf[0..nbytes]);
+        // Crc::amount is a u32 and wraps for files over 4 GiB
+        len += nbytes as u64;
+    }
+    Ok((len, crc.sum()))
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn query_cache_detects_changed_fastx() {
+        use super::FastxStamp;
+        use super::QueryCache;
+        use super::fastx_checksum;
+
+        use std::io::Cursor;
+
+        let fastq = b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nIIII\n".to_vec();
+        let (len, crc32) = fastx_checksum(&mut fastq.as_slice()).unwrap();
+        assert_eq!(len, fastq.len() as u64);
+        let stamp = FastxStamp{ len, mtime_ns: 1_700_000_000_000_000_000, inode: 42, crc32: None };
+
+        let cache = QueryCache::new(vec![b"r1".to_vec(), b"r2".to_vec()], FastxStamp{ crc32: Some(crc32), ..stamp.clone() });
+        let mut sidecar: Vec<u8> = Vec::new();
+        cache.write_to(&mut sidecar).unwrap();
+        let got = QueryCache::read_from(&mut Cursor::new(sidecar.clone())).unwrap();
+        assert_eq!(got, cache);
+        assert!(got.matches(&stamp));
+        assert!(got.matches(&FastxStamp{ crc32: Some(crc32), ..stamp.clone() }));
+
+        let mut changed = fastq.clone();
+        changed[1] = b'x';
+        let (_, changed_crc32) = fastx_checksum(&mut changed.as_slice()).unwrap();
+        assert!(!got.matches(&FastxStamp{ crc32: Some(changed_crc32), ..stamp.clone() }));
+        assert!(!got.matches(&FastxStamp{ mtime_ns: stamp.mtime_ns + 1, ..stamp.clone() }));
+        assert!(!got.matches(&FastxStamp{ inode: 43, ..stamp.clone() }));
+        assert!(!got.matches(&FastxStamp{ len: len + 1, ..stamp.clone() }));
+
+        let unchecked = QueryCache::new(cache.names.clone(), stamp.clone());
+        assert!(!unchecked.matches(&FastxStamp{ crc32: Some(crc32), ..stamp.clone() }));
+
+        assert!(QueryCache::read_from(&mut Cursor::new(fastq)).is_err());
+        assert!(QueryCache::read_from(&mut Cursor::new(&sidecar[0..(sidecar.len() - 1)])).is_err());
+    }
+
+    #[test]
+    fn fastx_stamp_reads_metadata() {
+        use super::fastx_stamp;
+        use super::fastx_checksum;
+
+        let fastq = b"@r1\nACGT\n+\nIIII\n".to_vec();
+        let path = std::env::temp_dir().join(format!("ahda-fastx-stamp-{}.fastq", std::process::id()));
+        std::fs::write(&path, &fastq).unwrap();
+
+        let stamp = fastx_stamp(&path, false).unwrap();
+        assert_eq!(stamp.len, fastq.len() as u64);
+        assert_eq!(stamp.crc32, None);
+
+        let checked = fastx_stamp(&path, true).unwrap();
+        assert_eq!(checked.crc32, Some(fastx_checksum(&mut fastq.as_slice()).unwrap().1));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,395 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Write the output of the reporting subcommands for machines or humans.
+//!
+//! `ahda stats` and `ahda check-consistency` print one line per field with
+//! the name of the field followed by its values. A [Report] collects these
+//! lines and writes them in a [ReportFormat]: tab-separated like earlier
+//! versions, as a JSON object, or as aligned columns for reading in a
+//! terminal, optionally in color.
+//!
+//! Colors follow the [NO_COLOR](https://no-color.org) convention, see
+//! [use_color].
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::report::Report;
+//! use ahda::report::ReportFormat;
+//! use ahda::report::Value;
+//!
+//! let mut report = Report::default();
+//! report.field("sample", Value::text("ERR4035126"));
+//! report.field("ones", Value::number(42));
+//! report.row("lane", vec![Value::text("L001.fastq"), Value::number(0)]);
+//! report.row("lane", vec![Value::text("L002.fastq"), Value::number(3)]);
+//!
+//! let mut tsv: Vec<u8> = Vec::new();
+//! report.write(&mut tsv, ReportFormat::Tsv, false).unwrap();
+//! assert_eq!(tsv, b"sample\tERR4035126\nones\t42\nlane\tL001.fastq\t0\nlane\tL002.fastq\t3\n");
+//!
+//! let mut json: Vec<u8> = Vec::new();
+//! report.write(&mut json, ReportFormat::Json, false).unwrap();
+//! assert_eq!(json, b"{\"sample\":\"ERR4035126\",\"ones\":42,\"lane\":[[\"L001.fastq\",0],[\"L002.fastq\",3]]}\n");
+//!
+//! let mut pretty: Vec<u8> = Vec::new();
+//! report.write(&mut pretty, ReportFormat::Pretty, false).unwrap();
+//! assert_eq!(pretty, b"sample  ERR4035126\nones    42\nlane    L001.fastq  0\nlane    L002.fastq  3\n");
+//! ```
+//!
+
+use std::io::Write;
+
+type E = Box<dyn std::error::Error>;
+
+/// How a [Report] is written.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// [ReportFormat::Pretty] when writing to a terminal and
+    /// [ReportFormat::Tsv] otherwise, see [ReportFormat::resolve].
+    #[default]
+    Auto,
+    /// One line per field with the name and values separated by tabs.
+    Tsv,
+    /// A JSON object with the fields as keys.
+    Json,
+    /// Aligned columns.
+    Pretty,
+}
+
+impl ReportFormat {
+    /// Replace [ReportFormat::Auto] with the format for an output that is a terminal or not.
+    pub fn resolve(
+        self,
+        is_terminal: bool,
+    ) -> Self {
+        match self {
+            ReportFormat::Auto if is_terminal => ReportFormat::Pretty,
+            ReportFormat::Auto => ReportFormat::Tsv,
+            format => format,
+        }
+    }
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ReportFormat::Auto),
+            "tsv" => Ok(ReportFormat::Tsv),
+            "json" => Ok(ReportFormat::Json),
+            "pretty" => Ok(ReportFormat::Pretty),
+            _ => Err(format!("'{}' is not a valid ReportFormat", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ReportFormat::Auto => write!(f, "auto"),
+            ReportFormat::Tsv => write!(f, "tsv"),
+            ReportFormat::Json => write!(f, "json"),
+            ReportFormat::Pretty => write!(f, "pretty"),
+        }
+    }
+}
+
+/// Check if output to a terminal should be in color.
+///
+/// Returns false if the `NO_COLOR` environment variable is set to a
+/// nonempty value or the output is not a terminal.
+pub fn use_color(
+    is_terminal: bool,
+) -> bool {
+    is_terminal && std::env::var_os("NO_COLOR").is_none_or(|x| x.is_empty())
+}
+
+/// A value in a [Report].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Text, quoted in JSON.
+    Text(String),
+    /// Formatted number, not quoted in JSON.
+    Number(String),
+    /// Unknown value, `-` in text and null in JSON.
+    Missing,
+}
+
+impl Value {
+    /// Text value.
+    pub fn text<T: std::fmt::Display>(
+        x: T,
+    ) -> Self {
+        Value::Text(x.to_string())
+    }
+
+    /// Number formatted with [Display](std::fmt::Display), eg. `format!("{:.4}", x)` for fixed precision.
+    pub fn number<T: std::fmt::Display>(
+        x: T,
+    ) -> Self {
+        Value::Number(x.to_string())
+    }
+
+    fn as_str(
+        &self,
+    ) -> &str {
+        match self {
+            Value::Text(x) | Value::Number(x) => x,
+            Value::Missing => "-",
+        }
+    }
+
+    fn write_json<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        match self {
+            Value::Text(x) => write_json_string(conn, x),
+            Value::Number(x) => Ok(write!(conn, "{}", x)?),
+            Value::Missing => Ok(write!(conn, "null")?),
+        }
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(x: Option<T>) -> Self {
+        x.map_or(Value::Missing, |x| x.into())
+    }
+}
+
+fn write_json_string<W: Write>(
+    conn: &mut W,
+    x: &str,
+) -> Result<(), E> {
+    write!(conn, "\"")?;
+    for c in x.chars() {
+        match c {
+            '"' => write!(conn, "\\\"")?,
+            '\\' => write!(conn, "\\\\")?,
+            '\n' => write!(conn, "\\n")?,
+            '\t' => write!(conn, "\\t")?,
+            '\r' => write!(conn, "\\r")?,
+            c if (c as u32) < 0x20 => write!(conn, "\\u{:04x}", c as u32)?,
+            c => write!(conn, "{}", c)?,
+        }
+    }
+    write!(conn, "\"")?;
+    Ok(())
+}
+
+/// A field of a [Report].
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    /// Field with one value, a scalar in JSON.
+    Single(String, Value),
+    /// One of the rows of a field that may repeat, an array of arrays in JSON.
+    Row(String, Vec<Value>),
+}
+
+impl Field {
+    fn name(
+        &self,
+    ) -> &str {
+        match self {
+            Field::Single(name, _) | Field::Row(name, _) => name,
+        }
+    }
+
+    fn values(
+        &self,
+    ) -> &[Value] {
+        match self {
+            Field::Single(_, value) => std::slice::from_ref(value),
+            Field::Row(_, values) => values,
+        }
+    }
+}
+
+/// Named fields written in order, see the [module documentation](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    fields: Vec<Field>,
+}
+
+impl Report {
+    /// Add field `name` with one `value`.
+    pub fn field(
+        &mut self,
+        name: &str,
+        value: Value,
+    ) {
+        self.fields.push(Field::Single(name.to_string(), value));
+    }
+
+    /// Add a row of `values` to field `name`, which may have any number of rows.
+    ///
+    /// In JSON, the rows of a field are an array even if there is only one.
+    pub fn row(
+        &mut self,
+        name: &str,
+        values: Vec<Value>,
+    ) {
+        self.fields.push(Field::Row(name.to_string(), values));
+    }
+
+    /// Write the report to `conn` in `format`.
+    ///
+    /// [ReportFormat::Auto] is written as [ReportFormat::Tsv], resolve it
+    /// first with [ReportFormat::resolve]. If `color` is true, the field
+    /// names of [ReportFormat::Pretty] are written in bold.
+    ///
+    /// ## Errors and panics
+    /// Errors if writing to `conn` fails.
+    ///
+    pub fn write<W: Write>(
+        &self,
+        conn: &mut W,
+        format: ReportFormat,
+        color: bool,
+    ) -> Result<(), E> {
+        match format {
+            ReportFormat::Json => self.write_json(conn),
+            ReportFormat::Pretty => self.write_pretty(conn, color),
+            _ => self.write_tsv(conn),
+        }
+    }
+
+    fn write_tsv<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        for field in self.fields.iter() {
+            write!(conn, "{}", field.name())?;
+            for value in field.values() {
+                write!(conn, "\t{}", value.as_str())?;
+            }
+            writeln!(conn)?;
+        }
+        Ok(())
+    }
+
+    fn write_json<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        // Rows of a field are gathered under the first occurrence of its name
+        let mut names: Vec<&str> = Vec::new();
+        for field in self.fields.iter() {
+            if !names.contains(&field.name()) {
+                names.push(field.name());
+            }
+        }
+        write!(conn, "{{")?;
+        for (idx, name) in names.iter().enumerate() {
+            if idx > 0 {
+                write!(conn, ",")?;
+            }
+            write_json_string(conn, name)?;
+            write!(conn, ":")?;
+            let fields: Vec<&Field> = self.fields.iter().filter(|x| x.name() == *name).collect();
+            match fields[0] {
+                Field::Single(_, value) => value.write_json(conn)?,
+                Field::Row(..) => {
+                    write!(conn, "[")?;
+                    for (row, field) in fields.iter().enumerate() {
+                        write!(conn, "{}[", if row > 0 { "," } else { "" })?;
+                        for (col, value) in field.values().iter().enumerate() {
+                            if col > 0 {
+                                write!(conn, ",")?;
+                            }
+                            value.write_json(conn)?;
+                        }
+                        write!(conn, "]")?;
+                    }
+                    write!(conn, "]")?;
+                },
+            }
+        }
+        writeln!(conn, "}}")?;
+        Ok(())
+    }
+
+    fn write_pretty<W: Write>(
+        &self,
+        conn: &mut W,
+        color: bool,
+    ) -> Result<(), E> {
+        let name_width = self.fields.iter().map(|x| x.name().chars().count()).max().unwrap_or(0);
+        // Columns are aligned within the rows of each field
+        let mut widths: std::collections::HashMap<&str, Vec<usize>> = std::collections::HashMap::new();
+        for field in self.fields.iter() {
+            let width = widths.entry(field.name()).or_default();
+            for (col, value) in field.values().iter().enumerate() {
+                if width.len() <= col {
+                    width.push(0);
+                }
+                width[col] = width[col].max(value.as_str().chars().count());
+            }
+        }
+        for field in self.fields.iter() {
+            let padding = " ".repeat(name_width - field.name().chars().count());
+            if color {
+                write!(conn, "\x1b[1m{}\x1b[0m{}", field.name(), padding)?;
+            } else {
+                write!(conn, "{}{}", field.name(), padding)?;
+            }
+            let width = &widths[field.name()];
+            let values = field.values();
+            for (col, value) in values.iter().enumerate() {
+                if col + 1 < values.len() {
+                    write!(conn, "  {:<w$}", value.as_str(), w = width[col])?;
+                } else {
+                    write!(conn, "  {}", value.as_str())?;
+                }
+            }
+            writeln!(conn)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn report_formats() {
+        use super::Report;
+        use super::ReportFormat;
+        use super::Value;
+
+        let mut report = Report::default();
+        report.field("sample", Value::text("a \"quoted\"\tname"));
+        report.field("ones", Value::Missing);
+        report.row("coverage", vec![Value::text("chr.fasta"), Value::number(format!("{:.4}", 0.5))]);
+        report.row("coverage", vec![Value::text("plasmid.fasta"), Value::from(None::<Value>)]);
+
+        let write = |format: ReportFormat, color: bool| {
+            let mut bytes: Vec<u8> = Vec::new();
+            report.write(&mut bytes, format, color).unwrap();
+            String::from_utf8(bytes).unwrap()
+        };
+        assert_eq!(write(ReportFormat::Tsv, false), "sample\ta \"quoted\"\tname\nones\t-\ncoverage\tchr.fasta\t0.5000\ncoverage\tplasmid.fasta\t-\n");
+        assert_eq!(write(ReportFormat::Json, false), "{\"sample\":\"a \\\"quoted\\\"\\tname\",\"ones\":null,\"coverage\":[[\"chr.fasta\",0.5000],[\"plasmid.fasta\",null]]}\n");
+        assert_eq!(write(ReportFormat::Pretty, false), "sample    a \"quoted\"\tname\nones      -\ncoverage  chr.fasta      0.5000\ncoverage  plasmid.fasta  -\n");
+        assert!(write(ReportFormat::Pretty, true).starts_with("\x1b[1msample\x1b[0m    a"));
+
+        assert_eq!(ReportFormat::Auto.resolve(true), ReportFormat::Pretty);
+        assert_eq!(ReportFormat::Auto.resolve(false), ReportFormat::Tsv);
+        assert_eq!(ReportFormat::Json.resolve(true), ReportFormat::Json);
+    }
+}
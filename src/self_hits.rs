@@ -0,0 +1,349 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Remove the hits of simulated reads to the genome they were simulated from.
+//!
+//! Benchmarking the specificity of a decontamination panel with reads
+//! simulated from the genomes in the panel needs the hits to other genomes
+//! only, since every read is expected to hit its own genome. [QuerySources]
+//! maps each query to the targets of its source genome, read from a
+//! tab-separated table of query names and source names with
+//! [QuerySources::from_read], and [exclude_self_hits_from_read_to_write]
+//! removes the hits to these targets from every record of an .ahda file.
+//!
+//! The output records that self-hits were removed in the file extensions
+//! under [SELF_HITS_KEY], see [SelfHits].
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::self_hits::QuerySources;
+//! use ahda::PseudoAln;
+//!
+//! let targets = vec![b"ecoli_chr".to_vec(), b"ecoli_plasmid".to_vec(), b"kpneumoniae_chr".to_vec()];
+//! let table = b"read.1\tecoli_chr\nread.1\tecoli_plasmid\nread.2\tkpneumoniae_chr\n";
+//! let sources = QuerySources::from_read(&mut table.as_slice(), &targets, &[]).unwrap();
+//!
+//! let mut record = PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1, 2]), query_name: Some(b"read.1".to_vec()) };
+//! assert_eq!(sources.apply(&mut record).unwrap(), 2);
+//! assert_eq!(record.ones, Some(vec![2]));
+//! ```
+//!
+
+use crate::EncodeOpts;
+use crate::PseudoAln;
+use crate::compression::flags_dictionary::FLAGS_DICTIONARY_KEY;
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use crate::headers::file::FileFlags;
+use crate::headers::file::TargetGroups;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Write;
+
+use bincode::{Encode, Decode};
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the [SelfHits] stored in the file extensions.
+pub const SELF_HITS_KEY: &[u8] = b"ahda.self_hits";
+
+/// Record that self-hits were removed by [exclude_self_hits_from_read_to_write].
+#[derive(Clone, Copy, Debug, Decode, Default, Encode, PartialEq, Eq)]
+pub struct SelfHits {
+    /// Number of queries with a source in the [QuerySources].
+    pub n_queries: u64,
+}
+
+impl SelfHits {
+    /// Read the summary stored in `flags`, if any.
+    ///
+    /// ## Errors and panics
+    /// Errors if the stored value can't be decoded.
+    ///
+    pub fn from_flags(
+        flags: &FileFlags,
+    ) -> Result<Option<SelfHits>, E> {
+        flags.extensions.as_ref().and_then(|x| x.get_as::<SelfHits>(SELF_HITS_KEY)).transpose()
+    }
+}
+
+/// Targets of the source genome of each query.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuerySources {
+    sources: HashMap<Vec<u8>, Vec<u32>>,
+}
+
+impl QuerySources {
+    /// Read a tab-separated table of query names and their sources.
+    ///
+    /// Each line has the name of a query and the name of a target or a
+    /// target group in `target_groups` that the query was simulated from,
+    /// in which case every target in the group is a source. A query can be
+    /// listed on several lines. The table has no header line:
+    ///
+    /// ```text
+    /// read.1    ecoli_chr
+    /// read.1    ecoli_plasmid
+    /// read.2    K. pneumoniae
+    /// ```
+    ///
+    /// ## Errors and panics
+    /// Errors with [InvalidQuerySourcesErr](crate::errors::InvalidQuerySourcesErr)
+    /// if a line does not have two columns, and with
+    /// [UnknownTargetErr](crate::errors::UnknownTargetErr) if a source is
+    /// not a target or a target group.
+    ///
+    pub fn from_read<R: Read>(
+        conn: &mut R,
+        targets: &[Vec<u8>],
+        target_groups: &[TargetGroups],
+    ) -> Result<Self, E> {
+        let mut bytes: Vec<u8> = Vec::new();
+        conn.read_to_end(&mut bytes)?;
+
+        let target_ids: HashMap<&[u8], u32> = targets.iter().enumerate().map(|(idx, name)| (name.as_slice(), idx as u32)).collect();
+        let mut sources = QuerySources::default();
+        bytes.split(|x| *x == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line)).filter(|line| !line.is_empty()).try_for_each(|line| {
+            let fields: Vec<&[u8]> = line.split(|x| *x == b'\t').collect();
+            if fields.len() != 2 {
+                return Err(Box::new(crate::errors::InvalidQuerySourcesErr{}) as E)
+            }
+            if let Some(target_id) = target_ids.get(fields[1]) {
+                sources.insert(fields[0], *target_id);
+                return Ok(())
+            }
+            let groups = target_groups.iter().filter_map(|x| x.group_names.iter().position(|name| name == fields[1]).map(|group| (x, group as u32)));
+            let mut found = false;
+            for (groups, group) in groups {
+                groups.membership.iter().enumerate().filter(|(_, x)| **x == group).for_each(|(target_id, _)| sources.insert(fields[0], target_id as u32));
+                found = true;
+            }
+            if !found {
+                return Err(Box::new(crate::errors::UnknownTargetErr{}))
+            }
+            Ok(())
+        })?;
+        Ok(sources)
+    }
+
+    /// Add `target_id` to the sources of `query_name`.
+    pub fn insert(
+        &mut self,
+        query_name: &[u8],
+        target_id: u32,
+    ) {
+        let targets = self.sources.entry(query_name.to_vec()).or_default();
+        if let Err(idx) = targets.binary_search(&target_id) {
+            targets.insert(idx, target_id);
+        }
+    }
+
+    /// Sorted target ids of the sources of `query_name`, empty if it has none.
+    pub fn get(
+        &self,
+        query_name: &[u8],
+    ) -> &[u32] {
+        self.sources.get(query_name).map(|x| x.as_slice()).unwrap_or(&[])
+    }
+
+    /// Number of queries with a source.
+    pub fn len(
+        &self,
+    ) -> usize {
+        self.sources.len()
+    }
+
+    /// Check if no query has a source.
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Remove the hits of `record` to the sources of its query.
+    ///
+    /// Returns the number of hits removed.
+    ///
+    /// ## Errors and panics
+    /// Errors with [MissingQueryNamesErr](crate::errors::MissingQueryNamesErr)
+    /// if the record has no query name.
+    ///
+    pub fn apply(
+        &self,
+        record: &mut PseudoAln,
+    ) -> Result<usize, E> {
+        let name = record.query_name.as_ref().ok_or(crate::errors::MissingQueryNamesErr{})?;
+        let sources = self.get(name);
+        let Some(ones) = record.ones.as_mut().filter(|_| !sources.is_empty()) else {
+            return Ok(0)
+        };
+        let n_ones = ones.len();
+        ones.retain(|x| sources.binary_search(x).is_err());
+        Ok(n_ones - ones.len())
+    }
+}
+
+/// Remove the hits of each query to its sources from [Read] and encode to [Write].
+///
+/// The records are streamed through a [Decoder] and an [Encoder]
+/// configured with `opts`, whose sample name is replaced by the sample name
+/// of the input. The output keeps the target groups and extensions of the
+/// input and stores a [SelfHits] under [SELF_HITS_KEY]. Second mates and
+/// stored target aliases of the input are not kept.
+///
+/// Returns the number of hits removed.
+///
+/// ## Usage
+///
+/// ```rust
+/// use ahda::self_hits::exclude_self_hits_from_read_to_write;
+/// use ahda::self_hits::QuerySources;
+/// use ahda::{decode_from_read, encode_to_write};
+/// use ahda::EncodeOpts;
+/// use ahda::PseudoAln;
+///
+/// let targets = vec![b"ecoli".to_vec(), b"kpneumoniae".to_vec()];
+/// let queries = vec![b"read.1".to_vec(), b"read.2".to_vec()];
+/// let data = vec![
+///     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some(b"read.1".to_vec()) },
+///     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: Some(b"read.2".to_vec()) },
+/// ];
+/// let mut opts = EncodeOpts::default();
+/// opts.encode_query_names = true;
+/// let mut bytes: Vec<u8> = Vec::new();
+/// encode_to_write(&targets, &queries, &data, &mut bytes, opts.clone()).unwrap();
+///
+/// let sources = QuerySources::from_read(&mut b"read.1\tecoli\nread.2\tkpneumoniae\n".as_slice(), &targets, &[]).unwrap();
+/// let mut output: Vec<u8> = Vec::new();
+/// let n_removed = exclude_self_hits_from_read_to_write(&mut bytes.as_slice(), &sources, &mut output, &opts).unwrap();
+///
+/// let (_, _, alns) = decode_from_read(&mut output.as_slice()).unwrap();
+/// assert_eq!(n_removed, 2);
+/// assert_eq!(alns[0].ones, Some(vec![1]));
+/// assert_eq!(alns[1].ones, Some(vec![]));
+/// ```
+///
+/// ## Errors and panics
+///
+/// Errors with [MissingQueryNamesErr](crate::errors::MissingQueryNamesErr)
+/// if the input does not store the query names, and if the input can't be
+/// decoded.
+///
+pub fn exclude_self_hits_from_read_to_write<R: Read, W: Write>(
+    conn_in: &mut R,
+    sources: &QuerySources,
+    conn_out: &mut W,
+    opts: &EncodeOpts,
+) -> Result<u64, E> {
+    let mut decoder = Decoder::try_new(conn_in)?;
+    let header = decoder.file_header().clone();
+    let flags = decoder.file_flags().clone();
+    if header.fields_present & crate::MASK_QUERIES == 0 {
+        return Err(Box::new(crate::errors::MissingQueryNamesErr{}))
+    }
+
+    let mut error: Option<E> = None;
+    let mut n_removed: u64 = 0;
+    let mut records = decoder.try_iter().map(|record| record.and_then(|mut x| {
+        n_removed += sources.apply(&mut x)? as u64;
+        Ok(x)
+    })).map_while(|record| record.map_err(|e| error = Some(e)).ok());
+
+    let opts = EncodeOpts{ accession: flags.query_name.clone(), encode_query_names: true, target_groups: flags.target_groups.clone(), ..opts.clone() };
+    let mut encoder = Encoder::new(&mut records, &flags.target_names, &opts.accession, header.n_queries as usize);
+    crate::configure_encoder(&mut encoder, &opts, true)?;
+    // The blocks are compressed again, so a dictionary of the input is not needed
+    let mut extensions = flags.extensions.clone().unwrap_or_default();
+    extensions.remove(FLAGS_DICTIONARY_KEY);
+    extensions.insert_as(SELF_HITS_KEY, &SelfHits{ n_queries: sources.len() as u64 })?;
+    encoder.set_extensions(extensions)?;
+
+    conn_out.write_all(&encoder.encode_file_header_and_flags()?)?;
+    for block in encoder.by_ref() {
+        conn_out.write_all(&block?)?;
+    }
+    conn_out.flush()?;
+    drop(encoder);
+    drop(records);
+
+    if let Some(e) = error {
+        return Err(e)
+    }
+    Ok(n_removed)
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn sources_from_targets_and_groups() {
+        use super::QuerySources;
+        use crate::headers::file::TargetGroups;
+
+        let targets = vec![b"chr_a".to_vec(), b"plasmid_a".to_vec(), b"chr_b".to_vec()];
+        let groups = vec![TargetGroups{ level: b"genome".to_vec(), group_names: vec![b"A".to_vec(), b"B".to_vec()], membership: vec![0, 0, 1] }];
+
+        let sources = QuerySources::from_read(&mut b"r1\tA\r\nr2\tchr_b\nr2\tchr_a\n\n".as_slice(), &targets, &groups).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources.get(b"r1"), &[0, 1]);
+        assert_eq!(sources.get(b"r2"), &[0, 2]);
+        assert!(sources.get(b"r3").is_empty());
+
+        assert!(QuerySources::from_read(&mut b"r1\tC\n".as_slice(), &targets, &groups).is_err());
+        assert!(QuerySources::from_read(&mut b"r1\n".as_slice(), &targets, &groups).is_err());
+    }
+
+    #[test]
+    fn exclude_self_hits_keeps_flags() {
+        use super::exclude_self_hits_from_read_to_write;
+        use super::QuerySources;
+        use super::SelfHits;
+        use crate::decode_from_read;
+        use crate::encode_to_write;
+        use crate::headers::file::TargetGroups;
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr_a".to_vec(), b"chr_b".to_vec()];
+        let queries = vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+        let data = vec![
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some(b"r1".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![0]), query_name: Some(b"r2".to_vec()) },
+            PseudoAln{ ones_names: None, query_id: Some(2), ones: Some(vec![1]), query_name: Some(b"r3".to_vec()) },
+        ];
+        let groups = vec![TargetGroups{ level: b"genome".to_vec(), group_names: vec![b"A".to_vec(), b"B".to_vec()], membership: vec![0, 1] }];
+        let opts = EncodeOpts{ accession: b"sim".to_vec(), encode_query_names: true, target_groups: groups.clone(), store_panel: true, ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
+
+        let sources = QuerySources::from_read(&mut b"r1\tA\nr3\tchr_a\n".as_slice(), &targets, &groups).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let n_removed = exclude_self_hits_from_read_to_write(&mut bytes.as_slice(), &sources, &mut output, &EncodeOpts::default()).unwrap();
+        assert_eq!(n_removed, 1);
+
+        let (_, flags, alns) = decode_from_read(&mut output.as_slice()).unwrap();
+        assert_eq!(alns.iter().map(|x| x.ones.clone().unwrap()).collect::<Vec<Vec<u32>>>(), vec![vec![1], vec![0], vec![1]]);
+        assert_eq!(flags.query_name, b"sim".to_vec());
+        assert_eq!(flags.target_groups, groups);
+        assert!(crate::panel::Panel::from_flags(&flags).unwrap().is_some());
+        assert_eq!(SelfHits::from_flags(&flags).unwrap(), Some(SelfHits{ n_queries: 2 }));
+
+        // Query names are needed to find the sources
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, EncodeOpts::default()).unwrap();
+        assert!(exclude_self_hits_from_read_to_write(&mut bytes.as_slice(), &sources, &mut Vec::new(), &EncodeOpts::default()).is_err());
+    }
+}
@@ -0,0 +1,673 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Answer queries about .ahda files held in memory over HTTP.
+//!
+//! A [Store] indexes the blocks of each loaded file with a [BlockIndex] and
+//! answers GET requests by decompressing only the blocks that a request
+//! needs. [serve] accepts connections on a [TcpListener] and answers one
+//! request per connection with a fixed number of threads, see [ServeOpts].
+//! Responses are [Reports](Report) written as JSON,
+//! or as TSV with `?format=tsv`:
+//!
+//! - `/files` lists the files with their number of queries, targets, and blocks.
+//! - `/files/{file}` has the sample name and the file header of `file`.
+//! - `/files/{file}/targets` has the number of queries that aligned to each target.
+//! - `/files/{file}/queries/{query_id}` has the name and targets of a query.
+//! - `/files/{file}/queries?start={start}&end={end}` has the targets of the
+//!   queries with `start <= query_id < end`.
+//! - `/files/{file}/slice?targets={name},{name}` has the targets of the
+//!   queries that aligned to any of the named targets. Blocks whose
+//!   [TargetSummary] has none of them are skipped. Add `start` and `end` to
+//!   only slice the queries with `start <= query_id < end`.
+//!
+//! Ranges and slices with more than [MAX_QUERY_RANGE] queries are refused
+//! with status 400.
+//!
+//! Files are named by their file name without the `.ahda` extension. There
+//! is no authentication, so the service should only listen on a trusted
+//! network. Requires the `serve` feature.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::serve::Store;
+//! use ahda::encode_to_write;
+//! use ahda::EncodeOpts;
+//! use ahda::PseudoAln;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let queries = vec![b"r1".to_vec(), b"r2".to_vec()];
+//! let data = vec![
+//!     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 1]), query_name: Some(b"r1".to_vec()) },
+//!     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![1]), query_name: Some(b"r2".to_vec()) },
+//! ];
+//! let mut opts = EncodeOpts::default();
+//! opts.encode_query_names = true;
+//! let mut bytes: Vec<u8> = Vec::new();
+//! encode_to_write(&targets, &queries, &data, &mut bytes, opts).unwrap();
+//!
+//! let store = Store::new(vec![("sample".to_string(), bytes.as_slice())]).unwrap();
+//! let response = store.handle("GET", "/files/sample/queries/1");
+//! assert_eq!(response.status, 200);
+//! assert_eq!(response.body, b"{\"query_id\":1,\"query_name\":\"r2\",\"targets\":[[\"plasmid.fasta\"]]}\n");
+//! ```
+//!
+
+use crate::decoder::block_index::BlockIndex;
+use crate::report::Report;
+use crate::report::ReportFormat;
+use crate::report::Value;
+use crate::target_summary::TargetSummary;
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use roaring::RoaringBitmap;
+use roaring::RoaringTreemap;
+
+type E = Box<dyn std::error::Error>;
+
+/// Maximum number of queries in the response to a range of query ids or a slice.
+pub const MAX_QUERY_RANGE: u32 = 100_000;
+
+/// Options for [serve].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServeOpts {
+    /// Number of threads answering requests.
+    pub threads: usize,
+
+    /// Time to wait for a client to send its request or read the response,
+    /// or None to wait forever.
+    pub timeout: Option<Duration>,
+
+    /// Maximum length of the request line and headers in bytes.
+    pub max_request_len: u64,
+}
+
+impl Default for ServeOpts {
+    /// Default options for [serve].
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use ahda::serve::ServeOpts;
+    /// use std::time::Duration;
+    ///
+    /// let opts = ServeOpts{
+    ///     threads: 4,
+    ///     timeout: Some(Duration::from_secs(10)),
+    ///     max_request_len: 8192,
+    /// };
+    /// # let expected = ServeOpts::default();
+    /// # assert_eq!(opts, expected);
+    /// ```
+    ///
+    fn default() -> ServeOpts {
+        ServeOpts{
+            threads: 4,
+            timeout: Some(Duration::from_secs(10)),
+            max_request_len: 8192,
+        }
+    }
+}
+
+/// Response to a request, see [Store::handle].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    /// HTTP status code.
+    pub status: u16,
+    /// Content type of `body`.
+    pub content_type: &'static str,
+    /// Body of the response.
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn from_report(
+        report: &Report,
+        format: ReportFormat,
+    ) -> Result<Self, E> {
+        let mut body: Vec<u8> = Vec::new();
+        report.write(&mut body, format, false)?;
+        let content_type = if format == ReportFormat::Json { "application/json" } else { "text/tab-separated-values" };
+        Ok(Response{ status: 200, content_type, body })
+    }
+
+    fn error(
+        status: u16,
+        msg: &str,
+    ) -> Self {
+        let mut report = Report::default();
+        report.field("error", Value::text(msg));
+        let mut body: Vec<u8> = Vec::new();
+        // Writing to memory can't fail
+        report.write(&mut body, ReportFormat::Json, false).unwrap();
+        Response{ status, content_type: "application/json", body }
+    }
+
+    fn reason(
+        &self,
+    ) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+
+    /// Write the response as HTTP/1.1 to `conn`.
+    pub fn write_http<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        write!(conn, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", self.status, self.reason(), self.content_type, self.body.len())?;
+        conn.write_all(&self.body)?;
+        conn.flush()?;
+        Ok(())
+    }
+}
+
+/// A loaded file.
+struct StoredFile<'a> {
+    name: String,
+    index: BlockIndex<'a>,
+    // Number of queries aligned to each target, counted on first use
+    target_counts: OnceLock<Vec<u64>>,
+}
+
+/// .ahda files that are queried by [serve].
+pub struct Store<'a> {
+    files: Vec<StoredFile<'a>>,
+}
+
+impl<'a> Store<'a> {
+    /// Index the files in `files`, given as pairs of a name and the bytes of the file.
+    ///
+    /// ## Errors and panics
+    /// Errors with [DuplicateFileNameErr](crate::errors::DuplicateFileNameErr)
+    /// if two files have the same name, and if the blocks of a file can't be
+    /// indexed, see [BlockIndex::new].
+    ///
+    pub fn new(
+        files: Vec<(String, &'a [u8])>,
+    ) -> Result<Self, E> {
+        for (idx, (name, _)) in files.iter().enumerate() {
+            if files[..idx].iter().any(|(x, _)| x == name) {
+                return Err(Box::new(crate::errors::DuplicateFileNameErr{ name: name.clone() }))
+            }
+        }
+        let files = files.into_iter().map(|(name, bytes)| {
+            Ok(StoredFile{ name, index: BlockIndex::new(bytes)?, target_counts: OnceLock::new() })
+        }).collect::<Result<Vec<StoredFile>, E>>()?;
+        Ok(Store{ files })
+    }
+
+    /// Answer a request for `target`, the path and query string of the URL.
+    ///
+    /// Only GET requests are supported. Unknown files, queries, and paths
+    /// are answered with status 404, and invalid parameters with 400.
+    pub fn handle(
+        &self,
+        method: &str,
+        target: &str,
+    ) -> Response {
+        if method != "GET" {
+            return Response::error(405, "only GET requests are supported")
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let params: HashMap<String, String> = query.split('&').filter(|x| !x.is_empty()).map(|x| {
+            let (key, value) = x.split_once('=').unwrap_or((x, ""));
+            (percent_decode(key), percent_decode(value))
+        }).collect();
+        let format = match params.get("format").map(|x| x.as_str()) {
+            None | Some("json") => ReportFormat::Json,
+            Some("tsv") => ReportFormat::Tsv,
+            Some(x) => return Response::error(400, &format!("unknown format `{}`, use json or tsv", x)),
+        };
+
+        let parts: Vec<String> = path.split('/').filter(|x| !x.is_empty()).map(percent_decode).collect();
+        let parts: Vec<&str> = parts.iter().map(|x| x.as_str()).collect();
+        let report = match parts.as_slice() {
+            ["files"] => Ok(self.files_report()),
+            ["files", name, rest @ ..] => {
+                let Some(file) = self.files.iter().find(|x| x.name == *name) else {
+                    return Response::error(404, &format!("no file named `{}`", name))
+                };
+                match rest {
+                    [] => Ok(file_report(file)),
+                    ["targets"] => target_counts_report(file),
+                    ["queries", query_id] => match query_id.parse::<u32>() {
+                        Ok(query_id) => match query_report(file, query_id) {
+                            Ok(Some(report)) => Ok(report),
+                            Ok(None) => return Response::error(404, &format!("no query {} in `{}`", query_id, name)),
+                            Err(e) => Err(e),
+                        },
+                        Err(_) => return Response::error(400, &format!("query id `{}` is not a number", query_id)),
+                    },
+                    ["queries"] => {
+                        let range = (params.get("start").map(|x| x.parse::<u32>()), params.get("end").map(|x| x.parse::<u32>()));
+                        match range {
+                            (Some(Ok(start)), Some(Ok(end))) if end >= start && end - start <= MAX_QUERY_RANGE => query_range_report(file, start, end),
+                            (Some(Ok(_)), Some(Ok(_))) => return Response::error(400, &format!("`end` must be at least `start` and at most {} more", MAX_QUERY_RANGE)),
+                            _ => return Response::error(400, "`start` and `end` must be query ids"),
+                        }
+                    },
+                    ["slice"] => {
+                        let names: Vec<&str> = params.get("targets").map(|x| x.split(',').filter(|x| !x.is_empty()).collect()).unwrap_or_default();
+                        let target_names = &file.index.file_flags().target_names;
+                        let mut targets = RoaringBitmap::new();
+                        for name in names.iter() {
+                            match target_names.iter().position(|x| x == name.as_bytes()) {
+                                Some(idx) => { targets.insert(idx as u32); },
+                                None => return Response::error(404, &format!("no target named `{}`", name)),
+                            }
+                        }
+                        if targets.is_empty() {
+                            return Response::error(400, "`targets` must list some target names")
+                        }
+                        let range = (params.get("start").map(|x| x.parse::<u32>()).unwrap_or(Ok(0)), params.get("end").map(|x| x.parse::<u32>()).unwrap_or(Ok(u32::MAX)));
+                        let (Ok(start), Ok(end)) = range else {
+                            return Response::error(400, "`start` and `end` must be query ids")
+                        };
+                        match slice_report(file, &targets, start, end) {
+                            Ok(Some(report)) => Ok(report),
+                            Ok(None) => return Response::error(400, &format!("more than {} queries aligned to `targets`, use `start` and `end` to page through them", MAX_QUERY_RANGE)),
+                            Err(e) => Err(e),
+                        }
+                    },
+                    _ => return Response::error(404, &format!("unknown path `{}`", path)),
+                }
+            },
+            _ => return Response::error(404, &format!("unknown path `{}`", path)),
+        };
+        match report.and_then(|x| Response::from_report(&x, format)) {
+            Ok(response) => response,
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    fn files_report(
+        &self,
+    ) -> Report {
+        let mut report = Report::default();
+        for file in self.files.iter() {
+            let header = file.index.file_header();
+            report.row("file", vec![Value::text(&file.name), Value::number(header.n_queries), Value::number(header.n_targets), Value::number(file.index.n_blocks())]);
+        }
+        report
+    }
+}
+
+fn file_report(
+    file: &StoredFile,
+) -> Report {
+    let header = file.index.file_header();
+    let mut report = Report::default();
+    report.field("name", Value::text(&file.name));
+    report.field("sample", Value::text(String::from_utf8_lossy(&file.index.file_flags().query_name)));
+    report.field("n_queries", Value::number(header.n_queries));
+    report.field("n_targets", Value::number(header.n_targets));
+    report.field("n_blocks", Value::number(file.index.n_blocks()));
+    report.field("block_size", Value::number(header.block_size));
+    report.field("bitmap_type", Value::number(header.bitmap_type));
+    report.field("fields_present", Value::number(header.fields_present));
+    report
+}
+
+fn target_counts_report(
+    file: &StoredFile,
+) -> Result<Report, E> {
+    let n_targets = file.index.file_header().n_targets as u64;
+    let counts = match file.target_counts.get() {
+        Some(counts) => counts,
+        None => {
+            let mut counts: Vec<u64> = vec![0; n_targets as usize];
+            for block_idx in 0..file.index.n_blocks() {
+                for bit in file.index.block_bitmap(block_idx)? {
+                    counts[(bit % n_targets) as usize] += 1;
+                }
+            }
+            file.target_counts.get_or_init(|| counts)
+        },
+    };
+    let mut report = Report::default();
+    for (name, count) in file.index.file_flags().target_names.iter().zip(counts.iter()) {
+        report.row("target", vec![Value::text(String::from_utf8_lossy(name)), Value::number(count)]);
+    }
+    Ok(report)
+}
+
+fn query_report(
+    file: &StoredFile,
+    query_id: u32,
+) -> Result<Option<Report>, E> {
+    let Some((block_idx, pos)) = file.index.find_query(query_id)? else {
+        return Ok(None)
+    };
+    let flags = file.index.block_flags(block_idx)?;
+    let mut bitmap = file.index.block_bitmap(block_idx)?;
+    let n_targets = file.index.file_header().n_targets as u64;
+    bitmap.remove_range(..(query_id as u64 * n_targets));
+    bitmap.remove_range(((query_id as u64 + 1) * n_targets)..);
+
+    let mut report = Report::default();
+    report.field("query_id", Value::number(query_id));
+    let name = flags.queries.as_ref().and_then(|x| x.get(pos)).map(|x| Value::text(String::from_utf8_lossy(x)));
+    report.field("query_name", Value::from(name));
+    report.row("targets", target_names(file, bitmap.iter().map(|x| x % n_targets))?);
+    Ok(Some(report))
+}
+
+fn query_range_report(
+    file: &StoredFile,
+    start: u32,
+    end: u32,
+) -> Result<Report, E> {
+    let bitmap = file.index.query_range_bitmap(start, end)?;
+    queries_report(file, &bitmap)
+}
+
+// None if more than MAX_QUERY_RANGE queries with `start <= query_id < end` aligned to `targets`
+fn slice_report(
+    file: &StoredFile,
+    targets: &RoaringBitmap,
+    start: u32,
+    end: u32,
+) -> Result<Option<Report>, E> {
+    let n_targets = file.index.file_header().n_targets as u64;
+    let queries = (start as u64)..(end as u64);
+    let mut n_hits: u64 = 0;
+    let mut bitmap = RoaringTreemap::new();
+    for block_idx in 0..file.index.n_blocks() {
+        let flags = file.index.block_flags(block_idx)?;
+        if TargetSummary::from_block_flags(&flags)?.is_some_and(|x| !x.contains_any(targets)) {
+            continue
+        }
+        let block = file.index.block_bitmap(block_idx)?;
+        let hits: RoaringTreemap = block.iter().filter(|bit| targets.contains((bit % n_targets) as u32)).map(|bit| bit / n_targets).filter(|x| queries.contains(x)).collect();
+        n_hits += hits.len();
+        if n_hits > MAX_QUERY_RANGE as u64 {
+            return Ok(None)
+        }
+        // Keep every target of the queries with a hit
+        bitmap.extend(block.iter().filter(|bit| hits.contains(bit / n_targets)));
+    }
+    queries_report(file, &bitmap).map(Some)
+}
+
+// One `query` row with the query id and target names per query with set bits in `bitmap`
+fn queries_report(
+    file: &StoredFile,
+    bitmap: &RoaringTreemap,
+) -> Result<Report, E> {
+    let n_targets = file.index.file_header().n_targets as u64;
+    let mut report = Report::default();
+    let mut bits = bitmap.iter().peekable();
+    while let Some(first) = bits.peek().copied() {
+        let query_id = first / n_targets;
+        let mut ones: Vec<u64> = Vec::new();
+        while let Some(bit) = bits.next_if(|x| x / n_targets == query_id) {
+            ones.push(bit % n_targets);
+        }
+        let mut row = vec![Value::number(query_id)];
+        row.extend(target_names(file, ones.into_iter())?);
+        report.row("query", row);
+    }
+    Ok(report)
+}
+
+fn target_names<I: Iterator<Item=u64>>(
+    file: &StoredFile,
+    target_ids: I,
+) -> Result<Vec<Value>, E> {
+    let names = &file.index.file_flags().target_names;
+    target_ids.map(|x| {
+        let name = names.get(x as usize).ok_or(crate::errors::TargetIndexOutOfRangeErr{})?;
+        Ok(Value::text(String::from_utf8_lossy(name)))
+    }).collect()
+}
+
+fn percent_decode(
+    x: &str,
+) -> String {
+    let bytes = x.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let hex = bytes.get((idx + 1)..(idx + 3)).and_then(|x| std::str::from_utf8(x).ok()).and_then(|x| u8::from_str_radix(x, 16).ok());
+        match (bytes[idx], hex) {
+            (b'%', Some(x)) => {
+                out.push(x);
+                idx += 3;
+            },
+            (b'+', _) => {
+                out.push(b' ');
+                idx += 1;
+            },
+            (x, _) => {
+                out.push(x);
+                idx += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+// Reads the request line and headers of one request and answers it
+fn handle_connection(
+    store: &Store,
+    stream: TcpStream,
+    opts: &ServeOpts,
+) -> Result<(), E> {
+    stream.set_read_timeout(opts.timeout)?;
+    stream.set_write_timeout(opts.timeout)?;
+    let mut reader = BufReader::new(stream.try_clone()?).take(opts.max_request_len);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are not used, a line cut by the length limit ends the request
+    let mut complete = request_line.ends_with('\n');
+    let mut line = String::new();
+    while complete && reader.read_line(&mut line)? > 2 {
+        complete = line.ends_with('\n');
+        line.clear();
+    }
+    let mut fields = request_line.split_whitespace();
+    let response = match (fields.next(), fields.next()) {
+        _ if !complete => Response::error(431, &format!("request line and headers are longer than {} bytes", opts.max_request_len)),
+        (Some(method), Some(target)) => store.handle(method, target),
+        _ => Response::error(400, "malformed request line"),
+    };
+    log::info!("{} {}", request_line.trim_end(), response.status);
+    let mut stream = stream;
+    response.write_http(&mut stream)
+}
+
+/// Answer requests to `listener` from `store`.
+///
+/// Connections are answered by `opts.threads` threads and closed after one
+/// request. New connections wait until a thread is free. Errors accepting a
+/// connection or answering a request are logged and don't stop the
+/// service, so this only returns if the listener stops. A request that
+/// panics only closes its connection.
+///
+pub fn serve(
+    listener: TcpListener,
+    store: &Store,
+    opts: &ServeOpts,
+) -> Result<(), E> {
+    let n_threads = opts.threads.max(1);
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<TcpStream>(n_threads);
+    let receiver = Mutex::new(receiver);
+    std::thread::scope(|s| {
+        for _ in 0..n_threads {
+            s.spawn(|| loop {
+                let next = receiver.lock().unwrap().recv();
+                let Ok(stream) = next else { break };
+                // A panic drops the connection but keeps the thread answering requests
+                match std::panic::catch_unwind(AssertUnwindSafe(|| handle_connection(store, stream, opts))) {
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => log::warn!("can't answer request: {}", e),
+                    Err(_) => log::error!("answering a request panicked, closed the connection"),
+                }
+            });
+        }
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => sender.send(stream)?,
+                Err(e) => {
+                    log::warn!("can't accept connection: {}", e);
+                    // Running out of file descriptors fails every accept until a connection is closed
+                    std::thread::sleep(Duration::from_millis(100));
+                },
+            }
+        }
+        drop(sender);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    fn encoded() -> Vec<u8> {
+        use crate::encoder::Encoder;
+        use crate::PseudoAln;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"phage.fasta".to_vec()];
+        let data: Vec<PseudoAln> = (0..6).map(|id| {
+            PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(if id == 5 { vec![2] } else { vec![id % 2] }), query_name: Some(format!("r{}", id).into_bytes()) }
+        }).collect();
+        let mut iter = data.into_iter();
+        let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 6);
+        encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
+        encoder.set_block_size(2).unwrap();
+        encoder.set_target_summary();
+        let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+        for block in encoder.by_ref() {
+            bytes.append(&mut block.unwrap());
+        }
+        bytes
+    }
+
+    #[test]
+    fn store_answers_requests() {
+        use super::Store;
+
+        let bytes = encoded();
+        let store = Store::new(vec![("sample".to_string(), bytes.as_slice())]).unwrap();
+
+        let body = |target: &str| String::from_utf8(store.handle("GET", target).body).unwrap();
+        assert_eq!(body("/files"), "{\"file\":[[\"sample\",6,3,3]]}\n");
+        assert_eq!(body("/files/sample/targets"), "{\"target\":[[\"chr.fasta\",3],[\"plasmid.fasta\",2],[\"phage.fasta\",1]]}\n");
+        assert_eq!(body("/files/sample/queries/4?format=tsv"), "query_id\t4\nquery_name\tr4\ntargets\tchr.fasta\n");
+        assert_eq!(body("/files/sample/queries?start=1&end=4"), "{\"query\":[[1,\"plasmid.fasta\"],[2,\"chr.fasta\"],[3,\"plasmid.fasta\"]]}\n");
+        assert_eq!(body("/files/sample/slice?targets=phage.fasta%2Cplasmid.fasta"), "{\"query\":[[1,\"plasmid.fasta\"],[3,\"plasmid.fasta\"],[5,\"phage.fasta\"]]}\n");
+        assert_eq!(body("/files/sample/slice?targets=phage.fasta,plasmid.fasta&start=2&end=5"), "{\"query\":[[3,\"plasmid.fasta\"]]}\n");
+
+        assert_eq!(store.handle("GET", "/files/other").status, 404);
+        assert_eq!(store.handle("GET", "/files/sample/queries/6").status, 404);
+        assert_eq!(store.handle("GET", "/files/sample/queries/x").status, 400);
+        assert_eq!(store.handle("GET", "/files/sample/queries?start=4&end=1").status, 400);
+        assert_eq!(store.handle("GET", "/files/sample/slice?targets=virus.fasta").status, 404);
+        assert_eq!(store.handle("GET", "/files/sample/slice?targets=chr.fasta&start=x").status, 400);
+        assert_eq!(store.handle("POST", "/files").status, 405);
+
+        assert!(Store::new(vec![("sample".to_string(), bytes.as_slice()), ("sample".to_string(), bytes.as_slice())]).is_err());
+    }
+
+    #[test]
+    fn target_names_out_of_range_fails() {
+        use super::Store;
+        use super::target_names;
+
+        let bytes = encoded();
+        let store = Store::new(vec![("sample".to_string(), bytes.as_slice())]).unwrap();
+        assert_eq!(target_names(&store.files[0], [2].into_iter()).unwrap().len(), 1);
+        assert!(target_names(&store.files[0], [3].into_iter()).is_err());
+    }
+
+    #[test]
+    fn answer_connection() {
+        use super::handle_connection;
+        use super::ServeOpts;
+        use super::Store;
+        use std::io::Read;
+        use std::io::Write;
+
+        let bytes = encoded();
+        let store = Store::new(vec![("sample".to_string(), bytes.as_slice())]).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = std::thread::spawn(move || {
+            let mut conn = std::net::TcpStream::connect(addr).unwrap();
+            conn.write_all(b"GET /files HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            conn.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        handle_connection(&store, stream, &ServeOpts::default()).unwrap();
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"file\":[[\"sample\",6,3,3]]}\n"));
+    }
+
+    #[test]
+    fn answer_connection_limits_requests() {
+        use super::handle_connection;
+        use super::ServeOpts;
+        use super::Store;
+        use std::io::Read;
+        use std::io::Write;
+        use std::time::Duration;
+
+        let bytes = encoded();
+        let store = Store::new(vec![("sample".to_string(), bytes.as_slice())]).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let opts = ServeOpts{ max_request_len: 64, timeout: Some(Duration::from_millis(100)), ..Default::default() };
+
+        // Request longer than the limit
+        let client = std::thread::spawn(move || {
+            let mut conn = std::net::TcpStream::connect(addr).unwrap();
+            conn.write_all(format!("GET /files/{} HTTP/1.1\r\n\r\n", "x".repeat(100)).as_bytes()).unwrap();
+            let mut response = String::new();
+            conn.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        handle_connection(&store, stream, &opts).unwrap();
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 431 "));
+
+        // Client that never sends its request
+        let conn = std::net::TcpStream::connect(addr).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert!(handle_connection(&store, stream, &opts).is_err());
+        drop(conn);
+    }
+}
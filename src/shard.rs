@@ -0,0 +1,414 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Split an .ahda record into shards that are processed in parallel.
+//!
+//! A [ShardPlan] assigns runs of consecutive blocks to shards of about the
+//! same size in bytes. Each [Shard] is a byte range of the blocks in the
+//! record, which a worker turns into a standalone .ahda record by reading
+//! the file header and flags in the first [header_len](Shard::header_len)
+//! bytes followed by the range, see [extract_shard]. Blocks that store a
+//! reference to an identical earlier block, see
+//! [dedup](crate::compression::dedup), are kept in the same shard as the
+//! block they refer to.
+//!
+//! A [MergePlan] lists the outputs of the workers in shard order, and
+//! `ahda cat --merge-plan` concatenates .ahda outputs in that order.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::shard::extract_shard;
+//! use ahda::shard::plan_shards;
+//! use ahda::encoder::Encoder;
+//! use ahda::decode_from_read;
+//! use ahda::PseudoAln;
+//! use std::io::Cursor;
+//!
+//! let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+//! let data: Vec<PseudoAln> = (0..6).map(|id| {
+//!     PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 2]), query_name: None }
+//! }).collect();
+//!
+//! // Encode in blocks of 2 queries
+//! let mut iter = data.into_iter();
+//! let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 6);
+//! encoder.set_block_size(2).unwrap();
+//! let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+//! for block in encoder.by_ref() {
+//!     bytes.append(&mut block.unwrap());
+//! }
+//!
+//! // One shard per block
+//! let plan = plan_shards(&mut bytes.as_slice(), 3).unwrap();
+//! assert_eq!(plan.shards.len(), 3);
+//! assert_eq!((plan.shards[2].first_block, plan.shards[2].start_query_id), (2, Some(4)));
+//!
+//! let mut shard: Vec<u8> = Vec::new();
+//! extract_shard(&mut Cursor::new(&bytes), &plan.shards[2], &mut shard).unwrap();
+//! let (_, _, alns) = decode_from_read(&mut shard.as_slice()).unwrap();
+//! assert_eq!(alns.iter().map(|x| x.query_id.unwrap()).collect::<Vec<u32>>(), vec![4, 5]);
+//! ```
+//!
+
+use crate::BlockCodec;
+use crate::BlockInfo;
+use crate::headers::file::decode_file_header;
+
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::PathBuf;
+
+type E = Box<dyn std::error::Error>;
+
+/// Blocks of an .ahda record processed by one worker.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Shard {
+    /// Position of the shard in the plan, starting from 0.
+    pub index: usize,
+    /// Number of bytes in the file header and flags at the start of the record.
+    pub header_len: u64,
+    /// Index of the first block in the shard.
+    pub first_block: usize,
+    /// Number of blocks in the shard.
+    pub n_blocks: usize,
+    /// Byte offset of the first block from the start of the record.
+    pub offset: u64,
+    /// Number of bytes in the blocks of the shard.
+    pub len: u64,
+    /// Smallest query id in the shard, or None if its blocks have no query ids.
+    pub start_query_id: Option<u32>,
+    /// Number of records in the shard.
+    pub n_records: u64,
+}
+
+/// Assignment of the blocks of an .ahda record to shards.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShardPlan {
+    /// Shards in the order of their blocks.
+    pub shards: Vec<Shard>,
+}
+
+impl ShardPlan {
+    /// Assign `blocks` listed by [block_info_from_read](crate::block_info_from_read) to at most `n_shards` shards.
+    ///
+    /// Shards are runs of consecutive blocks with about the same number of
+    /// bytes. There are fewer than `n_shards` shards if there are fewer
+    /// blocks, or if references to identical earlier blocks leave fewer
+    /// places to split the record. A record without blocks has no shards.
+    pub fn from_blocks(
+        header_len: u64,
+        blocks: &[BlockInfo],
+        n_shards: usize,
+    ) -> Self {
+        // A shard can't start at a block if it or a later block refers to an earlier one
+        let mut can_split = vec![true; blocks.len()];
+        let mut min_target = usize::MAX;
+        for (idx, block) in blocks.iter().enumerate().rev() {
+            if let BlockCodec::Reference(target) = block.codec {
+                min_target = min_target.min(target);
+            }
+            can_split[idx] = min_target >= idx;
+        }
+
+        // Bytes in the blocks before each block
+        let len_before: Vec<u64> = blocks.iter().scan(0, |len, block| {
+            let before = *len;
+            *len += block.len;
+            Some(before)
+        }).collect();
+        let total_len: u64 = blocks.iter().map(|x| x.len).sum();
+        let splits: Vec<usize> = (1..blocks.len()).filter(|idx| can_split[*idx]).collect();
+
+        // Start each shard at the split closest to its share of the bytes
+        let mut starts: Vec<usize> = if blocks.is_empty() { Vec::new() } else { vec![0] };
+        let n_shards = n_shards.max(1) as u64;
+        for shard in 1..n_shards {
+            let ideal = total_len * shard / n_shards;
+            let last = *starts.last().unwrap_or(&0);
+            let first = splits.partition_point(|idx| *idx <= last);
+            let pos = splits.partition_point(|idx| len_before[*idx] < ideal).max(first);
+            let closest = [pos.checked_sub(1).filter(|x| *x >= first), Some(pos)].into_iter().flatten()
+                .filter_map(|x| splits.get(x))
+                .min_by_key(|idx| len_before[**idx].abs_diff(ideal));
+            if let Some(idx) = closest {
+                starts.push(*idx);
+            }
+        }
+
+        let shards = starts.iter().enumerate().map(|(index, first_block)| {
+            let end = starts.get(index + 1).copied().unwrap_or(blocks.len());
+            let shard_blocks = &blocks[*first_block..end];
+            Shard{
+                index,
+                header_len,
+                first_block: *first_block,
+                n_blocks: shard_blocks.len(),
+                offset: shard_blocks[0].offset,
+                len: shard_blocks.iter().map(|x| x.len).sum(),
+                start_query_id: shard_blocks.iter().filter_map(|x| x.start_query_id).min(),
+                n_records: shard_blocks.iter().map(|x| x.num_records as u64).sum(),
+            }
+        }).collect();
+        ShardPlan{ shards }
+    }
+
+    /// Write the plan to `conn` as a TSV with a header line.
+    ///
+    /// Each line has everything a worker needs to read its shard. Missing
+    /// query ids are written as `-`.
+    pub fn write_tsv<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        writeln!(conn, "shard\theader_len\tfirst_block\tn_blocks\toffset\tlen\tstart_query_id\tn_records")?;
+        for shard in self.shards.iter() {
+            let start = shard.start_query_id.map(|x| x.to_string()).unwrap_or("-".to_string());
+            writeln!(conn, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", shard.index, shard.header_len, shard.first_block, shard.n_blocks, shard.offset, shard.len, start, shard.n_records)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plan the shards of the .ahda record in `conn`, see [ShardPlan::from_blocks].
+///
+/// Only the headers and block flags are decoded.
+///
+/// ## Errors and panics
+/// Errors with [ZstdContainerErr](crate::errors::ZstdContainerErr) if the
+/// record is in a zstd-seekable container, with
+/// [TargetChunksErr](crate::errors::TargetChunksErr) if its blocks split their
+/// targets across sub-blocks, and if the blocks can't be listed,
+/// see [block_info_from_read](crate::block_info_from_read).
+///
+pub fn plan_shards<R: Read>(
+    conn: &mut R,
+    n_shards: usize,
+) -> Result<ShardPlan, E> {
+    let mut header_bytes: Vec<u8> = vec![0; 32];
+    conn.read_exact(&mut header_bytes)?;
+    let header = decode_file_header(&header_bytes)?;
+    if header.is_zstd_seekable() {
+        return Err(Box::new(crate::errors::ZstdContainerErr{}))
+    }
+    if header.has_target_chunks() {
+        return Err(Box::new(crate::errors::TargetChunksErr{}))
+    }
+    let blocks = crate::block_info_from_read(&mut Cursor::new(header_bytes).chain(conn))?;
+    Ok(ShardPlan::from_blocks(32 + header.flags_len, &blocks, n_shards))
+}
+
+/// Write the blocks of `shard` from `conn_in` to `conn_out` as a standalone .ahda record.
+///
+/// The output has the file header and flags of the input followed by the
+/// blocks of the shard, so that the query ids are the same as in the input.
+///
+/// ## Errors and panics
+/// Errors with [TruncatedBlockErr](crate::errors::TruncatedBlockErr) if
+/// `conn_in` ends before the end of the shard.
+///
+pub fn extract_shard<R: Read + Seek, W: Write>(
+    conn_in: &mut R,
+    shard: &Shard,
+    conn_out: &mut W,
+) -> Result<(), E> {
+    for (start, len) in [(0, shard.header_len), (shard.offset, shard.len)] {
+        conn_in.seek(SeekFrom::Start(start))?;
+        if std::io::copy(&mut conn_in.by_ref().take(len), conn_out)? != len {
+            return Err(Box::new(crate::errors::TruncatedBlockErr{}))
+        }
+    }
+    Ok(())
+}
+
+/// Output of the worker that processed one shard.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergeEntry {
+    /// Index of the shard.
+    pub shard: usize,
+    /// Path of the output.
+    pub path: PathBuf,
+    /// Smallest query id in the shard, or None if its blocks have no query ids.
+    pub start_query_id: Option<u32>,
+    /// Number of records in the shard.
+    pub n_records: u64,
+}
+
+/// Outputs of the workers of a [ShardPlan] in the order they are combined.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergePlan {
+    /// One entry per shard, ordered by the shard index.
+    pub entries: Vec<MergeEntry>,
+}
+
+impl MergePlan {
+    /// Name the output of each shard in `plan` `{prefix}{shard}{suffix}`.
+    pub fn new(
+        plan: &ShardPlan,
+        prefix: &str,
+        suffix: &str,
+    ) -> Self {
+        let entries = plan.shards.iter().map(|shard| {
+            MergeEntry{ shard: shard.index, path: PathBuf::from(format!("{}{}{}", prefix, shard.index, suffix)), start_query_id: shard.start_query_id, n_records: shard.n_records }
+        }).collect();
+        MergePlan{ entries }
+    }
+
+    /// Read a plan written by [write_tsv](MergePlan::write_tsv).
+    ///
+    /// The entries are sorted by the shard index.
+    ///
+    /// ## Errors and panics
+    /// Errors with [InvalidMergePlanErr](crate::errors::InvalidMergePlanErr)
+    /// if a line doesn't have the shard index, path, start query id, and
+    /// number of records.
+    ///
+    pub fn from_read<R: Read>(
+        conn: &mut R,
+    ) -> Result<Self, E> {
+        let mut contents = String::new();
+        conn.read_to_string(&mut contents)?;
+
+        let mut entries: Vec<MergeEntry> = Vec::new();
+        for line in contents.lines().skip(1).filter(|x| !x.is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let entry = match fields.as_slice() {
+                [shard, path, start, n_records] => {
+                    let start_query_id = if *start == "-" { Ok(None) } else { start.parse::<u32>().map(Some) };
+                    match (shard.parse::<usize>(), start_query_id, n_records.parse::<u64>()) {
+                        (Ok(shard), Ok(start_query_id), Ok(n_records)) => Some(MergeEntry{ shard, path: PathBuf::from(path), start_query_id, n_records }),
+                        _ => None,
+                    }
+                },
+                _ => None,
+            };
+            entries.push(entry.ok_or(crate::errors::InvalidMergePlanErr{})?);
+        }
+        entries.sort_by_key(|x| x.shard);
+        Ok(MergePlan{ entries })
+    }
+
+    /// Write the plan to `conn` as a TSV with a header line.
+    pub fn write_tsv<W: Write>(
+        &self,
+        conn: &mut W,
+    ) -> Result<(), E> {
+        writeln!(conn, "shard\tpath\tstart_query_id\tn_records")?;
+        for entry in self.entries.iter() {
+            let start = entry.start_query_id.map(|x| x.to_string()).unwrap_or("-".to_string());
+            writeln!(conn, "{}\t{}\t{}\t{}", entry.shard, entry.path.to_string_lossy(), start, entry.n_records)?;
+        }
+        Ok(())
+    }
+
+    /// Paths of the outputs in shard order.
+    pub fn paths(
+        &self,
+    ) -> Vec<PathBuf> {
+        self.entries.iter().map(|x| x.path.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    fn block(
+        index: usize,
+        len: u64,
+        codec: crate::BlockCodec,
+    ) -> crate::BlockInfo {
+        crate::BlockInfo{ index, offset: 100 + 10 * index as u64, len, start_query_id: Some(2 * index as u32), num_records: 2, compressed_len: len, raw_len: len, codec, stats: None }
+    }
+
+    #[test]
+    fn shards_have_equal_bytes() {
+        use super::ShardPlan;
+        use crate::BlockCodec;
+
+        let blocks: Vec<crate::BlockInfo> = (0..6).map(|idx| block(idx, 10, BlockCodec::Gzip)).collect();
+        let plan = ShardPlan::from_blocks(100, &blocks, 3);
+        assert_eq!(plan.shards.iter().map(|x| (x.first_block, x.n_blocks, x.offset, x.len)).collect::<Vec<_>>(), vec![(0, 2, 100, 20), (2, 2, 120, 20), (4, 2, 140, 20)]);
+        assert_eq!((plan.shards[2].start_query_id, plan.shards[2].n_records), (Some(8), 4));
+
+        // Fewer blocks than shards
+        assert_eq!(ShardPlan::from_blocks(100, &blocks[0..2], 4).shards.len(), 2);
+        assert!(ShardPlan::from_blocks(100, &[], 4).shards.is_empty());
+    }
+
+    #[test]
+    fn shards_keep_references_together() {
+        use super::ShardPlan;
+        use crate::BlockCodec;
+
+        // Block 3 refers to block 1, so blocks 1 to 3 are in the same shard
+        let mut blocks: Vec<crate::BlockInfo> = (0..6).map(|idx| block(idx, 10, BlockCodec::Gzip)).collect();
+        blocks[3].codec = BlockCodec::Reference(1);
+        let plan = ShardPlan::from_blocks(100, &blocks, 3);
+        assert_eq!(plan.shards.iter().map(|x| (x.first_block, x.n_blocks)).collect::<Vec<_>>(), vec![(0, 1), (1, 3), (4, 2)]);
+    }
+
+    #[test]
+    fn merge_plan_round_trip() {
+        use super::MergePlan;
+        use super::ShardPlan;
+        use crate::BlockCodec;
+
+        let blocks: Vec<crate::BlockInfo> = (0..4).map(|idx| block(idx, 10, BlockCodec::Gzip)).collect();
+        let plan = MergePlan::new(&ShardPlan::from_blocks(100, &blocks, 2), "out/shard", ".ahda");
+
+        let mut tsv: Vec<u8> = Vec::new();
+        plan.write_tsv(&mut tsv).unwrap();
+        assert_eq!(tsv, b"shard\tpath\tstart_query_id\tn_records\n0\tout/shard0.ahda\t0\t4\n1\tout/shard1.ahda\t4\t4\n".to_vec());
+        assert_eq!(MergePlan::from_read(&mut tsv.as_slice()).unwrap(), plan);
+
+        assert!(MergePlan::from_read(&mut b"shard\tpath\tstart_query_id\tn_records\n0\tout/shard0.ahda\n".as_slice()).is_err());
+    }
+
+    #[test]
+    fn extracted_shards_concatenate_to_input() {
+        use super::extract_shard;
+        use super::plan_shards;
+        use crate::decode_from_read;
+        use crate::encoder::Encoder;
+        use crate::PseudoAln;
+        use std::io::Cursor;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec(), b"phage.fasta".to_vec()];
+        let data: Vec<PseudoAln> = (0..100).map(|id| {
+            PseudoAln{ ones_names: None, query_id: Some(id), ones: Some(vec![id % 3]), query_name: None }
+        }).collect();
+        let mut iter = data.clone().into_iter();
+        let mut encoder = Encoder::new(&mut iter, &targets, b"sample", 100);
+        encoder.set_block_size(8).unwrap();
+        let mut bytes = encoder.encode_file_header_and_flags().unwrap();
+        for block in encoder.by_ref() {
+            bytes.append(&mut block.unwrap());
+        }
+
+        let plan = plan_shards(&mut bytes.as_slice(), 4).unwrap();
+        assert_eq!(plan.shards.len(), 4);
+        let mut got: Vec<PseudoAln> = Vec::new();
+        for shard in plan.shards.iter() {
+            let mut out: Vec<u8> = Vec::new();
+            extract_shard(&mut Cursor::new(&bytes), shard, &mut out).unwrap();
+            let (_, _, mut alns) = decode_from_read(&mut out.as_slice()).unwrap();
+            assert_eq!(alns.len() as u64, shard.n_records);
+            got.append(&mut alns);
+        }
+        assert_eq!(got.iter().map(|x| (x.query_id, x.ones.clone())).collect::<Vec<_>>(), data.iter().map(|x| (x.query_id, x.ones.clone())).collect::<Vec<_>>());
+    }
+}
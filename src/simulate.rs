@@ -0,0 +1,199 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Synthetic pseudoalignment data for benchmarking and bug reports.
+//!
+//! [Simulator] returns [PseudoAln] records where each query aligns to each
+//! target independently with probability `density`. The output is fully
+//! determined by the arguments given to [Simulator::new], so the same data can
+//! be reproduced from the seed without sharing the original files.
+//!
+//! Query names are formatted as `{query_prefix}.{query_id + 1}` and target
+//! names as `{target_prefix}_{target_id + 1}`.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::Format;
+//! use ahda::printer::Printer;
+//! use ahda::simulate::Simulator;
+//!
+//! let mut simulator = Simulator::new(100, 5, 0.2, 42);
+//! let targets = simulator.targets();
+//! let queries = simulator.queries();
+//!
+//! let mut printer = Printer::new(&mut simulator, &targets, b"simulated", queries.len(), Format::Themisto);
+//! let lines: Vec<Vec<u8>> = printer.by_ref().collect();
+//!
+//! assert_eq!(lines.len(), 100);
+//! ```
+//!
+
+use crate::PseudoAln;
+
+pub struct Simulator {
+    // Inputs
+    n_queries: u32,
+    n_targets: u32,
+    density: f64,
+
+    query_prefix: Vec<u8>,
+    target_prefix: Vec<u8>,
+
+    // Internals
+    state: u64,
+    index: u32,
+}
+
+impl Simulator {
+    pub fn new(
+        n_queries: u32,
+        n_targets: u32,
+        density: f64,
+        seed: u64,
+    ) -> Self {
+        Simulator{
+            n_queries, n_targets,
+            density: density.clamp(0.0, 1.0),
+            query_prefix: b"read".to_vec(),
+            target_prefix: b"target".to_vec(),
+            state: seed,
+            index: 0,
+        }
+    }
+
+    pub fn set_query_prefix(
+        &mut self,
+        prefix: &[u8],
+    ) {
+        self.query_prefix = prefix.to_vec();
+    }
+
+    pub fn set_target_prefix(
+        &mut self,
+        prefix: &[u8],
+    ) {
+        self.target_prefix = prefix.to_vec();
+    }
+
+    /// Names of the simulated target sequences.
+    pub fn targets(
+        &self,
+    ) -> Vec<Vec<u8>> {
+        (0..self.n_targets).map(|idx| self.target_name(idx)).collect()
+    }
+
+    /// Names of the simulated query sequences.
+    pub fn queries(
+        &self,
+    ) -> Vec<Vec<u8>> {
+        (0..self.n_queries).map(|idx| self.query_name(idx)).collect()
+    }
+
+    fn query_name(
+        &self,
+        query_id: u32,
+    ) -> Vec<u8> {
+        let mut name = self.query_prefix.clone();
+        name.append(&mut format!(".{}", query_id + 1).as_bytes().to_vec());
+        name
+    }
+
+    fn target_name(
+        &self,
+        target_id: u32,
+    ) -> Vec<u8> {
+        let mut name = self.target_prefix.clone();
+        name.append(&mut format!("_{}", target_id + 1).as_bytes().to_vec());
+        name
+    }
+
+    // splitmix64, returns a value in [0, 1)
+    fn next_f64(
+        &mut self,
+    ) -> f64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+impl Iterator for Simulator {
+    type Item = PseudoAln;
+
+    fn next(
+        &mut self,
+    ) -> Option<PseudoAln> {
+        if self.index >= self.n_queries {
+            return None
+        }
+
+        let query_id = self.index;
+        self.index += 1;
+
+        let ones: Vec<u32> = (0..self.n_targets).filter(|_| self.next_f64() < self.density).collect();
+        let ones_names: Vec<Vec<u8>> = ones.iter().map(|idx| self.target_name(*idx)).collect();
+
+        Some(PseudoAln{ ones: Some(ones), ones_names: Some(ones_names), query_id: Some(query_id), query_name: Some(self.query_name(query_id)) })
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn simulate_is_reproducible() {
+        use super::Simulator;
+        use crate::PseudoAln;
+
+        let got_1: Vec<PseudoAln> = Simulator::new(50, 8, 0.3, 1234).collect();
+        let got_2: Vec<PseudoAln> = Simulator::new(50, 8, 0.3, 1234).collect();
+
+        assert_eq!(got_1.len(), 50);
+        assert_eq!(got_1, got_2);
+    }
+
+    #[test]
+    fn simulate_density_bounds() {
+        use super::Simulator;
+
+        let empty: Vec<Vec<u32>> = Simulator::new(10, 4, 0.0, 0).map(|x| x.ones.unwrap()).collect();
+        let full: Vec<Vec<u32>> = Simulator::new(10, 4, 1.0, 0).map(|x| x.ones.unwrap()).collect();
+
+        assert_eq!(empty, vec![Vec::<u32>::new(); 10]);
+        assert_eq!(full, vec![vec![0, 1, 2, 3]; 10]);
+    }
+
+    #[test]
+    fn simulate_names() {
+        use super::Simulator;
+        use crate::PseudoAln;
+
+        let mut simulator = Simulator::new(2, 2, 1.0, 0);
+        simulator.set_query_prefix(b"ERR4035126");
+        simulator.set_target_prefix(b"chr");
+
+        assert_eq!(simulator.targets(), vec![b"chr_1".to_vec(), b"chr_2".to_vec()]);
+        assert_eq!(simulator.queries(), vec![b"ERR4035126.1".to_vec(), b"ERR4035126.2".to_vec()]);
+
+        let got = simulator.next().unwrap();
+        let expected = PseudoAln{ ones: Some(vec![0, 1]), ones_names: Some(vec![b"chr_1".to_vec(), b"chr_2".to_vec()]), query_id: Some(0), query_name: Some(b"ERR4035126.1".to_vec()) };
+
+        assert_eq!(got, expected);
+    }
+}
@@ -0,0 +1,338 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Split the targets of each block across sub-blocks.
+//!
+//! A [Roaring32](crate::compression::BitmapType::Roaring32) block fits
+//! fewer queries the more targets there are, and with millions of targets a
+//! file is split into very many small blocks, see
+//! [check_shape](crate::compression::check_shape). In the target-chunked
+//! layout each block of queries is stored as consecutive sub-blocks that
+//! each cover `width` targets: sub-block `c` stores the targets from
+//! `c * width` up to `(c + 1) * width`, and bit
+//! `query_id * width + target - c * width` of its bitmap is set if the query
+//! aligned to `target`. The block size and bitmap type are then picked as if
+//! the file had `width` targets.
+//!
+//! Every sub-block stores the block flags of its block. The file header sets
+//! [FILE_HAS_TARGET_CHUNKS] in `fields_present` and the width is stored in
+//! the file extensions under [TARGET_CHUNKS_KEY]. Files are written in this
+//! layout with [Encoder::set_target_chunks](crate::encoder::Encoder::set_target_chunks)
+//! or `ahda encode --target-chunks` and read with the
+//! [Decoder](crate::decoder::Decoder), which merges the sub-blocks of each
+//! block. Other readers error with
+//! [TargetChunksErr](crate::errors::TargetChunksErr).
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::PseudoAln;
+//! use ahda::decoder::Decoder;
+//! use ahda::encoder::Encoder;
+//! use ahda::target_chunks::TargetChunks;
+//! use std::io::Cursor;
+//!
+//! let targets: Vec<Vec<u8>> = (0..5).map(|x| format!("t{}", x).into_bytes()).collect();
+//! let data = vec![
+//!     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 4]), query_name: None },
+//!     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![2]), query_name: None },
+//! ];
+//!
+//! let mut records = data.clone().into_iter();
+//! let mut encoder = Encoder::new(&mut records, &targets, b"sample", 2);
+//! encoder.set_target_chunks(2).unwrap();
+//! let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+//! encoder.for_each(|block| bytes.extend(block.unwrap()));
+//!
+//! let mut cursor = Cursor::new(bytes);
+//! let mut decoder = Decoder::new(&mut cursor);
+//! assert_eq!(decoder.target_chunks(), Some(&TargetChunks::new(2, 5).unwrap()));
+//!
+//! let got: Vec<Option<Vec<u32>>> = decoder.map(|x| x.ones).collect();
+//! assert_eq!(got, vec![Some(vec![0, 4]), Some(vec![2])]);
+//! ```
+//!
+
+use crate::PseudoAln;
+use crate::headers::file::FileFlags;
+use crate::headers::file::FileHeader;
+
+use ahda_core::file::FILE_HAS_TARGET_CHUNKS;
+
+use roaring::RoaringTreemap;
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the width of the target chunks stored in the file extensions.
+pub const TARGET_CHUNKS_KEY: &[u8] = b"ahda.target_chunks";
+
+/// Width of the target chunks suggested for files with too many targets, see [ShapeWarning::suggestion](crate::compression::ShapeWarning::suggestion).
+///
+/// [Roaring32](crate::compression::BitmapType::Roaring32) blocks of this
+/// many targets fit 65535 queries.
+pub const DEFAULT_TARGET_CHUNK_WIDTH: u32 = 65536;
+
+/// Ranges of targets that the blocks of a file are split into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetChunks {
+    /// Number of targets in each sub-block, the last one may have fewer.
+    width: u32,
+    /// Number of targets in the file.
+    n_targets: u32,
+}
+
+impl TargetChunks {
+    /// Split `n_targets` targets into chunks of `width` targets.
+    ///
+    /// ## Errors and panics
+    /// Errors with [InvalidTargetChunksErr](crate::errors::InvalidTargetChunksErr)
+    /// if `width` is 0.
+    ///
+    pub fn new(
+        width: u32,
+        n_targets: u32,
+    ) -> Result<Self, E> {
+        if width == 0 {
+            return Err(Box::new(crate::errors::InvalidTargetChunksErr{}))
+        }
+        Ok(TargetChunks{ width, n_targets })
+    }
+
+    /// Read the target chunks of a file with `header` and `flags`.
+    ///
+    /// Returns None if the blocks are not split, ie. `header` does not set
+    /// [FILE_HAS_TARGET_CHUNKS].
+    ///
+    /// ## Errors and panics
+    /// Errors with [InvalidTargetChunksErr](crate::errors::InvalidTargetChunksErr)
+    /// if the width is not stored in `flags` or is 0.
+    ///
+    pub fn from_header_and_flags(
+        header: &FileHeader,
+        flags: &FileFlags,
+    ) -> Result<Option<Self>, E> {
+        if !header.has_target_chunks() {
+            return Ok(None)
+        }
+        let width = flags.extensions.as_ref().and_then(|x| x.get_as::<u32>(TARGET_CHUNKS_KEY)).ok_or(crate::errors::InvalidTargetChunksErr{})??;
+        Ok(Some(TargetChunks::new(width, header.n_targets)?))
+    }
+
+    /// Number of targets in each sub-block.
+    pub fn width(
+        &self,
+    ) -> u32 {
+        self.width
+    }
+
+    /// Number of sub-blocks in each block.
+    ///
+    /// Files without targets have one sub-block per block.
+    pub fn n_chunks(
+        &self,
+    ) -> usize {
+        self.n_targets.div_ceil(self.width).max(1) as usize
+    }
+
+    /// Header that the sub-blocks of a file with `header` are packed and unpacked with.
+    ///
+    /// Same as `header` but with `width` targets and without
+    /// [FILE_HAS_TARGET_CHUNKS].
+    pub fn chunk_header(
+        &self,
+        header: &FileHeader,
+    ) -> FileHeader {
+        FileHeader{
+            n_targets: self.width,
+            fields_present: header.fields_present & !FILE_HAS_TARGET_CHUNKS,
+            ..header.clone()
+        }
+    }
+
+    /// Split `records` into the records of each sub-block.
+    ///
+    /// The targets of each record are numbered from the start of its chunk.
+    /// All records are kept in every sub-block, so that a query without
+    /// alignments to a chunk is stored as unaligned in its sub-block.
+    ///
+    /// ## Errors and panics
+    /// Errors with [TargetIndexOutOfRangeErr](crate::errors::TargetIndexOutOfRangeErr)
+    /// if a record aligned to a target past the last target.
+    ///
+    pub fn split(
+        &self,
+        records: &[PseudoAln],
+    ) -> Result<Vec<Vec<PseudoAln>>, E> {
+        if records.iter().filter_map(|x| x.ones.as_ref()).flatten().any(|x| *x >= self.n_targets) {
+            return Err(Box::new(crate::errors::TargetIndexOutOfRangeErr{}))
+        }
+        Ok((0..self.n_chunks()).map(|chunk| {
+            let start = chunk as u32 * self.width;
+            let end = start.saturating_add(self.width);
+            records.iter().map(|record| {
+                let ones = record.ones.as_ref().map(|ones| ones.iter().filter(|x| **x >= start && **x < end).map(|x| x - start).collect());
+                PseudoAln{ ones, ones_names: None, ..record.clone() }
+            }).collect()
+        }).collect())
+    }
+
+    /// Add the set bits of sub-block `chunk` to `bits` as bits of the whole file.
+    ///
+    /// ## Errors and panics
+    /// Errors with [TargetIndexOutOfRangeErr](crate::errors::TargetIndexOutOfRangeErr)
+    /// if a bit is past the last target, and with
+    /// [QueryIndexOutOfRangeErr](crate::errors::QueryIndexOutOfRangeErr) if
+    /// it doesn't fit in 64 bits.
+    ///
+    pub fn merge_bits<I: Iterator<Item = u64>>(
+        &self,
+        chunk: usize,
+        chunk_bits: I,
+        bits: &mut RoaringTreemap,
+    ) -> Result<(), E> {
+        let width = self.width as u64;
+        let start = chunk as u64 * width;
+        for bit in chunk_bits {
+            let target = start + bit % width;
+            if target >= self.n_targets as u64 {
+                return Err(Box::new(crate::errors::TargetIndexOutOfRangeErr{}))
+            }
+            let bit = (bit / width).checked_mul(self.n_targets as u64).and_then(|x| x.checked_add(target)).ok_or(crate::errors::QueryIndexOutOfRangeErr{})?;
+            bits.insert(bit);
+        }
+        Ok(())
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn split_and_merge_round_trip() {
+        use super::TargetChunks;
+        use crate::PseudoAln;
+        use roaring::RoaringTreemap;
+
+        let chunks = TargetChunks::new(2, 5).unwrap();
+        assert_eq!(chunks.n_chunks(), 3);
+
+        let records = vec![
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 3, 4]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![]), query_name: None },
+        ];
+        let split = chunks.split(&records).unwrap();
+        let ones: Vec<Vec<Option<Vec<u32>>>> = split.iter().map(|x| x.iter().map(|x| x.ones.clone()).collect()).collect();
+        assert_eq!(ones, vec![
+            vec![Some(vec![0]), Some(vec![])],
+            vec![Some(vec![1]), Some(vec![])],
+            vec![Some(vec![0]), Some(vec![])],
+        ]);
+
+        let mut bits = RoaringTreemap::new();
+        chunks.merge_bits(0, [0, 2].into_iter(), &mut bits).unwrap();
+        chunks.merge_bits(2, [0].into_iter(), &mut bits).unwrap();
+        assert_eq!(bits.iter().collect::<Vec<u64>>(), vec![0, 4, 5]);
+
+        // Second target of the last chunk is past the last target
+        assert!(chunks.merge_bits(2, [1].into_iter(), &mut bits).is_err());
+        let past_end = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![5]), query_name: None }];
+        assert!(chunks.split(&past_end).is_err());
+        assert!(TargetChunks::new(0, 5).is_err());
+    }
+
+    #[test]
+    fn decode_target_chunks() {
+        use super::TargetChunks;
+        use crate::PseudoAln;
+        use crate::decoder::Decoder;
+        use crate::encoder::Encoder;
+        use roaring::RoaringBitmap;
+        use std::collections::HashSet;
+        use std::io::Cursor;
+
+        let targets: Vec<Vec<u8>> = (0..7).map(|idx| format!("target{}", idx).into_bytes()).collect();
+        let data: Vec<PseudoAln> = (0..30).map(|idx| {
+            let ones = (0..7).filter(|target| (idx + target) % 4 == 0).collect();
+            PseudoAln{ ones_names: None, query_id: Some(idx), ones: Some(ones), query_name: Some(format!("r{}", idx).into_bytes()) }
+        }).collect();
+
+        let encode = |width: Option<u32>| -> Vec<u8> {
+            let mut records = data.clone().into_iter();
+            let mut encoder = Encoder::new(&mut records, &targets, b"sample", 30);
+            encoder.set_fields_present(crate::MASK_QUERY_IDS | crate::MASK_QUERIES);
+            if let Some(width) = width {
+                encoder.set_target_chunks(width).unwrap();
+            }
+            encoder.set_block_size(4).unwrap();
+            encoder.set_query_filter(0.01).unwrap();
+            encoder.set_target_summary();
+            let mut bytes: Vec<u8> = encoder.encode_file_header_and_flags().unwrap();
+            encoder.for_each(|block| bytes.extend(block.unwrap()));
+            bytes
+        };
+        let plain = encode(None);
+        let chunked = encode(Some(3));
+
+        let mut cursor = Cursor::new(&plain);
+        let expected: Vec<PseudoAln> = Decoder::new(&mut cursor).collect();
+
+        let mut cursor = Cursor::new(&chunked);
+        let mut decoder = Decoder::new(&mut cursor);
+        assert_eq!(decoder.target_chunks(), Some(&TargetChunks::new(3, 7).unwrap()));
+        assert!(!decoder.file_header().has_target_chunks());
+        let got: Vec<PseudoAln> = decoder.by_ref().collect();
+        assert!(decoder.take_error().is_none());
+        assert_eq!(got, expected);
+
+        // Skipped blocks skip all of their sub-blocks
+        let mut cursor = Cursor::new(&chunked);
+        let got = Decoder::new(&mut cursor).grep(&HashSet::from([b"r13".to_vec()])).unwrap();
+        assert_eq!(got, vec![expected[13].clone()]);
+
+        let mut cursor = Cursor::new(&chunked);
+        let got = Decoder::new(&mut cursor).slice(&RoaringBitmap::from_iter([6])).unwrap();
+        let want: Vec<PseudoAln> = expected.iter().filter(|x| x.ones.as_ref().unwrap().contains(&6)).cloned().collect();
+        assert_eq!(got, want);
+
+        let mut cursor = Cursor::new(&chunked);
+        let got = Decoder::new(&mut cursor).sample(3, 7).unwrap();
+        let mut cursor = Cursor::new(&plain);
+        assert_eq!(got, Decoder::new(&mut cursor).sample(3, 7).unwrap());
+    }
+
+    #[test]
+    fn other_readers_reject_target_chunks() {
+        use crate::EncodeOpts;
+        use crate::PseudoAln;
+        use crate::encode_to_write;
+        use crate::headers::file::read_file_header_and_flags;
+        use crate::headers::file::read_file_header_and_flags_any_layout;
+
+        let targets = vec![b"chr.fasta".to_vec(), b"plasmid.fasta".to_vec()];
+        let data = vec![PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![1]), query_name: None }];
+        let opts = EncodeOpts{ target_chunks: Some(1), ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_to_write(&targets, &[], &data, &mut bytes, opts).unwrap();
+
+        let err = read_file_header_and_flags(&mut bytes.as_slice()).unwrap_err();
+        assert!(err.is::<crate::errors::TargetChunksErr>());
+        assert!(matches!(ahda_core::split_record(&bytes), Err(ahda_core::Error::TargetChunks)));
+        assert!(crate::sort_block_flags(&mut bytes.as_slice(), &mut Vec::new()).is_err());
+
+        let (header, flags) = read_file_header_and_flags_any_layout(&mut bytes.as_slice()).unwrap();
+        assert!(header.has_target_chunks());
+        assert_eq!(flags.target_names, targets);
+    }
+}
@@ -0,0 +1,131 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Record which targets have hits in each block.
+//!
+//! Finding the reads that aligned to a few targets normally decompresses
+//! every block. A [TargetSummary] lists the targets that at least one record
+//! of a block aligned to and is stored in the extensions of the block flags
+//! under [TARGET_SUMMARY_KEY], so that blocks without hits to the wanted
+//! targets can be skipped after reading only their flags, see
+//! [Decoder::slice](crate::decoder::Decoder::slice).
+//!
+//! [Encoder::set_target_summary](crate::encoder::Encoder::set_target_summary)
+//! adds the summary to each block. Blocks without one are always read.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! use ahda::PseudoAln;
+//! use ahda::target_summary::TargetSummary;
+//! use roaring::RoaringBitmap;
+//!
+//! let records = vec![
+//!     PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![0, 3]), query_name: None },
+//!     PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![]), query_name: None },
+//! ];
+//! let summary = TargetSummary::from_records(&records);
+//!
+//! assert!(summary.contains_any(&RoaringBitmap::from_iter([3, 5])));
+//! assert!(!summary.contains_any(&RoaringBitmap::from_iter([1, 2])));
+//! ```
+//!
+
+use crate::PseudoAln;
+use crate::headers::block::BlockFlags;
+use crate::headers::extensions::Extensions;
+
+use roaring::RoaringBitmap;
+
+type E = Box<dyn std::error::Error>;
+
+/// Key of the [TargetSummary] stored in the block extensions.
+pub const TARGET_SUMMARY_KEY: &[u8] = b"ahda.target_summary";
+
+/// Targets that the records of a block aligned to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TargetSummary {
+    /// Ids of the targets with at least one hit.
+    pub targets: RoaringBitmap,
+}
+
+impl TargetSummary {
+    /// Summary of the targets in `records`.
+    pub fn from_records(
+        records: &[PseudoAln],
+    ) -> Self {
+        let targets = records.iter().filter_map(|x| x.ones.as_ref()).flatten().copied().collect();
+        TargetSummary{ targets }
+    }
+
+    /// Read the summary stored in `block_flags`, if any.
+    ///
+    /// ## Errors and panics
+    /// Errors if the stored value is not a serialized [RoaringBitmap].
+    ///
+    pub fn from_block_flags(
+        block_flags: &BlockFlags,
+    ) -> Result<Option<Self>, E> {
+        let Some(bytes) = block_flags.extensions.as_ref().and_then(|x| x.get(TARGET_SUMMARY_KEY)) else {
+            return Ok(None)
+        };
+        Ok(Some(TargetSummary{ targets: RoaringBitmap::deserialize_from(bytes)? }))
+    }
+
+    /// Store the summary in `extensions` under [TARGET_SUMMARY_KEY].
+    pub fn insert_into(
+        &self,
+        extensions: &mut Extensions,
+    ) -> Result<(), E> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.targets.serialized_size());
+        self.targets.serialize_into(&mut bytes)?;
+        extensions.insert(TARGET_SUMMARY_KEY, bytes);
+        Ok(())
+    }
+
+    /// Check if any target in `targets` has a hit.
+    pub fn contains_any(
+        &self,
+        targets: &RoaringBitmap,
+    ) -> bool {
+        !self.targets.is_disjoint(targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn target_summary_in_block_flags() {
+        use super::TargetSummary;
+        use crate::PseudoAln;
+        use crate::headers::block::BlockFlags;
+        use crate::headers::extensions::Extensions;
+
+        let records = vec![
+            PseudoAln{ ones_names: None, query_id: Some(0), ones: Some(vec![2, 7]), query_name: None },
+            PseudoAln{ ones_names: None, query_id: Some(1), ones: Some(vec![7, 100000]), query_name: None },
+        ];
+        let summary = TargetSummary::from_records(&records);
+        assert_eq!(summary.targets.iter().collect::<Vec<u32>>(), vec![2, 7, 100000]);
+
+        let mut block_flags = BlockFlags{ queries: None, query_ids: None, ..Default::default() };
+        assert_eq!(TargetSummary::from_block_flags(&block_flags).unwrap(), None);
+
+        let mut extensions = Extensions::default();
+        summary.insert_into(&mut extensions).unwrap();
+        block_flags.extensions = Some(extensions);
+        assert_eq!(TargetSummary::from_block_flags(&block_flags).unwrap(), Some(summary));
+    }
+}
@@ -0,0 +1,210 @@
+// ahda: Pseudoalignment compression and conversion between formats.
+//
+// Copyright 2025 Tommi Mäklin [tommi@maklin.fi].
+//
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+//
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+
+//! Runs the CLI on inputs that can't seek, like named pipes and `<(...)`.
+#![cfg(unix)]
+
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+
+const TARGETS: &[u8] = b"chr.fasta\nplasmid.fasta\nvirus.fasta\n";
+const QUERIES: &[u8] = b"@r1\nA\n+\nI\n@r2\nA\n+\nI\n";
+const DECODED: &[u8] = b"query_index\tquery_name\tchr.fasta\tplasmid.fasta\tvirus.fasta\n0\tr1\t1\t0\t0\n1\tr2\t0\t1\t1\n";
+
+/// Creates an empty directory with the targets and queries for `test`.
+fn setup(
+    test: &str,
+) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ahda-pipes-{}-{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("targets.txt"), TARGETS).unwrap();
+    std::fs::write(dir.join("queries.fq"), QUERIES).unwrap();
+    dir
+}
+
+/// Runs ahda in `dir` with `input` piped to stdin.
+fn ahda(
+    dir: &Path,
+    args: &[&str],
+    input: &[u8],
+) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ahda"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || { let _ = stdin.write_all(&input); });
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    output
+}
+
+/// Lists the files in `dir`.
+fn files_in(
+    dir: &Path,
+) -> Vec<String> {
+    let mut files: Vec<String> = std::fs::read_dir(dir).unwrap().map(|x| x.unwrap().file_name().to_string_lossy().to_string()).collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn encode_and_decode_dev_stdin() {
+    let dir = setup("dev-stdin");
+
+    let encoded = ahda(&dir, &["encode", "-t", "targets.txt", "-q", "queries.fq", "/dev/stdin"], b"0 0\n1 1 2\n");
+    assert!(encoded.status.success(), "{}", String::from_utf8_lossy(&encoded.stderr));
+
+    let decoded = ahda(&dir, &["decode", "/dev/stdin"], &encoded.stdout);
+    assert!(decoded.status.success(), "{}", String::from_utf8_lossy(&decoded.stderr));
+    assert_eq!(decoded.stdout, DECODED);
+
+    // Nothing is written next to or named after the input
+    assert_eq!(files_in(&dir), vec!["queries.fq", "targets.txt"]);
+    assert!(Path::new("/dev/stdin").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn convert_dev_stdin() {
+    let dir = setup("convert");
+
+    let converted = ahda(&dir, &["convert", "-t", "targets.txt", "-q", "queries.fq", "-F", "ahda-tsv", "/dev/stdin"], b"0 0\n1 1 2\n");
+    assert!(converted.status.success(), "{}", String::from_utf8_lossy(&converted.stderr));
+    let (program, rest) = converted.stdout.split_at(converted.stdout.iter().position(|x| *x == b'\n').unwrap() + 1);
+    assert!(program.starts_with(b"#PG\tID:ahda\tPN:ahda\tVN:"), "{}", String::from_utf8_lossy(program));
+    assert!(program.ends_with(b"-F ahda-tsv /dev/stdin\n"), "{}", String::from_utf8_lossy(program));
+    assert_eq!(rest, DECODED);
+    assert_eq!(files_in(&dir), vec!["queries.fq", "targets.txt"]);
+
+    let converted = ahda(&dir, &["convert", "--no-program-record", "-t", "targets.txt", "-q", "queries.fq", "-F", "ahda-tsv", "/dev/stdin"], b"0 0\n1 1 2\n");
+    assert!(converted.status.success(), "{}", String::from_utf8_lossy(&converted.stderr));
+    assert_eq!(converted.stdout, DECODED);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn merge_mates_from_named_pipe() {
+    let dir = setup("merge-mates");
+
+    let first = ahda(&dir, &["encode", "-t", "targets.txt", "-q", "queries.fq", "/dev/stdin"], b"0 0\n1 1\n");
+    assert!(first.status.success(), "{}", String::from_utf8_lossy(&first.stderr));
+    let second = ahda(&dir, &["encode", "-t", "targets.txt", "-q", "queries.fq", "/dev/stdin"], b"1 2\n");
+    assert!(second.status.success(), "{}", String::from_utf8_lossy(&second.stderr));
+    std::fs::write(dir.join("second.ahda"), &second.stdout).unwrap();
+
+    let status = Command::new("mkfifo").arg(dir.join("first.ahda")).status().unwrap();
+    assert!(status.success());
+    let fifo = dir.join("first.ahda");
+    let bytes = first.stdout.clone();
+    let writer = std::thread::spawn(move || std::fs::write(fifo, bytes).unwrap());
+
+    let merged = ahda(&dir, &["merge-mates", "first.ahda", "second.ahda", "-o", "merged.ahda"], b"");
+    writer.join().unwrap();
+    assert!(merged.status.success(), "{}", String::from_utf8_lossy(&merged.stderr));
+
+    let decoded = ahda(&dir, &["decode", "/dev/stdin"], &std::fs::read(dir.join("merged.ahda")).unwrap());
+    assert!(decoded.status.success(), "{}", String::from_utf8_lossy(&decoded.stderr));
+    assert_eq!(decoded.stdout, DECODED);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn encode_with_tee() {
+    use std::io::Read;
+
+    let dir = setup("tee");
+
+    let input = b"0 0\n1 1 2\n";
+    let encoded = ahda(&dir, &["encode", "-t", "targets.txt", "-q", "queries.fq", "--tee", "raw.txt.gz", "/dev/stdin"], input);
+    assert!(encoded.status.success(), "{}", String::from_utf8_lossy(&encoded.stderr));
+
+    let mut raw: Vec<u8> = Vec::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(dir.join("raw.txt.gz")).unwrap()).read_to_end(&mut raw).unwrap();
+    assert_eq!(raw, input);
+
+    let decoded = ahda(&dir, &["decode", "/dev/stdin"], &encoded.stdout);
+    assert!(decoded.status.success(), "{}", String::from_utf8_lossy(&decoded.stderr));
+    assert_eq!(decoded.stdout, DECODED);
+
+    // An existing copy is not overwritten without `--force`
+    let again = ahda(&dir, &["encode", "-t", "targets.txt", "-q", "queries.fq", "--tee", "raw.txt.gz", "/dev/stdin"], input);
+    assert!(!again.status.success());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn quiet_mode_and_exit_codes() {
+    let dir = setup("exit-codes");
+
+    // Nothing but the data is written with `--quiet`, even with `--verbose`
+    let encoded = ahda(&dir, &["encode", "--quiet", "-v", "-t", "targets.txt", "-q", "queries.fq", "/dev/stdin"], b"0 0\n1 1 2\n");
+    assert!(encoded.status.success(), "{}", String::from_utf8_lossy(&encoded.stderr));
+    assert!(encoded.stderr.is_empty(), "{}", String::from_utf8_lossy(&encoded.stderr));
+    let verbose = ahda(&dir, &["decode", "-v", "/dev/stdin"], &encoded.stdout);
+    let quiet = ahda(&dir, &["decode", "-v", "--quiet", "/dev/stdin"], &encoded.stdout);
+    assert_eq!(quiet.stdout, verbose.stdout);
+    assert!(quiet.stderr.is_empty(), "{}", String::from_utf8_lossy(&quiet.stderr));
+
+    let invalid = ahda(&dir, &["--quiet", "stats", "/dev/stdin"], b"not ahda data");
+    assert_eq!(invalid.status.code(), Some(3));
+    assert!(!invalid.stderr.is_empty());
+
+    std::fs::write(dir.join("a.ahda"), &encoded.stdout).unwrap();
+    std::fs::write(dir.join("targets.txt"), b"chr.fasta\nplasmid.fasta\nphage.fasta\n").unwrap();
+    let other = ahda(&dir, &["encode", "-t", "targets.txt", "-n", "sample", "/dev/stdin"], b"0 0\n");
+    std::fs::write(dir.join("b.ahda"), &other.stdout).unwrap();
+    let incompatible = ahda(&dir, &["cat", "-f", "b.ahda"], &encoded.stdout);
+    assert_eq!(incompatible.status.code(), Some(4), "{}", String::from_utf8_lossy(&incompatible.stderr));
+
+    let missing = ahda(&dir, &["stats", "missing.ahda"], b"");
+    assert_eq!(missing.status.code(), Some(5));
+
+    let usage = ahda(&dir, &["stats", "--no-such-option"], b"");
+    assert_eq!(usage.status.code(), Some(2));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn decode_symlink_keeps_link() {
+    let dir = setup("symlink");
+
+    let encoded = ahda(&dir, &["encode", "-t", "targets.txt", "-q", "queries.fq", "/dev/stdin"], b"0 0\n1 1 2\n");
+    assert!(encoded.status.success(), "{}", String::from_utf8_lossy(&encoded.stderr));
+    std::fs::write(dir.join("sample.ahda"), &encoded.stdout).unwrap();
+    std::os::unix::fs::symlink("sample.ahda", dir.join("link.ahda")).unwrap();
+
+    // A symlink to a regular file is decoded like the file, but is not removed
+    let decoded = ahda(&dir, &["decode", "link.ahda"], b"");
+    assert!(decoded.status.success(), "{}", String::from_utf8_lossy(&decoded.stderr));
+    assert_eq!(std::fs::read(dir.join("link")).unwrap(), DECODED);
+    assert_eq!(files_in(&dir), vec!["link", "link.ahda", "queries.fq", "sample.ahda", "targets.txt"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}